@@ -0,0 +1,41 @@
+use chrono::{TimeZone, Utc};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use nic::{
+    config::Watering,
+    watering::{
+        ds::SectorInfo,
+        water_window::WaterWin,
+        watering_alg::{calc_wizard_daily_plan, PlanSector},
+    },
+};
+
+/// Enough sectors for a large garden, with a spread of needs so most of them get placed
+fn sectors(count: u32) -> Vec<PlanSector> {
+    (1..=count)
+        .map(|id| {
+            let sec = SectorInfo::build(id, 2.5, 0.4 + f64::from(id % 5) * 0.4, 30 * 60, 0., 0.5, 0);
+            PlanSector::from(&sec)
+        })
+        .collect()
+}
+
+fn wizard_plan(c: &mut Criterion) {
+    // a Monday, the whole week left to plan
+    let now = Utc.with_ymd_and_hms(2024, 12, 16, 0, 0, 0).unwrap().timestamp();
+    let timeframe = WaterWin::new(now, 6, 12);
+    let cfg = Watering::default();
+    for count in [50, 200] {
+        let base = sectors(count);
+        let mut scratch = base.clone();
+        assert!(!calc_wizard_daily_plan(&mut scratch, &[], &[], now, timeframe, &[], &cfg).is_empty());
+        c.bench_function(&format!("wizard plan, {} sectors", count), |b| {
+            b.iter(|| {
+                scratch.clone_from(&base);
+                calc_wizard_daily_plan(black_box(&mut scratch), &[], &[], now, timeframe, &[], &cfg)
+            })
+        });
+    }
+}
+
+criterion_group!(benches, wizard_plan);
+criterion_main!(benches);
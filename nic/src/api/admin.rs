@@ -0,0 +1,147 @@
+//! Operating the controller: the config, the reports, the metrics, the audit log and the simulated clock
+
+use super::EventsQuery;
+use crate::{
+    config::manager::ConfigReload,
+    error::ApiError,
+    metrics,
+    watering::{
+        daily_report::DailyReport,
+        ds::{AppState, AuditEntry, SourceUsage},
+    },
+};
+#[cfg(feature = "sim")]
+use crate::{test::utils::mock_time::MockTimeProvider, time::TimeProvider};
+use axum::extract::{Path, Query, State};
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use chrono::{NaiveDate, NaiveTime};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+#[cfg(feature = "sim")]
+use std::time::Duration;
+#[cfg(feature = "sim")]
+use tracing::info;
+
+pub(super) fn routes() -> Router<Arc<AppState>> {
+    let routes = Router::new()
+        .route("/metrics", get(get_metrics))
+        .route("/config/reload", post(reload_config))
+        .route("/reports/:date", get(get_daily_report))
+        .route("/audit", get(get_audit))
+        .route("/stats/usage", get(get_usage));
+    #[cfg(feature = "sim")]
+    let routes = routes.route("/sim/time/set", post(sim_set_time)).route("/sim/time/advance", post(sim_advance_time));
+    routes
+}
+
+/// Re-reads the config file and applies what can change without a restart.<br>
+/// A file that doesn't parse or validate is a bad request, and the running config stays.
+pub async fn reload_config(app_state: State<Arc<AppState>>) -> Result<Json<ConfigReload>, ApiError> {
+    let reload = app_state.config.reload().map_err(|e| ApiError::BadRequest(e.to_string()))?;
+    Ok(Json(reload))
+}
+
+/// The report of a day, `YYYY-MM-DD` in UTC, written after midnight
+pub async fn get_daily_report(
+    Path(date): Path<String>, State(app_state): State<Arc<AppState>>,
+) -> Result<Json<DailyReport>, ApiError> {
+    let day = NaiveDate::parse_from_str(&date, "%Y-%m-%d")
+        .map_err(|_| ApiError::BadRequest(format!("'{}' is not a YYYY-MM-DD date", date)))?;
+    let day = day.and_time(NaiveTime::MIN).and_utc().timestamp();
+    let report = app_state.db.load_daily_report(day)?;
+    report.map(Json).ok_or_else(|| ApiError::NotFound(format!("no report for {}", date)))
+}
+
+/// Prometheus text format
+pub async fn get_metrics() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], metrics::registry().render())
+}
+
+/// The API calls that could change the controller, oldest first
+pub async fn get_audit(
+    Query(query): Query<EventsQuery>, State(app_state): State<Arc<AppState>>,
+) -> Json<Vec<AuditEntry>> {
+    let to = query.to.unwrap_or_else(|| app_state.time_provider.now() + 1);
+    let from = query.from.unwrap_or(to - 86_400);
+    Json(app_state.db.load_audit(from, to).unwrap_or_default())
+}
+
+/// Water drawn per source, summed over the days starting in `[from, to)`. Defaults to the last 30 days.
+pub async fn get_usage(
+    Query(query): Query<EventsQuery>, State(app_state): State<Arc<AppState>>,
+) -> Json<Vec<SourceUsage>> {
+    let to = query.to.unwrap_or_else(|| app_state.time_provider.now() + 1);
+    let from = query.from.unwrap_or(to - 30 * 86_400);
+    Json(app_state.db.load_source_usage(from, to).unwrap_or_default())
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SimSet {
+    /// Unix UTC, not before the current time
+    pub time: i64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SimAdvance {
+    pub secs: i64,
+    /// the clock moves this many seconds at a time, so the tasks see each step. All at once when not given.
+    #[serde(default)]
+    pub step_secs: Option<i64>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SimClock {
+    pub now: i64,
+}
+
+/// Real time the tasks woken by a step of `sim_advance_time` get before the next
+#[cfg(feature = "sim")]
+const SIM_STEP_PAUSE: Duration = Duration::from_millis(10);
+
+/// The held clock of a `time = "simulated"` profile
+#[cfg(feature = "sim")]
+fn sim_clock(app_state: &AppState) -> Result<&MockTimeProvider, ApiError> {
+    let clock = app_state.time_provider.as_any().downcast_ref::<MockTimeProvider>();
+    let problem = "the clock isn't simulated, run with a time = \"simulated\" profile";
+    clock.filter(|clock| clock.is_held()).ok_or(ApiError::Conflict(problem.to_owned()))
+}
+
+/// Moves the simulated clock to `time`, it only goes forward
+#[cfg(feature = "sim")]
+pub async fn sim_set_time(
+    State(app_state): State<Arc<AppState>>, Json(request): Json<SimSet>,
+) -> Result<Json<SimClock>, ApiError> {
+    let clock = sim_clock(&app_state)?;
+    let now = clock.now();
+    if request.time < now {
+        return Err(ApiError::BadRequest(format!("{} is before the current time, {}", request.time, now)));
+    }
+    clock.set(request.time);
+    info!(from = now, to = request.time, "Simulated time set.");
+    Ok(Json(SimClock { now: clock.now() }))
+}
+
+/// Moves the simulated clock `secs` on, `step_secs` at a time
+#[cfg(feature = "sim")]
+pub async fn sim_advance_time(
+    State(app_state): State<Arc<AppState>>, Json(request): Json<SimAdvance>,
+) -> Result<Json<SimClock>, ApiError> {
+    let clock = sim_clock(&app_state)?;
+    let step = request.step_secs.unwrap_or(request.secs);
+    if request.secs < 0 || step < 0 || (request.secs > 0 && step == 0) {
+        return Err(ApiError::BadRequest("secs and step_secs must not be negative".to_owned()));
+    }
+    let mut left = request.secs;
+    while left > 0 {
+        clock.advance_time(left.min(step)).await;
+        left -= left.min(step);
+        if left > 0 {
+            tokio::time::sleep(SIM_STEP_PAUSE).await;
+        }
+    }
+    info!(secs = request.secs, now = clock.now(), "Simulated time advanced.");
+    Ok(Json(SimClock { now: clock.now() }))
+}
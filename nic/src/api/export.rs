@@ -0,0 +1,133 @@
+//! The watering events and the weather as CSV, for a spreadsheet. The rows are read a page at a time as the body is
+//! sent, a year of observations is never in memory.
+
+use crate::{error::ApiError, watering::ds::AppState};
+use axum::body::Body;
+use axum::extract::{Query, State};
+use axum::http::header;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use chrono::{DateTime, Days, NaiveDate};
+use futures_util::stream::{self, StreamExt};
+use serde::Deserialize;
+use std::{fmt::Write, sync::Arc};
+use tracing::warn;
+
+/// Rows read at once
+const EXPORT_PAGE: u32 = 500;
+/// Days exported when the range doesn't say
+const DEFAULT_DAYS: u64 = 30;
+
+pub(super) fn routes() -> Router<Arc<AppState>> {
+    Router::new().route("/export/events.csv", get(export_events)).route("/export/weather.csv", get(export_weather))
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct ExportQuery {
+    /// UTC days, `YYYY-MM-DD`, both included. Up to today, and from 30 days before the last, when left out.
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
+
+impl ExportQuery {
+    /// `[from, to)` in Unix UTC timestamps
+    fn range(&self, now: i64) -> Result<(i64, i64), ApiError> {
+        let day = |date: &str| {
+            NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                .map_err(|_| ApiError::BadRequest(format!("'{}' is not a YYYY-MM-DD day", date)))
+        };
+        let today = DateTime::from_timestamp(now, 0).unwrap_or_default().date_naive();
+        let to = self.to.as_deref().map(day).transpose()?.unwrap_or(today);
+        let from = match self.from.as_deref() {
+            Some(from) => day(from)?,
+            None => to.checked_sub_days(Days::new(DEFAULT_DAYS)).unwrap_or(to),
+        };
+        if from > to {
+            return Err(ApiError::BadRequest(format!("{} is after {}", from, to)));
+        }
+        let start = |day: NaiveDate| day.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp();
+        Ok((start(from), start(to) + 86_400))
+    }
+}
+
+/// The watering events starting in the days, in the order they were logged
+pub async fn export_events(
+    Query(query): Query<ExportQuery>, State(app_state): State<Arc<AppState>>,
+) -> Result<Response, ApiError> {
+    let (from, to) = query.range(app_state.time_provider.now())?;
+    let header = "id,cycle_id,sector,start_utc,duration_secs,water_applied_cm,mode";
+    let body = csv_body(header, 0, move |after| {
+        let events = app_state.db.load_watering_events_page(from, to, after, EXPORT_PAGE)?;
+        let Some(&(last, _)) = events.last() else { return Ok(None) };
+        let mut rows = String::new();
+        for (id, evt) in events {
+            let cycle = evt.cycle_id.map(|cycle| cycle.to_string()).unwrap_or_default();
+            let (sec, start) = (evt.sector, utc(evt.sector.start));
+            let (applied, mode) = (evt.water_applied, evt.mode);
+            _ = writeln!(rows, "{},{},{},{},{},{},{}", id, cycle, sec.id, start, sec.duration, applied, mode);
+        }
+        Ok(Some((rows, last)))
+    });
+    Ok(csv_response("events.csv", body))
+}
+
+/// The observations of the days, by time
+pub async fn export_weather(
+    Query(query): Query<ExportQuery>, State(app_state): State<Arc<AppState>>,
+) -> Result<Response, ApiError> {
+    let (from, to) = query.range(app_state.time_provider.now())?;
+    let header = "time_utc,temperature,humidity,wind_speed,wind_gust,wind_direction,solar_radiation,rain,rain_rate";
+    let body = csv_body(header, from, move |from| {
+        let observations = app_state.db.load_observations_page(from, to, EXPORT_PAGE)?;
+        let Some(last) = observations.last().map(|obs| obs.timestamp) else { return Ok(None) };
+        let mut rows = String::new();
+        for obs in observations {
+            _ = writeln!(
+                rows,
+                "{},{},{},{},{},{},{},{},{}",
+                utc(obs.timestamp),
+                obs.temperature,
+                obs.humidity,
+                obs.wind_speed,
+                obs.wind_gust,
+                obs.wind_direction,
+                obs.solar_radiation,
+                obs.rain,
+                obs.rain_rate
+            );
+        }
+        Ok(Some((rows, last + 1)))
+    });
+    Ok(csv_response("weather.csv", body))
+}
+
+/// As a spreadsheet reads it
+fn utc(timestamp: i64) -> String {
+    DateTime::from_timestamp(timestamp, 0).unwrap_or_default().format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+/// The header, then the rows of each page, from `start`. A page gives its rows and where the next one starts, none
+/// once there are no more. A page that fails cuts the body short, the client sees it unfinished.
+fn csv_body<F>(header: &str, start: i64, page: F) -> Body
+where
+    F: Fn(i64) -> rusqlite::Result<Option<(String, i64)>> + Send + 'static,
+{
+    let header = stream::once(std::future::ready(Ok(format!("{}\n", header))));
+    let pages = stream::unfold((page, Some(start)), |(page, cursor)| async move {
+        match page(cursor?) {
+            Ok(Some((rows, next))) => Some((Ok(rows), (page, Some(next)))),
+            Ok(None) => None,
+            Err(e) => {
+                warn!("CSV export cut short: {}", e);
+                Some((Err(e), (page, None)))
+            }
+        }
+    });
+    Body::from_stream(header.chain(pages))
+}
+
+fn csv_response(file: &str, body: Body) -> Response {
+    let disposition = format!("attachment; filename=\"{}\"", file);
+    ([(header::CONTENT_TYPE, "text/csv".to_owned()), (header::CONTENT_DISPOSITION, disposition)], body).into_response()
+}
@@ -0,0 +1,116 @@
+//! How much one client can ask of the controller, so a dashboard that misbehaves can't flood `sm_tx`
+
+use crate::{config::WebServer, error::ApiError};
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::Method;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+use tracing::warn;
+
+/// Past this many clients, the ones back to a full bucket are forgotten
+const MAX_CLIENTS: usize = 1024;
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    at: Instant,
+}
+
+/// A token bucket per ip: `burst` calls at once, then `rate` a second
+#[derive(Debug)]
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(rate: f64, burst: u32) -> Self {
+        Self { rate, burst: f64::from(burst), buckets: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn from_cfg(cfg: &WebServer) -> Self {
+        Self::new(cfg.control_rate, cfg.control_burst)
+    }
+
+    /// Takes a token of `ip` at `now`, false when it has none left
+    pub fn check(&self, ip: IpAddr, now: Instant) -> bool {
+        if self.rate <= 0. {
+            return true;
+        }
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() > MAX_CLIENTS {
+            buckets.retain(|_, bucket| bucket.refilled(now, self.rate, self.burst) < self.burst);
+        }
+        let bucket = buckets.entry(ip).or_insert(Bucket { tokens: self.burst, at: now });
+        bucket.tokens = bucket.refilled(now, self.rate, self.burst);
+        bucket.at = now;
+        if bucket.tokens < 1. {
+            return false;
+        }
+        bucket.tokens -= 1.;
+        true
+    }
+}
+
+impl Bucket {
+    fn refilled(&self, now: Instant, rate: f64, burst: f64) -> f64 {
+        (self.tokens + now.saturating_duration_since(self.at).as_secs_f64() * rate).min(burst)
+    }
+}
+
+/// Never held back, an emergency stop from a client that used its burst is still an emergency
+fn is_emergency(path: &str) -> bool {
+    path.ends_with("/estop") || path.ends_with("/estop/clear")
+}
+
+/// Holds back the calls that can change the controller, the reads and the emergency stop are never limited
+pub(super) async fn rate_limit(State(limiter): State<Arc<RateLimiter>>, request: Request, next: Next) -> Response {
+    let method = request.method();
+    if method == Method::GET || method == Method::HEAD || is_emergency(request.uri().path()) {
+        return next.run(request).await;
+    }
+    let addr = request.extensions().get::<ConnectInfo<SocketAddr>>().map(|ConnectInfo(addr)| addr.ip());
+    let ip = addr.unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+    if !limiter.check(ip, Instant::now()) {
+        warn!(%ip, path = %request.uri().path(), "Too many calls, refused.");
+        return ApiError::TooManyRequests(format!("{} is calling too often, wait a moment", ip)).into_response();
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn each_ip_has_its_own_bucket() {
+        let limiter = RateLimiter::new(2., 3);
+        let (dashboard, phone): (IpAddr, IpAddr) = ("10.0.0.2".parse().unwrap(), "10.0.0.3".parse().unwrap());
+        let start = Instant::now();
+        let burst: Vec<bool> = (0..4).map(|_| limiter.check(dashboard, start)).collect();
+        assert_eq!(burst, [true, true, true, false]);
+        assert!(limiter.check(phone, start), "another client");
+
+        // half a second gives one back
+        assert!(limiter.check(dashboard, start + Duration::from_millis(500)));
+        assert!(!limiter.check(dashboard, start + Duration::from_millis(600)));
+        // never more than the burst
+        let later = start + Duration::from_secs(60);
+        assert_eq!((0..4).filter(|_| limiter.check(dashboard, later)).count(), 3);
+    }
+
+    #[test]
+    fn no_rate_no_limit() {
+        let limiter = RateLimiter::new(0., 1);
+        let ip = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        assert!((0..100).all(|_| limiter.check(ip, Instant::now())));
+    }
+}
@@ -0,0 +1,143 @@
+//! The HTTP API, under `/api/v1`. The paths of before the version, without the prefix, still answer the same and
+//! say they are deprecated.
+
+mod admin;
+mod export;
+pub mod limits;
+mod schedule;
+mod sectors;
+mod state;
+mod subscription;
+mod weather;
+
+pub use admin::*;
+pub use export::*;
+pub use schedule::*;
+pub use sectors::*;
+pub use state::*;
+
+use crate::{
+    config::WebServer,
+    error::ApiError,
+    metrics::{self, SIGNAL_ROUNDTRIP_SECONDS},
+    watering::ds::{AppState, AuditEntry, CtrlSignal, Reply},
+};
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::{header::HeaderValue, Method};
+use axum::middleware::{self, Next};
+use axum::response::Response;
+use axum::Router;
+use limits::{rate_limit, RateLimiter};
+use serde::Deserialize;
+use std::{
+    error::Error,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    sync::{broadcast::Sender, watch},
+    time::timeout,
+};
+use tracing::{error, info};
+
+/// Prefix of the current version of the API
+pub const API_PREFIX: &str = "/api/v1";
+
+/// Who is calling, as the client says, for the audit log
+pub const USER_HEADER: &str = "x-user";
+
+/// Every route of the API, without the prefix
+fn routes(cfg: &WebServer) -> Router<Arc<AppState>> {
+    Router::new()
+        .merge(state::routes())
+        .merge(schedule::routes(cfg))
+        .merge(sectors::routes())
+        .merge(weather::routes())
+        .merge(admin::routes())
+        .merge(export::routes())
+}
+
+pub async fn run_web_server(
+    app_state: Arc<AppState>, ip_addr: SocketAddr, stop_signal: watch::Receiver<bool>,
+) -> Result<(), Box<dyn Error>> {
+    let cfg = app_state.config.current().web_server.clone();
+    // the unversioned paths of the clients of before the prefix
+    let legacy = routes(&cfg).layer(middleware::map_response(deprecated));
+    let routes = Router::new().nest(API_PREFIX, routes(&cfg)).merge(legacy);
+    let limiter = Arc::new(RateLimiter::from_cfg(&cfg));
+    // the same under the site, the other sites of the database have their own controllers
+    let site = format!("/sites/{}", app_state.config.current().site.id);
+    let app = routes
+        .clone()
+        .nest(&site, routes)
+        .layer(middleware::from_fn_with_state(app_state.clone(), audit))
+        // the refused calls aren't audited, a flood would fill the log
+        .layer(middleware::from_fn_with_state(limiter, rate_limit))
+        .with_state(app_state);
+
+    info!("Starting HTTP server on http://{}", ip_addr);
+    let listener = tokio::net::TcpListener::bind(ip_addr).await?;
+    let app = app.into_make_service_with_connect_info::<SocketAddr>();
+    axum::serve(listener, app).with_graceful_shutdown(shutdown_signal(stop_signal)).await?;
+    Ok(())
+}
+
+/// Marks the answers of the paths without the version prefix, they go away in a later version
+async fn deprecated(mut response: Response) -> Response {
+    response.headers_mut().insert("deprecation", HeaderValue::from_static("true"));
+    response
+}
+
+/// Records every call that can change the controller, who made it and how it went.<br>
+/// `GET /command` is the only read that isn't one.
+async fn audit(State(app_state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+    let method = request.method().clone();
+    if (method == Method::GET || method == Method::HEAD) && !request.uri().path().ends_with("/command") {
+        return next.run(request).await;
+    }
+    let ip = request.extensions().get::<ConnectInfo<SocketAddr>>().map(|ConnectInfo(addr)| addr.ip().to_string());
+    let user = request.headers().get(USER_HEADER).and_then(|user| user.to_str().ok()).map(str::to_owned);
+    let action = format!("{} {}", method, request.uri());
+
+    let response = next.run(request).await;
+    let entry =
+        AuditEntry { timestamp: app_state.time_provider.now(), ip, user, action, status: response.status().as_u16() };
+    if let Err(e) = app_state.db.log_audit(entry) {
+        error!(error = ?e, "Failed to log the API call.");
+    }
+    response
+}
+
+/// The OS signals are handled by the shutdown coordinator, which flips `stop_signal` once the valves are closed
+async fn shutdown_signal(mut stop_signal: watch::Receiver<bool>) {
+    _ = stop_signal.wait_for(|stop| *stop).await;
+}
+
+/// How long a query waits on the watering loop before giving up on it
+const QUERY_WAIT: Duration = Duration::from_secs(2);
+
+/// Sends a query to the watering loop and waits for its answer, none when the loop didn't answer in time.<br>
+/// Each query carries its own reply, so concurrent callers never see each other's answers.
+pub async fn ask<T>(
+    sm_tx: &Sender<CtrlSignal>, query: impl FnOnce(Reply<T>) -> CtrlSignal, request: &'static str,
+) -> Option<T> {
+    let (reply, answer) = Reply::new();
+    let started = Instant::now();
+    sm_tx.send(query(reply)).ok()?;
+    let resp = timeout(QUERY_WAIT, answer).await.ok()?.ok()?;
+    metrics::registry().observe(SIGNAL_ROUNDTRIP_SECONDS, ("request", request), started.elapsed());
+    Some(resp)
+}
+
+/// Sends a command to the watering loop, that doesn't answer it
+fn tell(sm_tx: &Sender<CtrlSignal>, signal: CtrlSignal) -> Result<(), ApiError> {
+    sm_tx.send(signal).map(|_| ()).map_err(|_| ApiError::Internal("the watering loop isn't running".to_owned()))
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct EventsQuery {
+    /// Unix UTC timestamps, defaults to the last 24 hours
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+}
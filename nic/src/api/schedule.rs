@@ -0,0 +1,189 @@
+//! The weekly schedule of the auto mode, its programs and the watering windows
+
+use crate::{
+    config::{manager::ConfigReload, Config, WebServer, WindowOverrideCfg},
+    error::ApiError,
+    utils::load_sectors_into_hashmap,
+    watering::{
+        ds::{AppState, CtrlSignal, SectorInfo},
+        import::{import_controller, ControllerFormat, ImportReport},
+        schedule::validate,
+        schedule_file::{export, import, ScheduleFormat},
+        water_window::WaterWin,
+        watering_alg::{check_constraints, Schedule},
+    },
+};
+use axum::extract::{DefaultBodyLimit, Path, Query, State};
+use axum::http::header;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post, put};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+
+pub(super) fn routes(cfg: &WebServer) -> Router<Arc<AppState>> {
+    let upload = Router::new()
+        .route("/schedule/export", get(export_schedule).post(import_schedule))
+        .route("/schedule/auto", put(import_schedule))
+        .route("/import", post(import_from_controller))
+        .layer(DefaultBodyLimit::max(cfg.max_upload_bytes));
+    Router::new()
+        .merge(upload)
+        .route("/schedule/programs", get(list_programs))
+        .route("/schedule/programs/:program/:action", post(set_program))
+        .route("/window", get(get_windows))
+        .route("/window/overrides", get(get_window_overrides).put(set_window_overrides))
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct ScheduleQuery {
+    /// json or csv, defaults to json
+    #[serde(default)]
+    pub format: ScheduleFormat,
+}
+
+/// The weekly schedule of the auto mode
+pub async fn export_schedule(Query(query): Query<ScheduleQuery>, State(app_state): State<Arc<AppState>>) -> Response {
+    match app_state.db.load_auto_schedule() {
+        Ok(schedule) => {
+            let content_type = match query.format {
+                ScheduleFormat::Json => "application/json",
+                ScheduleFormat::Csv => "text/csv",
+            };
+            ([(header::CONTENT_TYPE, content_type)], export(&schedule, query.format)).into_response()
+        }
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+/// Replaces the weekly schedule with the one in the body, in the format of the export, where a cron expression can
+/// stand for the days and times of a plan. The running plans follow.
+/// A schedule that doesn't hold, with the constraints or the checks of `schedule::validate`, is refused whole.
+pub async fn import_schedule(
+    Query(query): Query<ScheduleQuery>, State(app_state): State<Arc<AppState>>, body: String,
+) -> Result<Json<String>, ApiError> {
+    let mut schedule = import(&body, query.format).map_err(|e| ApiError::BadRequest(e.to_string()))?;
+    check_schedule(&app_state, &schedule, load_sectors_into_hashmap(app_state.db.load_sectors()?))?;
+    // the disabled programs stay so
+    schedule.disabled = app_state.db.load_auto_schedule().map(|current| current.disabled).unwrap_or_default();
+    let days = schedule.entries.len();
+    app_state.db.save_auto_schedule(schedule.clone())?;
+    _ = app_state.sm_tx.send(CtrlSignal::ScheduleUpdate(schedule));
+    Ok(Json(format!("Schedule imported, {} day(s)", days)))
+}
+
+/// Every pair of runs too close for the sector constraints, and every run that makes no sense, in one error
+fn check_schedule(
+    app_state: &AppState, schedule: &Schedule, sectors: HashMap<u32, SectorInfo>,
+) -> Result<(), ApiError> {
+    let mut problems = check_constraints(schedule, &app_state.db.load_sector_constraints()?);
+    let issues = validate(schedule, &sectors, &app_state.config.current().watering);
+    problems.extend(issues.iter().map(|issue| issue.to_string()));
+    match problems.is_empty() {
+        true => Ok(()),
+        false => Err(ApiError::BadRequest(problems.join("; "))),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ImportQuery {
+    /// rachio or opensprinkler
+    pub format: ControllerFormat,
+}
+
+/// Replaces the weekly schedule with the one of another controller, see `import::import_controller`. Its sectors the
+/// database doesn't have are added with what the controller knew of them, the others keep their settings. Its
+/// programs are enabled or not as they were there. Checked as an imported schedule is.
+pub async fn import_from_controller(
+    Query(query): Query<ImportQuery>, State(app_state): State<Arc<AppState>>, body: String,
+) -> Result<Json<ImportReport>, ApiError> {
+    let transition = app_state.config.current().watering.sector_transation_secs;
+    let imported =
+        import_controller(&body, query.format, transition).map_err(|e| ApiError::BadRequest(e.to_string()))?;
+    let mut sectors = load_sectors_into_hashmap(app_state.db.load_sectors()?);
+    let added = imported.new_sectors(&sectors);
+    sectors.extend(added.iter().map(|sector| (sector.id, SectorInfo::from(sector))));
+    check_schedule(&app_state, &imported.schedule, sectors)?;
+
+    if !added.is_empty() {
+        app_state.db.import_sectors(added.clone())?;
+        _ = app_state.sm_tx.send(CtrlSignal::SectorsAdded(added.iter().map(SectorInfo::from).collect()));
+    }
+    app_state.db.save_auto_schedule(imported.schedule.clone())?;
+    let report = imported.report(&added);
+    for (program, &enabled) in &report.programs {
+        app_state.db.set_program_enabled(program.clone(), enabled)?;
+    }
+    _ = app_state.sm_tx.send(CtrlSignal::ScheduleUpdate(imported.schedule));
+    Ok(Json(report))
+}
+
+/// The programs of the auto schedule, and whether they are enabled
+pub async fn list_programs(State(app_state): State<Arc<AppState>>) -> Result<Json<BTreeMap<String, bool>>, ApiError> {
+    let schedule = app_state.db.load_auto_schedule()?;
+    Ok(Json(schedule.programs().into_iter().map(|(program, enabled)| (program.to_owned(), enabled)).collect()))
+}
+
+/// `enable` or `disable` a program, the running plans follow
+pub async fn set_program(
+    Path((program, action)): Path<(String, String)>, State(app_state): State<Arc<AppState>>,
+) -> Result<Json<String>, ApiError> {
+    let enabled = match action.as_str() {
+        "enable" => true,
+        "disable" => false,
+        _ => return Err(ApiError::BadRequest(format!("'{}' is not enable or disable", action))),
+    };
+    let mut schedule = app_state.db.load_auto_schedule()?;
+    if !schedule.programs().contains_key(program.as_str()) {
+        return Err(ApiError::NotFound(format!("no program '{}' in the schedule", program)));
+    }
+    app_state.db.set_program_enabled(program.clone(), enabled)?;
+    match enabled {
+        true => schedule.disabled.remove(&program),
+        false => schedule.disabled.insert(program.clone()),
+    };
+    _ = app_state.sm_tx.send(CtrlSignal::ScheduleUpdate(schedule));
+    Ok(Json(format!("Program {} {}d", program, action)))
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct WindowDay {
+    /// Unix UTC, both included. An empty window ends before it starts.
+    pub start: i64,
+    pub end: i64,
+    /// hours other than the `[watering]` ones
+    pub overridden: bool,
+}
+
+/// The watering windows of the next 7 days, the open one first
+pub async fn get_windows(State(app_state): State<Arc<AppState>>) -> Json<Vec<WindowDay>> {
+    let cfg = app_state.config.current();
+    let (hour_start, duration_hours) = (cfg.watering.window_start_hour, cfg.watering.window_duration_hours);
+    let now = app_state.time_provider.now();
+    let mut window = WaterWin::around(now, hour_start, duration_hours, &cfg.window_overrides);
+    let mut days = Vec::with_capacity(7);
+    for _ in 0..7 {
+        let overridden = (window.hour_start, window.duration_secs) != (hour_start, duration_hours * 3600);
+        days.push(WindowDay { start: window.day_start_time, end: window.day_end_time, overridden });
+        window = window.next_day(&cfg.window_overrides);
+    }
+    Json(days)
+}
+
+pub async fn get_window_overrides(State(app_state): State<Arc<AppState>>) -> Json<Vec<WindowOverrideCfg>> {
+    Json(app_state.config.current().window_overrides.clone())
+}
+
+/// Replaces the window overrides of the running config, checked as the config file is. The window and the plans
+/// follow. A reload of the file brings back the ones in it.
+pub async fn set_window_overrides(
+    State(app_state): State<Arc<AppState>>, Json(overrides): Json<Vec<WindowOverrideCfg>>,
+) -> Result<Json<ConfigReload>, ApiError> {
+    let mut cfg = Config::clone(&app_state.config.current());
+    cfg.window_overrides = overrides;
+    crate::config::validate::validate(&cfg).map_err(|e| ApiError::BadRequest(e.to_string()))?;
+    Ok(Json(app_state.config.apply(cfg)))
+}
@@ -0,0 +1,256 @@
+//! The sectors: test runs, calibration, moisture readings, seasonal targets and what was learned of them
+
+use super::ask;
+use crate::{
+    error::ApiError,
+    utils::load_sectors_into_hashmap,
+    watering::{
+        calibration::{Calibrated, CalibrationMeasure, MAX_CALIBRATION_SECS},
+        ds::{AppState, CtrlSignal},
+        efficiency::{efficiency, SectorEfficiency, REPORT_WEEKS},
+        learning::{LearnedParams, MoistureReading},
+        test_run::{TestRun, MAX_TEST_SECS},
+        watering_alg::SectorTarget,
+    },
+};
+use axum::extract::{Path, Query, State};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+pub(super) fn routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/manual", post(manual_start))
+        .route("/test-run", get(get_test_run).post(start_test_run))
+        .route("/stats/efficiency", get(get_efficiency))
+        .route("/sectors/:id/calibrate", post(start_calibration))
+        .route("/sectors/:id/calibrate/result", post(finish_calibration))
+        .route("/sectors/:id/moisture", post(add_moisture_reading))
+        .route("/sectors/:id/targets", get(get_sector_targets).put(set_sector_targets))
+        .route("/learning", get(get_learned_params))
+        .route("/learning/:sector/:action", post(resolve_learned_params))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ManualSector {
+    pub sector: u32,
+    pub duration_secs: i64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ManualStartRequest {
+    pub sectors: Vec<ManualSector>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ManualStartResponse {
+    /// its id in `GET /cycle`
+    pub cycle: i64,
+}
+
+/// Waters the sectors once, in order, in manual mode
+pub async fn manual_start(
+    State(app_state): State<Arc<AppState>>, Json(request): Json<ManualStartRequest>,
+) -> Result<Json<ManualStartResponse>, ApiError> {
+    let sectors = request.sectors.into_iter().map(|sec| (sec.sector, sec.duration_secs)).collect();
+    Ok(Json(ManualStartResponse { cycle: request_manual_start(&app_state, sectors).await? }))
+}
+
+/// The manual watering as the watering loop starts it, for the HTTP and the gRPC API
+pub async fn request_manual_start(app_state: &AppState, sectors: Vec<(u32, i64)>) -> Result<i64, ApiError> {
+    let max_secs = app_state.config.current().watering.max_duration_secs;
+    if sectors.is_empty() {
+        return Err(ApiError::BadRequest("no sector to water".to_owned()));
+    }
+    if let Some((sector, _)) = sectors.iter().find(|(_, secs)| !(1..=max_secs).contains(secs)) {
+        return Err(ApiError::BadRequest(format!("duration_secs of sector {} goes from 1 to {}", sector, max_secs)));
+    }
+    match ask(&app_state.sm_tx, move |reply| CtrlSignal::ManualStart(sectors, reply), "manual_start").await {
+        Some(Ok(cycle)) => Ok(cycle),
+        Some(Err(e)) => Err(ApiError::Conflict(e)),
+        None => Err(ApiError::no_answer("manual_start")),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TestRunRequest {
+    pub seconds_per_sector: i64,
+}
+
+/// Opens every sector in turn to check the heads. Answers with the plan, the outcome is at `GET /test-run`.
+pub async fn start_test_run(
+    State(app_state): State<Arc<AppState>>, Json(request): Json<TestRunRequest>,
+) -> Result<Json<TestRun>, ApiError> {
+    let seconds = request.seconds_per_sector;
+    if !(1..=MAX_TEST_SECS).contains(&seconds) {
+        return Err(ApiError::BadRequest(format!("seconds_per_sector goes from 1 to {}", MAX_TEST_SECS)));
+    }
+    match ask(&app_state.sm_tx, move |reply| CtrlSignal::TestRun(seconds, reply), "test_run").await {
+        Some(Ok(run)) => Ok(Json(run)),
+        Some(Err(e)) => Err(ApiError::Conflict(e)),
+        None => Err(ApiError::no_answer("test_run")),
+    }
+}
+
+/// How each sector of the running or last test run did
+pub async fn get_test_run(State(app_state): State<Arc<AppState>>) -> Result<Json<TestRun>, ApiError> {
+    match ask(&app_state.sm_tx, CtrlSignal::GetTestRun, "test_run").await {
+        Some(Some(run)) => Ok(Json(run)),
+        Some(None) => Err(ApiError::NotFound("no test run since the start".to_owned())),
+        None => Err(ApiError::no_answer("test_run")),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CalibrationRequest {
+    pub seconds: i64,
+}
+
+/// Runs the sector alone for `seconds`, whatever the mode and the window, for catch cups or the water meter.
+/// Once it ended, the measure goes to `/sectors/{id}/calibrate/result`.
+pub async fn start_calibration(
+    Path(id): Path<u32>, State(app_state): State<Arc<AppState>>, Json(request): Json<CalibrationRequest>,
+) -> Result<Json<TestRun>, ApiError> {
+    let seconds = request.seconds;
+    if !(60..=MAX_CALIBRATION_SECS).contains(&seconds) {
+        return Err(ApiError::BadRequest(format!("seconds goes from 60 to {}", MAX_CALIBRATION_SECS)));
+    }
+    if !app_state.db.load_sectors()?.iter().any(|sec| sec.id == id) {
+        return Err(ApiError::NotFound(format!("no sector {}", id)));
+    }
+    match ask(&app_state.sm_tx, move |reply| CtrlSignal::Calibrate(id, seconds, reply), "calibrate").await {
+        Some(Ok(run)) => Ok(Json(run)),
+        Some(Err(e)) => Err(ApiError::Conflict(e)),
+        None => Err(ApiError::no_answer("calibrate")),
+    }
+}
+
+/// What was measured over the calibration run of the sector, the `sprinkler_debit` it works out to is saved and
+/// watered with from then on
+pub async fn finish_calibration(
+    Path(id): Path<u32>, State(app_state): State<Arc<AppState>>, Json(measure): Json<CalibrationMeasure>,
+) -> Result<Json<Calibrated>, ApiError> {
+    measure.check().map_err(ApiError::BadRequest)?;
+    let query = move |reply| CtrlSignal::CalibrationResult(id, measure, reply);
+    match ask(&app_state.sm_tx, query, "calibration_result").await {
+        Some(Ok(calibrated)) => Ok(Json(calibrated)),
+        Some(Err(e)) => Err(ApiError::Conflict(e)),
+        None => Err(ApiError::no_answer("calibration_result")),
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct EfficiencyQuery {
+    /// whole weeks before today, 1 to 52
+    pub weeks: Option<i64>,
+}
+
+/// Per sector, what the wizard planned against what was watered and what it did for the weekly target
+pub async fn get_efficiency(
+    Query(query): Query<EfficiencyQuery>, State(app_state): State<Arc<AppState>>,
+) -> Result<Json<Vec<SectorEfficiency>>, ApiError> {
+    let weeks = query.weeks.unwrap_or(REPORT_WEEKS);
+    if !(1..=52).contains(&weeks) {
+        return Err(ApiError::BadRequest(format!("weeks must be 1 to 52, not {}", weeks)));
+    }
+    let sectors = load_sectors_into_hashmap(app_state.db.load_sectors()?);
+    let now = app_state.time_provider.now();
+    Ok(Json(efficiency(app_state.db.as_ref(), &sectors, now, weeks)?))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MoistureRequest {
+    /// now when not given
+    pub timestamp: Option<i64>,
+    /// cm of water in the root zone
+    pub water_cm: f64,
+}
+
+/// A soil moisture probe reading, for the debit and percolation estimates
+pub async fn add_moisture_reading(
+    Path(id): Path<u32>, State(app_state): State<Arc<AppState>>, Json(request): Json<MoistureRequest>,
+) -> Result<Json<String>, ApiError> {
+    if !request.water_cm.is_finite() || request.water_cm < 0. {
+        return Err(ApiError::BadRequest("water_cm must not be negative".to_owned()));
+    }
+    if !app_state.db.load_sectors()?.iter().any(|sec| sec.id == id) {
+        return Err(ApiError::NotFound(format!("no sector {}", id)));
+    }
+    let timestamp = request.timestamp.unwrap_or_else(|| app_state.time_provider.now());
+    let reading = MoistureReading { sector: id, timestamp, water_cm: request.water_cm };
+    app_state.db.add_moisture_reading(reading)?;
+    Ok(Json(format!("Reading of sector {} stored", id)))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MonthTarget {
+    /// 1 January .. 12 December
+    pub month: u32,
+    /// cm a week
+    pub weekly_target: f64,
+}
+
+/// The seasonal curve of the sector, the months not in it take its `weekly_target`
+pub async fn get_sector_targets(
+    Path(id): Path<u32>, State(app_state): State<Arc<AppState>>,
+) -> Result<Json<Vec<SectorTarget>>, ApiError> {
+    let targets = app_state.db.load_sector_targets()?;
+    Ok(Json(targets.into_iter().filter(|target| target.sector == id).collect()))
+}
+
+/// Replaces the seasonal curve of the sector, an empty one goes back to its `weekly_target` all year.
+/// The running plans follow.
+pub async fn set_sector_targets(
+    Path(id): Path<u32>, State(app_state): State<Arc<AppState>>, Json(request): Json<Vec<MonthTarget>>,
+) -> Result<Json<Vec<SectorTarget>>, ApiError> {
+    let sectors = app_state.db.load_sectors()?;
+    if !sectors.iter().any(|sec| sec.id == id) {
+        return Err(ApiError::NotFound(format!("no sector {}", id)));
+    }
+    let mut targets: Vec<SectorTarget> = Vec::with_capacity(request.len());
+    for MonthTarget { month, weekly_target } in request {
+        if !(1..=12).contains(&month) {
+            return Err(ApiError::BadRequest(format!("month {} is not 1 to 12", month)));
+        }
+        if !weekly_target.is_finite() || weekly_target < 0. {
+            return Err(ApiError::BadRequest(format!("the target of month {} must not be negative", month)));
+        }
+        if targets.iter().any(|target| target.month == month) {
+            return Err(ApiError::BadRequest(format!("month {} is given twice", month)));
+        }
+        targets.push(SectorTarget { sector: id, month, weekly_target });
+    }
+    targets.sort_by_key(|target| target.month);
+    app_state.db.set_sector_targets(id, targets.clone())?;
+    _ = app_state.sm_tx.send(CtrlSignal::SectorTargets(id, targets.clone()));
+    Ok(Json(targets))
+}
+
+/// Every debit and percolation estimate, pending, accepted or rejected, with what it was worked out from
+pub async fn get_learned_params(State(app_state): State<Arc<AppState>>) -> Result<Json<Vec<LearnedParams>>, ApiError> {
+    let learned = app_state.db.load_learned_params()?;
+    Ok(Json(learned))
+}
+
+/// `accept` or `reject` the pending estimate of a sector, an accepted one is watered with from then on
+pub async fn resolve_learned_params(
+    Path((sector, action)): Path<(u32, String)>, State(app_state): State<Arc<AppState>>,
+) -> Result<Json<LearnedParams>, ApiError> {
+    let accept = match action.as_str() {
+        "accept" => true,
+        "reject" => false,
+        _ => return Err(ApiError::BadRequest(format!("'{}' is not accept or reject", action))),
+    };
+    if !app_state.db.load_sectors()?.iter().any(|sec| sec.id == sector) {
+        return Err(ApiError::NotFound(format!("no sector {}", sector)));
+    }
+    let resolved = app_state.db.resolve_learned_params(sector, accept)?;
+    let Some(learned) = resolved else {
+        return Err(ApiError::NotFound(format!("no pending estimate for sector {}", sector)));
+    };
+    if accept {
+        _ = app_state.sm_tx.send(CtrlSignal::LearnedAccepted(learned.clone()));
+    }
+    Ok(Json(learned))
+}
@@ -0,0 +1,225 @@
+//! The watering loop: its state and mode, the cycles and the emergency stop
+
+use super::{ask, tell, EventsQuery};
+use crate::{
+    clock::ClockStatus,
+    config::Config,
+    db::maintenance::DbCheck,
+    error::ApiError,
+    links::LinkState,
+    sensors::interlock::InterlockStatus,
+    supervisor::TaskStatus,
+    watering::{
+        ds::{AppState, CtrlSignal, CycleRun, SystemEvent, WeatherConditions},
+        modes::Mode,
+    },
+    weather::freshness::FreshnessStatus,
+};
+use axum::extract::{Path, Query, State};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, str::FromStr, sync::Arc};
+
+pub(super) fn routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/state", get(get_state))
+        .route("/cycle", get(get_cycle))
+        .route("/cycles", get(get_cycle_runs))
+        .route("/switch/:mode", post(switch_mode))
+        .route("/command", get(send_command)) // Example: command=stop or command=auto
+        .route("/healthz", get(healthz))
+        .route("/status", get(get_status))
+        .route("/events/system", get(get_system_events))
+        .route("/interlock", get(get_interlock))
+        .route("/estop", post(emergency_stop))
+        .route("/estop/clear", post(clear_emergency_stop))
+}
+
+pub async fn switch_mode(Path(mode): Path<String>, app_state: State<Arc<AppState>>) -> Result<Json<String>, ApiError> {
+    let mode = request_mode(&app_state, &mode)?;
+    Ok(Json(format!("Switched to {} mode", mode)))
+}
+
+/// Asks the watering loop to change to `mode`, a bad request if there is no such mode
+pub fn request_mode(app_state: &AppState, mode: &str) -> Result<Mode, ApiError> {
+    let mode = Mode::from_str(mode).map_err(|_| ApiError::BadRequest(format!("'{}' is not a mode", mode)))?;
+    tell(&app_state.sm_tx, CtrlSignal::ChgMode(mode))?;
+    Ok(mode)
+}
+
+/// Closes every valve and keeps them closed until `/estop/clear`
+pub async fn emergency_stop(app_state: State<Arc<AppState>>) -> Result<Json<String>, ApiError> {
+    tell(&app_state.sm_tx, CtrlSignal::EmergencyStop)?;
+    Ok(Json("Emergency stop".to_owned()))
+}
+
+pub async fn clear_emergency_stop(app_state: State<Arc<AppState>>) -> Result<Json<String>, ApiError> {
+    tell(&app_state.sm_tx, CtrlSignal::ClearEmergencyStop)?;
+    Ok(Json("Emergency stop cleared".to_owned()))
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct WateringStateResponse {
+    pub error: Option<String>,
+    pub mode: Option<String>,
+    pub state: Option<String>,
+    pub current_cycle: Option<String>,
+}
+
+pub async fn get_state(State(app_state): State<Arc<AppState>>) -> Result<Json<WateringStateResponse>, ApiError> {
+    Ok(Json(request_state(&app_state).await?))
+}
+
+/// The state as the watering loop answers it, for the HTTP and the gRPC API
+pub async fn request_state(app_state: &AppState) -> Result<WateringStateResponse, ApiError> {
+    ask(&app_state.sm_tx, CtrlSignal::GetState, "state").await.ok_or_else(|| ApiError::no_answer("state"))
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct HealthResponse {
+    /// "ok", or "degraded" when we are planning without fresh weather data, a background task isn't running,
+    /// an MQTT client lost its broker, the last database check failed or the time isn't trusted
+    pub status: String,
+    pub weather: FreshnessStatus,
+    pub tasks: BTreeMap<&'static str, TaskStatus>,
+    pub links: BTreeMap<&'static str, LinkState>,
+    /// none before the first check
+    pub database: Option<DbCheck>,
+    pub clock: ClockStatus,
+}
+
+pub async fn healthz(State(app_state): State<Arc<AppState>>) -> Json<HealthResponse> {
+    let weather = app_state.freshness.status(app_state.time_provider.now());
+    let links = app_state.links.snapshot();
+    let connected = links.values().all(|link| link.connected);
+    let healthy = app_state.supervisor.healthy() && app_state.db_health.healthy() && app_state.clock.trusted();
+    let status = if weather.stale || !healthy || !connected { "degraded" } else { "ok" };
+    let (tasks, database, clock) =
+        (app_state.supervisor.snapshot(), app_state.db_health.last(), app_state.clock.status());
+    Json(HealthResponse { status: status.to_owned(), weather, tasks, links, database, clock })
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ActiveSector {
+    pub id: u32,
+    pub remaining_secs: i64,
+}
+
+/// What the watering loop knows of itself
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct MachineStatus {
+    pub mode: String,
+    /// idle, watering, paused or stopped
+    pub state: String,
+    /// watering or paused
+    pub active_sector: Option<ActiveSector>,
+    /// Unix UTC timestamp of the next planned sector, none in manual or off
+    pub next_run: Option<i64>,
+    pub faulted: Vec<u32>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct StatusResponse {
+    /// none when the watering loop didn't answer in time
+    pub machine: Option<MachineStatus>,
+    pub weather: Option<WeatherConditions>,
+    pub freshness: FreshnessStatus,
+    /// mm, of yesterday
+    pub last_rain: Option<f64>,
+    pub last_et: Option<f64>,
+    pub links: BTreeMap<&'static str, LinkState>,
+    pub interlock: InterlockStatus,
+    /// bytes of the database file
+    pub db_size: Option<u64>,
+    /// the water restriction of today, none outside them
+    pub restriction: Option<RestrictionStatus>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct RestrictionStatus {
+    /// `YYYY-MM-DD`, both included
+    pub from: String,
+    pub to: String,
+    /// % of the ET the sectors get back
+    pub et_pct: f64,
+    /// the sectors that get all of it
+    pub exempt: Vec<u32>,
+}
+
+impl RestrictionStatus {
+    pub fn at(cfg: &Config, time: i64) -> Option<Self> {
+        let restriction = cfg.restrictions.iter().find(|restriction| restriction.contains(time))?;
+        Some(Self {
+            from: restriction.from.clone(),
+            to: restriction.to.clone(),
+            et_pct: restriction.et_pct,
+            exempt: cfg.sectors.iter().filter(|sector| sector.deficit_exempt).map(|sector| sector.id).collect(),
+        })
+    }
+}
+
+/// Everything a dashboard shows, in one call
+pub async fn get_status(State(app_state): State<Arc<AppState>>) -> Json<StatusResponse> {
+    let machine = ask(&app_state.sm_tx, CtrlSignal::GetStatus, "status").await;
+
+    let now = app_state.time_provider.now();
+    let cfg = app_state.config.current();
+    Json(StatusResponse {
+        machine,
+        weather: app_state.db.get_current_weather(),
+        freshness: app_state.freshness.status(now),
+        last_rain: app_state.db.get_lastday_rain(now),
+        last_et: app_state.db.get_daily_et(now),
+        links: app_state.links.snapshot(),
+        interlock: app_state.interlock.status(),
+        db_size: fs::metadata(&cfg.database.name).ok().map(|meta| meta.len()),
+        restriction: RestrictionStatus::at(&cfg, now),
+    })
+}
+
+/// The cycles that started in the period, a day up to now by default, with the sectors planned and watered
+pub async fn get_cycle_runs(
+    Query(query): Query<EventsQuery>, State(app_state): State<Arc<AppState>>,
+) -> Result<Json<Vec<CycleRun>>, ApiError> {
+    let to = query.to.unwrap_or_else(|| app_state.time_provider.now() + 1);
+    let from = query.from.unwrap_or(to - 86_400);
+    let runs = app_state.db.load_cycle_runs(from, to)?;
+    Ok(Json(runs))
+}
+
+/// State machine audit trail, oldest first
+pub async fn get_system_events(
+    Query(query): Query<EventsQuery>, State(app_state): State<Arc<AppState>>,
+) -> Json<Vec<SystemEvent>> {
+    let to = query.to.unwrap_or_else(|| app_state.time_provider.now() + 1);
+    let from = query.from.unwrap_or(to - 86_400);
+    Json(app_state.db.load_system_events(from, to).unwrap_or_default())
+}
+
+/// Valves commanded open and the concurrency rule they are checked against
+pub async fn get_interlock(State(app_state): State<Arc<AppState>>) -> Json<InterlockStatus> {
+    Json(app_state.interlock.status())
+}
+
+pub async fn send_command(State(_app_state): State<Arc<AppState>>) -> String {
+    // Parse command and modify system state
+    // TODO:
+    "Command received".to_string()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CycleResponse {
+    pub error: Option<String>,
+    pub id: Option<i64>,
+    pub instructions: Option<Vec<(u32, String)>>, // Instruction details: sector and duration
+}
+
+pub async fn get_cycle(State(app_state): State<Arc<AppState>>) -> Result<Json<CycleResponse>, ApiError> {
+    Ok(Json(request_cycle(&app_state).await?))
+}
+
+/// The running cycle as the watering loop answers it, for the HTTP and the gRPC API
+pub async fn request_cycle(app_state: &AppState) -> Result<CycleResponse, ApiError> {
+    ask(&app_state.sm_tx, CtrlSignal::GetCycle, "cycle").await.ok_or_else(|| ApiError::no_answer("cycle"))
+}
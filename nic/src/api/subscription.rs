@@ -0,0 +1,185 @@
+//! What a WebSocket client asked for: its topics, and how often it takes a sample of the weather. A wall display on
+//! a battery doesn't want the wind every three seconds.
+
+use crate::watering::ds::CtrlSignal;
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    str::FromStr,
+    time::Duration,
+};
+use tokio::time::Instant;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Topic {
+    /// every observation of the station, as `WeatherData`
+    Observations,
+    /// the wind between the observations, as `RapidWind`
+    RapidWind,
+    /// the rain and wind signals the thresholds derive from them
+    Signals,
+    /// what the state machine does, as `StateEvent`
+    Events,
+    /// the nightly `DailyReport`
+    Reports,
+}
+
+impl Topic {
+    /// Only the last of these is worth sending, the others all go
+    fn is_sample(self) -> bool {
+        matches!(self, Topic::Observations | Topic::RapidWind)
+    }
+
+    /// The topic of a signal and it as JSON, none when no client gets it
+    pub fn message(signal: &CtrlSignal) -> Option<(Topic, String)> {
+        let (topic, json) = match signal {
+            CtrlSignal::WeatherData(data) => (Topic::Observations, serde_json::to_string(data)),
+            CtrlSignal::RapidWind(wind) => (Topic::RapidWind, serde_json::to_string(wind)),
+            CtrlSignal::Weather(signal) => (Topic::Signals, serde_json::to_string(signal)),
+            CtrlSignal::StateChanged(evt) => (Topic::Events, serde_json::to_string(evt)),
+            CtrlSignal::DailyReport(report) => (Topic::Reports, serde_json::to_string(report)),
+            _ => return None,
+        };
+        Some((topic, json.ok()?))
+    }
+}
+
+impl FromStr for Topic {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(serde_json::Value::String(s.to_owned()))
+            .map_err(|_| format!("'{}' is not observations, rapid_wind, signals, events or reports", s))
+    }
+}
+
+/// Sent as a text message, it replaces the one of the query
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Subscription {
+    /// what was sent before the topics when left out: the observations, the events and the reports
+    #[serde(default = "default_topics")]
+    pub topics: BTreeSet<Topic>,
+    /// at most an observation, and a rapid wind, every this many seconds, the last one. 0 for each of them.
+    #[serde(default)]
+    pub every_secs: u64,
+}
+
+fn default_topics() -> BTreeSet<Topic> {
+    BTreeSet::from([Topic::Observations, Topic::Events, Topic::Reports])
+}
+
+impl Default for Subscription {
+    fn default() -> Self {
+        Self { topics: default_topics(), every_secs: 0 }
+    }
+}
+
+/// `/ws/weather?topics=observations,signals&every_secs=5`
+#[derive(Debug, Default, Deserialize)]
+pub struct SubscriptionQuery {
+    pub topics: Option<String>,
+    pub every_secs: Option<u64>,
+}
+
+impl SubscriptionQuery {
+    pub fn subscription(&self) -> Result<Subscription, String> {
+        let topics = match &self.topics {
+            Some(topics) => topics.split(',').map(|topic| topic.trim().parse()).collect::<Result<_, _>>()?,
+            None => default_topics(),
+        };
+        Ok(Subscription { topics, every_secs: self.every_secs.unwrap_or_default() })
+    }
+}
+
+/// Holds back the samples that come sooner than `every` after the last one sent of their topic, keeping the last
+#[derive(Debug)]
+pub struct Throttle {
+    every: Duration,
+    sent: HashMap<Topic, Instant>,
+    held: BTreeMap<Topic, String>,
+}
+
+impl Throttle {
+    pub fn new(every_secs: u64) -> Self {
+        Self { every: Duration::from_secs(every_secs), sent: HashMap::new(), held: BTreeMap::new() }
+    }
+
+    /// The message to send now, none when it is held until its time
+    pub fn offer(&mut self, topic: Topic, json: String, now: Instant) -> Option<String> {
+        if !topic.is_sample() || self.every.is_zero() {
+            return Some(json);
+        }
+        match self.sent.get(&topic) {
+            Some(&sent) if now < sent + self.every => {
+                self.held.insert(topic, json);
+                None
+            }
+            _ => {
+                self.sent.insert(topic, now);
+                Some(json)
+            }
+        }
+    }
+
+    /// When the first of the held samples can go
+    pub fn next_due(&self) -> Option<Instant> {
+        self.held.keys().filter_map(|topic| self.sent.get(topic)).map(|&sent| sent + self.every).min()
+    }
+
+    /// The held samples that can go at `now`
+    pub fn due(&mut self, now: Instant) -> Vec<String> {
+        let topics: Vec<Topic> = self
+            .held
+            .keys()
+            .copied()
+            .filter(|topic| self.sent.get(topic).is_none_or(|&sent| now >= sent + self.every))
+            .collect();
+        let mut due = Vec::new();
+        for topic in topics {
+            if let Some(json) = self.held.remove(&topic) {
+                due.extend(self.offer(topic, json, now));
+            }
+        }
+        due
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_the_query() {
+        let query = SubscriptionQuery { topics: Some("rapid_wind, signals".to_owned()), every_secs: Some(5) };
+        let subscription = query.subscription().unwrap();
+        assert_eq!(subscription.topics, BTreeSet::from([Topic::RapidWind, Topic::Signals]));
+        assert_eq!(subscription.every_secs, 5);
+        assert_eq!(SubscriptionQuery::default().subscription().unwrap(), Subscription::default());
+
+        let query = SubscriptionQuery { topics: Some("wind".to_owned()), every_secs: None };
+        assert!(query.subscription().unwrap_err().starts_with("'wind' is not observations"));
+        let message: Subscription = serde_json::from_str(r#"{"topics": ["events"], "every_secs": 60}"#).unwrap();
+        assert_eq!(message.topics, BTreeSet::from([Topic::Events]));
+    }
+
+    #[test]
+    fn keeps_the_last_sample_until_its_time() {
+        let start = Instant::now();
+        let mut throttle = Throttle::new(5);
+        assert_eq!(throttle.offer(Topic::RapidWind, "1".to_owned(), start), Some("1".to_owned()));
+        assert_eq!(throttle.offer(Topic::RapidWind, "2".to_owned(), start + Duration::from_secs(1)), None);
+        assert_eq!(throttle.offer(Topic::RapidWind, "3".to_owned(), start + Duration::from_secs(3)), None);
+        // the other topics aren't held back by it
+        assert!(throttle.offer(Topic::Observations, "obs".to_owned(), start + Duration::from_secs(3)).is_some());
+        assert!(throttle.offer(Topic::Events, "evt".to_owned(), start + Duration::from_secs(3)).is_some());
+
+        assert_eq!(throttle.next_due(), Some(start + Duration::from_secs(5)));
+        assert!(throttle.due(start + Duration::from_secs(4)).is_empty());
+        assert_eq!(throttle.due(start + Duration::from_secs(5)), ["3"]);
+        assert_eq!(throttle.next_due(), None);
+
+        let mut every = Throttle::new(0);
+        assert!((0..3).all(|n| every.offer(Topic::RapidWind, n.to_string(), start).is_some()));
+    }
+}
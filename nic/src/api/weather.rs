@@ -0,0 +1,164 @@
+//! The weather and the devices, and the live updates of the WebSocket
+
+#[cfg(feature = "sim")]
+use super::tell;
+use super::{ask, subscription::*};
+#[cfg(feature = "sim")]
+use crate::weather::generator::SyntheticWeather;
+use crate::{
+    error::{ApiError, ErrorBody},
+    metrics,
+    watering::ds::{AppState, CtrlSignal, WeatherData},
+    weather::api::{get_forecast, list_devices, query_weather},
+};
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::Response;
+use axum::routing::get;
+use axum::Router;
+#[cfg(feature = "sim")]
+use axum::{routing::post, Json};
+use std::sync::Arc;
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+use tokio::time::{sleep_until, Instant};
+use tracing::warn;
+
+pub(super) fn routes() -> Router<Arc<AppState>> {
+    let routes = Router::new()
+        .route("/ws/weather", get(ws_handler))
+        .route("/devices", get(list_devices))
+        .route("/weather", get(query_weather))
+        .route("/weather/forecast", get(get_forecast));
+    #[cfg(feature = "sim")]
+    let routes = routes.route("/weather/generate", post(generate_weather).delete(stop_weather));
+    routes
+}
+
+/// Made up observations next to the station's, for demos and soak tests. They aren't stored.
+#[cfg(feature = "sim")]
+async fn generate_weather(
+    State(state): State<Arc<AppState>>, Json(weather): Json<SyntheticWeather>,
+) -> Result<Json<String>, ApiError> {
+    weather.validate().map_err(ApiError::BadRequest)?;
+    tell(&state.sm_tx, CtrlSignal::GenWeather(Some(weather)))?;
+    Ok(Json("Synthetic weather started".to_owned()))
+}
+
+#[cfg(feature = "sim")]
+async fn stop_weather(State(state): State<Arc<AppState>>) -> Result<Json<String>, ApiError> {
+    tell(&state.sm_tx, CtrlSignal::GenWeather(None))?;
+    Ok(Json("Synthetic weather stopped".to_owned()))
+}
+
+// Handler for the WebSocket upgrade, a subscription that doesn't parse is refused before it
+async fn ws_handler(
+    ws: WebSocketUpgrade, Query(query): Query<SubscriptionQuery>, State(state): State<Arc<AppState>>,
+) -> Result<Response, ApiError> {
+    let subscription = query.subscription().map_err(ApiError::BadRequest)?;
+    Ok(ws.on_upgrade(move |socket| handle_ws_connection(socket, state, subscription)))
+}
+
+/// The next signal of a bus, pending forever without one
+async fn next(rx: &mut Option<Receiver<CtrlSignal>>) -> Result<CtrlSignal, RecvError> {
+    match rx {
+        Some(rx) => rx.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+// Handle the WebSocket connection
+async fn handle_ws_connection(mut socket: WebSocket, state: Arc<AppState>, mut subscription: Subscription) {
+    let mut web_rx = state.web_rx.resubscribe();
+    // the weather signals go to the state machine only, the MQTT publisher listens to both buses
+    let signals = |subscription: &Subscription| subscription.topics.contains(&Topic::Signals);
+    let mut sm_rx = signals(&subscription).then(|| state.sm_tx.subscribe());
+    let mut throttle = Throttle::new(subscription.every_secs);
+
+    // Send updates to the client
+    loop {
+        let due = throttle.next_due();
+        let received = tokio::select! {
+            _ = sleep_until(due.unwrap_or_else(Instant::now)), if due.is_some() => {
+                if send_all(&mut socket, throttle.due(Instant::now())).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+            message = socket.recv() => {
+                let Some(Ok(message)) = message else { break };
+                let reply = match message {
+                    Message::Text(text) => match serde_json::from_str::<Subscription>(&text) {
+                        Ok(changed) => {
+                            throttle = Throttle::new(changed.every_secs);
+                            sm_rx = match sm_rx.take() {
+                                Some(rx) if signals(&changed) => Some(rx),
+                                _ => signals(&changed).then(|| state.sm_tx.subscribe()),
+                            };
+                            subscription = changed;
+                            continue;
+                        }
+                        Err(e) => ErrorBody { status: StatusCode::BAD_REQUEST.as_u16(), error: e.to_string() },
+                    },
+                    Message::Close(_) => break,
+                    _ => continue,
+                };
+                if send_all(&mut socket, serde_json::to_string(&reply).into_iter().collect()).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+            signal = web_rx.recv() => signal,
+            signal = next(&mut sm_rx) => match signal {
+                Ok(signal @ CtrlSignal::Weather(_)) => Ok(signal),
+                Ok(_) => continue,
+                Err(e) => Err(e),
+            },
+        };
+        let (topic, json) = match received {
+            Ok(signal) => match Topic::message(&signal) {
+                Some(message) => message,
+                None => continue,
+            },
+            Err(RecvError::Lagged(missed)) => {
+                metrics::dropped("websocket", missed);
+                warn!(missed, "WebSocket client fell behind, sending the current state instead.");
+                let resync = resync_messages(&state, &subscription).await;
+                if send_all(&mut socket, resync).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+        if !subscription.topics.contains(&topic) {
+            continue;
+        }
+        let Some(json) = throttle.offer(topic, json, Instant::now()) else { continue };
+        if socket.send(Message::Text(json)).await.is_err() {
+            break; // Exit loop if client disconnects
+        }
+    }
+}
+
+/// What a client that lost updates needs to catch up: the last weather and where the machine is, of its topics
+async fn resync_messages(state: &AppState, subscription: &Subscription) -> Vec<String> {
+    let weather = match subscription.topics.contains(&Topic::Observations) {
+        true => state.db.get_current_weather().map(|obs| WeatherData::from(&obs)),
+        false => None,
+    };
+    let machine = match subscription.topics.contains(&Topic::Events) {
+        true => ask(&state.sm_tx, CtrlSignal::GetStatus, "status").await,
+        false => None,
+    };
+    let weather = weather.and_then(|data| serde_json::to_string(&data).ok());
+    let machine = machine.and_then(|status| serde_json::to_string(&status).ok());
+    weather.into_iter().chain(machine).collect()
+}
+
+async fn send_all(socket: &mut WebSocket, messages: Vec<String>) -> Result<(), axum::Error> {
+    for json in messages {
+        socket.send(Message::Text(json)).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,298 @@
+#[cfg(feature = "sim")]
+use crate::weather::generator::SyntheticWeather;
+use crate::{
+    config::{init::check_broker, Config, SectorCfg, WeatherStation},
+    db::{
+        configure, import_sectors, initialize, load_auto_schedule, load_sector_constraints, load_sectors,
+        save_auto_schedule, set_program_enabled,
+    },
+    error::AppError,
+    utils::load_sectors_into_hashmap,
+    watering::{
+        ds::{DailyPlan, SectorInfo, WaterSector},
+        import::{import_controller, ControllerFormat},
+        schedule::validate,
+        schedule_file::{export, import, ScheduleFormat},
+        watering_alg::{check_constraints, Schedule, ScheduleEntry, ScheduleType},
+    },
+    weather::{
+        forecast::fetch_forecast, openweathermap::fetch_current_weather, provider::ProviderKind,
+        tempest::fetch_observation, udp::parse_udp_packet,
+    },
+};
+use chrono::Weekday;
+use rusqlite::Connection;
+use std::{fmt::Write, fs, path::Path, time::Duration};
+use tokio::net::UdpSocket;
+
+const UDP_WAIT: Duration = Duration::from_secs(10);
+
+/// The database of the config, with the current schema. One in memory would be gone with the command.
+fn open(cfg: &Config) -> Result<Connection, AppError> {
+    if cfg.database.in_memory() {
+        let name = &cfg.database.name;
+        return Err(AppError::ConfigError(format!("database.name '{}' is in memory, nothing to read or keep", name)));
+    }
+    let conn = Connection::open(&cfg.database.name)?;
+    configure(&conn, &cfg.database)?;
+    initialize(&conn)?;
+    Ok(conn)
+}
+
+/// Every pair of runs too close for the sector constraints, and every run that makes no sense, in one error.
+/// The `added` sectors are checked as if they were in the database.
+fn check_schedule(conn: &Connection, cfg: &Config, schedule: &Schedule, added: &[SectorCfg]) -> Result<(), AppError> {
+    let mut problems = check_constraints(schedule, &load_sector_constraints(conn, &cfg.site.id)?);
+    let mut sectors = load_sectors_into_hashmap(load_sectors(conn, &cfg.site.id)?);
+    sectors.extend(added.iter().map(|sector| (sector.id, SectorInfo::from(sector))));
+    problems.extend(validate(schedule, &sectors, &cfg.watering).iter().map(|issue| issue.to_string()));
+    match problems.is_empty() {
+        true => Ok(()),
+        false => Err(AppError::ConfigError(problems.join("; "))),
+    }
+}
+
+pub fn db_migrate(cfg: &Config) -> Result<String, AppError> {
+    open(cfg)?;
+    Ok(format!("{} is up to date", cfg.database.name))
+}
+
+pub fn schedule_show(cfg: &Config) -> Result<String, AppError> {
+    Ok(format_schedule(&load_auto_schedule(&open(cfg)?, &cfg.site.id)?))
+}
+
+pub fn schedule_set(cfg: &Config, program: &str, day: Weekday, sector: WaterSector) -> Result<String, AppError> {
+    let mut conn = open(cfg)?;
+    let schedule = set_entry(load_auto_schedule(&conn, &cfg.site.id)?, program, day, sector);
+    check_schedule(&conn, cfg, &schedule, &[])?;
+    save_auto_schedule(&mut conn, &cfg.site.id, &schedule)?;
+    Ok(format_schedule(&schedule))
+}
+
+pub fn schedule_export(cfg: &Config, format: ScheduleFormat) -> Result<String, AppError> {
+    Ok(export(&load_auto_schedule(&open(cfg)?, &cfg.site.id)?, format).trim_end().to_owned())
+}
+
+/// Replaces the schedule with the file, in the format of its extension unless `format` says otherwise
+pub fn schedule_import(cfg: &Config, file: &Path, format: Option<ScheduleFormat>) -> Result<String, AppError> {
+    let content =
+        fs::read_to_string(file).map_err(|e| AppError::ConfigError(format!("Can't read {}: {}", file.display(), e)))?;
+    let schedule = import(&content, format.unwrap_or_else(|| ScheduleFormat::of(file)))?;
+    let mut conn = open(cfg)?;
+    check_schedule(&conn, cfg, &schedule, &[])?;
+    save_auto_schedule(&mut conn, &cfg.site.id, &schedule)?;
+    Ok(format_schedule(&schedule))
+}
+
+/// Replaces the schedule with the one of another controller, in the format of its extension unless `format` says
+/// otherwise. The sectors the database doesn't have are added, the others keep their settings.
+pub fn controller_import(cfg: &Config, file: &Path, format: Option<ControllerFormat>) -> Result<String, AppError> {
+    let content =
+        fs::read_to_string(file).map_err(|e| AppError::ConfigError(format!("Can't read {}: {}", file.display(), e)))?;
+    let format = format.unwrap_or_else(|| ControllerFormat::of(file));
+    let imported = import_controller(&content, format, cfg.watering.sector_transation_secs)?;
+    let mut conn = open(cfg)?;
+    let added = imported.new_sectors(&load_sectors_into_hashmap(load_sectors(&conn, &cfg.site.id)?));
+    check_schedule(&conn, cfg, &imported.schedule, &added)?;
+    import_sectors(&mut conn, &cfg.site.id, &added)?;
+    save_auto_schedule(&mut conn, &cfg.site.id, &imported.schedule)?;
+    let report = imported.report(&added);
+    for (program, &enabled) in &report.programs {
+        set_program_enabled(&conn, &cfg.site.id, program, enabled)?;
+    }
+    Ok(format!("{}\n{}", format_schedule(&imported.schedule), report))
+}
+
+pub fn sector_list(cfg: &Config) -> Result<String, AppError> {
+    Ok(format_sectors(&load_sectors(&open(cfg)?, &cfg.site.id)?))
+}
+
+/// Replaces what the sector had on that day of the program, a 0 duration only removes it
+pub fn set_entry(mut schedule: Schedule, program: &str, day: Weekday, sector: WaterSector) -> Schedule {
+    let at = schedule.entries.iter().position(|entry| {
+        entry.program == program && entry.schedule_type == ScheduleType::Weekday(day) && entry.cron.is_none()
+    });
+    let entry = match at {
+        Some(at) => &mut schedule.entries[at],
+        None => {
+            let entry = ScheduleEntry {
+                program: program.to_owned(),
+                schedule_type: ScheduleType::Weekday(day),
+                start_times: DailyPlan::new(),
+                cron: None,
+            };
+            schedule.entries.push(entry);
+            schedule.entries.last_mut().unwrap()
+        }
+    };
+    entry.start_times.0.retain(|sec| sec.id != sector.id);
+    if sector.duration > 0 {
+        entry.start_times.0.push(sector);
+        entry.start_times.0.sort_by_key(|sec| sec.start);
+    }
+    schedule.entries.retain(|entry| !entry.start_times.0.is_empty());
+    schedule
+}
+
+fn hhmm(secs: i64) -> String {
+    format!("{:02}:{:02}", secs / 3600, secs % 3600 / 60)
+}
+
+pub fn format_schedule(schedule: &Schedule) -> String {
+    let mut entries: Vec<_> = schedule
+        .entries
+        .iter()
+        .filter_map(|entry| match entry.schedule_type {
+            ScheduleType::Weekday(day) => Some((entry.program.as_str(), day, &entry.start_times)),
+            ScheduleType::Date(_) => None,
+        })
+        .collect();
+    if entries.is_empty() {
+        return "no auto schedule".to_owned();
+    }
+    entries.sort_by_key(|(program, day, _)| (*program, day.num_days_from_monday()));
+    let mut out = String::new();
+    for (program, day, plan) in entries {
+        let off = if schedule.is_enabled(program) { "" } else { "  (disabled)" };
+        for sec in &plan.0 {
+            let (start, duration) = (hhmm(sec.start), sec.duration);
+            _ = writeln!(out, "{} {}  sector {:>3}  {}  {:>5} secs{}", program, day, sec.id, start, duration, off);
+        }
+    }
+    out.trim_end().to_owned()
+}
+
+pub fn format_sectors(sectors: &[SectorInfo]) -> String {
+    if sectors.is_empty() {
+        return "no sectors, add them as [[sectors]] in the config".to_owned();
+    }
+    let mut sectors = sectors.to_vec();
+    sectors.sort_by_key(|sec| sec.id);
+    let mut out = format!("{:>3}  {:<20} {:>8} {:>8} {:>8} {:>8}\n", "id", "name", "cm/h", "target", "progress", "max");
+    for sec in sectors {
+        _ = writeln!(
+            out,
+            "{:>3}  {:<20} {:>8.2} {:>8.2} {:>8.2} {:>8}",
+            sec.id, sec.name, sec.sprinkler_debit, sec.weekly_target, sec.progress, sec.max_duration
+        );
+    }
+    out.trim_end().to_owned()
+}
+
+/// One reading from every configured source, a line each
+pub async fn weather_test(cfg: &Config) -> String {
+    let ws = &cfg.weather_station;
+    let client = reqwest::Client::new();
+    let mut out = String::new();
+    for kind in &ws.providers {
+        let result = match kind {
+            ProviderKind::Udp => udp_packet(ws).await,
+            ProviderKind::Mqtt => {
+                check_broker(&cfg.mqtt).await.map(|_| format!("broker {} reachable", cfg.mqtt.address))
+            }
+            ProviderKind::Tempest => fetch_observation(&client, ws).await.map(|obs| match obs {
+                Some(obs) => {
+                    format!("{:.1}°C, {:.1} km/h wind, {:.1} mm/h rain", obs.temperature, obs.wind_speed, obs.rain_rate)
+                }
+                None => "no observation yet".to_owned(),
+            }),
+            ProviderKind::OpenWeatherMap => fetch_current_weather(&client, ws)
+                .await
+                .map(|obs| format!("{:.1}°C, {:.1} km/h wind", obs.temperature, obs.wind_speed)),
+        };
+        _ = writeln!(out, "{:?}: {}", kind, result.unwrap_or_else(|e| format!("FAILED {}", e)));
+    }
+    if let Some(kind) = ws.forecast_provider {
+        let result = fetch_forecast(&client, kind, ws).await.map(|hours| format!("{} hours", hours.len()));
+        _ = writeln!(out, "{:?} forecast: {}", kind, result.unwrap_or_else(|e| format!("FAILED {}", e)));
+    }
+    out.trim_end().to_owned()
+}
+
+async fn udp_packet(ws: &WeatherStation) -> Result<String, AppError> {
+    let socket = UdpSocket::bind(&ws.udp_address).await.map_err(|e| AppError::WeatherError(e.to_string()))?;
+    let mut buf = [0; 1024];
+    let received = tokio::time::timeout(UDP_WAIT, socket.recv_from(&mut buf)).await;
+    let Ok(received) = received else {
+        return Ok(format!("listening on {}, nothing in {} secs", ws.udp_address, UDP_WAIT.as_secs()));
+    };
+    let (len, from) = received.map_err(|e| AppError::WeatherError(e.to_string()))?;
+    match parse_udp_packet(&buf[..len]) {
+        Ok(_) => Ok(format!("station packet from {}", from)),
+        Err(e) => Err(AppError::WeatherError(format!("unknown packet from {}: {}", from, e))),
+    }
+}
+
+/// Starts the synthetic weather in the running controller, through its API, or stops it. The controller plays it
+/// without storing it, where packets to its UDP provider would be stored as the station's.
+#[cfg(feature = "sim")]
+pub async fn weather_generate(
+    cfg: &Config, weather: SyntheticWeather, to: Option<String>, stop: bool,
+) -> Result<String, AppError> {
+    weather.validate().map_err(AppError::ConfigError)?;
+    let to = to.unwrap_or_else(|| local_address(&cfg.web_server.address));
+    let url = format!("http://{}/api/v1/weather/generate", to);
+    let client = reqwest::Client::new();
+    let request = if stop { client.delete(&url) } else { client.post(&url).json(&weather) };
+    let response = request.send().await?.error_for_status()?;
+    Ok(response.json::<String>().await?)
+}
+
+/// Where a server bound to every interface is reached from this host
+#[cfg(feature = "sim")]
+fn local_address(bound: &str) -> String {
+    match bound.rsplit_once(':') {
+        Some(("0.0.0.0", port)) => format!("127.0.0.1:{}", port),
+        _ => bound.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::utils::mock_cfg::mock_cfg;
+
+    #[test]
+    fn edit_and_show_the_schedule() {
+        let schedule = Schedule::new(vec![]);
+        assert_eq!(format_schedule(&schedule), "no auto schedule");
+
+        let schedule = set_entry(schedule, "A", Weekday::Wed, WaterSector::new(2, 22 * 3600, 1200));
+        let schedule = set_entry(schedule, "A", Weekday::Mon, WaterSector::new(1, 23 * 3600, 600));
+        let schedule = set_entry(schedule, "A", Weekday::Mon, WaterSector::new(2, 22 * 3600, 900));
+        let schedule = set_entry(schedule, "A", Weekday::Wed, WaterSector::new(2, 22 * 3600 + 1800, 1200));
+        let mut schedule = set_entry(schedule, "B", Weekday::Mon, WaterSector::new(1, 6 * 3600, 300));
+        schedule.disabled.insert("B".to_owned());
+        assert_eq!(
+            format_schedule(&schedule),
+            "A Mon  sector   2  22:00    900 secs\n\
+             A Mon  sector   1  23:00    600 secs\n\
+             A Wed  sector   2  22:30   1200 secs\n\
+             B Mon  sector   1  06:00    300 secs  (disabled)"
+        );
+
+        let schedule = set_entry(schedule, "A", Weekday::Wed, WaterSector::new(2, 0, 0));
+        assert_eq!(schedule.entries.len(), 2);
+    }
+
+    #[test]
+    fn list_sectors() {
+        let sector = SectorInfo {
+            id: 1,
+            name: "lawn".to_owned(),
+            sprinkler_debit: 1.,
+            weekly_target: 2.5,
+            ..Default::default()
+        };
+        let list = format_sectors(&[sector]);
+        assert_eq!(list.lines().nth(1).unwrap(), "  1  lawn                     1.00     2.50     0.00        0");
+    }
+
+    #[test]
+    fn refuses_a_database_in_memory() {
+        let mut cfg = mock_cfg();
+        cfg.database.name = ":memory:".to_owned();
+        assert!(matches!(sector_list(&cfg), Err(AppError::ConfigError(_))));
+        assert!(matches!(db_migrate(&cfg), Err(AppError::ConfigError(_))));
+    }
+}
@@ -0,0 +1,208 @@
+//! Whether the wall clock can be trusted. A Pi without a real-time clock boots with the time it last saved, or
+//! 1970, and jumps when NTP sets it. Scheduling waits until the time is trusted, and a jump is alerted instead of
+//! watering what the jumped hours planned all at once.
+
+use crate::{config::Clock as ClockCfg, db::DatabaseTrait, time::TimeProvider, watering::ds::CtrlSignal};
+use serde::Serialize;
+use std::{
+    io,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::{net::UdpSocket, sync::broadcast::Sender};
+use tracing::{error, info, warn};
+
+/// seconds between two checks, the watering loop looks again as often while the time isn't trusted
+pub const CHECK_SECS: i64 = 60;
+const NTP_EVERY: Duration = Duration::from_secs(3600);
+const NTP_TIMEOUT: Duration = Duration::from_secs(5);
+/// seconds from 1900, where the NTP time starts, to 1970
+const NTP_UNIX_OFFSET: i64 = 2_208_988_800;
+
+/// The wall clock moved away from the time elapsed since the check before
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ClockJump {
+    /// where the clock was
+    pub timestamp: i64,
+    /// where it should have been
+    pub expected: i64,
+    /// forward, negative back
+    pub secs: i64,
+}
+
+/// What `/healthz` shows of the clock
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ClockStatus {
+    /// why the time isn't trusted, nothing new is scheduled until it is
+    pub distrusted: Option<String>,
+    /// seconds the clock is behind the NTP server, at its last answer
+    pub ntp_offset: Option<i64>,
+    pub jumps: u32,
+    pub last_jump: Option<ClockJump>,
+}
+
+/// Trusted until a check says otherwise, the simulations never check
+#[derive(Debug, Default)]
+pub struct ClockGuard(Mutex<ClockStatus>);
+
+impl ClockGuard {
+    pub fn trusted(&self) -> bool {
+        self.0.lock().unwrap().distrusted.is_none()
+    }
+
+    /// Jumps so far, the watering loop drops the missed plans when it sees a new one
+    pub fn jumps(&self) -> u32 {
+        self.0.lock().unwrap().jumps
+    }
+
+    pub fn status(&self) -> ClockStatus {
+        self.0.lock().unwrap().clone()
+    }
+
+    /// What a check found
+    pub fn record(&self, jump: Option<ClockJump>, distrusted: Option<String>, ntp_offset: Option<i64>) {
+        let mut status = self.0.lock().unwrap();
+        match (&status.distrusted, &distrusted) {
+            (None, Some(reason)) => warn!(reason, "The clock isn't trusted, nothing new is scheduled."),
+            (Some(_), None) => info!("The clock is trusted again."),
+            _ => (),
+        }
+        if let Some(jump) = jump {
+            status.jumps += 1;
+            status.last_jump = Some(jump);
+        }
+        status.distrusted = distrusted;
+        status.ntp_offset = ntp_offset;
+    }
+}
+
+/// A jump when the clock is more than `max_jump_secs` away from `expected`
+pub fn jump(cfg: &ClockCfg, expected: i64, now: i64) -> Option<ClockJump> {
+    let secs = now - expected;
+    (secs.abs() > cfg.max_jump_secs).then_some(ClockJump { timestamp: now, expected, secs })
+}
+
+/// Why the time isn't trusted: more than `max_drift_secs` off the NTP server, or without an answer from it, more
+/// than `max_jump_secs` behind the last time trusted, `mark`. An answer within the drift is trusted whatever the mark.
+pub fn distrust(cfg: &ClockCfg, now: i64, mark: Option<i64>, ntp_offset: Option<i64>) -> Option<String> {
+    match ntp_offset {
+        Some(offset) if offset.abs() > cfg.max_drift_secs => Some(format!("{}s off the NTP server", offset.abs())),
+        Some(_) => None,
+        None => mark
+            .filter(|&mark| now + cfg.max_jump_secs < mark)
+            .map(|mark| format!("{}s behind the last time trusted", mark - now)),
+    }
+}
+
+/// Seconds the clock is behind the server, SNTP with the send and receive times averaged
+pub async fn ntp_offset(server: &str, time_provider: &dyn TimeProvider) -> io::Result<i64> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    socket.connect(server).await?;
+    let mut packet = [0u8; 48];
+    packet[0] = 0x1b; // version 3, client
+    let sent = time_provider.now();
+    socket.send(&packet).await?;
+    let received = tokio::time::timeout(NTP_TIMEOUT, socket.recv(&mut packet))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "no answer"))??;
+    if received < packet.len() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "short answer"));
+    }
+    // the transmit timestamp, whole seconds
+    let server_time = i64::from(u32::from_be_bytes(packet[40..44].try_into().unwrap())) - NTP_UNIX_OFFSET;
+    Ok(server_time - (sent + time_provider.now()) / 2)
+}
+
+/// Checks the clock every `CHECK_SECS` against the time elapsed, the NTP server every hour and after a jump, and at
+/// start against the last time trusted. A jump goes to the webhooks and MQTT as `CtrlSignal::ClockJump`.
+pub async fn run_clock_check(
+    cfg: ClockCfg, guard: Arc<ClockGuard>, db: Arc<dyn DatabaseTrait>, web_tx: Sender<CtrlSignal>,
+    time_provider: Arc<dyn TimeProvider>,
+) {
+    let mut mark = db.load_clock_mark();
+    let (mut last, mut ntp, mut asked): (Option<(i64, Instant)>, Option<i64>, Option<Instant>) = (None, None, None);
+    let mut interval = tokio::time::interval(Duration::from_secs(CHECK_SECS as u64));
+    loop {
+        interval.tick().await;
+        let now = time_provider.now();
+        let jumped = last.and_then(|(wall, at)| jump(&cfg, wall + at.elapsed().as_secs() as i64, now));
+        last = Some((now, Instant::now()));
+        if let Some(jump) = &jumped {
+            warn!(secs = jump.secs, expected = jump.expected, "The clock jumped, the missed plans are dropped.");
+            _ = web_tx.send(CtrlSignal::ClockJump(jump.clone()));
+        }
+        if let Some(server) = &cfg.ntp_server {
+            if jumped.is_some() || asked.is_none_or(|at| at.elapsed() >= NTP_EVERY) {
+                asked = Some(Instant::now());
+                ntp = match ntp_offset(server, time_provider.as_ref()).await {
+                    Ok(offset) => Some(offset),
+                    Err(e) => {
+                        warn!(server, error = %e, "The NTP server didn't answer.");
+                        None
+                    }
+                };
+            }
+        }
+        guard.record(jumped, distrust(&cfg, now, mark, ntp), ntp);
+        if guard.trusted() {
+            match db.store_clock_mark(now) {
+                Ok(()) => mark = Some(now),
+                Err(e) => error!(error = ?e, "Failed to save the clock mark."),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::utils::mock_time::MockTimeProvider;
+
+    const NOON: i64 = 1_700_000_000;
+
+    #[test]
+    fn test_jumps_and_trust() {
+        let cfg = ClockCfg::default();
+        assert_eq!(jump(&cfg, NOON, NOON + 60), None);
+        assert_eq!(
+            jump(&cfg, NOON, NOON + 86_400),
+            Some(ClockJump { timestamp: NOON + 86_400, expected: NOON, secs: 86_400 })
+        );
+        assert_eq!(jump(&cfg, NOON, NOON - 3600).map(|jump| jump.secs), Some(-3600));
+
+        assert_eq!(distrust(&cfg, NOON, None, None), None, "nothing to compare with");
+        assert_eq!(distrust(&cfg, NOON, Some(NOON + 60), None), None, "within a jump");
+        assert_eq!(distrust(&cfg, NOON, Some(NOON + 3600), None).unwrap(), "3600s behind the last time trusted");
+        assert_eq!(distrust(&cfg, NOON, Some(NOON + 3600), Some(10)), None, "the NTP server vouches for it");
+        assert_eq!(distrust(&cfg, NOON, None, Some(-120)).unwrap(), "120s off the NTP server");
+    }
+
+    #[test]
+    fn test_guard_counts_the_jumps() {
+        let guard = ClockGuard::default();
+        assert!(guard.trusted());
+        guard.record(None, Some("behind".to_owned()), None);
+        assert!(!guard.trusted());
+        let jump = ClockJump { timestamp: NOON, expected: NOON - 3600, secs: 3600 };
+        guard.record(Some(jump.clone()), None, Some(2));
+        assert_eq!(
+            guard.status(),
+            ClockStatus { distrusted: None, ntp_offset: Some(2), jumps: 1, last_jump: Some(jump) }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ntp_offset() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let mut packet = [0u8; 48];
+            let (_, client) = server.recv_from(&mut packet).await.unwrap();
+            let time = (NOON + 90 + NTP_UNIX_OFFSET) as u32;
+            packet[40..44].copy_from_slice(&time.to_be_bytes());
+            server.send_to(&packet, client).await.unwrap();
+        });
+        let offset = ntp_offset(&addr, &MockTimeProvider::new(NOON)).await.unwrap();
+        assert_eq!(offset, 90);
+    }
+}
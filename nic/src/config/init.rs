@@ -0,0 +1,160 @@
+use super::{run_options::Args, Config, SectorCfg, MQTT};
+use crate::{
+    db::{configure, import_sector_constraints, import_sectors, initialize, load_auto_schedule, save_auto_schedule},
+    error::AppError,
+    sensors::mqtt_ctrl::mqtt_options,
+    watering::{
+        ds::{DailyPlan, WaterSector},
+        watering_alg::{Schedule, ScheduleEntry, ScheduleType, DEFAULT_PROGRAM},
+    },
+};
+use chrono::Weekday;
+use rumqttc::{AsyncClient, Event, Packet};
+use rusqlite::Connection;
+use std::{fmt::Display, fs, path::PathBuf, time::Duration};
+
+/// The commented `nic.toml` shipped with the sources, with two example sectors
+pub const DEFAULT_CONFIG: &str = include_str!("../../nic.toml");
+
+const BROKER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What `nic init` did
+#[derive(Debug)]
+pub struct InitReport {
+    pub config: PathBuf,
+    /// false when the file was already there
+    pub config_written: bool,
+    pub database: String,
+    pub sectors: usize,
+    /// false when the database already had an auto schedule
+    pub schedule_written: bool,
+    pub broker: Result<(), String>,
+}
+
+impl Display for InitReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let config = if self.config_written { "written" } else { "already there, kept" };
+        writeln!(f, "config   {}: {}", self.config.display(), config)?;
+        writeln!(f, "database {}: {} sector(s) imported", self.database, self.sectors)?;
+        let schedule = if self.schedule_written { "example written" } else { "already there, kept" };
+        writeln!(f, "schedule {}", schedule)?;
+        match &self.broker {
+            Ok(()) => write!(f, "mqtt     reachable"),
+            Err(e) => write!(f, "mqtt     not reachable: {}", e),
+        }
+    }
+}
+
+/// Bootstraps a new install: writes the default config unless there is one, creates the database with the
+/// config sectors and an example auto schedule, and checks the MQTT broker.<br>
+/// An unreachable broker is reported, not an error, it may just not be up yet.
+pub async fn init(cfg_file: PathBuf) -> Result<InitReport, AppError> {
+    let config_written = !cfg_file.exists();
+    if config_written {
+        fs::write(&cfg_file, DEFAULT_CONFIG)
+            .map_err(|e| AppError::ConfigError(format!("Can't write {}: {}", cfg_file.display(), e)))?;
+    }
+    let cfg = Config::load(Args { cfg_file: cfg_file.clone(), ..Default::default() })
+        .map_err(|e| AppError::ConfigError(e.to_string()))?;
+
+    let mut conn = Connection::open(&cfg.database.name)?;
+    configure(&conn, &cfg.database)?;
+    initialize(&conn)?;
+    let site = &cfg.site.id;
+    import_sectors(&mut conn, site, &cfg.sectors)?;
+    import_sector_constraints(&mut conn, site, &cfg.sector_constraints)?;
+    let schedule_written = load_auto_schedule(&conn, site)?.entries.is_empty() && !cfg.sectors.is_empty();
+    if schedule_written {
+        save_auto_schedule(&mut conn, site, &example_schedule(&cfg))?;
+    }
+
+    Ok(InitReport {
+        config: cfg_file,
+        config_written,
+        database: cfg.database.name.clone(),
+        sectors: cfg.sectors.len(),
+        schedule_written,
+        broker: check_broker(&cfg.mqtt).await.map_err(|e| e.to_string()),
+    })
+}
+
+/// Every sector on Monday, Wednesday and Friday, one after the other from the start of the watering window
+pub(crate) fn example_schedule(cfg: &Config) -> Schedule {
+    let plan = |sectors: &[SectorCfg]| {
+        let mut start = cfg.watering.window_start_hour * 3600;
+        let mut plan = DailyPlan::new();
+        for sector in sectors {
+            let duration = sector.max_duration.min(cfg.watering.max_duration_secs);
+            plan.0.push(WaterSector::new(sector.id, start, duration));
+            start += duration + cfg.watering.sector_transation_secs;
+        }
+        plan
+    };
+    let entries = [Weekday::Mon, Weekday::Wed, Weekday::Fri]
+        .into_iter()
+        .map(|day| ScheduleEntry {
+            program: DEFAULT_PROGRAM.to_owned(),
+            schedule_type: ScheduleType::Weekday(day),
+            start_times: plan(&cfg.sectors),
+            cron: None,
+        })
+        .collect();
+    Schedule::new(entries)
+}
+
+/// Ok once the broker accepts the connection
+pub async fn check_broker(mqtt: &MQTT) -> Result<(), AppError> {
+    let options = mqtt_options(mqtt, "init")?;
+    let (_client, mut eventloop) = AsyncClient::new(options, 1);
+    let connected = async {
+        loop {
+            match eventloop.poll().await {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => return Ok(()),
+                Ok(_) => {}
+                Err(e) => return Err(AppError::MQTTError(e.to_string())),
+            }
+        }
+    };
+    tokio::time::timeout(BROKER_TIMEOUT, connected)
+        .await
+        .unwrap_or_else(|_| Err(AppError::MQTTError(format!("No answer from {}", mqtt.address))))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::DEFAULT_SITE;
+
+    #[test]
+    fn default_config_is_valid() {
+        let cfg = Config::parse(DEFAULT_CONFIG).unwrap();
+        assert_eq!(cfg.sectors.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn scaffolds_the_database_once() {
+        let dir = std::env::temp_dir().join(format!("nic-init-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let cfg_file = dir.join("nic.toml");
+        let db_file = dir.join("nic.db");
+        // nothing listens on port 1
+        let cfg = DEFAULT_CONFIG
+            .replace("\"watering_system.db\"", &format!("{:?}", db_file.display().to_string()))
+            .replace("localhost:1883", "127.0.0.1:1");
+        fs::write(&cfg_file, cfg).unwrap();
+
+        let report = init(cfg_file.clone()).await.unwrap();
+        assert!(!report.config_written);
+        assert_eq!(report.sectors, 2);
+        assert!(report.schedule_written);
+        assert!(report.broker.is_err());
+        let conn = Connection::open(&db_file).unwrap();
+        let schedule = load_auto_schedule(&conn, DEFAULT_SITE).unwrap();
+        assert_eq!(schedule.entries.len(), 3);
+        assert_eq!(schedule.entries[0].start_times.0[1], WaterSector::new(2, 22 * 3600 + 1800 + 20, 1800));
+
+        // a second run leaves the schedule alone
+        assert!(!init(cfg_file).await.unwrap().schedule_written);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,274 @@
+use super::{run_options::Args, Config, WeatherStation};
+use crate::{error::AppError, watering::ds::CtrlSignal};
+use serde::Serialize;
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::broadcast::Sender;
+use tracing::{info, warn};
+
+/// What a reload did, section by section
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ConfigReload {
+    pub error: Option<String>,
+    pub applied: Vec<String>,
+    /// changed in the file, but only taken on a restart
+    pub rejected: Vec<String>,
+}
+
+/// Owns the running config and re-reads `nic.toml` on demand.<br>
+/// Changes that are safe at runtime go out as `CtrlSignal::ConfigUpdate` to the watering loop and the weather tasks.
+#[derive(Debug)]
+pub struct ConfigManager {
+    /// the file, and the profile and dry run applied again on every reload
+    args: Args,
+    current: Mutex<Arc<Config>>,
+    sm_tx: Arc<Sender<CtrlSignal>>,
+}
+
+impl ConfigManager {
+    pub fn new(path: PathBuf, cfg: Config, sm_tx: Arc<Sender<CtrlSignal>>) -> Self {
+        let args = Args { cfg_file: path, ..Default::default() };
+        Self { args, current: Mutex::new(Arc::new(cfg)), sm_tx }
+    }
+
+    /// The command line the config was loaded with
+    pub fn with_args(mut self, args: Args) -> Self {
+        self.args = Args { cfg_str: None, ..args };
+        self
+    }
+
+    pub fn current(&self) -> Arc<Config> {
+        self.current.lock().unwrap().clone()
+    }
+
+    pub fn reload(&self) -> Result<ConfigReload, AppError> {
+        // a broken file never replaces the running config
+        let cfg = Config::load(self.args.clone()).map_err(|e| AppError::ConfigError(e.to_string()))?;
+        Ok(self.apply(cfg))
+    }
+
+    /// Keeps the running value of everything that needs a restart, and broadcasts the rest if anything changed
+    pub fn apply(&self, cfg: Config) -> ConfigReload {
+        let mut current = self.current.lock().unwrap();
+        let (merged, reload) = merge(&current, cfg);
+        if !reload.rejected.is_empty() {
+            warn!(sections = ?reload.rejected, "Config changes that need a restart were ignored.");
+        }
+        if !reload.applied.is_empty() {
+            info!(sections = ?reload.applied, "Config reloaded.");
+            *current = Arc::new(merged);
+            _ = self.sm_tx.send(CtrlSignal::ConfigUpdate(current.clone()));
+        }
+        reload
+    }
+}
+
+/// The running config with the runtime safe changes of `new` on top
+pub fn merge(running: &Config, new: Config) -> (Config, ConfigReload) {
+    let mut reload = ConfigReload::default();
+    let mut merged = new.clone();
+
+    // sockets, files and hardware are set up once
+    if new.database != running.database {
+        reload.rejected.push("database".to_owned());
+        merged.database = running.database.clone();
+    }
+    if new.site != running.site {
+        reload.rejected.push("site".to_owned());
+        merged.site = running.site.clone();
+    }
+    if new.clock != running.clock {
+        reload.rejected.push("clock".to_owned());
+        merged.clock = running.clock.clone();
+    }
+    if new.web_server != running.web_server {
+        reload.rejected.push("web_server".to_owned());
+        merged.web_server = running.web_server.clone();
+    }
+    if new.mqtt != running.mqtt {
+        reload.rejected.push("mqtt".to_owned());
+        merged.mqtt = running.mqtt.clone();
+    }
+    if new.sensors != running.sensors {
+        reload.rejected.push("sensors".to_owned());
+        merged.sensors = running.sensors.clone();
+    }
+    // only imported at startup
+    if new.sectors != running.sectors {
+        reload.rejected.push("sectors".to_owned());
+        merged.sectors = running.sectors.clone();
+    }
+    if new.watering.pump_sector != running.watering.pump_sector {
+        reload.rejected.push("watering.pump_sector".to_owned());
+        merged.watering.pump_sector = running.watering.pump_sector;
+    }
+    // the interlock and the watchdog know the master valves from startup
+    if new.sources != running.sources {
+        reload.rejected.push("sources".to_owned());
+        merged.sources = running.sources.clone();
+    }
+    // stored with the sectors at startup
+    if new.sector_constraints != running.sector_constraints {
+        reload.rejected.push("sector_constraints".to_owned());
+        merged.sector_constraints = running.sector_constraints.clone();
+    }
+    // the log subscriber and the exporter's endpoint and buffer are set up once
+    if new.log != running.log {
+        reload.rejected.push("log".to_owned());
+        merged.log = running.log.clone();
+    }
+    if new.influx != running.influx {
+        reload.rejected.push("influx".to_owned());
+        merged.influx = running.influx.clone();
+    }
+    // the profile in use is applied again on every reload, into the sections above, but its time is taken at startup
+    if new.profiles != running.profiles {
+        reload.rejected.push("profiles".to_owned());
+        merged.profiles = running.profiles.clone();
+    }
+
+    // only the signal thresholds, the providers keep their connections
+    let thresholds = WeatherStation {
+        rain_threshold: new.weather_station.rain_threshold,
+        rain_hysteresis: new.weather_station.rain_hysteresis,
+        rain_debounce_secs: new.weather_station.rain_debounce_secs,
+        wind_threshold: new.weather_station.wind_threshold,
+        wind_hysteresis: new.weather_station.wind_hysteresis,
+        wind_debounce_secs: new.weather_station.wind_debounce_secs,
+        ..running.weather_station.clone()
+    };
+    if thresholds != new.weather_station {
+        reload.rejected.push("weather_station".to_owned());
+    }
+    if thresholds != running.weather_station {
+        reload.applied.push("weather_station".to_owned());
+    }
+    merged.weather_station = thresholds;
+
+    if merged.watering != running.watering {
+        reload.applied.push("watering".to_owned());
+    }
+    if merged.pause_policy != running.pause_policy {
+        reload.applied.push("pause_policy".to_owned());
+    }
+    if merged.restrictions != running.restrictions {
+        reload.applied.push("restrictions".to_owned());
+    }
+    if merged.window_overrides != running.window_overrides {
+        reload.applied.push("window_overrides".to_owned());
+    }
+    if merged.webhooks != running.webhooks {
+        reload.applied.push("webhooks".to_owned());
+    }
+    (merged, reload)
+}
+
+/// Reloads the config on SIGHUP
+pub async fn run_config_reload(manager: Arc<ConfigManager>) {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let Ok(mut hangup) = signal(SignalKind::hangup()) else {
+            warn!("Can't listen for SIGHUP, the config reloads through the API only.");
+            return;
+        };
+        while hangup.recv().await.is_some() {
+            info!("SIGHUP received, reloading the config.");
+            if let Err(e) = manager.reload() {
+                warn!(error = ?e, "Config not reloaded.");
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    drop(manager);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        config::{Profile, ProfileTime, WebhookCfg},
+        test::utils::mock_cfg::mock_cfg,
+    };
+
+    #[test]
+    fn applies_only_runtime_safe_changes() {
+        let running = mock_cfg();
+        let mut new = mock_cfg();
+        new.web_server.address = "0.0.0.0:9090".to_owned();
+        new.weather_station.rain_threshold = 2.5;
+        new.weather_station.udp_address = "0.0.0.0:50223".to_owned();
+        new.watering.max_pause_secs = 600;
+        new.watering.pump_sector = Some(9);
+
+        let (merged, reload) = merge(&running, new);
+        assert_eq!(reload.applied, vec!["weather_station", "watering"]);
+        assert_eq!(reload.rejected, vec!["web_server", "watering.pump_sector", "weather_station"]);
+        assert_eq!(merged.web_server, running.web_server);
+        assert_eq!(merged.weather_station.rain_threshold, 2.5);
+        assert_eq!(merged.weather_station.udp_address, running.weather_station.udp_address);
+        assert_eq!(merged.watering.max_pause_secs, 600);
+        assert_eq!(merged.watering.pump_sector, running.watering.pump_sector);
+
+        let (_, reload) = merge(&running, mock_cfg());
+        assert_eq!(reload, ConfigReload::default());
+    }
+
+    #[test]
+    fn log_needs_a_restart() {
+        let running = mock_cfg();
+        let mut new = mock_cfg();
+        new.log.console_level = "nic=trace".to_owned();
+        let (merged, reload) = merge(&running, new);
+        assert_eq!((reload.applied, reload.rejected), (vec![], vec!["log".to_owned()]));
+        assert_eq!(merged.log, running.log);
+    }
+
+    #[test]
+    fn influx_needs_a_restart() {
+        let running = mock_cfg();
+        let mut new = mock_cfg();
+        new.influx.enabled = !running.influx.enabled;
+        let (merged, reload) = merge(&running, new);
+        assert_eq!((reload.applied, reload.rejected), (vec![], vec!["influx".to_owned()]));
+        assert_eq!(merged.influx, running.influx);
+    }
+
+    #[test]
+    fn profiles_need_a_restart() {
+        let running = mock_cfg();
+        let mut new = mock_cfg();
+        new.profiles.insert("simulation".to_owned(), Profile { time: ProfileTime::Accelerated, ..Default::default() });
+        let (merged, reload) = merge(&running, new);
+        assert_eq!((reload.applied, reload.rejected), (vec![], vec!["profiles".to_owned()]));
+        assert_eq!(merged.profiles, running.profiles);
+    }
+
+    #[test]
+    fn applies_the_webhooks() {
+        let running = mock_cfg();
+        let mut new = mock_cfg();
+        new.webhooks.push(WebhookCfg { url: "http://localhost/hook".to_owned(), ..Default::default() });
+        let (merged, reload) = merge(&running, new.clone());
+        assert_eq!((reload.applied, reload.rejected), (vec!["webhooks".to_owned()], vec![]));
+        assert_eq!(merged.webhooks, new.webhooks);
+    }
+
+    #[test]
+    fn broadcasts_the_update() {
+        let (sm_tx, mut sm_rx) = tokio::sync::broadcast::channel(4);
+        let manager = ConfigManager::new(PathBuf::from("/nonexistent/nic.toml"), mock_cfg(), Arc::new(sm_tx));
+        assert!(matches!(manager.reload(), Err(AppError::ConfigError(_))));
+
+        let mut new = mock_cfg();
+        new.watering.window_start_hour = 21;
+        assert_eq!(manager.apply(new).applied, vec!["watering"]);
+        assert_eq!(manager.current().watering.window_start_hour, 21);
+        match sm_rx.try_recv() {
+            Ok(CtrlSignal::ConfigUpdate(cfg)) => assert_eq!(cfg.watering.window_start_hour, 21),
+            other => panic!("expected a config update, got {:?}", other),
+        }
+    }
+}
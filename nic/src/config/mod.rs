@@ -1,46 +1,190 @@
+pub mod init;
+pub mod manager;
 pub mod run_options;
+pub mod secrets;
+pub mod validate;
 
+use crate::{
+    db::{DEFAULT_SITE, DEFAULT_SLOW_QUERY_MS},
+    watering::{ds::WeatherSignal, modes::Mode, watering_alg::SectorConstraint},
+    weather::{forecast::ForecastKind, provider::ProviderKind},
+};
+use chrono::{DateTime, Datelike, NaiveDate, Weekday};
 use run_options::Args;
-use serde::Deserialize;
-use std::fs;
+use secrets::{Secret, Secrets};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs};
+use validate::{validate, ConfigError, ConfigIssue};
 
 pub const CONFIG_FILE: &str = "./nic.toml";
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default)]
 pub struct Database {
+    /// the file, or `:memory:` for a database gone with the process
     pub name: String,
+    /// commands that take longer are logged
+    pub slow_query_ms: u64,
+    /// how long a connection waits on another's lock before giving up with SQLITE_BUSY
+    pub busy_timeout_ms: u64,
+    pub synchronous: Synchronous,
+    /// read only connections kept for the history the API reads. 0 reads everything through the actor
+    pub read_connections: usize,
+    /// UTC hour from which the integrity check and maintenance may run, outside the watering window
+    pub maintenance_hour: i64,
+    /// days between two integrity checks, 0 never
+    pub maintenance_every_days: i64,
+    /// SQLCipher, in builds with the `sqlcipher` feature
+    pub encrypted: bool,
+    /// of the encryption, better kept in the secrets file
+    pub key: Secret,
 }
 
 impl Default for Database {
     fn default() -> Self {
-        Self { name: "nic.db".to_owned() }
+        Self {
+            name: "nic.db".to_owned(),
+            slow_query_ms: DEFAULT_SLOW_QUERY_MS,
+            busy_timeout_ms: 5000,
+            synchronous: Synchronous::default(),
+            read_connections: 2,
+            maintenance_hour: 13,
+            maintenance_every_days: 1,
+            encrypted: false,
+            key: Secret::default(),
+        }
+    }
+}
+
+impl Database {
+    /// SQLite keeps it in memory, another connection opens another database
+    pub fn in_memory(&self) -> bool {
+        self.name.is_empty() || self.name.contains(":memory:") || self.name.contains("mode=memory")
     }
 }
 
-#[derive(Debug, Deserialize)]
+/// The controllers sharing a database, a house and an allotment, each read and write only the rows of their site
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Site {
+    /// in the rows and the `/sites/<id>` routes, lowercase letters, digits, `-` and `_`
+    pub id: String,
+}
+
+impl Default for Site {
+    fn default() -> Self {
+        Self { id: DEFAULT_SITE.to_owned() }
+    }
+}
+
+/// A Pi without a real-time clock boots with a wrong time until NTP sets it. Until the time is trusted nothing new
+/// is scheduled, and a jump doesn't catch up on what the jumped hours planned.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Clock {
+    /// seconds the clock may move away from the time elapsed between two checks before it is a jump, and may be
+    /// behind the last time it was trusted at start
+    pub max_jump_secs: i64,
+    /// host:port asked for the time every hour, none trusts the clock unless it is behind the last time trusted
+    pub ntp_server: Option<String>,
+    /// seconds the clock may be off the NTP server before nothing new is scheduled
+    pub max_drift_secs: i64,
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Self { max_jump_secs: 300, ntp_server: None, max_drift_secs: 60 }
+    }
+}
+
+/// SQLite `synchronous` level. With WAL, `normal` can lose the last commits on a power cut but never corrupts
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Synchronous {
+    Off,
+    #[default]
+    Normal,
+    Full,
+    Extra,
+}
+
+impl Synchronous {
+    pub fn pragma(&self) -> &'static str {
+        match self {
+            Synchronous::Off => "OFF",
+            Synchronous::Normal => "NORMAL",
+            Synchronous::Full => "FULL",
+            Synchronous::Extra => "EXTRA",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default)]
 pub struct WebServer {
     pub address: String,
+    /// where the gRPC API listens, off when not set. Needs the `grpc` feature.
+    pub grpc_address: Option<String>,
+    /// calls that can change the controller, a second from one ip. 0 for no limit.
+    pub control_rate: f64,
+    /// how many of those one ip can make at once before `control_rate` holds it back
+    pub control_burst: u32,
+    /// bytes of the body of a schedule upload
+    pub max_upload_bytes: usize,
 }
 
 impl Default for WebServer {
     fn default() -> Self {
-        Self { address: "0.0.0.0:8080".to_owned() }
+        Self {
+            address: "0.0.0.0:8080".to_owned(),
+            grpc_address: None,
+            control_rate: 2.,
+            control_burst: 10,
+            max_upload_bytes: 256 * 1024,
+        }
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default)]
 pub struct MQTT {
     pub address: String,
     pub client_id: String,
+    pub username: Option<String>,
+    /// only sent with a username
+    pub password: Secret,
+    pub publish: MqttPublishCfg,
 }
 
 impl Default for MQTT {
     fn default() -> Self {
-        Self { address: "localhost:1883".to_owned(), client_id: "nic".to_owned() }
+        Self {
+            address: "localhost:1883".to_owned(),
+            client_id: "nic".to_owned(),
+            username: None,
+            password: Secret::default(),
+            publish: MqttPublishCfg::default(),
+        }
     }
 }
 
-#[derive(Clone, Copy, Debug, Deserialize)]
+/// Mirrors the controller to `nic/<client_id>/...` topics on the broker
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct MqttPublishCfg {
+    pub enabled: bool,
+    /// seconds between refreshes of the retained state, which also follows every transition
+    pub status_secs: u64,
+}
+
+impl Default for MqttPublishCfg {
+    fn default() -> Self {
+        Self { enabled: false, status_secs: 60 }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(default)]
 pub struct GeoPos {
     pub lat: f64,
     pub long: f64,
@@ -53,18 +197,48 @@ impl Default for GeoPos {
         Self { lat: 40.440_725, long: -8.682_944, elev: 51. }
     }
 }
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default)]
 pub struct WeatherStation {
     pub address: String,
+    /// mm/hour
     pub rain_threshold: f64,
+    /// how far below `rain_threshold` the rate must drop before we call the rain over
+    pub rain_hysteresis: f64,
+    pub rain_debounce_secs: i64,
+    /// km/h
     pub wind_threshold: f64,
+    pub wind_hysteresis: f64,
+    pub wind_debounce_secs: i64,
     pub geo_pos: GeoPos,
 
-    pub token_tempest: String,
+    /// weather sources to run, in order
+    pub providers: Vec<ProviderKind>,
+    pub udp_address: String,
+    pub mqtt_topic: String,
+
+    pub token_tempest: Secret,
     pub station_id_tempest: String,
     pub device_id_tempest: String,
+    pub url_tempest: String,
+    pub poll_interval_tempest_secs: u64,
+
+    pub api_key_openweathermap: Secret,
+    pub poll_interval_openweathermap_secs: u64,
 
+    pub forecast_provider: Option<ForecastKind>,
+    pub forecast_refresh_secs: u64,
+
+    /// hours without observations before the wizard stops trusting the station. 0 disables the check
+    pub stale_after_hours: i64,
+    /// days of history averaged into the ET used while the data is stale
+    pub fallback_et_days: i64,
+    /// mm/day, when there is no history either
+    pub fallback_et: f64,
+
+    /// ET model used by the wizard planner. 0 is the built in one, others are read from `ml_models_path`
     pub current_ml_model: u32,
+    pub ml_models_path: String,
 }
 
 impl Default for WeatherStation {
@@ -72,49 +246,729 @@ impl Default for WeatherStation {
         Self {
             address: "0.0.0.0:8080".to_owned(),
             rain_threshold: 1.,
+            rain_hysteresis: 0.5,
+            rain_debounce_secs: 120,
             wind_threshold: 20.,
+            wind_hysteresis: 5.,
+            wind_debounce_secs: 30,
             geo_pos: GeoPos::default(),
-            token_tempest: "".to_owned(),      //todo!(),
+            providers: vec![ProviderKind::Udp, ProviderKind::Mqtt],
+            udp_address: "0.0.0.0:50222".to_owned(), // Tempest hub broadcast port
+            mqtt_topic: "weather/observations".to_owned(),
+            token_tempest: Secret::default(),
             station_id_tempest: "".to_owned(), //,todo!(),
             device_id_tempest: "".to_owned(),  //,todo!(),
-            current_ml_model: 0,               //todo!(),
+            url_tempest: "https://swd.weatherflow.com/swd/rest".to_owned(),
+            poll_interval_tempest_secs: 300,
+            api_key_openweathermap: Secret::default(),
+            poll_interval_openweathermap_secs: 600,
+            forecast_provider: None,
+            forecast_refresh_secs: 3 * 3600,
+            stale_after_hours: 6,
+            fallback_et_days: 30,
+            fallback_et: 4.,
+            current_ml_model: 0,
+            ml_models_path: "models".to_owned(),
         }
     }
 }
 
-#[derive(Copy, Clone, Debug, Deserialize)]
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum SensorBackend {
+    /// the external "sensor-system" HTTP service
+    #[default]
+    Http,
+    /// relays wired to the Raspberry Pi GPIO (needs the `gpio` feature)
+    Gpio,
+    /// MQTT relays, using the `[mqtt]` broker
+    Mqtt,
+    /// PLC coils over Modbus TCP or RTU (needs the `modbus` feature)
+    Modbus,
+    /// no hardware, the commands are only logged
+    Stub,
+    /// no hardware, the commands are logged and recorded in the system events, for a dry run
+    Logging,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct SectorAddress {
+    pub sector: u32,
+    /// used in the URL in place of the sector id
+    pub address: String,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct HttpSensorCfg {
+    /// `{base_url}/activate/{address}`, `{base_url}/deactivate/{address}` and `{base_url}/state/{address}`
+    pub base_url: String,
+    /// sent as a bearer token when set
+    pub token: Option<String>,
+    pub timeout_secs: u64,
+    /// sectors not listed here are addressed by their id
+    pub addresses: Vec<SectorAddress>,
+}
+
+impl Default for HttpSensorCfg {
+    fn default() -> Self {
+        Self { base_url: "http://sensor-system".to_owned(), token: None, timeout_secs: 5, addresses: Vec::new() }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub struct PinMap {
+    pub sector: u32,
+    /// BCM pin number
+    pub pin: u8,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct GpioCfg {
+    /// most relay boards switch on when the pin is pulled low
+    pub active_low: bool,
+    pub pins: Vec<PinMap>,
+}
+
+/// `{sector}` in the topics is replaced by the sector id
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct MqttCtrlCfg {
+    pub command_topic: String,
+    pub state_topic: String,
+    pub on_payload: String,
+    pub off_payload: String,
+    pub ack_timeout_ms: u64,
+}
+
+impl Default for MqttCtrlCfg {
+    fn default() -> Self {
+        // Tasmota defaults
+        Self {
+            command_topic: "cmnd/valve{sector}/POWER".to_owned(),
+            state_topic: "stat/valve{sector}/POWER".to_owned(),
+            on_payload: "ON".to_owned(),
+            off_payload: "OFF".to_owned(),
+            ack_timeout_ms: 5000,
+        }
+    }
+}
+
+/// Battery and signal strength reported by wireless valves and sensors over the `[mqtt]` broker
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct TelemetryCfg {
+    pub enabled: bool,
+    /// `{device}` is replaced by the device id. Payload: `{"battery": 87, "rssi": -71}`
+    pub topic: String,
+    /// %, a notification is raised when a device drops below it
+    pub low_battery_pct: f64,
+}
+
+impl Default for TelemetryCfg {
+    fn default() -> Self {
+        Self { enabled: false, topic: "devices/{device}/telemetry".to_owned(), low_battery_pct: 20. }
+    }
+}
+
+/// Independent check that closed valves really closed
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct WatchdogCfg {
+    /// seconds a valve has to close before it counts as stuck open, 0 disables the watchdog
+    pub grace_secs: i64,
+}
+
+impl Default for WatchdogCfg {
+    fn default() -> Self {
+        Self { grace_secs: 60 }
+    }
+}
+
+/// Which valves may be open at the same time, checked on every command
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct InterlockCfg {
+    /// sectors open at once, the `[watering] pump_sector` doesn't count
+    pub max_open_sectors: usize,
+}
+
+impl Default for InterlockCfg {
+    fn default() -> Self {
+        Self { max_open_sectors: 1 }
+    }
+}
+
+/// Valve commands are retried with exponential backoff before the sector is given up as faulted
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct RetryCfg {
+    /// tries per command, the first one included
+    pub attempts: u32,
+    /// wait after the first failure, doubled after each one
+    pub backoff_ms: u64,
+    pub max_backoff_ms: u64,
+}
+
+impl Default for RetryCfg {
+    fn default() -> Self {
+        Self { attempts: 3, backoff_ms: 500, max_backoff_ms: 5000 }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ModbusTransport {
+    #[default]
+    Tcp,
+    Rtu,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub struct CoilMap {
+    pub sector: u32,
+    /// zero based coil address
+    pub coil: u16,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct ModbusCfg {
+    pub transport: ModbusTransport,
+    /// `host:port` for TCP, serial device for RTU
+    pub address: String,
+    /// RTU only
+    pub baud_rate: u32,
+    pub unit_id: u8,
+    pub coils: Vec<CoilMap>,
+}
+
+impl Default for ModbusCfg {
+    fn default() -> Self {
+        Self {
+            transport: ModbusTransport::Tcp,
+            address: "127.0.0.1:502".to_owned(),
+            baud_rate: 9600,
+            unit_id: 1,
+            coils: Vec::new(),
+        }
+    }
+}
+
+/// Sends a sector to a backend other than the default one
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub struct SectorRoute {
+    pub sector: u32,
+    pub backend: SensorBackend,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Sensors {
+    /// used by every sector without a route
+    pub backend: SensorBackend,
+    pub routes: Vec<SectorRoute>,
+    pub http: HttpSensorCfg,
+    pub gpio: GpioCfg,
+    pub mqtt: MqttCtrlCfg,
+    pub modbus: ModbusCfg,
+    pub retry: RetryCfg,
+    pub telemetry: TelemetryCfg,
+    pub watchdog: WatchdogCfg,
+    pub interlock: InterlockCfg,
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq)]
+#[serde(default)]
 pub struct Watering {
+    /// pause between two sectors, so the pressure settles
     pub sector_transation_secs: i64,
+    /// cap on a single sector session, on top of the sector own max_duration
     pub max_duration_secs: i64,
+    /// mm any sector may get in a watering day (the window and the hours to the next one), on top of its own cap
+    pub max_daily_mm: Option<f64>,
+    /// minutes any sector may water in a watering day, on top of its own cap
+    pub max_daily_minutes: Option<i64>,
+    /// sectors needing less than this are skipped for the day
     pub min_watering_secs: i64,
+    /// seconds a valve has to report the commanded state, 0 disables the check
+    pub valve_check_secs: i64,
+    /// relay of the pump or master valve, switched off when a valve doesn't follow its command
+    pub pump_sector: Option<u32>,
+    /// seconds a pause may last before the cycle is abandoned, 0 waits forever
+    pub max_pause_secs: i64,
+    /// UTC hour the watering window opens
+    pub window_start_hour: i64,
+    /// may run past midnight
+    pub window_duration_hours: i64,
+    /// UTC hour of the daily adjustments, the hour the window closes if not set
+    pub adjustment_hour: Option<i64>,
+    /// push the nightly report to the websocket clients, it is stored either way
+    pub notify_daily_report: bool,
+    /// days between two estimates of the sectors' debit and percolation from the moisture readings, 0 never
+    pub learning_every_days: i64,
+    /// days of readings an estimate looks at
+    pub learning_window_days: i64,
+    /// intervals between two readings a sector needs for an estimate
+    pub learning_min_samples: usize,
+    /// mm of rain in a forecast hour, weighted by its probability, the wizard moves its sessions away from. 0 never.
+    pub avoid_rain_mm: f64,
+    /// km/h of forecast wind the wizard moves its sessions away from, 0 never
+    pub avoid_wind_kmh: f64,
+    /// how the wizard spreads the week's watering over the nights
+    pub planning_strategy: PlanningStrategy,
+    /// seconds a wizard cycle may span, longer sessions run as several cycles one after the other. 0 one cycle.
+    pub max_cycle_secs: i64,
+    /// the auto programs may only start in the watering window, they start at any hour otherwise
+    pub auto_within_window: bool,
+    /// days down after which the stored progress of the week is dropped at start, the ET of those days is unknown
+    pub progress_stale_days: i64,
 }
 
 impl Default for Watering {
     fn default() -> Self {
-        Self { sector_transation_secs: 20, max_duration_secs: 1800, min_watering_secs: 300 }
+        Self {
+            sector_transation_secs: 20,
+            max_duration_secs: 1800,
+            max_daily_mm: None,
+            max_daily_minutes: None,
+            min_watering_secs: 300,
+            valve_check_secs: 10,
+            pump_sector: None,
+            max_pause_secs: 3600,
+            window_start_hour: 22,
+            window_duration_hours: 8,
+            adjustment_hour: None,
+            notify_daily_report: false,
+            learning_every_days: 7,
+            learning_window_days: 28,
+            learning_min_samples: 5,
+            avoid_rain_mm: 0.5,
+            avoid_wind_kmh: 20.,
+            planning_strategy: PlanningStrategy::Greedy,
+            max_cycle_secs: 0,
+            auto_within_window: false,
+            progress_stale_days: 2,
+        }
+    }
+}
+
+impl Watering {
+    /// The hour of the daily adjustments, after the window so a night's plans aren't worked out again mid-cycle
+    pub fn adjustment_hour(&self) -> i64 {
+        self.adjustment_hour.unwrap_or((self.window_start_hour + self.window_duration_hours) % 24)
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PlanningStrategy {
+    /// a sector waits until the nights left can only just make its target, then gets all it can
+    #[default]
+    Greedy,
+    /// a sector gets an even share of what is left every night, within the window
+    Balanced,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    #[default]
+    Plain,
+    /// an object per line, for log shippers
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogRotation {
+    Hourly,
+    /// a `<file>.YYYY-MM-DD` per day
+    #[default]
+    Daily,
+    /// at `max_size_mb`, to `<file>.1`, `<file>.2`, ...
+    Size,
+    Never,
+}
+
+/// An HTTP POST to `url` for each watering event it asks for
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct WebhookCfg {
+    pub url: String,
+    /// signs the body with HMAC-SHA256 in `X-Nic-Signature`, nothing is signed when empty
+    pub secret: Secret,
+    /// kinds of event, e.g. `cycle_started` or `sector_fault`. Empty sends all of them.
+    pub events: Vec<String>,
+    /// tries per event, a 4xx answer is not retried
+    pub attempts: u32,
+}
+
+impl Default for WebhookCfg {
+    fn default() -> Self {
+        Self { url: String::new(), secret: Secret::default(), events: vec![], attempts: 3 }
+    }
+}
+
+/// Weather, valve activations and water applied, written to InfluxDB or any line protocol endpoint
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct InfluxCfg {
+    pub enabled: bool,
+    /// the full write URL, with the database or org/bucket and `precision=s`
+    pub url: String,
+    /// sent as `Authorization: Token <token>` when set
+    pub token: Secret,
+    /// seconds between writes
+    pub flush_secs: u64,
+    /// points per write
+    pub batch_size: usize,
+    /// points kept while the endpoint is down, the oldest go first
+    pub max_buffer: usize,
+}
+
+impl Default for InfluxCfg {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            url: "http://localhost:8086/api/v2/write?org=home&bucket=nic&precision=s".to_owned(),
+            token: Secret::default(),
+            flush_secs: 10,
+            batch_size: 500,
+            max_buffer: 10_000,
+        }
+    }
+}
+
+/// The console always gets the log, the file only when `file` is set
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct LogCfg {
+    /// tracing filter directives, e.g. "nic=debug,rumqttc=warn"
+    pub console_level: String,
+    pub file: Option<String>,
+    pub file_level: String,
+    /// of the file, the console is always plain
+    pub format: LogFormat,
+    pub rotation: LogRotation,
+    pub max_size_mb: u64,
+    /// rotated files kept, 0 keeps them all unless the rotation is by size
+    pub max_files: usize,
+}
+
+impl Default for LogCfg {
+    fn default() -> Self {
+        Self {
+            console_level: "nic=debug".to_owned(),
+            file: None,
+            file_level: "nic=info".to_owned(),
+            format: LogFormat::Plain,
+            rotation: LogRotation::Daily,
+            max_size_mb: 10,
+            max_files: 14,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProfileTime {
+    #[default]
+    Real,
+    /// `time_factor` times faster than the wall clock, from the moment we start
+    Accelerated,
+    /// stands still from the moment we start, moved on through `/sim/time`. Needs the `sim` feature.
+    Simulated,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProfileSensors {
+    /// the `[sensors]` backends
+    #[default]
+    Real,
+    /// no hardware is touched, see `SensorBackend::Stub`
+    Stub,
+    /// no hardware is touched, the commands are recorded, see `SensorBackend::Logging`
+    Logging,
+}
+
+/// Selected with `--profile <name>`, for rehearsal runs with the same binary and config
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Profile {
+    pub time: ProfileTime,
+    pub time_factor: f64,
+    pub sensors: ProfileSensors,
+    /// replaces `[database]` name
+    pub database: Option<String>,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Self { time: ProfileTime::Real, time_factor: 60., sensors: ProfileSensors::Real, database: None }
+    }
+}
+
+/// A zone, imported into the `sectors` table at startup.<br>
+/// Keeps the watering progress of sectors already in the database.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct SectorCfg {
+    pub id: u32,
+    #[serde(default)]
+    pub name: String,
+    /// cm/hour
+    pub sprinkler_debit: f64,
+    /// mm/hour
+    pub percolation_rate: f64,
+    /// cm per week
+    pub weekly_target: f64,
+    /// seconds, longest safe watering per session
+    pub max_duration: i64,
+    /// keeps watering through the weather pauses, a greenhouse drip or a covered planter
+    #[serde(default)]
+    pub ignore_weather_pause: bool,
+    /// mm per watering day, whatever the plans say
+    #[serde(default)]
+    pub max_daily_mm: Option<f64>,
+    /// minutes per watering day, whatever the plans say
+    #[serde(default)]
+    pub max_daily_minutes: Option<i64>,
+    /// gets all of its ET back in the water restrictions, a vegetable patch
+    #[serde(default)]
+    pub deficit_exempt: bool,
+}
+
+/// Where the water comes from, a well or the mains, each behind its own master valve.<br>
+/// A sector takes the first source, in config order, that serves it at that hour.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct SourceCfg {
+    pub name: String,
+    /// relay of the source's master valve, opened with each sector it feeds
+    #[serde(default)]
+    pub master_sector: Option<u32>,
+    /// litres/minute, the usage is counted from it
+    #[serde(default)]
+    pub flow_lpm: f64,
+    /// UTC hours `[start, end)` it is used in, may wrap midnight. Any hour when not set.
+    #[serde(default)]
+    pub hours: Option<(u32, u32)>,
+    /// the sectors it feeds, all of them when empty
+    #[serde(default)]
+    pub sectors: Vec<u32>,
+}
+
+impl SourceCfg {
+    pub fn serves(&self, sector: u32, hour: u32) -> bool {
+        let in_hours = match self.hours {
+            None => true,
+            Some((start, end)) if start <= end => (start..end).contains(&hour),
+            Some((start, end)) => hour >= start || hour < end,
+        };
+        in_hours && (self.sectors.is_empty() || self.sectors.contains(&sector))
+    }
+}
+
+/// A declared water restriction, the sectors get back `et_pct` of the ET they lose in the days from `from` to `to`,
+/// both included
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct RestrictionCfg {
+    /// UTC days, `YYYY-MM-DD`
+    pub from: String,
+    pub to: String,
+    /// % of the ET replaced
+    pub et_pct: f64,
+}
+
+impl RestrictionCfg {
+    /// `(first, last)` day, none when a date doesn't parse
+    pub fn days(&self) -> Option<(NaiveDate, NaiveDate)> {
+        let day = |date: &str| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok();
+        Some((day(&self.from)?, day(&self.to)?))
+    }
+
+    pub fn contains(&self, time: i64) -> bool {
+        let Some(day) = DateTime::from_timestamp(time, 0).map(|time| time.date_naive()) else { return false };
+        self.days().is_some_and(|(first, last)| (first..=last).contains(&day))
+    }
+}
+
+/// Another watering window for some days, picked by the UTC day it opens on. The first one of a day wins, and what it
+/// leaves out the `[watering]` window gives. Lasting 0 hours, nothing is watered that day.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct WindowOverrideCfg {
+    /// `mon` to `sun`, any day when empty
+    #[serde(default)]
+    pub weekdays: Vec<String>,
+    /// UTC days, `YYYY-MM-DD`, both included. `to` is `from` when left out, and any day when neither is set.
+    #[serde(default)]
+    pub from: Option<String>,
+    #[serde(default)]
+    pub to: Option<String>,
+    #[serde(default)]
+    pub start_hour: Option<i64>,
+    #[serde(default)]
+    pub duration_hours: Option<i64>,
+}
+
+impl WindowOverrideCfg {
+    /// None when one doesn't parse
+    pub fn days_of_week(&self) -> Option<Vec<Weekday>> {
+        self.weekdays.iter().map(|day| day.parse().ok()).collect()
+    }
+
+    /// `(first, last)` day, none when a date doesn't parse or there is none
+    pub fn dates(&self) -> Option<(NaiveDate, NaiveDate)> {
+        let day = |date: &str| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok();
+        let first = day(self.from.as_deref()?)?;
+        let last = match &self.to {
+            Some(to) => day(to)?,
+            None => first,
+        };
+        Some((first, last))
+    }
+
+    pub fn applies(&self, day: NaiveDate) -> bool {
+        let weekday = self.days_of_week().is_some_and(|days| days.is_empty() || days.contains(&day.weekday()));
+        let dated = self.from.is_none() || self.dates().is_some_and(|(first, last)| (first..=last).contains(&day));
+        weekday && dated
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PauseAction {
+    /// close the valve and resume the rest of the sector when the weather clears
+    Pause,
+    /// close the valve and drop the cycle
+    Abort,
+    #[default]
+    Ignore,
+}
+
+/// What to do on `RainStart` and `WindHigh`
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct SignalPolicy {
+    pub rain: PauseAction,
+    pub wind: PauseAction,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct PausePolicy {
+    pub auto: SignalPolicy,
+    pub manual: SignalPolicy,
+    pub wizard: SignalPolicy,
+}
+
+impl Default for PausePolicy {
+    fn default() -> Self {
+        let pause = SignalPolicy { rain: PauseAction::Pause, wind: PauseAction::Pause };
+        Self { auto: SignalPolicy::default(), manual: SignalPolicy::default(), wizard: pause }
+    }
+}
+
+impl PausePolicy {
+    pub fn action(&self, mode: Mode, signal: &WeatherSignal) -> PauseAction {
+        let policy = match mode {
+            Mode::Auto => self.auto,
+            Mode::Manual => self.manual,
+            Mode::Wizard => self.wizard,
+            Mode::Off => return PauseAction::Ignore, // nothing to pause
+        };
+        match signal {
+            WeatherSignal::RainStart => policy.rain,
+            WeatherSignal::WindHigh => policy.wind,
+            // the end of a bad weather spell never pauses anything
+            WeatherSignal::RainStop | WeatherSignal::WindLow => PauseAction::Ignore,
+        }
+    }
+}
+
+/// Every section is optional, a missing one takes its defaults
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
 pub struct Config {
     pub database: Database,
+    pub site: Site,
+    pub clock: Clock,
     pub web_server: WebServer,
     pub mqtt: MQTT,
     pub weather_station: WeatherStation,
     pub watering: Watering,
+    pub sensors: Sensors,
+    pub pause_policy: PausePolicy,
+    pub log: LogCfg,
+    pub webhooks: Vec<WebhookCfg>,
+    pub influx: InfluxCfg,
+    pub sectors: Vec<SectorCfg>,
+    pub sources: Vec<SourceCfg>,
+    /// deficit irrigation, the days the sectors get only part of their ET back
+    pub restrictions: Vec<RestrictionCfg>,
+    /// the days the watering window differs from the `[watering]` one
+    pub window_overrides: Vec<WindowOverrideCfg>,
+    /// stored with the sectors at startup, for the wizard plan and the auto schedule
+    pub sector_constraints: Vec<SectorConstraint>,
+    pub profiles: BTreeMap<String, Profile>,
 }
 
 impl Config {
-    pub fn load(args: Args) -> Self {
-        let config_content = fs::read_to_string(args.cfg_file).expect("Unable to read config file");
-        let config: Config = toml::from_str(&config_content).expect("Unable to parse config");
-        config
+    /// With the secrets file and the environment on top, see `Secrets`
+    pub fn load(args: Args) -> Result<Self, ConfigError> {
+        let config_content = fs::read_to_string(&args.cfg_file)
+            .map_err(|e| ConfigError::Read { path: args.cfg_file.display().to_string(), error: e.to_string() })?;
+        let mut config: Config = toml::from_str(&config_content).map_err(|e| ConfigError::Parse(e.to_string()))?;
+        Secrets::load(&args.cfg_file)?.apply(&mut config, |name| std::env::var(name).ok());
+        if let Some(profile) = &args.profile {
+            config.apply_profile(profile)?;
+        }
+        if args.dry_run {
+            config.use_only(SensorBackend::Logging);
+        }
+        validate(&config)?;
+        Ok(config)
+    }
+
+    /// Parses and validates
+    pub fn parse(config_str: &str) -> Result<Self, ConfigError> {
+        let config: Config = toml::from_str(config_str).map_err(|e| ConfigError::Parse(e.to_string()))?;
+        validate(&config)?;
+        Ok(config)
+    }
+
+    /// Points the database and the sensors where the profile says, the time is up to the caller
+    pub fn apply_profile(&mut self, name: &str) -> Result<Profile, ConfigError> {
+        let Some(profile) = self.profiles.get(name).cloned() else {
+            let problem = format!("'{}' is not one of {:?}", name, self.profiles.keys().collect::<Vec<_>>());
+            return Err(ConfigError::Invalid(vec![ConfigIssue { field: "profiles".to_owned(), problem }]));
+        };
+        if let Some(database) = &profile.database {
+            database.clone_into(&mut self.database.name);
+        }
+        match profile.sensors {
+            ProfileSensors::Real => {}
+            ProfileSensors::Stub => self.use_only(SensorBackend::Stub),
+            ProfileSensors::Logging => self.use_only(SensorBackend::Logging),
+        }
+        Ok(profile)
+    }
+
+    /// Every sector on `backend`, the routes are dropped
+    pub fn use_only(&mut self, backend: SensorBackend) {
+        self.sensors.backend = backend;
+        self.sensors.routes.clear();
+    }
+
+    /// The sources' master valves, relays that aren't sectors like the pump
+    pub fn source_masters(&self) -> Vec<u32> {
+        self.sources.iter().filter_map(|source| source.master_sector).collect()
     }
 
     // test helper
     pub fn load_from_str(config_str: &str) -> Self {
-        let config: Config = toml::from_str(config_str).expect("Unable to parse config");
-        config
+        Self::parse(config_str).unwrap_or_else(|e| panic!("{}", e))
     }
 }
 
@@ -122,12 +976,154 @@ impl Config {
 pub mod tests {
     use crate::config::{
         run_options::{default_cfg_file, Args},
-        Config,
+        validate::ConfigError,
+        CoilMap, Config, ModbusTransport, PauseAction, PausePolicy, PinMap, PlanningStrategy, Profile, ProfileTime,
+        SectorRoute, SensorBackend, Sensors, Watering,
     };
+    use crate::watering::{ds::WeatherSignal, modes::Mode};
+    use chrono::{NaiveDate, TimeZone, Utc};
 
     #[test]
     fn load() {
         let cfg = default_cfg_file();
-        println!("{:?}", Config::load(Args { cfg_file: cfg, cfg_str: None }));
+        println!("{:?}", Config::load(Args { cfg_file: cfg, ..Default::default() }).unwrap());
+    }
+
+    #[test]
+    fn load_gpio_pins() {
+        let cfg: Sensors = toml::from_str(
+            r#"backend = "gpio"
+               [gpio]
+               active_low = true
+               pins = [{ sector = 1, pin = 17 }, { sector = 2, pin = 27 }]"#,
+        )
+        .unwrap();
+        assert_eq!(cfg.backend, SensorBackend::Gpio);
+        assert!(cfg.gpio.active_low);
+        assert_eq!(cfg.gpio.pins[1], PinMap { sector: 2, pin: 27 });
+        assert!(cfg.routes.is_empty());
+    }
+
+    #[test]
+    fn load_sector_routes() {
+        let cfg: Sensors = toml::from_str(
+            r#"backend = "gpio"
+               routes = [{ sector = 4, backend = "mqtt" }]"#,
+        )
+        .unwrap();
+        assert_eq!(cfg.routes, vec![SectorRoute { sector: 4, backend: SensorBackend::Mqtt }]);
+    }
+
+    #[test]
+    fn load_modbus_coils() {
+        let cfg: Sensors = toml::from_str(
+            r#"backend = "modbus"
+               [modbus]
+               transport = "rtu"
+               address = "/dev/ttyUSB0"
+               coils = [{ sector = 1, coil = 0 }, { sector = 2, coil = 1 }]"#,
+        )
+        .unwrap();
+        assert_eq!(cfg.backend, SensorBackend::Modbus);
+        assert_eq!(cfg.modbus.transport, ModbusTransport::Rtu);
+        assert_eq!((cfg.modbus.baud_rate, cfg.modbus.unit_id), (9600, 1));
+        assert_eq!(cfg.modbus.coils[1], CoilMap { sector: 2, coil: 1 });
+    }
+
+    #[test]
+    fn apply_profiles() {
+        let mut cfg = Config::load_from_str(
+            r#"[sensors]
+               routes = [{ sector = 4, backend = "mqtt" }]
+               [profiles.production]
+               [profiles.simulation]
+               time = "accelerated"
+               sensors = "stub"
+               database = "simulation.db"
+               [profiles.dry-run]
+               sensors = "logging""#,
+        );
+        assert_eq!(cfg.apply_profile("production").unwrap(), Profile::default());
+        assert_eq!(cfg.sensors.routes.len(), 1);
+
+        let profile = cfg.apply_profile("simulation").unwrap();
+        assert_eq!((profile.time, profile.time_factor), (ProfileTime::Accelerated, 60.));
+        assert_eq!(cfg.database.name, "simulation.db");
+        assert_eq!(cfg.sensors.backend, SensorBackend::Stub);
+        assert!(cfg.sensors.routes.is_empty());
+        cfg.apply_profile("dry-run").unwrap();
+        assert_eq!(cfg.sensors.backend, SensorBackend::Logging);
+
+        let Err(ConfigError::Invalid(issues)) = cfg.apply_profile("dry_run") else {
+            panic!("expected an unknown profile");
+        };
+        assert_eq!(issues[0].problem, r#"'dry_run' is not one of ["dry-run", "production", "simulation"]"#);
+    }
+
+    #[test]
+    fn load_pause_policy() {
+        let policy: PausePolicy = toml::from_str(r#"auto = { rain = "abort" }"#).unwrap();
+        assert_eq!(policy.action(Mode::Auto, &WeatherSignal::RainStart), PauseAction::Abort);
+        assert_eq!(policy.action(Mode::Auto, &WeatherSignal::WindHigh), PauseAction::Ignore);
+        assert_eq!(policy.action(Mode::Wizard, &WeatherSignal::WindHigh), PauseAction::Pause);
+        assert_eq!(policy.action(Mode::Wizard, &WeatherSignal::RainStop), PauseAction::Ignore);
+    }
+
+    #[test]
+    fn load_planning_strategy() {
+        let cfg: Watering = toml::from_str(r#"planning_strategy = "balanced""#).unwrap();
+        assert_eq!(cfg.planning_strategy, PlanningStrategy::Balanced);
+        assert!(toml::from_str::<Watering>(r#"planning_strategy = "lp""#).is_err());
+        assert_eq!(Watering::default().planning_strategy, PlanningStrategy::Greedy);
+    }
+
+    #[test]
+    fn restrictions_take_whole_days() {
+        let cfg = Config::load_from_str(
+            r#"[[restrictions]]
+               from = "2024-07-01"
+               to = "2024-08-31"
+               et_pct = 80"#,
+        );
+        let summer = &cfg.restrictions[0];
+        let at = |y, m, d, h| Utc.with_ymd_and_hms(y, m, d, h, 0, 0).unwrap().timestamp();
+        assert!(summer.contains(at(2024, 7, 1, 0)) && summer.contains(at(2024, 8, 31, 23)));
+        assert!(!summer.contains(at(2024, 6, 30, 23)) && !summer.contains(at(2024, 9, 1, 0)));
+        assert!(!summer.contains(at(2025, 7, 15, 12)), "only the year declared");
+    }
+
+    #[test]
+    fn window_overrides_by_weekday_and_date() {
+        let cfg = Config::load_from_str(
+            r#"[[window_overrides]]
+               weekdays = ["fri"]
+               duration_hours = 4
+               [[window_overrides]]
+               from = "2024-07-15"
+               to = "2024-07-21"
+               weekdays = ["mon", "tue"]
+               start_hour = 20"#,
+        );
+        let day = |m, d| NaiveDate::from_ymd_opt(2024, m, d).unwrap();
+        let (fridays, heat_wave) = (&cfg.window_overrides[0], &cfg.window_overrides[1]);
+        assert!(fridays.applies(day(7, 19)) && !fridays.applies(day(7, 20)));
+        assert!(heat_wave.applies(day(7, 15)) && heat_wave.applies(day(7, 16)));
+        assert!(!heat_wave.applies(day(7, 17)), "a wednesday");
+        assert!(!heat_wave.applies(day(7, 22)), "a monday after the dates");
+    }
+
+    #[test]
+    fn source_hours_wrap_midnight() {
+        let cfg = Config::load_from_str(
+            r#"[[sources]]
+               name = "well"
+               master_sector = 10
+               hours = [22, 6]
+               sectors = [1, 2]"#,
+        );
+        let well = &cfg.sources[0];
+        assert!(well.serves(1, 23) && well.serves(2, 0) && well.serves(1, 5));
+        assert!(!well.serves(1, 6) && !well.serves(1, 21) && !well.serves(3, 23));
+        assert_eq!(cfg.source_masters(), [10]);
     }
 }
@@ -1,60 +1,217 @@
-use std::{
-    env,
-    path::{Path, PathBuf},
-};
+use std::path::PathBuf;
 
-use getopts::Options;
-use tracing::warn;
+use chrono::Weekday;
+use clap::{Parser, Subcommand};
 
-use crate::{config::CONFIG_FILE, utils::remove_folder_from_path};
+#[cfg(feature = "sim")]
+use crate::weather::generator::SyntheticWeather;
+use crate::{
+    config::CONFIG_FILE,
+    utils::remove_folder_from_path,
+    watering::{
+        import::ControllerFormat,
+        schedule_file::ScheduleFormat,
+        watering_alg::{is_program_name, DEFAULT_PROGRAM},
+    },
+};
 
+/// What `Config::load` needs
 #[derive(Clone, Debug, Default)]
 pub struct Args {
     pub cfg_file: PathBuf,
+    /// `--profile`, one of the `[profiles]` in the config
+    pub profile: Option<String>,
+    /// `--dry-run`, every sector on the logging backend
+    pub dry_run: bool,
     // test helper
     pub cfg_str: Option<String>,
 }
 
-pub fn print_usage(program: &str, opts: Options) {
-    let brief = format!("Usage: {} [options] [config_file]", program);
-    print!("{}", opts.usage(&brief));
-}
-
-pub fn get_args() -> Args {
-    let args: Vec<String> = env::args().collect();
-    let program = args[0].clone();
-    let opts = Options::new();
-
-    let default_args = Args {
-        cfg_file: default_cfg_file(),
-        cfg_str: None,
-    };
-    let matches = match opts.parse(&args[1..]) {
-        Ok(m) => m,
-        Err(f) => {
-            warn!("Error parsing arguments: {}", f);
-            warn!("Proceeding with defaults.");
-            print_usage(&program, opts);
-            return default_args;
-        }
-    };
-
-    let config_file_path = matches.free.first().map(|s| s.as_str());
-    let Some(config_file_path) = config_file_path else {
-        return default_args;
-    };
-    let path = remove_folder_from_path(Path::new(config_file_path), "");
-
-    // Attempt to load the config file, but proceed with default if it fails
-    if !path.exists() {
-        eprintln!(
-            "Warning: Config file '{}' does not exist. Proceeding with defaults.",
-            config_file_path
-        );
-        return default_args;
+/// Irrigation controller. With no command, `nic run`.
+#[derive(Parser, Debug)]
+#[command(name = "nic", version, args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    #[command(flatten)]
+    pub run: RunArgs,
+}
+
+#[derive(clap::Args, Clone, Debug, Default, PartialEq)]
+pub struct CfgArgs {
+    /// The config file, ./nic.toml when not given
+    pub config: Option<PathBuf>,
+    /// One of the [profiles] of the config
+    #[arg(short, long)]
+    pub profile: Option<String>,
+}
+
+impl CfgArgs {
+    pub fn args(&self) -> Args {
+        let cfg_file = self.config.clone().unwrap_or_else(default_cfg_file);
+        Args { cfg_file, profile: self.profile.clone(), ..Default::default() }
+    }
+}
+
+#[derive(clap::Args, Clone, Debug, Default, PartialEq)]
+pub struct RunArgs {
+    #[command(flatten)]
+    pub cfg: CfgArgs,
+    /// Touch no valve, record what would have been done in the system events
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+impl RunArgs {
+    pub fn args(&self) -> Args {
+        Args { dry_run: self.dry_run, ..self.cfg.args() }
+    }
+}
+
+#[derive(Subcommand, Debug, PartialEq)]
+pub enum Command {
+    /// Start the controller
+    Run(RunArgs),
+    /// Write the default config, unless there is one, and create the database
+    Init { config: Option<PathBuf> },
+    /// Run the watering loop over the coming days, with no hardware, and print what it did each day
+    #[cfg(feature = "sim")]
+    Simulate {
+        #[command(flatten)]
+        cfg: CfgArgs,
+        #[arg(long, default_value_t = 14)]
+        days: u32,
+        /// Simulated seconds per second, as fast as possible when not given
+        #[arg(long)]
+        speed: Option<f64>,
+        /// The weather, a toml file
+        #[arg(long)]
+        scenario: Option<PathBuf>,
+    },
+    /// Backtest the wizard against a daily weather history, a `date,et,rain` csv
+    #[cfg(feature = "sim")]
+    Replay {
+        #[command(flatten)]
+        cfg: CfgArgs,
+        #[arg(long)]
+        weather: PathBuf,
+        /// The first days of the history, all of it when not given
+        #[arg(long)]
+        days: Option<u32>,
+    },
+    /// Database maintenance
+    #[command(subcommand)]
+    Db(DbCommand),
+    /// The weekly schedule of the auto mode
+    #[command(subcommand)]
+    Schedule(ScheduleCommand),
+    /// Replace the schedule with the one of another controller, adding the sectors the database doesn't have
+    Import {
+        file: PathBuf,
+        /// From the file extension when not given, opensprinkler for .ini
+        #[arg(long, value_enum)]
+        format: Option<ControllerFormat>,
+        #[command(flatten)]
+        cfg: CfgArgs,
+    },
+    /// The zones
+    #[command(subcommand)]
+    Sector(SectorCommand),
+    /// The weather sources
+    #[command(subcommand)]
+    Weather(WeatherCommand),
+}
+
+#[derive(Subcommand, Debug, PartialEq)]
+pub enum DbCommand {
+    /// Create the database, or bring an old one up to the current schema
+    Migrate(CfgArgs),
+}
+
+#[derive(Subcommand, Debug, PartialEq)]
+pub enum ScheduleCommand {
+    Show(CfgArgs),
+    /// Water a sector on a weekday, a duration of 0 removes it
+    Set {
+        /// mon, tue, ...
+        day: Weekday,
+        sector: u32,
+        /// UTC, HH:MM
+        #[arg(value_parser = parse_hour)]
+        start: i64,
+        /// seconds
+        duration: i64,
+        /// the program the day belongs to
+        #[arg(long, default_value = DEFAULT_PROGRAM, value_parser = parse_program)]
+        program: String,
+        #[command(flatten)]
+        cfg: CfgArgs,
+    },
+    /// Print the schedule
+    Export {
+        #[arg(long, value_enum, default_value_t)]
+        format: ScheduleFormat,
+        #[command(flatten)]
+        cfg: CfgArgs,
+    },
+    /// Replace the schedule with an exported one, a running controller takes it on its next start
+    Import {
+        file: PathBuf,
+        /// From the file extension when not given
+        #[arg(long, value_enum)]
+        format: Option<ScheduleFormat>,
+        #[command(flatten)]
+        cfg: CfgArgs,
+    },
+}
+
+#[derive(Subcommand, Debug, PartialEq)]
+pub enum SectorCommand {
+    List(CfgArgs),
+}
+
+#[derive(Subcommand, Debug, PartialEq)]
+pub enum WeatherCommand {
+    /// Get one reading from every configured source
+    Test(CfgArgs),
+    /// Start made up weather in a running controller built with `sim`, next to its station's and not stored
+    #[cfg(feature = "sim")]
+    Generate {
+        #[command(flatten)]
+        cfg: CfgArgs,
+        #[command(flatten)]
+        weather: SyntheticWeather,
+        /// Where the controller's API listens, the `web_server.address` of the config on this host when not given
+        #[arg(long)]
+        to: Option<String>,
+        /// Stop the weather going on instead
+        #[arg(long)]
+        stop: bool,
+    },
+}
+
+/// HH:MM to seconds from the start of the day
+fn parse_hour(value: &str) -> Result<i64, String> {
+    let bad = || format!("'{}' is not HH:MM", value);
+    let (hours, minutes) = value.split_once(':').ok_or_else(bad)?;
+    let (hours, minutes): (i64, i64) = (hours.parse().map_err(|_| bad())?, minutes.parse().map_err(|_| bad())?);
+    if !(0..24).contains(&hours) || !(0..60).contains(&minutes) {
+        return Err(bad());
     }
+    Ok(hours * 3600 + minutes * 60)
+}
 
-    Args { cfg_file: path , cfg_str: None}
+fn parse_program(value: &str) -> Result<String, String> {
+    match is_program_name(value) {
+        true => Ok(value.to_owned()),
+        false => Err(format!("'{}' is not a program name, letters and digits", value)),
+    }
+}
+
+/// `nic [config]` is still `nic run [config]`
+pub fn get_args() -> Command {
+    let cli = Cli::parse();
+    cli.command.unwrap_or(Command::Run(cli.run))
 }
 
 pub fn default_cfg_file() -> PathBuf {
@@ -64,3 +221,96 @@ pub fn default_cfg_file() -> PathBuf {
     new_configpath.push(CONFIG_FILE);
     new_configpath
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Command {
+        let cli = Cli::try_parse_from(args).unwrap();
+        cli.command.unwrap_or(Command::Run(cli.run))
+    }
+
+    #[test]
+    fn commands() {
+        let cfg = CfgArgs { config: Some(PathBuf::from("my.toml")), profile: None };
+        assert_eq!(parse(&["nic", "my.toml"]), Command::Run(RunArgs { cfg: cfg.clone(), dry_run: false }));
+        assert_eq!(
+            parse(&["nic", "run", "-p", "simulation"]),
+            Command::Run(RunArgs {
+                cfg: CfgArgs { config: None, profile: Some("simulation".to_owned()) },
+                dry_run: false
+            })
+        );
+        assert_eq!(parse(&["nic", "--dry-run"]), Command::Run(RunArgs { dry_run: true, ..Default::default() }));
+        assert_eq!(parse(&["nic", "sector", "list", "my.toml"]), Command::Sector(SectorCommand::List(cfg.clone())));
+        assert_eq!(
+            parse(&["nic", "schedule", "set", "wed", "2", "22:30", "1200"]),
+            Command::Schedule(ScheduleCommand::Set {
+                day: Weekday::Wed,
+                sector: 2,
+                start: 22 * 3600 + 1800,
+                duration: 1200,
+                program: "A".to_owned(),
+                cfg: CfgArgs::default()
+            })
+        );
+        assert_eq!(
+            parse(&["nic", "schedule", "set", "wed", "2", "22:30", "1200", "--program", "B"]),
+            Command::Schedule(ScheduleCommand::Set {
+                day: Weekday::Wed,
+                sector: 2,
+                start: 22 * 3600 + 1800,
+                duration: 1200,
+                program: "B".to_owned(),
+                cfg: CfgArgs::default()
+            })
+        );
+        assert!(Cli::try_parse_from(["nic", "schedule", "set", "wed", "2", "25:00", "1200"]).is_err());
+        assert_eq!(
+            parse(&["nic", "schedule", "export", "--format", "csv"]),
+            Command::Schedule(ScheduleCommand::Export { format: ScheduleFormat::Csv, cfg: CfgArgs::default() })
+        );
+        assert_eq!(
+            parse(&["nic", "schedule", "import", "week.csv"]),
+            Command::Schedule(ScheduleCommand::Import {
+                file: PathBuf::from("week.csv"),
+                format: None,
+                cfg: CfgArgs::default()
+            })
+        );
+        assert_eq!(
+            parse(&["nic", "import", "rachio.json", "--format", "opensprinkler"]),
+            Command::Import {
+                file: PathBuf::from("rachio.json"),
+                format: Some(ControllerFormat::OpenSprinkler),
+                cfg: CfgArgs::default()
+            }
+        );
+        #[cfg(feature = "sim")]
+        assert_eq!(
+            parse(&["nic", "simulate", "--days", "7", "--scenario", "dry.toml"]),
+            Command::Simulate {
+                cfg: CfgArgs::default(),
+                days: 7,
+                speed: None,
+                scenario: Some(PathBuf::from("dry.toml"))
+            }
+        );
+        #[cfg(feature = "sim")]
+        assert_eq!(
+            parse(&["nic", "replay", "--weather", "history.csv", "--days", "90"]),
+            Command::Replay { cfg: CfgArgs::default(), weather: PathBuf::from("history.csv"), days: Some(90) }
+        );
+        #[cfg(feature = "sim")]
+        assert_eq!(
+            parse(&["nic", "weather", "generate", "--hours", "2", "--speed", "60", "--seed", "7"]),
+            Command::Weather(WeatherCommand::Generate {
+                cfg: CfgArgs::default(),
+                weather: SyntheticWeather { hours: 2, speed: 60., seed: 7, ..Default::default() },
+                to: None,
+                stop: false,
+            })
+        );
+    }
+}
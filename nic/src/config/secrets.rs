@@ -0,0 +1,131 @@
+use super::{validate::ConfigError, Config};
+use serde::Deserialize;
+use std::{
+    fmt::{Debug, Display},
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Kept next to `nic.toml`, and out of git
+pub const SECRETS_FILE: &str = "nic.secrets.toml";
+
+/// A credential, printed as `***` in `Debug` and `Display`, so configs can be logged.<br>
+/// Use `expose` where the value is actually sent.
+#[derive(Clone, Default, Deserialize, PartialEq)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(value: &str) -> Self {
+        Self(value.to_owned())
+    }
+}
+
+impl Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+impl Display for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // an empty one is worth knowing about
+        if self.0.is_empty() {
+            write!(f, "\"\"")
+        } else {
+            write!(f, "***")
+        }
+    }
+}
+
+/// The secrets file, every entry optional
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Secrets {
+    pub token_tempest: Option<Secret>,
+    pub api_key_openweathermap: Option<Secret>,
+    pub mqtt_username: Option<String>,
+    pub mqtt_password: Option<Secret>,
+    pub db_key: Option<Secret>,
+}
+
+impl Secrets {
+    /// `NIC_SECRETS_FILE`, or `nic.secrets.toml` in the config folder. A missing file is fine.
+    pub fn load(cfg_file: &Path) -> Result<Self, ConfigError> {
+        let path = std::env::var("NIC_SECRETS_FILE")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| cfg_file.parent().unwrap_or(Path::new("")).join(SECRETS_FILE));
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(&path)
+            .map_err(|e| ConfigError::Read { path: path.display().to_string(), error: e.to_string() })?;
+        toml::from_str(&content).map_err(|e| ConfigError::Parse(format!("{}: {}", path.display(), e)))
+    }
+
+    /// The environment wins over the secrets file, which wins over `nic.toml`
+    pub fn apply(self, cfg: &mut Config, env: impl Fn(&str) -> Option<String>) {
+        let secret = |name: &str, file: Option<Secret>| env(name).map(Secret).or(file);
+        if let Some(token) = secret("NIC_TEMPEST_TOKEN", self.token_tempest) {
+            cfg.weather_station.token_tempest = token;
+        }
+        if let Some(key) = secret("NIC_OPENWEATHERMAP_KEY", self.api_key_openweathermap) {
+            cfg.weather_station.api_key_openweathermap = key;
+        }
+        if let Some(username) = env("NIC_MQTT_USERNAME").or(self.mqtt_username) {
+            cfg.mqtt.username = Some(username);
+        }
+        if let Some(password) = secret("NIC_MQTT_PASSWORD", self.mqtt_password) {
+            cfg.mqtt.password = password;
+        }
+        if let Some(key) = secret("NIC_DB_KEY", self.db_key) {
+            cfg.database.key = key;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn redacted_in_debug() {
+        let mut cfg = Config::default();
+        cfg.mqtt.password = "hunter2".into();
+        assert!(!format!("{:?}", cfg).contains("hunter2"));
+        assert_eq!(format!("{:?}", cfg.mqtt.password), "***");
+        assert_eq!(format!("{:?}", Secret::default()), "\"\"");
+    }
+
+    #[test]
+    fn env_over_file_over_config() {
+        let mut cfg = Config::default();
+        cfg.weather_station.token_tempest = "from nic.toml".into();
+        cfg.weather_station.api_key_openweathermap = "from nic.toml".into();
+        let secrets: Secrets = toml::from_str(
+            r#"token_tempest = "from the file"
+               mqtt_username = "nic"
+               mqtt_password = "from the file"
+               db_key = "from the file""#,
+        )
+        .unwrap();
+        let env = |name: &str| ["NIC_MQTT_PASSWORD", "NIC_DB_KEY"].contains(&name).then(|| "from env".to_owned());
+        secrets.apply(&mut cfg, env);
+
+        assert_eq!(cfg.weather_station.token_tempest.expose(), "from the file");
+        assert_eq!(cfg.weather_station.api_key_openweathermap.expose(), "from nic.toml");
+        assert_eq!(cfg.mqtt.username.as_deref(), Some("nic"));
+        assert_eq!(cfg.mqtt.password.expose(), "from env");
+        assert_eq!(cfg.database.key.expose(), "from env");
+    }
+}
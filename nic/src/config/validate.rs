@@ -0,0 +1,457 @@
+use super::{Config, LogRotation, ProfileTime, SensorBackend};
+use crate::{sensors::mqtt_ctrl::broker, weather::provider::ProviderKind, webhooks::WEBHOOK_EVENTS};
+use std::{collections::HashSet, fmt::Display, hash::Hash, net::SocketAddr};
+use thiserror::Error;
+use tracing_subscriber::EnvFilter;
+
+/// One thing wrong with the config, `field` is its path in `nic.toml`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigIssue {
+    pub field: String,
+    pub problem: String,
+}
+
+impl Display for ConfigIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "  {}: {}", self.field, self.problem)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("Can't read {path}: {error}")]
+    Read { path: String, error: String },
+    #[error("Can't parse the config: {0}")]
+    Parse(String),
+    #[error("{} problem(s) in the config:\n{}", .0.len(), report(.0))]
+    Invalid(Vec<ConfigIssue>),
+}
+
+fn report(issues: &[ConfigIssue]) -> String {
+    issues.iter().map(|issue| issue.to_string()).collect::<Vec<_>>().join("\n")
+}
+
+#[derive(Default)]
+struct Issues(Vec<ConfigIssue>);
+
+impl Issues {
+    fn check(&mut self, ok: bool, field: &str, problem: impl Into<String>) {
+        if !ok {
+            self.0.push(ConfigIssue { field: field.to_owned(), problem: problem.into() });
+        }
+    }
+
+    fn not_negative(&mut self, value: f64, field: &str) {
+        self.check(value >= 0., field, format!("must not be negative, got {}", value));
+    }
+
+    /// Each sector mapped once
+    fn unique<T: Eq + Hash>(&mut self, values: impl Iterator<Item = T>, field: &str) {
+        let mut seen = HashSet::new();
+        for value in values {
+            if !seen.insert(value) {
+                self.0
+                    .push(ConfigIssue { field: field.to_owned(), problem: "sector listed more than once".to_owned() });
+                return;
+            }
+        }
+    }
+}
+
+/// Every problem we can spot before starting, so they can all be fixed in one go
+pub fn validate(cfg: &Config) -> Result<(), ConfigError> {
+    let mut issues = Issues::default();
+
+    issues.check(!cfg.database.name.is_empty(), "database.name", "must not be empty");
+    let db = &cfg.database;
+    issues.check((0..24).contains(&db.maintenance_hour), "database.maintenance_hour", "must be between 0 and 23");
+    issues.not_negative(db.maintenance_every_days as f64, "database.maintenance_every_days");
+    let site = &cfg.site.id;
+    let site_ok =
+        !site.is_empty() && site.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "-_".contains(c));
+    issues.check(site_ok, "site.id", format!("'{}' isn't lowercase letters, digits, - and _", site));
+    let clock = &cfg.clock;
+    issues.check(clock.max_jump_secs > 0, "clock.max_jump_secs", "must be positive");
+    issues.check(clock.max_drift_secs > 0, "clock.max_drift_secs", "must be positive");
+    if let Some(server) = &clock.ntp_server {
+        let ok = server.rsplit_once(':').is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok());
+        issues.check(ok, "clock.ntp_server", format!("'{}' is not a host:port", server));
+    }
+    if db.encrypted {
+        issues.check(!db.key.is_empty(), "database.key", "an encrypted database needs a key");
+        issues.check(cfg!(feature = "sqlcipher"), "database.encrypted", "nic was built without the sqlcipher feature");
+    }
+    let web = &cfg.web_server.address;
+    issues.check(web.parse::<SocketAddr>().is_ok(), "web_server.address", format!("'{}' is not an ip:port", web));
+    if let Some(grpc) = &cfg.web_server.grpc_address {
+        let ok = grpc.parse::<SocketAddr>().is_ok();
+        issues.check(ok, "web_server.grpc_address", format!("'{}' is not an ip:port", grpc));
+        issues.check(cfg!(feature = "grpc"), "web_server.grpc_address", "nic was built without the grpc feature");
+    }
+    let rate = cfg.web_server.control_rate;
+    issues.check(rate.is_finite() && rate >= 0., "web_server.control_rate", "must not be negative");
+    issues.check(cfg.web_server.control_burst > 0, "web_server.control_burst", "must be positive");
+    issues.check(cfg.web_server.max_upload_bytes > 0, "web_server.max_upload_bytes", "must be positive");
+    for (name, profile) in &cfg.profiles {
+        let ok = profile.time != ProfileTime::Simulated || cfg!(feature = "sim");
+        issues.check(ok, &format!("profiles.{}.time", name), "nic was built without the sim feature");
+    }
+    issues.check(broker(&cfg.mqtt.address).is_ok(), "mqtt.address", format!("'{}' has a bad port", cfg.mqtt.address));
+    let publish = &cfg.mqtt.publish;
+    issues.check(!publish.enabled || publish.status_secs > 0, "mqtt.publish.status_secs", "must be > 0");
+
+    let ws = &cfg.weather_station;
+    if ws.providers.contains(&ProviderKind::Udp) {
+        let ok = ws.udp_address.parse::<SocketAddr>().is_ok();
+        issues.check(ok, "weather_station.udp_address", format!("'{}' is not an ip:port", ws.udp_address));
+    }
+    issues.not_negative(ws.rain_threshold, "weather_station.rain_threshold");
+    issues.not_negative(ws.rain_hysteresis, "weather_station.rain_hysteresis");
+    issues.not_negative(ws.rain_debounce_secs as f64, "weather_station.rain_debounce_secs");
+    issues.not_negative(ws.wind_threshold, "weather_station.wind_threshold");
+    issues.not_negative(ws.wind_hysteresis, "weather_station.wind_hysteresis");
+    issues.not_negative(ws.wind_debounce_secs as f64, "weather_station.wind_debounce_secs");
+    issues.not_negative(ws.stale_after_hours as f64, "weather_station.stale_after_hours");
+    issues.not_negative(ws.fallback_et, "weather_station.fallback_et");
+
+    let w = &cfg.watering;
+    issues.not_negative(w.sector_transation_secs as f64, "watering.sector_transation_secs");
+    issues.check(w.max_duration_secs > 0, "watering.max_duration_secs", "must be positive");
+    issues.not_negative(w.min_watering_secs as f64, "watering.min_watering_secs");
+    daily_caps(&mut issues, w.max_daily_mm, w.max_daily_minutes, "watering");
+    issues.check(
+        w.min_watering_secs <= w.max_duration_secs,
+        "watering.min_watering_secs",
+        format!("must not exceed max_duration_secs ({})", w.max_duration_secs),
+    );
+    issues.not_negative(w.valve_check_secs as f64, "watering.valve_check_secs");
+    issues.not_negative(w.max_pause_secs as f64, "watering.max_pause_secs");
+    issues.check((0..24).contains(&w.window_start_hour), "watering.window_start_hour", "must be between 0 and 23");
+    // longer than a day and tonight's window runs into tomorrow's
+    issues.check(
+        (1..=24).contains(&w.window_duration_hours),
+        "watering.window_duration_hours",
+        "must be between 1 and 24",
+    );
+    if let Some(hour) = w.adjustment_hour {
+        issues.check((0..24).contains(&hour), "watering.adjustment_hour", "must be between 0 and 23");
+    }
+    issues.not_negative(w.learning_every_days as f64, "watering.learning_every_days");
+    issues.check(w.learning_window_days > 0, "watering.learning_window_days", "must be positive");
+    // two unknowns, a third interval to tell the fit from chance
+    issues.check(w.learning_min_samples >= 3, "watering.learning_min_samples", "must be at least 3");
+    issues.not_negative(w.avoid_rain_mm, "watering.avoid_rain_mm");
+    issues.not_negative(w.avoid_wind_kmh, "watering.avoid_wind_kmh");
+    issues.not_negative(w.max_cycle_secs as f64, "watering.max_cycle_secs");
+    issues.not_negative(w.progress_stale_days as f64, "watering.progress_stale_days");
+
+    let s = &cfg.sensors;
+    issues.check(s.retry.attempts > 0, "sensors.retry.attempts", "must be at least 1");
+    issues.check(s.interlock.max_open_sectors > 0, "sensors.interlock.max_open_sectors", "must be at least 1");
+    issues.not_negative(s.watchdog.grace_secs as f64, "sensors.watchdog.grace_secs");
+    issues.unique(s.routes.iter().map(|route| route.sector), "sensors.routes");
+    issues.unique(s.gpio.pins.iter().map(|map| map.sector), "sensors.gpio.pins");
+    issues.unique(s.modbus.coils.iter().map(|map| map.sector), "sensors.modbus.coils");
+    if s.backend == SensorBackend::Http || s.routes.iter().any(|route| route.backend == SensorBackend::Http) {
+        let url = &s.http.base_url;
+        let http = url.starts_with("http://") || url.starts_with("https://");
+        issues.check(http, "sensors.http.base_url", format!("'{}' is not an http(s) URL", url));
+    }
+
+    let log = &cfg.log;
+    for (level, field) in [(&log.console_level, "log.console_level"), (&log.file_level, "log.file_level")] {
+        issues.check(EnvFilter::try_new(level).is_ok(), field, format!("'{}' is not a log filter", level));
+    }
+    if let Some(file) = &log.file {
+        issues.check(!file.is_empty(), "log.file", "must not be empty");
+    }
+    if log.rotation == LogRotation::Size {
+        issues.check(log.max_size_mb > 0, "log.max_size_mb", "must be positive");
+        issues.check(log.max_files > 0, "log.max_files", "must be at least 1 with a size rotation");
+    }
+
+    let influx = &cfg.influx;
+    if influx.enabled {
+        let http = influx.url.starts_with("http://") || influx.url.starts_with("https://");
+        issues.check(http, "influx.url", format!("'{}' is not an http(s) URL", influx.url));
+        issues.check(influx.flush_secs > 0, "influx.flush_secs", "must be positive");
+        issues.check(influx.batch_size > 0, "influx.batch_size", "must be at least 1");
+        issues.check(influx.max_buffer >= influx.batch_size, "influx.max_buffer", "must hold a batch");
+    }
+
+    for (n, hook) in cfg.webhooks.iter().enumerate() {
+        let field = |name: &str| format!("webhooks.{}.{}", n, name);
+        let http = hook.url.starts_with("http://") || hook.url.starts_with("https://");
+        issues.check(http, &field("url"), format!("'{}' is not an http(s) URL", hook.url));
+        issues.check(hook.attempts > 0, &field("attempts"), "must be at least 1");
+        for event in hook.events.iter().filter(|event| !WEBHOOK_EVENTS.contains(&event.as_str())) {
+            issues.check(false, &field("events"), format!("'{}' is not one of {:?}", event, WEBHOOK_EVENTS));
+        }
+    }
+
+    issues.unique(cfg.sectors.iter().map(|sector| sector.id), "sectors");
+    for sector in &cfg.sectors {
+        let field = |name: &str| format!("sectors.{}.{}", sector.id, name);
+        issues.check(sector.sprinkler_debit > 0., &field("sprinkler_debit"), "must be positive");
+        issues.not_negative(sector.percolation_rate, &field("percolation_rate"));
+        issues.not_negative(sector.weekly_target, &field("weekly_target"));
+        issues.check(sector.max_duration > 0, &field("max_duration"), "must be positive");
+        daily_caps(&mut issues, sector.max_daily_mm, sector.max_daily_minutes, &format!("sectors.{}", sector.id));
+    }
+
+    let mut names = HashSet::new();
+    for (n, source) in cfg.sources.iter().enumerate() {
+        let field = |name: &str| format!("sources.{}.{}", n, name);
+        issues.check(!source.name.is_empty(), &field("name"), "must not be empty");
+        issues.check(names.insert(&source.name), &field("name"), format!("'{}' is used twice", source.name));
+        issues.not_negative(source.flow_lpm, &field("flow_lpm"));
+        if let Some((start, end)) = source.hours {
+            issues.check(start < 24 && end < 24, &field("hours"), "must be hours between 0 and 23");
+        }
+    }
+
+    for (n, restriction) in cfg.restrictions.iter().enumerate() {
+        let field = |name: &str| format!("restrictions.{}.{}", n, name);
+        match restriction.days() {
+            Some((first, last)) => issues.check(first <= last, &field("to"), "must not be before from"),
+            None => issues.check(false, &field("from"), "from and to must be YYYY-MM-DD dates"),
+        }
+        let pct = restriction.et_pct;
+        issues.check(pct > 0. && pct <= 100., &field("et_pct"), "must be more than 0 and at most 100");
+    }
+
+    for (n, window) in cfg.window_overrides.iter().enumerate() {
+        let field = |name: &str| format!("window_overrides.{}.{}", n, name);
+        issues.check(window.days_of_week().is_some(), &field("weekdays"), "must be days from mon to sun");
+        match (window.from.is_some() || window.to.is_some(), window.dates()) {
+            (false, _) => issues.check(!window.weekdays.is_empty(), &field("weekdays"), "or from must be set"),
+            (true, Some((first, last))) => issues.check(first <= last, &field("to"), "must not be before from"),
+            (true, None) => issues.check(false, &field("from"), "from and to must be YYYY-MM-DD dates"),
+        }
+        if let Some(hour) = window.start_hour {
+            issues.check((0..24).contains(&hour), &field("start_hour"), "must be between 0 and 23");
+        }
+        if let Some(hours) = window.duration_hours {
+            issues.check((0..=24).contains(&hours), &field("duration_hours"), "must be between 0 and 24");
+        }
+    }
+
+    let mut pairs = HashSet::new();
+    for (n, constraint) in cfg.sector_constraints.iter().enumerate() {
+        let field = |name: &str| format!("sector_constraints.{}.{}", n, name);
+        let (a, b) = constraint.sectors;
+        issues.check(a != b, &field("sectors"), "must be two different sectors");
+        issues.check(pairs.insert((a.min(b), a.max(b))), &field("sectors"), "the pair is constrained twice");
+        issues.check(constraint.min_gap_secs >= 0, &field("min_gap_secs"), "must not be negative");
+    }
+
+    match issues.0.is_empty() {
+        true => Ok(()),
+        false => Err(ConfigError::Invalid(issues.0)),
+    }
+}
+
+/// A cap of 0 would never water, leave it out instead
+fn daily_caps(issues: &mut Issues, mm: Option<f64>, minutes: Option<i64>, section: &str) {
+    if let Some(mm) = mm {
+        issues.check(mm > 0., &format!("{}.max_daily_mm", section), "must be positive");
+    }
+    if let Some(minutes) = minutes {
+        issues.check(minutes > 0, &format!("{}.max_daily_minutes", section), "must be positive");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lists_every_problem() {
+        let cfg: Config = toml::from_str(
+            r#"[web_server]
+               address = "localhost"
+               [weather_station]
+               rain_threshold = -1
+               [watering]
+               window_duration_hours = 30
+               [sensors.gpio]
+               pins = [{ sector = 1, pin = 17 }, { sector = 1, pin = 27 }]"#,
+        )
+        .unwrap();
+        let Err(ConfigError::Invalid(issues)) = validate(&cfg) else {
+            panic!("expected the config to be invalid");
+        };
+        let fields: Vec<&str> = issues.iter().map(|issue| issue.field.as_str()).collect();
+        assert_eq!(
+            fields,
+            [
+                "web_server.address",
+                "weather_station.rain_threshold",
+                "watering.window_duration_hours",
+                "sensors.gpio.pins"
+            ]
+        );
+        let report = ConfigError::Invalid(issues).to_string();
+        assert!(report.starts_with("4 problem(s) in the config:\n  web_server.address: 'localhost' is not an ip:port"));
+    }
+
+    #[test]
+    fn checks_the_limits_of_the_web_server() {
+        let cfg: Config = toml::from_str(
+            r#"[web_server]
+               control_rate = -1
+               control_burst = 0"#,
+        )
+        .unwrap();
+        let Err(ConfigError::Invalid(issues)) = validate(&cfg) else {
+            panic!("expected the config to be invalid");
+        };
+        let fields: Vec<&str> = issues.iter().map(|issue| issue.field.as_str()).collect();
+        assert_eq!(fields, ["web_server.control_rate", "web_server.control_burst"]);
+    }
+
+    #[test]
+    fn checks_the_log() {
+        let cfg: Config = toml::from_str(
+            r#"[log]
+               console_level = "nic=loud"
+               file = "nic.log"
+               rotation = "size"
+               max_files = 0"#,
+        )
+        .unwrap();
+        let Err(ConfigError::Invalid(issues)) = validate(&cfg) else {
+            panic!("expected the config to be invalid");
+        };
+        let fields: Vec<&str> = issues.iter().map(|issue| issue.field.as_str()).collect();
+        assert_eq!(fields, ["log.console_level", "log.max_files"]);
+    }
+
+    #[test]
+    fn checks_the_webhooks() {
+        let cfg: Config = toml::from_str(
+            r#"[[webhooks]]
+               url = "http://hass.local/api/webhook/nic"
+               events = ["cycle_started", "sector_fault"]
+               [[webhooks]]
+               url = "hass.local"
+               events = ["rain"]
+               attempts = 0"#,
+        )
+        .unwrap();
+        let Err(ConfigError::Invalid(issues)) = validate(&cfg) else {
+            panic!("expected the config to be invalid");
+        };
+        let fields: Vec<&str> = issues.iter().map(|issue| issue.field.as_str()).collect();
+        assert_eq!(fields, ["webhooks.1.url", "webhooks.1.attempts", "webhooks.1.events"]);
+    }
+
+    #[test]
+    fn an_encrypted_database_needs_a_key() {
+        let cfg: Config = toml::from_str("[database]\nencrypted = true").unwrap();
+        let Err(ConfigError::Invalid(issues)) = validate(&cfg) else {
+            panic!("expected the config to be invalid");
+        };
+        assert_eq!(issues[0].field, "database.key");
+        assert_eq!(issues.len(), if cfg!(feature = "sqlcipher") { 1 } else { 2 });
+    }
+
+    #[test]
+    fn checks_the_sources() {
+        let cfg: Config = toml::from_str(
+            r#"[[sources]]
+               name = "well"
+               hours = [22, 6]
+               [[sources]]
+               name = "well"
+               flow_lpm = -1
+               hours = [6, 24]"#,
+        )
+        .unwrap();
+        let Err(ConfigError::Invalid(issues)) = validate(&cfg) else {
+            panic!("expected the config to be invalid");
+        };
+        let fields: Vec<&str> = issues.iter().map(|issue| issue.field.as_str()).collect();
+        assert_eq!(fields, ["sources.1.name", "sources.1.flow_lpm", "sources.1.hours"]);
+    }
+
+    #[test]
+    fn checks_the_restrictions() {
+        let cfg: Config = toml::from_str(
+            r#"[[restrictions]]
+               from = "2024-07-01"
+               to = "2024-08-31"
+               et_pct = 80
+               [[restrictions]]
+               from = "2024-09-01"
+               to = "2024-08-01"
+               et_pct = 0
+               [[restrictions]]
+               from = "1 July"
+               to = "2024-08-31"
+               et_pct = 100"#,
+        )
+        .unwrap();
+        let Err(ConfigError::Invalid(issues)) = validate(&cfg) else {
+            panic!("expected the config to be invalid");
+        };
+        let fields: Vec<&str> = issues.iter().map(|issue| issue.field.as_str()).collect();
+        assert_eq!(fields, ["restrictions.1.to", "restrictions.1.et_pct", "restrictions.2.from"]);
+    }
+
+    #[test]
+    fn checks_the_window_overrides() {
+        let cfg: Config = toml::from_str(
+            r#"[[window_overrides]]
+               weekdays = ["sat", "sun"]
+               start_hour = 2
+               duration_hours = 0
+               [[window_overrides]]
+               weekdays = ["saturday", "someday"]
+               start_hour = 24
+               [[window_overrides]]
+               duration_hours = 30"#,
+        )
+        .unwrap();
+        let Err(ConfigError::Invalid(issues)) = validate(&cfg) else {
+            panic!("expected the config to be invalid");
+        };
+        let fields: Vec<&str> = issues.iter().map(|issue| issue.field.as_str()).collect();
+        assert_eq!(
+            fields,
+            [
+                "window_overrides.1.weekdays",
+                "window_overrides.1.start_hour",
+                "window_overrides.2.weekdays",
+                "window_overrides.2.duration_hours"
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_the_sector_constraints() {
+        let cfg: Config = toml::from_str(
+            r#"[[sector_constraints]]
+               sectors = [3, 4]
+               min_gap_secs = 21600
+               [[sector_constraints]]
+               sectors = [4, 3]
+               min_gap_secs = -1
+               [[sector_constraints]]
+               sectors = [2, 2]
+               min_gap_secs = 0"#,
+        )
+        .unwrap();
+        let Err(ConfigError::Invalid(issues)) = validate(&cfg) else {
+            panic!("expected the config to be invalid");
+        };
+        let fields: Vec<&str> = issues.iter().map(|issue| issue.field.as_str()).collect();
+        let pair = ["sector_constraints.1.sectors", "sector_constraints.1.min_gap_secs"];
+        assert_eq!(fields, [&pair[..], &["sector_constraints.2.sectors"]].concat());
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        assert!(validate(&Config::default()).is_ok());
+    }
+}
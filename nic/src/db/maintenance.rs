@@ -0,0 +1,180 @@
+use super::DatabaseTrait;
+use crate::{
+    api::ask,
+    config::Database as DatabaseCfg,
+    time::TimeProvider,
+    utils::{get_hour_from_ts, sod},
+    watering::{ds::CtrlSignal, water_window::WaterWin},
+    weather::rollup::DAY_SECS,
+};
+use rusqlite::{Connection, Result};
+use serde::Serialize;
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::sync::broadcast::Sender;
+use tracing::{error, info};
+
+const CHECK_INTERVAL_SECS: u64 = 300;
+
+/// What the last integrity check and maintenance found
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DbCheck {
+    pub timestamp: i64,
+    /// `PRAGMA quick_check` found nothing
+    pub ok: bool,
+    /// what it found otherwise, at most 100 lines of it
+    pub problems: Vec<String>,
+    /// pages given back to the file system by the incremental vacuum
+    pub freed_pages: i64,
+    pub elapsed_ms: u64,
+}
+
+/// The last check, for `/healthz`
+#[derive(Debug, Default)]
+pub struct DbHealth(Mutex<Option<DbCheck>>);
+
+impl DbHealth {
+    pub fn record(&self, check: DbCheck) {
+        *self.0.lock().unwrap() = Some(check);
+    }
+
+    pub fn last(&self) -> Option<DbCheck> {
+        self.0.lock().unwrap().clone()
+    }
+
+    /// None checked is healthy
+    pub fn healthy(&self) -> bool {
+        self.0.lock().unwrap().as_ref().is_none_or(|check| check.ok)
+    }
+}
+
+/// `PRAGMA quick_check`, and when the file is sound the free pages go back to the file system and the query planner
+/// statistics are refreshed. A damaged file is left as it is, for whoever looks into it.<br>
+/// The incremental vacuum only frees pages in databases created with `auto_vacuum = INCREMENTAL`, see `configure`.
+pub fn maintain(conn: &Connection, now: i64) -> Result<DbCheck> {
+    let started = Instant::now();
+    let mut stmt = conn.prepare("PRAGMA quick_check")?;
+    let problems: Vec<String> = stmt.query_map([], |row| row.get(0))?.collect::<Result<_>>()?;
+    let problems: Vec<String> = problems.into_iter().filter(|line| line != "ok").collect();
+    let mut freed_pages = 0;
+    if problems.is_empty() {
+        let free = |conn: &Connection| conn.query_row("PRAGMA freelist_count", [], |row| row.get::<_, i64>(0));
+        let before = free(conn)?;
+        // a page each step
+        let mut stmt = conn.prepare("PRAGMA incremental_vacuum")?;
+        let mut rows = stmt.query([])?;
+        while rows.next()?.is_some() {}
+        freed_pages = before - free(conn)?;
+        conn.execute_batch("ANALYZE")?;
+    }
+    let elapsed_ms = started.elapsed().as_millis() as u64;
+    Ok(DbCheck { timestamp: now, ok: problems.is_empty(), problems, freed_pages, elapsed_ms })
+}
+
+/// At or after the maintenance hour of a day it hasn't run, outside the watering window
+pub fn maintenance_due(cfg: &DatabaseCfg, window: &WaterWin, last_run: Option<i64>, now: i64) -> bool {
+    cfg.maintenance_every_days > 0
+        && get_hour_from_ts(now) as i64 >= cfg.maintenance_hour
+        && !WaterWin::around(now, window.hour_start, window.duration_secs / 3600, &[]).is_within(now)
+        && last_run.is_none_or(|last| sod(now) - sod(last) >= cfg.maintenance_every_days * DAY_SECS)
+}
+
+/// Checks and tidies the database once every `maintenance_every_days`, in the idle hours of the day. A damaged
+/// database is logged, kept for `/healthz` and sent as `CtrlSignal::DbIntegrity` to the webhooks and MQTT.
+pub async fn run_db_maintenance(
+    cfg: DatabaseCfg, window: WaterWin, db: Arc<dyn DatabaseTrait>, health: Arc<DbHealth>,
+    sm_tx: Arc<Sender<CtrlSignal>>, web_tx: Sender<CtrlSignal>, time_provider: Arc<dyn TimeProvider>,
+) {
+    let mut last_run = health.last().map(|check| check.timestamp);
+    let mut interval = tokio::time::interval(Duration::from_secs(CHECK_INTERVAL_SECS));
+    loop {
+        interval.tick().await;
+        let now = time_provider.now();
+        if !maintenance_due(&cfg, &window, last_run, now) {
+            continue;
+        }
+        // a manual run, or a cycle that went past the window, waits for the next check
+        match ask(&sm_tx, CtrlSignal::GetStatus, "status").await {
+            Some(status) if status.state == "idle" => {}
+            _ => continue,
+        }
+        last_run = Some(now);
+        match db.maintain(now) {
+            Ok(check) if check.ok => {
+                info!(freed_pages = check.freed_pages, elapsed_ms = check.elapsed_ms, "Database checked and tidied.");
+                health.record(check);
+            }
+            Ok(check) => {
+                error!(problems = ?check.problems, "The database is damaged. Restore it from a backup.");
+                health.record(check.clone());
+                _ = web_tx.send(CtrlSignal::DbIntegrity(check));
+            }
+            Err(e) => {
+                error!(error = ?e, "Database check failed.");
+                let check =
+                    DbCheck { timestamp: now, ok: false, problems: vec![e.to_string()], freed_pages: 0, elapsed_ms: 0 };
+                health.record(check.clone());
+                _ = web_tx.send(CtrlSignal::DbIntegrity(check));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::{configure, initialize};
+
+    const NOON: i64 = 12 * 3600;
+
+    #[test]
+    fn test_maintain_frees_the_deleted_pages() {
+        let conn = Connection::open_in_memory().unwrap();
+        configure(&conn, &DatabaseCfg::default()).unwrap();
+        initialize(&conn).unwrap();
+        let padding = "x".repeat(2000);
+        for timestamp in 0..200 {
+            conn.execute(
+                "INSERT INTO weather_events (timestamp, kind, data) VALUES (?1, 'test', ?2)",
+                (timestamp, &padding),
+            )
+            .unwrap();
+        }
+        conn.execute("DELETE FROM weather_events", []).unwrap();
+        let check = maintain(&conn, NOON).unwrap();
+        assert_eq!((check.timestamp, check.ok, check.problems.len()), (NOON, true, 0));
+        assert!(check.freed_pages > 0);
+        let free: i64 = conn.query_row("PRAGMA freelist_count", [], |row| row.get(0)).unwrap();
+        assert_eq!(free, 0);
+    }
+
+    #[test]
+    fn test_due_once_a_day_outside_the_window() {
+        let cfg = DatabaseCfg { maintenance_hour: 11, ..Default::default() };
+        // 22:00 to 06:00
+        let window = WaterWin::new(0, 22, 8);
+        assert!(!maintenance_due(&cfg, &window, None, 10 * 3600), "before the hour");
+        assert!(maintenance_due(&cfg, &window, None, NOON));
+        assert!(!maintenance_due(&cfg, &window, Some(NOON), NOON + 600), "ran today");
+        assert!(!maintenance_due(&cfg, &window, Some(NOON), DAY_SECS + 23 * 3600), "in tonight's window");
+        assert!(maintenance_due(&cfg, &window, Some(NOON), DAY_SECS + 11 * 3600));
+        let weekly = DatabaseCfg { maintenance_every_days: 7, ..cfg.clone() };
+        assert!(!maintenance_due(&weekly, &window, Some(NOON), 6 * DAY_SECS + NOON));
+        assert!(maintenance_due(&weekly, &window, Some(NOON), 7 * DAY_SECS + NOON));
+        let never = DatabaseCfg { maintenance_every_days: 0, ..cfg };
+        assert!(!maintenance_due(&never, &window, None, NOON));
+    }
+
+    #[test]
+    fn test_health_keeps_the_last_check() {
+        let health = DbHealth::default();
+        assert!(health.healthy());
+        let check =
+            DbCheck { timestamp: NOON, ok: false, problems: vec!["page 3".to_owned()], freed_pages: 0, elapsed_ms: 1 };
+        health.record(check.clone());
+        assert!(!health.healthy());
+        assert_eq!(health.last(), Some(check));
+    }
+}
@@ -0,0 +1,295 @@
+//! The tables and the columns their entities are read and written with. The statements of `db` are built from
+//! these, so a column renamed in the schema fails the tests here rather than a command at runtime.
+
+/// The columns are in the order of the row mappers and of the params of `insert`. The `site_id` of the tables of a
+/// site is the last of them.
+#[derive(Debug)]
+pub struct Table {
+    pub name: &'static str,
+    /// given by the database, read before the columns but never written
+    pub id: bool,
+    pub columns: &'static [&'static str],
+}
+
+impl Table {
+    /// `SELECT <columns> FROM <table> <rest>`, `rest` is the WHERE and ORDER BY
+    pub fn select(&self, rest: &str) -> String {
+        let id = if self.id { "id, " } else { "" };
+        self.pick(&format!("{}{}", id, self.columns.join(", ")), rest)
+    }
+
+    /// Some columns or an aggregate of them
+    pub fn pick(&self, columns: &str, rest: &str) -> String {
+        format!("SELECT {} FROM {} {}", columns, self.name, rest).trim_end().to_owned()
+    }
+
+    /// Every column, `?1` for the first
+    pub fn insert(&self) -> String {
+        format!("INSERT INTO {} ({}) VALUES ({})", self.name, self.columns.join(", "), self.placeholders())
+    }
+
+    /// An `insert` that replaces the row with the same key
+    pub fn replace(&self) -> String {
+        self.insert().replacen("INSERT", "INSERT OR REPLACE", 1)
+    }
+
+    /// An `insert` of the row only when `rest`, a WHERE on the params, holds
+    pub fn insert_where(&self, rest: &str) -> String {
+        format!("INSERT INTO {} ({}) SELECT {} {}", self.name, self.columns.join(", "), self.placeholders(), rest)
+    }
+
+    /// A `replace` of the row only when `rest` holds
+    pub fn replace_where(&self, rest: &str) -> String {
+        self.insert_where(rest).replacen("INSERT", "INSERT OR REPLACE", 1)
+    }
+
+    /// An `insert` that updates the row with the same `key` with `set`, where `excluded` is the row not inserted
+    pub fn upsert(&self, key: &str, set: &str) -> String {
+        format!("{} ON CONFLICT ({}) DO UPDATE SET {}", self.insert(), key, set)
+    }
+
+    pub fn update(&self, set: &str, rest: &str) -> String {
+        format!("UPDATE {} SET {} {}", self.name, set, rest).trim_end().to_owned()
+    }
+
+    pub fn delete(&self, rest: &str) -> String {
+        format!("DELETE FROM {} {}", self.name, rest).trim_end().to_owned()
+    }
+
+    fn placeholders(&self) -> String {
+        (1..=self.columns.len()).map(|i| format!("?{}", i)).collect::<Vec<_>>().join(", ")
+    }
+}
+
+pub const SECTORS: Table = Table {
+    name: "sectors",
+    id: false,
+    columns: &[
+        "id",
+        "name",
+        "sprinkler_debit",
+        "percolation_rate",
+        "max_duration",
+        "weekly_target",
+        "progress",
+        "last_water",
+        "ignore_weather_pause",
+        "max_daily_mm",
+        "max_daily_minutes",
+        "deficit_exempt",
+        "config_debit",
+        "config_percolation",
+        "site_id",
+    ],
+};
+
+pub const CYCLES: Table =
+    Table { name: "cycles", id: false, columns: &["id", "sector_id", "start_time", "duration", "site_id"] };
+
+pub const WATERING_EVENTS: Table = Table {
+    name: "watering_events",
+    id: false,
+    columns: &["cycle_id", "sector_id", "start_time_utc", "duration", "water_applied", "type", "site_id"],
+};
+
+pub const CYCLE_RUNS: Table = Table {
+    name: "cycle_runs",
+    id: true,
+    columns: &["cycle", "mode", "planned", "start_time", "end_time", "outcome", "site_id"],
+};
+
+pub const AUTO_SCHEDULES: Table = Table {
+    name: "auto_schedules",
+    id: false,
+    columns: &["program", "day_of_week", "sector_id", "start_secs_from_day_start", "duration", "cron", "site_id"],
+};
+
+pub const AUTO_PROGRAMS: Table =
+    Table { name: "auto_programs", id: false, columns: &["program", "enabled", "site_id"] };
+
+pub const WIZARD_SCHEDULE: Table = Table {
+    name: "wizard_schedule",
+    id: false,
+    columns: &["date", "sector_id", "start_time", "duration", "cycle_id", "completed", "site_id"],
+};
+
+pub const WEATHER_OBSERVATIONS: Table = Table {
+    name: "weather_observations",
+    id: false,
+    columns: &[
+        "timestamp",
+        "temperature",
+        "humidity",
+        "wind_speed",
+        "wind_gust",
+        "wind_direction",
+        "solar_radiation",
+        "rain",
+        "rain_rate",
+    ],
+};
+
+pub const WEATHER_EVENTS: Table = Table { name: "weather_events", id: false, columns: &["timestamp", "kind", "data"] };
+
+pub const FORECASTS: Table = Table {
+    name: "forecasts",
+    id: false,
+    columns: &["timestamp", "temperature", "rain", "rain_probability", "wind_speed", "et0"],
+};
+
+pub const WEATHER_HOURLY: Table = Table {
+    name: "weather_hourly",
+    id: false,
+    columns: &[
+        "timestamp",
+        "samples",
+        "coverage",
+        "temp_avg",
+        "temp_min",
+        "temp_max",
+        "humidity_avg",
+        "wind_avg",
+        "wind_max",
+        "solar_energy",
+        "rain",
+    ],
+};
+
+pub const WEATHER_DAILY: Table = Table {
+    name: "weather_daily",
+    id: false,
+    columns: &[
+        "timestamp",
+        "hours",
+        "temp_avg",
+        "temp_min",
+        "temp_max",
+        "humidity_avg",
+        "wind_avg",
+        "wind_max",
+        "solar_energy",
+        "rain",
+        "et",
+    ],
+};
+
+pub const DEVICE_TELEMETRY: Table =
+    Table { name: "device_telemetry", id: false, columns: &["device", "timestamp", "battery", "rssi", "site_id"] };
+
+pub const EVENTS: Table =
+    Table { name: "events", id: false, columns: &["timestamp", "kind", "sector", "cycle", "detail", "site_id"] };
+
+pub const AUDIT_LOG: Table =
+    Table { name: "audit_log", id: false, columns: &["timestamp", "ip", "user", "action", "status", "site_id"] };
+
+/// A row per site
+pub const RESUME_POINT: Table = Table { name: "resume_point", id: false, columns: &["data", "site_id"] };
+
+pub const CURRENT_MODE: Table = Table { name: "current_mode", id: false, columns: &["mode", "site_id"] };
+
+pub const PROGRESS_DAY: Table = Table { name: "progress_day", id: false, columns: &["day", "site_id"] };
+
+pub const CLOCK_MARK: Table = Table { name: "clock_mark", id: false, columns: &["timestamp", "site_id"] };
+
+pub const DAILY_REPORTS: Table = Table { name: "daily_reports", id: false, columns: &["day", "data", "site_id"] };
+
+pub const SOURCE_USAGE: Table =
+    Table { name: "source_usage", id: false, columns: &["day", "source", "secs", "litres", "site_id"] };
+
+pub const SECTOR_CONSTRAINTS: Table =
+    Table { name: "sector_constraints", id: false, columns: &["sector_a", "sector_b", "min_gap_secs"] };
+
+pub const SECTOR_TARGETS: Table =
+    Table { name: "sector_targets", id: false, columns: &["sector_id", "month", "weekly_target"] };
+
+pub const MOISTURE_READINGS: Table =
+    Table { name: "moisture_readings", id: false, columns: &["sector", "timestamp", "water_cm"] };
+
+pub const LEARNED_PARAMS: Table = Table {
+    name: "learned_params",
+    id: false,
+    columns: &[
+        "sector",
+        "learned_at",
+        "sprinkler_debit",
+        "percolation_rate",
+        "previous_debit",
+        "previous_percolation",
+        "samples",
+        "from_ts",
+        "to_ts",
+        "rmse",
+        "status",
+    ],
+};
+
+pub const ALL: [&Table; 25] = [
+    &SECTORS,
+    &CYCLES,
+    &WATERING_EVENTS,
+    &CYCLE_RUNS,
+    &AUTO_SCHEDULES,
+    &AUTO_PROGRAMS,
+    &WIZARD_SCHEDULE,
+    &WEATHER_OBSERVATIONS,
+    &WEATHER_EVENTS,
+    &FORECASTS,
+    &WEATHER_HOURLY,
+    &WEATHER_DAILY,
+    &DEVICE_TELEMETRY,
+    &EVENTS,
+    &AUDIT_LOG,
+    &RESUME_POINT,
+    &CURRENT_MODE,
+    &PROGRESS_DAY,
+    &CLOCK_MARK,
+    &DAILY_REPORTS,
+    &SOURCE_USAGE,
+    &SECTOR_CONSTRAINTS,
+    &SECTOR_TARGETS,
+    &MOISTURE_READINGS,
+    &LEARNED_PARAMS,
+];
+
+#[cfg(test)]
+mod test {
+    use super::{Table, ALL, CYCLE_RUNS, SOURCE_USAGE};
+    use crate::db::initialize;
+    use rusqlite::Connection;
+
+    #[test]
+    fn test_statements() {
+        let table = Table { name: "t", id: false, columns: &["a", "b"] };
+        assert_eq!(table.select("WHERE a = ?1"), "SELECT a, b FROM t WHERE a = ?1");
+        assert_eq!(table.insert(), "INSERT INTO t (a, b) VALUES (?1, ?2)");
+        assert_eq!(table.replace(), "INSERT OR REPLACE INTO t (a, b) VALUES (?1, ?2)");
+        assert_eq!(table.insert_where("WHERE ?1 > 0"), "INSERT INTO t (a, b) SELECT ?1, ?2 WHERE ?1 > 0");
+        assert_eq!(table.replace_where("WHERE ?1 > 0"), "INSERT OR REPLACE INTO t (a, b) SELECT ?1, ?2 WHERE ?1 > 0");
+        assert_eq!(table.delete(""), "DELETE FROM t");
+        assert_eq!(table.update("b = ?2", "WHERE a = ?1"), "UPDATE t SET b = ?2 WHERE a = ?1");
+        assert_eq!(
+            SOURCE_USAGE.upsert("day, source", "secs = secs + excluded.secs"),
+            "INSERT INTO source_usage (day, source, secs, litres, site_id) VALUES (?1, ?2, ?3, ?4, ?5) \
+             ON CONFLICT (day, source) DO UPDATE SET secs = secs + excluded.secs"
+        );
+        assert!(CYCLE_RUNS.select("").starts_with("SELECT id, cycle, mode"), "the id is read");
+        assert!(!CYCLE_RUNS.insert().contains("id,"), "but never written");
+    }
+
+    #[test]
+    fn test_tables_match_the_schema() {
+        let conn = Connection::open_in_memory().unwrap();
+        initialize(&conn).unwrap();
+        for table in ALL {
+            let mut stmt = conn.prepare("SELECT name FROM pragma_table_info(?1)").unwrap();
+            let columns: Vec<String> =
+                stmt.query_map([table.name], |row| row.get(0)).unwrap().collect::<Result<_, _>>().unwrap();
+            assert!(!columns.is_empty(), "{} isn't in the schema", table.name);
+            for column in table.columns {
+                assert!(columns.iter().any(|c| c == column), "{}.{} isn't in the schema", table.name, column);
+            }
+            conn.prepare(&table.select("")).unwrap();
+            conn.prepare(&table.insert()).unwrap();
+        }
+    }
+}
@@ -1,3 +1,11 @@
+use axum::{
+    http,
+    response::{IntoResponse, Response},
+    Json,
+};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -8,10 +16,187 @@ pub enum AppError {
     HTTPError(#[from] reqwest::Error),
     #[error("Sensor error: {0}")]
     SensorError(String),
+    #[error("Interlock: {0}")]
+    InterlockError(String),
     #[error("Watering error: {0}")]
     WateringError(String),
     #[error("MQTT error: {0}")]
     MQTTError(String),
+    #[error("Weather error: {0}")]
+    WeatherError(String),
+    #[error("Config error: {0}")]
+    ConfigError(String),
     #[error("Unknown error")]
     Unknown,
-}
\ No newline at end of file
+}
+/// What a long running task should do with an error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// IO, the network or a busy database: the same call may work in a moment
+    Transient,
+    /// the config can't work, retrying won't change it
+    Config,
+    /// a device refused or failed a command
+    Hardware,
+    /// the code or the data are in a state they shouldn't be
+    Invariant,
+}
+
+impl AppError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            AppError::DatabaseError(e) => db_error_kind(e),
+            // the server won't take the request as it is, except for a rate limit
+            AppError::HTTPError(e)
+                if e.status().is_some_and(|s| s.is_client_error() && s != StatusCode::TOO_MANY_REQUESTS) =>
+            {
+                ErrorKind::Config
+            }
+            AppError::HTTPError(_) | AppError::MQTTError(_) | AppError::WeatherError(_) => ErrorKind::Transient,
+            AppError::SensorError(_) => ErrorKind::Hardware,
+            AppError::ConfigError(_) => ErrorKind::Config,
+            AppError::InterlockError(_) | AppError::WateringError(_) | AppError::Unknown => ErrorKind::Invariant,
+        }
+    }
+
+    /// Worth another try; anything else is escalated
+    pub fn is_retryable(&self) -> bool {
+        self.kind() == ErrorKind::Transient
+    }
+}
+
+fn db_error_kind(e: &rusqlite::Error) -> ErrorKind {
+    use rusqlite::ErrorCode;
+    match e.sqlite_error_code() {
+        Some(ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked | ErrorCode::SystemIoFailure | ErrorCode::DiskFull) => {
+            ErrorKind::Transient
+        }
+        Some(ErrorCode::CannotOpen | ErrorCode::PermissionDenied | ErrorCode::ReadOnly | ErrorCode::NotADatabase) => {
+            ErrorKind::Config
+        }
+        _ => ErrorKind::Invariant,
+    }
+}
+
+/// Delay between the tries of a task that keeps failing with transient errors, doubling from `min` up to `max`
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    min: Duration,
+    max: Duration,
+    next: Duration,
+}
+
+impl Backoff {
+    pub fn new(min: Duration, max: Duration) -> Self {
+        Self { min, max, next: min }
+    }
+
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.next;
+        self.next = (self.next * 2).min(self.max);
+        delay
+    }
+
+    /// After a success, the next failure waits `min` again
+    pub fn reset(&mut self) {
+        self.next = self.min;
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(1), Duration::from_secs(60))
+    }
+}
+
+/// What the HTTP API answers when it can't do what was asked, as a status and an `ErrorBody`
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ApiError {
+    /// the request doesn't make sense as it is
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("{0}")]
+    NotFound(String),
+    /// the request clashes with what the controller is doing
+    #[error("{0}")]
+    Conflict(String),
+    #[error("{0}")]
+    Internal(String),
+    /// the client called more often than the `[web_server]` limits allow
+    #[error("{0}")]
+    TooManyRequests(String),
+    /// the watering loop didn't answer in time
+    #[error("{0}")]
+    Timeout(String),
+}
+
+impl ApiError {
+    pub fn status(&self) -> http::StatusCode {
+        match self {
+            ApiError::BadRequest(_) => http::StatusCode::BAD_REQUEST,
+            ApiError::NotFound(_) => http::StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => http::StatusCode::CONFLICT,
+            ApiError::TooManyRequests(_) => http::StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Internal(_) => http::StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::Timeout(_) => http::StatusCode::GATEWAY_TIMEOUT,
+        }
+    }
+
+    /// No answer from the watering loop to a query of `request`
+    pub fn no_answer(request: &str) -> Self {
+        ApiError::Timeout(format!("the watering loop didn't answer the {} query", request))
+    }
+}
+
+impl From<rusqlite::Error> for ApiError {
+    fn from(e: rusqlite::Error) -> Self {
+        ApiError::Internal(e.to_string())
+    }
+}
+
+impl From<AppError> for ApiError {
+    fn from(e: AppError) -> Self {
+        ApiError::Internal(e.to_string())
+    }
+}
+
+/// The body of every error of the HTTP API
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ErrorBody {
+    pub status: u16,
+    pub error: String,
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        (status, Json(ErrorBody { status: status.as_u16(), error: self.to_string() })).into_response()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rusqlite::ffi;
+
+    #[test]
+    fn classifies() {
+        let sqlite = |code| AppError::DatabaseError(rusqlite::Error::SqliteFailure(ffi::Error::new(code), None));
+        assert!(sqlite(ffi::SQLITE_BUSY).is_retryable());
+        assert_eq!(sqlite(ffi::SQLITE_CANTOPEN).kind(), ErrorKind::Config);
+        assert_eq!(sqlite(ffi::SQLITE_CORRUPT).kind(), ErrorKind::Invariant);
+        assert_eq!(AppError::DatabaseError(rusqlite::Error::QueryReturnedNoRows).kind(), ErrorKind::Invariant);
+        assert!(AppError::MQTTError("connection refused".into()).is_retryable());
+        assert_eq!(AppError::SensorError("valve 2".into()).kind(), ErrorKind::Hardware);
+        assert!(!AppError::ConfigError("no sectors".into()).is_retryable());
+    }
+
+    #[test]
+    fn backs_off() {
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(5));
+        let delays: Vec<_> = (0..4).map(|_| backoff.next_delay().as_secs()).collect();
+        assert_eq!(delays, [1, 2, 4, 5]);
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Duration::from_secs(1));
+    }
+}
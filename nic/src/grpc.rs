@@ -0,0 +1,119 @@
+use crate::{
+    api::{request_cycle, request_manual_start, request_mode, request_state},
+    error::ApiError,
+    metrics,
+    watering::ds::{AppState, CtrlSignal, StateEvent},
+};
+use std::{net::SocketAddr, pin::Pin, sync::Arc};
+use tokio::sync::watch;
+use tokio_stream::{
+    wrappers::{errors::BroadcastStreamRecvError, BroadcastStream},
+    Stream, StreamExt,
+};
+use tonic::{
+    transport::{Error, Server},
+    Request, Response, Status,
+};
+use tracing::{info, warn};
+
+pub mod proto {
+    tonic::include_proto!("nic.v1");
+}
+
+use proto::{
+    control_server::{Control, ControlServer},
+    CycleReply, Empty, Event, Instruction, ManualStartReply, ManualStartRequest, StateReply, SwitchModeReply,
+    SwitchModeRequest,
+};
+
+impl From<&StateEvent> for Event {
+    fn from(evt: &StateEvent) -> Self {
+        Event {
+            timestamp: evt.timestamp,
+            sector: evt.sector,
+            cycle: evt.cycle,
+            kind: evt.change.kind().to_owned(),
+            json: serde_json::to_string(evt).unwrap_or_default(),
+        }
+    }
+}
+
+impl From<ApiError> for Status {
+    fn from(e: ApiError) -> Self {
+        match e {
+            ApiError::BadRequest(e) => Status::invalid_argument(e),
+            ApiError::NotFound(e) => Status::not_found(e),
+            ApiError::Conflict(e) => Status::failed_precondition(e),
+            ApiError::TooManyRequests(e) => Status::resource_exhausted(e),
+            ApiError::Internal(e) => Status::internal(e),
+            ApiError::Timeout(e) => Status::unavailable(e),
+        }
+    }
+}
+
+/// The gRPC face of the API, on the same `CtrlSignal` round trips as the HTTP handlers
+pub struct ControlService {
+    app_state: Arc<AppState>,
+}
+
+impl ControlService {
+    pub fn new(app_state: Arc<AppState>) -> Self {
+        Self { app_state }
+    }
+}
+
+#[tonic::async_trait]
+impl Control for ControlService {
+    async fn get_state(&self, _request: Request<Empty>) -> Result<Response<StateReply>, Status> {
+        let resp = request_state(&self.app_state).await?;
+        Ok(Response::new(StateReply { mode: resp.mode, state: resp.state, current_cycle: resp.current_cycle }))
+    }
+
+    async fn get_cycle(&self, _request: Request<Empty>) -> Result<Response<CycleReply>, Status> {
+        let resp = request_cycle(&self.app_state).await?;
+        let instructions = resp.instructions.unwrap_or_default();
+        let instructions =
+            instructions.into_iter().map(|(sector, duration)| Instruction { sector, duration }).collect();
+        Ok(Response::new(CycleReply { id: resp.id, instructions }))
+    }
+
+    async fn switch_mode(&self, request: Request<SwitchModeRequest>) -> Result<Response<SwitchModeReply>, Status> {
+        let mode = request.into_inner().mode;
+        let mode = request_mode(&self.app_state, &mode)?;
+        Ok(Response::new(SwitchModeReply { mode: mode.to_string() }))
+    }
+
+    async fn manual_start(&self, request: Request<ManualStartRequest>) -> Result<Response<ManualStartReply>, Status> {
+        let sectors = request.into_inner().sectors.into_iter().map(|sec| (sec.sector, sec.duration_secs)).collect();
+        let cycle = request_manual_start(&self.app_state, sectors).await?;
+        Ok(Response::new(ManualStartReply { cycle }))
+    }
+
+    type StreamEventsStream = Pin<Box<dyn Stream<Item = Result<Event, Status>> + Send>>;
+
+    async fn stream_events(&self, _request: Request<Empty>) -> Result<Response<Self::StreamEventsStream>, Status> {
+        let events = BroadcastStream::new(self.app_state.web_tx.subscribe()).filter_map(|signal| match signal {
+            Ok(CtrlSignal::StateChanged(evt)) => Some(Ok(Event::from(&evt))),
+            Ok(_) => None,
+            Err(BroadcastStreamRecvError::Lagged(missed)) => {
+                // a slow client misses events rather than holding up the others
+                metrics::dropped("grpc", missed);
+                warn!(missed, "gRPC event stream fell behind.");
+                None
+            }
+        });
+        Ok(Response::new(Box::pin(events)))
+    }
+}
+
+pub async fn run_grpc_server(
+    app_state: Arc<AppState>, addr: SocketAddr, mut stop_signal: watch::Receiver<bool>,
+) -> Result<(), Error> {
+    info!(%addr, "gRPC server listening.");
+    Server::builder()
+        .add_service(ControlServer::new(ControlService::new(app_state)))
+        .serve_with_shutdown(addr, async move {
+            _ = stop_signal.wait_for(|stop| *stop).await;
+        })
+        .await
+}
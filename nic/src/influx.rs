@@ -0,0 +1,337 @@
+use crate::{
+    config::InfluxCfg,
+    db::DatabaseTrait,
+    error::AppError,
+    metrics,
+    time::TimeProvider,
+    watering::{
+        ds::{CtrlSignal, SectorInfo, StateChange, StateEvent, WeatherConditions},
+        SECS_TO_HOUR_CONV,
+    },
+};
+use reqwest::{header::AUTHORIZATION, StatusCode};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::sync::broadcast::{error::RecvError, Sender};
+use tracing::{debug, info, warn};
+
+/// `name=value` pairs, leaving out the values line protocol can't carry: NaN and the infinities
+fn fields(values: &[(&str, f64)]) -> Vec<String> {
+    values.iter().filter(|(_, value)| value.is_finite()).map(|(name, value)| format!("{}={}", name, value)).collect()
+}
+
+/// `weather` point of an observation
+pub fn weather_line(obs: &WeatherConditions) -> String {
+    let mut fields = fields(&[
+        ("temperature", obs.temperature),
+        ("humidity", obs.humidity),
+        ("wind_speed", obs.wind_speed),
+        ("wind_gust", obs.wind_gust),
+        ("wind_direction", obs.wind_direction),
+        ("solar_radiation", obs.solar_radiation),
+        ("rain", obs.rain),
+        ("rain_rate", obs.rain_rate),
+    ]);
+    fields.push(format!("raining={}", obs.is_raining));
+    format!("weather {} {}", fields.join(","), obs.timestamp)
+}
+
+/// Points waiting for the endpoint. When full, the oldest are dropped to make room.
+#[derive(Debug, Default)]
+pub struct LineBuffer {
+    lines: VecDeque<String>,
+    max: usize,
+    pub dropped: u64,
+}
+
+impl LineBuffer {
+    pub fn new(max: usize) -> Self {
+        Self { lines: VecDeque::new(), max, dropped: 0 }
+    }
+
+    pub fn push(&mut self, line: String) {
+        if self.lines.len() >= self.max {
+            self.lines.pop_front();
+            self.dropped += 1;
+        }
+        self.lines.push_back(line);
+    }
+
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    /// The oldest `n` points, one per line
+    pub fn batch(&self, n: usize) -> String {
+        self.lines.iter().take(n).map(String::as_str).collect::<Vec<_>>().join("\n")
+    }
+
+    /// After a successful write of `batch(n)`
+    pub fn consume(&mut self, n: usize) {
+        self.lines.drain(..n.min(self.lines.len()));
+    }
+}
+
+/// Turns what the controller does into line protocol points and writes them in batches
+#[derive(Debug)]
+pub struct InfluxExporter {
+    cfg: InfluxCfg,
+    client: reqwest::Client,
+    pub buffer: LineBuffer,
+    /// cm/hour, per sector
+    debits: HashMap<u32, f64>,
+    /// when each open sector was opened
+    opened: HashMap<u32, i64>,
+    /// observations up to here are in the buffer
+    weather_until: i64,
+}
+
+impl InfluxExporter {
+    pub fn new(cfg: InfluxCfg, sectors: &[SectorInfo], now: i64) -> Self {
+        let client = reqwest::Client::builder().timeout(Duration::from_secs(10)).build().unwrap_or_default();
+        let debits = sectors.iter().map(|sec| (sec.id, sec.sprinkler_debit)).collect();
+        let buffer = LineBuffer::new(cfg.max_buffer);
+        Self { cfg, client, buffer, debits, opened: HashMap::new(), weather_until: now }
+    }
+
+    /// `valve` points on every activation and deactivation, and a `water` point when a sector closes
+    pub fn on_event(&mut self, evt: &StateEvent) {
+        let Some(sector) = evt.sector else {
+            return;
+        };
+        match evt.change {
+            StateChange::SectorActivated { .. } => {
+                self.opened.insert(sector, evt.timestamp);
+                self.buffer.push(format!("valve,sector={} open=true {}", sector, evt.timestamp));
+            }
+            StateChange::SectorDeactivated => {
+                self.buffer.push(format!("valve,sector={} open=false {}", sector, evt.timestamp));
+                let Some(opened) = self.opened.remove(&sector) else {
+                    return;
+                };
+                let secs = evt.timestamp - opened;
+                let cm = secs as f64 * SECS_TO_HOUR_CONV * self.debits.get(&sector).copied().unwrap_or(0.);
+                let mut fields = fields(&[("cm", cm)]);
+                fields.push(format!("secs={}i", secs));
+                self.buffer.push(format!("water,sector={} {} {}", sector, fields.join(","), evt.timestamp));
+            }
+            _ => {}
+        }
+    }
+
+    /// The observations stored since the last call
+    pub fn collect_weather(&mut self, db: &dyn DatabaseTrait, now: i64) {
+        match db.load_observations(self.weather_until, now + 1) {
+            Ok(observations) => {
+                for obs in &observations {
+                    self.buffer.push(weather_line(obs));
+                }
+                self.weather_until = now + 1;
+            }
+            Err(e) => warn!(error = ?e, "Failed to read the observations to export."),
+        }
+    }
+
+    /// Writes the buffer a batch at a time, stopping at the first failure so the rest waits for the next flush.
+    /// A batch the server won't take as it is, a 4xx other than a wrong token or url or a rate limit, is dropped:
+    /// sent again it would fail the same way and hold back every point behind it.
+    pub async fn flush(&mut self) -> Result<(), AppError> {
+        while !self.buffer.is_empty() {
+            let n = self.cfg.batch_size.min(self.buffer.len());
+            let mut request = self.client.post(&self.cfg.url).body(self.buffer.batch(n));
+            if !self.cfg.token.is_empty() {
+                request = request.header(AUTHORIZATION, format!("Token {}", self.cfg.token.expose()));
+            }
+            match request.send().await.and_then(|resp| resp.error_for_status()) {
+                Ok(_) => debug!(points = n, "Exported to InfluxDB."),
+                Err(e) if rejected(&e) => {
+                    warn!(points = n, error = ?e, "InfluxDB rejected the points, dropped.");
+                }
+                Err(e) => return Err(e.into()),
+            }
+            self.buffer.consume(n);
+        }
+        Ok(())
+    }
+}
+
+/// The server refused the points themselves, the credentials and the endpoint being right
+fn rejected(e: &reqwest::Error) -> bool {
+    e.status().is_some_and(|status| {
+        status.is_client_error()
+            && ![StatusCode::UNAUTHORIZED, StatusCode::FORBIDDEN, StatusCode::NOT_FOUND, StatusCode::TOO_MANY_REQUESTS]
+                .contains(&status)
+    })
+}
+
+/// Every `flush_secs` the new observations join the valve and water points gathered from the state events, and
+/// the lot is written out
+pub async fn run_influx_exporter(
+    cfg: InfluxCfg, db: Arc<dyn DatabaseTrait>, web_tx: Sender<CtrlSignal>, time_provider: Arc<dyn TimeProvider>,
+) {
+    let mut flush = tokio::time::interval(Duration::from_secs(cfg.flush_secs.max(1)));
+    let sectors = db.load_sectors().unwrap_or_else(|e| {
+        warn!(error = ?e, "Failed to load the sectors, the water applied is exported as 0.");
+        vec![]
+    });
+    let mut exporter = InfluxExporter::new(cfg, &sectors, time_provider.now());
+    let mut web_rx = web_tx.subscribe();
+    info!("Exporting to InfluxDB.");
+    loop {
+        tokio::select! {
+            _ = flush.tick() => {
+                exporter.collect_weather(db.as_ref(), time_provider.now());
+                let dropped = exporter.buffer.dropped;
+                if let Err(e) = exporter.flush().await {
+                    warn!(error = ?e, buffered = exporter.buffer.len(), "InfluxDB write failed, retrying later.");
+                }
+                if dropped > 0 {
+                    warn!(dropped, "InfluxDB buffer full, oldest points dropped.");
+                    exporter.buffer.dropped = 0;
+                }
+            }
+            signal = web_rx.recv() => match signal {
+                Ok(CtrlSignal::StateChanged(evt)) => exporter.on_event(&evt),
+                Ok(_) => {}
+                Err(RecvError::Lagged(missed)) => {
+                    metrics::dropped("influx", missed);
+                    warn!(missed, "InfluxDB exporter fell behind, events skipped.");
+                }
+                Err(RecvError::Closed) => return,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::Database;
+    use axum::{http::StatusCode, routing::post, Router};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn sector(id: u32, sprinkler_debit: f64) -> SectorInfo {
+        SectorInfo { id, sprinkler_debit, ..Default::default() }
+    }
+
+    #[test]
+    fn points_from_the_events_and_the_weather() {
+        let cfg = InfluxCfg { max_buffer: 10, ..Default::default() };
+        let mut exporter = InfluxExporter::new(cfg, &[sector(2, 1.)], 0);
+        let evt = |timestamp, change| StateEvent { timestamp, sector: Some(2), cycle: Some(1), change };
+        exporter.on_event(&evt(100, StateChange::SectorActivated { duration: 1800 }));
+        exporter.on_event(&evt(1900, StateChange::SectorDeactivated));
+
+        let db = Database::new(":memory:").unwrap();
+        let obs = WeatherConditions { timestamp: 1000, temperature: 21.5, rain: 0.2, ..Default::default() };
+        db.log_weather(obs).unwrap();
+        exporter.collect_weather(&db, 2000);
+        // already exported
+        exporter.collect_weather(&db, 2100);
+
+        assert_eq!(
+            exporter.buffer.batch(10),
+            "valve,sector=2 open=true 100\n\
+             valve,sector=2 open=false 1900\n\
+             water,sector=2 cm=0.5,secs=1800i 1900\n\
+             weather temperature=21.5,humidity=0,wind_speed=0,wind_gust=0,wind_direction=0,solar_radiation=0,\
+             rain=0.2,rain_rate=0,raining=true 1000"
+        );
+    }
+
+    #[test]
+    fn leaves_out_what_line_protocol_cant_carry() {
+        let obs =
+            WeatherConditions { timestamp: 10, temperature: f64::NAN, wind_gust: f64::INFINITY, ..Default::default() };
+        assert_eq!(
+            weather_line(&obs),
+            "weather humidity=0,wind_speed=0,wind_direction=0,solar_radiation=0,rain=0,rain_rate=0,raining=false 10"
+        );
+
+        let mut exporter = InfluxExporter::new(InfluxCfg::default(), &[sector(2, f64::NAN)], 0);
+        let evt = |timestamp, change| StateEvent { timestamp, sector: Some(2), cycle: None, change };
+        exporter.on_event(&evt(100, StateChange::SectorActivated { duration: 60 }));
+        exporter.on_event(&evt(160, StateChange::SectorDeactivated));
+        assert_eq!(exporter.buffer.batch(3).lines().last(), Some("water,sector=2 secs=60i 160"));
+    }
+
+    #[test]
+    fn drops_the_oldest() {
+        let mut buffer = LineBuffer::new(2);
+        for line in ["a", "b", "c"] {
+            buffer.push(line.to_owned());
+        }
+        assert_eq!((buffer.batch(5).as_str(), buffer.dropped), ("b\nc", 1));
+        buffer.consume(1);
+        assert_eq!(buffer.batch(5), "c");
+    }
+
+    #[tokio::test]
+    async fn keeps_the_points_through_an_outage() {
+        // down for the first write
+        let calls = Arc::new(AtomicUsize::new(0));
+        let calls_clone = calls.clone();
+        let app = Router::new().route(
+            "/write",
+            post(move || async move {
+                match calls_clone.fetch_add(1, Ordering::SeqCst) {
+                    0 => StatusCode::SERVICE_UNAVAILABLE,
+                    _ => StatusCode::NO_CONTENT,
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/write", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let cfg = InfluxCfg { url, batch_size: 2, ..Default::default() };
+        let mut exporter = InfluxExporter::new(cfg, &[], 0);
+        for n in 0..3 {
+            exporter.buffer.push(format!("valve,sector={} open=true 0", n));
+        }
+        assert!(exporter.flush().await.is_err());
+        assert_eq!(exporter.buffer.len(), 3);
+        exporter.flush().await.unwrap();
+        assert!(exporter.buffer.is_empty());
+        // two batches after the failed one
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn drops_the_points_the_server_refuses() {
+        // the first batch is malformed, then the server is down
+        let calls = Arc::new(AtomicUsize::new(0));
+        let calls_clone = calls.clone();
+        let app = Router::new().route(
+            "/write",
+            post(move || async move {
+                match calls_clone.fetch_add(1, Ordering::SeqCst) {
+                    0 => StatusCode::BAD_REQUEST,
+                    1 => StatusCode::SERVICE_UNAVAILABLE,
+                    _ => StatusCode::NO_CONTENT,
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/write", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let cfg = InfluxCfg { url, batch_size: 2, ..Default::default() };
+        let mut exporter = InfluxExporter::new(cfg, &[], 0);
+        for n in 0..3 {
+            exporter.buffer.push(format!("valve,sector={} open=true 0", n));
+        }
+        assert!(exporter.flush().await.is_err());
+        assert_eq!(exporter.buffer.batch(3), "valve,sector=2 open=true 0");
+        exporter.flush().await.unwrap();
+        assert!(exporter.buffer.is_empty());
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+}
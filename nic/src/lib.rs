@@ -1,12 +1,28 @@
 pub mod api;
+pub mod cli;
+pub mod clock;
 pub mod config;
 pub mod db;
 pub mod error;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod influx;
+pub mod links;
+pub mod log_file;
+pub mod metrics;
+pub mod publisher;
+#[cfg(feature = "sim")]
+pub mod replay;
 pub mod sensors;
+pub mod shutdown;
+#[cfg(feature = "sim")]
+pub mod simulation;
+pub mod supervisor;
 pub mod test;
 pub mod time;
 pub mod utils;
 pub mod watering;
 pub mod weather;
+pub mod webhooks;
 
 pub const MAX_MSGS: usize = 100;
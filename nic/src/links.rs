@@ -0,0 +1,64 @@
+use crate::time::TimeProvider;
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+/// Connection of a client to the MQTT broker
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct LinkState {
+    pub connected: bool,
+    /// Unix UTC timestamp of the last change
+    pub since: i64,
+}
+
+/// What the MQTT clients last knew of their broker connection: `weather`, `valves` and `telemetry`, when in use
+#[derive(Debug)]
+pub struct Links {
+    links: Mutex<BTreeMap<&'static str, LinkState>>,
+    time_provider: Arc<dyn TimeProvider>,
+}
+
+impl Links {
+    pub fn new(time_provider: Arc<dyn TimeProvider>) -> Self {
+        Self { links: Mutex::default(), time_provider }
+    }
+
+    /// Only a change moves `since`
+    pub fn set(&self, name: &'static str, connected: bool) {
+        let mut links = self.links.lock().unwrap();
+        match links.get(name) {
+            Some(link) if link.connected == connected => {}
+            _ => {
+                links.insert(name, LinkState { connected, since: self.time_provider.now() });
+            }
+        }
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<&'static str, LinkState> {
+        self.links.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::utils::mock_time::MockTimeProvider;
+
+    #[test]
+    fn keeps_the_time_of_the_change() {
+        let time_provider = Arc::new(MockTimeProvider::new(100));
+        let links = Links::new(time_provider.clone());
+        links.set("valves", true);
+        time_provider.set(200);
+        links.set("valves", true);
+        links.set("telemetry", false);
+        time_provider.set(300);
+        links.set("valves", false);
+
+        let snapshot = links.snapshot();
+        assert_eq!(snapshot["valves"], LinkState { connected: false, since: 300 });
+        assert_eq!(snapshot["telemetry"], LinkState { connected: false, since: 200 });
+    }
+}
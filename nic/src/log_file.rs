@@ -0,0 +1,118 @@
+use crate::config::{LogCfg, LogRotation};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+
+const MB: u64 = 1024 * 1024;
+
+/// The log file of the config, rotated as it says
+pub fn log_writer(cfg: &LogCfg, path: &Path) -> io::Result<Box<dyn Write + Send>> {
+    let rotation = match cfg.rotation {
+        LogRotation::Hourly => Rotation::HOURLY,
+        LogRotation::Daily => Rotation::DAILY,
+        LogRotation::Never => Rotation::NEVER,
+        LogRotation::Size => {
+            return Ok(Box::new(SizeRollingFile::new(path, cfg.max_size_mb * MB, cfg.max_files)?));
+        }
+    };
+    let name = path.file_name().and_then(|name| name.to_str()).unwrap_or("nic.log");
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let appender = RollingFileAppender::builder()
+        .rotation(rotation)
+        .filename_prefix(name)
+        .max_log_files(cfg.max_files)
+        .build(dir)
+        .map_err(io::Error::other)?;
+    Ok(Box::new(appender))
+}
+
+/// Appends to `path` until it holds `max_bytes`, then shifts it to `path.1`, `path.1` to `path.2` and so on,
+/// keeping `max_files` of them.<br>
+/// A single write is never split, so a file may end a line over the limit.
+#[derive(Debug)]
+pub struct SizeRollingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    file: File,
+    written: u64,
+}
+
+impl SizeRollingFile {
+    pub fn new(path: &Path, max_bytes: u64, max_files: usize) -> io::Result<Self> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let written = file.metadata()?.len();
+        Ok(Self { path: path.to_owned(), max_bytes, max_files, file, written })
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", n));
+        name.into()
+    }
+
+    fn roll(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        // the oldest is overwritten by the rename
+        for n in (1..self.max_files).rev() {
+            let from = self.rotated(n);
+            if from.exists() {
+                fs::rename(from, self.rotated(n + 1))?;
+            }
+        }
+        fs::rename(&self.path, self.rotated(1))?;
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.written = 0;
+        Ok(())
+    }
+}
+
+impl Write for SizeRollingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.written > 0 && self.written + buf.len() as u64 > self.max_bytes {
+            self.roll()?;
+        }
+        let n = self.file.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rolls_by_size() {
+        let dir = std::env::temp_dir().join(format!("nic_log_{}", std::process::id()));
+        _ = fs::remove_dir_all(&dir);
+        let path = dir.join("nic.log");
+        let mut log = SizeRollingFile::new(&path, 10, 2).unwrap();
+        for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+            log.write_all(line.as_bytes()).unwrap();
+        }
+        log.flush().unwrap();
+
+        let read = |name: &str| fs::read_to_string(dir.join(name)).unwrap_or_default();
+        assert_eq!(read("nic.log"), "fourth\n");
+        assert_eq!(read("nic.log.1"), "third\n");
+        assert_eq!(read("nic.log.2"), "second\n");
+        // past max_files
+        assert!(!dir.join("nic.log.3").exists());
+
+        // picks up where it was
+        let log = SizeRollingFile::new(&path, 10, 2).unwrap();
+        assert_eq!(log.written, 7);
+        _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -1,57 +1,316 @@
 use nic::api::run_web_server;
-use nic::config::run_options::get_args;
-use nic::config::Config;
-use nic::db::Database;
-use nic::sensors::interface::RealSensorController;
-use nic::time::RealTimeProvider;
+use nic::cli::{
+    controller_import, db_migrate, schedule_export, schedule_import, schedule_set, schedule_show, sector_list,
+    weather_test,
+};
+use nic::clock::{distrust, run_clock_check};
+use nic::config::init::init;
+use nic::config::manager::{run_config_reload, ConfigManager};
+use nic::config::run_options::{
+    default_cfg_file, get_args, Args, Command, DbCommand, ScheduleCommand, SectorCommand, WeatherCommand,
+};
+use nic::config::{Config, Profile, ProfileTime};
+use nic::db::maintenance::run_db_maintenance;
+use nic::db::{Database, DatabaseTrait};
+#[cfg(feature = "sim")]
+use nic::error::AppError;
+use nic::influx::run_influx_exporter;
+use nic::links::Links;
+use nic::publisher::run_mqtt_publisher;
+#[cfg(feature = "sim")]
+use nic::replay::replay;
+use nic::sensors::build_controller;
+use nic::sensors::interlock::Interlock;
+use nic::sensors::telemetry::monitor_telemetry;
+use nic::sensors::watchdog::{run_valve_watchdog, ValveWatchdog};
+use nic::shutdown::coordinate_shutdown;
+#[cfg(feature = "sim")]
+use nic::simulation::{simulate, Scenario};
+use nic::test::utils::mock_time::MockTimeProvider;
+use nic::time::{AcceleratedTimeProvider, RealTimeProvider, TimeProvider};
 use nic::utils::{init_broadcast_channels, init_channels, start_log};
-use nic::watering::ds::AppState;
+use nic::watering::ds::{AppState, WaterSector};
 use nic::watering::modes::Mode;
-use nic::watering::watering_system::run_watering_system;
-use nic::weather;
+use nic::watering::water_window::WaterWin;
+use nic::watering::watering_system::{run_watering_system, WateringSystem};
+use nic::weather::forecast::run_forecast_refresh;
+use nic::weather::freshness::{monitor_freshness, WeatherFreshness};
+#[cfg(feature = "sim")]
+use nic::weather::generator::run_weather_generator;
+use nic::weather::model::load_et_model_or_default;
+use nic::weather::provider::{build_providers, run_threshold_updates, run_weather_providers, ProviderCtx};
+use nic::weather::rollup::run_weather_rollup;
+use nic::webhooks::run_webhooks;
 use std::{error::Error, sync::Arc};
-use tracing::{error, info};
+use tracing::{info, warn};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    let args = get_args();
-    let cfg = if let Some(cfg_str) = args.cfg_str { Config::load_from_str(&cfg_str) } else { Config::load(args) };
-    start_log(None);
+    let result = match get_args() {
+        Command::Run(cfg) => return run(cfg.args()).await,
+        #[cfg(feature = "sim")]
+        Command::Simulate { cfg, days, speed, scenario } => {
+            let cfg = load_or_exit(cfg.args());
+            match scenario.map(|path| Scenario::load(&path)).transpose() {
+                // a scenario not met fails, for the regression runs
+                Ok(scenario) => match simulate(&cfg, &scenario.unwrap_or_default(), days, speed).await {
+                    Ok(report) if !report.violations.is_empty() => Err(AppError::WateringError(report.to_string())),
+                    result => result.map(|report| report.to_string()),
+                },
+                Err(e) => Err(e),
+            }
+        }
+        #[cfg(feature = "sim")]
+        Command::Replay { cfg, weather, days } => {
+            let cfg = load_or_exit(cfg.args());
+            match std::fs::read_to_string(&weather) {
+                Ok(history) => replay(&cfg, &history, days).await.map(|report| report.to_string()),
+                Err(e) => Err(AppError::ConfigError(format!("Can't read {}: {}", weather.display(), e))),
+            }
+        }
+        Command::Init { config } => {
+            init(config.unwrap_or_else(default_cfg_file)).await.map(|report| report.to_string())
+        }
+        Command::Db(DbCommand::Migrate(cfg)) => db_migrate(&load_or_exit(cfg.args())),
+        Command::Schedule(ScheduleCommand::Show(cfg)) => schedule_show(&load_or_exit(cfg.args())),
+        Command::Schedule(ScheduleCommand::Set { day, sector, start, duration, program, cfg }) => {
+            schedule_set(&load_or_exit(cfg.args()), &program, day, WaterSector::new(sector, start, duration))
+        }
+        Command::Schedule(ScheduleCommand::Export { format, cfg }) => {
+            schedule_export(&load_or_exit(cfg.args()), format)
+        }
+        Command::Schedule(ScheduleCommand::Import { file, format, cfg }) => {
+            schedule_import(&load_or_exit(cfg.args()), &file, format)
+        }
+        Command::Import { file, format, cfg } => controller_import(&load_or_exit(cfg.args()), &file, format),
+        Command::Sector(SectorCommand::List(cfg)) => sector_list(&load_or_exit(cfg.args())),
+        Command::Weather(WeatherCommand::Test(cfg)) => Ok(weather_test(&load_or_exit(cfg.args())).await),
+        #[cfg(feature = "sim")]
+        Command::Weather(WeatherCommand::Generate { cfg, weather, to, stop }) => {
+            nic::cli::weather_generate(&load_or_exit(cfg.args()), weather, to, stop).await
+        }
+    };
+    match result {
+        Ok(out) => println!("{}", out),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+    Ok(())
+}
+
+/// The config problems are printed, logging isn't up yet
+fn load_or_exit(args: Args) -> Config {
+    let cfg = match &args.cfg_str {
+        Some(cfg_str) => Config::parse(cfg_str),
+        None => Config::load(args),
+    };
+    cfg.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    })
+}
+
+/// The controller
+async fn run(args: Args) -> Result<(), Box<dyn Error>> {
+    let profile_name = args.profile.clone();
+    let cfg = load_or_exit(args.clone());
+    // dropping it loses the last lines of the log file
+    let _log_guard = start_log(&cfg.log, None)?;
 
     info!("Starting application...");
+    // already checked by the config load
+    let profile = profile_name.as_ref().map(|name| cfg.profiles[name].clone()).unwrap_or_default();
+    if profile != Profile::default() {
+        info!(profile = profile_name, settings = ?profile, "Running with a profile.");
+    }
+    if args.dry_run {
+        info!("Dry run, the valve commands are only recorded in the system events.");
+    }
 
-    let db = Arc::new(Database::new(&cfg.database.name)?);
+    let db = Arc::new(Database::open(&cfg.database, &cfg.site.id)?);
+    if cfg.database.in_memory() {
+        warn!("The database is in memory, nothing is kept after a restart.");
+    }
+    if !cfg.sectors.is_empty() {
+        db.import_sectors(cfg.sectors.clone())?;
+        db.import_sector_constraints(cfg.sector_constraints.clone())?;
+        info!(sectors = cfg.sectors.len(), "Sectors imported from the config.");
+    }
 
     let (sm_tx, sm_rx) = init_channels();
     let (web_tx, web_rx) = init_broadcast_channels();
 
-    let (_shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+    let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
 
-    let controller = Arc::new(RealSensorController {});
-    let time_provider = Arc::new(RealTimeProvider);
+    let time_provider: Arc<dyn TimeProvider> = match profile.time {
+        ProfileTime::Real => Arc::new(RealTimeProvider),
+        ProfileTime::Accelerated => Arc::new(AcceleratedTimeProvider::new(RealTimeProvider.now(), profile.time_factor)),
+        ProfileTime::Simulated => Arc::new(MockTimeProvider::held(RealTimeProvider.now())),
+    };
+    let links = Arc::new(Links::new(time_provider.clone()));
+    let watchdog = Arc::new(
+        ValveWatchdog::new(
+            build_controller(&cfg, sm_tx.clone(), db.clone(), time_provider.clone(), links.clone())?,
+            cfg.sensors.watchdog,
+            cfg.watering.pump_sector,
+            time_provider.clone(),
+        )
+        .with_source_masters(cfg.source_masters()),
+    );
     // TODO: read from config and db, in case is not a fresh start
-    let app_state = AppState::new(db.clone(), controller, time_provider, sm_tx.clone(), sm_rx, web_tx, web_rx).await?;
+    let et_model = load_et_model_or_default(&cfg.weather_station);
+    let last_obs = db.get_current_weather().map(|obs| obs.timestamp);
+    let freshness = Arc::new(WeatherFreshness::new(&cfg.weather_station, last_obs));
+    let interlock = Arc::new(
+        Interlock::new(watchdog.clone(), cfg.sensors.interlock, cfg.watering.pump_sector)
+            .with_source_masters(cfg.source_masters()),
+    );
+    let config = Arc::new(ConfigManager::new(args.cfg_file.clone(), cfg.clone(), sm_tx.clone()).with_args(args));
+    let app_state = AppState::new(
+        db.clone(),
+        interlock,
+        time_provider,
+        sm_tx.clone(),
+        sm_rx,
+        web_tx,
+        web_rx,
+        et_model,
+        freshness.clone(),
+        config.clone(),
+        links.clone(),
+    )
+    .await?;
 
-    tokio::spawn(weather::mqtt_mon::monitor_mqtt(sm_tx.clone()));
-    tokio::spawn(weather::mqtt_mon::monitor_udp(sm_tx.clone(), db.clone()));
+    let weather_ctx = ProviderCtx::new(
+        &cfg.weather_station,
+        db.clone(),
+        sm_tx.clone(),
+        app_state.web_tx.clone(),
+        freshness.clone(),
+        links.clone(),
+    );
+    // the tasks with nothing to do return at once, they aren't started so they aren't restarted
+    let supervisor = app_state.supervisor.clone();
+    let ctx = weather_ctx.clone();
+    supervisor.spawn("thresholds", move || run_threshold_updates(ctx.clone()));
+    #[cfg(feature = "sim")]
+    {
+        let (ctx, time_provider) = (weather_ctx.clone(), app_state.time_provider.clone());
+        supervisor.spawn("generator", move || run_weather_generator(ctx.clone(), time_provider.clone()));
+    }
+    if !cfg.weather_station.providers.is_empty() {
+        let (station, mqtt) = (cfg.weather_station.clone(), cfg.mqtt.clone());
+        supervisor
+            .spawn("weather", move || run_weather_providers(build_providers(&station, &mqtt), weather_ctx.clone()));
+    }
+    #[cfg(unix)]
+    supervisor.spawn("config_reload", move || run_config_reload(config.clone()));
+    if cfg.weather_station.forecast_provider.is_some() {
+        let (station, db) = (cfg.weather_station.clone(), db.clone());
+        supervisor.spawn("forecast", move || run_forecast_refresh(station.clone(), db.clone()));
+    }
+    let (db_clone, time_provider) = (db.clone(), app_state.time_provider.clone());
+    supervisor.spawn("rollup", move || run_weather_rollup(db_clone.clone(), time_provider.clone()));
+    let time_provider = app_state.time_provider.clone();
+    supervisor.spawn("freshness", move || monitor_freshness(freshness.clone(), time_provider.clone()));
+    if cfg.sensors.watchdog.grace_secs > 0 {
+        let web_tx = app_state.web_tx.clone();
+        supervisor.spawn("valve_watchdog", move || run_valve_watchdog(watchdog.clone(), web_tx.clone()));
+    }
+    if cfg.mqtt.publish.enabled {
+        let (mqtt, sm_tx, web_tx, links) = (cfg.mqtt.clone(), sm_tx.clone(), app_state.web_tx.clone(), links.clone());
+        supervisor
+            .spawn("publisher", move || run_mqtt_publisher(mqtt.clone(), sm_tx.clone(), web_tx.clone(), links.clone()));
+    }
+    // with none configured too, a reload can add them. Restarted with the hooks of the last reload.
+    {
+        let (config, sm_tx, web_tx) = (app_state.config.clone(), sm_tx.clone(), app_state.web_tx.clone());
+        let time_provider = app_state.time_provider.clone();
+        supervisor.spawn("webhooks", move || {
+            run_webhooks(config.current().webhooks.clone(), sm_tx.clone(), web_tx.clone(), time_provider.clone())
+        });
+    }
+    if cfg.influx.enabled {
+        let (influx, db, web_tx) = (cfg.influx.clone(), db.clone(), app_state.web_tx.clone());
+        let time_provider = app_state.time_provider.clone();
+        supervisor.spawn("influx", move || {
+            run_influx_exporter(influx.clone(), db.clone(), web_tx.clone(), time_provider.clone())
+        });
+    }
+    if cfg.database.maintenance_every_days > 0 {
+        let (db_cfg, db, health) = (cfg.database.clone(), db.clone(), app_state.db_health.clone());
+        let (sm_tx, web_tx, time_provider) = (sm_tx.clone(), app_state.web_tx.clone(), app_state.time_provider.clone());
+        let window =
+            WaterWin::new(time_provider.now(), cfg.watering.window_start_hour, cfg.watering.window_duration_hours);
+        supervisor.spawn("db_maintenance", move || {
+            run_db_maintenance(
+                db_cfg.clone(),
+                window,
+                db.clone(),
+                health.clone(),
+                sm_tx.clone(),
+                web_tx.clone(),
+                time_provider.clone(),
+            )
+        });
+    }
+    // a clock behind the last time trusted holds the scheduling from the start, until the check trusts it
+    let now = app_state.time_provider.now();
+    app_state.clock.record(None, distrust(&cfg.clock, now, db.load_clock_mark(), None), None);
+    let (clock, guard, db_clone, web_tx) =
+        (cfg.clock.clone(), app_state.clock.clone(), db.clone(), app_state.web_tx.clone());
+    let time_provider = app_state.time_provider.clone();
+    supervisor.spawn("clock", move || {
+        run_clock_check(clock.clone(), guard.clone(), db_clone.clone(), web_tx.clone(), time_provider.clone())
+    });
+    if cfg.sensors.telemetry.enabled {
+        let (mqtt, telemetry, db, web_tx) =
+            (cfg.mqtt.clone(), cfg.sensors.telemetry.clone(), db.clone(), app_state.web_tx.clone());
+        let time_provider = app_state.time_provider.clone();
+        supervisor.spawn("telemetry", move || {
+            monitor_telemetry(
+                mqtt.clone(),
+                telemetry.clone(),
+                db.clone(),
+                web_tx.clone(),
+                time_provider.clone(),
+                links.clone(),
+            )
+        });
+    }
 
     // Start watering system loop
     let app_state_clone = app_state.clone();
     let rx_clone = shutdown_rx.clone();
-    tokio::spawn(async move {
-        run_watering_system(app_state_clone, Some(Mode::Auto), rx_clone, None, None, cfg.watering)
-            .await
-            .unwrap_or_else(|e| error!("HTTP server error: {}", e)); // TODO
+    let now = app_state.time_provider.now();
+    let mode = db.load_mode().unwrap_or(Mode::Auto);
+    info!(mode = ?mode, "Starting in the last mode.");
+    let mut ws = WateringSystem::new(app_state.clone(), Some(mode), now, cfg.watering)?;
+    ws.sm.pause_policy = cfg.pause_policy;
+    ws.sm.sources = cfg.sources.clone();
+    ws.sm.restrictions = cfg.restrictions.clone();
+    // when it stops, for good or not, the shutdown follows
+    let watering = supervisor.spawn_critical("watering", async move {
+        run_watering_system(app_state_clone, Some(mode), rx_clone, None, Some(&mut ws), cfg.watering).await
     });
 
+    #[cfg(feature = "grpc")]
+    if let Some(addr) = &cfg.web_server.grpc_address {
+        // checked by the config load
+        let grpc = nic::grpc::run_grpc_server(app_state.clone(), addr.parse()?, shutdown_rx.clone());
+        // reported at /healthz, nothing waits on it: the HTTP API is still there without it
+        supervisor.spawn_critical("grpc", grpc);
+    }
+
     let app_state_clone = app_state.clone();
-    tokio::spawn(async move {
-        let ip_addr = cfg.web_server.address.parse().unwrap();
-        if let Err(e) = run_web_server(app_state_clone, ip_addr, shutdown_rx).await {
-            error!("Web server error: {}", e);
-        }
-    })
-    .await?;
+    let ip_addr = cfg.web_server.address.parse()?;
+    // not restarted either, the shutdown follows when it stops
+    let web = supervisor.spawn_critical("web", async move {
+        run_web_server(app_state_clone, ip_addr, shutdown_rx).await.map_err(|e| e.to_string())
+    });
 
+    coordinate_shutdown(shutdown_tx, watering, web, db).await;
     Ok(())
 }
@@ -0,0 +1,144 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{Mutex, OnceLock},
+    time::Duration,
+};
+
+/// Upper bounds, in seconds, of the latency buckets
+const BUCKETS: [f64; 10] = [0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.5, 1.];
+
+/// Seconds each database command took in the actor, by `command`
+pub const DB_COMMAND_SECONDS: &str = "nic_db_command_seconds";
+/// Seconds from an API request to the watering loop answering it, by `request`
+pub const SIGNAL_ROUNDTRIP_SECONDS: &str = "nic_signal_roundtrip_seconds";
+/// Signals a broadcast receiver lost by falling behind, by `receiver`
+pub const DROPPED_MESSAGES: &str = "nic_dropped_messages_total";
+/// Datagrams of the UDP weather listener, by `result`: parsed, empty, oversized, malformed or recv_error
+pub const UDP_PACKETS: &str = "nic_udp_packets_total";
+
+/// A label name and its value
+pub type Label = (&'static str, &'static str);
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Histogram {
+    /// per bucket, not cumulative
+    buckets: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    pub fn observe(&mut self, secs: f64) {
+        if let Some(bucket) = BUCKETS.iter().position(|le| secs <= *le) {
+            self.buckets[bucket] += 1;
+        }
+        self.count += 1;
+        self.sum += secs;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+}
+
+/// Histograms and counters by metric and label, served at `/metrics` in the Prometheus text format
+#[derive(Debug, Default)]
+pub struct Metrics {
+    histograms: Mutex<BTreeMap<(&'static str, Label), Histogram>>,
+    counters: Mutex<BTreeMap<(&'static str, Label), u64>>,
+}
+
+impl Metrics {
+    pub fn observe(&self, name: &'static str, label: Label, elapsed: Duration) {
+        self.histograms.lock().unwrap().entry((name, label)).or_default().observe(elapsed.as_secs_f64());
+    }
+
+    pub fn histogram(&self, name: &'static str, label: Label) -> Option<Histogram> {
+        self.histograms.lock().unwrap().get(&(name, label)).cloned()
+    }
+
+    pub fn add(&self, name: &'static str, label: Label, n: u64) {
+        *self.counters.lock().unwrap().entry((name, label)).or_default() += n;
+    }
+
+    pub fn counter(&self, name: &'static str, label: Label) -> u64 {
+        self.counters.lock().unwrap().get(&(name, label)).copied().unwrap_or(0)
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let mut last = "";
+        for ((name, (key, value)), hist) in self.histograms.lock().unwrap().iter() {
+            if *name != last {
+                _ = writeln!(out, "# TYPE {} histogram", name);
+                last = name;
+            }
+            let mut cumulative = 0;
+            for (le, n) in BUCKETS.iter().zip(hist.buckets) {
+                cumulative += n;
+                _ = writeln!(out, "{}_bucket{{{}=\"{}\",le=\"{}\"}} {}", name, key, value, le, cumulative);
+            }
+            _ = writeln!(out, "{}_bucket{{{}=\"{}\",le=\"+Inf\"}} {}", name, key, value, hist.count);
+            _ = writeln!(out, "{}_sum{{{}=\"{}\"}} {}", name, key, value, hist.sum);
+            _ = writeln!(out, "{}_count{{{}=\"{}\"}} {}", name, key, value, hist.count);
+        }
+        let mut last = "";
+        for ((name, (key, value)), n) in self.counters.lock().unwrap().iter() {
+            if *name != last {
+                _ = writeln!(out, "# TYPE {} counter", name);
+                last = name;
+            }
+            _ = writeln!(out, "{}{{{}=\"{}\"}} {}", name, key, value, n);
+        }
+        out
+    }
+}
+
+/// The process wide registry
+pub fn registry() -> &'static Metrics {
+    static REGISTRY: OnceLock<Metrics> = OnceLock::new();
+    REGISTRY.get_or_init(Metrics::default)
+}
+
+/// Counts the signals `receiver` lost to a lagging broadcast channel
+pub fn dropped(receiver: &'static str, missed: u64) {
+    registry().add(DROPPED_MESSAGES, ("receiver", receiver), missed);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn renders_cumulative_buckets() {
+        let metrics = Metrics::default();
+        let label = ("command", "load_sectors");
+        metrics.observe(DB_COMMAND_SECONDS, label, Duration::from_micros(300));
+        metrics.observe(DB_COMMAND_SECONDS, label, Duration::from_millis(20));
+        metrics.observe(DB_COMMAND_SECONDS, label, Duration::from_secs(2));
+        assert_eq!(metrics.histogram(DB_COMMAND_SECONDS, label).unwrap().count(), 3);
+
+        let text = metrics.render();
+        assert!(text.starts_with("# TYPE nic_db_command_seconds histogram\n"));
+        assert!(text.contains("nic_db_command_seconds_bucket{command=\"load_sectors\",le=\"0.0005\"} 1\n"));
+        assert!(text.contains("nic_db_command_seconds_bucket{command=\"load_sectors\",le=\"0.025\"} 2\n"));
+        assert!(text.contains("nic_db_command_seconds_bucket{command=\"load_sectors\",le=\"+Inf\"} 3\n"));
+        assert!(text.contains("nic_db_command_seconds_count{command=\"load_sectors\"} 3\n"));
+    }
+
+    #[test]
+    fn renders_counters() {
+        let metrics = Metrics::default();
+        metrics.add(DROPPED_MESSAGES, ("receiver", "watering"), 3);
+        metrics.add(DROPPED_MESSAGES, ("receiver", "watering"), 2);
+        metrics.add(DROPPED_MESSAGES, ("receiver", "websocket"), 1);
+        assert_eq!(metrics.counter(DROPPED_MESSAGES, ("receiver", "watering")), 5);
+        assert_eq!(metrics.counter(DROPPED_MESSAGES, ("receiver", "influx")), 0);
+
+        let text = metrics.render();
+        assert!(text.contains("# TYPE nic_dropped_messages_total counter\n"));
+        assert!(text.contains("nic_dropped_messages_total{receiver=\"watering\"} 5\n"));
+        assert!(text.contains("nic_dropped_messages_total{receiver=\"websocket\"} 1\n"));
+    }
+}
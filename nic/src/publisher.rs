@@ -0,0 +1,173 @@
+use crate::{
+    api::{ask, MachineStatus},
+    config::MQTT,
+    error::AppError,
+    links::Links,
+    metrics,
+    sensors::mqtt_ctrl::mqtt_options,
+    watering::ds::CtrlSignal,
+};
+use rumqttc::{AsyncClient, Event, LastWill, Packet, QoS};
+use serde::Serialize;
+use std::{sync::Arc, time::Duration};
+use tokio::sync::broadcast::{error::RecvError, Sender};
+use tracing::{info, warn};
+
+/// `nic/<client_id>`, every published topic is under it
+pub fn topic_prefix(mqtt: &MQTT) -> String {
+    format!("nic/{}", mqtt.client_id)
+}
+
+/// A message for the broker
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outgoing {
+    pub topic: String,
+    pub payload: String,
+    pub retain: bool,
+}
+
+fn json<T: Serialize>(value: &T) -> Option<String> {
+    serde_json::to_string(value).ok()
+}
+
+/// What a signal becomes on the broker, if anything. The device telemetry is retained, so a new subscriber gets
+/// the last of it right away.
+pub fn outgoing(prefix: &str, signal: &CtrlSignal) -> Option<Outgoing> {
+    let (topic, payload, retain) = match signal {
+        CtrlSignal::StateChanged(evt) => ("events".to_owned(), json(evt)?, false),
+        CtrlSignal::Weather(signal) => ("signals".to_owned(), json(signal)?, false),
+        CtrlSignal::Alarm(alarm) => ("alarms/valve".to_owned(), json(alarm)?, false),
+        CtrlSignal::StuckValve(alarm) => ("alarms/stuck_valve".to_owned(), json(alarm)?, false),
+        CtrlSignal::DbIntegrity(check) => ("alarms/db_integrity".to_owned(), json(check)?, false),
+        CtrlSignal::ClockJump(jump) => ("alarms/clock_jump".to_owned(), json(jump)?, false),
+        CtrlSignal::LowBattery(t) => (format!("telemetry/{}", t.device), json(t)?, true),
+        _ => return None,
+    };
+    Some(Outgoing { topic: format!("{}/{}", prefix, topic), payload, retain })
+}
+
+/// The retained `<prefix>/state`
+pub fn state_message(prefix: &str, status: &MachineStatus) -> Option<Outgoing> {
+    Some(Outgoing { topic: format!("{}/state", prefix), payload: json(status)?, retain: true })
+}
+
+async fn publish(client: &AsyncClient, msg: Outgoing) {
+    if let Err(e) = client.publish(msg.topic, QoS::AtLeastOnce, msg.retain, msg.payload).await {
+        warn!(error = ?e, "Failed to publish to MQTT.");
+    }
+}
+
+async fn publish_state(client: &AsyncClient, prefix: &str, sm_tx: &Sender<CtrlSignal>) {
+    let Some(status) = ask(sm_tx, CtrlSignal::GetStatus, "status").await else {
+        return;
+    };
+    if let Some(msg) = state_message(prefix, &status) {
+        publish(client, msg).await;
+    }
+}
+
+/// Mirrors the controller to the broker: what the state machine does, the weather signals it gets and the alarms.
+/// `<prefix>/status` is a retained "online", and "offline" as the last will when the connection drops.
+pub async fn run_mqtt_publisher(
+    mqtt: MQTT, sm_tx: Arc<Sender<CtrlSignal>>, web_tx: Sender<CtrlSignal>, links: Arc<Links>,
+) -> Result<(), AppError> {
+    let prefix = topic_prefix(&mqtt);
+    let status_topic = format!("{}/status", prefix);
+    let mut options = mqtt_options(&mqtt, "publisher")?;
+    options.set_keep_alive(Duration::from_secs(30));
+    options.set_last_will(LastWill::new(&status_topic, "offline", QoS::AtLeastOnce, true));
+    let (client, mut eventloop) = AsyncClient::new(options, 100);
+
+    let online = client.clone();
+    tokio::spawn(async move {
+        loop {
+            match eventloop.poll().await {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    links.set("publisher", true);
+                    // again on every reconnection, the broker sent the last will when we dropped
+                    _ = online.try_publish(&status_topic, QoS::AtLeastOnce, true, "online");
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    // the next poll reconnects
+                    links.set("publisher", false);
+                    warn!(error = ?e, "MQTT publisher connection error.");
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        }
+    });
+    info!(prefix, "Publishing the controller state.");
+
+    let (mut sm_rx, mut web_rx) = (sm_tx.subscribe(), web_tx.subscribe());
+    let mut refresh = tokio::time::interval(Duration::from_secs(mqtt.publish.status_secs.max(1)));
+    loop {
+        let signal = tokio::select! {
+            _ = refresh.tick() => {
+                publish_state(&client, &prefix, &sm_tx).await;
+                continue;
+            }
+            signal = sm_rx.recv() => signal,
+            signal = web_rx.recv() => signal,
+        };
+        let signal = match signal {
+            Ok(signal) => signal,
+            Err(RecvError::Lagged(missed)) => {
+                // not asking for the state here, that would push out the next signal we are about to read. The
+                // refresh puts it right.
+                metrics::dropped("publisher", missed);
+                warn!(missed, "MQTT publisher fell behind, messages skipped.");
+                continue;
+            }
+            Err(RecvError::Closed) => return Ok(()),
+        };
+        if let Some(msg) = outgoing(&prefix, &signal) {
+            publish(&client, msg).await;
+        }
+        if matches!(signal, CtrlSignal::StateChanged(_)) {
+            publish_state(&client, &prefix, &sm_tx).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        sensors::telemetry::DeviceTelemetry,
+        watering::ds::{StateChange, StateEvent, WeatherSignal},
+    };
+
+    #[test]
+    fn maps_signals_to_topics() {
+        let mqtt = MQTT { client_id: "garden".to_owned(), ..Default::default() };
+        let prefix = topic_prefix(&mqtt);
+
+        let evt = StateEvent { timestamp: 10, sector: Some(2), cycle: Some(1), change: StateChange::CycleCompleted };
+        let msg = outgoing(&prefix, &CtrlSignal::StateChanged(evt)).unwrap();
+        assert_eq!(msg.topic, "nic/garden/events");
+        assert!(msg.payload.contains(r#""kind":"cycle_completed""#));
+        assert!(!msg.retain);
+
+        let msg = outgoing(&prefix, &CtrlSignal::Weather(WeatherSignal::RainStart)).unwrap();
+        assert_eq!((msg.topic.as_str(), msg.payload.as_str()), ("nic/garden/signals", r#""rain_start""#));
+
+        let t = DeviceTelemetry { device: "valve-3".to_owned(), timestamp: 0, battery: Some(15.), rssi: None };
+        let msg = outgoing(&prefix, &CtrlSignal::LowBattery(t)).unwrap();
+        assert_eq!(msg.topic, "nic/garden/telemetry/valve-3");
+        assert!(msg.retain);
+
+        assert_eq!(outgoing(&prefix, &CtrlSignal::StopMachine), None);
+
+        let status = MachineStatus {
+            mode: "auto".to_owned(),
+            state: "idle".to_owned(),
+            active_sector: None,
+            next_run: Some(100),
+            faulted: vec![],
+        };
+        let msg = state_message(&prefix, &status).unwrap();
+        assert_eq!(msg.topic, "nic/garden/state");
+        assert!(msg.retain);
+    }
+}
@@ -0,0 +1,181 @@
+use crate::{
+    config::Config,
+    error::AppError,
+    simulation::{hour, simulate, Scenario, ScenarioDay, ScenarioSignal, SimulationReport},
+    watering::ds::WeatherSignal,
+};
+use chrono::NaiveDate;
+use std::fmt::Display;
+
+const HISTORY_HEADER: &str = "date,et,rain[,rain_from,rain_to]";
+
+/// Days of daily history: `date,et,rain` in mm, and optionally the `rain_from,rain_to` (HH:MM, UTC) of a night
+/// shower, sent as rain signals. Gaps take the scenario defaults.
+pub fn history_scenario(csv: &str) -> Result<Scenario, AppError> {
+    let mut rows = vec![];
+    for (n, line) in csv.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || (n == 0 && line.starts_with("date")) {
+            continue;
+        }
+        let bad = || AppError::ConfigError(format!("history: line {} is not {}", n + 1, HISTORY_HEADER));
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let (date, et, rain, shower) = match fields[..] {
+            [date, et, rain] => (date, et, rain, None),
+            [date, et, rain, from, to] => (date, et, rain, Some((from, to))),
+            _ => return Err(bad()),
+        };
+        let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|_| bad())?;
+        let shower = match shower {
+            Some((from, to)) if hour(from).is_some() && hour(to).is_some() => Some((from, to)),
+            Some(_) => return Err(bad()),
+            None => None,
+        };
+        rows.push((date, et.parse::<f64>().map_err(|_| bad())?, rain.parse::<f64>().map_err(|_| bad())?, shower));
+    }
+    rows.sort_by_key(|(date, ..)| *date);
+    let Some(first) = rows.first().map(|(date, ..)| *date) else {
+        return Err(AppError::ConfigError("history: no days".to_owned()));
+    };
+
+    let mut scenario = Scenario { start: Some(first.format("%Y-%m-%d").to_string()), ..Default::default() };
+    for (date, et, rain, shower) in rows {
+        let day = (date - first).num_days() as u32 + 1;
+        scenario.days.push(ScenarioDay { day, et: Some(et), rain: Some(rain) });
+        if let Some((from, to)) = shower {
+            let signal = |at: &str, signal| ScenarioSignal { day, at: at.to_owned(), signal };
+            scenario.signals.push(signal(from, WeatherSignal::RainStart));
+            scenario.signals.push(signal(to, WeatherSignal::RainStop));
+        }
+    }
+    Ok(scenario)
+}
+
+/// The simulation of a history, held against the weekly targets
+#[derive(Debug, Clone, Default)]
+pub struct ReplayReport {
+    pub simulation: SimulationReport,
+    /// mm, a day each
+    pub rain: Vec<f64>,
+}
+
+impl ReplayReport {
+    /// cm watered
+    pub fn water(&self, sector: u32) -> f64 {
+        let secs = self.simulation.days.iter().filter_map(|day| day.watered.get(&sector)).sum();
+        self.simulation.water(sector, secs)
+    }
+
+    pub fn pauses(&self) -> usize {
+        self.simulation.days.iter().map(|day| day.pauses).sum()
+    }
+
+    /// Days, from the 7th on, whose last 7 days of water and rain fell short of the sector weekly target
+    pub fn under_target(&self, sector: u32) -> Vec<usize> {
+        let Some(target) = self.simulation.sectors.iter().find(|sec| sec.id == sector).map(|sec| sec.weekly_target)
+        else {
+            return vec![];
+        };
+        let received: Vec<f64> = self
+            .simulation
+            .days
+            .iter()
+            .zip(self.rain.iter().chain(std::iter::repeat(&0.)))
+            .map(|(day, rain)| {
+                self.simulation.water(sector, day.watered.get(&sector).copied().unwrap_or(0)) + rain / 10.
+            })
+            .collect();
+        (6..received.len()).filter(|&day| received[day - 6..=day].iter().sum::<f64>() < target).collect()
+    }
+
+    /// Days with at least one sector under its target
+    pub fn days_under_target(&self) -> usize {
+        let mut days: Vec<usize> = self.simulation.sectors.iter().flat_map(|sec| self.under_target(sec.id)).collect();
+        days.sort_unstable();
+        days.dedup();
+        days.len()
+    }
+}
+
+impl Display for ReplayReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let rain: f64 = self.rain.iter().sum();
+        write!(
+            f,
+            "{} days, {:.1} mm of rain, {} pause(s), {} day(s) under target",
+            self.simulation.days.len(),
+            rain,
+            self.pauses(),
+            self.days_under_target()
+        )?;
+        for sec in &self.simulation.sectors {
+            write!(
+                f,
+                "\n  sector {:>3} {:<20} {:>7.2} cm watered, {} day(s) under {:.2} cm/week",
+                sec.id,
+                sec.name,
+                self.water(sec.id),
+                self.under_target(sec.id).len(),
+                sec.weekly_target
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// `nic replay`: the wizard over the history, the first `days` of it or all of it
+pub async fn replay(cfg: &Config, history: &str, days: Option<u32>) -> Result<ReplayReport, AppError> {
+    let scenario = history_scenario(history)?;
+    let span = scenario.days.last().map_or(0, |day| day.day);
+    let days = days.unwrap_or(span).min(span);
+    let simulation = simulate(cfg, &scenario, days, None).await?;
+    let rain = (1..=days).map(|day| scenario.weather(day).1).collect();
+    Ok(ReplayReport { simulation, rain })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::watering::{daily_report::DaySummary, ds::SectorInfo};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn reads_the_history() {
+        let scenario = history_scenario(
+            "date,et,rain,rain_from,rain_to\n\
+             2024-06-04,4.5,0,22:40,23:10\n\
+             2024-06-03,5.0,12.5\n",
+        )
+        .unwrap();
+        assert_eq!(scenario.start.as_deref(), Some("2024-06-03"));
+        assert_eq!(scenario.weather(1), (5., 12.5));
+        assert_eq!(scenario.weather(2), (4.5, 0.));
+        assert_eq!(scenario.signals.len(), 2);
+        assert_eq!(scenario.signals[0].signal, WeatherSignal::RainStart);
+
+        let bad = history_scenario("2024-06-03,5.0").unwrap_err().to_string();
+        assert_eq!(bad, "Config error: history: line 1 is not date,et,rain[,rain_from,rain_to]");
+        assert!(history_scenario("2024-06-03,5.0,0,25:00,26:00").is_err());
+    }
+
+    #[test]
+    fn counts_the_days_under_target() {
+        let lawn = SectorInfo { id: 1, sprinkler_debit: 1., weekly_target: 2.5, ..Default::default() };
+        // half an hour of water a day is 3.5 cm a week
+        let mut days: Vec<DaySummary> =
+            (0..10).map(|day| DaySummary { day, watered: BTreeMap::from([(1, 1800)]), ..Default::default() }).collect();
+        // four dry days in a row
+        for day in &mut days[5..9] {
+            day.watered.clear();
+        }
+        days[9].pauses = 1;
+        let report = ReplayReport {
+            simulation: SimulationReport { sectors: vec![lawn], days, ..Default::default() },
+            rain: vec![0., 0., 0., 0., 0., 0., 0., 0., 5., 0.],
+        };
+        assert_eq!(report.water(1), 3.);
+        assert_eq!(report.under_target(1), [7, 8, 9]);
+        assert_eq!(report.days_under_target(), 3);
+        assert_eq!(report.pauses(), 1);
+    }
+}
@@ -0,0 +1,100 @@
+use super::interface::{SensorController, ValveState};
+use crate::{config::GpioCfg, error::AppError};
+use async_trait::async_trait;
+use rppal::gpio::{Gpio, Level, OutputPin};
+use std::{collections::HashMap, sync::Mutex};
+use tracing::{debug, info, warn};
+
+/// Drives a relay board straight from the Raspberry Pi GPIO, one pin per sector
+#[derive(Debug)]
+pub struct GpioSensorController {
+    pins: Mutex<HashMap<u32, OutputPin>>,
+    active_low: bool,
+}
+
+/// Pin level that turns a relay on (`on == true`) or off
+pub fn relay_level(on: bool, active_low: bool) -> Level {
+    if on != active_low {
+        Level::High
+    } else {
+        Level::Low
+    }
+}
+
+impl GpioSensorController {
+    /// Claims every configured pin and drives it to off before anything else can happen
+    pub fn new(cfg: &GpioCfg) -> Result<Self, AppError> {
+        let gpio = Gpio::new().map_err(|e| AppError::SensorError(format!("GPIO not available: {}", e)))?;
+        let off = relay_level(false, cfg.active_low);
+        let mut pins = HashMap::with_capacity(cfg.pins.len());
+        for map in cfg.pins.iter() {
+            let pin = gpio
+                .get(map.pin)
+                .map_err(|e| AppError::SensorError(format!("GPIO pin {} for sector {}: {}", map.pin, map.sector, e)))?;
+            let out = match off {
+                Level::High => pin.into_output_high(),
+                Level::Low => pin.into_output_low(),
+            };
+            if pins.insert(map.sector, out).is_some() {
+                return Err(AppError::SensorError(format!("Sector {} mapped to more than one pin", map.sector)));
+            }
+        }
+        info!(pins = pins.len(), active_low = cfg.active_low, "GPIO relays ready, all off.");
+        Ok(Self { pins: Mutex::new(pins), active_low: cfg.active_low })
+    }
+
+    fn set(&self, sector: u32, on: bool) -> Result<(), AppError> {
+        let mut pins = self.pins.lock().unwrap();
+        let pin = pins
+            .get_mut(&sector)
+            .ok_or_else(|| AppError::SensorError(format!("No GPIO pin configured for sector {}", sector)))?;
+        pin.write(relay_level(on, self.active_low));
+        debug!(sector_id = sector, on, pin = pin.pin(), "GPIO relay set.");
+        Ok(())
+    }
+}
+
+// pin writes are a memory mapped register store, no need to leave the executor
+#[async_trait]
+impl SensorController for GpioSensorController {
+    async fn activate_sector(&self, sector: u32) -> Result<(), AppError> {
+        self.set(sector, true)
+    }
+
+    async fn deactivate_sector(&self, sector: u32) -> Result<(), AppError> {
+        self.set(sector, false)
+    }
+
+    /// The level we drive, read back from the pin register
+    async fn sector_state(&self, sector: u32) -> Result<ValveState, AppError> {
+        let pins = self.pins.lock().unwrap();
+        let pin = pins
+            .get(&sector)
+            .ok_or_else(|| AppError::SensorError(format!("No GPIO pin configured for sector {}", sector)))?;
+        let level = if pin.is_set_high() { Level::High } else { Level::Low };
+        Ok(if level == relay_level(true, self.active_low) { ValveState::Open } else { ValveState::Closed })
+    }
+}
+
+impl Drop for GpioSensorController {
+    fn drop(&mut self) {
+        let off = relay_level(false, self.active_low);
+        match self.pins.lock() {
+            Ok(mut pins) => pins.values_mut().for_each(|pin| pin.write(off)),
+            Err(_) => warn!("GPIO pins poisoned on shutdown, relays left as they were."),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn active_low_inverts_levels() {
+        assert_eq!(relay_level(true, false), Level::High);
+        assert_eq!(relay_level(false, false), Level::Low);
+        assert_eq!(relay_level(true, true), Level::Low);
+        assert_eq!(relay_level(false, true), Level::High);
+    }
+}
@@ -1,28 +1,77 @@
-use std::fmt::Debug;
+use std::{collections::HashMap, fmt::Debug, time::Duration};
 
-use reqwest;
-use reqwest::blocking;
+use async_trait::async_trait;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use reqwest::{header, Client, StatusCode};
+use serde::Serialize;
 use tracing::debug;
 
-use crate::error::AppError;
+use crate::{config::HttpSensorCfg, error::AppError};
+
+/// What a path segment leaves as it is, the unreserved characters of RFC 3986
+const SEGMENT: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'.').remove(b'_').remove(b'~');
 
 pub enum ControlMessage {
     Activate(u32),
     Deactivate(u32),
 }
 
-pub trait SensorController: Send + Sync + Debug{
-    fn activate_sector(&self, sector: u32) -> Result<(), AppError>;
-    fn deactivate_sector(&self, sector: u32) -> Result<(), AppError>;
+/// What the valve hardware reports, as opposed to what we commanded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ValveState {
+    Open,
+    Closed,
+    /// the backend can't tell (no report yet, or no read back at all)
+    Unknown,
+}
+
+#[async_trait]
+pub trait SensorController: Send + Sync + Debug {
+    async fn activate_sector(&self, sector: u32) -> Result<(), AppError>;
+    async fn deactivate_sector(&self, sector: u32) -> Result<(), AppError>;
+    async fn sector_state(&self, sector: u32) -> Result<ValveState, AppError>;
 }
 
+/// The external "sensor-system" HTTP service
 #[derive(Debug)]
-pub struct RealSensorController;
+pub struct RealSensorController {
+    client: Client,
+    base_url: String,
+    addresses: HashMap<u32, String>,
+}
+
+impl RealSensorController {
+    pub fn new(cfg: &HttpSensorCfg) -> Result<Self, AppError> {
+        // bounded, so a dead relay board can't stall the watering loop
+        let timeout = Duration::from_secs(cfg.timeout_secs);
+        let mut builder = Client::builder().timeout(timeout).connect_timeout(timeout);
+        if let Some(token) = &cfg.token {
+            let mut auth = header::HeaderValue::from_str(&format!("Bearer {}", token))
+                .map_err(|e| AppError::SensorError(format!("Invalid sensor token: {}", e)))?;
+            auth.set_sensitive(true);
+            builder = builder.default_headers(header::HeaderMap::from_iter([(header::AUTHORIZATION, auth)]));
+        }
+        Ok(Self {
+            client: builder.build()?,
+            base_url: cfg.base_url.trim_end_matches('/').to_owned(),
+            addresses: cfg.addresses.iter().map(|a| (a.sector, a.address.clone())).collect(),
+        })
+    }
+
+    /// The address is one path segment, whatever it has in it
+    pub fn url(&self, action: &str, sector: u32) -> String {
+        match self.addresses.get(&sector) {
+            Some(address) => format!("{}/{}/{}", self.base_url, action, utf8_percent_encode(address, SEGMENT)),
+            None => format!("{}/{}/{}", self.base_url, action, sector),
+        }
+    }
+}
 
+#[async_trait]
 impl SensorController for RealSensorController {
-    fn activate_sector(&self, sector: u32) -> Result<(), AppError> {
-        let url = format!("http://sensor-system/activate/{}", sector);
-        let response = blocking::get(&url)?;
+    async fn activate_sector(&self, sector: u32) -> Result<(), AppError> {
+        let response = self.client.get(self.url("activate", sector)).send().await?;
         if response.status().is_success() {
             debug!("Sector {} activated successfully.", sector);
             Ok(())
@@ -31,16 +80,66 @@ impl SensorController for RealSensorController {
         }
     }
 
-    fn deactivate_sector(&self, sector: u32) -> Result<(), AppError> {
-        let url = format!("http://sensor-system/deactivate/{}", sector);
-        let response = blocking::get(&url)?;
+    async fn deactivate_sector(&self, sector: u32) -> Result<(), AppError> {
+        let response = self.client.get(self.url("deactivate", sector)).send().await?;
         if response.status().is_success() {
             debug!("Sector {} deactivated successfully.", sector);
             Ok(())
         } else {
-            Err(AppError::SensorError(
-                format!("Failed to deactivate sector {}: {:?}", sector, response.status()),
-            ))
+            Err(AppError::SensorError(format!("Failed to deactivate sector {}: {:?}", sector, response.status())))
         }
     }
+
+    /// A service without a `/state` endpoint can't tell, that is no alarm
+    async fn sector_state(&self, sector: u32) -> Result<ValveState, AppError> {
+        let response = self.client.get(self.url("state", sector)).send().await?;
+        let status = response.status();
+        if matches!(status, StatusCode::NOT_FOUND | StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED) {
+            return Ok(ValveState::Unknown);
+        }
+        if !status.is_success() {
+            return Err(AppError::SensorError(format!("Failed to read sector {}: {:?}", sector, response.status())));
+        }
+        let state = match response.text().await?.trim().to_ascii_lowercase().as_str() {
+            "on" | "open" => ValveState::Open,
+            "off" | "closed" => ValveState::Closed,
+            _ => ValveState::Unknown,
+        };
+        Ok(state)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::SectorAddress;
+
+    #[test]
+    fn sector_urls() {
+        let cfg = HttpSensorCfg {
+            base_url: "http://10.0.0.5:8080/".to_owned(),
+            token: Some("secret".to_owned()),
+            addresses: vec![
+                SectorAddress { sector: 1, address: "front-lawn".to_owned() },
+                SectorAddress { sector: 3, address: "back yard/drip?".to_owned() },
+            ],
+            ..Default::default()
+        };
+        let ctrl = RealSensorController::new(&cfg).unwrap();
+        assert_eq!(ctrl.url("activate", 1), "http://10.0.0.5:8080/activate/front-lawn");
+        assert_eq!(ctrl.url("state", 2), "http://10.0.0.5:8080/state/2");
+        assert_eq!(ctrl.url("activate", 3), "http://10.0.0.5:8080/activate/back%20yard%2Fdrip%3F");
+    }
+
+    #[tokio::test]
+    async fn no_state_endpoint_is_unknown() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let app = axum::Router::new().route("/activate/:sector", axum::routing::get(|| async { "ok" }));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let ctrl = RealSensorController::new(&HttpSensorCfg { base_url, ..Default::default() }).unwrap();
+        assert!(ctrl.activate_sector(1).await.is_ok());
+        assert_eq!(ctrl.sector_state(1).await.unwrap(), ValveState::Unknown);
+    }
 }
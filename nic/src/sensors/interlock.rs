@@ -0,0 +1,124 @@
+use super::interface::{SensorController, ValveState};
+use crate::{config::InterlockCfg, error::AppError};
+use async_trait::async_trait;
+use serde::Serialize;
+use std::{
+    collections::BTreeSet,
+    sync::{Arc, Mutex},
+};
+use tracing::error;
+
+/// Defense in depth against logic bugs opening two zones at once.<br>
+/// Tracks the commanded state of every valve and refuses an activation that would leave more than
+/// `max_open_sectors` sectors open. The master valve is always allowed.
+#[derive(Debug)]
+pub struct Interlock {
+    inner: Arc<dyn SensorController>,
+    cfg: InterlockCfg,
+    /// pump or master valve, doesn't count as a sector
+    master_sector: Option<u32>,
+    /// master valves of the water sources, allowed like the pump
+    source_masters: Vec<u32>,
+    open: Mutex<BTreeSet<u32>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InterlockStatus {
+    /// sectors commanded open, the master valve included
+    pub open: Vec<u32>,
+    pub max_open_sectors: usize,
+    pub master_sector: Option<u32>,
+    pub source_masters: Vec<u32>,
+}
+
+impl Interlock {
+    pub fn new(inner: Arc<dyn SensorController>, cfg: InterlockCfg, master_sector: Option<u32>) -> Self {
+        Self { inner, cfg, master_sector, source_masters: Vec::new(), open: Mutex::new(BTreeSet::new()) }
+    }
+
+    pub fn with_source_masters(mut self, source_masters: Vec<u32>) -> Self {
+        self.source_masters = source_masters;
+        self
+    }
+
+    fn is_master(&self, sector: u32) -> bool {
+        Some(sector) == self.master_sector || self.source_masters.contains(&sector)
+    }
+
+    pub fn status(&self) -> InterlockStatus {
+        InterlockStatus {
+            open: self.open.lock().unwrap().iter().copied().collect(),
+            max_open_sectors: self.cfg.max_open_sectors,
+            master_sector: self.master_sector,
+            source_masters: self.source_masters.clone(),
+        }
+    }
+
+    /// Marks the sector as commanded open, unless that breaks the rules
+    fn claim(&self, sector: u32) -> Result<(), AppError> {
+        let mut open = self.open.lock().unwrap();
+        if !self.is_master(sector) {
+            let others: Vec<u32> = open.iter().copied().filter(|&s| s != sector && !self.is_master(s)).collect();
+            if others.len() >= self.cfg.max_open_sectors {
+                error!(sector_id = sector, open = ?others, "Interlock refused to open the sector.");
+                return Err(AppError::InterlockError(format!(
+                    "Sector {} refused, sectors {:?} are still open",
+                    sector, others
+                )));
+            }
+        }
+        open.insert(sector);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SensorController for Interlock {
+    /// Counted as open even when the command fails, the valve may well be open
+    async fn activate_sector(&self, sector: u32) -> Result<(), AppError> {
+        self.claim(sector)?;
+        self.inner.activate_sector(sector).await
+    }
+
+    /// Always allowed, and counted as closed once commanded
+    async fn deactivate_sector(&self, sector: u32) -> Result<(), AppError> {
+        self.open.lock().unwrap().remove(&sector);
+        self.inner.deactivate_sector(sector).await
+    }
+
+    async fn sector_state(&self, sector: u32) -> Result<ValveState, AppError> {
+        self.inner.sector_state(sector).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::utils::mock_sensors::MockSensorController;
+
+    #[tokio::test]
+    async fn one_sector_and_the_master() {
+        let mut inner = MockSensorController::new();
+        inner.expect_activate_sector().returning(|sector| {
+            assert_ne!(sector, 2, "refused sectors never reach the hardware");
+            Ok(())
+        });
+        inner.expect_deactivate_sector().returning(|_| Ok(()));
+        let interlock = Interlock::new(Arc::new(inner), InterlockCfg::default(), Some(9)).with_source_masters(vec![10]);
+
+        interlock.activate_sector(9).await.unwrap();
+        interlock.activate_sector(10).await.unwrap();
+        interlock.activate_sector(1).await.unwrap();
+        interlock.activate_sector(1).await.unwrap(); // same sector again is fine
+        assert!(matches!(interlock.activate_sector(2).await, Err(AppError::InterlockError(_))));
+        assert_eq!(interlock.status().open, vec![1, 9, 10]);
+
+        interlock.deactivate_sector(1).await.unwrap();
+        interlock.deactivate_sector(10).await.unwrap();
+        interlock.activate_sector(3).await.unwrap();
+        assert_eq!(
+            interlock.status(),
+            InterlockStatus { open: vec![3, 9], max_open_sectors: 1, master_sector: Some(9), source_masters: vec![10] }
+        );
+    }
+}
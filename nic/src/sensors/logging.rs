@@ -0,0 +1,80 @@
+use super::{
+    interface::{SensorController, ValveState},
+    stub::StubSensorController,
+};
+use crate::{db::DatabaseTrait, error::AppError, time::TimeProvider, watering::ds::SystemEvent};
+use async_trait::async_trait;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+/// Dry run: no hardware, every command goes to the log and to the system events as `dry_run_activated` or
+/// `dry_run_deactivated`.<br>
+/// Safe in production, for a trial period of watching what the controller would do.
+#[derive(Debug)]
+pub struct LoggingSensorController {
+    valves: StubSensorController,
+    db: Arc<dyn DatabaseTrait>,
+    time_provider: Arc<dyn TimeProvider>,
+}
+
+impl LoggingSensorController {
+    pub fn new(db: Arc<dyn DatabaseTrait>, time_provider: Arc<dyn TimeProvider>) -> Self {
+        Self { valves: StubSensorController::default(), db, time_provider }
+    }
+
+    fn record(&self, sector: u32, kind: &str) {
+        let timestamp = self.time_provider.now();
+        info!(sector_id = sector, event = kind, "Dry run, the valve was not touched.");
+        let evt = SystemEvent {
+            timestamp,
+            kind: kind.to_owned(),
+            sector: Some(sector),
+            cycle: None,
+            detail: "dry run".to_owned(),
+        };
+        // the command itself can't fail, losing its record only deserves a warning
+        if let Err(e) = self.db.log_system_event(evt) {
+            warn!(sector_id = sector, error = ?e, "Dry run command not recorded.");
+        }
+    }
+}
+
+#[async_trait]
+impl SensorController for LoggingSensorController {
+    async fn activate_sector(&self, sector: u32) -> Result<(), AppError> {
+        self.record(sector, "dry_run_activated");
+        self.valves.activate_sector(sector).await
+    }
+
+    async fn deactivate_sector(&self, sector: u32) -> Result<(), AppError> {
+        self.record(sector, "dry_run_deactivated");
+        self.valves.deactivate_sector(sector).await
+    }
+
+    async fn sector_state(&self, sector: u32) -> Result<ValveState, AppError> {
+        self.valves.sector_state(sector).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::utils::{mock_db::MockDatabase, mock_time::MockTimeProvider};
+
+    #[tokio::test]
+    async fn records_every_command() {
+        let db = Arc::new(MockDatabase::new());
+        let time_provider = Arc::new(MockTimeProvider::new(1_000));
+        let ctrl = LoggingSensorController::new(db.clone(), time_provider.clone());
+
+        ctrl.activate_sector(3).await.unwrap();
+        assert_eq!(ctrl.sector_state(3).await.unwrap(), ValveState::Open);
+        time_provider.set(1_600);
+        ctrl.deactivate_sector(3).await.unwrap();
+        assert_eq!(ctrl.sector_state(3).await.unwrap(), ValveState::Closed);
+
+        let events = db.events.lock().unwrap();
+        let recorded: Vec<_> = events.iter().map(|evt| (evt.timestamp, evt.kind.as_str(), evt.sector)).collect();
+        assert_eq!(recorded, [(1_000, "dry_run_activated", Some(3)), (1_600, "dry_run_deactivated", Some(3))]);
+    }
+}
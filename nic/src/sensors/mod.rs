@@ -1 +1,78 @@
-pub mod interface;
\ No newline at end of file
+#[cfg(feature = "gpio")]
+pub mod gpio;
+pub mod interface;
+pub mod interlock;
+pub mod logging;
+#[cfg(feature = "modbus")]
+pub mod modbus;
+pub mod mqtt_ctrl;
+pub mod retry;
+pub mod router;
+pub mod stub;
+pub mod telemetry;
+pub mod watchdog;
+
+use crate::{
+    config::{Config, SensorBackend},
+    db::DatabaseTrait,
+    error::AppError,
+    links::Links,
+    time::TimeProvider,
+    watering::ds::CtrlSignal,
+};
+use interface::{RealSensorController, SensorController};
+use retry::RetryingController;
+use router::ControllerRouter;
+use std::{collections::HashMap, sync::Arc};
+use tokio::sync::broadcast::Sender;
+
+/// Controller for the backends selected in `[sensors]`, with the retry policy on top.<br>
+/// The database and the clock are for the dry run backend, the links for the MQTT one.
+pub fn build_controller(
+    cfg: &Config, sm_tx: Arc<Sender<CtrlSignal>>, db: Arc<dyn DatabaseTrait>, time_provider: Arc<dyn TimeProvider>,
+    links: Arc<Links>,
+) -> Result<Arc<dyn SensorController>, AppError> {
+    let build_backend = |backend| build_backend(cfg, backend, &db, &time_provider, &links);
+    let default = build_backend(cfg.sensors.backend)?;
+    let backend: Arc<dyn SensorController> = if cfg.sensors.routes.is_empty() {
+        default
+    } else {
+        // one instance per backend, shared by all of its sectors
+        let mut backends = HashMap::from([(cfg.sensors.backend, default.clone())]);
+        let mut routes = HashMap::with_capacity(cfg.sensors.routes.len());
+        for route in cfg.sensors.routes.iter() {
+            let ctrl = match backends.get(&route.backend) {
+                Some(ctrl) => ctrl.clone(),
+                None => build_backend(route.backend)?,
+            };
+            backends.insert(route.backend, ctrl.clone());
+            routes.insert(route.sector, ctrl);
+        }
+        Arc::new(ControllerRouter::new(default, routes))
+    };
+    Ok(Arc::new(RetryingController::new(backend, cfg.sensors.retry, sm_tx)))
+}
+
+fn build_backend(
+    cfg: &Config, backend: SensorBackend, db: &Arc<dyn DatabaseTrait>, time_provider: &Arc<dyn TimeProvider>,
+    links: &Arc<Links>,
+) -> Result<Arc<dyn SensorController>, AppError> {
+    match backend {
+        SensorBackend::Http => Ok(Arc::new(RealSensorController::new(&cfg.sensors.http)?)),
+        SensorBackend::Mqtt => {
+            Ok(Arc::new(mqtt_ctrl::MqttSensorController::new(&cfg.mqtt, &cfg.sensors.mqtt, links.clone())?))
+        }
+        SensorBackend::Stub => Ok(Arc::new(stub::StubSensorController::default())),
+        SensorBackend::Logging => {
+            Ok(Arc::new(logging::LoggingSensorController::new(db.clone(), time_provider.clone())))
+        }
+        #[cfg(feature = "gpio")]
+        SensorBackend::Gpio => Ok(Arc::new(gpio::GpioSensorController::new(&cfg.sensors.gpio)?)),
+        #[cfg(not(feature = "gpio"))]
+        SensorBackend::Gpio => Err(AppError::SensorError("nic was built without the gpio feature".to_owned())),
+        #[cfg(feature = "modbus")]
+        SensorBackend::Modbus => Ok(Arc::new(modbus::ModbusSensorController::new(&cfg.sensors.modbus)?)),
+        #[cfg(not(feature = "modbus"))]
+        SensorBackend::Modbus => Err(AppError::SensorError("nic was built without the modbus feature".to_owned())),
+    }
+}
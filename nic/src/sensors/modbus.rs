@@ -0,0 +1,144 @@
+use super::interface::{SensorController, ValveState};
+use crate::{
+    config::{ModbusCfg, ModbusTransport},
+    error::AppError,
+};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use tokio::sync::{Mutex, MutexGuard};
+use tokio_modbus::{client::Context, prelude::*};
+use tracing::{debug, info, warn};
+
+/// Valves and pumps behind a PLC, one coil per sector.<br>
+/// Connects on the first command and reconnects after any transport error.
+pub struct ModbusSensorController {
+    cfg: ModbusCfg,
+    coils: HashMap<u32, u16>,
+    ctx: Mutex<Option<Context>>,
+}
+
+impl std::fmt::Debug for ModbusSensorController {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ModbusSensorController")
+            .field("cfg", &self.cfg)
+            .field("coils", &self.coils)
+            .finish_non_exhaustive()
+    }
+}
+
+fn modbus_err(e: impl std::fmt::Display) -> AppError {
+    AppError::SensorError(format!("Modbus: {}", e))
+}
+
+impl ModbusSensorController {
+    pub fn new(cfg: &ModbusCfg) -> Result<Self, AppError> {
+        let mut coils = HashMap::with_capacity(cfg.coils.len());
+        for map in cfg.coils.iter() {
+            if coils.insert(map.sector, map.coil).is_some() {
+                return Err(AppError::SensorError(format!("Sector {} mapped to more than one coil", map.sector)));
+            }
+        }
+        info!(transport = ?cfg.transport, address = cfg.address, coils = coils.len(), "Modbus controller ready.");
+        Ok(Self { cfg: cfg.clone(), coils, ctx: Mutex::new(None) })
+    }
+
+    fn coil(&self, sector: u32) -> Result<u16, AppError> {
+        self.coils
+            .get(&sector)
+            .copied()
+            .ok_or_else(|| AppError::SensorError(format!("No coil configured for sector {}", sector)))
+    }
+
+    async fn connect(&self) -> Result<Context, AppError> {
+        let slave = Slave(self.cfg.unit_id);
+        match self.cfg.transport {
+            ModbusTransport::Tcp => {
+                let addr = tokio::net::lookup_host(&self.cfg.address)
+                    .await
+                    .map_err(modbus_err)?
+                    .next()
+                    .ok_or_else(|| modbus_err(format!("can't resolve {}", self.cfg.address)))?;
+                tcp::connect_slave(addr, slave).await.map_err(modbus_err)
+            }
+            ModbusTransport::Rtu => {
+                let builder = tokio_serial::new(&self.cfg.address, self.cfg.baud_rate);
+                let port = tokio_serial::SerialStream::open(&builder).map_err(modbus_err)?;
+                Ok(rtu::attach_slave(port, slave))
+            }
+        }
+    }
+
+    /// Locks the shared connection, connecting first when there is none
+    async fn lock_ctx(&self) -> Result<MutexGuard<'_, Option<Context>>, AppError> {
+        let mut ctx = self.ctx.lock().await;
+        if ctx.is_none() {
+            *ctx = Some(self.connect().await?);
+        }
+        Ok(ctx)
+    }
+
+    async fn set(&self, sector: u32, on: bool) -> Result<(), AppError> {
+        let coil = self.coil(sector)?;
+        let mut ctx = self.lock_ctx().await?;
+        let res = ctx.as_mut().unwrap().write_single_coil(coil, on).await;
+        check(&mut ctx, res)?;
+        debug!(sector_id = sector, coil, on, "Modbus coil set.");
+        Ok(())
+    }
+}
+
+/// Flattens the Modbus result. Transport errors drop the connection, so the next command reconnects.
+fn check<T>(ctx: &mut Option<Context>, res: tokio_modbus::Result<T>) -> Result<T, AppError> {
+    match res {
+        Ok(Ok(value)) => Ok(value),
+        Ok(Err(exception)) => Err(modbus_err(exception)),
+        Err(e) => {
+            warn!(error = ?e, "Modbus connection lost.");
+            *ctx = None;
+            Err(modbus_err(e))
+        }
+    }
+}
+
+#[async_trait]
+impl SensorController for ModbusSensorController {
+    async fn activate_sector(&self, sector: u32) -> Result<(), AppError> {
+        self.set(sector, true).await
+    }
+
+    async fn deactivate_sector(&self, sector: u32) -> Result<(), AppError> {
+        self.set(sector, false).await
+    }
+
+    async fn sector_state(&self, sector: u32) -> Result<ValveState, AppError> {
+        let coil = self.coil(sector)?;
+        let mut ctx = self.lock_ctx().await?;
+        let res = ctx.as_mut().unwrap().read_coils(coil, 1).await;
+        let coils = check(&mut ctx, res)?;
+        Ok(match coils.first() {
+            Some(true) => ValveState::Open,
+            Some(false) => ValveState::Closed,
+            None => ValveState::Unknown,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::CoilMap;
+
+    #[test]
+    fn coil_per_sector() {
+        let mut cfg = ModbusCfg {
+            coils: vec![CoilMap { sector: 1, coil: 0 }, CoilMap { sector: 2, coil: 7 }],
+            ..Default::default()
+        };
+        let ctrl = ModbusSensorController::new(&cfg).unwrap();
+        assert_eq!(ctrl.coil(2).unwrap(), 7);
+        assert!(ctrl.coil(3).is_err());
+
+        cfg.coils.push(CoilMap { sector: 1, coil: 4 });
+        assert!(ModbusSensorController::new(&cfg).is_err());
+    }
+}
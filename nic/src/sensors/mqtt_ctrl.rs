@@ -0,0 +1,180 @@
+use super::interface::{SensorController, ValveState};
+use crate::{
+    config::{MqttCtrlCfg, MQTT},
+    error::AppError,
+    links::Links,
+};
+use async_trait::async_trait;
+use rumqttc::{AsyncClient, ConnAck, Event, MqttOptions, Packet, QoS};
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{sync::Notify, time::Instant};
+use tracing::{debug, info, warn};
+
+pub const SECTOR_PLACEHOLDER: &str = "{sector}";
+
+/// Last state payload reported by each valve, and a wake up for whoever waits on an ack
+#[derive(Debug, Default)]
+struct States {
+    last: Mutex<HashMap<u32, String>>,
+    changed: Notify,
+}
+
+/// Valves behind MQTT relays (Shelly, Tasmota, ...).<br>
+/// Publishes the command on the sector command topic and waits for the relay to report the new state.
+pub struct MqttSensorController {
+    client: AsyncClient,
+    cfg: MqttCtrlCfg,
+    states: Arc<States>,
+}
+
+impl Debug for MqttSensorController {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MqttSensorController").field("cfg", &self.cfg).finish_non_exhaustive()
+    }
+}
+
+pub fn topic_for(template: &str, sector: u32) -> String {
+    template.replace(SECTOR_PLACEHOLDER, &sector.to_string())
+}
+
+pub fn sector_from_topic(template: &str, topic: &str) -> Option<u32> {
+    let (prefix, suffix) = template.split_once(SECTOR_PLACEHOLDER)?;
+    topic.strip_prefix(prefix)?.strip_suffix(suffix)?.parse().ok()
+}
+
+pub(crate) fn broker(address: &str) -> Result<(String, u16), AppError> {
+    let (host, port) = address.rsplit_once(':').unwrap_or((address, "1883"));
+    let port = port.parse().map_err(|_| AppError::MQTTError(format!("Invalid broker address '{}'", address)))?;
+    Ok((host.to_owned(), port))
+}
+
+/// Connection to the `[mqtt]` broker, `role` tells our clients apart
+pub(crate) fn mqtt_options(mqtt: &MQTT, role: &str) -> Result<MqttOptions, AppError> {
+    let (host, port) = broker(&mqtt.address)?;
+    let mut options = MqttOptions::new(format!("{}-{}", mqtt.client_id, role), host, port);
+    if let Some(username) = &mqtt.username {
+        options.set_credentials(username, mqtt.password.expose());
+    }
+    Ok(options)
+}
+
+/// Our clients connect with a clean session, so their subscriptions go with the connection. They are made
+/// again on every connection the broker has no session for, the first one included.
+pub(crate) fn resubscribe(client: &AsyncClient, ack: &ConnAck, topics: &[&str]) {
+    if ack.session_present {
+        return;
+    }
+    for topic in topics {
+        if let Err(e) = client.try_subscribe(*topic, QoS::AtLeastOnce) {
+            warn!(topic, error = ?e, "MQTT subscription not sent.");
+        }
+    }
+}
+
+impl MqttSensorController {
+    /// Must be called from inside the tokio runtime, the event loop runs on its own task
+    pub fn new(mqtt: &MQTT, cfg: &MqttCtrlCfg, links: Arc<Links>) -> Result<Self, AppError> {
+        let mut options = mqtt_options(mqtt, "valves")?;
+        options.set_keep_alive(Duration::from_secs(5));
+        let (client, mut eventloop) = AsyncClient::new(options, 10);
+
+        let subscription = cfg.state_topic.replace(SECTOR_PLACEHOLDER, "+");
+
+        let states = Arc::new(States::default());
+        let (states_clone, template) = (states.clone(), cfg.state_topic.clone());
+        let (client_clone, topic) = (client.clone(), subscription.clone());
+        tokio::spawn(async move {
+            loop {
+                match eventloop.poll().await {
+                    Ok(Event::Incoming(Packet::Publish(publish))) => {
+                        let Some(sector) = sector_from_topic(&template, &publish.topic) else {
+                            continue;
+                        };
+                        let payload = String::from_utf8_lossy(&publish.payload).trim().to_owned();
+                        debug!(sector_id = sector, state = payload, "Valve state.");
+                        states_clone.last.lock().unwrap().insert(sector, payload);
+                        states_clone.changed.notify_waiters();
+                    }
+                    Ok(Event::Incoming(Packet::ConnAck(ack))) => {
+                        resubscribe(&client_clone, &ack, &[&topic]);
+                        links.set("valves", true);
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        // the next poll reconnects
+                        links.set("valves", false);
+                        warn!(error = ?e, "MQTT valve connection error.");
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                    }
+                }
+            }
+        });
+        info!(topic = subscription, "MQTT valve controller ready.");
+        Ok(Self { client, cfg: cfg.clone(), states })
+    }
+
+    async fn set(&self, sector: u32, on: bool) -> Result<(), AppError> {
+        let payload = if on { &self.cfg.on_payload } else { &self.cfg.off_payload };
+        // forget the previous state, so we only accept an ack for this command
+        self.states.last.lock().unwrap().remove(&sector);
+        self.client
+            .publish(topic_for(&self.cfg.command_topic, sector), QoS::AtLeastOnce, false, payload.as_bytes())
+            .await
+            .map_err(|e| AppError::MQTTError(e.to_string()))?;
+
+        let deadline = Instant::now() + Duration::from_millis(self.cfg.ack_timeout_ms);
+        loop {
+            // register before checking, so a state arriving in between isn't missed
+            let changed = self.states.changed.notified();
+            tokio::pin!(changed);
+            changed.as_mut().enable();
+            if self.states.last.lock().unwrap().get(&sector).is_some_and(|state| state.eq_ignore_ascii_case(payload)) {
+                debug!(sector_id = sector, on, "Valve acknowledged.");
+                return Ok(());
+            }
+            if tokio::time::timeout_at(deadline, changed).await.is_err() {
+                return Err(AppError::SensorError(format!("Sector {} did not acknowledge {}", sector, payload)));
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl SensorController for MqttSensorController {
+    async fn activate_sector(&self, sector: u32) -> Result<(), AppError> {
+        self.set(sector, true).await
+    }
+
+    async fn deactivate_sector(&self, sector: u32) -> Result<(), AppError> {
+        self.set(sector, false).await
+    }
+
+    async fn sector_state(&self, sector: u32) -> Result<ValveState, AppError> {
+        let state = match self.states.last.lock().unwrap().get(&sector) {
+            Some(s) if s.eq_ignore_ascii_case(&self.cfg.on_payload) => ValveState::Open,
+            Some(s) if s.eq_ignore_ascii_case(&self.cfg.off_payload) => ValveState::Closed,
+            _ => ValveState::Unknown,
+        };
+        Ok(state)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sector_topics() {
+        assert_eq!(topic_for("cmnd/valve{sector}/POWER", 3), "cmnd/valve3/POWER");
+        assert_eq!(sector_from_topic("stat/valve{sector}/POWER", "stat/valve12/POWER"), Some(12));
+        assert_eq!(sector_from_topic("stat/valve{sector}/POWER", "stat/valve12/RESULT"), None);
+        assert_eq!(sector_from_topic("stat/valve/POWER", "stat/valve/POWER"), None);
+        assert_eq!(broker("localhost:1884").unwrap(), ("localhost".to_owned(), 1884));
+        assert_eq!(broker("broker").unwrap(), ("broker".to_owned(), 1883));
+    }
+}
@@ -0,0 +1,121 @@
+use super::interface::{SensorController, ValveState};
+use crate::{
+    config::RetryCfg,
+    error::AppError,
+    watering::ds::{CtrlSignal, SectorFault, ValveAction},
+};
+use async_trait::async_trait;
+use std::{sync::Arc, time::Duration};
+use tokio::sync::broadcast::Sender;
+use tracing::{error, warn};
+
+/// Wait before the try that follows the `failures`-th failure
+pub fn backoff(cfg: &RetryCfg, failures: u32) -> Duration {
+    let factor = 2u64.saturating_pow(failures.saturating_sub(1));
+    Duration::from_millis(cfg.backoff_ms.saturating_mul(factor).min(cfg.max_backoff_ms))
+}
+
+/// Retries the valve commands of the wrapped controller.<br>
+/// When a command still fails after `attempts` tries, a `CtrlSignal::SectorFault` goes to the state machine, which
+/// aborts the cycle and stops using the sector.
+#[derive(Debug)]
+pub struct RetryingController {
+    inner: Arc<dyn SensorController>,
+    cfg: RetryCfg,
+    sm_tx: Arc<Sender<CtrlSignal>>,
+}
+
+impl RetryingController {
+    pub fn new(inner: Arc<dyn SensorController>, cfg: RetryCfg, sm_tx: Arc<Sender<CtrlSignal>>) -> Self {
+        Self { inner, cfg, sm_tx }
+    }
+
+    async fn run(&self, sector: u32, action: ValveAction) -> Result<(), AppError> {
+        let attempts = self.cfg.attempts.max(1);
+        let mut failures = 0;
+        loop {
+            let res = match action {
+                ValveAction::Open => self.inner.activate_sector(sector).await,
+                ValveAction::Close => self.inner.deactivate_sector(sector).await,
+            };
+            let Err(e) = res else {
+                return Ok(());
+            };
+            failures += 1;
+            if failures >= attempts {
+                error!(sector_id = sector, ?action, error = ?e, "Valve command failed, giving up on the sector.");
+                let fault = SectorFault { sector, action, error: e.to_string() };
+                _ = self.sm_tx.send(CtrlSignal::SectorFault(fault));
+                return Err(e);
+            }
+            let wait = backoff(&self.cfg, failures);
+            warn!(sector_id = sector, ?action, error = ?e, retry_in_ms = wait.as_millis() as u64, "Valve command failed.");
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+#[async_trait]
+impl SensorController for RetryingController {
+    async fn activate_sector(&self, sector: u32) -> Result<(), AppError> {
+        self.run(sector, ValveAction::Open).await
+    }
+
+    async fn deactivate_sector(&self, sector: u32) -> Result<(), AppError> {
+        self.run(sector, ValveAction::Close).await
+    }
+
+    /// Not retried, the state machine polls it anyway
+    async fn sector_state(&self, sector: u32) -> Result<ValveState, AppError> {
+        self.inner.sector_state(sector).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::utils::mock_sensors::MockSensorController;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    const FAST: RetryCfg = RetryCfg { attempts: 3, backoff_ms: 1, max_backoff_ms: 2 };
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let cfg = RetryCfg { attempts: 5, backoff_ms: 500, max_backoff_ms: 1500 };
+        let waits: Vec<u64> = (1..5).map(|n| backoff(&cfg, n).as_millis() as u64).collect();
+        assert_eq!(waits, vec![500, 1000, 1500, 1500]);
+    }
+
+    #[tokio::test]
+    async fn recovers_from_transient_failures() {
+        let calls = Arc::new(AtomicU32::new(0));
+        let calls_clone = calls.clone();
+        let mut mock = MockSensorController::new();
+        mock.expect_activate_sector().returning(move |_| match calls_clone.fetch_add(1, Ordering::Relaxed) {
+            0 | 1 => Err(AppError::SensorError("timeout".to_owned())),
+            _ => Ok(()),
+        });
+        let (sm_tx, mut sm_rx) = tokio::sync::broadcast::channel(4);
+        let ctrl = RetryingController::new(Arc::new(mock), FAST, Arc::new(sm_tx));
+
+        assert!(ctrl.activate_sector(1).await.is_ok());
+        assert_eq!(calls.load(Ordering::Relaxed), 3);
+        assert!(sm_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn escalates_after_last_attempt() {
+        let mut mock = MockSensorController::new();
+        mock.expect_deactivate_sector().times(3).returning(|_| Err(AppError::SensorError("offline".to_owned())));
+        let (sm_tx, mut sm_rx) = tokio::sync::broadcast::channel(4);
+        let ctrl = RetryingController::new(Arc::new(mock), FAST, Arc::new(sm_tx));
+
+        assert!(ctrl.deactivate_sector(2).await.is_err());
+        match sm_rx.try_recv() {
+            Ok(CtrlSignal::SectorFault(fault)) => {
+                assert_eq!((fault.sector, fault.action), (2, ValveAction::Close));
+            }
+            other => panic!("expected a sector fault, got {:?}", other),
+        }
+    }
+}
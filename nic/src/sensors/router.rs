@@ -0,0 +1,61 @@
+use super::interface::{SensorController, ValveState};
+use crate::error::AppError;
+use async_trait::async_trait;
+use std::{collections::HashMap, sync::Arc};
+
+/// Dispatches each sector to its own backend, for installations that mix relays (e.g. GPIO zones and a Wi-Fi
+/// valve hub). Sectors without a route go to the default controller.
+#[derive(Debug)]
+pub struct ControllerRouter {
+    default: Arc<dyn SensorController>,
+    routes: HashMap<u32, Arc<dyn SensorController>>,
+}
+
+impl ControllerRouter {
+    pub fn new(default: Arc<dyn SensorController>, routes: HashMap<u32, Arc<dyn SensorController>>) -> Self {
+        Self { default, routes }
+    }
+
+    fn route(&self, sector: u32) -> &dyn SensorController {
+        self.routes.get(&sector).unwrap_or(&self.default).as_ref()
+    }
+}
+
+#[async_trait]
+impl SensorController for ControllerRouter {
+    async fn activate_sector(&self, sector: u32) -> Result<(), AppError> {
+        self.route(sector).activate_sector(sector).await
+    }
+
+    async fn deactivate_sector(&self, sector: u32) -> Result<(), AppError> {
+        self.route(sector).deactivate_sector(sector).await
+    }
+
+    async fn sector_state(&self, sector: u32) -> Result<ValveState, AppError> {
+        self.route(sector).sector_state(sector).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::utils::mock_sensors::MockSensorController;
+    use mockall::predicate::eq;
+
+    #[tokio::test]
+    async fn routes_by_sector() {
+        let mut default = MockSensorController::new();
+        default.expect_activate_sector().with(eq(1)).times(1).returning(|_| Ok(()));
+        default.expect_sector_state().with(eq(1)).times(1).returning(|_| Ok(ValveState::Open));
+        let mut hub = MockSensorController::new();
+        hub.expect_activate_sector().with(eq(2)).times(1).returning(|_| Ok(()));
+        hub.expect_deactivate_sector().with(eq(2)).times(1).returning(|_| Ok(()));
+
+        let hub: Arc<dyn SensorController> = Arc::new(hub);
+        let router = ControllerRouter::new(Arc::new(default), HashMap::from([(2, hub)]));
+        router.activate_sector(1).await.unwrap();
+        router.activate_sector(2).await.unwrap();
+        router.deactivate_sector(2).await.unwrap();
+        assert_eq!(router.sector_state(1).await.unwrap(), ValveState::Open);
+    }
+}
@@ -0,0 +1,36 @@
+use super::interface::{SensorController, ValveState};
+use crate::error::AppError;
+use async_trait::async_trait;
+use std::{collections::HashMap, sync::Mutex};
+use tracing::info;
+
+/// No hardware at all, logs the commands and reports every valve as commanded.<br>
+/// Used by the simulation and dry-run profiles.
+#[derive(Debug, Default)]
+pub struct StubSensorController {
+    states: Mutex<HashMap<u32, ValveState>>,
+}
+
+impl StubSensorController {
+    fn set(&self, sector: u32, state: ValveState) {
+        info!(sector_id = sector, state = ?state, "Stub valve.");
+        self.states.lock().unwrap().insert(sector, state);
+    }
+}
+
+#[async_trait]
+impl SensorController for StubSensorController {
+    async fn activate_sector(&self, sector: u32) -> Result<(), AppError> {
+        self.set(sector, ValveState::Open);
+        Ok(())
+    }
+
+    async fn deactivate_sector(&self, sector: u32) -> Result<(), AppError> {
+        self.set(sector, ValveState::Closed);
+        Ok(())
+    }
+
+    async fn sector_state(&self, sector: u32) -> Result<ValveState, AppError> {
+        Ok(self.states.lock().unwrap().get(&sector).copied().unwrap_or(ValveState::Closed))
+    }
+}
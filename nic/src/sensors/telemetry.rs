@@ -0,0 +1,162 @@
+use super::mqtt_ctrl::{mqtt_options, resubscribe};
+use crate::{
+    config::{TelemetryCfg, MQTT},
+    db::DatabaseTrait,
+    error::AppError,
+    links::Links,
+    time::TimeProvider,
+    watering::ds::CtrlSignal,
+};
+use rumqttc::{AsyncClient, Event, Packet};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, sync::Arc, time::Duration};
+use tokio::sync::broadcast::Sender;
+use tracing::{debug, error, info, warn};
+
+pub const DEVICE_PLACEHOLDER: &str = "{device}";
+
+/// Last report of a wireless valve or sensor
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DeviceTelemetry {
+    pub device: String,
+    /// Unix UTC timestamp of the report
+    pub timestamp: i64,
+    /// %
+    pub battery: Option<f64>,
+    /// dBm
+    pub rssi: Option<i32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct TelemetryPayload {
+    battery: Option<f64>,
+    rssi: Option<i32>,
+}
+
+pub fn parse_telemetry(template: &str, topic: &str, payload: &[u8], timestamp: i64) -> Option<DeviceTelemetry> {
+    let (prefix, suffix) = template.split_once(DEVICE_PLACEHOLDER)?;
+    let device = topic.strip_prefix(prefix)?.strip_suffix(suffix)?;
+    if device.is_empty() || device.contains('/') {
+        return None;
+    }
+    let payload: TelemetryPayload = serde_json::from_slice(payload).ok()?;
+    Some(DeviceTelemetry { device: device.to_owned(), timestamp, battery: payload.battery, rssi: payload.rssi })
+}
+
+/// Notifies once when a device drops below the threshold, and again only after it went back up (battery replaced)
+#[derive(Debug)]
+pub struct LowBatteryTracker {
+    threshold: f64,
+    low: HashSet<String>,
+}
+
+impl LowBatteryTracker {
+    pub fn new(threshold: f64) -> Self {
+        Self { threshold, low: HashSet::new() }
+    }
+
+    /// true when this report should raise a notification
+    pub fn update(&mut self, t: &DeviceTelemetry) -> bool {
+        match t.battery {
+            Some(battery) if battery < self.threshold => self.low.insert(t.device.clone()),
+            Some(_) => {
+                self.low.remove(&t.device);
+                false
+            }
+            None => false,
+        }
+    }
+}
+
+/// Stores every telemetry report and sends `CtrlSignal::LowBattery` on `web_tx` when a device needs a new battery
+pub async fn monitor_telemetry(
+    mqtt: MQTT, cfg: TelemetryCfg, db: Arc<dyn DatabaseTrait>, web_tx: Sender<CtrlSignal>,
+    time_provider: Arc<dyn TimeProvider>, links: Arc<Links>,
+) -> Result<(), AppError> {
+    let mut options = mqtt_options(&mqtt, "telemetry")?;
+    options.set_keep_alive(Duration::from_secs(30));
+    let (client, mut eventloop) = AsyncClient::new(options, 10);
+    let subscription = cfg.topic.replace(DEVICE_PLACEHOLDER, "+");
+    info!(topic = subscription, "Listening for device telemetry.");
+
+    let mut tracker = LowBatteryTracker::new(cfg.low_battery_pct);
+    loop {
+        let publish = match eventloop.poll().await {
+            Ok(Event::Incoming(Packet::Publish(publish))) => publish,
+            Ok(Event::Incoming(Packet::ConnAck(ack))) => {
+                resubscribe(&client, &ack, &[&subscription]);
+                links.set("telemetry", true);
+                continue;
+            }
+            Ok(_) => continue,
+            Err(e) => {
+                // the next poll reconnects
+                links.set("telemetry", false);
+                warn!(error = ?e, "MQTT telemetry connection error.");
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+        let Some(t) = parse_telemetry(&cfg.topic, &publish.topic, &publish.payload, time_provider.now()) else {
+            warn!(topic = publish.topic, "Invalid telemetry message.");
+            continue;
+        };
+        debug!(device = t.device, battery = ?t.battery, rssi = ?t.rssi, "Device telemetry.");
+        if tracker.update(&t) {
+            warn!(device = t.device, battery = ?t.battery, "Device battery low.");
+            _ = web_tx.send(CtrlSignal::LowBattery(t.clone()));
+        }
+        if let Err(e) = db.store_device_telemetry(t) {
+            error!(error = ?e, "Failed to store device telemetry.");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::Database;
+
+    const TOPIC: &str = "devices/{device}/telemetry";
+
+    #[test]
+    fn parse_reports() {
+        let t = parse_telemetry(TOPIC, "devices/valve-3/telemetry", br#"{"battery": 18.5, "rssi": -71}"#, 100).unwrap();
+        assert_eq!(
+            t,
+            DeviceTelemetry { device: "valve-3".to_owned(), timestamp: 100, battery: Some(18.5), rssi: Some(-71) }
+        );
+        assert_eq!(parse_telemetry(TOPIC, "devices/soil/telemetry", br#"{"rssi": -50}"#, 0).unwrap().battery, None);
+        assert!(parse_telemetry(TOPIC, "devices/a/b/telemetry", b"{}", 0).is_none());
+        assert!(parse_telemetry(TOPIC, "devices/valve-3/state", b"{}", 0).is_none());
+        assert!(parse_telemetry(TOPIC, "devices/valve-3/telemetry", b"low", 0).is_none());
+    }
+
+    #[test]
+    fn low_battery_notifies_once() {
+        let report = |battery| DeviceTelemetry { device: "valve-1".to_owned(), timestamp: 0, battery, rssi: None };
+        let mut tracker = LowBatteryTracker::new(20.);
+        assert!(!tracker.update(&report(Some(25.))));
+        assert!(tracker.update(&report(Some(19.))));
+        assert!(!tracker.update(&report(Some(18.))));
+        assert!(!tracker.update(&report(None)));
+        assert!(!tracker.update(&report(Some(100.)))); // replaced
+        assert!(tracker.update(&report(Some(10.))));
+    }
+
+    #[test]
+    fn last_report_per_device() {
+        let db = Database::new(":memory:").unwrap();
+        let report = |device: &str, timestamp| DeviceTelemetry {
+            device: device.to_owned(),
+            timestamp,
+            battery: Some(50.),
+            rssi: Some(-60),
+        };
+        db.store_device_telemetry(report("b", 1)).unwrap();
+        db.store_device_telemetry(report("a", 1)).unwrap();
+        db.store_device_telemetry(report("b", 2)).unwrap();
+        assert_eq!(db.load_device_telemetry().unwrap(), vec![report("a", 1), report("b", 2)]);
+    }
+}
@@ -0,0 +1,163 @@
+use super::interface::{SensorController, ValveState};
+use crate::{
+    config::WatchdogCfg,
+    error::AppError,
+    time::TimeProvider,
+    watering::ds::{AlarmEvent, CtrlSignal},
+};
+use async_trait::async_trait;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::broadcast::Sender;
+use tracing::{error, info, trace};
+
+const CHECK_INTERVAL_SECS: u64 = 5;
+
+/// Remembers when each sector was commanded off, so a separate task can check the valve really closed.<br>
+/// It runs apart from the state machine loop, so a stuck valve is caught even if the loop is wedged.
+#[derive(Debug)]
+pub struct ValveWatchdog {
+    inner: Arc<dyn SensorController>,
+    cfg: WatchdogCfg,
+    /// pump or master valve shut when a valve is stuck open
+    master_sector: Option<u32>,
+    /// master valves of the water sources, shut along with it
+    source_masters: Vec<u32>,
+    time_provider: Arc<dyn TimeProvider>,
+    closed_at: Mutex<HashMap<u32, i64>>,
+}
+
+impl ValveWatchdog {
+    pub fn new(
+        inner: Arc<dyn SensorController>, cfg: WatchdogCfg, master_sector: Option<u32>,
+        time_provider: Arc<dyn TimeProvider>,
+    ) -> Self {
+        Self {
+            inner,
+            cfg,
+            master_sector,
+            source_masters: Vec::new(),
+            time_provider,
+            closed_at: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_source_masters(mut self, source_masters: Vec<u32>) -> Self {
+        self.source_masters = source_masters;
+        self
+    }
+
+    /// Reads back the valves closed more than `grace_secs` ago. Returns the ones stuck open.
+    pub async fn check(&self, web_tx: &Sender<CtrlSignal>) -> Vec<u32> {
+        let now = self.time_provider.now();
+        let due: Vec<u32> = self
+            .closed_at
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, &at)| now - at >= self.cfg.grace_secs)
+            .map(|(&sector, _)| sector)
+            .collect();
+
+        let mut stuck = Vec::new();
+        for sector in due {
+            match self.inner.sector_state(sector).await {
+                Ok(ValveState::Open) => stuck.push(sector),
+                Ok(_) => {}
+                Err(e) => {
+                    // try again on the next check
+                    trace!(sector_id = sector, error = ?e, "Watchdog read back failed.");
+                    continue;
+                }
+            }
+            self.closed_at.lock().unwrap().remove(&sector);
+        }
+
+        for &sector in stuck.iter() {
+            error!(sector_id = sector, master = ?self.master_sector, "Valve stuck open. Shutting the master valve.");
+            for &master in self.master_sector.iter().chain(&self.source_masters) {
+                if let Err(e) = self.inner.deactivate_sector(master).await {
+                    error!(sector_id = master, error = ?e, "Failed to shut the master valve.");
+                }
+            }
+            // one more try on the valve itself, can't hurt
+            _ = self.inner.deactivate_sector(sector).await;
+            let alarm =
+                AlarmEvent { timestamp: now, sector, expected: ValveState::Closed, actual: Some(ValveState::Open) };
+            _ = web_tx.send(CtrlSignal::StuckValve(alarm));
+        }
+        stuck
+    }
+}
+
+#[async_trait]
+impl SensorController for ValveWatchdog {
+    async fn activate_sector(&self, sector: u32) -> Result<(), AppError> {
+        self.closed_at.lock().unwrap().remove(&sector);
+        self.inner.activate_sector(sector).await
+    }
+
+    /// Watched even when the command failed, the valve may well be open
+    async fn deactivate_sector(&self, sector: u32) -> Result<(), AppError> {
+        let res = self.inner.deactivate_sector(sector).await;
+        if self.cfg.grace_secs > 0 && Some(sector) != self.master_sector {
+            self.closed_at.lock().unwrap().insert(sector, self.time_provider.now());
+        }
+        res
+    }
+
+    async fn sector_state(&self, sector: u32) -> Result<ValveState, AppError> {
+        self.inner.sector_state(sector).await
+    }
+}
+
+pub async fn run_valve_watchdog(watchdog: Arc<ValveWatchdog>, web_tx: Sender<CtrlSignal>) {
+    if watchdog.cfg.grace_secs <= 0 {
+        return;
+    }
+    info!(grace_secs = watchdog.cfg.grace_secs, "Valve watchdog running.");
+    let mut interval = tokio::time::interval(Duration::from_secs(CHECK_INTERVAL_SECS));
+    loop {
+        interval.tick().await;
+        watchdog.check(&web_tx).await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::utils::{mock_sensors::MockSensorController, mock_time::MockTimeProvider};
+    use mockall::predicate::eq;
+
+    #[tokio::test]
+    async fn stuck_valve_shuts_master() {
+        let mut inner = MockSensorController::new();
+        inner.expect_deactivate_sector().returning(|_| Ok(()));
+        inner.expect_activate_sector().returning(|_| Ok(()));
+        inner.expect_sector_state().with(eq(1)).returning(|_| Ok(ValveState::Open));
+        inner.expect_sector_state().with(eq(2)).returning(|_| Ok(ValveState::Closed));
+        let time = Arc::new(MockTimeProvider::new(1000));
+        let watchdog = ValveWatchdog::new(Arc::new(inner), WatchdogCfg { grace_secs: 60 }, Some(9), time.clone())
+            .with_source_masters(vec![10]);
+        let (web_tx, mut web_rx) = tokio::sync::broadcast::channel(4);
+
+        watchdog.deactivate_sector(1).await.unwrap();
+        watchdog.deactivate_sector(2).await.unwrap();
+        watchdog.deactivate_sector(3).await.unwrap();
+        watchdog.activate_sector(3).await.unwrap(); // back on, not watched anymore
+        watchdog.deactivate_sector(9).await.unwrap(); // the master itself isn't watched
+
+        time.set(1059);
+        assert!(watchdog.check(&web_tx).await.is_empty(), "still within the grace period");
+        time.set(1060);
+        assert_eq!(watchdog.check(&web_tx).await, vec![1]);
+        assert!(watchdog.closed_at.lock().unwrap().is_empty());
+        match web_rx.try_recv() {
+            Ok(CtrlSignal::StuckValve(alarm)) => assert_eq!((alarm.sector, alarm.timestamp), (1, 1060)),
+            other => panic!("expected a stuck valve alert, got {:?}", other),
+        }
+    }
+}
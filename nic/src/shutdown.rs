@@ -0,0 +1,110 @@
+use crate::db::DatabaseTrait;
+use std::{sync::Arc, time::Duration};
+use tokio::{signal, sync::watch, task::JoinHandle};
+use tracing::{error, info, warn};
+
+/// How long each task gets to wind down before we stop waiting for it
+pub const SHUTDOWN_TIMEOUT_SECS: u64 = 30;
+
+/// Resolves on Ctrl-C or, on unix, SIGTERM
+pub async fn os_signal() {
+    let ctrl_c = async {
+        signal::ctrl_c().await.expect("failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("failed to install signal handler")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => info!("Ctrl-C received."),
+        _ = terminate => info!("SIGTERM received."),
+    }
+}
+
+/// Waits for the OS to ask us to stop, or for the watering loop or the web server to give up, then shuts down in
+/// order.
+pub async fn coordinate_shutdown(
+    shutdown_tx: watch::Sender<bool>, mut watering: JoinHandle<()>, mut web: JoinHandle<()>, db: Arc<dyn DatabaseTrait>,
+) {
+    tokio::select! {
+        _ = os_signal() => shutdown(shutdown_tx, watering, web, db).await,
+        _ = &mut watering => {
+            error!("Watering loop stopped, shutting down.");
+            shutdown(shutdown_tx, tokio::spawn(async {}), web, db).await;
+        }
+        // without the API nobody can see or control the garden
+        _ = &mut web => {
+            error!("Web server stopped, shutting down.");
+            shutdown(shutdown_tx, watering, tokio::spawn(async {}), db).await;
+        }
+    }
+}
+
+/// The watering loop goes first, so the active sector is closed before anything else goes away.
+/// Then the web server drains, and the DB actor writes out what is queued.
+pub async fn shutdown(
+    shutdown_tx: watch::Sender<bool>, watering: JoinHandle<()>, web: JoinHandle<()>, db: Arc<dyn DatabaseTrait>,
+) {
+    info!("Shutting down...");
+    _ = shutdown_tx.send(true);
+    let timeout = Duration::from_secs(SHUTDOWN_TIMEOUT_SECS);
+    if tokio::time::timeout(timeout, watering).await.is_err() {
+        warn!("Watering loop did not stop in time, valves may be left open.");
+    }
+    if tokio::time::timeout(timeout, web).await.is_err() {
+        warn!("Web server did not stop in time.");
+    }
+    // the actor handles commands in order, so this returns after every pending write
+    if let Err(e) = db.flush() {
+        error!(error = ?e, "Failed to flush the database.");
+    }
+    info!("Shutdown complete.");
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::Database;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[tokio::test]
+    async fn waits_for_the_watering_loop() {
+        let (shutdown_tx, mut shutdown_rx) = watch::channel(false);
+        let closed = Arc::new(AtomicBool::new(false));
+        let closed_clone = closed.clone();
+        let watering = tokio::spawn(async move {
+            _ = shutdown_rx.changed().await;
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            closed_clone.store(true, Ordering::SeqCst);
+        });
+        let web = tokio::spawn(async {});
+        let db = Arc::new(Database::new(":memory:").unwrap());
+
+        shutdown(shutdown_tx, watering, web, db).await;
+        assert!(closed.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn a_dead_web_server_shuts_down() {
+        let (shutdown_tx, mut shutdown_rx) = watch::channel(false);
+        let closed = Arc::new(AtomicBool::new(false));
+        let closed_clone = closed.clone();
+        let watering = tokio::spawn(async move {
+            _ = shutdown_rx.changed().await;
+            closed_clone.store(true, Ordering::SeqCst);
+        });
+        // the bind failed
+        let web = tokio::spawn(async {});
+        let db = Arc::new(Database::new(":memory:").unwrap());
+
+        coordinate_shutdown(shutdown_tx, watering, web, db).await;
+        assert!(closed.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,509 @@
+use crate::{
+    config::{init::example_schedule, manager::ConfigManager, Config, Database as DatabaseCfg, SectorCfg},
+    db::{
+        configure, import_sector_constraints, import_sectors, initialize, load_auto_schedule, save_auto_schedule,
+        store_daily_rollup, Database, DatabaseTrait,
+    },
+    error::AppError,
+    links::Links,
+    sensors::{interlock::Interlock, stub::StubSensorController},
+    time::TimeProvider,
+    utils::{init_broadcast_channels, init_channels, sod},
+    watering::{
+        daily_report::DaySummary,
+        ds::{AppState, CtrlSignal, SectorInfo, WeatherSignal},
+        modes::Mode,
+        water_window::WaterWin,
+        watering_system::{run_watering_system, WateringSystem},
+    },
+    weather::{freshness::WeatherFreshness, model::load_et_model_or_default, rollup::DailyRollup},
+};
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate, Utc};
+use rusqlite::Connection;
+use serde::Deserialize;
+use std::{
+    any::Any,
+    collections::{BTreeMap, VecDeque},
+    fmt::{Display, Write},
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicI64, AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::sync::broadcast::Sender;
+
+/// Tells apart the database copies of the simulations of one process
+static RUNS: AtomicU32 = AtomicU32::new(0);
+
+/// The weather of a `nic simulate` run, and what it should come to, every entry optional
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Scenario {
+    /// YYYY-MM-DD, the next midnight UTC when not given
+    pub start: Option<String>,
+    pub mode: Mode,
+    /// mm, of the days not in `days`
+    pub et: f64,
+    /// mm, of the days not in `days`
+    pub rain: f64,
+    pub days: Vec<ScenarioDay>,
+    pub signals: Vec<ScenarioSignal>,
+    /// the sectors of the run instead of the config ones, on a new database so it only depends on the scenario
+    pub sectors: Vec<SectorCfg>,
+    pub expect: Expectations,
+}
+
+impl Default for Scenario {
+    fn default() -> Self {
+        Self {
+            start: None,
+            mode: Mode::Wizard,
+            et: 5.,
+            rain: 0.,
+            days: vec![],
+            signals: vec![],
+            sectors: vec![],
+            expect: Expectations::default(),
+        }
+    }
+}
+
+/// What a run must come to, checked once it is over
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Expectations {
+    /// no sector waters outside the watering window, false for auto programs that start at any hour
+    pub in_window: bool,
+    /// each sector gets its weekly target over the whole weeks of the run, short of it by at most this share
+    pub weekly_tolerance: Option<f64>,
+    /// over the run
+    pub max_pauses: Option<usize>,
+}
+
+impl Default for Expectations {
+    fn default() -> Self {
+        Self { in_window: true, weekly_tolerance: None, max_pauses: None }
+    }
+}
+
+/// The weather of one day, day 1 is the first one
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct ScenarioDay {
+    pub day: u32,
+    pub et: Option<f64>,
+    pub rain: Option<f64>,
+}
+
+/// A rain or wind signal, as the weather station would send it
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct ScenarioSignal {
+    pub day: u32,
+    /// UTC, HH:MM
+    pub at: String,
+    pub signal: WeatherSignal,
+}
+
+impl Scenario {
+    pub fn load(path: &Path) -> Result<Self, AppError> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| AppError::ConfigError(format!("Can't read {}: {}", path.display(), e)))?;
+        toml::from_str(&content).map_err(|e| AppError::ConfigError(format!("{}: {}", path.display(), e)))
+    }
+
+    fn start(&self, now: i64) -> Result<i64, AppError> {
+        let Some(start) = &self.start else {
+            return Ok(sod(now) + 86_400);
+        };
+        let date = NaiveDate::parse_from_str(start, "%Y-%m-%d")
+            .map_err(|_| AppError::ConfigError(format!("start: '{}' is not YYYY-MM-DD", start)))?;
+        Ok(date.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp())
+    }
+
+    /// The config with the sectors of the scenario, when it has some
+    fn config(&self, cfg: &Config) -> Config {
+        let mut cfg = cfg.clone();
+        if !self.sectors.is_empty() {
+            cfg.sectors = self.sectors.clone();
+            cfg.sector_constraints.clear();
+        }
+        cfg
+    }
+
+    /// (et, rain) of a day, day 0 being the one before the start
+    pub(crate) fn weather(&self, day: u32) -> (f64, f64) {
+        let given = self.days.iter().find(|d| d.day == day);
+        (given.and_then(|d| d.et).unwrap_or(self.et), given.and_then(|d| d.rain).unwrap_or(self.rain))
+    }
+
+    /// The signals as timestamps, oldest first
+    fn timeline(&self, start: i64) -> Result<VecDeque<(i64, WeatherSignal)>, AppError> {
+        let mut timeline = Vec::with_capacity(self.signals.len());
+        for signal in &self.signals {
+            let at = hour(&signal.at)
+                .ok_or_else(|| AppError::ConfigError(format!("signals: '{}' is not HH:MM", signal.at)))?;
+            let day = signal.day.max(1) as i64 - 1;
+            timeline.push((start + day * 86_400 + at, signal.signal.clone()));
+        }
+        timeline.sort_by_key(|(at, _)| *at);
+        Ok(timeline.into())
+    }
+}
+
+/// HH:MM to seconds from the start of the day
+pub(crate) fn hour(value: &str) -> Option<i64> {
+    let (hours, minutes) = value.split_once(':')?;
+    let (hours, minutes): (i64, i64) = (hours.parse().ok()?, minutes.parse().ok()?);
+    ((0..24).contains(&hours) && (0..60).contains(&minutes)).then_some(hours * 3600 + minutes * 60)
+}
+
+/// An expectation of the scenario the run didn't meet
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    /// Unix UTC timestamp of the start of the day, the first one for those of the whole run
+    pub day: i64,
+    pub sector: Option<u32>,
+    pub problem: String,
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "  {}", DateTime::from_timestamp(self.day, 0).unwrap_or_default().format("%Y-%m-%d"))?;
+        if let Some(sector) = self.sector {
+            write!(f, " sector {}", sector)?;
+        }
+        write!(f, ": {}", self.problem)
+    }
+}
+
+/// The per-day summaries of a `nic simulate` run, and what it didn't meet of the scenario
+#[derive(Debug, Clone, Default)]
+pub struct SimulationReport {
+    pub sectors: Vec<SectorInfo>,
+    pub days: Vec<DaySummary>,
+    pub violations: Vec<Violation>,
+}
+
+impl SimulationReport {
+    /// cm, from the sprinkler debit
+    pub fn water(&self, sector: u32, secs: i64) -> f64 {
+        let debit = self.sectors.iter().find(|sec| sec.id == sector).map_or(0., |sec| sec.sprinkler_debit);
+        secs as f64 / 3600. * debit
+    }
+
+    /// The scenario expectations the days don't meet, in the windows of `cfg`
+    pub fn check(&self, cfg: &Config, expect: &Expectations) -> Vec<Violation> {
+        let mut violations = vec![];
+        let Some(first) = self.days.first().map(|day| day.day) else { return violations };
+        let (hour_start, hours) = (cfg.watering.window_start_hour, cfg.watering.window_duration_hours);
+        for day in self.days.iter().filter(|_| expect.in_window) {
+            for &(sector, from, to) in &day.runs {
+                let window = WaterWin::around(from, hour_start, hours, &cfg.window_overrides);
+                if !window.is_within(from) || to > window.day_end_time + 1 {
+                    let (from, to) = (hh_mm(from), hh_mm(to));
+                    let problem = format!("watered from {} to {}, outside the window", from, to);
+                    violations.push(Violation { day: day.day, sector: Some(sector), problem });
+                }
+            }
+        }
+        let weeks = self.days.len() / 7;
+        if let Some(tolerance) = expect.weekly_tolerance.filter(|_| weeks > 0) {
+            for sec in &self.sectors {
+                let secs = self.days[..weeks * 7].iter().filter_map(|day| day.watered.get(&sec.id)).sum();
+                let (water, target) = (self.water(sec.id, secs), sec.weekly_target * weeks as f64);
+                if water < target * (1. - tolerance) {
+                    let problem = format!("{:.2} cm in {} weeks, the target is {:.2} cm", water, weeks, target);
+                    violations.push(Violation { day: first, sector: Some(sec.id), problem });
+                }
+            }
+        }
+        let pauses = self.days.iter().map(|day| day.pauses).sum::<usize>();
+        if let Some(max) = expect.max_pauses.filter(|max| pauses > *max) {
+            let problem = format!("{} pauses, over {}", pauses, max);
+            violations.push(Violation { day: first, sector: None, problem });
+        }
+        violations
+    }
+}
+
+fn hh_mm(timestamp: i64) -> String {
+    DateTime::from_timestamp(timestamp, 0).unwrap_or_default().format("%H:%M").to_string()
+}
+
+impl Display for SimulationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut totals: BTreeMap<u32, i64> = BTreeMap::new();
+        for day in &self.days {
+            let date = DateTime::from_timestamp(day.day, 0).unwrap_or_default();
+            let mut line = format!("{}  cycles {}  pauses {}", date.format("%Y-%m-%d %a"), day.cycles, day.pauses);
+            for (sector, secs) in &day.watered {
+                *totals.entry(*sector).or_default() += secs;
+                _ = write!(line, "  sector {}: {} min {:.2} cm", sector, secs / 60, self.water(*sector, *secs));
+            }
+            writeln!(f, "{}", line)?;
+        }
+        let weeks = self.days.len() as f64 / 7.;
+        write!(f, "total")?;
+        for sec in &self.sectors {
+            let water = self.water(sec.id, totals.get(&sec.id).copied().unwrap_or(0));
+            let target = sec.weekly_target * weeks;
+            write!(f, "\n  sector {:>3} {:<20} {:>7.2} cm of {:>7.2} cm", sec.id, sec.name, water, target)?;
+        }
+        if !self.violations.is_empty() {
+            write!(f, "\nnot as expected")?;
+            for violation in &self.violations {
+                write!(f, "\n{}", violation)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The clock of a simulation: jumps from wakeup to wakeup, stopping at the scenario signals to send them.<br>
+/// With a `speed`, every jump also takes its simulated time divided by it.
+#[derive(Debug)]
+struct ScenarioClock {
+    now: AtomicI64,
+    speed: Option<f64>,
+    signals: Mutex<VecDeque<(i64, WeatherSignal)>>,
+    sm_tx: Arc<Sender<CtrlSignal>>,
+}
+
+impl ScenarioClock {
+    /// Sends the signals that are due
+    fn send_due(&self) {
+        let now = self.now();
+        let mut signals = self.signals.lock().unwrap();
+        while signals.front().is_some_and(|(at, _)| *at <= now) {
+            let (_, signal) = signals.pop_front().unwrap();
+            _ = self.sm_tx.send(CtrlSignal::Weather(signal));
+        }
+    }
+}
+
+#[async_trait]
+impl TimeProvider for ScenarioClock {
+    fn now(&self) -> i64 {
+        self.now.load(Ordering::SeqCst)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        self.sleep_until(self.now() + duration.as_secs() as i64).await;
+    }
+
+    async fn sleep_until(&self, time: i64) {
+        let next_signal = self.signals.lock().unwrap().front().map(|(at, _)| *at);
+        let time = next_signal.map_or(time, |at| time.min(at));
+        match self.speed {
+            Some(speed) => tokio::time::sleep(Duration::from_secs_f64((time - self.now()).max(0) as f64 / speed)).await,
+            // the other tasks get a chance to run, as a real sleep would
+            None => tokio::task::yield_now().await,
+        }
+        self.now.fetch_max(time, Ordering::SeqCst);
+        self.send_due();
+    }
+
+    async fn advance_time(&self, seconds: i64) {
+        self.sleep_until(self.now() + seconds).await;
+    }
+
+    fn set(&self, time: i64) {
+        self.now.store(time, Ordering::SeqCst);
+    }
+}
+
+/// Runs the watering loop for `days` on a copy of the database, with stub valves and the scenario weather, then
+/// checks the expectations of the scenario.<br>
+/// The copy is thrown away, so the real progress and history are left alone.
+pub async fn simulate(
+    cfg: &Config, scenario: &Scenario, days: u32, speed: Option<f64>,
+) -> Result<SimulationReport, AppError> {
+    let cfg = &scenario.config(cfg);
+    let start = scenario.start(Utc::now().timestamp())?;
+    let end = start + days as i64 * 86_400;
+    let run_id = RUNS.fetch_add(1, Ordering::SeqCst);
+    let db_file = std::env::temp_dir().join(format!("nic-simulate-{}-{}.db", std::process::id(), run_id));
+    prepare_db(cfg, scenario, &db_file, start, days)?;
+    let result = run(cfg, scenario, &db_file, start, end, speed).await;
+    _ = fs::remove_file(&db_file);
+    let mut report = result?;
+    report.violations = report.check(cfg, &scenario.expect);
+    Ok(report)
+}
+
+/// The database settings of the config, and its key, for the copy
+fn copy_cfg(cfg: &Config, db_file: &Path) -> DatabaseCfg {
+    DatabaseCfg { name: db_file.to_string_lossy().into_owned(), ..cfg.database.clone() }
+}
+
+/// The config database, or a new one, with the config sectors, an auto schedule and the scenario weather
+fn prepare_db(cfg: &Config, scenario: &Scenario, db_file: &PathBuf, start: i64, days: u32) -> Result<(), AppError> {
+    if scenario.sectors.is_empty() && Path::new(&cfg.database.name).exists() {
+        fs::copy(&cfg.database.name, db_file)
+            .map_err(|e| AppError::ConfigError(format!("Can't copy {}: {}", cfg.database.name, e)))?;
+    } else {
+        _ = fs::remove_file(db_file);
+    }
+    let mut conn = Connection::open(db_file)?;
+    configure(&conn, &copy_cfg(cfg, db_file))?;
+    initialize(&conn)?;
+    let site = &cfg.site.id;
+    import_sectors(&mut conn, site, &cfg.sectors)?;
+    import_sector_constraints(&mut conn, site, &cfg.sector_constraints)?;
+    if load_auto_schedule(&conn, site)?.entries.is_empty() {
+        save_auto_schedule(&mut conn, site, &example_schedule(cfg))?;
+    }
+    // the rollup of a day drives the adjustments of the next one
+    for day in 0..=days {
+        let (et, rain) = scenario.weather(day);
+        let timestamp = start + (day as i64 - 1) * 86_400;
+        store_daily_rollup(&conn, &DailyRollup { timestamp, hours: 24, et, rain, ..Default::default() })?;
+    }
+    Ok(())
+}
+
+async fn run(
+    cfg: &Config, scenario: &Scenario, db_file: &Path, start: i64, end: i64, speed: Option<f64>,
+) -> Result<SimulationReport, AppError> {
+    let db = Arc::new(Database::open(&copy_cfg(cfg, db_file), &cfg.site.id)?);
+    let (sm_tx, sm_rx) = init_channels();
+    let (web_tx, web_rx) = init_broadcast_channels();
+    let clock = Arc::new(ScenarioClock {
+        now: AtomicI64::new(start),
+        speed: speed.filter(|speed| *speed > 0.),
+        signals: Mutex::new(scenario.timeline(start)?),
+        sm_tx: sm_tx.clone(),
+    });
+    let stub = Arc::new(StubSensorController::default());
+    let interlock = Arc::new(
+        Interlock::new(stub, cfg.sensors.interlock, cfg.watering.pump_sector).with_source_masters(cfg.source_masters()),
+    );
+    let config = Arc::new(ConfigManager::new(db_file.to_path_buf(), cfg.clone(), sm_tx.clone()));
+    let links = Arc::new(Links::new(clock.clone()));
+    let app_state = AppState::new(
+        db.clone(),
+        interlock,
+        clock,
+        sm_tx,
+        sm_rx,
+        web_tx,
+        web_rx,
+        load_et_model_or_default(&cfg.weather_station),
+        Arc::new(WeatherFreshness::disabled()),
+        config,
+        links,
+    )
+    .await?;
+
+    let mut ws = WateringSystem::new(app_state.clone(), Some(scenario.mode), start, cfg.watering)?;
+    ws.sm.pause_policy = cfg.pause_policy;
+    ws.sm.sources = cfg.sources.clone();
+    ws.sm.restrictions = cfg.restrictions.clone();
+    let (_stop_tx, stop_rx) = tokio::sync::watch::channel(false);
+    run_watering_system(app_state, Some(scenario.mode), stop_rx, Some(end), Some(&mut ws), cfg.watering).await?;
+
+    let mut report = SimulationReport { sectors: db.load_sectors()?, ..Default::default() };
+    report.sectors.sort_by_key(|sec| sec.id);
+    for day in (start..end).step_by(86_400) {
+        report.days.push(DaySummary::from_events(day, &db.load_system_events(day, day + 86_400)?));
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::SectorCfg;
+
+    #[test]
+    fn checks_the_expectations() {
+        let day = 1_717_372_800; // 2024-06-03
+        let sector = |id| SectorInfo { id, sprinkler_debit: 1., weekly_target: 1., ..Default::default() };
+        let mut report = SimulationReport { sectors: vec![sector(1), sector(2)], ..Default::default() };
+        for n in 0..7 {
+            let start = day + n * 86_400 + 22 * 3600;
+            let runs = vec![(1, start, start + 1800), (2, start + 1800, start + 2100)];
+            let watered = BTreeMap::from([(1, 1800), (2, 300)]);
+            report.days.push(DaySummary { day: day + n * 86_400, runs, watered, ..Default::default() });
+        }
+        // the window closes at 06:00, and an hour of it is the day's pause
+        report.days[1].runs.push((1, day + 86_400 + 6 * 3600, day + 86_400 + 7 * 3600));
+        report.days[1].pauses = 1;
+        let expect = Expectations { weekly_tolerance: Some(0.1), max_pauses: Some(0), ..Default::default() };
+
+        let problems: Vec<_> = report.check(&Config::default(), &expect).iter().map(ToString::to_string).collect();
+        assert_eq!(
+            problems,
+            [
+                "  2024-06-04 sector 1: watered from 06:00 to 07:00, outside the window",
+                "  2024-06-03 sector 2: 0.58 cm in 1 weeks, the target is 1.00 cm",
+                "  2024-06-03: 1 pauses, over 0"
+            ]
+        );
+        assert!(report.check(&Config::default(), &Expectations { in_window: false, ..Default::default() }).is_empty());
+    }
+
+    #[test]
+    fn scenario_weather() {
+        let scenario: Scenario = toml::from_str(
+            r#"start = "2024-06-03"
+               rain = 1.0
+               [[days]]
+               day = 2
+               rain = 12.0
+               [[signals]]
+               day = 2
+               at = "22:40"
+               signal = "rain_start"
+               [[sectors]]
+               id = 3
+               sprinkler_debit = 1.6
+               percolation_rate = 0.29
+               weekly_target = 2.5
+               max_duration = 1800
+               [expect]
+               weekly_tolerance = 0.1"#,
+        )
+        .unwrap();
+        let start = scenario.start(0).unwrap();
+        assert_eq!(DateTime::from_timestamp(start, 0).unwrap().to_rfc3339(), "2024-06-03T00:00:00+00:00");
+        assert_eq!(scenario.mode, Mode::Wizard);
+        assert_eq!(scenario.weather(1), (5., 1.));
+        assert_eq!(scenario.weather(2), (5., 12.));
+        assert_eq!(scenario.timeline(start).unwrap(), [(start + 86_400 + 22 * 3600 + 2400, WeatherSignal::RainStart)]);
+        assert_eq!(scenario.config(&Config::default()).sectors[0].id, 3);
+        assert_eq!(scenario.expect, Expectations { weekly_tolerance: Some(0.1), ..Default::default() });
+    }
+
+    #[tokio::test]
+    async fn waters_the_config_sectors() {
+        let mut cfg = Config::default();
+        cfg.database.name = "/nonexistent/nic.db".to_owned();
+        cfg.sectors = vec![SectorCfg {
+            id: 1,
+            name: "lawn".to_owned(),
+            sprinkler_debit: 1.6,
+            percolation_rate: 0.29,
+            weekly_target: 2.5,
+            max_duration: 1800,
+            ignore_weather_pause: false,
+            max_daily_mm: None,
+            max_daily_minutes: None,
+            deficit_exempt: false,
+        }];
+        let scenario = Scenario { start: Some("2024-06-03".to_owned()), ..Default::default() };
+
+        let report = simulate(&cfg, &scenario, 7, None).await.unwrap();
+        assert_eq!(report.days.len(), 7);
+        assert!(report.days.iter().any(|day| day.cycles > 0));
+        assert!(report.days.iter().map(|day| day.watered.get(&1).copied().unwrap_or(0)).sum::<i64>() > 0);
+        assert!(report.to_string().starts_with("2024-06-03 Mon  cycles"));
+    }
+}
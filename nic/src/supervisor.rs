@@ -0,0 +1,202 @@
+use crate::{error::Backoff, time::TimeProvider};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fmt::Debug,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::task::JoinHandle;
+use tracing::{error, warn};
+
+/// A task that ran this long had a good run, the next restart waits the minimum again
+const GOOD_RUN: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskState {
+    Running,
+    /// waiting out the backoff
+    Restarting,
+    /// not restarted, the watering loop only
+    Stopped,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TaskStatus {
+    pub state: TaskState,
+    pub restarts: u32,
+    /// why it last stopped
+    pub last_error: Option<String>,
+    /// Unix UTC timestamp of the last change
+    pub since: i64,
+}
+
+/// How a task ended, for the tasks that return nothing and the ones that return an error
+pub trait TaskExit {
+    fn error(self) -> Option<String>;
+}
+
+impl TaskExit for () {
+    fn error(self) -> Option<String> {
+        None
+    }
+}
+
+impl<E: Debug> TaskExit for Result<(), E> {
+    fn error(self) -> Option<String> {
+        self.err().map(|e| format!("{:?}", e))
+    }
+}
+
+/// Owns the background tasks: they are restarted with backoff when they panic or return, and their status is
+/// kept for `/healthz`.<br>
+/// None of them is meant to return, so the ones with nothing to do aren't handed to it.
+#[derive(Debug)]
+pub struct Supervisor {
+    tasks: Mutex<BTreeMap<&'static str, TaskStatus>>,
+    time_provider: Arc<dyn TimeProvider>,
+    min_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl Supervisor {
+    pub fn new(time_provider: Arc<dyn TimeProvider>) -> Self {
+        Self {
+            tasks: Mutex::default(),
+            time_provider,
+            min_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+        }
+    }
+
+    pub fn with_backoff(mut self, min: Duration, max: Duration) -> Self {
+        self.min_backoff = min;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Runs what `task` builds, and builds it again each time it ends
+    pub fn spawn<F, Fut>(self: &Arc<Self>, name: &'static str, task: F) -> JoinHandle<()>
+    where
+        F: Fn() -> Fut + Send + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: TaskExit + Send,
+    {
+        let supervisor = self.clone();
+        tokio::spawn(async move {
+            let mut backoff = Backoff::new(supervisor.min_backoff, supervisor.max_backoff);
+            let mut restarts = 0;
+            loop {
+                supervisor.set(name, TaskState::Running, restarts, None);
+                let started = Instant::now();
+                let reason = exit_reason(tokio::spawn(task()).await);
+                if started.elapsed() > GOOD_RUN {
+                    backoff.reset();
+                }
+                let delay = backoff.next_delay();
+                warn!(task = name, reason, retry_in = ?delay, "Background task stopped, restarting.");
+                supervisor.set(name, TaskState::Restarting, restarts, Some(reason));
+                tokio::time::sleep(delay).await;
+                restarts += 1;
+            }
+        })
+    }
+
+    /// Not restarted: the watering loop's state went with it, and a server that can't bind won't the next time
+    /// either. The handle ends when it does, for the shutdown.
+    pub fn spawn_critical<Fut>(self: &Arc<Self>, name: &'static str, task: Fut) -> JoinHandle<()>
+    where
+        Fut: Future + Send + 'static,
+        Fut::Output: TaskExit + Send,
+    {
+        let supervisor = self.clone();
+        supervisor.set(name, TaskState::Running, 0, None);
+        tokio::spawn(async move {
+            let reason = exit_reason(tokio::spawn(task).await);
+            error!(task = name, reason, "Critical task stopped.");
+            supervisor.set(name, TaskState::Stopped, 0, Some(reason));
+        })
+    }
+
+    fn set(&self, name: &'static str, state: TaskState, restarts: u32, last_error: Option<String>) {
+        let mut tasks = self.tasks.lock().unwrap();
+        let last_error = last_error.or_else(|| tasks.get(name).and_then(|task| task.last_error.clone()));
+        tasks.insert(name, TaskStatus { state, restarts, last_error, since: self.time_provider.now() });
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<&'static str, TaskStatus> {
+        self.tasks.lock().unwrap().clone()
+    }
+
+    /// Every task running
+    pub fn healthy(&self) -> bool {
+        self.tasks.lock().unwrap().values().all(|task| task.state == TaskState::Running)
+    }
+}
+
+fn exit_reason<T: TaskExit>(exit: Result<T, tokio::task::JoinError>) -> String {
+    match exit {
+        Ok(out) => out.error().unwrap_or_else(|| "returned".to_owned()),
+        Err(e) if e.is_panic() => {
+            let panic = e.into_panic();
+            let msg = panic
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_default();
+            format!("panicked: {}", msg)
+        }
+        Err(_) => "cancelled".to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::utils::mock_time::MockTimeProvider;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn supervisor() -> Arc<Supervisor> {
+        let time_provider = Arc::new(MockTimeProvider::new(100));
+        Arc::new(Supervisor::new(time_provider).with_backoff(Duration::from_millis(1), Duration::from_millis(5)))
+    }
+
+    #[tokio::test]
+    async fn restarts_after_a_panic_or_an_error() {
+        let supervisor = supervisor();
+        let runs = Arc::new(AtomicU32::new(0));
+        let runs_clone = runs.clone();
+        supervisor.spawn("flaky", move || {
+            let runs = runs_clone.clone();
+            async move {
+                match runs.fetch_add(1, Ordering::SeqCst) {
+                    0 => panic!("boom"),
+                    1 => Err("lost the broker"),
+                    _ => std::future::pending().await,
+                }
+            }
+        });
+        while runs.load(Ordering::SeqCst) < 3 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+
+        let task = &supervisor.snapshot()["flaky"];
+        assert_eq!((task.state, task.restarts), (TaskState::Running, 2));
+        assert_eq!(task.last_error.as_deref(), Some("\"lost the broker\""));
+        assert!(supervisor.healthy());
+    }
+
+    #[tokio::test]
+    async fn a_stopped_critical_task_ends_its_handle() {
+        let supervisor = supervisor();
+        let handle = supervisor.spawn_critical("watering", async { Err::<(), _>("db gone") });
+        handle.await.unwrap();
+
+        let task = &supervisor.snapshot()["watering"];
+        assert_eq!(task.state, TaskState::Stopped);
+        assert_eq!(task.last_error.as_deref(), Some("\"db gone\""));
+        assert!(!supervisor.healthy());
+    }
+}
@@ -0,0 +1,142 @@
+//! A real database in memory, seeded in one call. The `MockDatabase` answers what each test taught it and drifts
+//! from the schema, a fixture goes through the same SQL as the controller.
+
+use crate::{
+    config::SectorCfg,
+    db::{Database, DatabaseTrait},
+    test::utils::{mock_db::new_with_mock, mock_sensors::set_sensor_controller0, mock_time::MockTimeProvider},
+    utils::sod,
+    watering::{
+        ds::{AppState, WeatherConditions},
+        watering_alg::Schedule,
+    },
+    weather::{
+        generator::{SyntheticWeather, WeatherGenerator},
+        rollup::{run_rollup, DAY_SECS},
+    },
+};
+use std::sync::Arc;
+
+/// A cm an hour, 2.5 cm a week, up to half an hour a run
+pub fn sector(id: u32) -> SectorCfg {
+    SectorCfg {
+        id,
+        name: format!("zone {}", id),
+        sprinkler_debit: 1.0,
+        percolation_rate: 0.5,
+        weekly_target: 2.5,
+        max_duration: 1800,
+        ignore_weather_pause: false,
+        max_daily_mm: None,
+        max_daily_minutes: None,
+        deficit_exempt: false,
+    }
+}
+
+/// `Fixture::new().with_sectors([sector(1), sector(2)]).with_weather_days(7, now).app_state(now)`
+#[derive(Debug, Default)]
+pub struct Fixture {
+    sectors: Vec<SectorCfg>,
+    schedule: Option<Schedule>,
+    weather: Vec<WeatherConditions>,
+}
+
+impl Fixture {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_sectors(mut self, sectors: impl IntoIterator<Item = SectorCfg>) -> Self {
+        self.sectors.extend(sectors);
+        self
+    }
+
+    /// The auto schedule, its sectors must be there
+    pub fn with_schedule(mut self, schedule: Schedule) -> Self {
+        self.schedule = Some(schedule);
+        self
+    }
+
+    pub fn with_weather(mut self, observations: impl IntoIterator<Item = WeatherConditions>) -> Self {
+        self.weather.extend(observations);
+        self
+    }
+
+    /// The synthetic weather of the `days` before the day of `now`, an observation every 10 minutes
+    pub fn with_weather_days(self, days: u32, now: i64) -> Self {
+        let weather = SyntheticWeather { hours: days * 24, every_secs: 600, ..Default::default() };
+        self.with_weather(WeatherGenerator::new(weather, sod(now) - i64::from(days) * DAY_SECS))
+    }
+
+    /// The database, with the days of the weather rolled up as the controller does after midnight
+    pub fn build(self) -> Arc<Database> {
+        let db = Arc::new(Database::new(":memory:").unwrap());
+        db.import_sectors(self.sectors).unwrap();
+        if let Some(schedule) = self.schedule {
+            db.save_auto_schedule(schedule).unwrap();
+        }
+        let days: Vec<i64> = self.weather.iter().map(|obs| sod(obs.timestamp)).collect();
+        for obs in self.weather {
+            db.log_weather(obs).unwrap();
+        }
+        if let (Some(&first), Some(&last)) = (days.iter().min(), days.iter().max()) {
+            for day in (first..=last).step_by(DAY_SECS as usize) {
+                run_rollup(db.as_ref(), day + DAY_SECS);
+            }
+        }
+        db
+    }
+
+    /// Its database behind the mock sensors and a clock stopped at `now`
+    pub fn app_state(self, now: i64) -> Arc<AppState> {
+        new_with_mock(self.build(), set_sensor_controller0(), Arc::new(MockTimeProvider::new(now))).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::watering::{
+        ds::{DailyPlan, WaterSector},
+        watering_alg::{ScheduleEntry, ScheduleType, DEFAULT_PROGRAM},
+    };
+    use chrono::Weekday;
+
+    const NOON: i64 = 1_717_416_000; // 2024-06-03 12:00
+
+    #[test]
+    fn seeds_it_in_one_call() {
+        let run = WaterSector::new(2, 22 * 3600, 900);
+        let entry = ScheduleEntry {
+            program: DEFAULT_PROGRAM.to_owned(),
+            schedule_type: ScheduleType::Weekday(Weekday::Mon),
+            start_times: DailyPlan(vec![run]),
+            cron: None,
+        };
+        let app_state = Fixture::new()
+            .with_sectors([sector(1), sector(2)])
+            .with_schedule(Schedule::new(vec![entry]))
+            .with_weather_days(3, NOON)
+            .app_state(NOON);
+        let db = &app_state.db;
+
+        assert_eq!(db.load_sectors().unwrap().len(), 2);
+        let schedule = db.load_auto_schedule().unwrap();
+        assert_eq!(schedule.entries.len(), 1);
+        assert_eq!(schedule.entries[0].start_times.0, [run]);
+        let observations = db.load_observations(sod(NOON) - 3 * DAY_SECS, sod(NOON)).unwrap();
+        assert_eq!(observations.len(), 3 * 24 * 6);
+        // the days are rolled up, so the ET of yesterday is there
+        assert!(db.get_daily_et(NOON).is_some_and(|et| et > 0.));
+        assert!(db.get_avg_daily_et(sod(NOON) - 3 * DAY_SECS, sod(NOON)).is_some());
+    }
+
+    #[test]
+    fn each_build_is_a_database_of_its_own() {
+        let first = Fixture::new().with_sectors([sector(1)]).build();
+        let second = Fixture::new().build();
+        assert_eq!(first.load_sectors().unwrap().len(), 1);
+        assert!(second.load_sectors().unwrap().is_empty());
+        assert!(first.readers.is_none());
+    }
+}
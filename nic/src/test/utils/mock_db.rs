@@ -1,10 +1,25 @@
-use crate::db::{DatabaseCommand, DatabaseTrait};
+use crate::config::{manager::ConfigManager, run_options::default_cfg_file, InterlockCfg, SectorCfg};
+use crate::db::{maintenance::DbCheck, DatabaseCommand, DatabaseTrait};
 use crate::error::AppError;
-use crate::sensors::interface::SensorController;
+use crate::links::Links;
+use crate::sensors::{interface::SensorController, interlock::Interlock, telemetry::DeviceTelemetry};
+use crate::supervisor::Supervisor;
+use crate::test::utils::mock_cfg::mock_cfg;
 use crate::time::TimeProvider;
 use crate::utils::{init_broadcast_channels, init_channels, sod};
-use crate::watering::ds::{AppState, Cycle, DailyPlan, SectorInfo, WaterSector, WateringEvent, WeatherConditions};
-use crate::watering::watering_alg::{Schedule, ScheduleEntry, ScheduleType};
+use crate::watering::daily_report::DailyReport;
+use crate::watering::ds::{
+    AppState, AuditEntry, Cycle, CycleRun, DailyPlan, SectorInfo, SourceUsage, SystemEvent, WaterSector, WateringEvent,
+    WeatherConditions,
+};
+use crate::watering::learning::{LearnedParams, LearnedStatus, MoistureReading};
+use crate::watering::modes::Mode;
+use crate::watering::state_machine::ResumePoint;
+use crate::watering::watering_alg::{Schedule, ScheduleEntry, ScheduleType, SectorConstraint, SectorTarget};
+use crate::weather::forecast::HourlyForecast;
+use crate::weather::freshness::WeatherFreshness;
+use crate::weather::model::DefaultEtModel;
+use crate::weather::rollup::{DailyRollup, HourlyRollup};
 use async_trait::async_trait;
 use chrono::Weekday;
 use rusqlite::Result;
@@ -17,7 +32,30 @@ pub fn new_with_mock(
 ) -> Result<Arc<AppState>, AppError> {
     let (sm_tx, sm_rx) = init_channels();
     let (web_tx, web_rx) = init_broadcast_channels();
-    Ok(Arc::new(AppState { db, sm_tx, sm_rx, web_tx, web_rx, sensors_ctrl, time_provider }))
+    let et_model = Arc::new(DefaultEtModel);
+    let freshness = Arc::new(WeatherFreshness::disabled());
+    let interlock = Arc::new(Interlock::new(sensors_ctrl, InterlockCfg::default(), None));
+    let sensors_ctrl = interlock.clone();
+    let config = Arc::new(ConfigManager::new(default_cfg_file(), mock_cfg(), sm_tx.clone()));
+    let links = Arc::new(Links::new(time_provider.clone()));
+    let supervisor = Arc::new(Supervisor::new(time_provider.clone()));
+    Ok(Arc::new(AppState {
+        db,
+        sm_tx,
+        sm_rx,
+        web_tx,
+        web_rx,
+        sensors_ctrl,
+        interlock,
+        config,
+        time_provider,
+        et_model,
+        freshness,
+        links,
+        supervisor,
+        db_health: Arc::default(),
+        clock: Arc::default(),
+    }))
 }
 
 #[derive(Clone, Debug)]
@@ -26,6 +64,18 @@ pub struct MockDatabase {
     pub data: Arc<Mutex<HashMap<String, String>>>, // Simulates database storage
     pub et_data: HashMap<i64, f64>,
     pub rain_data: HashMap<i64, f64>,
+    pub events: Arc<Mutex<Vec<SystemEvent>>>, // Kept so tests can check the audit trail
+    pub audit: Arc<Mutex<Vec<AuditEntry>>>,
+    /// per day, as added
+    pub usage: Arc<Mutex<Vec<(i64, SourceUsage)>>>,
+    pub constraints: Arc<Mutex<Vec<SectorConstraint>>>,
+    pub targets: Arc<Mutex<Vec<SectorTarget>>>,
+    pub moisture: Arc<Mutex<Vec<MoistureReading>>>,
+    pub learned: Arc<Mutex<Vec<LearnedParams>>>,
+    pub wizard_plans: Arc<Mutex<Vec<WaterSector>>>,
+    pub cycle_runs: Arc<Mutex<Vec<CycleRun>>>,
+    /// the calibrated debits, as set
+    pub debits: Arc<Mutex<Vec<(u32, f64)>>>,
 }
 
 impl MockDatabase {
@@ -72,6 +122,10 @@ impl MockDatabase {
                         let weather = mock_weather();
                         let _ = response.send(Some(weather));
                     }
+                    DatabaseCommand::LogWeather { obs, response } => {
+                        println!("Mock log weather: {:?}", obs);
+                        let _ = response.send(Ok(()));
+                    }
                     DatabaseCommand::GetLastdayRain { response, .. } => {
                         println!("Mock get last day rain");
                         let _ = response.send(Some(1.));
@@ -85,11 +139,29 @@ impl MockDatabase {
                         let entries = mock_schedule();
                         let _ = response.send(Ok(Schedule::new(entries)));
                     }
+                    // newer commands are only served through the DatabaseTrait impl below, dropping their
+                    // response would leave the caller with a bare `RecvError`
+                    command => panic!("Mock database got {}, only served through DatabaseTrait", command.name()),
                 }
             }
         });
 
-        MockDatabase { sender: tx, data, et_data: HashMap::new(), rain_data: HashMap::new() }
+        MockDatabase {
+            sender: tx,
+            data,
+            et_data: HashMap::new(),
+            rain_data: HashMap::new(),
+            events: Arc::default(),
+            audit: Arc::default(),
+            usage: Arc::default(),
+            constraints: Arc::default(),
+            targets: Arc::default(),
+            moisture: Arc::default(),
+            learned: Arc::default(),
+            wizard_plans: Arc::default(),
+            cycle_runs: Arc::default(),
+            debits: Arc::default(),
+        }
     }
 }
 
@@ -97,64 +169,97 @@ pub fn mock_sector() -> Vec<SectorInfo> {
     let sectors = vec![
         SectorInfo {
             id: 1,
+            name: "sector 1".to_owned(),
             weekly_target: 2.5,
             sprinkler_debit: 1.0,
             max_duration: 30 * 3600,
             percolation_rate: 0.5,
             progress: 0.,
             last_water: 0,
+            ignore_weather_pause: false,
+            max_daily_mm: None,
+            max_daily_minutes: None,
+            deficit_exempt: false,
         },
         SectorInfo {
             id: 2,
+            name: "sector 2".to_owned(),
             weekly_target: 2.5,
             sprinkler_debit: 1.0,
             max_duration: 30 * 3600,
             percolation_rate: 0.5,
             progress: 0.,
             last_water: 0,
+            ignore_weather_pause: false,
+            max_daily_mm: None,
+            max_daily_minutes: None,
+            deficit_exempt: false,
         },
         SectorInfo {
             id: 3,
+            name: "sector 3".to_owned(),
             weekly_target: 2.5,
             sprinkler_debit: 1.0,
             max_duration: 30 * 3600,
             percolation_rate: 0.5,
             progress: 0.,
             last_water: 0,
+            ignore_weather_pause: false,
+            max_daily_mm: None,
+            max_daily_minutes: None,
+            deficit_exempt: false,
         },
         SectorInfo {
             id: 4,
+            name: "sector 4".to_owned(),
             weekly_target: 2.5,
             sprinkler_debit: 1.0,
             max_duration: 30 * 3600,
             percolation_rate: 0.5,
             progress: 0.,
             last_water: 0,
+            ignore_weather_pause: false,
+            max_daily_mm: None,
+            max_daily_minutes: None,
+            deficit_exempt: false,
         },
     ];
     sectors
 }
 
 fn mock_weather() -> WeatherConditions {
-    WeatherConditions { is_raining: false, wind_speed: 10.0, humidity: 20., solar_radiation: 1., temperature: 15. }
+    WeatherConditions {
+        is_raining: false,
+        wind_speed: 10.0,
+        humidity: 20.,
+        solar_radiation: 1.,
+        temperature: 15.,
+        ..Default::default()
+    }
 }
 
 fn mock_schedule() -> Vec<ScheduleEntry> {
     let entries = vec![
         ScheduleEntry {
+            program: "A".to_owned(),
             schedule_type: ScheduleType::Weekday(Weekday::Mon),
             start_times: DailyPlan(vec![
                 WaterSector::new(1, 6 * 3600, 30 * 60),
                 WaterSector::new(2, 7 * 3600, 20 * 60),
             ]),
+            cron: None,
         },
         ScheduleEntry {
+            program: "B".to_owned(),
             schedule_type: ScheduleType::Weekday(Weekday::Mon),
             start_times: DailyPlan(vec![WaterSector::new(3, 8 * 3600, 40 * 60)]),
+            cron: None,
         },
         ScheduleEntry {
+            program: "C".to_owned(),
             schedule_type: ScheduleType::Weekday(Weekday::Mon),
             start_times: DailyPlan(vec![WaterSector::new(4, 9 * 3600, 50 * 60)]),
+            cron: None,
         },
     ];
     entries
@@ -191,6 +296,38 @@ impl DatabaseTrait for MockDatabase {
         Some(mock_weather())
     }
 
+    fn log_weather(&self, _obs: WeatherConditions) -> Result<()> {
+        Ok(()) // Simulate success
+    }
+
+    fn log_weather_event(&self, _timestamp: i64, _kind: String, _data: String) -> Result<()> {
+        Ok(()) // Simulate success
+    }
+
+    fn store_forecast(&self, _forecast: Vec<HourlyForecast>) -> Result<()> {
+        Ok(()) // Simulate success
+    }
+
+    fn load_forecast(&self, _from: i64, _to: i64) -> Result<Vec<HourlyForecast>> {
+        Ok(vec![])
+    }
+
+    fn load_observations(&self, _from: i64, _to: i64) -> Result<Vec<WeatherConditions>> {
+        Ok(vec![])
+    }
+
+    fn load_observations_page(&self, _from: i64, _to: i64, _limit: u32) -> Result<Vec<WeatherConditions>> {
+        Ok(vec![])
+    }
+
+    fn store_hourly_rollups(&self, _hours: Vec<HourlyRollup>) -> Result<()> {
+        Ok(()) // Simulate success
+    }
+
+    fn store_daily_rollup(&self, _day: DailyRollup) -> Result<()> {
+        Ok(()) // Simulate success
+    }
+
     fn get_lastday_rain(&self, timestamp: i64) -> Option<f64> {
         self.rain_data.get(&sod(timestamp)).cloned()
     }
@@ -199,7 +336,236 @@ impl DatabaseTrait for MockDatabase {
         self.et_data.get(&sod(timestamp)).cloned()
     }
 
+    fn get_avg_daily_et(&self, _from: i64, _to: i64) -> Option<f64> {
+        None
+    }
+
     fn load_auto_schedule(&self) -> Result<Schedule, rusqlite::Error> {
         Ok(Schedule::new(mock_schedule()))
     }
+
+    fn save_auto_schedule(&self, _schedule: Schedule) -> Result<()> {
+        Ok(()) // Simulate success
+    }
+
+    fn set_program_enabled(&self, _program: String, _enabled: bool) -> Result<()> {
+        Ok(()) // Simulate success
+    }
+
+    fn store_device_telemetry(&self, _telemetry: DeviceTelemetry) -> Result<()> {
+        Ok(()) // Simulate success
+    }
+
+    fn load_device_telemetry(&self) -> Result<Vec<DeviceTelemetry>> {
+        Ok(vec![])
+    }
+
+    fn store_resume_point(&self, _point: Option<ResumePoint>) -> Result<()> {
+        Ok(()) // Simulate success
+    }
+
+    fn load_resume_point(&self) -> Option<ResumePoint> {
+        None
+    }
+
+    fn add_sector_progress(&self, _sector: u32, _cm: f64, _last_water: i64) -> Result<()> {
+        Ok(()) // Simulate success
+    }
+
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn log_system_event(&self, evt: SystemEvent) -> Result<()> {
+        self.events.lock().unwrap().push(evt);
+        Ok(())
+    }
+
+    fn load_system_events(&self, from: i64, to: i64) -> Result<Vec<SystemEvent>> {
+        let events = self.events.lock().unwrap();
+        Ok(events.iter().filter(|evt| evt.timestamp >= from && evt.timestamp < to).cloned().collect())
+    }
+
+    fn log_audit(&self, entry: AuditEntry) -> Result<()> {
+        self.audit.lock().unwrap().push(entry);
+        Ok(())
+    }
+
+    fn load_audit(&self, from: i64, to: i64) -> Result<Vec<AuditEntry>> {
+        let audit = self.audit.lock().unwrap();
+        Ok(audit.iter().filter(|entry| entry.timestamp >= from && entry.timestamp < to).cloned().collect())
+    }
+
+    fn import_sectors(&self, _sectors: Vec<SectorCfg>) -> Result<()> {
+        Ok(())
+    }
+
+    fn store_mode(&self, mode: Mode) -> Result<()> {
+        self.data.lock().unwrap().insert("mode".to_owned(), mode.to_string());
+        Ok(())
+    }
+
+    fn load_mode(&self) -> Option<Mode> {
+        self.data.lock().unwrap().get("mode").and_then(|mode| mode.parse().ok())
+    }
+
+    fn store_progress_day(&self, day: i64) -> Result<()> {
+        self.data.lock().unwrap().insert("progress day".to_owned(), day.to_string());
+        Ok(())
+    }
+
+    fn load_progress_day(&self) -> Option<i64> {
+        self.data.lock().unwrap().get("progress day").and_then(|day| day.parse().ok())
+    }
+
+    fn store_daily_report(&self, report: DailyReport) -> Result<()> {
+        let key = format!("report {}", report.day);
+        self.data.lock().unwrap().insert(key, serde_json::to_string(&report).unwrap());
+        Ok(())
+    }
+
+    fn load_daily_report(&self, day: i64) -> Result<Option<DailyReport>> {
+        let data = self.data.lock().unwrap();
+        Ok(data.get(&format!("report {}", day)).map(|report| serde_json::from_str(report).unwrap()))
+    }
+
+    fn add_source_usage(&self, day: i64, source: String, secs: i64, litres: f64) -> Result<()> {
+        self.usage.lock().unwrap().push((day, SourceUsage { source, secs, litres }));
+        Ok(())
+    }
+
+    fn load_source_usage(&self, from: i64, to: i64) -> Result<Vec<SourceUsage>> {
+        let mut usage: Vec<SourceUsage> = Vec::new();
+        for (_, added) in self.usage.lock().unwrap().iter().filter(|(day, _)| *day >= from && *day < to) {
+            match usage.iter_mut().find(|total| total.source == added.source) {
+                Some(total) => (total.secs, total.litres) = (total.secs + added.secs, total.litres + added.litres),
+                None => usage.push(added.clone()),
+            }
+        }
+        usage.sort_by(|a, b| a.source.cmp(&b.source));
+        Ok(usage)
+    }
+
+    fn import_sector_constraints(&self, constraints: Vec<SectorConstraint>) -> Result<()> {
+        *self.constraints.lock().unwrap() = constraints;
+        Ok(())
+    }
+
+    fn load_sector_constraints(&self) -> Result<Vec<SectorConstraint>> {
+        Ok(self.constraints.lock().unwrap().clone())
+    }
+
+    fn set_sector_targets(&self, sector: u32, targets: Vec<SectorTarget>) -> Result<()> {
+        let mut curves = self.targets.lock().unwrap();
+        curves.retain(|target| target.sector != sector);
+        curves.extend(targets);
+        Ok(())
+    }
+
+    fn load_sector_targets(&self) -> Result<Vec<SectorTarget>> {
+        Ok(self.targets.lock().unwrap().clone())
+    }
+
+    fn add_moisture_reading(&self, reading: MoistureReading) -> Result<()> {
+        let mut moisture = self.moisture.lock().unwrap();
+        moisture.retain(|r| (r.sector, r.timestamp) != (reading.sector, reading.timestamp));
+        moisture.push(reading);
+        moisture.sort_by_key(|r| (r.sector, r.timestamp));
+        Ok(())
+    }
+
+    fn load_moisture_readings(&self, from: i64, to: i64) -> Result<Vec<MoistureReading>> {
+        let moisture = self.moisture.lock().unwrap();
+        Ok(moisture.iter().filter(|r| r.timestamp >= from && r.timestamp < to).copied().collect())
+    }
+
+    fn store_learned_params(&self, params: Vec<LearnedParams>) -> Result<()> {
+        let mut learned = self.learned.lock().unwrap();
+        for p in params {
+            learned.retain(|l| l.sector != p.sector || l.status != LearnedStatus::Pending);
+            learned.push(p);
+        }
+        Ok(())
+    }
+
+    fn load_learned_params(&self) -> Result<Vec<LearnedParams>> {
+        Ok(self.learned.lock().unwrap().clone())
+    }
+
+    fn set_sprinkler_debit(&self, sector: u32, debit: f64) -> Result<()> {
+        self.debits.lock().unwrap().push((sector, debit));
+        Ok(())
+    }
+
+    fn resolve_learned_params(&self, sector: u32, accept: bool) -> Result<Option<LearnedParams>> {
+        let mut learned = self.learned.lock().unwrap();
+        let pending = learned.iter_mut().find(|l| l.sector == sector && l.status == LearnedStatus::Pending);
+        Ok(pending.map(|l| {
+            l.status = if accept { LearnedStatus::Accepted } else { LearnedStatus::Rejected };
+            l.clone()
+        }))
+    }
+
+    fn store_wizard_plans(&self, from: i64, plans: Vec<DailyPlan>) -> Result<()> {
+        let first = plans.iter().filter_map(|plan| plan.0.first()).map(|sec| sec.start).min();
+        let mut stored = self.wizard_plans.lock().unwrap();
+        stored.retain(|sec| sec.start < first.unwrap_or(from).min(from));
+        stored.extend(plans.into_iter().flat_map(|plan| plan.0));
+        Ok(())
+    }
+
+    fn load_wizard_plans(&self, from: i64, to: i64) -> Result<Vec<WaterSector>> {
+        Ok(self.wizard_plans.lock().unwrap().iter().copied().filter(|sec| (from..to).contains(&sec.start)).collect())
+    }
+
+    fn load_wizard_queue(&self, _from: i64) -> Result<Vec<DailyPlan>> {
+        Ok(vec![])
+    }
+
+    fn complete_wizard_plans(&self, _cycle_id: i64) -> Result<()> {
+        Ok(()) // Simulate success
+    }
+
+    fn start_cycle_run(&self, cycle: i64, mode: Mode, planned: Vec<WaterSector>, start: i64) -> Result<u32> {
+        let mut runs = self.cycle_runs.lock().unwrap();
+        let id = runs.len() as u32 + 1;
+        let executed = Vec::new();
+        runs.push(CycleRun { id, cycle, mode, planned, executed, start, end: None, outcome: None });
+        Ok(id)
+    }
+
+    fn finish_cycle_run(&self, id: u32, end: i64, outcome: String) -> Result<()> {
+        if let Some(run) = self.cycle_runs.lock().unwrap().iter_mut().find(|run| run.id == id) {
+            (run.end, run.outcome) = (Some(end), Some(outcome));
+        }
+        Ok(())
+    }
+
+    fn load_cycle_runs(&self, from: i64, to: i64) -> Result<Vec<CycleRun>> {
+        let runs = self.cycle_runs.lock().unwrap();
+        Ok(runs.iter().filter(|run| (from..to).contains(&run.start)).cloned().collect())
+    }
+
+    fn load_watering_events(&self, _from: i64, _to: i64) -> Result<Vec<WateringEvent>> {
+        Ok(vec![])
+    }
+
+    fn load_watering_events_page(
+        &self, _from: i64, _to: i64, _after: i64, _limit: u32,
+    ) -> Result<Vec<(i64, WateringEvent)>> {
+        Ok(vec![])
+    }
+
+    fn maintain(&self, now: i64) -> Result<DbCheck> {
+        Ok(DbCheck { timestamp: now, ok: true, problems: Vec::new(), freed_pages: 0, elapsed_ms: 0 })
+    }
+
+    fn store_clock_mark(&self, timestamp: i64) -> Result<()> {
+        self.data.lock().unwrap().insert("clock mark".to_owned(), timestamp.to_string());
+        Ok(())
+    }
+
+    fn load_clock_mark(&self) -> Option<i64> {
+        self.data.lock().unwrap().get("clock mark").and_then(|mark| mark.parse().ok())
+    }
 }
@@ -1,7 +1,8 @@
 use tracing::trace;
 // use futures_util::FutureExt;
-use crate::sensors::interface::SensorController;
+use crate::sensors::interface::{SensorController, ValveState};
 use crate::test::utils::AppError;
+use async_trait::async_trait;
 use mockall::mock;
 use std::sync::Arc;
 
@@ -9,9 +10,11 @@ mock! {
     #[derive(Debug)]
     pub SensorController {}
 
+    #[async_trait]
     impl SensorController for SensorController {
-        fn activate_sector(&self, sector: u32) -> Result<(), AppError>;
-        fn deactivate_sector(&self, sector: u32) -> Result<(), AppError>;
+        async fn activate_sector(&self, sector: u32) -> Result<(), AppError>;
+        async fn deactivate_sector(&self, sector: u32) -> Result<(), AppError>;
+        async fn sector_state(&self, sector: u32) -> Result<ValveState, AppError>;
     }
 }
 
@@ -29,6 +32,8 @@ pub fn set_sensor_controller0() -> Arc<MockSensorController> {
         trace!(sector_id = sector, "Mocked deactivation-0.");
         Ok(())
     });
+    // No read back, so the state machine skips the valve checks
+    mock_controller.expect_sector_state().times(0..).returning(|_| Ok(ValveState::Unknown));
 
     Arc::new(mock_controller)
 }
@@ -45,5 +50,6 @@ pub fn set_sensor_controller1() -> Arc<MockSensorController> {
         trace!(sector_id = sector, "Mocked deactivation-1.");
         Ok(())
     });
+    mock_controller.expect_sector_state().times(0..).returning(|_| Ok(ValveState::Unknown));
     Arc::new(mock_controller)
 }
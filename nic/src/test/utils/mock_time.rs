@@ -9,16 +9,56 @@ use std::{
     },
     time::Duration,
 };
+use tokio::sync::Notify;
 use tracing_subscriber::fmt::time::FormatTime;
 
 #[derive(Debug)]
 pub struct MockTimeProvider {
     current_time: Arc<AtomicI64>,
+    /// seconds the wall clock was stepped, the monotonic one is behind it by as much
+    stepped: AtomicI64,
+    /// the sleeps wait for `set` or `advance_time` to move the time, rather than jumping there themselves
+    held: bool,
+    moved: Notify,
 }
 
 impl MockTimeProvider {
     pub fn new(start_time: i64) -> Self {
-        Self { current_time: Arc::new(AtomicI64::new(start_time)) }
+        Self {
+            current_time: Arc::new(AtomicI64::new(start_time)),
+            stepped: AtomicI64::new(0),
+            held: false,
+            moved: Notify::new(),
+        }
+    }
+
+    /// A clock that stands still until it is moved, for a running process driven from outside
+    pub fn held(start_time: i64) -> Self {
+        Self { held: true, ..Self::new(start_time) }
+    }
+
+    /// Steps the wall clock `secs`, as NTP does, leaving the monotonic one where it is
+    pub fn step(&self, secs: i64) {
+        self.current_time.fetch_add(secs, Ordering::SeqCst);
+        self.stepped.fetch_add(secs, Ordering::SeqCst);
+        self.moved.notify_waiters();
+    }
+
+    pub fn is_held(&self) -> bool {
+        self.held
+    }
+
+    async fn wait_until(&self, time: i64) {
+        loop {
+            // registered before the check, so a move in between isn't missed
+            let moved = self.moved.notified();
+            tokio::pin!(moved);
+            moved.as_mut().enable();
+            if self.now() >= time {
+                return;
+            }
+            moved.await;
+        }
     }
 }
 
@@ -28,19 +68,40 @@ impl TimeProvider for MockTimeProvider {
         self.current_time.load(Ordering::SeqCst)
     }
 
+    fn monotonic(&self) -> i64 {
+        self.now() - self.stepped.load(Ordering::SeqCst)
+    }
+
     fn as_any(&self) -> &dyn Any {
         self
     }
 
-    async fn sleep(&self, _duration: Duration) {}
+    async fn sleep(&self, duration: Duration) {
+        if self.held {
+            self.wait_until(self.now() + duration.as_secs_f64().ceil() as i64).await;
+        }
+    }
+
+    async fn sleep_until(&self, time: i64) {
+        if self.held {
+            return self.wait_until(time).await;
+        }
+        // give the other tasks a chance to run, as a real sleep would
+        tokio::task::yield_now().await;
+        self.current_time.fetch_max(time, Ordering::SeqCst);
+    }
 
     async fn advance_time(&self, seconds: i64) {
-        self.sleep(Duration::from_micros(100)).await;
+        if !self.held {
+            self.sleep(Duration::from_micros(100)).await;
+        }
         self.current_time.fetch_add(seconds, Ordering::SeqCst);
+        self.moved.notify_waiters();
     }
 
     fn set(&self, time: i64) {
         self.current_time.store(time, Ordering::SeqCst);
+        self.moved.notify_waiters();
     }
 }
 
@@ -1,11 +1,23 @@
 use async_trait::async_trait;
-use std::{any::Any, fmt::Debug, time::Duration};
+use std::{
+    any::Any,
+    fmt::Debug,
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
 
 #[async_trait]
 pub trait TimeProvider: Send + Sync + Debug {
     fn now(&self) -> i64; // Returns the current time as a Unix UTC timestamp
+    /// Seconds from a source that never steps, for how long something lasted. The wall clock steps when NTP sets it,
+    /// the simulated ones don't.
+    fn monotonic(&self) -> i64 {
+        self.now()
+    }
     fn as_any(&self) -> &dyn Any;
     async fn sleep(&self, duration: Duration);
+    /// Until the Unix UTC timestamp `time`. Simulations jump straight there.
+    async fn sleep_until(&self, time: i64);
     async fn advance_time(&self, seconds: i64);
     fn set(&self, new_time: i64);
 }
@@ -19,6 +31,11 @@ impl TimeProvider for RealTimeProvider {
         chrono::Utc::now().timestamp()
     }
 
+    fn monotonic(&self) -> i64 {
+        static STARTED: OnceLock<Instant> = OnceLock::new();
+        STARTED.get_or_init(Instant::now).elapsed().as_secs() as i64
+    }
+
     fn as_any(&self) -> &dyn Any {
         self
     }
@@ -27,9 +44,75 @@ impl TimeProvider for RealTimeProvider {
         tokio::time::sleep(duration).await;
     }
 
+    async fn sleep_until(&self, time: i64) {
+        let wait_ms = time * 1000 - chrono::Utc::now().timestamp_millis();
+        self.sleep(Duration::from_millis(wait_ms.max(0) as u64)).await;
+    }
+
     async fn advance_time(&self, _seconds: i64) {
         self.sleep(Duration::from_secs(1)).await;
     }
 
     fn set(&self, _new_time: i64) {}
 }
+
+/// Wall clock running `factor` times faster from `start`, for rehearsal runs.<br>
+/// Sleeps are shortened by the same factor.
+#[derive(Debug)]
+pub struct AcceleratedTimeProvider {
+    started: Instant,
+    start: i64,
+    factor: f64,
+}
+
+impl AcceleratedTimeProvider {
+    pub fn new(start: i64, factor: f64) -> Self {
+        Self { started: Instant::now(), start, factor: factor.max(1.) }
+    }
+
+    fn real(&self, simulated: Duration) -> Duration {
+        simulated.div_f64(self.factor)
+    }
+}
+
+#[async_trait]
+impl TimeProvider for AcceleratedTimeProvider {
+    fn now(&self) -> i64 {
+        self.start + (self.started.elapsed().as_secs_f64() * self.factor) as i64
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        tokio::time::sleep(self.real(duration)).await;
+    }
+
+    async fn sleep_until(&self, time: i64) {
+        let wait = (time - self.now()).max(0) as u64;
+        self.sleep(Duration::from_secs(wait)).await;
+    }
+
+    async fn advance_time(&self, _seconds: i64) {
+        self.sleep(Duration::from_secs(1)).await;
+    }
+
+    fn set(&self, _new_time: i64) {}
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn accelerated_clock() {
+        let clock = AcceleratedTimeProvider::new(1_000, 3600.);
+        assert_eq!(clock.now(), 1_000);
+        // an hour of simulated time in a second
+        let started = Instant::now();
+        clock.sleep(Duration::from_secs(360)).await;
+        assert!(started.elapsed() < Duration::from_millis(500));
+        assert!(clock.now() >= 1_360);
+    }
+}
@@ -1,12 +1,24 @@
-use std::{collections::HashMap, path::{Path, PathBuf}, sync::Arc};
+use std::{collections::HashMap, io, path::{Path, PathBuf}, sync::Arc};
 
 use chrono::{DateTime, Datelike, Local, NaiveDateTime, TimeZone, Timelike, Utc, Weekday};
 use tokio::sync::{
     broadcast::{self, Receiver, Sender},
     Mutex,
 };
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::{
+    fmt::{
+        self,
+        time::{FormatTime, SystemTime},
+    },
+    layer::SubscriberExt,
+    util::SubscriberInitExt,
+    EnvFilter, Layer,
+};
 
 use crate::{
+    config::{LogCfg, LogFormat},
+    log_file::log_writer,
     test::utils::mock_time::MockTimeFormatter,
     time::TimeProvider,
     watering::ds::{CtrlSignal, SectorInfo},
@@ -35,22 +47,55 @@ pub fn sod(ts: i64) -> i64 {
     ts - (ts % 86_400)
 }
 
-pub fn start_log(time_provider: Option<Arc<dyn TimeProvider>>) {
-    let subscriber_builder = tracing_subscriber::fmt().with_env_filter("nic=debug").with_target(false); // Hide target module info
+/// The wall clock, or the one of the tests
+#[derive(Clone)]
+struct LogTimer(Option<Arc<dyn TimeProvider>>);
 
-    if let Some(time_provider) = time_provider {
-        let time_formatter = MockTimeFormatter { time_provider };
-        subscriber_builder.with_timer(time_formatter).init();
-    } else {
-        subscriber_builder.init();
+impl FormatTime for LogTimer {
+    fn format_time(&self, w: &mut fmt::format::Writer<'_>) -> std::fmt::Result {
+        match &self.0 {
+            Some(time_provider) => MockTimeFormatter { time_provider: time_provider.clone() }.format_time(w),
+            None => SystemTime.format_time(w),
+        }
     }
 }
 
+/// Console and, when configured, file logging, each with its own level.<br>
+/// The file is written from a background thread, keep the guard until the end so the last lines get there.
+pub fn start_log(cfg: &LogCfg, time_provider: Option<Arc<dyn TimeProvider>>) -> io::Result<Option<WorkerGuard>> {
+    let timer = LogTimer(time_provider);
+    let console = fmt::layer()
+        .with_target(false) // Hide target module info
+        .with_timer(timer.clone())
+        .with_filter(EnvFilter::new(&cfg.console_level));
+
+    let (file, guard) = match &cfg.file {
+        Some(path) => {
+            let (writer, guard) = tracing_appender::non_blocking(log_writer(cfg, Path::new(path))?);
+            let layer = fmt::layer().with_target(false).with_ansi(false).with_timer(timer).with_writer(writer);
+            let layer = match cfg.format {
+                LogFormat::Plain => layer.boxed(),
+                LogFormat::Json => layer.json().boxed(),
+            };
+            (Some(layer.with_filter(EnvFilter::new(&cfg.file_level))), Some(guard))
+        }
+        None => (None, None),
+    };
+    tracing_subscriber::registry().with(console).with(file).init();
+    Ok(guard)
+}
+
 pub fn get_week_day_from_ts(time: i64) -> Weekday {
     let datetime = DateTime::<Utc>::from_timestamp(time, 0).unwrap();
     datetime.weekday()
 }
 
+/// 1 January .. 12 December
+pub fn get_month_from_ts(time: i64) -> u32 {
+    let datetime = DateTime::<Utc>::from_timestamp(time, 0).unwrap();
+    datetime.month()
+}
+
 pub fn get_hour_from_ts(time: i64) -> u32 {
     let datetime = DateTime::<Utc>::from_timestamp(time, 0).unwrap();
     datetime.hour()
@@ -66,11 +111,9 @@ pub fn init_broadcast_channels() -> (tokio::sync::broadcast::Sender<CtrlSignal>,
     (tx, rx)
 }
 
-/// Assumes that whevever the machine stops, nect start will be with 0 progress.<br>
-/// It is not supposed that the machine stops.  It it does (maintenance), or other reason, we do not know for sure how long it stopped.<br>
+/// The sectors by id, with 0 progress.<br>
+/// For when we don't know how old the stored progress is, the state machine keeps it when it is recent enough.<br>
 pub fn load_sectors_into_hashmap(sectors: Vec<SectorInfo>) -> HashMap<u32, SectorInfo> {
-    // TODO: maybe we can start with a parameter giving that indication and move from there to make the thing smarter.
-    // or just get some last rec from db and use that
     sectors
         .into_iter()
         .map(|sector| {
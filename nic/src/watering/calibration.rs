@@ -0,0 +1,71 @@
+use serde::{Deserialize, Serialize};
+
+/// Longest a calibration run can be, enough for a catch-cup test of a slow drip line
+pub const MAX_CALIBRATION_SECS: i64 = 3600;
+
+/// What was measured once the calibration run of a sector ended
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum CalibrationMeasure {
+    /// mm of water the cups caught over the run, averaged
+    CatchCup { mm: f64 },
+    /// the water meter over the sector's area
+    Flow { litres_per_min: f64, area_m2: f64 },
+}
+
+impl CalibrationMeasure {
+    pub fn check(&self) -> Result<(), String> {
+        let positive = |value: f64| value.is_finite() && value > 0.;
+        match *self {
+            CalibrationMeasure::CatchCup { mm } if !positive(mm) => Err("mm must be positive".to_owned()),
+            CalibrationMeasure::Flow { litres_per_min, area_m2 } if !positive(litres_per_min) || !positive(area_m2) => {
+                Err("litres_per_min and area_m2 must be positive".to_owned())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// cm/hour of the sector, for a run of `seconds`. A litre over a m² is a mm.
+    pub fn debit(&self, seconds: i64) -> f64 {
+        match *self {
+            CalibrationMeasure::CatchCup { mm } => mm / 10. * 3600. / seconds as f64,
+            CalibrationMeasure::Flow { litres_per_min, area_m2 } => litres_per_min * 60. / area_m2 / 10.,
+        }
+    }
+}
+
+/// The calibration run of a sector, a test run of it alone, waiting for its measure
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Calibration {
+    pub sector: u32,
+    pub started: i64,
+    pub seconds: i64,
+}
+
+/// The debit a calibration measured, already written to the sector
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Calibrated {
+    pub sector: u32,
+    pub seconds: i64,
+    /// cm/hour
+    pub sprinkler_debit: f64,
+    pub previous_debit: f64,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn works_out_the_debit() {
+        // 5 mm in 15 minutes is 2 cm an hour
+        assert_eq!(CalibrationMeasure::CatchCup { mm: 5. }.debit(900), 2.);
+        // 10 l/min over 50 m² is 12 mm an hour, whatever the run
+        let flow = CalibrationMeasure::Flow { litres_per_min: 10., area_m2: 50. };
+        assert!((flow.debit(60) - 1.2).abs() < 1e-9);
+
+        assert!(CalibrationMeasure::CatchCup { mm: 0. }.check().is_err());
+        assert!(CalibrationMeasure::Flow { litres_per_min: 10., area_m2: f64::NAN }.check().is_err());
+        assert!(flow.check().is_ok());
+    }
+}
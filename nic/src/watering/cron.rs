@@ -0,0 +1,96 @@
+//! The `minute hour day-of-month month day-of-week` expressions of the auto schedule, `"0 22 * * mon,thu"`. The
+//! weekly schedule has no dates, so the day of the month and the month are always `*`.
+
+use chrono::Weekday;
+
+/// Days and times of a cron expression, in order
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cron {
+    pub days: Vec<Weekday>,
+    /// seconds from the start of the day
+    pub starts: Vec<i64>,
+}
+
+const DAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// Lists, ranges and steps of numbers, `*/15` or `1-5`, and the days by name too, `mon-fri`. 0 and 7 are Sunday.
+pub fn parse(expr: &str) -> Result<Cron, String> {
+    let fields: Vec<&str> = expr.split_whitespace().collect();
+    let [minute, hour, day, month, weekday] = fields[..] else {
+        return Err(format!("'{}' is not minute hour day-of-month month day-of-week", expr));
+    };
+    if day != "*" || month != "*" {
+        return Err(format!("'{}': the day of the month and the month must be *, the schedule is weekly", expr));
+    }
+    let minutes = values(minute, 0, 59, &[]).ok_or_else(|| format!("'{}': bad minute '{}'", expr, minute))?;
+    let hours = values(hour, 0, 23, &[]).ok_or_else(|| format!("'{}': bad hour '{}'", expr, hour))?;
+    let weekdays = values(&weekday.to_lowercase(), 0, 7, &DAYS)
+        .ok_or_else(|| format!("'{}': bad day of the week '{}'", expr, weekday))?;
+
+    let mut days: Vec<Weekday> = weekdays.iter().map(|&n| Weekday::try_from((n as u8 + 6) % 7).unwrap()).collect();
+    days.sort_by_key(|day| day.num_days_from_monday());
+    days.dedup();
+    let starts = hours.iter().flat_map(|hour| minutes.iter().map(move |minute| hour * 3600 + minute * 60)).collect();
+    Ok(Cron { days, starts })
+}
+
+/// The sorted values of a field between `min` and `max`, `names` from `min` on
+fn values(field: &str, min: i64, max: i64, names: &[&str]) -> Option<Vec<i64>> {
+    let value = |text: &str| {
+        let n = names.iter().position(|name| *name == text).map(|at| at as i64 + min).or_else(|| text.parse().ok())?;
+        (min..=max).contains(&n).then_some(n)
+    };
+    let mut all = vec![];
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => (range, step.parse::<i64>().ok().filter(|&step| step > 0)?),
+            None => (item, 1),
+        };
+        let (from, to) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((from, to)) => (value(from)?, value(to)?),
+            None if step > 1 => (value(range)?, max),
+            None => (value(range)?, value(range)?),
+        };
+        if from > to {
+            return None;
+        }
+        all.extend((from..=to).step_by(step as usize));
+    }
+    all.sort_unstable();
+    all.dedup();
+    Some(all)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_the_days_and_times() {
+        let cron = parse("0 22 * * MON,THU").unwrap();
+        assert_eq!(cron, Cron { days: vec![Weekday::Mon, Weekday::Thu], starts: vec![22 * 3600] });
+
+        let cron = parse("*/30 5-6 * * 1-5").unwrap();
+        assert_eq!(cron.days, [Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri]);
+        assert_eq!(cron.starts, [5 * 3600, 5 * 3600 + 1800, 6 * 3600, 6 * 3600 + 1800]);
+
+        // sunday is 0 and 7
+        assert_eq!(parse("15 6 * * 0,7,sat").unwrap().days, [Weekday::Sat, Weekday::Sun]);
+        assert_eq!(parse("0 6,22 * * *").unwrap().days.len(), 7);
+    }
+
+    #[test]
+    fn rejects_what_the_weekly_schedule_cant_hold() {
+        let bad = |expr: &str| parse(expr).unwrap_err();
+        assert_eq!(bad("0 22 * *"), "'0 22 * *' is not minute hour day-of-month month day-of-week");
+        assert_eq!(
+            bad("0 22 1 * *"),
+            "'0 22 1 * *': the day of the month and the month must be *, the schedule is weekly"
+        );
+        assert_eq!(bad("60 22 * * *"), "'60 22 * * *': bad minute '60'");
+        assert_eq!(bad("0 18-6 * * *"), "'0 18-6 * * *': bad hour '18-6'");
+        assert_eq!(bad("0 22 * * someday"), "'0 22 * * someday': bad day of the week 'someday'");
+        assert_eq!(bad("*/0 22 * * *"), "'*/0 22 * * *': bad minute '*/0'");
+    }
+}
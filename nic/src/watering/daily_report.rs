@@ -0,0 +1,154 @@
+use super::{
+    ds::{DailyPlan, SectorInfo, SystemEvent},
+    efficiency::SectorEfficiency,
+};
+use chrono::DateTime;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// System events a person should look at
+const ANOMALIES: [&str; 3] = ["cycle_aborted", "pause_abandoned", "emergency_stop"];
+
+/// What happened on a day, from its system events
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DaySummary {
+    /// Unix UTC timestamp of the start of the day
+    pub day: i64,
+    pub cycles: usize,
+    pub pauses: usize,
+    /// sector id -> watered seconds
+    pub watered: BTreeMap<u32, i64>,
+    /// (sector id, from, to) of each time a valve was open
+    pub runs: Vec<(u32, i64, i64)>,
+}
+
+impl DaySummary {
+    /// Watered time from the valve events: a sector waters from activated or resumed, to deactivated, paused or aborted
+    pub fn from_events(day: i64, events: &[SystemEvent]) -> Self {
+        let mut summary = DaySummary { day, ..Default::default() };
+        let mut open: HashMap<u32, i64> = HashMap::new();
+        for evt in events {
+            match (evt.kind.as_str(), evt.sector) {
+                ("cycle_started", _) => summary.cycles += 1,
+                ("paused", _) => summary.pauses += 1,
+                _ => {}
+            }
+            let Some(sector) = evt.sector else { continue };
+            match evt.kind.as_str() {
+                "sector_activated" | "resumed" => {
+                    open.insert(sector, evt.timestamp);
+                }
+                "sector_deactivated" | "paused" | "cycle_aborted" => {
+                    if let Some(from) = open.remove(&sector) {
+                        *summary.watered.entry(sector).or_default() += evt.timestamp - from;
+                        summary.runs.push((sector, from, evt.timestamp));
+                    }
+                }
+                _ => {}
+            }
+        }
+        summary
+    }
+}
+
+/// The summary of a day, built after the midnight adjustments and kept in `daily_reports`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DailyReport {
+    /// Unix UTC timestamp of the start of the reported day
+    pub day: i64,
+    /// mm, what the adjustments used
+    pub et: f64,
+    pub rain: f64,
+    /// mm, of the day starting now
+    pub predicted_et: f64,
+    /// sector id -> cm applied
+    pub water: BTreeMap<u32, f64>,
+    /// what the current mode waters next, empty in manual and off
+    pub plan: DailyPlan,
+    pub anomalies: Vec<String>,
+    /// per sector, over the weeks before the day starting now
+    #[serde(default)]
+    pub efficiency: Vec<SectorEfficiency>,
+}
+
+impl DailyReport {
+    /// Water and anomalies from the events of the day, the weather and the plan are up to the caller
+    pub fn from_events(day: i64, events: &[SystemEvent], sectors: &HashMap<u32, SectorInfo>) -> Self {
+        let summary = DaySummary::from_events(day, events);
+        let water = summary
+            .watered
+            .into_iter()
+            .map(|(sector, secs)| {
+                let debit = sectors.get(&sector).map_or(0., |sec| sec.sprinkler_debit);
+                (sector, secs as f64 / 3600. * debit)
+            })
+            .collect();
+        let anomalies = events.iter().filter(|evt| ANOMALIES.contains(&evt.kind.as_str())).map(anomaly).collect();
+        Self { day, water, anomalies, ..Default::default() }
+    }
+}
+
+/// `22:40 cycle_aborted sector 2: valve stuck`
+fn anomaly(evt: &SystemEvent) -> String {
+    let at = DateTime::from_timestamp(evt.timestamp, 0).unwrap_or_default().format("%H:%M");
+    let mut line = format!("{} {}", at, evt.kind);
+    if let Some(sector) = evt.sector {
+        line.push_str(&format!(" sector {}", sector));
+    }
+    if !evt.detail.is_empty() {
+        line.push_str(&format!(": {}", evt.detail));
+    }
+    line
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn event(timestamp: i64, kind: &str, sector: Option<u32>) -> SystemEvent {
+        SystemEvent { timestamp, kind: kind.to_owned(), sector, cycle: None, detail: String::new() }
+    }
+
+    #[test]
+    fn sums_the_day() {
+        let evt = |timestamp, kind: &str, sector, detail: &str| SystemEvent {
+            timestamp,
+            kind: kind.to_owned(),
+            sector,
+            cycle: Some(1),
+            detail: detail.to_owned(),
+        };
+        let events = [
+            evt(79_200, "cycle_started", None, ""),
+            evt(79_200, "sector_activated", Some(1), ""),
+            evt(81_000, "sector_deactivated", Some(1), ""),
+            evt(81_020, "sector_activated", Some(2), ""),
+            evt(81_920, "cycle_aborted", Some(2), "valve stuck"),
+        ];
+        let sector = |id, sprinkler_debit| (id, SectorInfo { id, sprinkler_debit, ..Default::default() });
+        let sectors = HashMap::from([sector(1, 1.), sector(2, 2.)]);
+
+        let report = DailyReport::from_events(0, &events, &sectors);
+        assert_eq!(report.water, BTreeMap::from([(1, 0.5), (2, 0.5)]));
+        assert_eq!(report.anomalies, ["22:45 cycle_aborted sector 2: valve stuck"]);
+    }
+
+    #[test]
+    fn sums_the_watered_time() {
+        let events = [
+            event(100, "cycle_started", None),
+            event(100, "sector_activated", Some(1)),
+            event(400, "paused", Some(1)),
+            event(1_000, "resumed", Some(1)),
+            event(1_200, "sector_deactivated", Some(1)),
+            event(1_220, "sector_activated", Some(2)),
+            event(1_820, "sector_deactivated", Some(2)),
+            event(1_820, "cycle_completed", None),
+        ];
+        let summary = DaySummary::from_events(0, &events);
+        assert_eq!(summary.cycles, 1);
+        assert_eq!(summary.pauses, 1);
+        assert_eq!(summary.watered, BTreeMap::from([(1, 500), (2, 600)]));
+        assert_eq!(summary.runs, [(1, 100, 400), (1, 1_000, 1_200), (2, 1_220, 1_820)]);
+    }
+}
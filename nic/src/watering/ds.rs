@@ -1,21 +1,40 @@
-use super::modes::Mode;
+use super::{
+    calibration::{Calibrated, CalibrationMeasure},
+    daily_report::DailyReport,
+    learning::LearnedParams,
+    modes::Mode,
+    test_run::TestRun,
+    watering_alg::{Schedule, SectorTarget},
+};
 use crate::{
-    api::{CycleResponse, WateringStateResponse},
-    db::DatabaseTrait,
+    api::{CycleResponse, MachineStatus, WateringStateResponse},
+    clock::{ClockGuard, ClockJump},
+    config::{manager::ConfigManager, Config, SectorCfg},
+    db::{
+        maintenance::{DbCheck, DbHealth},
+        DatabaseTrait,
+    },
     error::AppError,
-    sensors::interface::SensorController,
+    links::Links,
+    sensors::{
+        interface::{SensorController, ValveState},
+        interlock::Interlock,
+        telemetry::DeviceTelemetry,
+    },
+    supervisor::Supervisor,
     time::TimeProvider,
+    weather::{freshness::WeatherFreshness, generator::SyntheticWeather, model::EtModel},
 };
 use std::{fmt::Display, sync::Arc};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use tokio::sync::{
     broadcast::{Receiver, Sender},
-    Mutex,
+    oneshot, Mutex,
 };
 
 pub type WeeklyPlan = Vec<(i64, DailyPlan)>; // A week's plan: date -> daily plan
 
-#[derive(Debug, Default, Clone, PartialEq)]
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
 pub struct DailyPlan(pub Vec<WaterSector>); // A day's plan: (sector_id , start time,  duration)
 
 impl DailyPlan {
@@ -24,7 +43,7 @@ impl DailyPlan {
     }
 
     pub fn is_watering_time(&self, current_time: i64) -> bool {
-        self.0.first().map_or(false, |first_sector| first_sector.start <= current_time)
+        self.0.first().is_some_and(|first_sector| first_sector.start <= current_time)
     }
 
     pub fn get_cycle(&self, current_time: i64) -> Option<Cycle> {
@@ -35,6 +54,7 @@ impl DailyPlan {
 #[derive(Debug, Clone, Default)]
 pub struct SectorInfo {
     pub id: u32,
+    pub name: String,
     /// cm /hour
     pub sprinkler_debit: f64, // cm/hour (sprinkler output rate)
     /// mm/hour
@@ -47,6 +67,14 @@ pub struct SectorInfo {
     pub progress: f64,
     /// last watered
     pub last_water: i64,
+    /// weather pauses wait for the next sector
+    pub ignore_weather_pause: bool,
+    /// mm per watering day
+    pub max_daily_mm: Option<f64>,
+    /// minutes per watering day
+    pub max_daily_minutes: Option<i64>,
+    /// gets all of its ET back in the water restrictions
+    pub deficit_exempt: bool,
 }
 
 impl SectorInfo {
@@ -54,11 +82,44 @@ impl SectorInfo {
         id: u32, weekly_target: f64, sprinkler_debit: f64, max_duration: i64, progress: f64, percolation_rate: f64,
         last_water: i64,
     ) -> SectorInfo {
-        SectorInfo { id, weekly_target, sprinkler_debit, percolation_rate, max_duration, progress, last_water }
+        SectorInfo {
+            id,
+            name: String::new(),
+            weekly_target,
+            sprinkler_debit,
+            percolation_rate,
+            max_duration,
+            progress,
+            last_water,
+            ignore_weather_pause: false,
+            max_daily_mm: None,
+            max_daily_minutes: None,
+            deficit_exempt: false,
+        }
+    }
+}
+
+/// A sector new to the database, nothing watered yet
+impl From<&SectorCfg> for SectorInfo {
+    fn from(cfg: &SectorCfg) -> Self {
+        SectorInfo {
+            id: cfg.id,
+            name: cfg.name.clone(),
+            sprinkler_debit: cfg.sprinkler_debit,
+            percolation_rate: cfg.percolation_rate,
+            max_duration: cfg.max_duration,
+            weekly_target: cfg.weekly_target,
+            progress: 0.,
+            last_water: 0,
+            ignore_weather_pause: cfg.ignore_weather_pause,
+            max_daily_mm: cfg.max_daily_mm,
+            max_daily_minutes: cfg.max_daily_minutes,
+            deficit_exempt: cfg.deficit_exempt,
+        }
     }
 }
 
-#[derive(Debug, Default, Clone, Copy, PartialEq, Ord, PartialOrd, Eq)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Ord, PartialOrd, Eq, Serialize, Deserialize)]
 pub struct WaterSector {
     pub id: u32,
     pub start: i64,
@@ -76,17 +137,20 @@ impl WaterSector {
     }
 }
 
-#[derive(Debug, Default, Clone, PartialEq)]
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Cycle {
     pub id: i64,
     pub daily_plan: DailyPlan,
     pub curr_sector: usize,
+    /// its `CycleRun`, none for a test run
+    #[serde(default)]
+    pub run: Option<u32>,
 }
 
 impl Cycle {
     pub fn build(daily_plan: DailyPlan) -> Self {
         assert!(!daily_plan.0.is_empty());
-        Cycle { id: daily_plan.0[0].start, daily_plan, curr_sector: usize::MAX }
+        Cycle { id: daily_plan.0[0].start, daily_plan, curr_sector: usize::MAX, run: None }
     }
 
     pub fn get_start(&self) -> Option<i64> {
@@ -103,7 +167,8 @@ impl Cycle {
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum WeatherSignal {
     RainStart,
     RainStop,
@@ -111,6 +176,17 @@ pub enum WeatherSignal {
     WindLow,
 }
 
+impl WeatherSignal {
+    /// The signal this one ends: `RainStop` ends `RainStart`, `WindLow` ends `WindHigh`
+    pub fn ends(&self) -> Option<WeatherSignal> {
+        match self {
+            WeatherSignal::RainStop => Some(WeatherSignal::RainStart),
+            WeatherSignal::WindLow => Some(WeatherSignal::WindHigh),
+            WeatherSignal::RainStart | WeatherSignal::WindHigh => None,
+        }
+    }
+}
+
 impl Display for WeatherSignal {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let mode = match *self {
@@ -133,26 +209,293 @@ pub struct WeatherData{
     pub et: Option<f64>
 }
 
+/// The wind of the station between its observations, every few seconds from a Tempest hub
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RapidWind {
+    pub timestamp: i64,
+    /// km/h
+    pub speed: f64,
+    pub direction: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ValveAction {
+    Open,
+    Close,
+}
+
+/// A valve command that kept failing after all the retries
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SectorFault {
+    pub sector: u32,
+    pub action: ValveAction,
+    pub error: String,
+}
+
+/// A valve didn't end up in the state we commanded
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AlarmEvent {
+    pub timestamp: i64,
+    pub sector: u32,
+    pub expected: ValveState,
+    /// last state read, `None` when the read itself failed
+    pub actual: Option<ValveState>,
+}
+
+/// Water drawn from a source, summed over the days asked for
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SourceUsage {
+    pub source: String,
+    /// seconds its master valve fed a sector
+    pub secs: i64,
+    pub litres: f64,
+}
+
+/// A row of the `audit_log` table: an API call that could change the controller
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub timestamp: i64,
+    /// of the client, none when the server doesn't know it
+    pub ip: Option<String>,
+    /// the `X-User` header, as the client states it
+    pub user: Option<String>,
+    /// method and path, e.g. `POST /switch/auto`
+    pub action: String,
+    /// HTTP status of the answer
+    pub status: u16,
+}
+
+/// A row of the `events` audit table: a state machine transition and why it happened
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SystemEvent {
+    pub timestamp: i64,
+    /// cycle_started, cycle_completed, cycle_aborted, sector_activated, sector_deactivated, paused, resumed,
+    /// pause_abandoned, mode_changed, plan_recalculated, emergency_stop, emergency_stop_cleared, and the
+    /// dry_run_activated, dry_run_deactivated valve commands of the logging backend
+    pub kind: String,
+    pub sector: Option<u32>,
+    pub cycle: Option<i64>,
+    pub detail: String,
+}
+
+/// What a state machine transition did
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum StateChange {
+    CycleStarted { mode: Mode },
+    /// a cycle of every sector, to check the heads
+    TestRunStarted { sectors: usize, seconds_per_sector: i64 },
+    CycleCompleted,
+    CycleAborted { reason: Option<String> },
+    SectorActivated { duration: i64 },
+    SectorDeactivated,
+    /// the sector is cut to the seconds its daily cap has left, 0 when it is skipped
+    DailyCapReached { allowed: i64 },
+    Paused { signal: WeatherSignal, elapsed: i64 },
+    Resumed { remaining: i64, duration: i64 },
+    PauseAbandoned { paused_secs: i64, signals: Vec<WeatherSignal> },
+    /// the sector and cycle of the event are the ones the change stopped
+    ModeChanged { from: Mode, to: Mode },
+    /// sectors per daily plan
+    PlanRecalculated { wizard: Vec<usize>, auto: Vec<usize> },
+    EmergencyStop,
+    EmergencyStopCleared,
+    /// the debit, cm/hour, a calibration run measured
+    Calibrated { debit: f64, previous: f64 },
+}
+
+impl StateChange {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            StateChange::CycleStarted { .. } => "cycle_started",
+            StateChange::TestRunStarted { .. } => "test_run_started",
+            StateChange::CycleCompleted => "cycle_completed",
+            StateChange::CycleAborted { .. } => "cycle_aborted",
+            StateChange::SectorActivated { .. } => "sector_activated",
+            StateChange::SectorDeactivated => "sector_deactivated",
+            StateChange::Paused { .. } => "paused",
+            StateChange::Resumed { .. } => "resumed",
+            StateChange::PauseAbandoned { .. } => "pause_abandoned",
+            StateChange::ModeChanged { .. } => "mode_changed",
+            StateChange::PlanRecalculated { .. } => "plan_recalculated",
+            StateChange::EmergencyStop => "emergency_stop",
+            StateChange::EmergencyStopCleared => "emergency_stop_cleared",
+            StateChange::DailyCapReached { .. } => "daily_cap_reached",
+            StateChange::Calibrated { .. } => "calibrated",
+        }
+    }
+}
+
+/// The human readable detail stored in the audit table
+impl Display for StateChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let join = |items: &[usize]| items.iter().map(|n| n.to_string()).collect::<Vec<_>>().join("+");
+        match self {
+            StateChange::CycleStarted { mode } => write!(f, "{} mode", mode),
+            StateChange::TestRunStarted { sectors, seconds_per_sector } => {
+                write!(f, "{} sectors, {}s each", sectors, seconds_per_sector)
+            }
+            StateChange::CycleAborted { reason: Some(reason) } => f.write_str(reason),
+            StateChange::CycleCompleted
+            | StateChange::CycleAborted { .. }
+            | StateChange::SectorDeactivated
+            | StateChange::EmergencyStop
+            | StateChange::EmergencyStopCleared => Ok(()),
+            StateChange::SectorActivated { duration } => write!(f, "for {}s", duration),
+            StateChange::DailyCapReached { allowed } => write!(f, "{}s left of the daily cap", allowed),
+            StateChange::Paused { signal, elapsed } => write!(f, "{} after {}s", signal, elapsed),
+            StateChange::Resumed { remaining, duration } => {
+                write!(f, "{}s left, {}s fit in the window", remaining, duration)
+            }
+            StateChange::PauseAbandoned { paused_secs, signals } => {
+                let signals = signals.iter().map(|s| s.to_string()).collect::<Vec<_>>().join(", ");
+                write!(f, "paused for {}s by {}", paused_secs, signals)
+            }
+            StateChange::ModeChanged { from, to } => write!(f, "{} -> {}", from, to),
+            StateChange::Calibrated { debit, previous } => write!(f, "{:.2} cm/h, was {:.2}", debit, previous),
+            StateChange::PlanRecalculated { wizard, auto } => {
+                write!(f, "wizard {} sectors, auto {} sectors", join(wizard), join(auto))
+            }
+        }
+    }
+}
+
+/// Emitted by the state machine on every transition, as `CtrlSignal::StateChanged`.<br>
+/// Streamed to the WebSocket clients and stored in the `events` audit table.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StateEvent {
+    pub timestamp: i64,
+    pub sector: Option<u32>,
+    pub cycle: Option<i64>,
+    #[serde(flatten)]
+    pub change: StateChange,
+}
+
+impl From<&StateEvent> for SystemEvent {
+    fn from(evt: &StateEvent) -> Self {
+        SystemEvent {
+            timestamp: evt.timestamp,
+            kind: evt.change.kind().to_owned(),
+            sector: evt.sector,
+            cycle: evt.cycle,
+            detail: evt.change.to_string(),
+        }
+    }
+}
+
+/// Where the answer to a query goes, straight to the one caller that asked.<br>
+/// Clones share the slot, as the signal is broadcast, and only the first answer is delivered.
+pub struct Reply<T>(Arc<std::sync::Mutex<Option<oneshot::Sender<T>>>>);
+
+impl<T> Reply<T> {
+    pub fn new() -> (Self, oneshot::Receiver<T>) {
+        let (tx, rx) = oneshot::channel();
+        (Self(Arc::new(std::sync::Mutex::new(Some(tx)))), rx)
+    }
+
+    /// False when already answered or the caller is gone
+    pub fn send(&self, value: T) -> bool {
+        let tx = self.0.lock().unwrap().take();
+        tx.is_some_and(|tx| tx.send(value).is_ok())
+    }
+}
+
+impl<T> Clone for Reply<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T> std::fmt::Debug for Reply<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Reply")
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum CtrlSignal {
     Weather(WeatherSignal),
     WeatherData(WeatherData),
+    /// only for the WebSocket clients that ask for it
+    RapidWind(RapidWind),
     StopMachine,
-    GenWeather(String),
+    /// starts the synthetic weather, in place of the one going on, or stops it
+    GenWeather(Option<SyntheticWeather>),
     DevicesState(String),
     ChgMode(Mode),
-    GetState,
-    GetStateResponse(WateringStateResponse),
-    GetCycle,
-    GetCycleResponse(CycleResponse),
+    GetState(Reply<WateringStateResponse>),
+    GetCycle(Reply<CycleResponse>),
+    GetStatus(Reply<MachineStatus>),
+    SectorFault(SectorFault),
+    Alarm(AlarmEvent),
+    LowBattery(DeviceTelemetry),
+    /// critical: a valve kept watering after it was closed
+    StuckValve(AlarmEvent),
+    StateChanged(StateEvent),
+    /// closes every valve and latches the machine until `ClearEmergencyStop`
+    EmergencyStop,
+    ClearEmergencyStop,
+    /// the running config after a reload, only the runtime safe parts changed
+    ConfigUpdate(Arc<Config>),
+    /// the weekly schedule of the auto mode, already saved
+    ScheduleUpdate(Schedule),
+    /// sectors an import added to the database, before the schedule that waters them
+    SectorsAdded(Vec<SectorInfo>),
+    /// every sector for the given seconds, answered with the run or why it can't start
+    TestRun(i64, Reply<Result<TestRun, String>>),
+    /// the running or last test run
+    GetTestRun(Reply<Option<TestRun>>),
+    /// waters the sectors once, in order, each for its seconds, answered with the cycle or why it can't start
+    ManualStart(Vec<(u32, i64)>, Reply<Result<i64, String>>),
+    /// runs a sector alone for the given seconds, answered with the run or why it can't start
+    Calibrate(u32, i64, Reply<Result<TestRun, String>>),
+    /// what was measured over the calibration run of a sector, answered with its new debit
+    CalibrationResult(u32, CalibrationMeasure, Reply<Result<Calibrated, String>>),
+    /// the nightly summary, when `notify_daily_report` is on
+    DailyReport(DailyReport),
+    /// a receiver fell behind and lost signals, whoever keeps state sends it again
+    Resync,
+    /// an accepted debit and percolation estimate, already written to the sector
+    LearnedAccepted(LearnedParams),
+    /// the new seasonal curve of a sector, already saved
+    SectorTargets(u32, Vec<SectorTarget>),
+    /// the periodic check found the database damaged, or couldn't run
+    DbIntegrity(DbCheck),
+    /// the wall clock moved away from the time elapsed
+    ClockJump(ClockJump),
 }
 
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct WeatherConditions {
+    /// Unix UTC timestamp of the observation
+    pub timestamp: i64,
+    /// rain fell during the observation interval, `rain > 0`
     pub is_raining: bool,
-    pub wind_speed: f64, // in km/h or m/s
+    pub wind_speed: f64, // km/h
+    pub wind_gust: f64,  // km/h
+    pub wind_direction: f64,
     pub temperature: f64,
     pub humidity: f64,
     pub solar_radiation: f64,
+    /// mm accumulated during the observation interval
+    pub rain: f64,
+    /// mm/hour
+    pub rain_rate: f64,
+}
+
+impl From<&WeatherConditions> for WeatherData {
+    fn from(obs: &WeatherConditions) -> Self {
+        WeatherData {
+            rain: obs.rain,
+            wind_intensity: obs.wind_speed,
+            wind_direction: obs.wind_direction,
+            humidity: obs.humidity,
+            rain_probability: None,
+            et: None,
+        }
+    }
 }
 
 pub struct AppState {
@@ -162,21 +505,71 @@ pub struct AppState {
     pub web_tx: tokio::sync::broadcast::Sender<CtrlSignal>,
     pub sm_rx: Arc<Mutex<Receiver<CtrlSignal>>>,
     pub sensors_ctrl: Arc<dyn SensorController>,
+    /// the same controller as `sensors_ctrl`, for its status
+    pub interlock: Arc<Interlock>,
+    pub config: Arc<ConfigManager>,
     pub time_provider: Arc<dyn TimeProvider>,
+    pub et_model: Arc<dyn EtModel>,
+    pub freshness: Arc<WeatherFreshness>,
+    pub links: Arc<Links>,
+    pub supervisor: Arc<Supervisor>,
+    /// the last database integrity check
+    pub db_health: Arc<DbHealth>,
+    /// whether the time can be trusted for scheduling
+    pub clock: Arc<ClockGuard>,
 }
 
 impl AppState {
+    #[allow(clippy::too_many_arguments)]
     pub async fn new(
-        db: Arc<dyn DatabaseTrait>, sensors_ctrl: Arc<dyn SensorController>, time_provider: Arc<dyn TimeProvider>,
-        sm_tx: Arc<Sender<CtrlSignal>>, sm_rx: Arc<Mutex<Receiver<CtrlSignal>>>, web_tx: tokio::sync::broadcast::Sender<CtrlSignal>,
-        web_rx: tokio::sync::broadcast::Receiver<CtrlSignal>,
+        db: Arc<dyn DatabaseTrait>, interlock: Arc<Interlock>, time_provider: Arc<dyn TimeProvider>,
+        sm_tx: Arc<Sender<CtrlSignal>>, sm_rx: Arc<Mutex<Receiver<CtrlSignal>>>,
+        web_tx: tokio::sync::broadcast::Sender<CtrlSignal>, web_rx: tokio::sync::broadcast::Receiver<CtrlSignal>,
+        et_model: Arc<dyn EtModel>, freshness: Arc<WeatherFreshness>, config: Arc<ConfigManager>, links: Arc<Links>,
     ) -> Result<Arc<Self>, AppError> {
-        Ok(Arc::new(AppState { db, sm_tx, sm_rx, web_tx, web_rx, sensors_ctrl, time_provider }))
+        let sensors_ctrl = interlock.clone();
+        let supervisor = Arc::new(Supervisor::new(time_provider.clone()));
+        Ok(Arc::new(AppState {
+            db,
+            sm_tx,
+            sm_rx,
+            web_tx,
+            web_rx,
+            sensors_ctrl,
+            interlock,
+            config,
+            time_provider,
+            et_model,
+            freshness,
+            links,
+            supervisor,
+            db_health: Arc::default(),
+            clock: Arc::default(),
+        }))
     }
 }
 
-#[derive(Debug)]
+/// A cycle as it ran, the sectors of its plan against the watering events that name it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CycleRun {
+    /// the `cycle_id` of its watering events
+    pub id: u32,
+    /// the start of its plan, `Cycle::id`
+    pub cycle: i64,
+    pub mode: Mode,
+    pub planned: Vec<WaterSector>,
+    /// what the valves watered
+    pub executed: Vec<WaterSector>,
+    pub start: i64,
+    /// none while it runs, or when the machine stopped in it
+    pub end: Option<i64>,
+    /// the kind of the event that ended it: `cycle_completed`, `cycle_aborted` or `pause_abandoned`
+    pub outcome: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct WateringEvent {
+    /// the `CycleRun` it was part of
     pub cycle_id: Option<u32>,
     pub sector: WaterSector,
     pub water_applied: f64,
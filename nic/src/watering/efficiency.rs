@@ -0,0 +1,165 @@
+use super::ds::{SectorInfo, SystemEvent, WaterSector, WateringEvent};
+use crate::db::DatabaseTrait;
+use crate::utils::sod;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+const DAY_SECS: i64 = 86_400;
+const WEEK_SECS: i64 = 7 * DAY_SECS;
+
+/// Weeks the nightly report looks back
+pub const REPORT_WEEKS: i64 = 4;
+
+/// How a sector did over the weeks, what the wizard planned against what went in
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SectorEfficiency {
+    pub sector: u32,
+    /// seconds of the wizard plans, and of the valve open
+    pub planned_secs: i64,
+    pub watered_secs: i64,
+    /// planned seconds that weren't watered
+    pub lost_secs: i64,
+    /// cm from the valve, and from the sky
+    pub applied_cm: f64,
+    pub rain_cm: f64,
+    /// of the weekly target, what went in (water and rain) on average, 1 is the target
+    pub achievement: f64,
+    /// weeks it got at least its target
+    pub weeks_met: usize,
+    /// weather pauses of the sector, the ones that timed out, and the seconds it waited
+    pub pauses: usize,
+    pub pauses_abandoned: usize,
+    pub paused_secs: i64,
+}
+
+/// Whole weeks from `from`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Period {
+    pub from: i64,
+    pub weeks: i64,
+}
+
+impl Period {
+    /// The weeks up to the start of the day of `now`
+    pub fn until(now: i64, weeks: i64) -> Self {
+        Self { from: sod(now) - weeks * WEEK_SECS, weeks }
+    }
+
+    pub fn to(&self) -> i64 {
+        self.from + self.weeks * WEEK_SECS
+    }
+
+    fn week_of(&self, time: i64) -> Option<usize> {
+        (self.from..self.to()).contains(&time).then(|| ((time - self.from) / WEEK_SECS) as usize)
+    }
+}
+
+/// Every sector, by id. `rain_cm` is the rain of each week of the period.
+pub fn analyse(
+    sectors: &HashMap<u32, SectorInfo>, period: Period, planned: &[WaterSector], watered: &[WateringEvent],
+    rain_cm: &[f64], events: &[SystemEvent],
+) -> Vec<SectorEfficiency> {
+    let mut ids: Vec<u32> = sectors.keys().copied().collect();
+    ids.sort_unstable();
+    let rain: f64 = rain_cm.iter().sum();
+    ids.into_iter()
+        .map(|id| {
+            let mut stats = SectorEfficiency { sector: id, rain_cm: rain, ..Default::default() };
+            stats.planned_secs = planned.iter().filter(|sec| sec.id == id).map(|sec| sec.duration).sum();
+            let mut applied = vec![0.; period.weeks as usize];
+            for evt in watered.iter().filter(|evt| evt.sector.id == id) {
+                stats.watered_secs += evt.sector.duration;
+                stats.applied_cm += evt.water_applied;
+                if let Some(week) = period.week_of(evt.sector.start) {
+                    applied[week] += evt.water_applied;
+                }
+            }
+            stats.lost_secs = (stats.planned_secs - stats.watered_secs).max(0);
+
+            let target = sectors[&id].weekly_target;
+            let weekly = applied.iter().zip(rain_cm).map(|(water, rain)| match target {
+                target if target > 0. => (water + rain) / target,
+                _ => 1.,
+            });
+            let weekly: Vec<f64> = weekly.collect();
+            stats.weeks_met = weekly.iter().filter(|&&week| week >= 1.).count();
+            stats.achievement = weekly.iter().sum::<f64>() / weekly.len().max(1) as f64;
+
+            let mut paused_at = None;
+            for evt in events.iter().filter(|evt| evt.sector == Some(id)) {
+                match evt.kind.as_str() {
+                    "paused" => {
+                        stats.pauses += 1;
+                        paused_at = Some(evt.timestamp);
+                    }
+                    "resumed" | "pause_abandoned" | "cycle_aborted" => {
+                        if let Some(at) = paused_at.take() {
+                            stats.paused_secs += evt.timestamp - at;
+                        }
+                        stats.pauses_abandoned += usize::from(evt.kind == "pause_abandoned");
+                    }
+                    _ => {}
+                }
+            }
+            stats
+        })
+        .collect()
+}
+
+/// The analysis of the `weeks` before today, from the stored plans, watering events, rain rollups and system events
+pub fn efficiency(
+    db: &dyn DatabaseTrait, sectors: &HashMap<u32, SectorInfo>, now: i64, weeks: i64,
+) -> rusqlite::Result<Vec<SectorEfficiency>> {
+    let period = Period::until(now, weeks);
+    let (from, to) = (period.from, period.to());
+    let planned = db.load_wizard_plans(from, to)?;
+    let watered = db.load_watering_events(from, to)?;
+    let events = db.load_system_events(from, to)?;
+    // mm per day, the rollup of a day is asked for with a time in the next one
+    let rain_cm: Vec<f64> = (0..weeks)
+        .map(|week| {
+            let start = from + week * WEEK_SECS;
+            (0..7).filter_map(|day| db.get_lastday_rain(start + (day + 1) * DAY_SECS)).sum::<f64>() / 10.
+        })
+        .collect();
+    Ok(analyse(sectors, period, &planned, &watered, &rain_cm, &events))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::watering::modes::Mode;
+
+    #[test]
+    fn planned_against_watered() {
+        let sector = |id, weekly_target| (id, SectorInfo { id, weekly_target, ..Default::default() });
+        let sectors = HashMap::from([sector(1, 2.), sector(2, 3.)]);
+        let period = Period { from: 0, weeks: 2 };
+        let planned = [WaterSector::new(1, 79_200, 3600), WaterSector::new(2, 82_820, 1800)];
+        let watered = [
+            WateringEvent::new(Some(1), WaterSector::new(1, 79_200, 3600), 1.5, Mode::Wizard),
+            WateringEvent::new(Some(1), WaterSector::new(2, 82_820, 600), 0.5, Mode::Wizard),
+            WateringEvent::new(None, WaterSector::new(2, WEEK_SECS + 3600, 1800), 1., Mode::Manual),
+        ];
+        let evt = |timestamp, kind: &str| SystemEvent {
+            timestamp,
+            kind: kind.to_owned(),
+            sector: Some(2),
+            cycle: Some(1),
+            detail: String::new(),
+        };
+        let events =
+            [evt(83_420, "paused"), evt(84_020, "resumed"), evt(84_100, "paused"), evt(87_700, "pause_abandoned")];
+
+        let stats = analyse(&sectors, period, &planned, &watered, &[0.5, 2.], &events);
+        assert_eq!(stats.len(), 2);
+        let (lawn, beds) = (&stats[0], &stats[1]);
+        assert_eq!((lawn.planned_secs, lawn.watered_secs, lawn.lost_secs), (3600, 3600, 0));
+        // 2 of 2, then 2 of 2 from the rain
+        assert_eq!((lawn.achievement, lawn.weeks_met, lawn.rain_cm), (1., 2, 2.5));
+        assert_eq!((beds.planned_secs, beds.watered_secs, beds.lost_secs), (1800, 2400, 0));
+        assert_eq!((beds.pauses, beds.pauses_abandoned, beds.paused_secs), (2, 1, 4200));
+        assert_eq!(beds.weeks_met, 1);
+        assert!((beds.achievement - (1. / 3. + 1.) / 2.).abs() < 1e-9);
+    }
+}
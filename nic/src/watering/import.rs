@@ -0,0 +1,555 @@
+//! The schedules of other controllers, so moving to nic doesn't start with writing the week again
+
+use super::{
+    ds::{DailyPlan, SectorInfo, WaterSector},
+    schedule_file::seconds_of_day,
+    watering_alg::{is_program_name, Schedule, ScheduleEntry, ScheduleType},
+};
+use crate::{config::SectorCfg, error::AppError};
+use chrono::Weekday;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt,
+    path::Path,
+};
+
+/// What a sector gets when the other controller doesn't know, as the example sectors of nic.toml
+const DEFAULT_DEBIT: f64 = 1.0; // cm/h
+const DEFAULT_PERCOLATION: f64 = 0.5; // mm/h
+const DEFAULT_WEEKLY_TARGET: f64 = 2.5; // cm
+const DEFAULT_MAX_DURATION: i64 = 1800;
+const MM_PER_INCH: f64 = 25.4;
+/// Rachio counts the days of the week from Sunday
+const SUNDAY_FIRST: [Weekday; 7] =
+    [Weekday::Sun, Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri, Weekday::Sat];
+
+/// The controllers a schedule can be brought from
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ControllerFormat {
+    /// the device of the Rachio API, with its `zones` and `scheduleRules`, or the person with its `devices`
+    Rachio,
+    /// an ini of the programs: `n = name` lines in `[stations]`, and a `[program <name>]` section each with
+    /// `enabled`, `days` (mon, wed, ...), `starts` (HH:MM, ...) and `durations` (seconds by station, 0 for none)
+    #[value(name = "opensprinkler")]
+    OpenSprinkler,
+}
+
+impl ControllerFormat {
+    /// From the file extension, OpenSprinkler for `.ini`, Rachio otherwise
+    pub fn of(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("ini") => ControllerFormat::OpenSprinkler,
+            _ => ControllerFormat::Rachio,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            ControllerFormat::Rachio => "rachio",
+            ControllerFormat::OpenSprinkler => "opensprinkler",
+        }
+    }
+}
+
+/// A schedule brought from another controller, with its sectors
+#[derive(Debug, Clone)]
+pub struct Imported {
+    /// the programs disabled there are disabled here
+    pub schedule: Schedule,
+    /// the zones or stations of the controller, with what it knew of them
+    pub sectors: Vec<SectorCfg>,
+    /// what nic has no place for, a line each
+    pub skipped: Vec<String>,
+}
+
+impl Imported {
+    /// The sectors the database doesn't have yet, those it has keep their settings
+    pub fn new_sectors(&self, known: &HashMap<u32, SectorInfo>) -> Vec<SectorCfg> {
+        self.sectors.iter().filter(|sector| !known.contains_key(&sector.id)).cloned().collect()
+    }
+
+    pub fn report(&self, sectors_added: &[SectorCfg]) -> ImportReport {
+        let programs = self.schedule.programs().into_iter().map(|(program, on)| (program.to_owned(), on)).collect();
+        ImportReport {
+            days: self.schedule.entries.len(),
+            programs,
+            sectors_added: sectors_added.iter().map(|sector| sector.id).collect(),
+            skipped: self.skipped.clone(),
+        }
+    }
+}
+
+/// What an import did
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImportReport {
+    pub days: usize,
+    /// the programs, and whether they are enabled
+    pub programs: BTreeMap<String, bool>,
+    pub sectors_added: Vec<u32>,
+    pub skipped: Vec<String>,
+}
+
+impl fmt::Display for ImportReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let programs: Vec<String> = self
+            .programs
+            .iter()
+            .map(|(program, on)| if *on { program.clone() } else { format!("{} (disabled)", program) })
+            .collect();
+        write!(f, "{} day(s) imported, programs {}", self.days, programs.join(", "))?;
+        if !self.sectors_added.is_empty() {
+            let sectors: Vec<String> = self.sectors_added.iter().map(u32::to_string).collect();
+            write!(f, "\nsectors added: {}", sectors.join(", "))?;
+        }
+        for skipped in &self.skipped {
+            write!(f, "\nleft out: {}", skipped)?;
+        }
+        Ok(())
+    }
+}
+
+/// The whole schedule of the other controller, a run of an unknown zone or a time that isn't one fails the import.
+/// Each program waters its sectors one after the other from its start, `transition` seconds apart. The times are
+/// taken as UTC, as the rest of the schedule, and what has no weekday, intervals or odd days, is left out.
+pub fn import_controller(content: &str, format: ControllerFormat, transition: i64) -> Result<Imported, AppError> {
+    let imported = match format {
+        ControllerFormat::Rachio => from_rachio(content, transition),
+        ControllerFormat::OpenSprinkler => from_opensprinkler(content, transition),
+    };
+    imported.map_err(|e| AppError::ConfigError(format!("{}: {}", format.name(), e)))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RachioDevice {
+    zones: Vec<RachioZone>,
+    #[serde(default)]
+    schedule_rules: Vec<RachioRule>,
+    #[serde(default)]
+    flex_schedule_rules: Vec<serde::de::IgnoredAny>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RachioZone {
+    id: String,
+    zone_number: u32,
+    #[serde(default)]
+    name: String,
+    #[serde(default = "enabled")]
+    enabled: bool,
+    /// seconds
+    max_runtime: Option<i64>,
+    custom_nozzle: Option<RachioNozzle>,
+    custom_soil: Option<RachioSoil>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RachioNozzle {
+    inches_per_hour: Option<f64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RachioSoil {
+    /// inches/hour
+    infiltration_rate: Option<f64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RachioRule {
+    #[serde(default)]
+    name: String,
+    #[serde(default = "enabled")]
+    enabled: bool,
+    start_hour: i64,
+    #[serde(default)]
+    start_minute: i64,
+    /// `DAY_OF_WEEK_0` is Sunday, the intervals and the odd or even days have no weekday
+    #[serde(default)]
+    schedule_job_types: Vec<String>,
+    #[serde(default)]
+    zones: Vec<RachioRun>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RachioRun {
+    zone_id: String,
+    /// seconds
+    duration: i64,
+    #[serde(default)]
+    sort_order: i64,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+fn from_rachio(content: &str, transition: i64) -> Result<Imported, String> {
+    let mut skipped = vec![];
+    let mut value: serde_json::Value = serde_json::from_str(content).map_err(|e| e.to_string())?;
+    // the whole account, its first controller
+    if let Some(devices) = value.get_mut("devices").and_then(|devices| devices.as_array_mut()) {
+        if devices.len() > 1 {
+            skipped.push(format!("{} more devices, only the first is imported", devices.len() - 1));
+        }
+        value = devices.first_mut().map(serde_json::Value::take).ok_or("no devices")?;
+    }
+    let device: RachioDevice = serde_json::from_value(value).map_err(|e| e.to_string())?;
+    if !device.flex_schedule_rules.is_empty() {
+        skipped.push(format!("{} flex schedule(s), the wizard mode works them out", device.flex_schedule_rules.len()));
+    }
+
+    let zones: HashMap<&str, &RachioZone> = device.zones.iter().map(|zone| (zone.id.as_str(), zone)).collect();
+    let mut sectors: Vec<SectorCfg> = vec![];
+    for zone in device.zones.iter().filter(|zone| zone.enabled) {
+        let mut sector = new_sector(zone.zone_number, &zone.name);
+        if let Some(inches) = zone.custom_nozzle.as_ref().and_then(|nozzle| nozzle.inches_per_hour) {
+            sector.sprinkler_debit = inches * MM_PER_INCH / 10.;
+        }
+        if let Some(inches) = zone.custom_soil.as_ref().and_then(|soil| soil.infiltration_rate) {
+            sector.percolation_rate = inches * MM_PER_INCH;
+        }
+        sector.max_duration = zone.max_runtime.unwrap_or(DEFAULT_MAX_DURATION);
+        sectors.push(sector);
+    }
+    sectors.sort_by_key(|sector| sector.id);
+
+    let (mut entries, mut programs, mut disabled) = (vec![], vec![], BTreeSet::new());
+    for rule in &device.schedule_rules {
+        let mut days = vec![];
+        for job in &rule.schedule_job_types {
+            let day = job.strip_prefix("DAY_OF_WEEK_").and_then(|n| n.parse::<usize>().ok());
+            match day.and_then(|n| SUNDAY_FIRST.get(n)) {
+                Some(&day) => days.push(day),
+                None => skipped.push(format!("rule '{}': {}", rule.name, job)),
+            }
+        }
+        if days.is_empty() {
+            skipped.push(format!("rule '{}', it has no weekdays", rule.name));
+            continue;
+        }
+        if !(0..24).contains(&rule.start_hour) || !(0..60).contains(&rule.start_minute) {
+            return Err(format!("rule '{}' starts at {}:{}", rule.name, rule.start_hour, rule.start_minute));
+        }
+        let mut runs: Vec<&RachioRun> = rule.zones.iter().collect();
+        runs.sort_by_key(|run| run.sort_order);
+        let mut plan = vec![];
+        for run in runs {
+            let zone = zones
+                .get(run.zone_id.as_str())
+                .ok_or_else(|| format!("rule '{}' waters zone {}, not one of the device", rule.name, run.zone_id))?;
+            match zone.enabled {
+                true => plan.push((zone.zone_number, run.duration)),
+                false => skipped.push(format!("rule '{}': zone {}, it is disabled", rule.name, zone.zone_number)),
+            }
+        }
+        let program = program_name(&rule.name, &programs);
+        add_program(&mut entries, &program, &days, rule.start_hour * 3600 + rule.start_minute * 60, &plan, transition);
+        if !rule.enabled {
+            disabled.insert(program.clone());
+        }
+        programs.push(program);
+    }
+    Ok(Imported { schedule: schedule(entries, disabled), sectors, skipped })
+}
+
+#[derive(Default)]
+struct OsProgram {
+    name: String,
+    enabled: bool,
+    days: Vec<Weekday>,
+    starts: Vec<i64>,
+    durations: Vec<i64>,
+}
+
+enum Section {
+    None,
+    Stations,
+    Program,
+    Other,
+}
+
+fn from_opensprinkler(content: &str, transition: i64) -> Result<Imported, String> {
+    let (mut section, mut skipped) = (Section::None, vec![]);
+    let mut stations: BTreeMap<u32, String> = BTreeMap::new();
+    let mut os_programs: Vec<OsProgram> = vec![];
+    for (n, line) in content.lines().enumerate() {
+        let line = line.split([';', '#']).next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+            section = match header.trim().split_once(char::is_whitespace) {
+                _ if header.trim() == "stations" => Section::Stations,
+                Some(("program", name)) => {
+                    os_programs.push(OsProgram { name: name.trim().to_owned(), enabled: true, ..Default::default() });
+                    Section::Program
+                }
+                _ => {
+                    skipped.push(format!("[{}]", header.trim()));
+                    Section::Other
+                }
+            };
+            continue;
+        }
+        let (key, value) = line.split_once('=').ok_or_else(|| format!("line {} is not key = value", n + 1))?;
+        let (key, value) = (key.trim(), value.trim());
+        let list = || value.split(',').map(str::trim).filter(|item| !item.is_empty());
+        let bad = |item: &str, what: &str| format!("line {}: '{}' is not {}", n + 1, item, what);
+        match section {
+            Section::None => return Err(format!("line {} is outside a section", n + 1)),
+            Section::Other => (),
+            Section::Stations => {
+                let station = key.parse().ok().filter(|&station| station > 0).ok_or_else(|| bad(key, "a station"))?;
+                stations.insert(station, value.to_owned());
+            }
+            Section::Program => {
+                // the section was pushed with its header
+                let program = os_programs.last_mut().unwrap();
+                match key {
+                    "enabled" => {
+                        program.enabled = match value {
+                            "1" | "true" => true,
+                            "0" | "false" => false,
+                            _ => return Err(bad(value, "0 or 1")),
+                        }
+                    }
+                    "days" => {
+                        for day in list() {
+                            program.days.push(day.parse().map_err(|_| bad(day, "a weekday"))?);
+                        }
+                    }
+                    "starts" => {
+                        for start in list() {
+                            program.starts.push(seconds_of_day(start).ok_or_else(|| bad(start, "HH:MM"))?);
+                        }
+                    }
+                    "durations" => {
+                        for duration in list() {
+                            let secs = duration.parse().ok().filter(|&secs: &i64| secs >= 0);
+                            program.durations.push(secs.ok_or_else(|| bad(duration, "seconds"))?);
+                        }
+                    }
+                    _ => skipped.push(format!("program '{}': {}", program.name, key)),
+                }
+            }
+        }
+    }
+
+    let (mut entries, mut programs, mut disabled) = (vec![], vec![], BTreeSet::new());
+    for os_program in &os_programs {
+        if os_program.days.is_empty() || os_program.starts.is_empty() {
+            skipped.push(format!("program '{}', it has no days or no starts", os_program.name));
+            continue;
+        }
+        let plan: Vec<(u32, i64)> = (1..)
+            .zip(&os_program.durations)
+            .filter(|(_, duration)| **duration > 0)
+            .map(|(station, duration)| (station, *duration))
+            .collect();
+        for &(station, _) in &plan {
+            stations.entry(station).or_insert_with(|| format!("station {}", station));
+        }
+        let program = program_name(&os_program.name, &programs);
+        for &start in &os_program.starts {
+            add_program(&mut entries, &program, &os_program.days, start, &plan, transition);
+        }
+        if !os_program.enabled {
+            disabled.insert(program.clone());
+        }
+        programs.push(program);
+    }
+    let sectors = stations.iter().map(|(&station, name)| new_sector(station, name)).collect();
+    Ok(Imported { schedule: schedule(entries, disabled), sectors, skipped })
+}
+
+fn new_sector(id: u32, name: &str) -> SectorCfg {
+    SectorCfg {
+        id,
+        name: name.to_owned(),
+        sprinkler_debit: DEFAULT_DEBIT,
+        percolation_rate: DEFAULT_PERCOLATION,
+        weekly_target: DEFAULT_WEEKLY_TARGET,
+        max_duration: DEFAULT_MAX_DURATION,
+        ignore_weather_pause: false,
+        max_daily_mm: None,
+        max_daily_minutes: None,
+        deficit_exempt: false,
+    }
+}
+
+/// Its letters and digits, or the first letter not taken when that leaves none or a name already taken
+fn program_name(name: &str, taken: &[String]) -> String {
+    let name: String = name.chars().filter(char::is_ascii_alphanumeric).take(16).collect();
+    if is_program_name(&name) && !taken.contains(&name) {
+        return name;
+    }
+    let letters = ('A'..='Z').map(String::from);
+    letters.chain((1..).map(|n| format!("P{}", n))).find(|name| !taken.contains(name)).unwrap()
+}
+
+/// The sectors one after the other from `start`, on each of the days
+fn add_program(
+    entries: &mut Vec<ScheduleEntry>, program: &str, days: &[Weekday], start: i64, runs: &[(u32, i64)], transition: i64,
+) {
+    let mut plan = DailyPlan::new();
+    let mut at = start;
+    for &(sector, duration) in runs {
+        plan.0.push(WaterSector::new(sector, at, duration));
+        at += duration + transition;
+    }
+    for &day in days {
+        let found = entries
+            .iter_mut()
+            .find(|entry| entry.program == program && entry.schedule_type == ScheduleType::Weekday(day));
+        match found {
+            Some(entry) => entry.start_times.0.extend(plan.0.iter().copied()),
+            None => entries.push(ScheduleEntry {
+                program: program.to_owned(),
+                schedule_type: ScheduleType::Weekday(day),
+                start_times: plan.clone(),
+                cron: None,
+            }),
+        }
+    }
+}
+
+fn schedule(mut entries: Vec<ScheduleEntry>, disabled: BTreeSet<String>) -> Schedule {
+    for entry in entries.iter_mut() {
+        entry.start_times.0.sort_by_key(|sec| sec.start);
+    }
+    entries.retain(|entry| !entry.start_times.0.is_empty());
+    let mut schedule = Schedule::new(entries);
+    schedule.disabled =
+        disabled.into_iter().filter(|program| schedule.programs().contains_key(program.as_str())).collect();
+    schedule
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const RACHIO: &str = r#"{
+        "devices": [{
+            "name": "Front yard",
+            "zones": [
+                {"id": "z-1", "zoneNumber": 1, "name": "Lawn", "enabled": true, "maxRuntime": 3600,
+                 "customNozzle": {"name": "Rotor", "inchesPerHour": 0.5}, "customSoil": {"infiltrationRate": 0.2}},
+                {"id": "z-2", "zoneNumber": 2, "name": "Beds", "enabled": true},
+                {"id": "z-3", "zoneNumber": 3, "name": "Unused", "enabled": false}
+            ],
+            "scheduleRules": [
+                {"name": "Morning Lawn", "enabled": true, "startHour": 6, "startMinute": 30,
+                 "scheduleJobTypes": ["DAY_OF_WEEK_1", "DAY_OF_WEEK_3"],
+                 "zones": [{"zoneId": "z-2", "duration": 300, "sortOrder": 2},
+                           {"zoneId": "z-1", "duration": 600, "sortOrder": 1},
+                           {"zoneId": "z-3", "duration": 600, "sortOrder": 3}]},
+                {"name": "Summer", "enabled": false, "startHour": 21, "scheduleJobTypes": ["DAY_OF_WEEK_0"],
+                 "zones": [{"zoneId": "z-1", "duration": 900}]},
+                {"name": "Every 3 days", "startHour": 5, "scheduleJobTypes": ["INTERVAL_3"],
+                 "zones": [{"zoneId": "z-1", "duration": 900}]}
+            ],
+            "flexScheduleRules": [{"name": "Flex"}]
+        }]
+    }"#;
+
+    /// sector, start, duration
+    type Runs = Vec<(u32, i64, i64)>;
+
+    fn days(schedule: &Schedule) -> Vec<(&str, Weekday, Runs)> {
+        let mut days: Vec<_> = schedule
+            .entries
+            .iter()
+            .map(|entry| {
+                let ScheduleType::Weekday(day) = entry.schedule_type else { panic!("a date entry") };
+                let plan = entry.start_times.0.iter().map(|sec| (sec.id, sec.start, sec.duration)).collect();
+                (entry.program.as_str(), day, plan)
+            })
+            .collect();
+        days.sort_by_key(|(program, day, _)| (*program, day.num_days_from_monday()));
+        days
+    }
+
+    #[test]
+    fn rachio_rules_become_programs() {
+        let imported = import_controller(RACHIO, ControllerFormat::Rachio, 20).unwrap();
+        let morning = vec![(1, 6 * 3600 + 1800, 600), (2, 6 * 3600 + 1800 + 620, 300)];
+        assert_eq!(
+            days(&imported.schedule),
+            [
+                ("MorningLawn", Weekday::Mon, morning.clone()),
+                ("MorningLawn", Weekday::Wed, morning),
+                ("Summer", Weekday::Sun, vec![(1, 21 * 3600, 900)])
+            ]
+        );
+        assert!(!imported.schedule.is_enabled("Summer"));
+
+        let sectors: Vec<_> = imported.sectors.iter().map(|sec| (sec.id, sec.name.as_str())).collect();
+        assert_eq!(sectors, [(1, "Lawn"), (2, "Beds")]);
+        let lawn = &imported.sectors[0];
+        assert!((lawn.sprinkler_debit - 1.27).abs() < 1e-9 && (lawn.percolation_rate - 5.08).abs() < 1e-9);
+        assert_eq!((lawn.max_duration, imported.sectors[1].sprinkler_debit), (3600, DEFAULT_DEBIT));
+        assert_eq!(imported.skipped.len(), 4, "{:?}", imported.skipped);
+
+        let report = imported.report(&imported.new_sectors(&HashMap::from([(1, SectorInfo::default())])));
+        assert_eq!(report.sectors_added, [2]);
+        assert!(report.to_string().starts_with("3 day(s) imported, programs MorningLawn, Summer (disabled)"));
+    }
+
+    #[test]
+    fn opensprinkler_programs_water_each_start() {
+        let ini = "
+            ; exported from the controller
+            [stations]
+            1 = Lawn
+            2 = Beds
+
+            [program Lawn]
+            enabled = 1
+            days = mon, thu
+            starts = 06:00, 20:00
+            durations = 600, 0, 300
+            interval = 2
+
+            [program Lawn]
+            enabled = 0
+            days = sat
+            starts = 07:00
+            durations = 0, 900
+        ";
+        let imported = import_controller(ini, ControllerFormat::OpenSprinkler, 0).unwrap();
+        let lawn = vec![(1, 6 * 3600, 600), (3, 6 * 3600 + 600, 300), (1, 20 * 3600, 600), (3, 20 * 3600 + 600, 300)];
+        assert_eq!(
+            days(&imported.schedule),
+            [
+                ("A", Weekday::Sat, vec![(2, 7 * 3600, 900)]),
+                ("Lawn", Weekday::Mon, lawn.clone()),
+                ("Lawn", Weekday::Thu, lawn),
+            ]
+        );
+        assert!(!imported.schedule.is_enabled("A"));
+        let sectors: Vec<_> = imported.sectors.iter().map(|sec| (sec.id, sec.name.as_str())).collect();
+        assert_eq!(sectors, [(1, "Lawn"), (2, "Beds"), (3, "station 3")]);
+        assert_eq!(imported.skipped, ["program 'Lawn': interval"]);
+    }
+
+    #[test]
+    fn rejects_what_cant_be_read() {
+        let unknown_zone = r#"{"zones": [], "scheduleRules": [{"name": "A", "startHour": 6,
+            "scheduleJobTypes": ["DAY_OF_WEEK_1"], "zones": [{"zoneId": "gone", "duration": 60}]}]}"#;
+        let e = import_controller(unknown_zone, ControllerFormat::Rachio, 0).unwrap_err();
+        assert!(e.to_string().contains("rachio: rule 'A' waters zone gone"), "{}", e);
+        assert!(import_controller("[]", ControllerFormat::Rachio, 0).is_err());
+
+        let e = import_controller("[program A]\nstarts = 25:00\n", ControllerFormat::OpenSprinkler, 0).unwrap_err();
+        assert!(e.to_string().contains("line 2: '25:00' is not HH:MM"), "{}", e);
+        assert!(import_controller("days = mon\n", ControllerFormat::OpenSprinkler, 0).is_err());
+        assert_eq!(ControllerFormat::of(Path::new("backup.INI")), ControllerFormat::OpenSprinkler);
+    }
+}
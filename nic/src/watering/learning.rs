@@ -0,0 +1,287 @@
+use super::ds::{SectorInfo, SystemEvent};
+use crate::db::DatabaseTrait;
+use crate::utils::sod;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+const DAY_SECS: i64 = 86_400;
+
+/// Water held in a sector's root zone, from a soil moisture probe
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct MoistureReading {
+    pub sector: u32,
+    /// Unix UTC timestamp
+    pub timestamp: i64,
+    /// cm of water
+    pub water_cm: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LearnedStatus {
+    Pending,
+    Accepted,
+    Rejected,
+}
+
+impl LearnedStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LearnedStatus::Pending => "pending",
+            LearnedStatus::Accepted => "accepted",
+            LearnedStatus::Rejected => "rejected",
+        }
+    }
+
+    pub fn parse(status: &str) -> Option<Self> {
+        match status {
+            "pending" => Some(LearnedStatus::Pending),
+            "accepted" => Some(LearnedStatus::Accepted),
+            "rejected" => Some(LearnedStatus::Rejected),
+            _ => None,
+        }
+    }
+}
+
+/// What the readings say a sector really does, and what it was worked out from. Nothing changes until it is
+/// accepted.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LearnedParams {
+    pub sector: u32,
+    /// Unix UTC timestamp of the estimate
+    pub learned_at: i64,
+    /// cm/h
+    pub sprinkler_debit: f64,
+    /// mm/h
+    pub percolation_rate: f64,
+    /// the values it replaces
+    pub previous_debit: f64,
+    pub previous_percolation: f64,
+    /// intervals between two readings the fit used, and the time they span
+    pub samples: usize,
+    pub from: i64,
+    pub to: i64,
+    /// cm, what the fit leaves unexplained, per interval
+    pub rmse: f64,
+    pub status: LearnedStatus,
+}
+
+/// The time between two readings of a sector
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sample {
+    pub hours: f64,
+    pub watered_hours: f64,
+    /// cm, rain less ET
+    pub weather_cm: f64,
+    /// cm, from the first reading to the second
+    pub change_cm: f64,
+}
+
+/// Least squares of `change - weather = debit * watered_hours - percolation / 10 * hours`, with no intercept.<br>
+/// `(debit cm/h, percolation mm/h, rmse cm)`, none when the samples can't tell the two apart (no watering in any
+/// of them) or the answer makes no physical sense.
+pub fn fit(samples: &[Sample]) -> Option<(f64, f64, f64)> {
+    let (mut s11, mut s12, mut s22, mut s1y, mut s2y) = (0., 0., 0., 0., 0.);
+    for sample in samples {
+        let (x1, x2, y) = (sample.watered_hours, -sample.hours / 10., sample.change_cm - sample.weather_cm);
+        s11 += x1 * x1;
+        s12 += x1 * x2;
+        s22 += x2 * x2;
+        s1y += x1 * y;
+        s2y += x2 * y;
+    }
+    let det = s11 * s22 - s12 * s12;
+    if det.abs() < 1e-9 {
+        return None;
+    }
+    let debit = (s1y * s22 - s2y * s12) / det;
+    let percolation = (s2y * s11 - s1y * s12) / det;
+    if debit <= 0. || percolation < 0. {
+        return None;
+    }
+    let residuals: f64 = samples
+        .iter()
+        .map(|s| s.change_cm - s.weather_cm - debit * s.watered_hours + percolation / 10. * s.hours)
+        .map(|r| r * r)
+        .sum();
+    Some((debit, percolation, (residuals / samples.len() as f64).sqrt()))
+}
+
+/// Seconds the sector's valve was open within `[from, to)`, from the valve events in time order
+pub fn watered_secs(events: &[SystemEvent], sector: u32, from: i64, to: i64) -> i64 {
+    let mut open = None;
+    let mut secs = 0;
+    for evt in events.iter().filter(|evt| evt.sector == Some(sector)) {
+        match evt.kind.as_str() {
+            "sector_activated" | "resumed" => open = Some(evt.timestamp),
+            "sector_deactivated" | "paused" | "cycle_aborted" => {
+                if let Some(start) = open.take() {
+                    secs += (evt.timestamp.min(to) - start.max(from)).max(0);
+                }
+            }
+            _ => {}
+        }
+    }
+    secs
+}
+
+/// The intervals between consecutive readings of one sector. One that spans a day without a weather rollup is left
+/// out, its rain isn't known.
+pub fn samples(readings: &[MoistureReading], events: &[SystemEvent], weather: &HashMap<i64, f64>) -> Vec<Sample> {
+    readings
+        .windows(2)
+        .filter_map(|pair| {
+            let (first, second) = (pair[0], pair[1]);
+            let (from, to) = (first.timestamp, second.timestamp);
+            if to <= from {
+                return None;
+            }
+            let mut weather_cm = 0.;
+            let mut day = sod(from);
+            while day < to {
+                let overlap = (to.min(day + DAY_SECS) - from.max(day)) as f64 / DAY_SECS as f64;
+                weather_cm += weather.get(&day)? * overlap;
+                day += DAY_SECS;
+            }
+            Some(Sample {
+                hours: (to - from) as f64 / 3600.,
+                watered_hours: watered_secs(events, first.sector, from, to) as f64 / 3600.,
+                weather_cm,
+                change_cm: second.water_cm - first.water_cm,
+            })
+        })
+        .collect()
+}
+
+/// Estimates for the sectors with at least `min_samples` intervals in the `window_days` before `now`. They are
+/// only proposals, stored as pending by the caller.
+pub fn learn(
+    db: &dyn DatabaseTrait, sectors: &HashMap<u32, SectorInfo>, now: i64, window_days: i64, min_samples: usize,
+) -> rusqlite::Result<Vec<LearnedParams>> {
+    let from = sod(now) - window_days * DAY_SECS;
+    let readings = db.load_moisture_readings(from, now)?;
+    if readings.is_empty() {
+        return Ok(Vec::new());
+    }
+    let events = db.load_system_events(from, now)?;
+    // cm per day, the rollup of a day is asked for with a time in the next one
+    let weather: HashMap<i64, f64> = (0..=window_days)
+        .map(|n| from + n * DAY_SECS)
+        .filter_map(|day| {
+            let rain = db.get_lastday_rain(day + DAY_SECS)?;
+            let et = db.get_daily_et(day + DAY_SECS)?;
+            Some((day, (rain - et) / 10.))
+        })
+        .collect();
+
+    let mut learned = Vec::new();
+    for sector_readings in readings.chunk_by(|a, b| a.sector == b.sector) {
+        let Some(sector) = sectors.get(&sector_readings[0].sector) else { continue };
+        let samples = samples(sector_readings, &events, &weather);
+        if samples.len() < min_samples {
+            continue;
+        }
+        let Some((sprinkler_debit, percolation_rate, rmse)) = fit(&samples) else { continue };
+        learned.push(LearnedParams {
+            sector: sector.id,
+            learned_at: now,
+            sprinkler_debit,
+            percolation_rate,
+            previous_debit: sector.sprinkler_debit,
+            previous_percolation: sector.percolation_rate,
+            samples: samples.len(),
+            from: sector_readings[0].timestamp,
+            to: sector_readings[sector_readings.len() - 1].timestamp,
+            rmse,
+            status: LearnedStatus::Pending,
+        });
+    }
+    Ok(learned)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{db::Database, weather::rollup::DailyRollup};
+
+    #[test]
+    fn fit_finds_the_debit_and_percolation() {
+        // 1.5 cm/h and 0.4 mm/h, days with and without watering
+        let sample = |watered_hours: f64, weather_cm: f64| Sample {
+            hours: 24.,
+            watered_hours,
+            weather_cm,
+            change_cm: 1.5 * watered_hours - 0.04 * 24. + weather_cm,
+        };
+        let samples = [sample(0.5, -0.4), sample(0., -0.5), sample(1., 0.2), sample(0.25, -0.3), sample(0., 1.)];
+        let (debit, percolation, rmse) = fit(&samples).unwrap();
+        assert!((debit - 1.5).abs() < 1e-9 && (percolation - 0.4).abs() < 1e-9 && rmse < 1e-9);
+
+        // never watered, the debit can't be told
+        assert!(fit(&[sample(0., -0.5), sample(0., 0.3)]).is_none());
+    }
+
+    #[test]
+    fn samples_take_the_watering_and_weather_in_between() {
+        let evt = |timestamp, kind: &str| SystemEvent {
+            timestamp,
+            kind: kind.to_owned(),
+            sector: Some(1),
+            cycle: Some(1),
+            detail: String::new(),
+        };
+        let events = [evt(79_200, "sector_activated"), evt(81_000, "sector_deactivated")];
+        let reading = |timestamp, water_cm| MoistureReading { sector: 1, timestamp, water_cm };
+        let readings = [reading(43_200, 3.), reading(129_600, 3.5), reading(216_000, 3.2)];
+        // no rollup for the third day
+        let weather = HashMap::from([(0, -0.4), (DAY_SECS, 0.2)]);
+
+        let samples = samples(&readings, &events, &weather);
+        assert_eq!(samples.len(), 1);
+        assert_eq!((samples[0].hours, samples[0].watered_hours), (24., 0.5));
+        assert!((samples[0].weather_cm + 0.1).abs() < 1e-9 && (samples[0].change_cm - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn learns_from_a_week_of_readings() {
+        let db = Database::new(":memory:").unwrap();
+        // a sector that really does 1.5 cm/h and drains 0.4 mm/h, configured with 1 and 0.5
+        let sector = SectorInfo { id: 1, sprinkler_debit: 1., percolation_rate: 0.5, ..Default::default() };
+        let sectors = HashMap::from([(1, sector)]);
+        let row = "INSERT INTO sectors (id, sprinkler_debit, percolation_rate, max_duration, weekly_target, progress, \
+                   last_water) VALUES (1, 1, 0.5, 1800, 2.5, 0, 0)";
+        db.execute(row, vec![]).unwrap();
+        let weather = |day: i64| ((day % 3) as f64 * 2., 4. + (day % 2) as f64);
+        let watered = |day: i64| [1800, 0, 900, 2700, 0, 1200, 600][day as usize % 7];
+        let mut water_cm = 4.;
+        for day in 0..8 {
+            let start = day * DAY_SECS;
+            let (rain, et) = weather(day);
+            db.store_daily_rollup(DailyRollup { timestamp: start, hours: 24, rain, et, ..Default::default() }).unwrap();
+            db.add_moisture_reading(MoistureReading { sector: 1, timestamp: start + 43_200, water_cm }).unwrap();
+            for (offset, kind) in [(79_200, "sector_activated"), (79_200 + watered(day), "sector_deactivated")] {
+                let detail = String::new();
+                let evt = SystemEvent {
+                    timestamp: start + offset,
+                    kind: kind.to_owned(),
+                    sector: Some(1),
+                    cycle: None,
+                    detail,
+                };
+                db.log_system_event(evt).unwrap();
+            }
+            // noon to noon, half of each day's weather
+            let (next_rain, next_et) = weather(day + 1);
+            let weather_cm = ((rain - et) + (next_rain - next_et)) / 2. / 10.;
+            water_cm += 1.5 * watered(day) as f64 / 3600. - 0.04 * 24. + weather_cm;
+        }
+
+        let learned = learn(&db, &sectors, 8 * DAY_SECS, 8, 5).unwrap();
+        assert_eq!(learned.len(), 1);
+        let learned = &learned[0];
+        assert!((learned.sprinkler_debit - 1.5).abs() < 1e-6 && (learned.percolation_rate - 0.4).abs() < 1e-6);
+        assert_eq!((learned.previous_debit, learned.samples, learned.status), (1., 7, LearnedStatus::Pending));
+        // not enough intervals
+        assert!(learn(&db, &sectors, 8 * DAY_SECS, 8, 8).unwrap().is_empty());
+    }
+}
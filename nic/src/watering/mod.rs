@@ -1,8 +1,17 @@
+pub mod calibration;
+pub mod cron;
+pub mod daily_report;
 pub mod ds;
+pub mod efficiency;
+pub mod import;
+pub mod learning;
 pub mod modes;
+pub mod schedule;
+pub mod schedule_file;
 pub mod watering_alg;
 #[allow(non_snake_case)]
 pub mod state_machine;
+pub mod test_run;
 pub mod watering_system;
 pub mod water_window;
 
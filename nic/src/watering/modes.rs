@@ -1,13 +1,17 @@
 use super::ds::DailyPlan;
 use num_derive::FromPrimitive;
+use serde::{Deserialize, Serialize};
 use std::fmt::{Debug, Display};
 
-#[derive(Clone, Copy, Debug, PartialEq, FromPrimitive)]
+#[derive(Clone, Copy, Debug, PartialEq, FromPrimitive, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 #[repr(usize)]
 pub enum Mode {
     Auto = 0,
     Manual = 1,
     Wizard = 2,
+    /// standby: no plans, nothing waters
+    Off = 3,
 }
 
 impl Display for Mode {
@@ -16,6 +20,7 @@ impl Display for Mode {
             Mode::Auto => "auto",
             Mode::Manual => "manual",
             Mode::Wizard => "wizard",
+            Mode::Off => "off",
         };
         f.write_str(mode)
     }
@@ -29,20 +34,30 @@ impl std::str::FromStr for Mode {
             "auto" => Ok(Mode::Auto),
             "manual" => Ok(Mode::Manual),
             "wizard" => Ok(Mode::Wizard),
+            "off" => Ok(Mode::Off),
             _ => Err("Invalid mode"),
         }
     }
 }
 
+/// The cycles still to run, in start order. A day may have any number of them, each program of the schedule is one.
 #[derive(Clone, Debug)]
 pub struct ModeAuto {
     pub daily_plan: Vec<DailyPlan>, // Store the schedule here
 }
 
+/// The cycles still to run, in start order, the night's session split in blocks by `max_cycle_secs`
 #[derive(Clone, Debug)]
 pub struct ModeWizard {
     pub daily_plan: Vec<DailyPlan>,
 }
 
+/// Takes the cycle that ended off the queue, found by its start, with anything that starts before it: the queue
+/// runs in order, so that already ran or was missed. A cycle that isn't in the queue anymore (restored after a
+/// restart, or the plans were recalculated while it ran) leaves the plans after it alone.
+pub fn consume_plans(plans: &mut Vec<DailyPlan>, cycle_id: i64) {
+    plans.retain(|plan| plan.0.first().is_some_and(|sec| sec.start > cycle_id));
+}
+
 #[derive(Clone, Debug)]
 pub struct ModeManual;
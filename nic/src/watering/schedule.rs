@@ -0,0 +1,149 @@
+use super::{
+    ds::SectorInfo,
+    watering_alg::{Schedule, ScheduleType},
+};
+use crate::config::Watering;
+use chrono::Weekday;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+};
+
+const DAY_SECS: i64 = 86_400;
+
+/// A run of the weekly schedule that makes no sense, `entry` and `run` are where it is in the schedule
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScheduleIssue {
+    pub program: String,
+    pub day: Weekday,
+    pub sector: u32,
+    /// seconds from the start of the day
+    pub start: i64,
+    pub problem: String,
+    entry: usize,
+    run: usize,
+}
+
+/// `A Mon 22:00 sector 2: lasts 3600s, over its max_duration of 1800s`
+impl Display for ScheduleIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (hours, minutes) = (self.start.div_euclid(3600), self.start.rem_euclid(3600) / 60);
+        write!(f, "{} {} {:02}:{:02} sector {}: {}", self.program, self.day, hours, minutes, self.sector, self.problem)
+    }
+}
+
+/// The runs of the weekday entries that can't be watered as written: a start outside its day (or outside the
+/// watering window, when `auto_within_window`), a duration that isn't positive or is over the sector's
+/// `max_duration`, a sector at the same day and time twice, and a run that starts before the one before it in the
+/// program's day ends. The programs may overlap each other, one waits for the other.
+pub fn validate(schedule: &Schedule, sectors: &HashMap<u32, SectorInfo>, cfg: &Watering) -> Vec<ScheduleIssue> {
+    let mut issues = Vec::new();
+    let mut keys = HashSet::new();
+    for (entry, day_entry) in schedule.entries.iter().enumerate() {
+        let ScheduleType::Weekday(day) = day_entry.schedule_type else { continue };
+        let mut ends: Vec<(i64, i64)> = Vec::new();
+        for (run, sec) in day_entry.start_times.0.iter().enumerate() {
+            let mut issue = |problem: String| {
+                let program = day_entry.program.clone();
+                issues.push(ScheduleIssue { program, day, sector: sec.id, start: sec.start, problem, entry, run });
+            };
+            let window_start = cfg.window_start_hour * 3600;
+            if !(0..DAY_SECS).contains(&sec.start) {
+                issue("starts outside its day".to_owned());
+            } else if cfg.auto_within_window
+                && (sec.start - window_start).rem_euclid(DAY_SECS) >= cfg.window_duration_hours * 3600
+            {
+                issue(format!(
+                    "starts outside the watering window, {:02}:00 for {}h",
+                    cfg.window_start_hour, cfg.window_duration_hours
+                ));
+            } else if sec.duration <= 0 {
+                issue("the duration must be positive".to_owned());
+            } else if let Some(max) =
+                sectors.get(&sec.id).map(|sector| sector.max_duration).filter(|&max| sec.duration > max)
+            {
+                issue(format!("lasts {}s, over its max_duration of {}s", sec.duration, max));
+            } else if !keys.insert((day, sec.id, sec.start)) {
+                issue("the sector is already scheduled then".to_owned());
+            } else if let Some(&(start, end)) =
+                ends.iter().find(|&&(start, end)| sec.start < end && sec.start + sec.duration > start)
+            {
+                issue(format!("overlaps the run from {} to {} of the program", clock(start), clock(end)));
+            } else {
+                ends.push((sec.start, sec.start + sec.duration));
+            }
+        }
+    }
+    issues
+}
+
+/// The schedule without the runs of `issues`, the days left empty are dropped
+pub fn without(mut schedule: Schedule, issues: &[ScheduleIssue]) -> Schedule {
+    for (entry, day_entry) in schedule.entries.iter_mut().enumerate() {
+        let mut run = 0;
+        day_entry.start_times.0.retain(|_| {
+            run += 1;
+            !issues.iter().any(|issue| (issue.entry, issue.run) == (entry, run - 1))
+        });
+    }
+    schedule.entries.retain(|entry| !entry.start_times.0.is_empty());
+    schedule
+}
+
+fn clock(secs: i64) -> String {
+    format!("{:02}:{:02}", secs.div_euclid(3600), secs.rem_euclid(3600) / 60)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::watering::{
+        ds::{DailyPlan, WaterSector},
+        watering_alg::ScheduleEntry,
+    };
+
+    fn entry(program: &str, day: Weekday, runs: &[(u32, i64, i64)]) -> ScheduleEntry {
+        let runs = runs.iter().map(|&(id, start, duration)| WaterSector::new(id, start, duration));
+        ScheduleEntry {
+            program: program.to_owned(),
+            schedule_type: ScheduleType::Weekday(day),
+            start_times: DailyPlan(runs.collect()),
+            cron: None,
+        }
+    }
+
+    #[test]
+    fn finds_the_runs_that_make_no_sense() {
+        let sector = |id| (id, SectorInfo { id, max_duration: 1800, ..Default::default() });
+        let sectors = HashMap::from([sector(1), sector(2), sector(3)]);
+        let h = |hours: i64| hours * 3600;
+        let schedule = Schedule::new(vec![
+            entry("A", Weekday::Mon, &[(1, h(22), 1800), (2, h(22) + 900, 600), (3, h(23), 3600), (3, h(25), 600)]),
+            // B may overlap A, but not at the same time for the same sector
+            entry("B", Weekday::Mon, &[(2, h(22) + 1200, 600), (1, h(22), 600), (9, h(6), 0)]),
+        ]);
+        let mut cfg = Watering::default();
+        let issues = validate(&schedule, &sectors, &cfg);
+        let found: Vec<String> = issues.iter().map(|issue| issue.to_string()).collect();
+        assert_eq!(
+            found,
+            [
+                "A Mon 22:15 sector 2: overlaps the run from 22:00 to 22:30 of the program",
+                "A Mon 23:00 sector 3: lasts 3600s, over its max_duration of 1800s",
+                "A Mon 25:00 sector 3: starts outside its day",
+                "B Mon 22:00 sector 1: the sector is already scheduled then",
+                "B Mon 06:00 sector 9: the duration must be positive",
+            ]
+        );
+        let kept = without(schedule.clone(), &issues);
+        let runs: Vec<Vec<u32>> =
+            kept.entries.iter().map(|entry| entry.start_times.0.iter().map(|sec| sec.id).collect()).collect();
+        assert_eq!(runs, [vec![1], vec![2]]);
+
+        // programs held to the watering window, 22:00 for 8h
+        cfg.auto_within_window = true;
+        let morning = Schedule::new(vec![entry("A", Weekday::Tue, &[(1, h(5) + 1800, 600), (2, h(6), 600)])]);
+        let found: Vec<String> = validate(&morning, &sectors, &cfg).iter().map(|issue| issue.to_string()).collect();
+        assert_eq!(found, ["A Tue 06:00 sector 2: starts outside the watering window, 22:00 for 8h"]);
+    }
+}
@@ -0,0 +1,379 @@
+use super::{
+    cron,
+    ds::{DailyPlan, WaterSector},
+    watering_alg::{is_program_name, Schedule, ScheduleEntry, ScheduleType, DEFAULT_PROGRAM},
+};
+use crate::error::AppError;
+use chrono::Weekday;
+use serde::{Deserialize, Serialize};
+use std::{fmt::Write, path::Path};
+
+const CSV_HEADER: &str = "day,sector,start,duration,program";
+
+/// How the auto schedule is written out, for version control, other controllers or a spreadsheet
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ScheduleFormat {
+    #[default]
+    Json,
+    /// a `day,sector,start,duration,program` row per sector, the program can be left out for A. The day can be a
+    /// cron expression in double quotes.
+    Csv,
+}
+
+impl ScheduleFormat {
+    /// From the file extension, json unless it is `.csv`
+    pub fn of(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("csv") => ScheduleFormat::Csv,
+            _ => ScheduleFormat::Json,
+        }
+    }
+}
+
+/// A day of a program of the weekly schedule, or the days of a cron expression, as exported
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DayFile {
+    /// A when left out, the files from before the programs
+    #[serde(default = "default_program")]
+    pub program: String,
+    /// mon, tue, ..., left out with a cron
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub day: String,
+    /// `minute hour * * day-of-week`, the plan runs at each of its times, see `cron::parse`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cron: Option<String>,
+    pub plan: Vec<SectorFile>,
+}
+
+/// A sector of a daily plan, as exported
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SectorFile {
+    pub sector: u32,
+    /// UTC, HH:MM or HH:MM:SS. With a cron, from each of its times and 00:00 when left out.
+    #[serde(default)]
+    pub start: String,
+    /// seconds
+    pub duration: i64,
+}
+
+fn default_program() -> String {
+    DEFAULT_PROGRAM.to_owned()
+}
+
+/// The weekday entries by program, Monday first, then those of its cron expressions, written back as they were
+/// given. Date entries are wizard plans, never saved, so they are left out. Whether a program is enabled isn't part
+/// of the schedule.
+pub fn export(schedule: &Schedule, format: ScheduleFormat) -> String {
+    let mut days: Vec<(&str, Weekday, &DailyPlan, Option<&String>)> = schedule
+        .entries
+        .iter()
+        .filter_map(|entry| match entry.schedule_type {
+            ScheduleType::Weekday(day) => Some((entry.program.as_str(), day, &entry.start_times, entry.cron.as_ref())),
+            ScheduleType::Date(_) => None,
+        })
+        .collect();
+    days.sort_by_key(|(program, day, _, cron)| (*program, *cron, day.num_days_from_monday()));
+    let plan_file = |sectors: &mut dyn Iterator<Item = (u32, i64, i64)>| {
+        sectors.map(|(sector, start, duration)| SectorFile { sector, start: time_of_day(start), duration }).collect()
+    };
+    let mut files: Vec<DayFile> = vec![];
+    for (program, day, plan, cron) in days {
+        let Some(cron) = cron else {
+            let plan = plan_file(&mut plan.0.iter().map(|sec| (sec.id, sec.start, sec.duration)));
+            files.push(DayFile { program: program.to_owned(), day: day.to_string().to_lowercase(), cron: None, plan });
+            continue;
+        };
+        if files.last().is_some_and(|file| file.program == program && file.cron.as_ref() == Some(cron)) {
+            continue; // the first day has it all
+        }
+        // the plan of the first time of the day, from that time
+        let starts = cron::parse(cron).map(|cron| cron.starts).unwrap_or_default();
+        let first = starts.first().copied().unwrap_or_else(|| plan.0.iter().map(|sec| sec.start).min().unwrap_or(0));
+        let next = starts.get(1).copied().unwrap_or(i64::MAX);
+        let runs = plan.0.iter().filter(|sec| (first..next).contains(&sec.start));
+        let plan = plan_file(&mut runs.map(|sec| (sec.id, sec.start - first, sec.duration)));
+        files.push(DayFile { program: program.to_owned(), day: String::new(), cron: Some(cron.clone()), plan });
+    }
+    match format {
+        ScheduleFormat::Json => serde_json::to_string_pretty(&files).unwrap(),
+        ScheduleFormat::Csv => {
+            let mut out = format!("{}\n", CSV_HEADER);
+            for file in files {
+                let day = file.cron.map_or(file.day, |cron| format!("\"{}\"", cron));
+                for sec in file.plan {
+                    _ = writeln!(out, "{},{},{},{},{}", day, sec.sector, sec.start, sec.duration, file.program);
+                }
+            }
+            out
+        }
+    }
+}
+
+/// The whole weekly schedule, checked row by row: a bad one fails the import
+pub fn import(content: &str, format: ScheduleFormat) -> Result<Schedule, AppError> {
+    let days: Vec<DayFile> = match format {
+        ScheduleFormat::Json => {
+            serde_json::from_str(content).map_err(|e| AppError::ConfigError(format!("schedule: {}", e)))?
+        }
+        ScheduleFormat::Csv => from_csv(content)?,
+    };
+    let mut entries: Vec<ScheduleEntry> = vec![];
+    for day_file in days {
+        let (weekdays, times) = match &day_file.cron {
+            Some(expr) => {
+                let cron = cron::parse(expr).map_err(|e| AppError::ConfigError(format!("schedule: {}", e)))?;
+                (cron.days, cron.starts)
+            }
+            None => {
+                let day: Weekday = day_file
+                    .day
+                    .parse()
+                    .map_err(|_| AppError::ConfigError(format!("schedule: '{}' is not a weekday", day_file.day)))?;
+                (vec![day], vec![0])
+            }
+        };
+        if !is_program_name(&day_file.program) {
+            let problem = format!("schedule: '{}' is not a program name, letters and digits", day_file.program);
+            return Err(AppError::ConfigError(problem));
+        }
+        let mut plan = vec![];
+        for sec in &day_file.plan {
+            let start = match sec.start.as_str() {
+                "" if day_file.cron.is_some() => Some(0),
+                start => seconds_of_day(start),
+            };
+            let start =
+                start.ok_or_else(|| AppError::ConfigError(format!("schedule: '{}' is not HH:MM", sec.start)))?;
+            if sec.duration <= 0 {
+                let day = day_file.cron.as_ref().unwrap_or(&day_file.day);
+                let problem = format!("schedule: sector {} on {} has no duration", sec.sector, day);
+                return Err(AppError::ConfigError(problem));
+            }
+            plan.extend(times.iter().map(|time| WaterSector::new(sec.sector, time + start, sec.duration)));
+        }
+        for day in weekdays {
+            let at = entries.iter().position(|entry| {
+                entry.program == day_file.program
+                    && entry.schedule_type == ScheduleType::Weekday(day)
+                    && entry.cron == day_file.cron
+            });
+            let entry = match at {
+                Some(at) => &mut entries[at],
+                None => {
+                    let entry = ScheduleEntry {
+                        program: day_file.program.clone(),
+                        schedule_type: ScheduleType::Weekday(day),
+                        start_times: DailyPlan::new(),
+                        cron: day_file.cron.clone(),
+                    };
+                    entries.push(entry);
+                    entries.last_mut().unwrap()
+                }
+            };
+            entry.start_times.0.extend(plan.iter().copied());
+        }
+    }
+    for entry in entries.iter_mut() {
+        entry.start_times.0.sort_by_key(|sec| sec.start);
+    }
+    entries.retain(|entry| !entry.start_times.0.is_empty());
+    Ok(Schedule::new(entries))
+}
+
+fn from_csv(content: &str) -> Result<Vec<DayFile>, AppError> {
+    let mut days = vec![];
+    for (n, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || (n == 0 && line.starts_with("day")) {
+            continue;
+        }
+        let bad = || AppError::ConfigError(format!("schedule: line {} is not {}", n + 1, CSV_HEADER));
+        let fields = split_csv(line);
+        let (day, sector, start, duration, program) = match fields[..] {
+            [day, sector, start, duration] => (day, sector, start, duration, DEFAULT_PROGRAM),
+            [day, sector, start, duration, program] => (day, sector, start, duration, program),
+            _ => return Err(bad()),
+        };
+        let sec = SectorFile {
+            sector: sector.parse().map_err(|_| bad())?,
+            start: start.to_owned(),
+            duration: duration.parse().map_err(|_| bad())?,
+        };
+        let (day, cron) = match day.strip_prefix('"').and_then(|day| day.strip_suffix('"')) {
+            Some(cron) => (String::new(), Some(cron.to_owned())),
+            None => (day.to_owned(), None),
+        };
+        days.push(DayFile { program: program.to_owned(), day, cron, plan: vec![sec] });
+    }
+    Ok(days)
+}
+
+/// The fields of a row, the commas in double quotes are part of the field
+fn split_csv(line: &str) -> Vec<&str> {
+    let mut fields = vec![];
+    let (mut from, mut quoted) = (0, false);
+    for (at, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                fields.push(line[from..at].trim());
+                from = at + 1;
+            }
+            _ => (),
+        }
+    }
+    fields.push(line[from..].trim());
+    fields
+}
+
+/// HH:MM, with the seconds only when there are some
+fn time_of_day(secs: i64) -> String {
+    match secs % 60 {
+        0 => format!("{:02}:{:02}", secs / 3600, secs % 3600 / 60),
+        s => format!("{:02}:{:02}:{:02}", secs / 3600, secs % 3600 / 60, s),
+    }
+}
+
+pub(super) fn seconds_of_day(value: &str) -> Option<i64> {
+    let parts = value.split(':').map(|part| part.parse::<i64>().ok()).collect::<Option<Vec<_>>>()?;
+    let (hours, minutes, seconds) = match parts[..] {
+        [hours, minutes] => (hours, minutes, 0),
+        [hours, minutes, seconds] => (hours, minutes, seconds),
+        _ => return None,
+    };
+    ((0..24).contains(&hours) && (0..60).contains(&minutes) && (0..60).contains(&seconds))
+        .then_some(hours * 3600 + minutes * 60 + seconds)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn schedule() -> Schedule {
+        let plan = |sectors: Vec<WaterSector>| DailyPlan(sectors);
+        Schedule::new(vec![
+            ScheduleEntry {
+                program: "A".to_owned(),
+                schedule_type: ScheduleType::Weekday(Weekday::Wed),
+                start_times: plan(vec![WaterSector::new(1, 22 * 3600, 1800)]),
+                cron: None,
+            },
+            ScheduleEntry {
+                program: "A".to_owned(),
+                schedule_type: ScheduleType::Weekday(Weekday::Mon),
+                start_times: plan(vec![
+                    WaterSector::new(1, 22 * 3600, 1800),
+                    WaterSector::new(2, 22 * 3600 + 1820, 900),
+                ]),
+                cron: None,
+            },
+            ScheduleEntry {
+                program: "A".to_owned(),
+                schedule_type: ScheduleType::Date(0),
+                start_times: plan(vec![WaterSector::new(3, 0, 60)]),
+                cron: None,
+            },
+        ])
+    }
+
+    fn days(schedule: &Schedule) -> Vec<(String, ScheduleType, DailyPlan)> {
+        let day =
+            |entry: &ScheduleEntry| (entry.program.clone(), entry.schedule_type.clone(), entry.start_times.clone());
+        schedule.entries.iter().map(day).collect()
+    }
+
+    #[test]
+    fn round_trips() {
+        let schedule = schedule();
+        let csv = export(&schedule, ScheduleFormat::Csv);
+        assert_eq!(
+            csv,
+            "day,sector,start,duration,program\n\
+             mon,1,22:00,1800,A\n\
+             mon,2,22:30:20,900,A\n\
+             wed,1,22:00,1800,A\n"
+        );
+        let json = export(&schedule, ScheduleFormat::Json);
+        assert!(json.contains(r#""start": "22:30:20""#));
+
+        let weekdays = days(&schedule)[..2].iter().rev().cloned().collect::<Vec<_>>();
+        assert_eq!(days(&import(&csv, ScheduleFormat::Csv).unwrap()), weekdays);
+        assert_eq!(days(&import(&json, ScheduleFormat::Json).unwrap()), weekdays);
+    }
+
+    #[test]
+    fn rejects_bad_rows() {
+        let bad = |csv: &str| import(csv, ScheduleFormat::Csv).unwrap_err().to_string();
+        assert_eq!(bad("mon,1,22:00"), "Config error: schedule: line 1 is not day,sector,start,duration,program");
+        assert_eq!(
+            bad("day,sector,start,duration\nsomeday,1,22:00,60"),
+            "Config error: schedule: 'someday' is not a weekday"
+        );
+        assert_eq!(bad("mon,1,25:00,60"), "Config error: schedule: '25:00' is not HH:MM");
+        assert_eq!(bad("mon,1,22:00,0"), "Config error: schedule: sector 1 on mon has no duration");
+        assert_eq!(
+            bad("mon,1,22:00,60,a-b"),
+            "Config error: schedule: 'a-b' is not a program name, letters and digits"
+        );
+        assert_eq!(ScheduleFormat::of(Path::new("week.CSV")), ScheduleFormat::Csv);
+        assert_eq!(ScheduleFormat::of(Path::new("week")), ScheduleFormat::Json);
+    }
+
+    #[test]
+    fn keeps_the_programs_apart() {
+        // the files from before the programs are program A
+        let csv = "day,sector,start,duration\nmon,1,06:00,600\nmon,2,06:00,600,B\nmon,3,06:10,600";
+        let schedule = import(csv, ScheduleFormat::Csv).unwrap();
+        let mut programs: Vec<_> =
+            days(&schedule).into_iter().map(|(program, _, plan)| (program, plan.0.len())).collect();
+        programs.sort();
+        assert_eq!(programs, [("A".to_owned(), 2), ("B".to_owned(), 1)]);
+
+        let json = r#"[{"day": "tue", "plan": [{"sector": 1, "start": "05:00", "duration": 60}]}]"#;
+        assert_eq!(import(json, ScheduleFormat::Json).unwrap().entries[0].program, "A");
+        assert!(export(&schedule, ScheduleFormat::Csv).ends_with("mon,2,06:00,600,B\n"));
+    }
+
+    #[test]
+    fn cron_entries_expand_and_round_trip() {
+        let csv = "tue,1,06:00,600,B\n\"0 6,22 * * mon,thu\",1,,600,B\n\"0 6,22 * * mon,thu\",2,00:15,300,B";
+        let schedule = import(csv, ScheduleFormat::Csv).unwrap();
+        let mut runs: Vec<(Weekday, Vec<(u32, i64)>)> = schedule
+            .entries
+            .iter()
+            .filter(|entry| entry.cron.is_some())
+            .map(|entry| {
+                let ScheduleType::Weekday(day) = entry.schedule_type else { unreachable!() };
+                (day, entry.start_times.0.iter().map(|sec| (sec.id, sec.start / 60)).collect())
+            })
+            .collect();
+        runs.sort_by_key(|(day, _)| day.num_days_from_monday());
+        let day = vec![(1, 6 * 60), (2, 6 * 60 + 15), (1, 22 * 60), (2, 22 * 60 + 15)];
+        assert_eq!(runs, [(Weekday::Mon, day.clone()), (Weekday::Thu, day)]);
+
+        let exported = export(&schedule, ScheduleFormat::Csv);
+        assert_eq!(
+            exported,
+            "day,sector,start,duration,program\n\
+             tue,1,06:00,600,B\n\
+             \"0 6,22 * * mon,thu\",1,00:00,600,B\n\
+             \"0 6,22 * * mon,thu\",2,00:15,300,B\n"
+        );
+        let json = export(&schedule, ScheduleFormat::Json);
+        assert!(json.contains(r#""cron": "0 6,22 * * mon,thu""#));
+        let entries = |schedule: &Schedule| {
+            let mut entries = days(schedule);
+            entries.sort_by_key(|(_, day, plan)| (format!("{:?}", day), plan.0.len()));
+            entries
+        };
+        assert_eq!(entries(&import(&exported, ScheduleFormat::Csv).unwrap()), entries(&schedule));
+        assert_eq!(entries(&import(&json, ScheduleFormat::Json).unwrap()), entries(&schedule));
+
+        let bad = import(r#"[{"cron": "0 22 1 * *", "plan": []}]"#, ScheduleFormat::Json).unwrap_err();
+        assert_eq!(
+            bad.to_string(),
+            "Config error: schedule: '0 22 1 * *': the day of the month and the month must be *, the schedule is weekly"
+        );
+    }
+}
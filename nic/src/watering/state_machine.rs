@@ -1,26 +1,58 @@
 use super::{
-    ds::{CtrlSignal, Cycle, DailyPlan, SectorInfo, WaterSector, WeatherSignal},
+    calibration::{Calibrated, Calibration, CalibrationMeasure},
+    ds::{
+        AlarmEvent, CtrlSignal, Cycle, DailyPlan, SectorFault, SectorInfo, StateChange, StateEvent, SystemEvent,
+        WaterSector, WeatherSignal,
+    },
+    learning::LearnedParams,
     modes::*,
+    schedule,
+    test_run::TestRun,
     water_window::WaterWin,
     watering_alg::*,
 };
 use crate::{
-    config::Watering,
+    config::{Config, PauseAction, PausePolicy, RestrictionCfg, SourceCfg, Watering, WindowOverrideCfg},
     db::DatabaseTrait,
     error::AppError,
-    sensors::interface::SensorController,
+    sensors::interface::{SensorController, ValveState},
+    time::TimeProvider,
     utils::{get_week_day_from_ts, load_sectors_into_hashmap, sod, ux_ts_to_string},
     watering::{ds::WateringEvent, SECS_TO_HOUR_CONV},
+    weather::forecast::{expected_rain_cm, HourlyForecast},
 };
 use chrono::Weekday;
+use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
-use std::{collections::HashMap, sync::Arc};
-use tracing::{error, info, trace};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+use tokio::sync::broadcast::Sender;
+use tracing::{error, info, trace, warn};
+
+/// Seconds between saves of the watering progress, bounds what a crash can re-water
+pub const RESUME_SAVE_SECS: i64 = 60;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct PausedData {
     pub state: Box<SMState>,
+    /// what holds the pause. Empty after a restart, so the next update resumes.
     pub signals: Vec<WeatherSignal>,
+    /// seconds of the paused sector already watered
+    pub elapsed: i64,
+    /// when the pause started
+    pub since: i64,
+}
+
+/// Where the in progress sector was, persisted so a restart resumes instead of starting the cycle over
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResumePoint {
+    pub cycle: Cycle,
+    pub sector: WaterSector,
+    /// seconds of `sector` already watered
+    pub elapsed: i64,
+    pub saved_at: i64,
 }
 
 #[derive(Debug, Clone, Default, PartialEq)]
@@ -29,6 +61,8 @@ pub enum SMState {
     Idle,
     Watering(WaterSector),
     Paused(PausedData),
+    /// emergency stop, latched until cleared
+    Stopped,
 }
 
 impl SMState {
@@ -49,10 +83,19 @@ impl SMState {
     }
 }
 
+/// A valve command waiting for the hardware to report the new state
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ValveCheck {
+    pub sector: u32,
+    pub expected: ValveState,
+    pub deadline: i64,
+}
+
 #[derive(Debug)]
 pub struct StateMachine {
     pub controller: Arc<dyn SensorController>,
     pub db: Arc<dyn DatabaseTrait>,
+    pub web_tx: Sender<CtrlSignal>,
     pub sectors: HashMap<u32, SectorInfo>,
     pub timeframe: WaterWin,
 
@@ -67,56 +110,257 @@ pub struct StateMachine {
     pub mode_auto: ModeAuto,
     pub mode_wizard: ModeWizard,
 
+    /// Latest cached hourly forecast, refreshed before the daily adjustments
+    pub forecast: Vec<HourlyForecast>,
+    /// mm of ET expected in the next 24h, from the configured ET model
+    pub predicted_et: f64,
+    /// Sectors whose valve gave up on a command. Left out of the wizard plans until restart.
+    pub faulted: HashSet<u32>,
+    pub valve_checks: Vec<ValveCheck>,
+    /// what weather signals do to a running cycle, per mode
+    pub pause_policy: PausePolicy,
+    /// up to when the active sector's progress has been accounted for
+    pub progress_at: i64,
+    /// seconds the active sector has watered, as accounted for so far
+    pub watered_secs: i64,
+    /// Pause signals a weather-proof sector watered through, the cycle pauses before the next sector that isn't
+    pub deferred_pause: Vec<WeatherSignal>,
+    /// The test run going on, or the last one for its report
+    pub test_run: Option<TestRun>,
+    /// the calibration run waiting for its measure, its progress is the one of `test_run`
+    pub calibration: Option<Calibration>,
+    /// where the water comes from, in the order they are picked
+    pub sources: Vec<SourceCfg>,
+    /// the source feeding the active sector, index in `sources`
+    active_source: Option<usize>,
+    /// the days the sectors get only part of their ET back
+    pub restrictions: Vec<RestrictionCfg>,
+    /// the days the window differs from the `[watering]` one, see `set_window_overrides`
+    pub window_overrides: Vec<WindowOverrideCfg>,
+    /// seconds each sector watered in the watering day, for the daily caps
+    pub watered_today: HashMap<u32, i64>,
+    /// the window start of that watering day
+    watered_day: i64,
+    /// the wizard planner's scratch list, kept so each day reuses it
+    plan_sectors: Vec<PlanSector>,
+    /// pairs of sectors kept apart, the wizard plans around them
+    pub constraints: Vec<SectorConstraint>,
+    /// the seasonal curves of the sectors, and the `weekly_target` of each for the months without one
+    pub targets: Vec<SectorTarget>,
+    base_targets: HashMap<u32, f64>,
+    /// First write that failed for good, for the loop to escalate
+    pub db_fault: Mutex<Option<AppError>>,
+    /// the monotonic clock the active sector is timed with
+    pub time_provider: Arc<dyn TimeProvider>,
+    /// the wall and monotonic times of the last look at the clocks
+    clock_at: Option<(i64, i64)>,
+
     pub cfg: Watering,
 }
 
+/// `check_db`, for when `self` is borrowed elsewhere
+fn record_db(db_fault: &Mutex<Option<AppError>>, res: rusqlite::Result<()>, what: &str) {
+    let Err(e) = res.map_err(AppError::from) else {
+        return;
+    };
+    if e.is_retryable() {
+        warn!(error = ?e, "Failed to {}, the next write retries.", what);
+        return;
+    }
+    error!(error = ?e, error_kind = ?e.kind(), "Failed to {}.", what);
+    db_fault.lock().unwrap().get_or_insert(e);
+}
+
 impl StateMachine {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         controller: Arc<dyn SensorController>, starting_mode: Option<Mode>, sectors: Vec<SectorInfo>,
-        current_time: i64, db: Arc<dyn DatabaseTrait>, cfg: Watering,
+        current_time: i64, db: Arc<dyn DatabaseTrait>, web_tx: Sender<CtrlSignal>,
+        time_provider: Arc<dyn TimeProvider>, window_overrides: Vec<WindowOverrideCfg>, cfg: Watering,
     ) -> Result<Self, AppError> {
-        let auto_schedule = db.load_auto_schedule()?;
-        let mode_auto = ModeAuto { daily_plan: load_auto_schedule(&auto_schedule, current_time) };
-        Ok(Self {
+        let current_mode = starting_mode.unwrap_or(Mode::Auto);
+        let sectors = restore_progress(db.as_ref(), sectors, current_time, cfg.progress_stale_days)?;
+        let mut auto_schedule = db.load_auto_schedule()?;
+        // rows stored before the checks, or written straight to the table, are left out rather than watered
+        let issues = schedule::validate(&auto_schedule, &sectors, &cfg);
+        for issue in &issues {
+            warn!("Leaving out the auto schedule run {}.", issue);
+        }
+        if !issues.is_empty() {
+            auto_schedule = schedule::without(auto_schedule, &issues);
+        }
+        let constraints = db.load_sector_constraints()?;
+        let targets = db.load_sector_targets()?;
+        let base_targets = sectors.values().map(|sec| (sec.id, sec.weekly_target)).collect();
+        let daily_plan = match current_mode {
+            Mode::Off => Vec::new(),
+            _ => load_auto_schedule(&auto_schedule, current_time, &cfg),
+        };
+        let mode_auto = ModeAuto { daily_plan };
+        // tonight's plans, a restart doesn't forget them until the next daily adjustment. The ones of a window that
+        // closed while we were down are left.
+        let (hour_start, duration_hours) = (cfg.window_start_hour, cfg.window_duration_hours);
+        let window = WaterWin::around(current_time, hour_start, duration_hours, &window_overrides);
+        let daily_plan = match current_mode {
+            Mode::Off => Vec::new(),
+            _ => db.load_wizard_queue(window.day_start_time)?,
+        };
+        let mode_wizard = ModeWizard { daily_plan };
+        let resume_point = db.load_resume_point();
+        let timeframe = WaterWin::with_overrides(current_time, hour_start, duration_hours, &window_overrides);
+        let mut sm = Self {
             state: SMState::Idle,
-            sectors: load_sectors_into_hashmap(sectors),
-            current_mode: starting_mode.unwrap_or(Mode::Auto),
-            timeframe: WaterWin::new(current_time, 22, 8),
+            sectors,
+            current_mode,
+            timeframe,
             controller,
             db,
+            web_tx,
             auto_schedule,
             mode_manual: ModeManual,
             mode_auto,
-            mode_wizard: ModeWizard { daily_plan: Vec::with_capacity(2) },
+            mode_wizard,
             cycle: None,
+            forecast: Vec::new(),
+            predicted_et: 0.,
+            faulted: HashSet::new(),
+            valve_checks: Vec::new(),
+            pause_policy: PausePolicy::default(),
+            progress_at: current_time,
+            watered_secs: 0,
+            deferred_pause: Vec::new(),
+            test_run: None,
+            calibration: None,
+            sources: Vec::new(),
+            active_source: None,
+            restrictions: Vec::new(),
+            window_overrides,
+            watered_today: HashMap::new(),
+            watered_day: timeframe.day_start_time,
+            plan_sectors: Vec::new(),
+            constraints,
+            targets,
+            base_targets,
+            db_fault: Mutex::new(None),
+            time_provider,
+            clock_at: None,
             cfg,
-        })
+        };
+        sm.select_targets(current_time);
+        match resume_point {
+            // switched off while watering, nothing to resume
+            Some(_) if current_mode == Mode::Off => {
+                sm.check_db(sm.db.store_resume_point(None), "clear the watering progress")
+            }
+            Some(point) => sm.restore(point, current_time),
+            None => (),
+        }
+        Ok(sm)
+    }
+
+    /// Picks up the sector that was in progress when the process stopped. It comes back paused with nothing holding
+    /// the pause, so the next update resumes it with the remaining time (or drops it if the window is over).<br>
+    /// Only while the window it was saved in is still open, the time the process was down isn't a weather pause.
+    pub fn restore(&mut self, point: ResumePoint, current_time: i64) {
+        let (hour_start, duration_hours) = (self.cfg.window_start_hour, self.cfg.window_duration_hours);
+        let window = WaterWin::around(point.saved_at, hour_start, duration_hours, &self.window_overrides);
+        if !window.is_within(current_time) {
+            info!(
+                sector = point.sector.id,
+                saved_at = point.saved_at,
+                "Watering window is over, dropping the progress."
+            );
+            let reason = Some("window over before the restart".to_owned());
+            self.emit(current_time, Some(point.sector.id), StateChange::CycleAborted { reason });
+            self.check_db(self.db.store_resume_point(None), "clear the watering progress");
+            return;
+        }
+        info!(
+            sector = point.sector.id,
+            elapsed = point.elapsed,
+            saved_at = point.saved_at,
+            "Restoring watering progress."
+        );
+        // a window across midnight opened the day before
+        self.timeframe = window;
+        self.watered_day = window.day_start_time;
+        self.cycle = Some(point.cycle);
+        // the seconds watered before the restart count towards the daily cap
+        self.watered_today.insert(point.sector.id, point.elapsed);
+        let state = Box::new(SMState::Watering(point.sector));
+        self.state =
+            SMState::Paused(PausedData { state, signals: vec![], elapsed: point.elapsed, since: current_time });
+    }
+
+    /// Emits a transition of the cycle in progress, if any
+    fn emit(&self, current_time: i64, sector: Option<u32>, change: StateChange) {
+        let cycle = self.cycle.as_ref().map(|cycle| cycle.id);
+        let ends_run = matches!(
+            change,
+            StateChange::CycleCompleted | StateChange::CycleAborted { .. } | StateChange::PauseAbandoned { .. }
+        );
+        if let Some(run) = self.cycle.as_ref().and_then(|cycle| cycle.run).filter(|_| ends_run) {
+            let outcome = change.kind().to_owned();
+            self.check_db(self.db.finish_cycle_run(run, current_time, outcome), "save the end of the cycle run");
+        }
+        self.emit_event(StateEvent { timestamp: current_time, sector, cycle, change });
+    }
+
+    /// Stores the event in the `events` audit table and broadcasts it to the web layer
+    fn emit_event(&self, evt: StateEvent) {
+        let what = format!("log the {} event", evt.change.kind());
+        self.check_db(self.db.log_system_event(SystemEvent::from(&evt)), &what);
+        _ = self.web_tx.send(CtrlSignal::StateChanged(evt));
+    }
+
+    /// A test run isn't resumed after a restart
+    fn save_resume_point(&self, sec: WaterSector, elapsed: i64, current_time: i64) {
+        let Some(cycle) = self.cycle.clone().filter(|_| !self.testing()) else {
+            return;
+        };
+        let point = ResumePoint { cycle, sector: sec, elapsed, saved_at: current_time };
+        self.check_db(self.db.store_resume_point(Some(point)), "save the watering progress");
+    }
+
+    /// A write the machine goes on without. A transient failure is left to the next write, anything else is kept
+    /// in `db_fault`.
+    fn check_db(&self, res: rusqlite::Result<()>, what: &str) {
+        record_db(&self.db_fault, res, what);
+    }
+
+    /// The write error the loop has to escalate, if any
+    pub fn take_db_fault(&self) -> Option<AppError> {
+        self.db_fault.lock().unwrap().take()
     }
 
     // Update the machine on every time tick
-    pub fn update(&mut self, current_time: i64) {
-        self.timeframe.roll_window(current_time);
+    pub async fn update(&mut self, current_time: i64) {
+        self.follow_clock_step();
+        self.timeframe.roll_window(current_time, &self.window_overrides);
+        if self.watered_day != self.timeframe.day_start_time {
+            self.watered_day = self.timeframe.day_start_time;
+            self.watered_today.clear();
+        }
+        self.check_valves(current_time).await;
         match self.state {
             SMState::Watering(sec) => {
                 trace!(sector_id = sec.id, "Watering sector.");
                 if current_time >= sec.start + sec.duration {
-                    self.deactivate_sector(current_time, sec);
-                    if let Some(next_sec) = self.cycle.as_mut().and_then(|cycle| cycle.next_sector()) {
-                        self.activate_sector(next_sec);
-                    } else {
-                        info!("Cycle completed. Returning to Idle state.");
-                        self.stop();
-                    }
+                    self.deactivate_sector(current_time, sec).await;
+                    self.move_on(current_time).await;
                 } else {
                     self.update_active_sector(sec, current_time);
                 }
             }
-            SMState::Idle if self.is_auto_or_wizard() => self.trans_watering(current_time),
+            SMState::Idle if self.is_auto_or_wizard() => self.trans_watering(current_time).await,
+            SMState::Paused(ref data) if self.pause_expired(data, current_time) => self.abandon_pause(current_time),
+            SMState::Paused(ref data) if data.signals.is_empty() => self.resume(current_time).await,
             _ => trace!("Update ignored in current state."),
         }
+        self.debug_check_invariants();
     }
 
-    pub fn trans_watering(&mut self, current_time: i64) {
+    pub async fn trans_watering(&mut self, current_time: i64) {
         let daily_plan = match self.current_mode {
             Mode::Auto => &self.mode_auto.daily_plan,
             Mode::Wizard => &self.mode_wizard.daily_plan,
@@ -124,138 +368,772 @@ impl StateMachine {
         };
         if !daily_plan.is_empty() {
             trace!("{} mode schedule {:?}", self.current_mode, daily_plan);
-            if let Some(mut cycle) = daily_plan.first().unwrap().get_cycle(current_time) {
+            if let Some(cycle) = daily_plan.first().unwrap().get_cycle(current_time) {
                 info!(
                     mode = ?self.current_mode,
                     cycle_start = ux_ts_to_string(cycle.get_start_unchecked()),
                     "Starting watering cycle.",
                 );
+                self.start_cycle(cycle, current_time).await;
+            }
+        }
+    }
 
-                if let Some(sec) = cycle.next_sector() {
-                    self.cycle = Some(cycle);
-                    self.activate_sector(sec);
+    /// Saves the run of the cycle and opens its first sector
+    async fn start_cycle(&mut self, mut cycle: Cycle, current_time: i64) {
+        if let Some(sec) = cycle.next_sector() {
+            let planned = cycle.daily_plan.0.clone();
+            cycle.run = match self.db.start_cycle_run(cycle.id, self.current_mode, planned, current_time) {
+                Ok(run) => Some(run),
+                Err(e) => {
+                    self.check_db(Err(e), "save the cycle run");
+                    None
                 }
+            };
+            self.cycle = Some(cycle);
+            self.emit(current_time, None, StateChange::CycleStarted { mode: self.current_mode });
+            self.activate_sector(current_time, sec).await;
+        }
+    }
+
+    /// Waters `sectors` once, in order, each for its seconds and `sector_transation_secs` apart, as a cycle of the
+    /// manual mode: the water counts towards the week like a planned one. Answers with the id of the cycle.
+    pub async fn start_manual(&mut self, sectors: Vec<(u32, i64)>, current_time: i64) -> Result<i64, String> {
+        if self.current_mode != Mode::Manual {
+            return Err(format!("the machine is in {} mode, manual watering needs the manual mode", self.current_mode));
+        }
+        if self.state != SMState::Idle {
+            return Err("the machine isn't idle, the manual watering waits for the cycle to end".to_owned());
+        }
+        if sectors.is_empty() {
+            return Err("no sector to water".to_owned());
+        }
+        let mut start = current_time;
+        let mut plan = Vec::with_capacity(sectors.len());
+        for (id, secs) in sectors {
+            if !self.sectors.contains_key(&id) || self.is_master(id) {
+                return Err(format!("no sector {} to water", id));
+            }
+            if self.faulted.contains(&id) {
+                return Err(format!("sector {} is faulted", id));
             }
+            if !(1..=self.cfg.max_duration_secs).contains(&secs) {
+                return Err(format!("sector {} waters from 1 to {} seconds", id, self.cfg.max_duration_secs));
+            }
+            plan.push(WaterSector::new(id, start, secs));
+            start += secs + self.cfg.sector_transation_secs;
+        }
+        info!(sectors = plan.len(), "Starting manual watering.");
+        let cycle = Cycle::build(DailyPlan(plan));
+        let id = cycle.id;
+        self.start_cycle(cycle, current_time).await;
+        Ok(id)
+    }
+
+    /// Opens the next sector of the cycle, unless it is the one a deferred pause was waiting for
+    /// To the next sector of the cycle, or back to idle when it was the last
+    async fn move_on(&mut self, current_time: i64) {
+        if let Some(next_sec) = self.cycle.as_mut().and_then(|cycle| cycle.next_sector()) {
+            self.next_sector(current_time, next_sec).await;
+        } else {
+            info!("Cycle completed. Returning to Idle state.");
+            self.emit(current_time, None, StateChange::CycleCompleted);
+            self.stop();
+        }
+    }
+
+    async fn next_sector(&mut self, current_time: i64, sec: WaterSector) {
+        if self.deferred_pause.is_empty() || self.ignores_weather(sec.id) {
+            self.activate_sector(current_time, sec).await;
+            return;
+        }
+        let signals = std::mem::take(&mut self.deferred_pause);
+        info!(sector = sec.id, signals = ?signals, "Deferred pause, the sector waits for the weather.");
+        self.emit(current_time, Some(sec.id), StateChange::Paused { signal: signals[0].clone(), elapsed: 0 });
+        self.save_resume_point(sec, 0, current_time);
+        let waiting = Box::new(SMState::Watering(sec));
+        self.state = SMState::Paused(PausedData { state: waiting, signals, elapsed: 0, since: current_time });
+    }
+
+    fn ignores_weather(&self, sector: u32) -> bool {
+        self.sectors.get(&sector).is_some_and(|sec| sec.ignore_weather_pause)
+    }
+
+    /// Opens every sector that isn't faulted, in order, for `seconds_per_sector`, whatever the mode and the window.
+    /// Only from Idle, so it doesn't cut a cycle short.
+    pub async fn start_test_run(&mut self, seconds_per_sector: i64, current_time: i64) -> Result<TestRun, String> {
+        if self.state != SMState::Idle {
+            return Err("the machine isn't idle, the test run waits for the cycle to end".to_owned());
         }
+        let mut sectors: Vec<u32> =
+            self.sectors.keys().copied().filter(|&id| !self.faulted.contains(&id) && !self.is_master(id)).collect();
+        if sectors.is_empty() {
+            return Err("no sector to test".to_owned());
+        }
+        sectors.sort_unstable();
+        self.calibration = None;
+        self.run_sectors(sectors, seconds_per_sector, current_time).await
     }
 
-    fn activate_sector(&mut self, sec: WaterSector) {
+    /// Runs `sector` alone for `seconds`, like a test run, whose measure `finish_calibration` turns into its debit
+    pub async fn start_calibration(&mut self, sector: u32, seconds: i64, current_time: i64) -> Result<TestRun, String> {
+        if self.state != SMState::Idle {
+            return Err("the machine isn't idle, the calibration waits for the cycle to end".to_owned());
+        }
+        if !self.sectors.contains_key(&sector) || self.is_master(sector) {
+            return Err(format!("sector {} can't be calibrated", sector));
+        }
+        if self.faulted.contains(&sector) {
+            return Err(format!("sector {} is faulted", sector));
+        }
+        let run = self.run_sectors(vec![sector], seconds, current_time).await?;
+        self.calibration = Some(Calibration { sector, started: current_time, seconds });
+        Ok(run)
+    }
+
+    /// The debit of the sector from what was measured over its calibration run, once that ended. It is saved and
+    /// the plans follow.
+    pub fn finish_calibration(
+        &mut self, sector: u32, measure: CalibrationMeasure, current_time: i64,
+    ) -> Result<Calibrated, String> {
+        let Some(calibration) = self.calibration.filter(|calibration| calibration.sector == sector) else {
+            return Err(format!("no calibration run of sector {}", sector));
+        };
+        let run = self.test_run.as_ref().filter(|run| run.started == calibration.started);
+        match run.map(|run| (run.finished, run.sectors[0].activated)) {
+            Some((true, Some(true))) => (),
+            Some((false, _)) => return Err(format!("the calibration run of sector {} hasn't ended", sector)),
+            _ => return Err(format!("sector {} didn't open, its calibration has to run again", sector)),
+        }
+        measure.check()?;
+        let debit = measure.debit(calibration.seconds);
+        self.db.set_sprinkler_debit(sector, debit).map_err(|e| e.to_string())?;
+        self.calibration = None;
+        let info = self.sectors.get_mut(&sector).ok_or_else(|| format!("no sector {}", sector))?;
+        let calibrated = Calibrated {
+            sector,
+            seconds: calibration.seconds,
+            sprinkler_debit: debit,
+            previous_debit: info.sprinkler_debit,
+        };
+        info.sprinkler_debit = debit;
+        info!(sector, debit, previous = calibrated.previous_debit, "Sector calibrated.");
+        self.emit(current_time, Some(sector), StateChange::Calibrated { debit, previous: calibrated.previous_debit });
+        if self.state == SMState::Idle {
+            self.load_plans(current_time);
+        }
+        Ok(calibrated)
+    }
+
+    /// The cycle of a test or calibration run, `seconds` each of `sectors` in order
+    async fn run_sectors(
+        &mut self, sectors: Vec<u32>, seconds_per_sector: i64, current_time: i64,
+    ) -> Result<TestRun, String> {
+        let (run, mut cycle) =
+            TestRun::plan(&sectors, current_time, seconds_per_sector, self.cfg.sector_transation_secs);
+        info!(sectors = sectors.len(), seconds_per_sector, "Starting the test run.");
+        let first = cycle.next_sector().unwrap();
+        self.cycle = Some(cycle);
+        self.test_run = Some(run.clone());
+        self.emit(current_time, None, StateChange::TestRunStarted { sectors: sectors.len(), seconds_per_sector });
+        self.activate_sector(current_time, first).await;
+        Ok(run)
+    }
+
+    fn testing(&self) -> bool {
+        self.test_run.as_ref().is_some_and(|run| !run.finished)
+    }
+
+    /// Seconds the sector may still water today, when its daily cap doesn't let it run to its end. A test run is
+    /// left alone, it is short and asked for.
+    fn daily_cap_left(&self, sec: WaterSector, current_time: i64) -> Option<i64> {
+        let cap = daily_cap_secs(self.sectors.get(&sec.id)?, &self.cfg).filter(|_| !self.testing())?;
+        let left = (cap - self.watered_today.get(&sec.id).copied().unwrap_or(0)).max(0);
+        (sec.start + sec.duration > current_time + left).then_some(left)
+    }
+
+    /// The pump and the sources' master valves, relays that aren't sectors
+    fn masters(&self) -> Vec<u32> {
+        let sources = self.sources.iter().filter_map(|source| source.master_sector);
+        self.cfg.pump_sector.into_iter().chain(sources).collect()
+    }
+
+    fn is_master(&self, sector: u32) -> bool {
+        self.masters().contains(&sector)
+    }
+
+    /// Opens the master valve of the first source that feeds the sector at this hour. Without one the sector still
+    /// opens, a config gap shouldn't cost the watering.
+    async fn open_source(&mut self, sector: u32, current_time: i64) -> Option<usize> {
+        if self.sources.is_empty() {
+            return None;
+        }
+        let hour = ((current_time - sod(current_time)) / 3600) as u32;
+        let Some(n) = self.sources.iter().position(|source| source.serves(sector, hour)) else {
+            warn!(sector_id = sector, hour, "No water source feeds the sector at this hour.");
+            return None;
+        };
+        let source = &self.sources[n];
+        if let Some(master) = source.master_sector {
+            if let Err(e) = self.controller.activate_sector(master).await {
+                error!(sector_id = master, source = source.name, error = ?e, "Failed to open the source's master valve.");
+            }
+        }
+        info!(sector = sector, source = source.name, "Watering from source.");
+        Some(n)
+    }
+
+    /// Closes the master valve after its sector, and adds what the sector drew to the source's usage
+    async fn close_source(&mut self, n: usize, secs: i64, current_time: i64) {
+        let Some(source) = self.sources.get(n) else {
+            return;
+        };
+        if let Some(master) = source.master_sector {
+            if let Err(e) = self.controller.deactivate_sector(master).await {
+                error!(sector_id = master, source = source.name, error = ?e, "Failed to close the source's master valve.");
+            }
+        }
+        if secs > 0 {
+            let litres = secs as f64 / 60. * source.flow_lpm;
+            let res = self.db.add_source_usage(sod(current_time), source.name.clone(), secs, litres);
+            self.check_db(res, "add the source usage");
+        }
+    }
+
+    async fn activate_sector(&mut self, current_time: i64, mut sec: WaterSector) {
+        self.progress_at = current_time;
+        self.watered_secs = 0;
+        if let Some(allowed) = self.daily_cap_left(sec, current_time) {
+            warn!(sector = sec.id, allowed, "Daily cap reached, the sector is cut short.");
+            self.emit(current_time, Some(sec.id), StateChange::DailyCapReached { allowed });
+            if allowed == 0 {
+                // nothing left, the sector is skipped without opening its valve
+                Box::pin(self.move_on(current_time)).await;
+                return;
+            }
+            sec = WaterSector { start: current_time, duration: allowed, ..sec };
+        }
         self.state = SMState::Watering(sec);
+        self.active_source = self.open_source(sec.id, current_time).await;
         // we know that we have one sector at least, otherwise next_sector returns None
-        if let Err(e) = self.controller.activate_sector(sec.id) {
+        let activated = self.controller.activate_sector(sec.id).await;
+        if let Some(run) = self.test_run.as_mut().filter(|run| !run.finished) {
+            run.record(sec.id, activated.as_ref().map(|_| ()).map_err(|e| e.to_string()));
+        }
+        if let Err(e) = activated {
             error!("Failed to activate sector {}: {}", sec.id, e);
         } else {
             info!(sector = sec.id, "Moving to sector.");
+            self.emit(current_time, Some(sec.id), StateChange::SectorActivated { duration: sec.duration });
+            self.expect_valve(sec.id, ValveState::Open, current_time);
+            self.save_resume_point(sec, 0, current_time);
         }
     }
 
-    fn deactivate_sector(&mut self, current_time: i64, sec: WaterSector) {
+    /// The watering event has the seconds actually accounted for, however often the loop got to update
+    async fn deactivate_sector(&mut self, current_time: i64, sec: WaterSector) {
+        self.account_progress(sec, current_time);
+        let watered_secs = self.watered_secs;
+        if self.watered_secs > 0 {
+            let water_applied = self.watered_secs as f64 * SECS_TO_HOUR_CONV * self.sectors[&sec.id].sprinkler_debit;
+            let cycle_id = self.cycle.as_ref().and_then(|cycle| cycle.run);
+            let watered = WaterSector { duration: self.watered_secs, ..sec };
+            let evt = WateringEvent::new(cycle_id, watered, water_applied, self.current_mode);
+            self.check_db(self.db.log_watering_event(evt), "log the watering event");
+            self.watered_secs = 0;
+        }
         self.sectors.get_mut(&sec.id).unwrap().last_water = current_time;
-        if let Err(e) = self.controller.deactivate_sector(sec.id) {
+        self.check_db(self.db.add_sector_progress(sec.id, 0., current_time), "save the last watering");
+        // a restart right after doesn't water it again
+        self.check_db(self.db.flush(), "save the watering progress");
+        if let Err(e) = self.controller.deactivate_sector(sec.id).await {
             error!(sector_id=sec.id, error=?e,"Failed to deactivate sector");
+        } else {
+            self.expect_valve(sec.id, ValveState::Closed, current_time);
+            self.emit(current_time, Some(sec.id), StateChange::SectorDeactivated);
+        }
+        if let Some(n) = self.active_source.take() {
+            self.close_source(n, watered_secs, current_time).await;
+        }
+    }
+
+    /// Queues a read back of the valve. A newer command on the same sector replaces the pending check.
+    fn expect_valve(&mut self, sector: u32, expected: ValveState, current_time: i64) {
+        self.valve_checks.retain(|check| check.sector != sector);
+        if self.cfg.valve_check_secs > 0 {
+            self.valve_checks.push(ValveCheck { sector, expected, deadline: current_time + self.cfg.valve_check_secs });
+        }
+    }
+
+    /// Polls the pending valve checks. A valve that doesn't report the commanded state by the deadline raises an
+    /// alarm, stops the pump and aborts the cycle. Backends that can't read back (`ValveState::Unknown`) are trusted.
+    async fn check_valves(&mut self, current_time: i64) {
+        for check in std::mem::take(&mut self.valve_checks) {
+            let actual = match self.controller.sector_state(check.sector).await {
+                Ok(state) => Some(state),
+                Err(e) => {
+                    trace!(sector_id = check.sector, error = ?e, "Valve read back failed.");
+                    None
+                }
+            };
+            // only a valve seen in the wrong state is an alarm, one that can't be read back isn't
+            match actual {
+                Some(state) if state == check.expected || state == ValveState::Unknown => {}
+                _ if current_time < check.deadline => self.valve_checks.push(check),
+                Some(state) => {
+                    let actual = Some(state);
+                    let alarm =
+                        AlarmEvent { timestamp: current_time, sector: check.sector, expected: check.expected, actual };
+                    self.raise_alarm(alarm).await;
+                }
+                None => warn!(sector_id = check.sector, expected = ?check.expected, "Valve state not confirmed."),
+            }
+        }
+    }
+
+    async fn raise_alarm(&mut self, alarm: AlarmEvent) {
+        error!(sector_id = alarm.sector, expected = ?alarm.expected, actual = ?alarm.actual, "Valve alarm.");
+        if let Some(run) = self.test_run.as_mut().filter(|run| !run.finished) {
+            run.record(
+                alarm.sector,
+                Err(format!("the valve reported {:?}, expected {:?}", alarm.actual, alarm.expected)),
+            );
+        }
+        _ = self.web_tx.send(CtrlSignal::Alarm(alarm.clone()));
+        if let Some(pump) = self.cfg.pump_sector {
+            if let Err(e) = self.controller.deactivate_sector(pump).await {
+                error!(sector_id = pump, error = ?e, "Failed to stop the pump.");
+            }
+        }
+        let open = match self.state {
+            SMState::Watering(sec) => Some(sec),
+            _ => None,
+        };
+        self.abort_cycle(alarm.timestamp, open).await;
+    }
+
+    /// Closes `open`, if given, and drops what is left of the cycle
+    async fn abort_cycle(&mut self, current_time: i64, open: Option<WaterSector>) {
+        if let Some(sec) = open {
+            self.deactivate_sector(current_time, sec).await;
+        }
+        if matches!(self.state, SMState::Watering(_) | SMState::Paused(_)) {
+            info!("Cycle aborted.");
+            self.emit(current_time, open.map(|sec| sec.id), StateChange::CycleAborted { reason: None });
+            self.stop();
+        }
+    }
+
+    /// The active sector is timed by the monotonic clock: a step of the wall clock since the last look moves its start
+    /// as much, so it waters for its duration whatever NTP does. The schedule starts stay on the wall clock.<br>
+    /// A second either way is the two clocks rounding apart.
+    fn follow_clock_step(&mut self) {
+        let (wall_now, monotonic) = (self.time_provider.now(), self.time_provider.monotonic());
+        let last = self.clock_at.replace((wall_now, monotonic));
+        let (SMState::Watering(sec), Some((wall, at))) = (&mut self.state, last) else {
+            return;
         };
+        let step = (wall_now - wall) - (monotonic - at);
+        if step.abs() > 1 {
+            warn!(sector = sec.id, step, "The wall clock stepped while watering, the sector keeps its duration.");
+            sec.start += step;
+            self.progress_at += step;
+        }
     }
 
+    /// Before the end of the sector, `update` closes it at the end
     fn update_active_sector(&mut self, sec: WaterSector, current_time: i64) {
-        let elapsed_secs = (current_time - sec.start) as f64;
+        let from = self.account_progress(sec, current_time);
+        let elapsed = current_time - sec.start;
+        // updates don't come every second anymore, so save whenever we crossed a save point
+        if elapsed / RESUME_SAVE_SECS > (from - sec.start) / RESUME_SAVE_SECS {
+            self.save_resume_point(sec, elapsed, current_time);
+        }
+    }
 
-        let sector = self.sectors.get_mut(&sec.id).unwrap();
-        let sprinkler_debit_per_sec = SECS_TO_HOUR_CONV * sector.sprinkler_debit;
-        if elapsed_secs >= sec.duration as f64 {
-            info!(sector = sector.id, "Completed watering for sector.");
-            let water_applied = elapsed_secs * sprinkler_debit_per_sec; // Final water applied
+    /// Adds the water applied since the progress was last accounted for, and returns when that was
+    fn account_progress(&mut self, sec: WaterSector, current_time: i64) -> i64 {
+        let (from, until) = (self.progress_at, current_time.min(sec.start + sec.duration));
+        if let Some(sector) = self.sectors.get_mut(&sec.id).filter(|_| until > from) {
+            let cm = (until - from) as f64 * SECS_TO_HOUR_CONV * sector.sprinkler_debit;
+            sector.progress += cm;
+            trace!("Sector {} watering progress: {:.2} cm", sector.id, sector.progress);
+            self.progress_at = until;
+            self.watered_secs += until - from;
+            *self.watered_today.entry(sec.id).or_default() += until - from;
+            // batched by the database actor
+            self.check_db(self.db.add_sector_progress(sec.id, cm, 0), "save the watering progress");
+        }
+        from
+    }
 
-            _ = self.db.log_watering_event(WateringEvent::new(None, sec, water_applied, self.current_mode));
-            return;
+    /// Next instant `update` has something to do, so the loop can sleep until then
+    pub fn next_wakeup(&self, current_time: i64) -> i64 {
+        if !self.valve_checks.is_empty() {
+            return current_time + 1; // valves are polled every second until they report
         }
-        sector.progress += sprinkler_debit_per_sec;
-        trace!("Sector {} watering progress: {:.2} cm", sector.id, sector.progress);
+        let next = match &self.state {
+            SMState::Watering(sec) => {
+                let saves = (current_time - sec.start).div_euclid(RESUME_SAVE_SECS) + 1;
+                (sec.start + sec.duration).min(sec.start + saves * RESUME_SAVE_SECS)
+            }
+            SMState::Paused(data) if self.cfg.max_pause_secs > 0 => data.since + self.cfg.max_pause_secs,
+            SMState::Idle if self.is_auto_or_wizard() => self.next_plan_start().unwrap_or(i64::MAX),
+            SMState::Paused(_) | SMState::Idle | SMState::Stopped => i64::MAX,
+        };
+        // the window rolls over to the next day on the first update past its end
+        next.min(self.timeframe.day_end_time + 1).max(current_time + 1)
     }
 
-    pub fn trans_pause(&mut self, signal: WeatherSignal, current_time: i64) {
-        if self.current_mode != Mode::Wizard {
-            trace!(mode=?self.current_mode,"Pause not applicable.");
-            return;
+    /// The plan the current mode runs next, none in manual or off
+    pub fn next_plan(&self) -> Option<&DailyPlan> {
+        match self.current_mode {
+            Mode::Auto => self.mode_auto.daily_plan.first(),
+            Mode::Wizard => self.mode_wizard.daily_plan.first(),
+            _ => None,
+        }
+    }
+
+    /// When the next plan starts, none in manual or off
+    pub fn next_plan_start(&self) -> Option<i64> {
+        self.next_plan().and_then(|plan| plan.0.first()).map(|sec| sec.start)
+    }
+
+    pub async fn trans_pause(&mut self, signal: WeatherSignal, current_time: i64) {
+        match self.pause_policy.action(self.current_mode, &signal) {
+            PauseAction::Ignore => trace!(mode = ?self.current_mode, signal = ?signal, "Pause not applicable."),
+            PauseAction::Abort => {
+                info!(mode = ?self.current_mode, signal = ?signal, "Aborting the cycle due to the weather.");
+                let open = match self.state {
+                    SMState::Watering(sec) => Some(sec),
+                    _ => None,
+                };
+                self.abort_cycle(current_time, open).await;
+            }
+            PauseAction::Pause => self.pause(signal, current_time).await,
         }
+    }
+
+    async fn pause(&mut self, signal: WeatherSignal, current_time: i64) {
         match &mut self.state {
+            SMState::Watering(sec) if self.sectors.get(&sec.id).is_some_and(|sec| sec.ignore_weather_pause) => {
+                info!(sector = sec.id, signal = ?signal, "Sector ignores the weather, pausing at the next one.");
+                if !self.deferred_pause.contains(&signal) {
+                    self.deferred_pause.push(signal);
+                }
+            }
             SMState::Watering(sec) => {
                 let sec_clone = *sec;
-                self.deactivate_sector(current_time, sec_clone);
-                info!(sector = sec_clone.id, signal = ?signal, "Sector deactivated due to pause signal");
-                let paused_data = PausedData { state: self.state.boxed(), signals: vec![signal] };
+                let elapsed = (current_time - sec_clone.start).clamp(0, sec_clone.duration);
+                self.deactivate_sector(current_time, sec_clone).await;
+                info!(sector = sec_clone.id, signal = ?signal, elapsed, "Sector deactivated due to pause signal");
+                self.emit(current_time, Some(sec_clone.id), StateChange::Paused { signal: signal.clone(), elapsed });
+                self.save_resume_point(sec_clone, elapsed, current_time);
+                let paused_data =
+                    PausedData { state: self.state.boxed(), signals: vec![signal], elapsed, since: current_time };
                 self.state = SMState::Paused(paused_data);
             }
-            SMState::Paused(data) => {
-                if data.signals.iter().all(|existing_signal| *existing_signal != signal) {
-                    data.signals.push(signal);
-                }
+            SMState::Paused(data) if data.signals.iter().all(|existing_signal| *existing_signal != signal) => {
+                data.signals.push(signal);
             }
             _ => (), //nop
         }
     }
 
-    /// panics if mode daily plan don't have secs, or if called more times than the number of sectors
+    /// Ends the cycle, taking its plan off the queue of the mode
     pub fn stop(&mut self) {
-        self.cycle = None;
-        match self.current_mode {
-            Mode::Auto => {
-                self.mode_auto.daily_plan.remove(0);
-            } // we have only 2 cycles per day, max, so remove/shifting 1 element is ok
-            Mode::Wizard => {
-                self.mode_wizard.daily_plan.remove(0);
-            } // we have only 2 cycles per day, max, so remove/shifting 1 element is ok
+        self.deferred_pause.clear();
+        let tested = self.testing();
+        if let Some(run) = self.test_run.as_mut() {
+            run.finished = true;
+        }
+        let ended = self.cycle.take();
+        if ended.is_some() {
+            self.check_db(self.db.store_resume_point(None), "clear the watering progress");
+        }
+        match (self.current_mode, ended) {
+            // the test run wasn't one of the plans
+            _ if tested => (),
+            (Mode::Auto, Some(cycle)) => consume_plans(&mut self.mode_auto.daily_plan, cycle.id),
+            (Mode::Wizard, Some(cycle)) => {
+                consume_plans(&mut self.mode_wizard.daily_plan, cycle.id);
+                self.check_db(self.db.complete_wizard_plans(cycle.id), "mark the wizard plan completed");
+            }
             _ => (),
         }
         self.state = SMState::Idle;
     }
 
-    pub fn trans_resume(&mut self, env_signal: WeatherSignal, current_time: i64) {
-        if !matches!(env_signal, WeatherSignal::WindLow | WeatherSignal::RainStop) {
+    pub async fn trans_resume(&mut self, env_signal: WeatherSignal, current_time: i64) {
+        let Some(ended) = env_signal.ends() else {
             return; // Ignore irrelevant signals early
-        }
+        };
 
+        self.deferred_pause.retain(|signal| *signal != ended);
         if let SMState::Paused(data) = &mut self.state {
-            if data.signals.len() == 1 {
-                self.state = std::mem::replace(&mut data.state, SMState::Idle);
-
-                if self.timeframe.is_within(current_time) {
-                    info!("Resuming paused watering");
-                    let cycle = self.cycle.as_ref().unwrap();
-                    let sec = cycle.daily_plan.0[cycle.curr_sector];
-                    self.activate_sector(sec);
-                } else {
-                    self.stop();
-                }
-            } else {
-                data.signals.retain(|signal| signal != &env_signal);
+            data.signals.retain(|signal| *signal != ended);
+            if data.signals.is_empty() {
+                self.resume(current_time).await;
+            }
+        }
+    }
+
+    fn pause_expired(&self, data: &PausedData, current_time: i64) -> bool {
+        self.cfg.max_pause_secs > 0 && current_time - data.since >= self.cfg.max_pause_secs
+    }
+
+    /// The weather didn't let up in time: drop the cycle instead of holding it until the window closes
+    fn abandon_pause(&mut self, current_time: i64) {
+        let SMState::Paused(data) = &self.state else {
+            return;
+        };
+        let (paused_secs, signals) = (current_time - data.since, data.signals.clone());
+        let sector = match *data.state {
+            SMState::Watering(sec) => sec.id,
+            _ => 0,
+        };
+        info!(event = "pause_abandoned", sector, paused_secs, signals = ?signals, "Pause timed out, abandoning the cycle.");
+        self.emit(current_time, Some(sector), StateChange::PauseAbandoned { paused_secs, signals });
+        self.stop();
+    }
+
+    /// Waters what is left of the paused sector, clamped to the end of the window
+    async fn resume(&mut self, current_time: i64) {
+        let SMState::Paused(data) = std::mem::take(&mut self.state) else {
+            return;
+        };
+        let SMState::Watering(sec) = *data.state else {
+            return;
+        };
+        if !self.timeframe.is_within(current_time) {
+            info!(sector = sec.id, "Watering window is over, dropping the paused cycle.");
+            let reason = Some("window over while paused".to_owned());
+            self.emit(current_time, Some(sec.id), StateChange::CycleAborted { reason });
+            self.stop();
+            return;
+        }
+        let remaining = (sec.duration - data.elapsed).max(0);
+        let duration = remaining.min(self.timeframe.day_end_time + 1 - current_time);
+        let resumed = WaterSector { start: current_time, duration, ..sec };
+        info!(sector = sec.id, remaining, duration, "Resuming paused watering");
+        self.emit(current_time, Some(sec.id), StateChange::Resumed { remaining, duration });
+        if duration > 0 {
+            self.activate_sector(current_time, resumed).await;
+        } else {
+            // nothing left, the next update moves on to the next sector
+            self.state = SMState::Watering(resumed);
+        }
+    }
+
+    pub async fn trans_fault(&mut self, fault: SectorFault, current_time: i64) {
+        warn!(sector_id = fault.sector, action = ?fault.action, error = fault.error, "Sector faulted.");
+        self.faulted.insert(fault.sector);
+        let open = match &self.state {
+            SMState::Watering(sec) => Some(*sec),
+            SMState::Paused(_) | SMState::Idle | SMState::Stopped => None,
+        };
+        // no point in commanding the valve that just gave up
+        self.abort_cycle(current_time, open.filter(|sec| sec.id != fault.sector)).await;
+    }
+
+    /// Closes the active sector and the pump, keeping the progress so the next start resumes it
+    pub async fn shutdown(&mut self, current_time: i64) {
+        self.follow_clock_step();
+        if let SMState::Watering(sec) = self.state {
+            let elapsed = (current_time - sec.start).clamp(0, sec.duration);
+            self.deactivate_sector(current_time, sec).await;
+            self.save_resume_point(sec, elapsed, current_time);
+            info!(sector = sec.id, elapsed, "Sector closed for shutdown.");
+        }
+        if let Some(pump) = self.cfg.pump_sector {
+            if let Err(e) = self.controller.deactivate_sector(pump).await {
+                error!(sector_id = pump, error = ?e, "Failed to stop the pump.");
+            }
+        }
+    }
+
+    /// Closes the master valves and every sector, whatever we think their state is, drops the cycle and latches the
+    /// machine in `Stopped`. Schedules are ignored until the stop is cleared.
+    pub async fn trans_emergency_stop(&mut self, current_time: i64) {
+        warn!(state = ?self.state, "Emergency stop.");
+        for master in self.masters() {
+            if let Err(e) = self.controller.deactivate_sector(master).await {
+                error!(sector_id = master, error = ?e, "Failed to shut the master valve.");
+            }
+        }
+        let open = match self.state {
+            SMState::Watering(sec) => Some(sec),
+            _ => None,
+        };
+        self.abort_cycle(current_time, open).await;
+        let mut sectors: Vec<u32> =
+            self.sectors.keys().copied().filter(|&id| Some(id) != open.map(|sec| sec.id)).collect();
+        sectors.sort_unstable();
+        for sector in sectors {
+            if let Err(e) = self.controller.deactivate_sector(sector).await {
+                error!(sector_id = sector, error = ?e, "Failed to close the sector.");
+            }
+        }
+        self.state = SMState::Stopped;
+        self.emit(current_time, None, StateChange::EmergencyStop);
+    }
+
+    /// Takes the runtime safe settings of a reloaded config. The window keeps the day it is on, and the plans are
+    /// recalculated for it unless a cycle is running.
+    pub fn apply_config(&mut self, cfg: &Config, current_time: i64) {
+        self.pause_policy = cfg.pause_policy;
+        cfg.restrictions.clone_into(&mut self.restrictions);
+        let window_changed = (cfg.watering.window_start_hour, cfg.watering.window_duration_hours)
+            != (self.cfg.window_start_hour, self.cfg.window_duration_hours)
+            || cfg.window_overrides != self.window_overrides;
+        self.cfg = cfg.watering;
+        if window_changed {
+            self.set_window_overrides(cfg.window_overrides.clone(), current_time);
+            info!(start = self.timeframe.day_start_time, end = self.timeframe.day_end_time, "Watering window changed.");
+            if self.state == SMState::Idle {
+                self.load_plans(current_time);
             }
         }
     }
 
-    pub fn trans_change_mode(&mut self, new_mode: Mode) {
-        if new_mode != self.current_mode {
-            //TODO  -
-            info!(current_mode = ?self.current_mode, new_mode = ?new_mode, "Changing mode.");
-            self.current_mode = new_mode;
+    /// The window keeps the day it is on, with the hours the overrides give it
+    pub fn set_window_overrides(&mut self, overrides: Vec<WindowOverrideCfg>, current_time: i64) {
+        self.window_overrides = overrides;
+        let day = sod(self.timeframe.day_start_time);
+        let (hour_start, duration_hours) = (self.cfg.window_start_hour, self.cfg.window_duration_hours);
+        self.timeframe = WaterWin::with_overrides(day, hour_start, duration_hours, &self.window_overrides);
+        self.timeframe.roll_window(current_time, &self.window_overrides);
+    }
+
+    /// Takes an imported weekly schedule, the auto plans follow at once unless a cycle is running
+    pub fn apply_schedule(&mut self, schedule: Schedule, current_time: i64) {
+        self.auto_schedule = schedule;
+        info!(days = self.auto_schedule.entries.len(), "Auto schedule replaced.");
+        if self.state == SMState::Idle {
+            self.load_plans(current_time);
+        }
+    }
+
+    /// New sectors, the ones it knows keep their progress. The schedule that waters them comes next.
+    pub fn add_sectors(&mut self, sectors: Vec<SectorInfo>) {
+        for sector in sectors {
+            info!(sector = sector.id, name = sector.name, "Sector added.");
+            self.sectors.entry(sector.id).or_insert(sector);
+        }
+    }
+
+    /// An accepted debit and percolation estimate, the plans follow unless a cycle is running
+    pub fn apply_learned(&mut self, learned: &LearnedParams, current_time: i64) {
+        let Some(sector) = self.sectors.get_mut(&learned.sector) else { return };
+        sector.sprinkler_debit = learned.sprinkler_debit;
+        sector.percolation_rate = learned.percolation_rate;
+        info!(
+            sector = learned.sector,
+            debit = learned.sprinkler_debit,
+            percolation = learned.percolation_rate,
+            "Learned sector parameters applied."
+        );
+        if self.state == SMState::Idle {
+            self.load_plans(current_time);
+        }
+    }
+
+    /// The weekly target of each sector for the month of `current_time`
+    pub fn select_targets(&mut self, current_time: i64) {
+        for sector in self.sectors.values_mut() {
+            let base = self.base_targets.get(&sector.id).copied().unwrap_or(sector.weekly_target);
+            sector.weekly_target = seasonal_target(&self.targets, sector.id, base, current_time);
+        }
+    }
+
+    /// A new seasonal curve of the sector, already saved. The plans follow unless a cycle is running.
+    pub fn apply_targets(&mut self, sector: u32, targets: Vec<SectorTarget>, current_time: i64) {
+        self.targets.retain(|target| target.sector != sector);
+        self.targets.extend(targets);
+        self.select_targets(current_time);
+        info!(sector, "Seasonal targets applied.");
+        if self.state == SMState::Idle {
+            self.load_plans(current_time);
+        }
+    }
+
+    /// Re-arms the machine after an emergency stop, it starts over from Idle
+    pub fn trans_clear_emergency_stop(&mut self, current_time: i64) {
+        info!("Emergency stop cleared.");
+        self.state = SMState::Idle;
+        self.emit(current_time, None, StateChange::EmergencyStopCleared);
+    }
+
+    /// A cycle belongs to the plan of the mode that started it, so changing mode closes the active sector and drops
+    /// the cycle (consuming its plan entry) before switching. The new mode starts from Idle, and is saved so it
+    /// survives a restart.
+    pub async fn trans_change_mode(&mut self, new_mode: Mode, current_time: i64) {
+        if new_mode == self.current_mode {
+            return;
         }
+        info!(current_mode = ?self.current_mode, new_mode = ?new_mode, "Changing mode.");
+        let open = match self.state {
+            SMState::Watering(sec) => Some(sec),
+            _ => None,
+        };
+        let dropped_cycle = self.cycle.as_ref().map(|cycle| cycle.id);
+        self.abort_cycle(current_time, open).await;
+        let change = StateChange::ModeChanged { from: self.current_mode, to: new_mode };
+        let was_off = self.current_mode == Mode::Off;
+        self.current_mode = new_mode;
+        self.check_db(self.db.store_mode(new_mode), "save the mode");
+        self.emit_event(StateEvent {
+            timestamp: current_time,
+            sector: open.map(|sec| sec.id),
+            cycle: dropped_cycle,
+            change,
+        });
+        if was_off || new_mode == Mode::Off {
+            self.load_plans(current_time);
+        } else if new_mode == Mode::Wizard {
+            self.record_wizard_plans(current_time);
+        }
+    }
+
+    /// What the wizard means to water from now on, for the planned against watered stats
+    fn record_wizard_plans(&self, current_time: i64) {
+        let plans = self.mode_wizard.daily_plan.clone();
+        self.check_db(self.db.store_wizard_plans(current_time, plans), "save the wizard plans");
     }
 
-    pub fn handle_signal(&mut self, signal: CtrlSignal, current_time: i64) {
+    pub async fn handle_signal(&mut self, signal: CtrlSignal, current_time: i64) {
+        self.follow_clock_step();
         match (&mut self.state, signal) {
+            (_, CtrlSignal::EmergencyStop) => self.trans_emergency_stop(current_time).await,
+            (SMState::Stopped, CtrlSignal::ClearEmergencyStop) => self.trans_clear_emergency_stop(current_time),
+            // nothing but a clear gets the machine out of the stop
+            (SMState::Stopped, signal) => trace!(signal = ?signal, "Emergency stop latched, signal ignored."),
             // Idle state
-            (SMState::Idle, CtrlSignal::ChgMode(new_mode)) => self.trans_change_mode(new_mode),
+            (SMState::Idle, CtrlSignal::ChgMode(new_mode)) => self.trans_change_mode(new_mode, current_time).await,
             (SMState::Idle, CtrlSignal::Weather(_)) => {}
             (SMState::Idle, CtrlSignal::StopMachine) => {}
             // Watering State
-            (SMState::Watering(_), CtrlSignal::ChgMode(new_mode)) => self.trans_change_mode(new_mode),
-            (SMState::Watering(_), CtrlSignal::Weather(env_signal)) => self.trans_pause(env_signal, current_time),
-            (SMState::Watering(_), CtrlSignal::StopMachine) => self.trans_change_mode(Mode::Manual),
+            (SMState::Watering(_), CtrlSignal::ChgMode(new_mode)) => {
+                self.trans_change_mode(new_mode, current_time).await
+            }
+            // the weather a weather-proof sector watered through may clear before the next sector
+            (SMState::Watering(_), CtrlSignal::Weather(env_signal)) if env_signal.ends().is_some() => {
+                self.trans_resume(env_signal, current_time).await
+            }
+            (SMState::Watering(_), CtrlSignal::Weather(env_signal)) => self.trans_pause(env_signal, current_time).await,
+            (SMState::Watering(_), CtrlSignal::StopMachine) => self.trans_change_mode(Mode::Manual, current_time).await,
             // Paused State
-            (SMState::Paused(_), CtrlSignal::ChgMode(new_mode)) => self.trans_change_mode(new_mode),
-            (SMState::Paused(_), CtrlSignal::Weather(env_signal)) => self.trans_resume(env_signal, current_time),
-            (SMState::Paused(_), CtrlSignal::StopMachine) => self.trans_change_mode(Mode::Manual),
+            (SMState::Paused(_), CtrlSignal::ChgMode(new_mode)) => self.trans_change_mode(new_mode, current_time).await,
+            (SMState::Paused(_), CtrlSignal::Weather(env_signal)) if env_signal.ends().is_some() => {
+                self.trans_resume(env_signal, current_time).await
+            }
+            (SMState::Paused(_), CtrlSignal::Weather(env_signal)) => self.trans_pause(env_signal, current_time).await,
+            (SMState::Paused(_), CtrlSignal::StopMachine) => self.trans_change_mode(Mode::Manual, current_time).await,
+            // Any state
+            (_, CtrlSignal::SectorFault(fault)) => self.trans_fault(fault, current_time).await,
             _ => {}
         }
+        self.debug_check_invariants();
     }
 
     pub fn do_daily_adjustments(&mut self, current_time: i64, daily_et: f64, daily_rain: f64) {
@@ -264,50 +1142,206 @@ impl StateMachine {
         if new_week {
             info!("New week.")
         }
-        // 1. Adjust progress for each sector
-        adjust_daily_sector_progress(
-            &mut self.sectors.values_mut().collect::<Vec<_>>(),
-            daily_et,
-            daily_rain,
-            new_week,
-        );
+        self.select_targets(current_time);
+        // 1. Adjust progress for each sector, in a water restriction with only part of yesterday's ET
+        let yesterday = sod(current_time) - 86_400;
+        for sector in self.sectors.values_mut() {
+            let et = daily_et * et_share(&self.restrictions, sector, yesterday);
+            let cm = adjust_sector_progress(sector, et, daily_rain, new_week);
+            record_db(&self.db_fault, self.db.add_sector_progress(sector.id, cm, 0), "save the daily adjustment");
+        }
+        self.check_db(self.db.store_progress_day(sod(current_time)), "save the daily adjustment");
+
+        // at the hour the window closes it is still the one just over, the plans go in the next
+        self.timeframe.roll_window(current_time, &self.window_overrides);
+        self.load_plans(current_time);
+        self.debug_check_invariants();
+    }
+
+    /// Drops the plans that should have started before `current_time`, all but the running cycle's, after the clock
+    /// jumped over them. The wizard ones are taken off its queue.
+    pub fn drop_missed_plans(&mut self, current_time: i64) {
+        let running = self.cycle.as_ref().map(|cycle| cycle.id);
+        let kept = |plan: &DailyPlan| {
+            plan.0.first().is_some_and(|sec| sec.start >= current_time || Some(sec.start) == running)
+        };
+        let missed = self.mode_wizard.daily_plan.iter().filter(|plan| !kept(plan)).filter_map(|plan| plan.0.first());
+        if let Some(last) = missed.map(|sec| sec.start).max() {
+            self.check_db(self.db.complete_wizard_plans(last), "mark the missed wizard plans completed");
+        }
+        let before = self.mode_auto.daily_plan.len() + self.mode_wizard.daily_plan.len();
+        self.mode_auto.daily_plan.retain(kept);
+        self.mode_wizard.daily_plan.retain(kept);
+        let dropped = before - self.mode_auto.daily_plan.len() - self.mode_wizard.daily_plan.len();
+        if dropped > 0 {
+            warn!(dropped, "Plans missed in the jump of the clock dropped.");
+        }
+    }
 
-        // 2. Recalculate the next day plan for wizard_mode, so we can switch at any time and the info is up to date
-        let secs_clone = &self.sectors.values().cloned().collect::<Vec<_>>();
+    /// Recalculates the plans of both modes, so we can switch at any time and the info is up to date.<br>
+    /// In Off mode no plans are loaded, they are calculated when switching back on.
+    pub fn load_plans(&mut self, current_time: i64) {
+        if self.current_mode == Mode::Off {
+            self.mode_wizard.daily_plan.clear();
+            self.mode_auto.daily_plan.clear();
+            return;
+        }
+        // Wizard: rain expected in the next 24h is counted as progress, so we don't water what the sky will,
+        // and the predicted ET is taken out, so we water what the day will take, its share in a restriction.
+        let expected_rain = expected_rain_cm(&self.forecast, current_time, current_time + 86_400);
+        let expected_et = self.predicted_et / 10.;
+        self.plan_sectors.clear();
+        self.plan_sectors.extend(self.sectors.values().filter(|sec| !self.faulted.contains(&sec.id)).map(|sec| {
+            let expected_et = expected_et * et_share(&self.restrictions, sec, current_time);
+            PlanSector {
+                progress: (sec.progress + expected_rain - expected_et).max(0.),
+                max_duration: sec
+                    .max_duration
+                    .min(self.cfg.max_duration_secs)
+                    .min(daily_cap_secs(sec, &self.cfg).unwrap_or(i64::MAX)),
+                ..PlanSector::from(sec)
+            }
+        }));
+        let avoid = hours_to_avoid(&self.forecast, &self.cfg);
         self.mode_wizard.daily_plan = calc_wizard_daily_plan(
-            secs_clone,
+            &mut self.plan_sectors,
+            &self.constraints,
+            &avoid,
             current_time,
             self.timeframe,
-            self.cfg.sector_transation_secs,
-            self.cfg.min_watering_secs,
+            &self.window_overrides,
+            &self.cfg,
         );
 
-        // 3. Recalculate the next day plan for auto_mode, so we can switch at any time and the info is up to date
-        self.mode_auto.daily_plan = load_auto_schedule(&self.auto_schedule, current_time);
+        // Auto: the weekly schedule
+        self.mode_auto.daily_plan = load_auto_schedule(&self.auto_schedule, current_time, &self.cfg);
+        if self.current_mode == Mode::Wizard {
+            self.record_wizard_plans(current_time);
+        }
+
+        let sectors = |plans: &[DailyPlan]| plans.iter().map(|plan| plan.0.len()).collect();
+        let change = StateChange::PlanRecalculated {
+            wizard: sectors(&self.mode_wizard.daily_plan),
+            auto: sectors(&self.mode_auto.daily_plan),
+        };
+        self.emit(current_time, None, change);
     }
 
     pub fn is_auto_or_wizard(&self) -> bool {
         matches!(self.current_mode, Mode::Auto | Mode::Wizard)
     }
+
+    /// What the machine should always hold and doesn't, empty when sound. Asserted in the debug builds after each
+    /// transition, and open to the property tests:
+    /// - the sectors of the plans of each mode don't overlap, nor do the plans
+    /// - the sectors of the cycle are in start order
+    /// - the active sector of a planned cycle started in the window, unless the auto programs start at any hour
+    /// - a pause is one the pause policy of the mode asks for, or one of before a restart with nothing holding it
+    pub fn check_invariants(&self) -> Vec<String> {
+        let mut broken = vec![];
+        for (mode, plans) in [(Mode::Auto, &self.mode_auto.daily_plan), (Mode::Wizard, &self.mode_wizard.daily_plan)] {
+            let mut sectors: Vec<&WaterSector> = plans.iter().flat_map(|plan| &plan.0).collect();
+            sectors.sort_by_key(|sec| sec.start);
+            for pair in sectors.windows(2) {
+                if pair[0].start + pair[0].duration > pair[1].start {
+                    broken.push(format!("{} plans: sector {} overlaps sector {}", mode, pair[0].id, pair[1].id));
+                }
+            }
+        }
+        if let Some(cycle) = &self.cycle {
+            if !cycle.daily_plan.0.is_sorted_by_key(|sec| sec.start) {
+                broken.push(format!("cycle {}: sectors out of start order", cycle.id));
+            }
+        }
+        let windowed = match self.current_mode {
+            Mode::Wizard => true,
+            Mode::Auto => self.cfg.auto_within_window,
+            Mode::Manual | Mode::Off => false,
+        };
+        if let SMState::Watering(sec) = self.state {
+            let (hour_start, hours) = (self.cfg.window_start_hour, self.cfg.window_duration_hours);
+            let window = WaterWin::around(sec.start, hour_start, hours, &self.window_overrides);
+            if windowed && !self.testing() && !window.is_within(sec.start) {
+                broken.push(format!("sector {} started at {}, out of the window", sec.id, ux_ts_to_string(sec.start)));
+            }
+        }
+        if let SMState::Paused(data) = &self.state {
+            if !data.state.is_watering() {
+                broken.push(format!("paused without a sector: {:?}", data.state));
+            }
+            let asked = |signal| self.pause_policy.action(self.current_mode, signal) == PauseAction::Pause;
+            if let Some(signal) = data.signals.iter().find(|signal| !asked(signal)) {
+                broken.push(format!("paused by {} in {}, not the policy of the mode", signal, self.current_mode));
+            }
+        }
+        broken
+    }
+
+    /// `check_invariants`, in the debug builds
+    fn debug_check_invariants(&self) {
+        let broken = self.check_invariants();
+        debug_assert!(broken.is_empty(), "state machine invariants broken: {:?}", broken);
+    }
+}
+
+/// The sectors with the progress they had when the process stopped, as of the last daily adjustment of it.
+/// It starts over at 0 in a new week, when the machine was down more than `stale_days`, or when we don't know. The
+/// progress is written as additions, so starting over is written too, or the next restart finds the old one again.
+fn restore_progress(
+    db: &dyn DatabaseTrait, sectors: Vec<SectorInfo>, current_time: i64, stale_days: i64,
+) -> Result<HashMap<u32, SectorInfo>, AppError> {
+    let progress_day = db.load_progress_day();
+    let today = sod(current_time);
+    let monday = today - i64::from(get_week_day_from_ts(current_time).num_days_from_monday()) * 86_400;
+    let Some(day) = progress_day.filter(|&day| day >= monday && today - day <= stale_days * 86_400) else {
+        info!(progress_day, "Starting the week's progress over.");
+        for sector in sectors.iter().filter(|sector| sector.progress != 0.) {
+            db.add_sector_progress(sector.id, -sector.progress, 0)?;
+        }
+        db.flush()?;
+        return Ok(load_sectors_into_hashmap(sectors));
+    };
+    info!(progress_day = day, "Restoring the week's progress.");
+    Ok(sectors.into_iter().map(|sector| (sector.id, sector)).collect())
 }
 
-fn load_auto_schedule(schedule: &Schedule, current_time: i64) -> Vec<DailyPlan> {
-    let mut plans: Vec<DailyPlan> = Vec::with_capacity(2);
+/// The enabled programs from one daily adjustment to the next, a plan each, in start order: those of the day at or
+/// after the adjustment hour and those of the next day before it. A program that would start while the one before
+/// is still watering waits for it to end and the sector transition, as the valves are opened one at a time.
+fn load_auto_schedule(schedule: &Schedule, current_time: i64, cfg: &Watering) -> Vec<DailyPlan> {
+    let mut plans: Vec<DailyPlan> = Vec::new();
 
-    let current_weekday = get_week_day_from_ts(current_time);
-    let day_start = sod(current_time);
+    let adjustment = cfg.adjustment_hour() * 3600;
+    let day_start = sod(current_time - adjustment);
+    let weekday = get_week_day_from_ts(day_start);
+    let next_weekday = get_week_day_from_ts(day_start + 86_400);
 
-    for schedule in schedule.entries.iter() {
-        if let ScheduleType::Weekday(weekday) = schedule.schedule_type {
-            if weekday == current_weekday {
-                let mut daily_plan = Vec::new();
-                for sec in schedule.start_times.0.iter() {
+    for schedule_entry in schedule.entries.iter().filter(|entry| schedule.is_enabled(&entry.program)) {
+        if let ScheduleType::Weekday(entry_weekday) = schedule_entry.schedule_type {
+            let mut daily_plan = Vec::new();
+            for sec in schedule_entry.start_times.0.iter() {
+                if entry_weekday == weekday && sec.start >= adjustment {
                     daily_plan.push(WaterSector::new(sec.id, day_start + sec.start, sec.duration));
+                } else if entry_weekday == next_weekday && sec.start < adjustment {
+                    daily_plan.push(WaterSector::new(sec.id, day_start + 86_400 + sec.start, sec.duration));
                 }
+            }
+            if !daily_plan.is_empty() {
                 daily_plan.sort_by_key(|sector| sector.start); // Sort by start time
                 plans.push(DailyPlan(daily_plan));
             }
         }
     }
+    plans.sort_by_key(|plan| plan.0[0].start);
+
+    let mut free_at: Option<i64> = None;
+    for plan in plans.iter_mut() {
+        let delay = free_at.map_or(0, |free_at| (free_at - plan.0[0].start).max(0));
+        for sec in plan.0.iter_mut() {
+            sec.start += delay;
+        }
+        let last = plan.0.last().unwrap();
+        free_at = Some(last.start + last.duration + cfg.sector_transation_secs);
+    }
     plans
 }
@@ -0,0 +1,69 @@
+use super::ds::{Cycle, DailyPlan, WaterSector};
+use serde::{Deserialize, Serialize};
+
+/// Longest a sector runs in a test, it is a look at the heads and not a watering
+pub const MAX_TEST_SECS: i64 = 600;
+
+/// How a sector did in the test run
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TestRunSector {
+    pub sector: u32,
+    /// Unix UTC timestamp it is planned to open at
+    pub start: i64,
+    /// none until its turn, false when the valve didn't take the command
+    pub activated: Option<bool>,
+    pub error: Option<String>,
+}
+
+/// Every sector opened briefly in order, the spring walk around the yard to check the heads
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TestRun {
+    pub started: i64,
+    pub seconds_per_sector: i64,
+    pub sectors: Vec<TestRunSector>,
+    /// the last sector closed, or the run was stopped
+    pub finished: bool,
+}
+
+impl TestRun {
+    /// The run and the cycle that waters it, the sectors `transition_secs` apart like in a planned cycle
+    pub fn plan(sectors: &[u32], start: i64, seconds_per_sector: i64, transition_secs: i64) -> (Self, Cycle) {
+        let plan: Vec<WaterSector> = sectors
+            .iter()
+            .enumerate()
+            .map(|(n, &id)| {
+                WaterSector::new(id, start + n as i64 * (seconds_per_sector + transition_secs), seconds_per_sector)
+            })
+            .collect();
+        let sectors = plan
+            .iter()
+            .map(|sec| TestRunSector { sector: sec.id, start: sec.start, activated: None, error: None })
+            .collect();
+        let run = TestRun { started: start, seconds_per_sector, sectors, finished: false };
+        (run, Cycle::build(DailyPlan(plan)))
+    }
+
+    /// The outcome of a sector's turn, a later error (the valve read back) replaces an earlier success
+    pub fn record(&mut self, sector: u32, result: Result<(), String>) {
+        if let Some(sec) = self.sectors.iter_mut().find(|sec| sec.sector == sector) {
+            sec.activated = Some(result.is_ok());
+            sec.error = result.err();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn spaces_the_sectors() {
+        let (mut run, cycle) = TestRun::plan(&[1, 3], 1000, 60, 20);
+        let starts: Vec<_> = cycle.daily_plan.0.iter().map(|sec| (sec.id, sec.start, sec.duration)).collect();
+        assert_eq!(starts, [(1, 1000, 60), (3, 1080, 60)]);
+
+        run.record(3, Err("relay 3 didn't answer".to_owned()));
+        assert_eq!((run.sectors[0].activated, run.sectors[1].activated), (None, Some(false)));
+        assert_eq!(run.sectors[1].error.as_deref(), Some("relay 3 didn't answer"));
+    }
+}
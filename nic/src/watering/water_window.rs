@@ -1,4 +1,5 @@
-use crate::utils::sod;
+use crate::{config::WindowOverrideCfg, utils::sod};
+use chrono::DateTime;
 
 #[derive(Debug, Clone, Copy)]
 pub struct WaterWin {
@@ -6,6 +7,8 @@ pub struct WaterWin {
     pub duration_secs: i64, // Duration in seconds (can span across days)
     pub day_start_time: i64,
     pub day_end_time: i64,
+    /// start hour and hours of the days without an override
+    pub base: (i64, i64),
 }
 
 impl WaterWin {
@@ -14,7 +17,37 @@ impl WaterWin {
         let day_start_time = sod(current_time) + hour_start * 3600;
         let duration_secs = duration_hours * 3600;
         let day_end_time = day_start_time + duration_secs - 1;
-        Self { hour_start, duration_secs, day_start_time, day_end_time }
+        Self { hour_start, duration_secs, day_start_time, day_end_time, base: (hour_start, duration_hours) }
+    }
+
+    /// `new`, with what the first of `overrides` for the day of `current_time` changes. An empty window, of 0 hours,
+    /// has nothing within.
+    pub fn with_overrides(
+        current_time: i64, hour_start: i64, duration_hours: i64, overrides: &[WindowOverrideCfg],
+    ) -> Self {
+        let day = DateTime::from_timestamp(sod(current_time), 0).map(|time| time.date_naive());
+        let window = day.and_then(|day| overrides.iter().find(|window| window.applies(day)));
+        let (start, hours) = window.map_or((hour_start, duration_hours), |window| {
+            (window.start_hour.unwrap_or(hour_start), window.duration_hours.unwrap_or(duration_hours))
+        });
+        Self { base: (hour_start, duration_hours), ..Self::new(current_time, start, hours) }
+    }
+
+    /// The window `time` falls in, which for a window across midnight may have started the day before. Otherwise the
+    /// one of the day of `time`.
+    pub fn around(time: i64, hour_start: i64, duration_hours: i64, overrides: &[WindowOverrideCfg]) -> Self {
+        let previous = Self::with_overrides(time - 86_400, hour_start, duration_hours, overrides);
+        if previous.is_within(time) {
+            previous
+        } else {
+            Self::with_overrides(time, hour_start, duration_hours, overrides)
+        }
+    }
+
+    /// The window of the day after, with the override of that day
+    pub fn next_day(&self, overrides: &[WindowOverrideCfg]) -> Self {
+        let (hour_start, duration_hours) = self.base;
+        Self::with_overrides(sod(self.day_start_time) + 86_400, hour_start, duration_hours, overrides)
     }
 
     pub fn next_mut(&mut self) {
@@ -28,9 +61,9 @@ impl WaterWin {
         new_tf
     }
 
-    pub fn roll_window(&mut self, current_time: i64) {
+    pub fn roll_window(&mut self, current_time: i64, overrides: &[WindowOverrideCfg]) {
         if current_time > self.day_end_time {
-            self.next_mut();
+            *self = self.next_day(overrides);
         }
     }
 
@@ -56,7 +89,7 @@ impl WaterWin {
 pub mod tests {
     use chrono::{TimeZone, Utc};
 
-    use crate::{utils::sod, watering::water_window::WaterWin};
+    use crate::{config::WindowOverrideCfg, utils::sod, watering::water_window::WaterWin};
 
     #[test]
     fn allowed_timeframe_same_day() {
@@ -118,6 +151,49 @@ pub mod tests {
         assert_eq!(waterwin.day_end_time, waterwin.day_start_time + waterwin.duration_secs - 1);
     }
 
+    #[test]
+    fn waterwin_around() {
+        // 02:00, inside the window that opened at 22:00 the day before
+        let curr_time = Utc.with_ymd_and_hms(2024, 11, 26, 2, 0, 0).unwrap().timestamp();
+        let tf = WaterWin::around(curr_time, 22, 8, &[]);
+        assert_eq!(tf.day_start_time, Utc.with_ymd_and_hms(2024, 11, 25, 22, 0, 0).unwrap().timestamp());
+        assert!(tf.is_within(curr_time));
+
+        // 07:00, the next one
+        let curr_time = Utc.with_ymd_and_hms(2024, 11, 26, 7, 0, 0).unwrap().timestamp();
+        let tf = WaterWin::around(curr_time, 22, 8, &[]);
+        assert_eq!(tf.day_start_time, Utc.with_ymd_and_hms(2024, 11, 26, 22, 0, 0).unwrap().timestamp());
+    }
+
+    #[test]
+    fn waterwin_with_overrides() {
+        // a monday, no watering friday nights and a later start on the weekend
+        let monday = Utc.with_ymd_and_hms(2024, 11, 25, 12, 0, 0).unwrap().timestamp();
+        let overrides = [
+            WindowOverrideCfg { weekdays: vec!["fri".to_owned()], duration_hours: Some(0), ..Default::default() },
+            WindowOverrideCfg {
+                weekdays: vec!["sat".to_owned(), "sun".to_owned()],
+                start_hour: Some(23),
+                ..Default::default()
+            },
+        ];
+        let mut tf = WaterWin::with_overrides(monday, 22, 8, &overrides);
+        assert_eq!((tf.hour_start, tf.duration_secs), (22, 8 * 3600));
+        for _ in 0..4 {
+            tf = tf.next_day(&overrides);
+        }
+        assert_eq!(tf.day_start_time, sod(monday) + 4 * 86_400 + 22 * 3600);
+        assert!(!tf.is_within(tf.day_start_time), "friday has no window");
+
+        tf.roll_window(tf.day_start_time, &overrides);
+        assert_eq!((tf.hour_start, tf.duration_secs), (23, 8 * 3600), "saturday");
+        assert_eq!(tf.day_end_time, sod(monday) + 6 * 86_400 + 7 * 3600 - 1);
+
+        // sunday at 03:00 is still in saturday's window
+        let tf = WaterWin::around(sod(monday) + 6 * 86_400 + 3 * 3600, 22, 8, &overrides);
+        assert_eq!(tf.day_start_time, sod(monday) + 5 * 86_400 + 23 * 3600);
+    }
+
     #[test]
     fn waterwin_next() {
         let fixed_time = Utc.with_ymd_and_hms(2023, 12, 25, 0, 0, 0).unwrap().timestamp();
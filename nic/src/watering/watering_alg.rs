@@ -3,10 +3,20 @@ use super::{
     water_window::WaterWin,
     DAILY_PERCOLATION_FACTOR, SECS_TO_HOUR_CONV,
 };
-use crate::utils::get_week_day_from_ts;
+use crate::{
+    config::{PlanningStrategy, RestrictionCfg, Watering, WindowOverrideCfg},
+    utils::{get_month_from_ts, get_week_day_from_ts},
+    weather::forecast::HourlyForecast,
+};
+use chrono::Weekday;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
 use tracing::debug;
 
-#[derive(Clone, Debug)]
+/// The program of the schedules written before there were programs
+pub const DEFAULT_PROGRAM: &str = "A";
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum ScheduleType {
     Weekday(chrono::Weekday), // For auto mode
     Date(i64),                // For wizard mode (specific dates)
@@ -14,34 +24,191 @@ pub enum ScheduleType {
 
 #[derive(Clone, Debug)]
 pub struct ScheduleEntry {
+    /// A, B, C..., each with its own days and start times
+    pub program: String,
     pub schedule_type: ScheduleType,
     pub start_times: DailyPlan,
+    /// the cron expression the entry was worked out from, written back in its place
+    pub cron: Option<String>,
 }
 
 #[derive(Clone, Debug)]
 pub struct Schedule {
     pub entries: Vec<ScheduleEntry>,
+    /// Programs kept but not watered
+    pub disabled: BTreeSet<String>,
 }
 
 impl Schedule {
     pub fn new(entries: Vec<ScheduleEntry>) -> Self {
-        Self { entries }
+        Self { entries, disabled: BTreeSet::new() }
+    }
+
+    pub fn is_enabled(&self, program: &str) -> bool {
+        !self.disabled.contains(program)
+    }
+
+    /// The programs with entries, and whether they are enabled
+    pub fn programs(&self) -> BTreeMap<&str, bool> {
+        self.entries.iter().map(|entry| (entry.program.as_str(), self.is_enabled(&entry.program))).collect()
+    }
+}
+
+/// Two sectors kept apart, a shared hydraulic branch or a slope that has to drain first
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SectorConstraint {
+    pub sectors: (u32, u32),
+    /// from the end of one to the start of the other. 0 only keeps them from running one right after the other.
+    #[serde(default)]
+    pub min_gap_secs: i64,
+}
+
+impl SectorConstraint {
+    /// The other sector, when it is about `sector`
+    pub fn other(&self, sector: u32) -> Option<u32> {
+        match self.sectors {
+            (a, b) if a == sector => Some(b),
+            (a, b) if b == sector => Some(a),
+            _ => None,
+        }
+    }
+
+    /// `adjacent` when nothing waters between the two runs
+    fn broken_by(&self, a: &WaterSector, b: &WaterSector, adjacent: bool) -> bool {
+        let gap = (b.start - (a.start + a.duration)).max(a.start - (b.start + b.duration));
+        self.other(a.id) == Some(b.id)
+            && match self.min_gap_secs {
+                0 => adjacent,
+                min_gap => gap < min_gap,
+            }
+    }
+}
+
+/// The weekly target of a sector in a month of the year, the months without one take the sector's `weekly_target`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SectorTarget {
+    pub sector: u32,
+    /// 1 January .. 12 December
+    pub month: u32,
+    /// cm
+    pub weekly_target: f64,
+}
+
+/// The runs of the weekly schedule that break the constraints. The programs of a day are taken together, and the
+/// week wraps, so Sunday night is close to Monday morning.
+pub fn check_constraints(schedule: &Schedule, constraints: &[SectorConstraint]) -> Vec<String> {
+    const WEEK: i64 = 7 * 86_400;
+    let mut runs: Vec<(Weekday, WaterSector)> = Vec::new();
+    for entry in schedule.entries.iter() {
+        if let ScheduleType::Weekday(day) = entry.schedule_type {
+            let day_start = i64::from(day.num_days_from_monday()) * 86_400;
+            runs.extend(
+                entry.start_times.0.iter().map(|sec| (day, WaterSector { start: day_start + sec.start, ..*sec })),
+            );
+        }
+    }
+    runs.sort_by_key(|(_, sec)| sec.start);
+
+    let at = |(day, sec): &(Weekday, WaterSector)| {
+        format!("{} {:02}:{:02}", day, sec.start % 86_400 / 3600, sec.start % 3600 / 60)
+    };
+    let mut problems = Vec::new();
+    for (n, a) in runs.iter().enumerate() {
+        for (m, b) in runs.iter().enumerate().skip(n + 1) {
+            let wrapped = WaterSector { start: b.1.start - WEEK, ..b.1 };
+            let adjacent = m == n + 1 && a.0 == b.0;
+            let broken =
+                constraints.iter().find(|c| c.broken_by(&a.1, &b.1, adjacent) || c.broken_by(&a.1, &wrapped, false));
+            if let Some(c) = broken {
+                let rule = match c.min_gap_secs {
+                    0 => "must not run back to back".to_owned(),
+                    secs => format!("must be at least {}s apart", secs),
+                };
+                problems.push(format!("sector {} at {} and sector {} at {} {}", a.1.id, at(a), b.1.id, at(b), rule));
+            }
+        }
+    }
+    problems
+}
+
+/// Letters and digits, so it fits a CSV column and a URL path
+pub fn is_program_name(name: &str) -> bool {
+    !name.is_empty() && name.len() <= 16 && name.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+/// What the wizard planner reads of a sector. It is `Copy`, so the plan is worked out on a scratch list the caller
+/// keeps between days, without cloning the names.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlanSector {
+    pub id: u32,
+    /// cm per week
+    pub weekly_target: f64,
+    /// cm, spent by the planner as it places the sector
+    pub progress: f64,
+    /// cm/hour
+    pub sprinkler_debit: f64,
+    pub max_duration: i64,
+}
+
+impl From<&SectorInfo> for PlanSector {
+    fn from(sec: &SectorInfo) -> Self {
+        Self {
+            id: sec.id,
+            weekly_target: sec.weekly_target,
+            progress: sec.progress,
+            sprinkler_debit: sec.sprinkler_debit,
+            max_duration: sec.max_duration,
+        }
+    }
+}
+
+impl PlanSector {
+    /// Seconds to reach the weekly target, within `max_duration`. None when it is met
+    pub fn irrigation_time(&self) -> Option<i64> {
+        let remaining_target = self.weekly_target - self.progress; // Total water needed in cm
+        if remaining_target <= 0. {
+            return None; // No watering needed; target met
+        }
+        let irrigation_time = ((remaining_target / self.sprinkler_debit) * 3600.0).ceil() as i64;
+        Some(irrigation_time.min(self.max_duration))
     }
 }
 
 pub fn adjust_daily_sector_progress(sectors: &mut [&mut SectorInfo], daily_et: f64, daily_rain: f64, new_week: bool) {
-    let adjustment = daily_et - daily_rain + if new_week { 2.5 } else { 0. };
-    let mut percolation;
     for sector in sectors.iter_mut() {
-        percolation = calc_daily_percolation(sector).max(0.0);
-        sector.progress = (sector.progress - adjustment - percolation).max(0.);
-        debug!(
-                "Sector {}: Adjusted progress by -{:.2} cm due to evapotranspiration, -{:.2} due to percolation and +{:.2} mm due to rain. New progress: {:.2} cm.",
-                sector.id, daily_et, percolation, daily_rain, sector.progress
-            );
+        adjust_sector_progress(sector, daily_et, daily_rain, new_week);
+    }
+}
+
+/// One sector's share of `adjust_daily_sector_progress`. Returns the change, in cm
+pub fn adjust_sector_progress(sector: &mut SectorInfo, daily_et: f64, daily_rain: f64, new_week: bool) -> f64 {
+    let adjustment = daily_et - daily_rain + if new_week { 2.5 } else { 0. };
+    let percolation = calc_daily_percolation(sector).max(0.0);
+    let before = sector.progress;
+    sector.progress = (sector.progress - adjustment - percolation).max(0.);
+    debug!(
+            "Sector {}: Adjusted progress by -{:.2} cm due to evapotranspiration, -{:.2} due to percolation and +{:.2} cm due to rain. New progress: {:.2} cm.",
+            sector.id, daily_et, percolation, daily_rain, sector.progress
+        );
+    sector.progress - before
+}
+
+/// Of the ET of the day of `time`, what the sector gets back. `et_pct` in a declared restriction, the first that
+/// has the day, unless the sector is exempt.
+pub fn et_share(restrictions: &[RestrictionCfg], sector: &SectorInfo, time: i64) -> f64 {
+    match restrictions.iter().find(|restriction| restriction.contains(time)) {
+        Some(restriction) if !sector.deficit_exempt => restriction.et_pct / 100.,
+        _ => 1.,
     }
 }
 
+/// The weekly target of the sector in the month of `time`, `base` when its curve doesn't have the month
+pub fn seasonal_target(targets: &[SectorTarget], sector: u32, base: f64, time: i64) -> f64 {
+    let month = get_month_from_ts(time);
+    let target = targets.iter().find(|target| target.sector == sector && target.month == month);
+    target.map_or(base, |target| target.weekly_target)
+}
+
 /// Calculate dialy percolation in the soil in cm
 pub fn calc_daily_percolation(sector: &SectorInfo) -> f64 {
     sector.percolation_rate * DAILY_PERCOLATION_FACTOR
@@ -49,62 +216,128 @@ pub fn calc_daily_percolation(sector: &SectorInfo) -> f64 {
 
 /// Calculate irrigation time in seconds
 pub fn calc_irrigation_time(sector: &SectorInfo) -> Option<i64> {
-    let remaining_target = sector.weekly_target - sector.progress; // Total water needed in cm
-    if remaining_target <= 0. {
-        return None; // No watering needed; target met
-    }
-    let irrigation_time = ((remaining_target / sector.sprinkler_debit) * 3600.0).ceil() as i64;
-    Some(irrigation_time.min(sector.max_duration))
+    PlanSector::from(sector).irrigation_time()
+}
+
+/// Seconds the sector may water in a watering day, the tightest of its own caps and the global ones. None when
+/// nothing caps it.
+pub fn daily_cap_secs(sector: &SectorInfo, cfg: &Watering) -> Option<i64> {
+    let minutes = [sector.max_daily_minutes, cfg.max_daily_minutes].into_iter().flatten().map(|minutes| minutes * 60);
+    let mm = [sector.max_daily_mm, cfg.max_daily_mm].into_iter().flatten();
+    let mm = mm.map(|mm| (mm / 10. / sector.sprinkler_debit * 3600.) as i64);
+    minutes.chain(mm).min()
+}
+
+/// Start of the forecast hours a session shouldn't run in, rain (probability weighted) or wind over the `[watering]`
+/// limits
+pub fn hours_to_avoid(forecast: &[HourlyForecast], cfg: &Watering) -> Vec<i64> {
+    let rainy =
+        |hour: &HourlyForecast| cfg.avoid_rain_mm > 0. && hour.rain * hour.rain_probability >= cfg.avoid_rain_mm;
+    let windy = |hour: &HourlyForecast| cfg.avoid_wind_kmh > 0. && hour.wind_speed >= cfg.avoid_wind_kmh;
+    forecast.iter().filter(|hour| rainy(hour) || windy(hour)).map(|hour| hour.timestamp).collect()
 }
 
+/// The plans up to the end of the week. The progress in `sectors` is used up as the sectors are placed, then each
+/// session is moved within its window off the `avoid` hours, then split in cycles of at most `max_cycle_secs`. The
+/// transition between sectors and the shortest watering worth opening a valve for come from `cfg`, the days after
+/// `timeframe` take their window from `overrides`.
 pub fn calc_wizard_daily_plan(
-    sectors: &[SectorInfo], current_time: i64, timeframe: WaterWin, sec_transition_secs: i64, min_watering_secs: i64,
+    sectors: &mut [PlanSector], constraints: &[SectorConstraint], avoid: &[i64], current_time: i64,
+    timeframe: WaterWin, overrides: &[WindowOverrideCfg], cfg: &Watering,
 ) -> Vec<DailyPlan> {
     let remaining_days = calculate_remaining_days(current_time);
-    let mut plans = gen_wizard_daily_plan(sectors, remaining_days, timeframe, sec_transition_secs, min_watering_secs);
+    let mut plans = gen_wizard_daily_plan(sectors, constraints, remaining_days, timeframe, overrides, cfg);
     plans.iter_mut().for_each(|daily_plan| {
         daily_plan.0.sort_by_key(|sector| sector.start);
     });
-    plans
+    place_around(&mut plans, avoid, current_time, timeframe, overrides);
+    split_cycles(plans, cfg.max_cycle_secs)
+}
+
+/// Splits each session in consecutive cycles of at most `max_cycle_secs`, from the first start to the last end. A
+/// sector longer than that is a cycle of its own, 0 keeps the sessions whole.
+fn split_cycles(plans: Vec<DailyPlan>, max_cycle_secs: i64) -> Vec<DailyPlan> {
+    if max_cycle_secs <= 0 {
+        return plans;
+    }
+    let mut cycles = Vec::with_capacity(plans.len());
+    for plan in plans {
+        let mut cycle: Vec<WaterSector> = Vec::new();
+        for sec in plan.0 {
+            if cycle.first().is_some_and(|first| sec.start + sec.duration - first.start > max_cycle_secs) {
+                cycles.push(DailyPlan(std::mem::take(&mut cycle)));
+            }
+            cycle.push(sec);
+        }
+        if !cycle.is_empty() {
+            cycles.push(DailyPlan(cycle));
+        }
+    }
+    cycles
+}
+
+/// Moves each session as a whole to the start nearest its own, the later one on a tie, that keeps it inside its
+/// window and out of the hours to avoid. One that can't be kept out stays, the weather pause takes care of it.
+fn place_around(
+    plans: &mut [DailyPlan], avoid: &[i64], current_time: i64, timeframe: WaterWin, overrides: &[WindowOverrideCfg],
+) {
+    if avoid.is_empty() {
+        return;
+    }
+    let (hour_start, duration_hours) = timeframe.base;
+    for plan in plans.iter_mut() {
+        let (Some(first), Some(last)) = (plan.0.first(), plan.0.last()) else { continue };
+        let (start, length) = (first.start, last.start + last.duration - first.start);
+        let window = WaterWin::around(start, hour_start, duration_hours, overrides);
+        let earliest = window.day_start_time.max(current_time);
+        let latest = window.day_end_time + 1 - length;
+        let clear = |from: i64| {
+            (earliest..=latest).contains(&from)
+                && avoid.iter().all(|&hour| hour + 3600 <= from || hour >= from + length)
+        };
+        if clear(start) {
+            continue;
+        }
+        let candidates = avoid.iter().flat_map(|&hour| [hour + 3600, hour - length]);
+        let Some(best) = candidates.filter(|&from| clear(from)).min_by_key(|&from| ((from - start).abs(), -from))
+        else {
+            debug!(start, "No clear hours in the window for the session, it keeps its start.");
+            continue;
+        };
+        plan.0.iter_mut().for_each(|sec| sec.start += best - start);
+        debug!(start, moved_to = best, "Session moved off the forecast rain or wind.");
+    }
 }
 
 /// Is always called at new day (midnight), which means that when turned on, only will water next day morning.
 /// If one needs immediate watering, should do a manual watering
 #[allow(clippy::option_map_unit_fn)] //complexity/readability.
 fn gen_wizard_daily_plan(
-    sectors: &[SectorInfo], remaining_days: i64, mut timeframe: WaterWin, sec_transition_secs: i64,
-    min_watering_secs: i64,
+    sectors: &mut [PlanSector], constraints: &[SectorConstraint], remaining_days: i64, mut timeframe: WaterWin,
+    overrides: &[WindowOverrideCfg], cfg: &Watering,
 ) -> Vec<DailyPlan> {
-    let mut plans = Vec::with_capacity(2); // at max we have a morning and evening session
+    let mut plans = Vec::with_capacity(2); // at max we have a morning and evening session, split in cycles later
 
-    // Clone sectors to modify their progress during calculation without altering original values
-    let mut sectors = sectors.to_vec();
     for rem_days in (0..remaining_days).rev() {
-        // Check if there's unmet target across all sectors
-        if !sectors.iter().all(|sec| sec.weekly_target > sec.progress) {
-            timeframe.next_mut();
+        // Check if there's unmet target across all sectors, for the balanced plan in any of them
+        let unmet = |sec: &PlanSector| sec.weekly_target > sec.progress;
+        let skip = match cfg.planning_strategy {
+            PlanningStrategy::Greedy => !sectors.iter().all(unmet),
+            PlanningStrategy::Balanced => !sectors.iter().any(unmet),
+        };
+        // or if it has no window
+        if skip || timeframe.duration_secs <= 0 {
+            timeframe = timeframe.next_day(overrides);
             continue; // Skip this day if no sector needs watering
         }
-        let (need_evening, mut daily_plan) = get_next_wiz_watering_for_day(
-            &mut sectors,
-            &mut timeframe,
-            rem_days,
-            true,
-            sec_transition_secs,
-            min_watering_secs,
-        );
+        let (need_evening, mut daily_plan) =
+            get_next_wiz_watering_for_day(sectors, constraints, &mut timeframe, rem_days, true, cfg);
         daily_plan.take().map(|p| plans.push(p));
         // advance timeframe.  either will serve the next day at 22, and also the next morning if the evening whatering is not needed
-        timeframe.next_mut();
-        if need_evening {
-            let (_, mut daily_plan) = get_next_wiz_watering_for_day(
-                &mut sectors,
-                &mut timeframe,
-                rem_days,
-                false,
-                sec_transition_secs,
-                min_watering_secs,
-            );
+        timeframe = timeframe.next_day(overrides);
+        if need_evening && timeframe.duration_secs > 0 {
+            let (_, mut daily_plan) =
+                get_next_wiz_watering_for_day(sectors, constraints, &mut timeframe, rem_days, false, cfg);
             daily_plan.take().map(|p| plans.push(p));
         }
         if !plans.is_empty() {
@@ -115,34 +348,61 @@ fn gen_wizard_daily_plan(
 }
 
 fn get_next_wiz_watering_for_day(
-    sectors: &mut [SectorInfo], timeframe: &mut WaterWin, remaining_days: i64, morning: bool, sec_transition_secs: i64,
-    min_watering_secs: i64,
+    sectors: &mut [PlanSector], constraints: &[SectorConstraint], timeframe: &mut WaterWin, remaining_days: i64,
+    morning: bool, cfg: &Watering,
 ) -> (bool, Option<DailyPlan>) {
+    let (sec_transition_secs, min_watering_secs) = (cfg.sector_transation_secs, cfg.min_watering_secs);
     let mut daily_plan = DailyPlan::new();
     let mut need_evening = false;
     let mut water_time = if morning { timeframe.day_end_time } else { timeframe.day_start_time };
-    let sector_iter: Box<dyn Iterator<Item = &mut SectorInfo>> =
-        if morning { Box::new(sectors.iter_mut().rev()) } else { Box::new(sectors.iter_mut()) };
+    let count = sectors.len();
+    let mut waiting = Vec::with_capacity(count);
 
-    for sector in sector_iter {
+    // the morning is laid out backwards from the end of the window
+    for n in 0..count {
+        let idx = if morning { count - 1 - n } else { n };
+        let sector = &sectors[idx];
         // Calculate remaining weekly water needs for the sector
         let remaining_weekly_need = (sector.weekly_target - sector.progress).max(0.0);
         let daily_capacity = (sector.max_duration as f64 * SECS_TO_HOUR_CONV) * sector.sprinkler_debit;
 
-        // Skip the sector if the (remaining days - 1) are sufficient to fulfill its needs
-        if remaining_weekly_need <= daily_capacity * (remaining_days - 1) as f64 {
-            continue;
-        }
         if remaining_weekly_need > daily_capacity * remaining_days as f64 {
             need_evening = true;
         }
+        let secs_irrigation_time = match cfg.planning_strategy {
+            PlanningStrategy::Greedy => {
+                // Skip the sector if the (remaining days - 1) are sufficient to fulfill its needs
+                if remaining_weekly_need <= daily_capacity * (remaining_days - 1) as f64 {
+                    continue;
+                }
+                let secs = sector.irrigation_time().unwrap_or(0);
+                if secs <= min_watering_secs {
+                    continue; // Skip sectors with negligible needs
+                }
+                secs
+            }
+            PlanningStrategy::Balanced => {
+                // `remaining_days` are the ones after this
+                let Some(secs) = balanced_secs(sector, remaining_days + 1, min_watering_secs) else { continue };
+                secs
+            }
+        };
+        waiting.push((idx, secs_irrigation_time));
+    }
+    if cfg.planning_strategy == PlanningStrategy::Balanced {
+        fit_to_window(&mut waiting, timeframe.duration_secs, cfg);
+    }
 
-        let secs_irrigation_time = calc_irrigation_time(sector).unwrap_or(0);
-        if secs_irrigation_time <= min_watering_secs {
-            continue; // Skip sectors with negligible needs
-        }
-
-        let proposed_start = if morning { water_time - secs_irrigation_time - sec_transition_secs } else { water_time };
+    // in that order, but a sector the constraints keep from its turn waits for a later one, or for another day
+    let propose =
+        |water_time: i64, secs: i64| if morning { water_time - secs - sec_transition_secs } else { water_time };
+    while let Some(pos) = waiting.iter().position(|&(idx, secs)| {
+        let candidate = WaterSector::new(sectors[idx].id, propose(water_time, secs), secs);
+        !breaks_constraints(&daily_plan, &candidate, constraints)
+    }) {
+        let (idx, secs_irrigation_time) = waiting.remove(pos);
+        let sector = &mut sectors[idx];
+        let proposed_start = propose(water_time, secs_irrigation_time);
 
         daily_plan.0.push(WaterSector::new(sector.id, proposed_start, secs_irrigation_time));
         sector.progress += secs_irrigation_time as f64 * (sector.sprinkler_debit * SECS_TO_HOUR_CONV);
@@ -153,9 +413,48 @@ fn get_next_wiz_watering_for_day(
             water_time += secs_irrigation_time + sec_transition_secs; // Move later for evening sessions
         }
     }
+    if !waiting.is_empty() {
+        debug!("{} sectors left for another day by the constraints", waiting.len());
+    }
     (need_evening, (!daily_plan.0.is_empty()).then_some(daily_plan))
 }
 
+/// The sector's even share of what is left of the week, more when the nights after this one can't make up the rest,
+/// and no less than `min_watering_secs` so a small share isn't put off night after night. None when the need is
+/// negligible. `nights` left in the week, this one included.
+fn balanced_secs(sector: &PlanSector, nights: i64, min_watering_secs: i64) -> Option<i64> {
+    let need_secs = ((sector.weekly_target - sector.progress).max(0.) / sector.sprinkler_debit * 3600.).ceil() as i64;
+    if need_secs <= min_watering_secs {
+        return None;
+    }
+    let later = sector.max_duration * (nights - 1).max(0);
+    let share = (need_secs / nights.max(1)).max(need_secs - later);
+    Some(share.max(min_watering_secs).min(need_secs).min(sector.max_duration))
+}
+
+/// Shrinks the sessions in proportion when they and their transitions don't fit in the window. The ones that fall
+/// under `min_watering_secs` are left for another night.
+fn fit_to_window(waiting: &mut Vec<(usize, i64)>, window_secs: i64, cfg: &Watering) {
+    // the morning is laid out back from the last second of the window, a transition after each sector
+    let transitions = waiting.len() as i64 * cfg.sector_transation_secs + 1;
+    let total: i64 = waiting.iter().map(|&(_, secs)| secs).sum();
+    if total + transitions <= window_secs {
+        return;
+    }
+    let room = (window_secs - transitions).max(0);
+    debug!(total, room, "The night's shares don't fit in the window, shrinking them.");
+    waiting.iter_mut().for_each(|(_, secs)| *secs = *secs * room / total);
+    waiting.retain(|&(_, secs)| secs > cfg.min_watering_secs);
+}
+
+/// The last one placed is next to the candidate, whether the session is laid out forwards or backwards
+fn breaks_constraints(plan: &DailyPlan, candidate: &WaterSector, constraints: &[SectorConstraint]) -> bool {
+    let last = plan.0.len().checked_sub(1);
+    plan.0.iter().enumerate().any(|(n, placed)| {
+        constraints.iter().any(|constraint| constraint.broken_by(placed, candidate, Some(n) == last))
+    })
+}
+
 fn calculate_remaining_days(current_time: i64) -> i64 {
     7 - get_week_day_from_ts(current_time).num_days_from_sunday() as i64
 }
@@ -173,12 +472,20 @@ mod test {
     fn mock_sector_info(
         id: u32, weekly_target: f64, progress: f64, sprinkler_debit: f64, percolation_rate: f64, max_duration: i64,
     ) -> SectorInfo {
-        SectorInfo { id, weekly_target, progress, sprinkler_debit, percolation_rate, max_duration, last_water: 0 }
+        SectorInfo {
+            id,
+            weekly_target,
+            progress,
+            sprinkler_debit,
+            percolation_rate,
+            max_duration,
+            ..Default::default()
+        }
     }
 
     #[tokio::test]
     async fn et_adjustments() {
-        let mut sectors = vec![SectorInfo::build(1, 3., 1., 30 * 60, 0.5, 0.5, 0)];
+        let mut sectors = [SectorInfo::build(1, 3., 1., 30 * 60, 0.5, 0.5, 0)];
         let secs = &mut sectors.iter_mut().collect::<Vec<&mut SectorInfo>>();
         adjust_daily_sector_progress(secs, 1., 0.5, false);
         assert!(sectors[0].progress == 0.5 - 1. + 0.5)
@@ -198,6 +505,19 @@ mod test {
         assert_eq!(irrigation_time, Some(1800)); // Only needs 0.5 hour
     }
 
+    #[test]
+    fn daily_cap_is_the_tightest() {
+        let mut sector = mock_sector(1, 10.0, 0.0, 3600, 2.0); // 2cm/hr, 6 minutes a mm
+        let mut cfg = crate::config::Watering::default();
+        assert_eq!(daily_cap_secs(&sector, &cfg), None);
+        cfg.max_daily_minutes = Some(60);
+        sector.max_daily_mm = Some(5.);
+        assert_eq!(daily_cap_secs(&sector, &cfg), Some(900));
+        cfg.max_daily_mm = Some(2.);
+        sector.max_daily_minutes = Some(10);
+        assert_eq!(daily_cap_secs(&sector, &cfg), Some(360));
+    }
+
     #[test]
     fn calculate_irrigation_time() {
         let sector = SectorInfo::build(1, 2.5, 1.0, 30 * 60, 1., 0.5, 0);
@@ -209,10 +529,8 @@ mod test {
 
     #[test]
     fn daily_et_adjustment() {
-        let mut sectors = vec![
-            SectorInfo::build(1, 2.5, 1., 30 * 60, 1.5, 0., 0),
-            SectorInfo::build(2, 1.8, 0.8, 20 * 60, 0.5, 0., 0),
-        ];
+        let mut sectors =
+            [SectorInfo::build(1, 2.5, 1., 30 * 60, 1.5, 0., 0), SectorInfo::build(2, 1.8, 0.8, 20 * 60, 0.5, 0., 0)];
 
         let daily_et = 0.3;
         let secs = &mut sectors.iter_mut().collect::<Vec<&mut SectorInfo>>();
@@ -222,6 +540,35 @@ mod test {
         assert_eq!(sectors[1].progress, 0.2); // Reduced by 0.3 but clamped to 0.2
     }
 
+    #[test]
+    fn restrictions_give_back_part_of_the_et() {
+        let summer = RestrictionCfg { from: "2024-07-01".to_owned(), to: "2024-08-31".to_owned(), et_pct: 80. };
+        let restrictions = [summer];
+        let july = Utc.with_ymd_and_hms(2024, 7, 15, 0, 0, 0).unwrap().timestamp();
+        let mut lawn = mock_sector(1, 2.5, 2., 3600, 1.);
+        let patch = SectorInfo { deficit_exempt: true, ..mock_sector(2, 2.5, 2., 3600, 1.) };
+        assert_eq!((et_share(&restrictions, &lawn, july), et_share(&restrictions, &patch, july)), (0.8, 1.));
+        assert_eq!(et_share(&restrictions, &lawn, july + 60 * 86_400), 1., "over by september");
+
+        let et = 0.5 * et_share(&restrictions, &lawn, july);
+        adjust_sector_progress(&mut lawn, et, 0., false);
+        assert!((lawn.progress - 1.6).abs() < 1e-9);
+    }
+
+    #[test]
+    fn the_target_follows_the_season() {
+        let curve = [
+            SectorTarget { sector: 1, month: 7, weekly_target: 3.5 },
+            SectorTarget { sector: 1, month: 10, weekly_target: 1. },
+            SectorTarget { sector: 2, month: 10, weekly_target: 0.5 },
+        ];
+        let at = |month| Utc.with_ymd_and_hms(2024, month, 15, 0, 0, 0).unwrap().timestamp();
+        assert_eq!(seasonal_target(&curve, 1, 2.5, at(7)), 3.5);
+        assert_eq!(seasonal_target(&curve, 1, 2.5, at(10)), 1.);
+        assert_eq!(seasonal_target(&curve, 1, 2.5, at(5)), 2.5, "no target for may");
+        assert_eq!(seasonal_target(&curve, 3, 2.5, at(10)), 2.5, "no curve");
+    }
+
     #[test]
     fn test_calculate_remaining_days() {
         // we checked that this day is a wednesday
@@ -235,17 +582,18 @@ mod test {
 
     #[test]
     fn generate_weekly_plan_with_waterwin() {
-        let sectors =
-            vec![mock_sector_info(1, 10.0, 5.0, 2.0, 0.5, 3600), mock_sector_info(2, 15.0, 10.0, 1.5, 0.4, 3600)];
+        let sectors = [mock_sector_info(1, 10.0, 5.0, 2.0, 0.5, 3600), mock_sector_info(2, 15.0, 10.0, 1.5, 0.4, 3600)];
         let fixed_time = Utc.with_ymd_and_hms(2023, 12, 25, 0, 0, 0).unwrap().timestamp();
         let timeframe = WaterWin::new(fixed_time, 6, 12);
 
         let current_time = timeframe.day_start_time; // Fixed current time
         let remaining_days = calculate_remaining_days(current_time);
-        let weekly_plan = gen_wizard_daily_plan(&sectors, remaining_days, timeframe, 20, 300);
+        let mut sectors: Vec<_> = sectors.iter().map(PlanSector::from).collect();
+        let weekly_plan =
+            gen_wizard_daily_plan(&mut sectors, &[], remaining_days, timeframe, &[], &Watering::default());
 
         assert!(!weekly_plan.is_empty());
-        if let Some(daily_plan) = weekly_plan.get(0) {
+        if let Some(daily_plan) = weekly_plan.first() {
             assert!(!daily_plan.0.is_empty());
             assert!(daily_plan.0.iter().all(|sector| timeframe.is_within_or_future(sector.start)));
         }
@@ -254,12 +602,13 @@ mod test {
     #[test]
     fn test_get_next_watering_for_day() {
         let fixed_time = Utc.with_ymd_and_hms(2024, 12, 14, 2, 0, 0).unwrap().timestamp();
-        let mut sectors =
-            vec![mock_sector_info(1, 10.0, 9.0, 1.0, 0.1, 3600), mock_sector_info(2, 8.0, 7.5, 0.8, 0.2, 2700)];
+        let sectors = [mock_sector_info(1, 10.0, 9.0, 1.0, 0.1, 3600), mock_sector_info(2, 8.0, 7.5, 0.8, 0.2, 2700)];
+        let mut sectors: Vec<_> = sectors.iter().map(PlanSector::from).collect();
         let mut timeframe = WaterWin::new(fixed_time, 6, 12);
 
         // Call the function for morning session
-        let result_morning = get_next_wiz_watering_for_day(&mut sectors, &mut timeframe, 1, true, 20, 300);
+        let result_morning =
+            get_next_wiz_watering_for_day(&mut sectors, &[], &mut timeframe, 1, true, &Watering::default());
 
         // Assert that a valid daily plan is returned for morning
         assert!(result_morning.1.is_some(), "Morning session should have a valid daily plan.");
@@ -267,7 +616,8 @@ mod test {
         assert!(!daily_plan.0.is_empty(), "Morning session should have watering tasks.");
 
         // Validate evening session
-        let result_evening = get_next_wiz_watering_for_day(&mut sectors, &mut timeframe, 7, false, 20, 300);
+        let result_evening =
+            get_next_wiz_watering_for_day(&mut sectors, &[], &mut timeframe, 7, false, &Watering::default());
 
         // Assert that the evening session is valid only if more progress is needed
         if sectors.iter().any(|sec| sec.weekly_target > sec.progress) {
@@ -284,16 +634,181 @@ mod test {
 
     #[test]
     fn test_calc_daily_plan_with_waterwin() {
-        let sectors =
-            vec![mock_sector_info(1, 10.0, 5.0, 2.0, 0.5, 3600), mock_sector_info(2, 15.0, 10.0, 1.5, 0.4, 3600)];
+        let sectors = [mock_sector_info(1, 10.0, 5.0, 2.0, 0.5, 3600), mock_sector_info(2, 15.0, 10.0, 1.5, 0.4, 3600)];
         let fixed_time = Utc.with_ymd_and_hms(2023, 12, 25, 0, 0, 0).unwrap().timestamp();
         let timeframe = WaterWin::new(fixed_time, 6, 12);
         let current_time = timeframe.day_start_time + 10;
 
-        let daily_plan = calc_wizard_daily_plan(&sectors, current_time, timeframe, 20, 300);
+        let mut sectors: Vec<_> = sectors.iter().map(PlanSector::from).collect();
+        let daily_plan =
+            calc_wizard_daily_plan(&mut sectors, &[], &[], current_time, timeframe, &[], &Watering::default());
 
         assert!(!daily_plan.is_empty());
-        let daily_plan = daily_plan.get(0).unwrap();
+        let daily_plan = daily_plan.first().unwrap();
         assert!(!daily_plan.0.is_empty());
     }
+
+    #[test]
+    fn the_plan_follows_the_watering_config() {
+        let fixed_time = Utc.with_ymd_and_hms(2024, 12, 14, 2, 0, 0).unwrap().timestamp();
+        // 30, 6 and 60 minutes of watering
+        let sectors = [
+            mock_sector_info(1, 10.0, 9.5, 1.0, 0.1, 3600),
+            mock_sector_info(2, 10.0, 9.9, 1.0, 0.1, 3600),
+            mock_sector_info(3, 10.0, 9.0, 1.0, 0.1, 3600),
+        ];
+        let plan = |cfg: &Watering| {
+            let mut sectors: Vec<_> = sectors.iter().map(PlanSector::from).collect();
+            let mut timeframe = WaterWin::new(fixed_time, 6, 12);
+            let (_, plan) = get_next_wiz_watering_for_day(&mut sectors, &[], &mut timeframe, 1, false, cfg);
+            let start = timeframe.day_start_time;
+            plan.unwrap().0.iter().map(|sec| (sec.id, sec.start - start)).collect::<Vec<_>>()
+        };
+        let cfg = Watering::default();
+        assert_eq!(plan(&cfg), [(1, 0), (2, 1820), (3, 2200)]);
+        assert_eq!(plan(&Watering { sector_transation_secs: 60, ..cfg }), [(1, 0), (2, 1860), (3, 2280)]);
+        // 6 minutes isn't worth opening the valve for
+        assert_eq!(plan(&Watering { min_watering_secs: 600, ..cfg }), [(1, 0), (3, 1820)]);
+    }
+
+    #[test]
+    fn a_day_without_window_is_skipped() {
+        // Monday, with no watering on monday nights
+        let monday = Utc.with_ymd_and_hms(2024, 6, 3, 12, 0, 0).unwrap().timestamp();
+        let overrides =
+            [WindowOverrideCfg { weekdays: vec!["mon".to_owned()], duration_hours: Some(0), ..Default::default() }];
+        let cfg = Watering { planning_strategy: PlanningStrategy::Balanced, ..Default::default() };
+        let mut sectors = vec![PlanSector::from(&mock_sector_info(1, 2.5, 0., 1., 0.1, 1800))];
+        let timeframe = WaterWin::with_overrides(monday, 22, 8, &overrides);
+        let plans = calc_wizard_daily_plan(&mut sectors, &[], &[], monday, timeframe, &overrides, &cfg);
+        let tuesday = timeframe.next_day(&overrides);
+        assert!(!plans.is_empty() && plans[0].0.iter().all(|sec| tuesday.is_within(sec.start)));
+        assert!(plans.iter().flat_map(|plan| plan.0.iter()).all(|sec| sec.start >= tuesday.day_start_time));
+    }
+
+    #[test]
+    fn balanced_spreads_the_week() {
+        // Monday, six nights to Saturday, 2h30 of watering a sector and half an hour a night at most
+        let monday = Utc.with_ymd_and_hms(2024, 6, 3, 12, 0, 0).unwrap().timestamp();
+        let nights = |planning_strategy, window_hours| {
+            let cfg = Watering { planning_strategy, ..Default::default() };
+            let mut sectors: Vec<_> =
+                (1..=4).map(|id| PlanSector::from(&mock_sector_info(id, 2.5, 0., 1., 0.1, 1800))).collect();
+            let mut nights = Vec::new();
+            for day in 0..6 {
+                let now = monday + day * 86_400;
+                let timeframe = WaterWin::new(now, 22, window_hours);
+                let mut scratch = sectors.clone();
+                let plans = calc_wizard_daily_plan(&mut scratch, &[], &[], now, timeframe, &[], &cfg);
+                let tonight = plans.iter().flat_map(|plan| plan.0.iter()).filter(|sec| timeframe.is_within(sec.start));
+                let mut secs = Vec::new();
+                for sec in tonight {
+                    let sector = sectors.iter_mut().find(|sector| sector.id == sec.id).unwrap();
+                    sector.progress += sec.duration as f64 * SECS_TO_HOUR_CONV * sector.sprinkler_debit;
+                    secs.push(sec.duration);
+                }
+                nights.push(secs);
+            }
+            nights
+        };
+        let total = |nights: Vec<Vec<i64>>| nights.iter().map(|secs| secs.iter().sum()).collect::<Vec<i64>>();
+        assert_eq!(total(nights(PlanningStrategy::Greedy, 8)), [7200, 7200, 7200, 7200, 7200, 0]);
+        assert_eq!(total(nights(PlanningStrategy::Balanced, 8)), [6000; 6]);
+
+        // an hour doesn't hold the four shares and their transitions
+        assert_eq!(nights(PlanningStrategy::Balanced, 1)[0], [879; 4]);
+    }
+
+    #[test]
+    fn sessions_split_in_cycles() {
+        let session = DailyPlan(vec![
+            WaterSector::new(1, 0, 1200),
+            WaterSector::new(2, 1220, 1200),
+            WaterSector::new(3, 2440, 3000),
+            WaterSector::new(4, 5460, 600),
+        ]);
+        let ids = |plans: &[DailyPlan]| {
+            plans.iter().map(|plan| plan.0.iter().map(|sec| sec.id).collect::<Vec<_>>()).collect::<Vec<_>>()
+        };
+        assert_eq!(ids(&split_cycles(vec![session.clone()], 0)), [vec![1, 2, 3, 4]]);
+        assert_eq!(ids(&split_cycles(vec![session.clone()], 2600)), [vec![1, 2], vec![3], vec![4]]);
+        assert_eq!(ids(&split_cycles(vec![session], 3700)), [vec![1, 2], vec![3, 4]]);
+    }
+
+    #[test]
+    fn sessions_move_off_the_forecast_rain() {
+        let day = Utc.with_ymd_and_hms(2024, 6, 3, 0, 0, 0).unwrap().timestamp();
+        let timeframe = WaterWin::new(day, 22, 8);
+        let start = timeframe.day_start_time;
+        let session = || DailyPlan(vec![WaterSector::new(1, start, 3600), WaterSector::new(2, start + 3600, 3600)]);
+        let starts = |plans: &[DailyPlan]| plans[0].0.iter().map(|sec| (sec.start - start) / 3600).collect::<Vec<_>>();
+
+        // rain from 22:00 to 01:00, clear after
+        let hour = |offset: i64, rain: f64| HourlyForecast {
+            timestamp: start + offset * 3600,
+            rain,
+            rain_probability: 0.8,
+            ..Default::default()
+        };
+        let forecast = [hour(0, 2.), hour(1, 1.), hour(2, 0.8), hour(3, 0.2)];
+        let avoid = hours_to_avoid(&forecast, &Watering::default());
+        assert_eq!(avoid.len(), 3);
+        let mut plans = [session()];
+        place_around(&mut plans, &avoid, day, timeframe, &[]);
+        assert_eq!(starts(&plans), [3, 4]);
+
+        // rain all night, nowhere to go
+        let avoid: Vec<i64> = (0..8).map(|n| start + n * 3600).collect();
+        let mut plans = [session()];
+        place_around(&mut plans, &avoid, day, timeframe, &[]);
+        assert_eq!(starts(&plans), [0, 1]);
+    }
+
+    #[test]
+    fn plans_around_the_constraints() {
+        let fixed_time = Utc.with_ymd_and_hms(2024, 12, 14, 2, 0, 0).unwrap().timestamp();
+        let sectors: Vec<_> = (1..=3).map(|id| mock_sector_info(id, 10.0, 5.0, 1.0, 0.1, 1800)).collect();
+        let cfg = Watering::default();
+        let order = |constraints: &[SectorConstraint]| {
+            let mut sectors: Vec<_> = sectors.iter().map(PlanSector::from).collect();
+            let mut timeframe = WaterWin::new(fixed_time, 6, 12);
+            let (_, plan) = get_next_wiz_watering_for_day(&mut sectors, constraints, &mut timeframe, 1, false, &cfg);
+            plan.unwrap().0.iter().map(|sec| sec.id).collect::<Vec<_>>()
+        };
+        assert_eq!(order(&[]), [1, 2, 3]);
+        // 2 waits for 3
+        assert_eq!(order(&[SectorConstraint { sectors: (2, 1), min_gap_secs: 0 }]), [1, 3, 2]);
+        // and can't fit 6 hours away in the night, it is left for another day
+        assert_eq!(order(&[SectorConstraint { sectors: (1, 2), min_gap_secs: 6 * 3600 }]), [1, 3]);
+    }
+
+    #[test]
+    fn schedule_checked_against_the_constraints() {
+        let entry = |program: &str, day, runs: &[(u32, i64)]| ScheduleEntry {
+            program: program.to_owned(),
+            schedule_type: ScheduleType::Weekday(day),
+            start_times: DailyPlan(runs.iter().map(|&(id, start)| WaterSector::new(id, start, 600)).collect()),
+            cron: None,
+        };
+        let schedule = Schedule::new(vec![
+            entry("A", Weekday::Mon, &[(3, 6 * 3600), (4, 6 * 3600 + 620)]),
+            entry("B", Weekday::Sun, &[(1, 22 * 3600)]),
+            entry("B", Weekday::Mon, &[(2, 2 * 3600)]),
+        ]);
+        assert!(check_constraints(&schedule, &[]).is_empty());
+
+        let back_to_back = [SectorConstraint { sectors: (4, 3), min_gap_secs: 0 }];
+        assert_eq!(
+            check_constraints(&schedule, &back_to_back),
+            ["sector 3 at Mon 06:00 and sector 4 at Mon 06:10 must not run back to back"]
+        );
+        // Sunday night to Monday night, over the end of the week
+        let apart = [SectorConstraint { sectors: (1, 2), min_gap_secs: 6 * 3600 }];
+        assert_eq!(
+            check_constraints(&schedule, &apart),
+            ["sector 2 at Mon 02:00 and sector 1 at Sun 22:00 must be at least 21600s apart"]
+        );
+        let day_apart = [SectorConstraint { sectors: (1, 2), min_gap_secs: 3 * 3600 }];
+        assert!(check_constraints(&schedule, &day_apart).is_empty());
+    }
 }
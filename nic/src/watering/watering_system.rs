@@ -1,20 +1,34 @@
 use super::{
+    daily_report::DailyReport,
     ds::{AppState, CtrlSignal},
+    efficiency, learning,
     modes::*,
     state_machine::*,
 };
 use crate::{
-    api::{CycleResponse, WateringStateResponse},
+    api::{ActiveSector, CycleResponse, MachineStatus, WateringStateResponse},
+    clock::{self, ClockGuard},
     config::Watering,
     db::DatabaseTrait,
     error::AppError,
+    metrics,
     sensors::interface::SensorController,
     time::TimeProvider,
     utils::sod,
+    weather::{
+        freshness::WeatherFreshness,
+        model::{EtFeatures, EtModel},
+    },
 };
 use std::sync::Arc;
-use tokio::sync::{broadcast::Receiver, Mutex};
-use tracing::info;
+use tokio::sync::{
+    broadcast::{
+        error::{RecvError, TryRecvError},
+        Receiver,
+    },
+    watch, Mutex,
+};
+use tracing::{error, info, warn};
 
 #[derive(Debug)]
 pub struct WateringSystem {
@@ -23,7 +37,15 @@ pub struct WateringSystem {
     pub time_provider: Arc<dyn TimeProvider>,  // Injected time provider
     pub db: Arc<dyn DatabaseTrait>,            // Injected db provider
     pub web_tx: tokio::sync::broadcast::Sender<CtrlSignal>,
+    pub sm_tx: Arc<tokio::sync::broadcast::Sender<CtrlSignal>>,
     pub sm_rx: Arc<Mutex<Receiver<CtrlSignal>>>,
+    pub et_model: Arc<dyn EtModel>, // Next day ET predictor for the wizard planner
+    pub freshness: Arc<WeatherFreshness>,
+    /// a `Resync` we sent after losing signals that hasn't come back yet
+    resync_pending: bool,
+    pub clock: Arc<ClockGuard>,
+    /// the jumps of the clock whose missed plans are dropped
+    seen_jumps: u32,
 }
 
 impl WateringSystem {
@@ -37,6 +59,9 @@ impl WateringSystem {
             sectors,
             current_time,
             app_state.db.clone(),
+            app_state.web_tx.clone(),
+            app_state.time_provider.clone(),
+            app_state.config.current().window_overrides.clone(),
             cfg,
         )?;
         Ok(WateringSystem {
@@ -45,52 +70,211 @@ impl WateringSystem {
             controller: app_state.sensors_ctrl.clone(),
             time_provider: app_state.time_provider.clone(),
             web_tx: app_state.web_tx.clone(),
+            sm_tx: app_state.sm_tx.clone(),
             sm_rx: app_state.sm_rx.clone(),
+            et_model: app_state.et_model.clone(),
+            freshness: app_state.freshness.clone(),
+            resync_pending: false,
+            clock: app_state.clock.clone(),
+            seen_jumps: app_state.clock.jumps(),
         })
     }
 
     async fn handle_control_signals(&mut self, current_time: i64) {
-        if let Ok(signal) = self.sm_rx.lock().await.try_recv() {
-            match signal {
-                CtrlSignal::DevicesState(_x) => {} //TODO
-                CtrlSignal::Weather(_) | CtrlSignal::StopMachine | CtrlSignal::ChgMode(_) => {
-                    self.sm.handle_signal(signal, current_time)
-                }
-                CtrlSignal::GetCycle => {
-                    let resp = self.get_cycle();
-                    let _res = self.web_tx.send(CtrlSignal::GetCycleResponse(resp));
-                }
-                CtrlSignal::GetState => {
-                    let resp = self.get_state();
-                    let _res = self.web_tx.send(CtrlSignal::GetStateResponse(resp));
-                }
-                CtrlSignal::GenWeather(_x) => {} //TODO
-                //the next arms are not needed
-                _ => (),
-                // ControlSignal::GetStateResponse(watering_state_response) => ()
-                // ControlSignal::GetCycleResponse(cycle_response) => ()
+        let signal = self.sm_rx.lock().await.try_recv();
+        match signal {
+            Ok(signal) => self.handle_control_signal(signal, current_time).await,
+            Err(TryRecvError::Lagged(missed)) => self.lagged(missed),
+            Err(_) => {}
+        }
+    }
+
+    /// Signals were lost, a `RainStart` among them maybe, so the weather is asked for again.<br>
+    /// Once until the `Resync` comes back: sending into the channel we are behind on pushes out the next signal,
+    /// and asking on every lag would never let us catch up.
+    fn lagged(&mut self, missed: u64) {
+        metrics::dropped("watering", missed);
+        warn!(missed, "Watering loop fell behind, control signals lost.");
+        if !self.resync_pending {
+            self.resync_pending = true;
+            _ = self.sm_tx.send(CtrlSignal::Resync);
+        }
+    }
+
+    async fn handle_control_signal(&mut self, signal: CtrlSignal, current_time: i64) {
+        match signal {
+            CtrlSignal::DevicesState(_x) => {} //TODO
+            CtrlSignal::Weather(_)
+            | CtrlSignal::StopMachine
+            | CtrlSignal::ChgMode(_)
+            | CtrlSignal::SectorFault(_)
+            | CtrlSignal::EmergencyStop
+            | CtrlSignal::ClearEmergencyStop => self.sm.handle_signal(signal, current_time).await,
+            CtrlSignal::GetCycle(reply) => _ = reply.send(self.get_cycle()),
+            CtrlSignal::GetState(reply) => _ = reply.send(self.get_state()),
+            CtrlSignal::GetStatus(reply) => _ = reply.send(self.get_status(current_time)),
+            CtrlSignal::ConfigUpdate(cfg) => self.sm.apply_config(&cfg, current_time),
+            CtrlSignal::ScheduleUpdate(schedule) => self.sm.apply_schedule(schedule, current_time),
+            CtrlSignal::SectorsAdded(sectors) => self.sm.add_sectors(sectors),
+            CtrlSignal::TestRun(seconds, reply) => _ = reply.send(self.sm.start_test_run(seconds, current_time).await),
+            CtrlSignal::GetTestRun(reply) => _ = reply.send(self.sm.test_run.clone()),
+            CtrlSignal::ManualStart(sectors, reply) => {
+                _ = reply.send(self.sm.start_manual(sectors, current_time).await)
+            }
+            CtrlSignal::Calibrate(sector, seconds, reply) => {
+                _ = reply.send(self.sm.start_calibration(sector, seconds, current_time).await)
+            }
+            CtrlSignal::CalibrationResult(sector, measure, reply) => {
+                _ = reply.send(self.sm.finish_calibration(sector, measure, current_time))
+            }
+            CtrlSignal::Resync => self.resync_pending = false,
+            CtrlSignal::LearnedAccepted(learned) => self.sm.apply_learned(&learned, current_time),
+            CtrlSignal::SectorTargets(sector, targets) => self.sm.apply_targets(sector, targets, current_time),
+            //the next arms are not needed
+            _ => (),
+        }
+    }
+
+    /// Next instant the loop has work: whatever the state machine is waiting for, or the next daily adjustments.
+    /// Adjustments put off by a cycle wait for its end, which the state machine wakes for.
+    fn next_wakeup(&self, now: i64, last_day: i64, end_time: Option<i64>) -> i64 {
+        let adjust_at = last_day + 86_400 + self.sm.cfg.adjustment_hour() * 3600;
+        let wake_at = self.sm.next_wakeup(now);
+        let wake_at = if adjust_at > now { wake_at.min(adjust_at) } else { wake_at };
+        end_time.map_or(wake_at, |end| wake_at.min(end))
+    }
+
+    /// Whether the time can be trusted for what's scheduled. What a jump of the clock went over is dropped rather
+    /// than watered late, all at once.
+    fn clock_trusted(&mut self, now: i64) -> bool {
+        let jumps = self.clock.jumps();
+        if jumps != self.seen_jumps {
+            self.seen_jumps = jumps;
+            self.sm.drop_missed_plans(now);
+        }
+        self.clock.trusted()
+    }
+
+    /// The day whose adjustments are the last due at `now`, the one before until the adjustment hour
+    fn adjustment_day(&self, now: i64) -> i64 {
+        sod(now - self.sm.cfg.adjustment_hour() * 3600)
+    }
+
+    /// Sleeps until `wake_at`, waking early for a control signal or the stop signal
+    async fn wait(&mut self, wake_at: i64, stop_signal: &mut watch::Receiver<bool>) {
+        let (sm_rx, time_provider) = (self.sm_rx.clone(), self.time_provider.clone());
+        let stopped = async {
+            if stop_signal.changed().await.is_err() {
+                std::future::pending::<()>().await; // sender gone, nobody can stop us anymore
             }
+        };
+        let signal = tokio::select! {
+            biased;
+            _ = stopped => None,
+            signal = async { sm_rx.lock().await.recv().await } => Some(signal),
+            _ = time_provider.sleep_until(wake_at) => None,
+        };
+        match signal {
+            Some(Ok(signal)) => self.handle_control_signal(signal, self.time_provider.now()).await,
+            Some(Err(RecvError::Lagged(missed))) => self.lagged(missed),
+            _ => {}
         }
     }
 
+    /// Once a day at the adjustment hour, the sectors' progress takes the day's ET and rain and the plans are
+    /// worked out again. A cycle running then, out of the window or a manual one, is let finish first.
     fn do_daily_adjustments(&mut self, last_day: &mut i64, now: i64) {
-        let day_start = sod(now);
-        if *last_day == day_start {
+        let day_start = self.adjustment_day(now);
+        if *last_day >= day_start {
             return; // Skip unnecessary processing if adjustments have already been made for today
         }
+        if self.sm.cycle.is_some() {
+            return;
+        }
 
         *last_day = day_start;
 
-        // Use default values directly in a single call to reduce redundant operations
-        let (daily_et, daily_rain) =
-            (self.db.get_daily_et(day_start).unwrap_or(0.0), self.db.get_lastday_rain(day_start).unwrap_or(0.0));
+        self.sm.forecast = self.db.load_forecast(now, now + 2 * 86_400).unwrap_or_default();
+        let stale = self.freshness.is_stale(now);
+        let (daily_et, daily_rain) = if stale {
+            // Station is silent: don't trust yesterday's numbers, assume an average day without rain
+            let et = self.fallback_et(day_start);
+            self.sm.predicted_et = et;
+            (et, 0.)
+        } else {
+            // Use default values directly in a single call to reduce redundant operations
+            let (daily_et, daily_rain) =
+                (self.db.get_daily_et(day_start).unwrap_or(0.0), self.db.get_lastday_rain(day_start).unwrap_or(0.0));
+            self.sm.predicted_et =
+                self.et_model.predict(&EtFeatures::new(daily_et, &self.sm.forecast, now, now + 86_400));
+            (daily_et, daily_rain)
+        };
 
-        self.sm.do_daily_adjustments(now, daily_et, daily_rain);
+        // the rollups are in mm, the progress in cm
+        self.sm.do_daily_adjustments(now, daily_et / 10., daily_rain / 10.);
         info!(
             event = "daily_adjustments",
             daily_et = format!("{:.2}", daily_et),
             daily_rain = format!("{:.2}", daily_rain),
+            predicted_et = format!("{:.2}", self.sm.predicted_et),
+            stale_weather = stale,
         );
+        self.daily_report(day_start, daily_et, daily_rain, stale);
+        self.learn(now);
+    }
+
+    /// Every `learning_every_days`, new estimates of the sectors' debit and percolation wait for the user
+    fn learn(&self, now: i64) {
+        let cfg = &self.sm.cfg;
+        if cfg.learning_every_days <= 0 || (sod(now) / 86_400) % cfg.learning_every_days != 0 {
+            return;
+        }
+        let (window, min_samples) = (cfg.learning_window_days, cfg.learning_min_samples);
+        match learning::learn(self.db.as_ref(), &self.sm.sectors, now, window, min_samples) {
+            Ok(learned) if learned.is_empty() => {}
+            Ok(learned) => {
+                let sectors = learned.len();
+                match self.db.store_learned_params(learned) {
+                    Ok(()) => info!(sectors, "New debit and percolation estimates to review (GET /learning)."),
+                    Err(e) => error!(error = ?e, "Failed to store the learned sector parameters."),
+                }
+            }
+            Err(e) => error!(error = ?e, "Failed to learn the sector parameters."),
+        }
+    }
+
+    /// Yesterday's report, stored and, when configured, pushed to the web clients
+    fn daily_report(&self, day_start: i64, daily_et: f64, daily_rain: f64, stale: bool) {
+        let yesterday = day_start - 86_400;
+        let events = self.db.load_system_events(yesterday, day_start).unwrap_or_default();
+        let mut report = DailyReport::from_events(yesterday, &events, &self.sm.sectors);
+        report.et = daily_et;
+        report.rain = daily_rain;
+        report.predicted_et = self.sm.predicted_et;
+        report.plan = self.sm.next_plan().cloned().unwrap_or_default();
+        match efficiency::efficiency(self.db.as_ref(), &self.sm.sectors, day_start, efficiency::REPORT_WEEKS) {
+            Ok(sectors) => report.efficiency = sectors,
+            Err(e) => error!(error = ?e, "Failed to work out the watering efficiency."),
+        }
+        if stale {
+            report.anomalies.push("stale weather, the fallback ET was used".to_owned());
+        }
+        let mut faulted: Vec<_> = self.sm.faulted.iter().collect();
+        faulted.sort_unstable();
+        report.anomalies.extend(faulted.into_iter().map(|sector| format!("sector {} faulted", sector)));
+
+        if let Err(e) = self.db.store_daily_report(report.clone()) {
+            error!(error = ?e, "Failed to store the daily report.");
+        }
+        if self.sm.cfg.notify_daily_report {
+            _ = self.web_tx.send(CtrlSignal::DailyReport(report));
+        }
+    }
+
+    /// Historical ET average, used while the weather data is stale. In mm/day, like the rollups it stands for.
+    fn fallback_et(&self, day_start: i64) -> f64 {
+        let from = day_start - self.freshness.fallback_et_days * 86_400;
+        self.db.get_avg_daily_et(from, day_start).unwrap_or(self.freshness.fallback_et)
     }
 
     pub fn get_state(&self) -> WateringStateResponse {
@@ -98,6 +282,7 @@ impl WateringSystem {
 
         let state = match &self.sm.state {
             SMState::Idle => "Idle".to_string(),
+            SMState::Stopped => "Emergency stop".to_string(),
             SMState::Watering(sec) => {
                 format!("Watering sector {} for {:.2} minutes", sec.id, sec.duration_minutes())
             }
@@ -112,6 +297,31 @@ impl WateringSystem {
         WateringStateResponse { error: None, mode: Some(mode.to_string()), state: Some(state), current_cycle }
     }
 
+    pub fn get_status(&self, now: i64) -> MachineStatus {
+        let (state, active_sector) = match &self.sm.state {
+            SMState::Idle => ("idle", None),
+            SMState::Stopped => ("stopped", None),
+            SMState::Watering(sec) => {
+                ("watering", Some(ActiveSector { id: sec.id, remaining_secs: (sec.start + sec.duration - now).max(0) }))
+            }
+            SMState::Paused(data) => match *data.state {
+                SMState::Watering(ref sec) => {
+                    ("paused", Some(ActiveSector { id: sec.id, remaining_secs: sec.duration - data.elapsed }))
+                }
+                _ => unreachable!(),
+            },
+        };
+        let mut faulted: Vec<u32> = self.sm.faulted.iter().copied().collect();
+        faulted.sort_unstable();
+        MachineStatus {
+            mode: self.sm.current_mode.to_string(),
+            state: state.to_owned(),
+            active_sector,
+            next_run: self.sm.next_plan_start(),
+            faulted,
+        }
+    }
+
     pub fn get_cycle(&self) -> CycleResponse {
         CycleResponse {
             error: None,
@@ -134,19 +344,42 @@ pub async fn run_watering_system(
     let mut now = app_state.time_provider.now();
     let ws = if let Some(ws1) = ws { ws1 } else { &mut WateringSystem::new(app_state, starting_mode, now, cfg)? };
 
-    let mut last_day = sod(now);
-    let stop_signal = stop_signal; // Clone the receiver for use in the loop
-    while end_time.map_or(true, |end| now < end) && !*stop_signal.borrow() {
+    // down over the adjustment hour, the day's adjustment is still to do
+    let today = ws.adjustment_day(now);
+    let mut last_day = ws.db.load_progress_day().map_or(today, |day| day.min(today));
+    let mut stop_signal = stop_signal;
+    while end_time.is_none_or(|end| now < end) && !*stop_signal.borrow() {
         now = ws.time_provider.now();
 
+        // until the time is trusted nothing new starts, a running cycle goes on to its end
+        let trusted = ws.clock_trusted(now);
+
         // in the fn we validate if it is a new day and a new week
-        ws.do_daily_adjustments(&mut last_day, now);
+        if trusted {
+            ws.do_daily_adjustments(&mut last_day, now);
+        }
 
         ws.handle_control_signals(now).await;
 
-        ws.sm.update(now);
+        if trusted || ws.sm.cycle.is_some() {
+            ws.sm.update(now).await;
+        }
+
+        // without its writes a restart would water again, so close the valves and let the caller escalate
+        if let Some(e) = ws.sm.take_db_fault() {
+            ws.sm.shutdown(ws.time_provider.now()).await;
+            return Err(e);
+        }
 
-        ws.time_provider.advance_time(1).await;
+        let mut wake_at = ws.next_wakeup(now, last_day, end_time);
+        if !trusted {
+            wake_at = wake_at.min(now + clock::CHECK_SECS);
+        }
+        ws.wait(wake_at, &mut stop_signal).await;
+        now = ws.time_provider.now();
+    }
+    if *stop_signal.borrow() {
+        ws.sm.shutdown(ws.time_provider.now()).await;
     }
     info!("Ending watering system.");
     Ok(())
@@ -2,11 +2,19 @@ use axum::{extract::State, Json};
 
 use std::sync::Arc;
 
+use crate::sensors::telemetry::DeviceTelemetry;
 use crate::watering::ds::AppState;
+use crate::weather::forecast::HourlyForecast;
 
-pub async fn list_devices(State(_app_state): State<Arc<AppState>>) -> Json<Vec<String>> {
-    // Fetch devices from DB or MQTT
-    Json(vec!["Device1".to_string(), "Device2".to_string()])
+/// Last battery and signal report of each wireless device
+pub async fn list_devices(State(app_state): State<Arc<AppState>>) -> Json<Vec<DeviceTelemetry>> {
+    Json(app_state.db.load_device_telemetry().unwrap_or_default())
+}
+
+/// Cached hourly forecast for the next 48 hours
+pub async fn get_forecast(State(app_state): State<Arc<AppState>>) -> Json<Vec<HourlyForecast>> {
+    let now = app_state.time_provider.now();
+    Json(app_state.db.load_forecast(now - now % 3600, now + 48 * 3600).unwrap_or_default())
 }
 
 pub async fn query_weather(State(_app_state): State<Arc<AppState>>) -> Json<serde_json::Value> {
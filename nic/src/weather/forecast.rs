@@ -0,0 +1,225 @@
+use super::tempest::MS_TO_KMH;
+use crate::{config::WeatherStation, db::DatabaseTrait, error::AppError};
+use serde::{Deserialize, Serialize};
+use std::{sync::Arc, time::Duration};
+use tracing::{error, info};
+
+pub const OPEN_METEO_URL: &str = "https://api.open-meteo.com/v1/forecast";
+pub const OWM_ONECALL_URL: &str = "https://api.openweathermap.org/data/3.0/onecall";
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ForecastKind {
+    OpenMeteo,
+    OpenWeatherMap,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct HourlyForecast {
+    /// Unix UTC timestamp of the start of the hour
+    pub timestamp: i64,
+    pub temperature: f64,
+    /// mm expected during the hour
+    pub rain: f64,
+    /// 0..1
+    pub rain_probability: f64,
+    /// km/h
+    pub wind_speed: f64,
+    /// mm, reference evapotranspiration when the provider supplies it
+    pub et0: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenMeteoHourly {
+    time: Vec<i64>,
+    #[serde(default)]
+    temperature_2m: Vec<Option<f64>>,
+    #[serde(default)]
+    precipitation: Vec<Option<f64>>,
+    #[serde(default)]
+    precipitation_probability: Vec<Option<f64>>,
+    #[serde(default)]
+    wind_speed_10m: Vec<Option<f64>>,
+    #[serde(default)]
+    et0_fao_evapotranspiration: Vec<Option<f64>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenMeteoResponse {
+    hourly: OpenMeteoHourly,
+}
+
+fn at(values: &[Option<f64>], i: usize) -> Option<f64> {
+    values.get(i).copied().flatten()
+}
+
+/// Parse an Open-Meteo response requested with `timeformat=unixtime`
+pub fn parse_open_meteo(body: &str) -> Result<Vec<HourlyForecast>, serde_json::Error> {
+    let resp: OpenMeteoResponse = serde_json::from_str(body)?;
+    let hourly = resp.hourly;
+    Ok(hourly
+        .time
+        .iter()
+        .enumerate()
+        .map(|(i, &timestamp)| HourlyForecast {
+            timestamp,
+            temperature: at(&hourly.temperature_2m, i).unwrap_or(0.),
+            rain: at(&hourly.precipitation, i).unwrap_or(0.),
+            rain_probability: at(&hourly.precipitation_probability, i).unwrap_or(0.) / 100.,
+            wind_speed: at(&hourly.wind_speed_10m, i).unwrap_or(0.),
+            et0: at(&hourly.et0_fao_evapotranspiration, i),
+        })
+        .collect())
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct OwmRain {
+    #[serde(rename = "1h", default)]
+    one_hour: f64,
+}
+
+#[derive(Debug, Deserialize)]
+struct OwmHour {
+    dt: i64,
+    #[serde(default)]
+    temp: f64,
+    #[serde(default)]
+    wind_speed: f64,
+    #[serde(default)]
+    pop: f64,
+    rain: Option<OwmRain>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OwmOneCall {
+    #[serde(default)]
+    hourly: Vec<OwmHour>,
+}
+
+/// Parse an OpenWeatherMap One Call response (metric units)
+pub fn parse_owm_onecall(body: &str) -> Result<Vec<HourlyForecast>, serde_json::Error> {
+    let resp: OwmOneCall = serde_json::from_str(body)?;
+    Ok(resp
+        .hourly
+        .into_iter()
+        .map(|hour| HourlyForecast {
+            timestamp: hour.dt,
+            temperature: hour.temp,
+            rain: hour.rain.map_or(0., |rain| rain.one_hour),
+            rain_probability: hour.pop,
+            wind_speed: hour.wind_speed * MS_TO_KMH,
+            et0: None,
+        })
+        .collect())
+}
+
+pub async fn fetch_forecast(
+    client: &reqwest::Client, kind: ForecastKind, cfg: &WeatherStation,
+) -> Result<Vec<HourlyForecast>, AppError> {
+    let (lat, lon) = (cfg.geo_pos.lat.to_string(), cfg.geo_pos.long.to_string());
+    let req = match kind {
+        ForecastKind::OpenMeteo => client.get(OPEN_METEO_URL).query(&[
+            ("latitude", lat.as_str()),
+            ("longitude", lon.as_str()),
+            (
+                "hourly",
+                "temperature_2m,precipitation,precipitation_probability,wind_speed_10m,et0_fao_evapotranspiration",
+            ),
+            ("timeformat", "unixtime"),
+            ("forecast_days", "3"),
+        ]),
+        ForecastKind::OpenWeatherMap => client.get(OWM_ONECALL_URL).query(&[
+            ("lat", lat.as_str()),
+            ("lon", lon.as_str()),
+            ("exclude", "current,minutely,daily,alerts"),
+            ("units", "metric"),
+            ("appid", cfg.api_key_openweathermap.expose()),
+        ]),
+    };
+    // the url carries the key
+    let response =
+        req.send().await.and_then(reqwest::Response::error_for_status).map_err(reqwest::Error::without_url)?;
+    let body = response.text().await.map_err(reqwest::Error::without_url)?;
+    let parsed = match kind {
+        ForecastKind::OpenMeteo => parse_open_meteo(&body),
+        ForecastKind::OpenWeatherMap => parse_owm_onecall(&body),
+    };
+    parsed.map_err(|e| AppError::WeatherError(format!("Invalid {:?} forecast: {}", kind, e)))
+}
+
+/// Rain the planner can count on between `from` and `to`, in cm (probability weighted)
+pub fn expected_rain_cm(forecast: &[HourlyForecast], from: i64, to: i64) -> f64 {
+    forecast
+        .iter()
+        .filter(|hour| hour.timestamp >= from && hour.timestamp < to)
+        .map(|hour| hour.rain * hour.rain_probability)
+        .sum::<f64>()
+        / 10.
+}
+
+/// Refreshes the cached forecast every `forecast_refresh_secs`
+pub async fn run_forecast_refresh(cfg: WeatherStation, db: Arc<dyn DatabaseTrait>) {
+    let Some(kind) = cfg.forecast_provider else {
+        return;
+    };
+    info!(provider = ?kind, "Starting forecast refresh.");
+    let client = reqwest::Client::new();
+    let mut interval = tokio::time::interval(Duration::from_secs(cfg.forecast_refresh_secs.max(60)));
+    loop {
+        interval.tick().await;
+        match fetch_forecast(&client, kind, &cfg).await {
+            Ok(forecast) => {
+                info!(hours = forecast.len(), "Forecast refreshed.");
+                if let Err(e) = db.store_forecast(forecast) {
+                    error!(error = ?e, "Failed to store forecast.");
+                }
+            }
+            Err(e) => error!(error = ?e, "Failed to fetch forecast."),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_open_meteo_hourly() {
+        let body = r#"{"latitude": 40.4, "hourly": {
+            "time": [1733000400, 1733004000],
+            "temperature_2m": [11.2, null],
+            "precipitation": [0.4, 0.0],
+            "precipitation_probability": [80, 10],
+            "wind_speed_10m": [12.0, 9.5],
+            "et0_fao_evapotranspiration": [0.02, null]
+        }}"#;
+        let forecast = parse_open_meteo(body).unwrap();
+        assert_eq!(forecast.len(), 2);
+        assert_eq!(forecast[0].rain_probability, 0.8);
+        assert_eq!(forecast[0].et0, Some(0.02));
+        assert_eq!(forecast[1].temperature, 0.);
+        assert_eq!(forecast[1].et0, None);
+    }
+
+    #[test]
+    fn parse_owm_hourly() {
+        let body = r#"{"lat": 40.4, "hourly": [
+            {"dt": 1733000400, "temp": 10.0, "wind_speed": 2.0, "pop": 0.5, "rain": {"1h": 1.2}},
+            {"dt": 1733004000, "temp": 9.0, "wind_speed": 1.0, "pop": 0}
+        ]}"#;
+        let forecast = parse_owm_onecall(body).unwrap();
+        assert_eq!(forecast[0].rain, 1.2);
+        assert_eq!(forecast[0].wind_speed, 2. * MS_TO_KMH);
+        assert_eq!(forecast[1].rain, 0.);
+    }
+
+    #[test]
+    fn expected_rain_is_probability_weighted() {
+        let forecast = vec![
+            HourlyForecast { timestamp: 0, rain: 4., rain_probability: 0.5, ..Default::default() },
+            HourlyForecast { timestamp: 3600, rain: 2., rain_probability: 1., ..Default::default() },
+            HourlyForecast { timestamp: 7200, rain: 10., rain_probability: 1., ..Default::default() },
+        ];
+        assert!((expected_rain_cm(&forecast, 0, 7200) - 0.4).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,111 @@
+use crate::{config::WeatherStation, time::TimeProvider};
+use serde::Serialize;
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicI64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tracing::{info, warn};
+
+const NEVER: i64 = i64::MIN;
+const CHECK_INTERVAL_SECS: u64 = 60;
+
+/// Tracks when the last weather observation arrived, so we know when to stop trusting the station
+#[derive(Debug)]
+pub struct WeatherFreshness {
+    last_obs: AtomicI64,
+    /// 0 disables the check
+    max_age_secs: i64,
+    stale: AtomicBool,
+    /// days of daily rollups averaged into the fallback ET
+    pub fallback_et_days: i64,
+    /// mm/day, used when there is no history to average either
+    pub fallback_et: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FreshnessStatus {
+    pub last_observation: Option<i64>,
+    pub age_secs: Option<i64>,
+    pub stale: bool,
+}
+
+impl WeatherFreshness {
+    pub fn new(cfg: &WeatherStation, last_obs: Option<i64>) -> Self {
+        Self {
+            last_obs: AtomicI64::new(last_obs.unwrap_or(NEVER)),
+            max_age_secs: cfg.stale_after_hours.max(0) * 3600,
+            fallback_et_days: cfg.fallback_et_days,
+            fallback_et: cfg.fallback_et,
+            ..Self::disabled()
+        }
+    }
+
+    /// Never reports stale data
+    pub fn disabled() -> Self {
+        Self {
+            last_obs: AtomicI64::new(NEVER),
+            max_age_secs: 0,
+            stale: AtomicBool::new(false),
+            fallback_et_days: 0,
+            fallback_et: 0.,
+        }
+    }
+
+    pub fn touch(&self, timestamp: i64) {
+        self.last_obs.fetch_max(timestamp, Ordering::Relaxed);
+    }
+
+    pub fn last_observation(&self) -> Option<i64> {
+        Some(self.last_obs.load(Ordering::Relaxed)).filter(|&ts| ts != NEVER)
+    }
+
+    pub fn is_stale(&self, now: i64) -> bool {
+        self.max_age_secs > 0 && self.last_observation().is_none_or(|ts| now - ts > self.max_age_secs)
+    }
+
+    pub fn status(&self, now: i64) -> FreshnessStatus {
+        let last_observation = self.last_observation();
+        FreshnessStatus { last_observation, age_secs: last_observation.map(|ts| now - ts), stale: self.is_stale(now) }
+    }
+}
+
+/// Logs when the weather data goes stale and when it recovers
+pub async fn monitor_freshness(freshness: Arc<WeatherFreshness>, time_provider: Arc<dyn TimeProvider>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(CHECK_INTERVAL_SECS));
+    loop {
+        interval.tick().await;
+        let status = freshness.status(time_provider.now());
+        if freshness.stale.swap(status.stale, Ordering::Relaxed) == status.stale {
+            continue;
+        }
+        if status.stale {
+            warn!(last_observation = ?status.last_observation, "Weather data is stale. Planning with the fallback ET.");
+        } else {
+            info!(last_observation = ?status.last_observation, "Weather data is fresh again.");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn stale_after_max_age() {
+        let cfg = WeatherStation { stale_after_hours: 1, ..Default::default() };
+        let freshness = WeatherFreshness::new(&cfg, None);
+        assert!(freshness.is_stale(0));
+        freshness.touch(1000);
+        freshness.touch(500); // late observation doesn't move the clock back
+        assert_eq!(freshness.last_observation(), Some(1000));
+        assert!(!freshness.is_stale(4600));
+        assert_eq!(
+            freshness.status(4601),
+            FreshnessStatus { last_observation: Some(1000), age_secs: Some(3601), stale: true }
+        );
+        assert!(!WeatherFreshness::disabled().is_stale(i64::MAX));
+    }
+}
@@ -0,0 +1,262 @@
+//! Made up weather, for demos and soak tests without a station, started from the API of a build with `sim`. The temperature and the sun follow the hour of the
+//! day, the wind wanders around its mean with gusts on top, and showers come and go. The same seed gives the same
+//! weather, so a soak test can be run again.
+
+use super::provider::ProviderCtx;
+use crate::{
+    metrics,
+    time::TimeProvider,
+    watering::ds::{CtrlSignal, WeatherConditions},
+};
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use std::{f64::consts::PI, sync::Arc, time::Duration};
+use tokio::{sync::broadcast::error::RecvError, task::JoinHandle};
+use tracing::info;
+
+/// °C between the mean and the warmest hour
+const TEMPERATURE_SWING: f64 = 6.;
+/// W/m², the sun at noon on a clear day
+const SOLAR_NOON: f64 = 900.;
+
+/// What to make up, every entry optional
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Args)]
+#[serde(default)]
+pub struct SyntheticWeather {
+    /// Simulated hours of weather, 0 until stopped
+    #[arg(long, default_value_t = 24)]
+    pub hours: u32,
+    /// Simulated seconds between two observations
+    #[arg(long, default_value_t = 60)]
+    pub every_secs: u64,
+    /// Simulated seconds per second, 1 is real time. Faster, the observations run ahead of the clock.
+    #[arg(long, default_value_t = 1.)]
+    pub speed: f64,
+    /// The same seed gives the same weather
+    #[arg(long, default_value_t = 1)]
+    pub seed: u64,
+    /// °C, the mean of the day
+    #[arg(long, default_value_t = 18.)]
+    pub temperature: f64,
+    /// km/h, the mean wind
+    #[arg(long, default_value_t = 8.)]
+    pub wind: f64,
+    /// Chance of a shower starting in an hour
+    #[arg(long, default_value_t = 0.05)]
+    pub rain_chance: f64,
+}
+
+impl Default for SyntheticWeather {
+    fn default() -> Self {
+        Self { hours: 24, every_secs: 60, speed: 1., seed: 1, temperature: 18., wind: 8., rain_chance: 0.05 }
+    }
+}
+
+impl SyntheticWeather {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.every_secs == 0 {
+            return Err("every_secs must be over 0".to_owned());
+        }
+        if self.speed.is_nan() || self.speed <= 0. {
+            return Err(format!("speed must be over 0, not {}", self.speed));
+        }
+        if !(0. ..=1.).contains(&self.rain_chance) {
+            return Err(format!("rain_chance must be from 0 to 1, not {}", self.rain_chance));
+        }
+        if self.wind < 0. {
+            return Err(format!("wind can't be negative, {}", self.wind));
+        }
+        Ok(())
+    }
+
+    /// Real time between two observations
+    pub fn pace(&self) -> Duration {
+        Duration::from_secs_f64(self.every_secs as f64 / self.speed)
+    }
+}
+
+/// splitmix64, enough for weather and the same everywhere
+#[derive(Debug, Clone)]
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// in `[from, to)`
+    fn range(&mut self, from: f64, to: f64) -> f64 {
+        from + (to - from) * ((self.next_u64() >> 11) as f64 / (1u64 << 53) as f64)
+    }
+}
+
+/// The observations of a `SyntheticWeather`, `every_secs` apart from `start`, until its hours are over
+#[derive(Debug, Clone)]
+pub struct WeatherGenerator {
+    cfg: SyntheticWeather,
+    rng: Rng,
+    /// of the next observation
+    time: i64,
+    end: Option<i64>,
+    /// km/h
+    wind: f64,
+    direction: f64,
+    /// (end, mm/hour) of the shower going on
+    shower: Option<(i64, f64)>,
+}
+
+impl WeatherGenerator {
+    pub fn new(cfg: SyntheticWeather, start: i64) -> Self {
+        let end = (cfg.hours > 0).then(|| start + i64::from(cfg.hours) * 3600);
+        let mut rng = Rng(cfg.seed);
+        let direction = rng.range(0., 360.);
+        Self { wind: cfg.wind, rng, time: start, end, direction, shower: None, cfg }
+    }
+
+    /// The rain of the observation at `time`, a shower starting at the chance of the interval
+    fn rain_rate(&mut self, time: i64, secs: f64) -> f64 {
+        if self.shower.is_some_and(|(end, _)| time >= end) {
+            self.shower = None;
+        }
+        let chance = 1. - (1. - self.cfg.rain_chance).powf(secs / 3600.);
+        if self.shower.is_none() && self.rng.range(0., 1.) < chance {
+            let minutes = self.rng.range(20., 120.);
+            self.shower = Some((time + (minutes * 60.) as i64, self.rng.range(1., 15.)));
+        }
+        self.shower.map_or(0., |(_, rate)| rate)
+    }
+}
+
+impl Iterator for WeatherGenerator {
+    type Item = WeatherConditions;
+
+    fn next(&mut self) -> Option<WeatherConditions> {
+        let time = self.time;
+        if self.end.is_some_and(|end| time >= end) {
+            return None;
+        }
+        let secs = self.cfg.every_secs as f64;
+        self.time += self.cfg.every_secs as i64;
+        // by the UTC hour, the coldest at 03:00 and the warmest at 15:00
+        let hour = time.rem_euclid(86_400) as f64 / 3600.;
+        let rain_rate = self.rain_rate(time, secs);
+        let raining = rain_rate > 0.;
+
+        let mean = self.cfg.temperature;
+        let cooling = if raining { 3. } else { 0. };
+        let temperature =
+            mean + TEMPERATURE_SWING * (2. * PI * (hour - 9.) / 24.).sin() - cooling + self.rng.range(-0.3, 0.3);
+        let sun = if (6. ..18.).contains(&hour) { SOLAR_NOON * (PI * (hour - 6.) / 12.).sin() } else { 0. };
+        let solar_radiation = sun * if raining { 0.2 } else { self.rng.range(0.85, 1.) };
+        let humidity = 65. - 2.5 * (temperature - mean) + if raining { 25. } else { 0. } + self.rng.range(-2., 2.);
+
+        // pulled back to a mean that picks up in the afternoon and with the showers
+        let target = self.cfg.wind * (1. + 0.4 * (2. * PI * (hour - 10.) / 24.).sin() + if raining { 0.5 } else { 0. });
+        self.wind = (self.wind + 0.3 * (target - self.wind) + self.rng.range(-2., 2.)).max(0.);
+        let burst = if self.rng.range(0., 1.) < 0.05 { 2. } else { 1. };
+        let wind_gust = self.wind * self.rng.range(1.2, 1.6) * burst;
+        self.direction = (self.direction + self.rng.range(-15., 15.)).rem_euclid(360.);
+
+        Some(WeatherConditions {
+            timestamp: time,
+            is_raining: raining,
+            wind_speed: self.wind,
+            wind_gust,
+            wind_direction: self.direction,
+            temperature,
+            humidity: humidity.clamp(15., 100.),
+            solar_radiation,
+            rain: rain_rate * secs / 3600.,
+            rain_rate,
+        })
+    }
+}
+
+/// Hands the observations over as a station would, at their pace, until they are over. They aren't stored.
+pub async fn generate(cfg: SyntheticWeather, ctx: ProviderCtx, start: i64) {
+    let pace = cfg.pace();
+    info!(hours = cfg.hours, every_secs = cfg.every_secs, speed = cfg.speed, seed = cfg.seed, "Synthetic weather.");
+    for obs in WeatherGenerator::new(cfg, start) {
+        ctx.play(&obs);
+        tokio::time::sleep(pace).await;
+    }
+    info!("Synthetic weather over.");
+}
+
+/// Starts the synthetic weather of each `CtrlSignal::GenWeather`, in place of the one going on. An empty one stops
+/// it.
+pub async fn run_weather_generator(ctx: ProviderCtx, time_provider: Arc<dyn TimeProvider>) {
+    let mut sm_rx = ctx.sm_tx.subscribe();
+    let mut running: Option<JoinHandle<()>> = None;
+    loop {
+        match sm_rx.recv().await {
+            Ok(CtrlSignal::GenWeather(cfg)) => {
+                if let Some(task) = running.take() {
+                    task.abort();
+                    info!("Synthetic weather stopped.");
+                }
+                running = cfg.map(|cfg| tokio::spawn(generate(cfg, ctx.clone(), time_provider.now())));
+            }
+            Ok(_) => {}
+            Err(RecvError::Lagged(missed)) => metrics::dropped("generator", missed),
+            Err(RecvError::Closed) => break,
+        }
+    }
+    if let Some(task) = running {
+        task.abort();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const MIDNIGHT: i64 = 1_717_372_800; // 2024-06-03
+
+    #[test]
+    fn the_same_seed_gives_the_same_weather() {
+        let day = |seed| WeatherGenerator::new(SyntheticWeather { seed, ..Default::default() }, MIDNIGHT);
+        let first: Vec<_> = day(7).collect();
+        assert_eq!(first.len(), 24 * 60);
+        assert_eq!(first, day(7).collect::<Vec<_>>());
+        assert_ne!(first, day(8).collect::<Vec<_>>());
+        assert_eq!(first[1].timestamp - first[0].timestamp, 60);
+    }
+
+    #[test]
+    fn follows_the_hour_of_the_day() {
+        let cfg = SyntheticWeather { rain_chance: 0., every_secs: 3600, ..Default::default() };
+        let day: Vec<_> = WeatherGenerator::new(cfg, MIDNIGHT).collect();
+        assert_eq!(day.len(), 24);
+        assert!(day[15].temperature > day[3].temperature + 10., "{} {}", day[15].temperature, day[3].temperature);
+        assert_eq!(day[2].solar_radiation, 0.);
+        assert!(day[12].solar_radiation > 700.);
+        assert!(day.iter().all(|obs| !obs.is_raining && obs.wind_gust >= obs.wind_speed));
+    }
+
+    #[test]
+    fn showers_come_and_go() {
+        let cfg = SyntheticWeather { rain_chance: 0.5, hours: 72, ..Default::default() };
+        let days: Vec<_> = WeatherGenerator::new(cfg, MIDNIGHT).collect();
+        let wet = days.iter().filter(|obs| obs.is_raining).count();
+        assert!(wet > 0 && wet < days.len(), "{} of {}", wet, days.len());
+        let obs = days.iter().find(|obs| obs.is_raining).unwrap();
+        assert!((1. ..15.).contains(&obs.rain_rate));
+        assert!((obs.rain - obs.rain_rate / 60.).abs() < 1e-9);
+        assert!(days.iter().filter(|obs| !obs.is_raining).all(|obs| obs.rain == 0.));
+    }
+
+    #[test]
+    fn until_stopped_without_hours() {
+        let cfg = SyntheticWeather { hours: 0, ..Default::default() };
+        assert_eq!(WeatherGenerator::new(cfg, MIDNIGHT).take(10_000).count(), 10_000);
+        assert!(SyntheticWeather::default().validate().is_ok());
+        assert!(SyntheticWeather { every_secs: 0, ..Default::default() }.validate().is_err());
+        assert!(SyntheticWeather { speed: 0., ..Default::default() }.validate().is_err());
+        assert!(SyntheticWeather { rain_chance: 2., ..Default::default() }.validate().is_err());
+    }
+}
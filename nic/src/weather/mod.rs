@@ -1,5 +1,15 @@
 pub mod api;
+pub mod forecast;
+pub mod freshness;
+pub mod generator;
+pub mod model;
 pub mod mqtt_mon;
+pub mod openweathermap;
+pub mod provider;
+pub mod rollup;
+pub mod signals;
+pub mod tempest;
+pub mod udp;
 
 // TODO call the right function and math
 pub fn calculate_et(temp: f64, humidity: f64, wind_speed: f64, solar_radiation: f64) -> f64 {
@@ -0,0 +1,166 @@
+use super::forecast::HourlyForecast;
+use crate::{config::WeatherStation, error::AppError};
+use serde::Deserialize;
+use std::{collections::HashMap, fmt::Debug, path::Path, sync::Arc};
+use tracing::{info, warn};
+
+/// Built in model, used when `current_ml_model` is 0 or the selected model can't be loaded
+pub const DEFAULT_MODEL_ID: u32 = 0;
+
+/// What the models get to predict the ET of the next 24h
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EtFeatures {
+    /// mm, measured ET of the last full day
+    pub last_et: f64,
+    pub temp_avg: f64,
+    /// km/h
+    pub wind_avg: f64,
+    /// mm
+    pub rain: f64,
+    /// mm, sum of the forecast reference ET when the provider supplies it
+    pub forecast_et0: Option<f64>,
+}
+
+impl EtFeatures {
+    pub fn new(last_et: f64, forecast: &[HourlyForecast], from: i64, to: i64) -> Self {
+        let hours: Vec<&HourlyForecast> = forecast.iter().filter(|h| h.timestamp >= from && h.timestamp < to).collect();
+        let n = hours.len().max(1) as f64;
+        let et0: Vec<f64> = hours.iter().filter_map(|h| h.et0).collect();
+        Self {
+            last_et,
+            temp_avg: hours.iter().map(|h| h.temperature).sum::<f64>() / n,
+            wind_avg: hours.iter().map(|h| h.wind_speed).sum::<f64>() / n,
+            rain: hours.iter().map(|h| h.rain * h.rain_probability).sum(),
+            forecast_et0: (!et0.is_empty()).then(|| et0.iter().sum()),
+        }
+    }
+
+    fn get(&self, name: &str) -> Option<f64> {
+        match name {
+            "last_et" => Some(self.last_et),
+            "temp_avg" => Some(self.temp_avg),
+            "wind_avg" => Some(self.wind_avg),
+            "rain" => Some(self.rain),
+            "forecast_et0" => Some(self.forecast_et0.unwrap_or(self.last_et)),
+            _ => None,
+        }
+    }
+}
+
+pub trait EtModel: Send + Sync + Debug {
+    fn id(&self) -> u32;
+    /// Expected ET for the next 24h, in mm
+    fn predict(&self, features: &EtFeatures) -> f64;
+}
+
+/// Forecast ET0 when we have it, otherwise assume tomorrow looks like yesterday
+#[derive(Debug, Default)]
+pub struct DefaultEtModel;
+
+impl EtModel for DefaultEtModel {
+    fn id(&self) -> u32 {
+        DEFAULT_MODEL_ID
+    }
+
+    fn predict(&self, features: &EtFeatures) -> f64 {
+        features.forecast_et0.unwrap_or(features.last_et).max(0.)
+    }
+}
+
+/// `intercept + sum(weight * feature)`, loaded from `et_model_<id>.json`:
+/// ```json
+/// { "intercept": 0.2, "weights": { "last_et": 0.5, "temp_avg": 0.08, "forecast_et0": 0.4 } }
+/// ```
+#[derive(Debug, Deserialize)]
+pub struct LinearEtModel {
+    #[serde(skip)]
+    pub id: u32,
+    #[serde(default)]
+    pub intercept: f64,
+    pub weights: HashMap<String, f64>,
+}
+
+impl LinearEtModel {
+    pub fn from_json(id: u32, json: &str) -> Result<Self, AppError> {
+        let mut model: LinearEtModel = serde_json::from_str(json)
+            .map_err(|e| AppError::WeatherError(format!("Invalid ET model {}: {}", id, e)))?;
+        let probe = EtFeatures::default();
+        if let Some(unknown) = model.weights.keys().find(|name| probe.get(name).is_none()) {
+            return Err(AppError::WeatherError(format!("ET model {} uses unknown feature '{}'", id, unknown)));
+        }
+        model.id = id;
+        Ok(model)
+    }
+}
+
+impl EtModel for LinearEtModel {
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn predict(&self, features: &EtFeatures) -> f64 {
+        let sum: f64 = self.weights.iter().map(|(name, w)| w * features.get(name).unwrap_or(0.)).sum();
+        (self.intercept + sum).max(0.)
+    }
+}
+
+pub fn model_path(models_path: &str, id: u32) -> std::path::PathBuf {
+    Path::new(models_path).join(format!("et_model_{}.json", id))
+}
+
+/// Loads the model selected by `current_ml_model`
+pub fn load_et_model(cfg: &WeatherStation) -> Result<Arc<dyn EtModel>, AppError> {
+    if cfg.current_ml_model == DEFAULT_MODEL_ID {
+        return Ok(Arc::new(DefaultEtModel));
+    }
+    let path = model_path(&cfg.ml_models_path, cfg.current_ml_model);
+    let json = std::fs::read_to_string(&path)
+        .map_err(|e| AppError::WeatherError(format!("Can't read ET model {}: {}", path.display(), e)))?;
+    let model = LinearEtModel::from_json(cfg.current_ml_model, &json)?;
+    info!(model = model.id, "ET model loaded.");
+    Ok(Arc::new(model))
+}
+
+/// Like `load_et_model`, but never fails: we'd rather plan with the default model than not plan at all
+pub fn load_et_model_or_default(cfg: &WeatherStation) -> Arc<dyn EtModel> {
+    load_et_model(cfg).unwrap_or_else(|e| {
+        warn!(error = ?e, "Using the default ET model.");
+        Arc::new(DefaultEtModel)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn features_from_forecast() {
+        let forecast = vec![
+            HourlyForecast { timestamp: 0, temperature: 10., rain: 2., rain_probability: 0.5, ..Default::default() },
+            HourlyForecast { timestamp: 3600, temperature: 20., et0: Some(0.3), ..Default::default() },
+            HourlyForecast { timestamp: 90_000, temperature: 40., et0: Some(9.), ..Default::default() },
+        ];
+        let features = EtFeatures::new(4., &forecast, 0, 86_400);
+        assert_eq!(features.temp_avg, 15.);
+        assert_eq!(features.rain, 1.);
+        assert_eq!(features.forecast_et0, Some(0.3));
+        assert_eq!(DefaultEtModel.predict(&features), 0.3);
+        assert_eq!(DefaultEtModel.predict(&EtFeatures::new(4., &[], 0, 86_400)), 4.);
+    }
+
+    #[test]
+    fn linear_model() {
+        let model =
+            LinearEtModel::from_json(3, r#"{"intercept": 1, "weights": {"last_et": 0.5, "temp_avg": 0.1}}"#).unwrap();
+        let features = EtFeatures { last_et: 4., temp_avg: 20., ..Default::default() };
+        assert_eq!(model.id(), 3);
+        assert!((model.predict(&features) - 5.).abs() < 1e-9);
+
+        assert!(LinearEtModel::from_json(3, r#"{"weights": {"humidity": 1}}"#).is_err());
+
+        let cfg =
+            WeatherStation { current_ml_model: 7, ml_models_path: "/nonexistent".to_owned(), ..Default::default() };
+        assert!(load_et_model(&cfg).is_err());
+        assert_eq!(load_et_model_or_default(&cfg).id(), DEFAULT_MODEL_ID);
+    }
+}
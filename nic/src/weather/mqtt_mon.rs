@@ -1,54 +1,54 @@
-use crate::db::DatabaseTrait;
-use crate::watering::ds::CtrlSignal;
+use super::provider::ProviderCtx;
+use crate::config::MQTT;
+use crate::error::{AppError, Backoff};
+use crate::sensors::mqtt_ctrl::{mqtt_options, resubscribe};
+use crate::watering::ds::{CtrlSignal, WeatherConditions};
 use rumqttc::AsyncClient;
-use rumqttc::{Event, MqttOptions, Packet};
-use std::sync::Arc;
+use rumqttc::{Event, Packet};
 use std::time::Duration;
-use tokio::net::UdpSocket;
-use tokio::sync::broadcast;
+use tracing::warn;
 
-pub async fn monitor_udp<D: DatabaseTrait + 'static>(
-    tx: Arc<broadcast::Sender<CtrlSignal>>,
-    _db: Arc<D>,
-) {
-    let socket = UdpSocket::bind("0.0.0.0:12345").await.unwrap();
-    let mut buf = [0; 1024];
-
-    loop {
-        let (len, _addr) = socket.recv_from(&mut buf).await.unwrap();
-        if let Ok(data) = serde_json::from_slice::<serde_json::Value>(&buf[..len]) {
-            // Save to DB
-            // sqlx::query("INSERT INTO weather (data) VALUES (?)")
-            //     .bind(data.to_string())
-            //     .execute(&db_pool)
-            //     .await
-            //     .unwrap();
-
-            // Notify WebSocket clients
-            tx.send(CtrlSignal::GenWeather(data.to_string())).unwrap();
-        }
-    }
-}
-
-#[allow(clippy::single_match)]
-pub async fn monitor_mqtt(tx: Arc<broadcast::Sender<CtrlSignal>>) {
-    let mut mqttoptions = MqttOptions::new("client_id", "broker.hivemq.com", 1883);
+/// Observations from `weather_topic` on the `[mqtt]` broker
+pub async fn monitor_mqtt(mqtt: &MQTT, weather_topic: &str, ctx: ProviderCtx) -> Result<(), AppError> {
+    let mut mqttoptions = mqtt_options(mqtt, "weather")?;
     mqttoptions.set_keep_alive(Duration::from_secs(5));
 
     let (client, mut connection) = AsyncClient::new(mqttoptions, 10);
-    client
-        .subscribe("devices/+/state", rumqttc::QoS::AtLeastOnce)
-        .await
-        .unwrap();
 
-    while let Ok(event) = connection.poll().await {
-        match event {
-            Event::Incoming(Packet::Publish(publish)) => {
-                if let Ok(msg) = String::from_utf8(publish.payload.to_vec()) {
-                    tx.send(CtrlSignal::DevicesState(msg)).unwrap();
-                }
+    let mut backoff = Backoff::default();
+    loop {
+        let event = match connection.poll().await.map_err(|e| AppError::MQTTError(e.to_string())) {
+            Ok(event) => event,
+            Err(e) if e.is_retryable() => {
+                // the next poll reconnects
+                ctx.links.set("weather", false);
+                let delay = backoff.next_delay();
+                warn!(error = ?e, retry_in = ?delay, "MQTT weather connection error.");
+                tokio::time::sleep(delay).await;
+                continue;
+            }
+            Err(e) => {
+                ctx.links.set("weather", false);
+                return Err(e);
+            }
+        };
+        let publish = match event {
+            Event::Incoming(Packet::Publish(publish)) => publish,
+            Event::Incoming(Packet::ConnAck(ack)) => {
+                resubscribe(&client, &ack, &["devices/+/state", weather_topic]);
+                ctx.links.set("weather", true);
+                backoff.reset();
+                continue;
+            }
+            _ => continue, // Handle other events if necessary
+        };
+        if publish.topic == weather_topic {
+            match serde_json::from_slice::<WeatherConditions>(&publish.payload) {
+                Ok(obs) => ctx.publish(obs),
+                Err(e) => warn!(error = ?e, "Invalid weather payload on MQTT."),
             }
-            _ => {} // Handle other events if necessary
+        } else if let Ok(msg) = String::from_utf8(publish.payload.to_vec()) {
+            _ = ctx.sm_tx.send(CtrlSignal::DevicesState(msg));
         }
     }
 }
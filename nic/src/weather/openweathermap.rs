@@ -0,0 +1,112 @@
+use super::{provider::ProviderCtx, tempest::MS_TO_KMH};
+use crate::{config::WeatherStation, error::AppError, watering::ds::WeatherConditions};
+use serde::Deserialize;
+use std::time::Duration;
+use tracing::{error, info};
+
+pub const OWM_URL: &str = "https://api.openweathermap.org/data/2.5/weather";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct OwmMain {
+    temp: f64,
+    humidity: f64,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct OwmWind {
+    /// m/s with metric units
+    speed: f64,
+    deg: f64,
+    gust: f64,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct OwmRain {
+    #[serde(rename = "1h", default)]
+    one_hour: f64,
+}
+
+#[derive(Debug, Deserialize)]
+struct OwmCurrent {
+    dt: i64,
+    #[serde(default)]
+    main: OwmMain,
+    #[serde(default)]
+    wind: OwmWind,
+    rain: Option<OwmRain>,
+}
+
+/// Parse a `/data/2.5/weather` response (metric units)
+pub fn parse_current_weather(body: &str) -> Result<WeatherConditions, serde_json::Error> {
+    let resp: OwmCurrent = serde_json::from_str(body)?;
+    let rain_rate = resp.rain.map_or(0., |rain| rain.one_hour);
+    Ok(WeatherConditions {
+        timestamp: resp.dt,
+        is_raining: rain_rate > 0.,
+        wind_speed: resp.wind.speed * MS_TO_KMH,
+        wind_gust: resp.wind.gust * MS_TO_KMH,
+        wind_direction: resp.wind.deg,
+        temperature: resp.main.temp,
+        humidity: resp.main.humidity,
+        solar_radiation: 0., // not provided
+        rain: rain_rate,
+        rain_rate,
+    })
+}
+
+pub async fn fetch_current_weather(
+    client: &reqwest::Client, cfg: &WeatherStation,
+) -> Result<WeatherConditions, AppError> {
+    let body = client
+        .get(OWM_URL)
+        .query(&[
+            ("lat", cfg.geo_pos.lat.to_string()),
+            ("lon", cfg.geo_pos.long.to_string()),
+            ("units", "metric".to_owned()),
+            ("appid", cfg.api_key_openweathermap.expose().to_owned()),
+        ])
+        // the url carries the key, the errors leave it out
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(reqwest::Error::without_url)?
+        .text()
+        .await
+        .map_err(reqwest::Error::without_url)?;
+    parse_current_weather(&body).map_err(|e| AppError::WeatherError(format!("Invalid OpenWeatherMap response: {}", e)))
+}
+
+pub async fn poll_openweathermap(cfg: &WeatherStation, ctx: ProviderCtx) -> Result<(), AppError> {
+    info!("Starting OpenWeatherMap poller.");
+    let client = reqwest::Client::new();
+    let mut interval = tokio::time::interval(Duration::from_secs(cfg.poll_interval_openweathermap_secs.max(1)));
+
+    loop {
+        interval.tick().await;
+        match fetch_current_weather(&client, cfg).await {
+            Ok(obs) => ctx.publish(obs),
+            Err(e) => error!(error = ?e, "Failed to poll OpenWeatherMap."),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_owm_current() {
+        let body = r#"{"dt": 1733000000, "main": {"temp": 9.3, "humidity": 93},
+                       "wind": {"speed": 5, "deg": 200}, "rain": {"1h": 2.1}}"#;
+        let obs = parse_current_weather(body).unwrap();
+        assert_eq!(obs.timestamp, 1733000000);
+        assert_eq!(obs.wind_speed, 5. * MS_TO_KMH);
+        assert_eq!(obs.rain_rate, 2.1);
+        assert!(obs.is_raining);
+
+        let dry = parse_current_weather(r#"{"dt": 1, "main": {"temp": 20, "humidity": 40}}"#).unwrap();
+        assert!(!dry.is_raining);
+    }
+}
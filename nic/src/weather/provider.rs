@@ -0,0 +1,264 @@
+use super::{
+    freshness::WeatherFreshness,
+    mqtt_mon, openweathermap,
+    signals::{SignalGenerator, Source},
+    tempest, udp,
+};
+use crate::{
+    config::{WeatherStation, MQTT},
+    db::DatabaseTrait,
+    error::{AppError, Backoff},
+    links::Links,
+    metrics,
+    watering::ds::{CtrlSignal, WeatherConditions, WeatherData, WeatherSignal},
+};
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::{
+    fmt::Debug,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::{
+    sync::broadcast::{error::RecvError, Sender},
+    task::JoinSet,
+};
+use tracing::{error, info, warn};
+
+/// A provider that ran this long before failing gets the shortest retry delay again
+const GOOD_RUN: Duration = Duration::from_secs(300);
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ProviderKind {
+    Udp,
+    Mqtt,
+    Tempest,
+    OpenWeatherMap,
+}
+
+/// What every provider needs to hand its observations over to the rest of the system
+#[derive(Clone, Debug)]
+pub struct ProviderCtx {
+    pub db: Arc<dyn DatabaseTrait>,
+    pub sm_tx: Arc<Sender<CtrlSignal>>,
+    pub web_tx: Sender<CtrlSignal>,
+    /// shared by all providers, so the state machine sees one consistent signal stream
+    pub signals: Arc<Mutex<SignalGenerator>>,
+    pub freshness: Arc<WeatherFreshness>,
+    pub links: Arc<Links>,
+    /// the provider this context was handed to, `None` for the made up weather
+    pub source: Source,
+}
+
+impl ProviderCtx {
+    pub fn new(
+        cfg: &WeatherStation, db: Arc<dyn DatabaseTrait>, sm_tx: Arc<Sender<CtrlSignal>>, web_tx: Sender<CtrlSignal>,
+        freshness: Arc<WeatherFreshness>, links: Arc<Links>,
+    ) -> Self {
+        let signals = Arc::new(Mutex::new(SignalGenerator::new(cfg)));
+        Self { db, sm_tx, web_tx, signals, freshness, links, source: None }
+    }
+
+    /// Store the observation, forward it to the web layer and tell the state machine about threshold crossings
+    pub fn publish(&self, obs: WeatherConditions) {
+        self.freshness.touch(obs.timestamp);
+        if let Err(e) = self.db.log_weather(obs.clone()) {
+            error!(error = ?e, "Failed to store weather observation.");
+        }
+        self.play(&obs);
+    }
+
+    /// `publish` without the storing, for made up observations: they don't take the place of the station's rows,
+    /// feed the ET, or make the station look fresh
+    pub fn play(&self, obs: &WeatherConditions) {
+        _ = self.web_tx.send(CtrlSignal::WeatherData(WeatherData::from(obs)));
+        self.eval_signals(|signals| signals.eval(obs, self.source));
+    }
+
+    /// Run `f` against the shared generator and send whatever signals it produced
+    pub fn eval_signals<F: FnOnce(&mut SignalGenerator) -> Vec<WeatherSignal>>(&self, f: F) {
+        let signals = f(&mut self.signals.lock().unwrap());
+        self.send_signals(signals);
+    }
+
+    pub fn send_signals(&self, signals: Vec<WeatherSignal>) {
+        for signal in signals {
+            info!(signal = %signal, "Weather signal.");
+            _ = self.sm_tx.send(CtrlSignal::Weather(signal));
+        }
+    }
+}
+
+#[async_trait]
+pub trait WeatherProvider: Send + Sync + Debug {
+    fn kind(&self) -> ProviderKind;
+    /// Runs until the source is exhausted or fails. The manager decides what to do afterwards.
+    async fn run(&self, ctx: ProviderCtx) -> Result<(), AppError>;
+}
+
+#[derive(Debug)]
+pub struct UdpProvider {
+    pub address: String,
+}
+
+#[async_trait]
+impl WeatherProvider for UdpProvider {
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::Udp
+    }
+
+    async fn run(&self, ctx: ProviderCtx) -> Result<(), AppError> {
+        udp::monitor_udp(&self.address, ctx).await
+    }
+}
+
+#[derive(Debug)]
+pub struct MqttProvider {
+    pub mqtt: MQTT,
+    pub weather_topic: String,
+}
+
+#[async_trait]
+impl WeatherProvider for MqttProvider {
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::Mqtt
+    }
+
+    async fn run(&self, ctx: ProviderCtx) -> Result<(), AppError> {
+        mqtt_mon::monitor_mqtt(&self.mqtt, &self.weather_topic, ctx).await
+    }
+}
+
+#[derive(Debug)]
+pub struct TempestProvider {
+    pub cfg: WeatherStation,
+}
+
+#[async_trait]
+impl WeatherProvider for TempestProvider {
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::Tempest
+    }
+
+    async fn run(&self, ctx: ProviderCtx) -> Result<(), AppError> {
+        tempest::poll_tempest(&self.cfg, ctx).await
+    }
+}
+
+#[derive(Debug)]
+pub struct OpenWeatherMapProvider {
+    pub cfg: WeatherStation,
+}
+
+#[async_trait]
+impl WeatherProvider for OpenWeatherMapProvider {
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::OpenWeatherMap
+    }
+
+    async fn run(&self, ctx: ProviderCtx) -> Result<(), AppError> {
+        openweathermap::poll_openweathermap(&self.cfg, ctx).await
+    }
+}
+
+/// The MQTT provider listens on the `[mqtt]` broker
+pub fn build_providers(cfg: &WeatherStation, mqtt: &MQTT) -> Vec<Box<dyn WeatherProvider>> {
+    let mut providers: Vec<Box<dyn WeatherProvider>> = Vec::with_capacity(cfg.providers.len());
+    for kind in cfg.providers.iter() {
+        match kind {
+            ProviderKind::Udp => providers.push(Box::new(UdpProvider { address: cfg.udp_address.clone() })),
+            ProviderKind::Mqtt => {
+                providers.push(Box::new(MqttProvider { mqtt: mqtt.clone(), weather_topic: cfg.mqtt_topic.clone() }))
+            }
+            ProviderKind::Tempest if cfg.token_tempest.is_empty() => {
+                warn!("Tempest provider selected but token_tempest is not set. Skipping.")
+            }
+            ProviderKind::Tempest => providers.push(Box::new(TempestProvider { cfg: cfg.clone() })),
+            ProviderKind::OpenWeatherMap if cfg.api_key_openweathermap.is_empty() => {
+                warn!("OpenWeatherMap provider selected but api_key_openweathermap is not set. Skipping.")
+            }
+            ProviderKind::OpenWeatherMap => providers.push(Box::new(OpenWeatherMapProvider { cfg: cfg.clone() })),
+        }
+    }
+    providers
+}
+
+/// Owns the configured weather providers. Returns when all of them have stopped.
+pub async fn run_weather_providers(providers: Vec<Box<dyn WeatherProvider>>, ctx: ProviderCtx) {
+    let mut tasks = JoinSet::new();
+    for provider in providers {
+        let ctx = ProviderCtx { source: Some(provider.kind()), ..ctx.clone() };
+        info!(provider = ?provider.kind(), "Starting weather provider.");
+        tasks.spawn(async move { (provider.kind(), supervise(provider.as_ref(), ctx).await) });
+    }
+
+    while let Some(res) = tasks.join_next().await {
+        match res {
+            Ok((kind, Ok(()))) => info!(provider = ?kind, "Weather provider ended."),
+            Ok((kind, Err(e))) => {
+                error!(provider = ?kind, error = ?e, error_kind = ?e.kind(), "Weather provider failed.")
+            }
+            Err(e) => error!(error = ?e, "Weather provider panicked."),
+        }
+    }
+    warn!("No weather providers running.");
+}
+
+/// Runs the provider again after a transient failure, waiting longer each time it fails without a good run
+/// in between. Any other error is handed back.
+async fn supervise(provider: &dyn WeatherProvider, ctx: ProviderCtx) -> Result<(), AppError> {
+    let mut backoff = Backoff::default();
+    loop {
+        let started = Instant::now();
+        match provider.run(ctx.clone()).await {
+            Err(e) if e.is_retryable() => {
+                if started.elapsed() > GOOD_RUN {
+                    backoff.reset();
+                }
+                let delay = backoff.next_delay();
+                warn!(provider = ?provider.kind(), error = ?e, retry_in = ?delay, "Weather provider failed, restarting.");
+                tokio::time::sleep(delay).await;
+            }
+            res => return res,
+        }
+    }
+}
+
+/// Applies the signal thresholds of every config reload to the shared generator, and sends the weather signals
+/// again when someone lost them
+pub async fn run_threshold_updates(ctx: ProviderCtx) {
+    let mut sm_rx = ctx.sm_tx.subscribe();
+    loop {
+        match sm_rx.recv().await {
+            Ok(CtrlSignal::ConfigUpdate(cfg)) => {
+                ctx.signals.lock().unwrap().set_thresholds(&cfg.weather_station);
+                info!("Weather signal thresholds updated.");
+            }
+            Ok(CtrlSignal::Resync) => {
+                info!("Resending the weather signals.");
+                ctx.eval_signals(|signals| signals.current());
+            }
+            Ok(_) => {}
+            Err(RecvError::Lagged(missed)) => metrics::dropped("thresholds", missed),
+            Err(RecvError::Closed) => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn providers_from_config() {
+        let cfg = WeatherStation {
+            providers: vec![ProviderKind::Udp, ProviderKind::Tempest, ProviderKind::OpenWeatherMap],
+            api_key_openweathermap: "key".into(),
+            ..Default::default()
+        };
+        // Tempest has no token, so it is skipped
+        let kinds: Vec<_> = build_providers(&cfg, &MQTT::default()).iter().map(|p| p.kind()).collect();
+        assert_eq!(kinds, vec![ProviderKind::Udp, ProviderKind::OpenWeatherMap]);
+    }
+}
@@ -0,0 +1,214 @@
+use super::calculate_et;
+use crate::{db::DatabaseTrait, time::TimeProvider, utils::sod, watering::ds::WeatherConditions};
+use serde::{Deserialize, Serialize};
+use std::{sync::Arc, time::Duration};
+use tracing::{error, info};
+
+pub const HOUR_SECS: i64 = 3600;
+pub const DAY_SECS: i64 = 86_400;
+/// Longest stretch a single observation may stand for. Anything beyond is a gap (station offline).
+pub const MAX_SAMPLE_SPAN_SECS: i64 = 600;
+/// Hours with less data than this don't contribute to the daily means
+pub const MIN_HOUR_COVERAGE: f64 = 0.5;
+pub const ROLLUP_INTERVAL_SECS: u64 = 900;
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct HourlyRollup {
+    /// Unix UTC timestamp of the start of the hour
+    pub timestamp: i64,
+    pub samples: u32,
+    /// fraction of the hour covered by observations, 0..1
+    pub coverage: f64,
+    pub temp_avg: f64,
+    pub temp_min: f64,
+    pub temp_max: f64,
+    pub humidity_avg: f64,
+    /// km/h
+    pub wind_avg: f64,
+    /// km/h, highest gust
+    pub wind_max: f64,
+    /// Wh/m2 over the covered part of the hour
+    pub solar_energy: f64,
+    /// mm
+    pub rain: f64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DailyRollup {
+    /// Unix UTC timestamp of the start of the day
+    pub timestamp: i64,
+    /// hours with enough coverage to be trusted
+    pub hours: u32,
+    pub temp_avg: f64,
+    pub temp_min: f64,
+    pub temp_max: f64,
+    pub humidity_avg: f64,
+    /// km/h
+    pub wind_avg: f64,
+    /// km/h
+    pub wind_max: f64,
+    /// Wh/m2, partially covered hours are extrapolated
+    pub solar_energy: f64,
+    /// mm
+    pub rain: f64,
+    /// mm
+    pub et: f64,
+}
+
+/// Time weighted aggregate of the observations that fall inside `[hour_start, hour_start + 1h)`.<br>
+/// Each observation stands for the time until the next one, capped at `MAX_SAMPLE_SPAN_SECS`.
+pub fn rollup_hour(hour_start: i64, obs: &[WeatherConditions]) -> Option<HourlyRollup> {
+    let hour_end = hour_start + HOUR_SECS;
+    let mut obs: Vec<&WeatherConditions> =
+        obs.iter().filter(|o| o.timestamp >= hour_start && o.timestamp < hour_end).collect();
+    if obs.is_empty() {
+        return None;
+    }
+    obs.sort_by_key(|o| o.timestamp);
+
+    let spans: Vec<f64> = obs
+        .iter()
+        .enumerate()
+        .map(|(i, o)| {
+            let next = obs.get(i + 1).map_or(hour_end, |n| n.timestamp);
+            (next - o.timestamp).min(MAX_SAMPLE_SPAN_SECS) as f64
+        })
+        .collect();
+    let covered: f64 = spans.iter().sum();
+    // all samples on the same second: fall back to a plain mean
+    let weight = |i: usize| if covered > 0. { spans[i] / covered } else { 1. / obs.len() as f64 };
+    let mean = |f: fn(&WeatherConditions) -> f64| obs.iter().enumerate().map(|(i, o)| f(o) * weight(i)).sum::<f64>();
+
+    Some(HourlyRollup {
+        timestamp: hour_start,
+        samples: obs.len() as u32,
+        coverage: covered / HOUR_SECS as f64,
+        temp_avg: mean(|o| o.temperature),
+        temp_min: obs.iter().map(|o| o.temperature).fold(f64::INFINITY, f64::min),
+        temp_max: obs.iter().map(|o| o.temperature).fold(f64::NEG_INFINITY, f64::max),
+        humidity_avg: mean(|o| o.humidity),
+        wind_avg: mean(|o| o.wind_speed),
+        wind_max: obs.iter().map(|o| o.wind_gust.max(o.wind_speed)).fold(0., f64::max),
+        solar_energy: obs.iter().zip(&spans).map(|(o, span)| o.solar_radiation * span).sum::<f64>() / HOUR_SECS as f64,
+        rain: obs.iter().map(|o| o.rain).sum(),
+    })
+}
+
+/// Daily aggregate of the hourly rollups. Means only use hours with `MIN_HOUR_COVERAGE`; rain is summed from every
+/// hour since whatever was measured did fall. Returns `None` when the station was offline the whole day.
+pub fn rollup_day(day_start: i64, hours: &[HourlyRollup]) -> Option<DailyRollup> {
+    let day_hours: Vec<&HourlyRollup> =
+        hours.iter().filter(|h| h.timestamp >= day_start && h.timestamp < day_start + DAY_SECS).collect();
+    let good: Vec<&HourlyRollup> = day_hours.iter().copied().filter(|h| h.coverage >= MIN_HOUR_COVERAGE).collect();
+    if good.is_empty() {
+        return None;
+    }
+    let n = good.len() as f64;
+    let mean = |f: fn(&HourlyRollup) -> f64| good.iter().map(|h| f(h)).sum::<f64>() / n;
+
+    let mut day = DailyRollup {
+        timestamp: day_start,
+        hours: good.len() as u32,
+        temp_avg: mean(|h| h.temp_avg),
+        temp_min: good.iter().map(|h| h.temp_min).fold(f64::INFINITY, f64::min),
+        temp_max: good.iter().map(|h| h.temp_max).fold(f64::NEG_INFINITY, f64::max),
+        humidity_avg: mean(|h| h.humidity_avg),
+        wind_avg: mean(|h| h.wind_avg),
+        wind_max: good.iter().map(|h| h.wind_max).fold(0., f64::max),
+        solar_energy: good.iter().map(|h| h.solar_energy / h.coverage.min(1.)).sum(),
+        rain: day_hours.iter().map(|h| h.rain).sum(),
+        et: 0.,
+    };
+    // Wh/m2 -> MJ/m2 and % -> fraction, as calculate_et expects
+    day.et = calculate_et(day.temp_avg, day.humidity_avg / 100., day.wind_avg, day.solar_energy * 0.0036).max(0.);
+    Some(day)
+}
+
+/// Recomputes the hourly rollups from the start of yesterday up to the last full hour, and yesterday's daily rollup.<br>
+/// Idempotent, so late observations and restarts are picked up on the next run.
+pub fn run_rollup(db: &dyn DatabaseTrait, now: i64) {
+    let from = sod(now) - DAY_SECS;
+    let to = now - now.rem_euclid(HOUR_SECS);
+    let obs = match db.load_observations(from, to) {
+        Ok(obs) => obs,
+        Err(e) => {
+            error!(error = ?e, "Failed to load weather observations.");
+            return;
+        }
+    };
+    let hours: Vec<HourlyRollup> =
+        (from..to).step_by(HOUR_SECS as usize).filter_map(|hour| rollup_hour(hour, &obs)).collect();
+    if let Err(e) = db.store_hourly_rollups(hours.clone()) {
+        error!(error = ?e, "Failed to store hourly weather rollups.");
+    }
+    if let Some(day) = rollup_day(from, &hours) {
+        info!(day = from, hours = day.hours, rain = day.rain, et = day.et, "Daily weather rollup.");
+        if let Err(e) = db.store_daily_rollup(day) {
+            error!(error = ?e, "Failed to store daily weather rollup.");
+        }
+    }
+}
+
+pub async fn run_weather_rollup(db: Arc<dyn DatabaseTrait>, time_provider: Arc<dyn TimeProvider>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(ROLLUP_INTERVAL_SECS));
+    loop {
+        interval.tick().await;
+        run_rollup(db.as_ref(), time_provider.now());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn obs(timestamp: i64, temperature: f64, solar_radiation: f64, rain: f64) -> WeatherConditions {
+        WeatherConditions { timestamp, temperature, solar_radiation, rain, humidity: 50., ..Default::default() }
+    }
+
+    #[test]
+    fn hourly_is_time_weighted() {
+        // 10 degrees for 45 minutes, 30 degrees for the last 15
+        let samples = (0..45).map(|m| obs(m * 60, 10., 100., 0.1)).chain((45..60).map(|m| obs(m * 60, 30., 100., 0.)));
+        let hour = rollup_hour(0, &samples.collect::<Vec<_>>()).unwrap();
+        assert_eq!(hour.samples, 60);
+        assert_eq!(hour.coverage, 1.);
+        assert!((hour.temp_avg - 15.).abs() < 1e-9);
+        assert_eq!((hour.temp_min, hour.temp_max), (10., 30.));
+        assert!((hour.solar_energy - 100.).abs() < 1e-9);
+        assert!((hour.rain - 4.5).abs() < 1e-9);
+        assert!(rollup_hour(HOUR_SECS, &[obs(0, 1., 0., 0.)]).is_none());
+    }
+
+    #[test]
+    fn gaps_reduce_coverage() {
+        // one reading, then the station went offline
+        let hour = rollup_hour(0, &[obs(0, 20., 500., 0.)]).unwrap();
+        assert_eq!(hour.coverage, MAX_SAMPLE_SPAN_SECS as f64 / HOUR_SECS as f64);
+
+        let hours = vec![
+            HourlyRollup {
+                timestamp: 0,
+                coverage: 1.,
+                temp_avg: 10.,
+                solar_energy: 100.,
+                rain: 1.,
+                ..Default::default()
+            },
+            HourlyRollup {
+                timestamp: HOUR_SECS,
+                coverage: 0.5,
+                temp_avg: 20.,
+                solar_energy: 100.,
+                ..Default::default()
+            },
+            // mostly offline: only its rain counts
+            HourlyRollup { timestamp: 2 * HOUR_SECS, coverage: 0.1, temp_avg: 90., rain: 2., ..Default::default() },
+        ];
+        let day = rollup_day(0, &hours).unwrap();
+        assert_eq!(day.hours, 2);
+        assert_eq!(day.temp_avg, 15.);
+        assert_eq!(day.solar_energy, 300.);
+        assert_eq!(day.rain, 3.);
+        assert!(rollup_day(DAY_SECS, &hours).is_none());
+    }
+}
@@ -0,0 +1,198 @@
+use super::provider::ProviderKind;
+use crate::{
+    config::WeatherStation,
+    watering::ds::{WeatherConditions, WeatherSignal},
+};
+use std::collections::HashMap;
+
+/// Whose observation it is, `None` for the made up weather. Each source debounces on its own timestamps, so a
+/// station clock ahead of the others doesn't hold their crossings back.
+pub type Source = Option<ProviderKind>;
+
+/// Two level threshold with a debounce window.<br>
+/// Switches on when the value reaches `on`, off when it drops below `off`, and only after the new state held for
+/// `debounce_secs` (measured with the observation timestamps of each source).
+#[derive(Debug, Clone)]
+struct Detector {
+    on: f64,
+    off: f64,
+    debounce_secs: i64,
+    active: bool,
+    /// when the value first crossed towards the opposite state, per source
+    pending_since: HashMap<Source, i64>,
+}
+
+impl Detector {
+    fn new(threshold: f64, hysteresis: f64, debounce_secs: i64) -> Self {
+        Self {
+            on: threshold,
+            off: threshold - hysteresis.max(0.),
+            debounce_secs: debounce_secs.max(0),
+            active: false,
+            pending_since: HashMap::new(),
+        }
+    }
+
+    /// Returns the new state when it changes
+    fn update(&mut self, value: f64, timestamp: i64, source: Source) -> Option<bool> {
+        let crossing = if self.active { value < self.off } else { value >= self.on };
+        if !crossing {
+            self.pending_since.remove(&source);
+            return None;
+        }
+        let since = *self.pending_since.entry(source).or_insert(timestamp);
+        if timestamp - since < self.debounce_secs {
+            return None;
+        }
+        // whatever the other sources had pending was towards the state we just left
+        self.pending_since.clear();
+        self.active = !self.active;
+        Some(self.active)
+    }
+
+    fn force_on(&mut self) -> bool {
+        self.pending_since.clear();
+        !std::mem::replace(&mut self.active, true)
+    }
+}
+
+/// Turns the raw observation stream into `WeatherSignal`s for the state machine.<br>
+/// Thresholds come from the config: rain in mm/hour, wind in km/h.
+#[derive(Debug, Clone)]
+pub struct SignalGenerator {
+    rain: Detector,
+    wind: Detector,
+}
+
+impl SignalGenerator {
+    pub fn new(cfg: &WeatherStation) -> Self {
+        Self {
+            rain: Detector::new(cfg.rain_threshold, cfg.rain_hysteresis, cfg.rain_debounce_secs),
+            wind: Detector::new(cfg.wind_threshold, cfg.wind_hysteresis, cfg.wind_debounce_secs),
+        }
+    }
+
+    /// New thresholds from a config reload. A signal in progress stays on until the value drops below the new level.
+    pub fn set_thresholds(&mut self, cfg: &WeatherStation) {
+        let (rain, wind) = (self.rain.active, self.wind.active);
+        *self = Self::new(cfg);
+        (self.rain.active, self.wind.active) = (rain, wind);
+    }
+
+    pub fn eval(&mut self, obs: &WeatherConditions, source: Source) -> Vec<WeatherSignal> {
+        let mut signals = self.eval_rain(obs.rain_rate, obs.timestamp, source);
+        signals.extend(self.eval_wind(obs.wind_speed, obs.timestamp, source));
+        signals
+    }
+
+    pub fn eval_rain(&mut self, rain_rate: f64, timestamp: i64, source: Source) -> Vec<WeatherSignal> {
+        match self.rain.update(rain_rate, timestamp, source) {
+            Some(true) => vec![WeatherSignal::RainStart],
+            Some(false) => vec![WeatherSignal::RainStop],
+            None => vec![],
+        }
+    }
+
+    pub fn eval_wind(&mut self, wind_speed: f64, timestamp: i64, source: Source) -> Vec<WeatherSignal> {
+        match self.wind.update(wind_speed, timestamp, source) {
+            Some(true) => vec![WeatherSignal::WindHigh],
+            Some(false) => vec![WeatherSignal::WindLow],
+            None => vec![],
+        }
+    }
+
+    /// Where both detectors stand, for a receiver that may have lost the signals
+    pub fn current(&self) -> Vec<WeatherSignal> {
+        let rain = if self.rain.active { WeatherSignal::RainStart } else { WeatherSignal::RainStop };
+        let wind = if self.wind.active { WeatherSignal::WindHigh } else { WeatherSignal::WindLow };
+        vec![rain, wind]
+    }
+
+    /// The station detected the start of rain. We trust it and skip the debounce.
+    pub fn rain_started(&mut self) -> Vec<WeatherSignal> {
+        if self.rain.force_on() {
+            vec![WeatherSignal::RainStart]
+        } else {
+            vec![]
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn cfg(debounce: i64) -> WeatherStation {
+        WeatherStation {
+            rain_threshold: 1.,
+            rain_hysteresis: 0.5,
+            rain_debounce_secs: debounce,
+            wind_threshold: 20.,
+            wind_hysteresis: 5.,
+            wind_debounce_secs: debounce,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn signals_only_on_change() {
+        let mut signals = SignalGenerator::new(&cfg(0));
+        let wet = WeatherConditions { rain_rate: 2., ..Default::default() };
+        assert_eq!(signals.eval(&wet, None), vec![WeatherSignal::RainStart]);
+        assert!(signals.eval(&wet, None).is_empty());
+        let windy = WeatherConditions { wind_speed: 25., ..Default::default() };
+        assert_eq!(signals.eval(&windy, None), vec![WeatherSignal::RainStop, WeatherSignal::WindHigh]);
+        assert_eq!(signals.current(), vec![WeatherSignal::RainStop, WeatherSignal::WindHigh]);
+    }
+
+    #[test]
+    fn hysteresis_keeps_state_near_threshold() {
+        let mut signals = SignalGenerator::new(&cfg(0));
+        assert_eq!(signals.eval_wind(21., 0, None), vec![WeatherSignal::WindHigh]);
+        // below the threshold but inside the hysteresis band
+        assert!(signals.eval_wind(17., 1, None).is_empty());
+        assert!(signals.eval_wind(20., 2, None).is_empty());
+        assert_eq!(signals.eval_wind(14., 3, None), vec![WeatherSignal::WindLow]);
+    }
+
+    #[test]
+    fn debounce_ignores_short_bursts() {
+        let mut signals = SignalGenerator::new(&cfg(60));
+        assert!(signals.eval_rain(3., 0, None).is_empty());
+        assert!(signals.eval_rain(3., 30, None).is_empty());
+        // burst ended before the window
+        assert!(signals.eval_rain(0., 45, None).is_empty());
+        assert!(signals.eval_rain(3., 100, None).is_empty());
+        assert_eq!(signals.eval_rain(3., 160, None), vec![WeatherSignal::RainStart]);
+
+        // station event skips the debounce
+        let mut signals = SignalGenerator::new(&cfg(60));
+        assert_eq!(signals.rain_started(), vec![WeatherSignal::RainStart]);
+        assert!(signals.rain_started().is_empty());
+        assert!(signals.eval_rain(3., 200, None).is_empty());
+    }
+
+    #[test]
+    fn each_source_debounces_on_its_own_clock() {
+        let (udp, tempest) = (Some(ProviderKind::Udp), Some(ProviderKind::Tempest));
+        let mut signals = SignalGenerator::new(&cfg(60));
+        // the station clock runs well ahead of the REST observations
+        assert!(signals.eval_rain(3., 10_000, udp).is_empty());
+        assert!(signals.eval_rain(3., 0, tempest).is_empty());
+        // a dry reading of one source leaves the window of the other alone
+        assert!(signals.eval_rain(0., 30, tempest).is_empty());
+        assert!(signals.eval_rain(3., 40, tempest).is_empty());
+        assert_eq!(signals.eval_rain(3., 10_060, udp), vec![WeatherSignal::RainStart]);
+        assert!(signals.eval_rain(3., 100, tempest).is_empty(), "already raining");
+    }
+
+    #[test]
+    fn new_thresholds_keep_the_state() {
+        let mut signals = SignalGenerator::new(&cfg(0));
+        assert_eq!(signals.eval_wind(21., 0, None), vec![WeatherSignal::WindHigh]);
+        signals.set_thresholds(&WeatherStation { wind_threshold: 30., ..cfg(0) });
+        assert!(signals.eval_wind(26., 1, None).is_empty(), "still windy, nothing to say");
+        assert_eq!(signals.eval_wind(24., 2, None), vec![WeatherSignal::WindLow]);
+        assert!(signals.eval_wind(29., 3, None).is_empty());
+    }
+}
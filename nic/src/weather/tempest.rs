@@ -0,0 +1,159 @@
+use super::provider::ProviderCtx;
+use crate::{config::WeatherStation, error::AppError, watering::ds::WeatherConditions};
+use serde::Deserialize;
+use std::time::Duration;
+use tracing::{debug, error, info, warn};
+
+/// m/s to km/h
+pub const MS_TO_KMH: f64 = 3.6;
+
+#[derive(Debug, Deserialize)]
+struct StationObservations {
+    #[serde(default)]
+    obs: Vec<TempestObs>,
+}
+
+/// One observation as returned by `/observations/station/{station_id}`.<br>
+/// The API omits (or nulls) fields the station did not report, so everything but the timestamp is optional.
+#[derive(Debug, Default, Deserialize)]
+pub struct TempestObs {
+    pub timestamp: i64,
+    pub air_temperature: Option<f64>,
+    pub relative_humidity: Option<f64>,
+    /// m/s
+    pub wind_avg: Option<f64>,
+    /// m/s
+    pub wind_gust: Option<f64>,
+    pub wind_direction: Option<f64>,
+    pub solar_radiation: Option<f64>,
+    /// mm during the last minute
+    pub precip: Option<f64>,
+    /// mm during the last hour
+    pub precip_accum_last_1hr: Option<f64>,
+}
+
+impl From<TempestObs> for WeatherConditions {
+    fn from(obs: TempestObs) -> Self {
+        let rain_rate = obs.precip_accum_last_1hr.unwrap_or(0.);
+        let rain = obs.precip.unwrap_or(0.);
+        WeatherConditions {
+            timestamp: obs.timestamp,
+            is_raining: rain > 0.,
+            wind_speed: obs.wind_avg.unwrap_or(0.) * MS_TO_KMH,
+            wind_gust: obs.wind_gust.unwrap_or(0.) * MS_TO_KMH,
+            wind_direction: obs.wind_direction.unwrap_or(0.),
+            temperature: obs.air_temperature.unwrap_or(0.),
+            humidity: obs.relative_humidity.unwrap_or(0.),
+            solar_radiation: obs.solar_radiation.unwrap_or(0.),
+            rain,
+            rain_rate,
+        }
+    }
+}
+
+/// Latest observation from the station, normalized. `None` if the station has not reported anything yet.
+pub fn parse_station_observations(body: &str) -> Result<Option<WeatherConditions>, serde_json::Error> {
+    let resp: StationObservations = serde_json::from_str(body)?;
+    Ok(resp.obs.into_iter().max_by_key(|obs| obs.timestamp).map(WeatherConditions::from))
+}
+
+pub async fn fetch_observation(
+    client: &reqwest::Client, cfg: &WeatherStation,
+) -> Result<Option<WeatherConditions>, AppError> {
+    let url = format!("{}/observations/station/{}", cfg.url_tempest, cfg.station_id_tempest);
+    let body = client
+        .get(&url)
+        // the url carries the token, the errors leave it out
+        .query(&[("token", cfg.token_tempest.expose())])
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(reqwest::Error::without_url)?
+        .text()
+        .await
+        .map_err(reqwest::Error::without_url)?;
+    parse_station_observations(&body).map_err(|e| AppError::WeatherError(format!("Invalid Tempest response: {}", e)))
+}
+
+/// Polls the Tempest REST API every `poll_interval_tempest_secs`, stores the observation and feeds the state machine.
+pub async fn poll_tempest(cfg: &WeatherStation, ctx: ProviderCtx) -> Result<(), AppError> {
+    info!(station = cfg.station_id_tempest, "Starting Tempest REST poller.");
+    let client = reqwest::Client::new();
+    let mut interval = tokio::time::interval(Duration::from_secs(cfg.poll_interval_tempest_secs.max(1)));
+    let mut last_ts = 0;
+
+    loop {
+        interval.tick().await;
+        let obs = match fetch_observation(&client, cfg).await {
+            Ok(Some(obs)) => obs,
+            Ok(None) => {
+                warn!("Tempest returned no observations.");
+                continue;
+            }
+            Err(e) => {
+                error!(error = ?e, "Failed to poll Tempest.");
+                continue;
+            }
+        };
+        if obs.timestamp == last_ts {
+            debug!("No new Tempest observation.");
+            continue;
+        }
+        last_ts = obs.timestamp;
+
+        ctx.publish(obs);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SAMPLE: &str = r#"{
+        "station_id": 1234,
+        "obs": [{
+            "timestamp": 1733000000,
+            "air_temperature": 12.5,
+            "relative_humidity": 81,
+            "wind_avg": 2.5,
+            "wind_gust": 5.0,
+            "wind_direction": 270,
+            "solar_radiation": 0,
+            "precip": 0.1,
+            "precip_accum_last_1hr": 1.6
+        }]
+    }"#;
+
+    #[test]
+    fn parse_observation() {
+        let obs = parse_station_observations(SAMPLE).unwrap().unwrap();
+        assert_eq!(obs.timestamp, 1733000000);
+        assert_eq!(obs.temperature, 12.5);
+        assert_eq!(obs.wind_speed, 2.5 * MS_TO_KMH);
+        assert_eq!(obs.rain_rate, 1.6);
+        assert!(obs.is_raining);
+    }
+
+    #[test]
+    fn parse_empty_station() {
+        assert!(parse_station_observations(r#"{"obs": []}"#).unwrap().is_none());
+        assert!(parse_station_observations(r#"{"status": {}}"#).unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn the_errors_leave_the_token_out() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let failing = format!("http://{}", listener.local_addr().unwrap());
+        let app = axum::Router::new().fallback(|| async { axum::http::StatusCode::INTERNAL_SERVER_ERROR });
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let client = reqwest::Client::new();
+        // a server that fails, and one that isn't there
+        for url in [failing, "http://127.0.0.1:1".to_owned()] {
+            let cfg = WeatherStation { url_tempest: url, token_tempest: "s3cr3t-t0ken".into(), ..Default::default() };
+            let e = fetch_observation(&client, &cfg).await.unwrap_err();
+            assert!(!e.to_string().contains("s3cr3t-t0ken"), "{}", e);
+            assert!(!format!("{:?}", e).contains("s3cr3t-t0ken"), "{:?}", e);
+        }
+    }
+}
@@ -0,0 +1,298 @@
+use super::{provider::ProviderCtx, tempest::MS_TO_KMH};
+use crate::{
+    error::AppError,
+    metrics::{self, UDP_PACKETS},
+    watering::ds::{CtrlSignal, RapidWind, WeatherConditions},
+};
+use serde::Deserialize;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tracing::{debug, error, info, trace, warn};
+
+/// Bytes. Tempest messages stay well under 1 KB, anything this big is not from the hub.
+pub const MAX_PACKET: usize = 2048;
+/// Receive errors in a row before the socket is dropped, and bound again by the provider restart
+const MAX_RECV_ERRORS: u32 = 5;
+const RECV_RETRY: Duration = Duration::from_secs(1);
+
+/// Messages broadcast by a Tempest hub on UDP port 50222.<br>
+/// See <https://weatherflow.github.io/Tempest/api/udp/v171/>
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type")]
+pub enum TempestUdpMsg {
+    #[serde(rename = "obs_st")]
+    ObsSt { obs: Vec<Vec<Option<f64>>> },
+    /// [epoch, wind speed m/s, wind direction]
+    #[serde(rename = "rapid_wind")]
+    RapidWind { ob: [f64; 3] },
+    /// [epoch]
+    #[serde(rename = "evt_precip")]
+    EvtPrecip { evt: Vec<i64> },
+    #[serde(rename = "hub_status")]
+    HubStatus {
+        #[serde(default)]
+        timestamp: i64,
+        #[serde(default)]
+        uptime: i64,
+        #[serde(default)]
+        rssi: f64,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum UdpWeatherEvent {
+    Observation(WeatherConditions),
+    /// km/h
+    RapidWind {
+        timestamp: i64,
+        speed: f64,
+        direction: f64,
+    },
+    RainStart {
+        timestamp: i64,
+    },
+    HubStatus {
+        timestamp: i64,
+        uptime: i64,
+        rssi: f64,
+    },
+}
+
+// obs_st field positions
+const OBS_EPOCH: usize = 0;
+const OBS_WIND_AVG: usize = 2;
+const OBS_WIND_GUST: usize = 3;
+const OBS_WIND_DIR: usize = 4;
+const OBS_AIR_TEMP: usize = 7;
+const OBS_RH: usize = 8;
+const OBS_SOLAR_RAD: usize = 11;
+const OBS_RAIN: usize = 12;
+const OBS_REPORT_INTERVAL: usize = 17;
+
+fn obs_st_to_conditions(obs: &[Option<f64>]) -> Option<WeatherConditions> {
+    let field = |i: usize| obs.get(i).copied().flatten().unwrap_or(0.);
+    let timestamp = obs.get(OBS_EPOCH).copied().flatten()? as i64;
+    let rain = field(OBS_RAIN);
+    let report_interval_min = field(OBS_REPORT_INTERVAL).max(1.);
+    Some(WeatherConditions {
+        timestamp,
+        is_raining: rain > 0.,
+        wind_speed: field(OBS_WIND_AVG) * MS_TO_KMH,
+        wind_gust: field(OBS_WIND_GUST) * MS_TO_KMH,
+        wind_direction: field(OBS_WIND_DIR),
+        temperature: field(OBS_AIR_TEMP),
+        humidity: field(OBS_RH),
+        solar_radiation: field(OBS_SOLAR_RAD),
+        rain,
+        rain_rate: rain * 60. / report_interval_min,
+    })
+}
+
+/// Parse one datagram. Unknown message types and malformed obs rows yield no events.
+pub fn parse_udp_packet(packet: &[u8]) -> Result<Vec<UdpWeatherEvent>, serde_json::Error> {
+    let events = match serde_json::from_slice::<TempestUdpMsg>(packet)? {
+        TempestUdpMsg::ObsSt { obs } => {
+            obs.iter().filter_map(|row| obs_st_to_conditions(row)).map(UdpWeatherEvent::Observation).collect()
+        }
+        TempestUdpMsg::RapidWind { ob } => {
+            vec![UdpWeatherEvent::RapidWind { timestamp: ob[0] as i64, speed: ob[1] * MS_TO_KMH, direction: ob[2] }]
+        }
+        TempestUdpMsg::EvtPrecip { evt } => {
+            evt.first().map(|&timestamp| UdpWeatherEvent::RainStart { timestamp }).into_iter().collect()
+        }
+        TempestUdpMsg::HubStatus { timestamp, uptime, rssi } => {
+            vec![UdpWeatherEvent::HubStatus { timestamp, uptime, rssi }]
+        }
+        TempestUdpMsg::Other => vec![],
+    };
+    Ok(events)
+}
+
+/// Why a datagram was dropped
+#[derive(Debug)]
+pub enum PacketError {
+    Empty,
+    /// bigger than `MAX_PACKET`
+    Oversized,
+    Malformed(serde_json::Error),
+}
+
+impl PacketError {
+    /// The `result` label of the packet counter
+    pub fn label(&self) -> &'static str {
+        match self {
+            PacketError::Empty => "empty",
+            PacketError::Oversized => "oversized",
+            PacketError::Malformed(_) => "malformed",
+        }
+    }
+}
+
+/// Size checks, then `parse_udp_packet`
+pub fn check_packet(packet: &[u8]) -> Result<Vec<UdpWeatherEvent>, PacketError> {
+    if packet.is_empty() {
+        return Err(PacketError::Empty);
+    }
+    if packet.len() > MAX_PACKET {
+        return Err(PacketError::Oversized);
+    }
+    parse_udp_packet(packet).map_err(PacketError::Malformed)
+}
+
+fn handle_event(evt: UdpWeatherEvent, ctx: &ProviderCtx) {
+    match evt {
+        UdpWeatherEvent::Observation(obs) => ctx.publish(obs),
+        UdpWeatherEvent::RapidWind { timestamp, speed, direction } => {
+            trace!(speed, "Rapid wind.");
+            _ = ctx.web_tx.send(CtrlSignal::RapidWind(RapidWind { timestamp, speed, direction }));
+            ctx.eval_signals(|signals| signals.eval_wind(speed, timestamp, ctx.source));
+        }
+        UdpWeatherEvent::RainStart { timestamp } => {
+            if let Err(e) = ctx.db.log_weather_event(timestamp, "evt_precip".to_owned(), "{}".to_owned()) {
+                error!(error = ?e, "Failed to store weather event.");
+            }
+            ctx.eval_signals(|signals| signals.rain_started());
+        }
+        UdpWeatherEvent::HubStatus { timestamp, uptime, rssi } => {
+            debug!(uptime, rssi, "Tempest hub status.");
+            let data = serde_json::json!({ "uptime": uptime, "rssi": rssi }).to_string();
+            if let Err(e) = ctx.db.log_weather_event(timestamp, "hub_status".to_owned(), data) {
+                error!(error = ?e, "Failed to store weather event.");
+            }
+        }
+    }
+}
+
+/// Listens on `address` for the hub broadcasts. Every datagram is counted by what became of it.<br>
+/// A failing socket is tried again a few times before the error goes up, for the provider to bind it anew.
+pub async fn monitor_udp(address: &str, ctx: ProviderCtx) -> Result<(), AppError> {
+    let socket = UdpSocket::bind(address)
+        .await
+        .map_err(|e| AppError::WeatherError(format!("Can't bind UDP {}: {}", address, e)))?;
+    info!(address, "Listening for UDP weather broadcasts.");
+    // one byte over, to tell a datagram that fits from one cut short
+    let mut buf = vec![0; MAX_PACKET + 1];
+    let mut errors = 0;
+
+    loop {
+        let len = match socket.recv_from(&mut buf).await {
+            Ok((len, _addr)) => len,
+            Err(e) => {
+                metrics::registry().add(UDP_PACKETS, ("result", "recv_error"), 1);
+                errors += 1;
+                if errors >= MAX_RECV_ERRORS {
+                    return Err(AppError::WeatherError(format!("UDP {}: {}", address, e)));
+                }
+                warn!(error = ?e, errors, "UDP receive failed, retrying.");
+                tokio::time::sleep(RECV_RETRY).await;
+                continue;
+            }
+        };
+        errors = 0;
+        match check_packet(&buf[..len]) {
+            Ok(events) => {
+                metrics::registry().add(UDP_PACKETS, ("result", "parsed"), 1);
+                events.into_iter().for_each(|evt| handle_event(evt, &ctx));
+            }
+            Err(e) => {
+                metrics::registry().add(UDP_PACKETS, ("result", e.label()), 1);
+                debug!(error = ?e, len, "Ignoring UDP packet.");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        config::WeatherStation,
+        links::Links,
+        test::utils::{mock_db::MockDatabase, mock_time::MockTimeProvider},
+        utils::{init_broadcast_channels, init_channels},
+        weather::freshness::WeatherFreshness,
+    };
+    use std::sync::Arc;
+
+    #[test]
+    fn parse_obs_st() {
+        let packet = br#"{"serial_number": "ST-00000512", "type": "obs_st", "hub_sn": "HB-00013030",
+            "obs": [[1588948614, 0.18, 0.22, 0.27, 144, 6, 1017.57, 22.37, 50.26, 328, 0.03, 3, 0.5, 1, 0, 0, 2.410, 1]],
+            "firmware_revision": 129}"#;
+        let events = parse_udp_packet(packet).unwrap();
+        let [UdpWeatherEvent::Observation(obs)] = events.as_slice() else { panic!("expected one observation") };
+        assert_eq!(obs.timestamp, 1588948614);
+        assert_eq!(obs.temperature, 22.37);
+        assert_eq!(obs.rain, 0.5);
+        assert_eq!(obs.rain_rate, 30.); // 0.5 mm in a 1 minute report interval
+        assert!(obs.is_raining);
+    }
+
+    #[test]
+    fn parse_other_messages() {
+        let wind = parse_udp_packet(
+            br#"{"serial_number": "SK-00008453", "type": "rapid_wind", "ob": [1493322445, 2.3, 128]}"#,
+        );
+        assert_eq!(
+            wind.unwrap(),
+            vec![UdpWeatherEvent::RapidWind { timestamp: 1493322445, speed: 2.3 * MS_TO_KMH, direction: 128. }]
+        );
+
+        let rain = parse_udp_packet(br#"{"serial_number": "SK-00008453", "type": "evt_precip", "evt": [1493322445]}"#);
+        assert_eq!(rain.unwrap(), vec![UdpWeatherEvent::RainStart { timestamp: 1493322445 }]);
+
+        let hub = parse_udp_packet(
+            br#"{"serial_number": "HB-00000001", "type": "hub_status", "firmware_revision": "35", "uptime": 1670133,
+                 "rssi": -62, "timestamp": 1495724691, "reset_flags": "BOR,PIN,POR"}"#,
+        );
+        assert_eq!(
+            hub.unwrap(),
+            vec![UdpWeatherEvent::HubStatus { timestamp: 1495724691, uptime: 1670133, rssi: -62. }]
+        );
+
+        assert!(parse_udp_packet(br#"{"type": "device_status", "uptime": 1}"#).unwrap().is_empty());
+        assert!(parse_udp_packet(b"not json").is_err());
+    }
+
+    #[test]
+    fn checks_the_size() {
+        assert!(matches!(check_packet(b""), Err(PacketError::Empty)));
+        let big = format!(r#"{{"type": "device_status", "pad": "{}"}}"#, "x".repeat(MAX_PACKET));
+        assert_eq!(check_packet(big.as_bytes()).unwrap_err().label(), "oversized");
+        assert_eq!(check_packet(b"{").unwrap_err().label(), "malformed");
+        assert!(check_packet(br#"{"type": "device_status"}"#).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn counts_the_packets() {
+        let db = Arc::new(MockDatabase::new());
+        let (sm_tx, _) = init_channels();
+        let (web_tx, _) = init_broadcast_channels();
+        let time_provider = Arc::new(MockTimeProvider::new(0));
+        let ctx = ProviderCtx::new(
+            &WeatherStation::default(),
+            db,
+            sm_tx,
+            web_tx,
+            Arc::new(WeatherFreshness::disabled()),
+            Arc::new(Links::new(time_provider)),
+        );
+        let address = "127.0.0.1:50291";
+        tokio::spawn(async move { monitor_udp(address, ctx).await });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let count = |result| metrics::registry().counter(UDP_PACKETS, ("result", result));
+        let (parsed, malformed, oversized) = (count("parsed"), count("malformed"), count("oversized"));
+        let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        sender.send_to(br#"{"type": "evt_precip", "evt": [1493322445]}"#, address).await.unwrap();
+        sender.send_to(b"not json", address).await.unwrap();
+        sender.send_to(&[b' '; MAX_PACKET + 10], address).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        assert_eq!(count("parsed"), parsed + 1);
+        assert_eq!(count("malformed"), malformed + 1);
+        assert_eq!(count("oversized"), oversized + 1);
+    }
+}
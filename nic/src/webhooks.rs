@@ -0,0 +1,289 @@
+use crate::{
+    config::WebhookCfg,
+    error::{AppError, Backoff},
+    metrics,
+    time::TimeProvider,
+    watering::ds::CtrlSignal,
+};
+use hmac::{Hmac, Mac};
+use reqwest::header::CONTENT_TYPE;
+use serde::Serialize;
+use sha2::Sha256;
+use std::{fmt::Write, sync::Arc, time::Duration};
+use tokio::sync::broadcast::{error::RecvError, Sender};
+use tracing::{debug, error, info, warn};
+
+pub const SIGNATURE_HEADER: &str = "x-nic-signature";
+pub const EVENT_HEADER: &str = "x-nic-event";
+
+/// Every kind a webhook can ask for: the state machine transitions, a sector that kept failing its valve
+/// commands, a valve that kept watering after it was closed, a damaged database and a jump of the clock
+pub const WEBHOOK_EVENTS: [&str; 17] = [
+    "cycle_started",
+    "cycle_completed",
+    "cycle_aborted",
+    "sector_activated",
+    "sector_deactivated",
+    "paused",
+    "resumed",
+    "pause_abandoned",
+    "mode_changed",
+    "plan_recalculated",
+    "emergency_stop",
+    "emergency_stop_cleared",
+    "daily_cap_reached",
+    "sector_fault",
+    "stuck_valve",
+    "db_integrity",
+    "clock_jump",
+];
+
+/// A watering event as the webhooks get it: a JSON object with its `kind` and `timestamp`
+#[derive(Debug, Clone, PartialEq)]
+pub struct HookEvent {
+    pub kind: &'static str,
+    pub body: String,
+}
+
+/// The event a signal is for the webhooks, if any
+pub fn hook_event(signal: &CtrlSignal, now: i64) -> Option<HookEvent> {
+    let (kind, body) = match signal {
+        CtrlSignal::StateChanged(evt) => (evt.change.kind(), serde_json::to_string(evt).ok()?),
+        // the fault has no time of its own
+        CtrlSignal::SectorFault(fault) => ("sector_fault", tagged("sector_fault", now, fault)?),
+        CtrlSignal::StuckValve(alarm) => ("stuck_valve", tagged("stuck_valve", alarm.timestamp, alarm)?),
+        CtrlSignal::DbIntegrity(check) => ("db_integrity", tagged("db_integrity", check.timestamp, check)?),
+        CtrlSignal::ClockJump(jump) => ("clock_jump", tagged("clock_jump", jump.timestamp, jump)?),
+        _ => return None,
+    };
+    Some(HookEvent { kind, body })
+}
+
+fn tagged<T: Serialize>(kind: &str, timestamp: i64, value: &T) -> Option<String> {
+    let mut value = serde_json::to_value(value).ok()?;
+    let object = value.as_object_mut()?;
+    object.insert("kind".to_owned(), kind.into());
+    object.insert("timestamp".to_owned(), timestamp.into());
+    Some(value.to_string())
+}
+
+pub fn wants(hook: &WebhookCfg, kind: &str) -> bool {
+    hook.events.is_empty() || hook.events.iter().any(|event| event == kind)
+}
+
+/// HMAC-SHA256 of the body, in hex
+pub fn sign(secret: &str, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(body.as_bytes());
+    mac.finalize().into_bytes().iter().fold(String::new(), |mut hex, byte| {
+        _ = write!(hex, "{:02x}", byte);
+        hex
+    })
+}
+
+/// What the logs show of a hook, its path and query can hold a secret
+fn host(url: &str) -> String {
+    reqwest::Url::parse(url).ok().and_then(|url| url.host_str().map(str::to_owned)).unwrap_or_default()
+}
+
+/// POSTs the event, trying again after transient failures until the hook's `attempts` run out
+pub async fn deliver(
+    client: &reqwest::Client, hook: &WebhookCfg, evt: &HookEvent, mut backoff: Backoff,
+) -> Result<(), AppError> {
+    let mut attempt = 1;
+    loop {
+        let mut request = client
+            .post(&hook.url)
+            .header(CONTENT_TYPE, "application/json")
+            .header(EVENT_HEADER, evt.kind)
+            .body(evt.body.clone());
+        if !hook.secret.is_empty() {
+            request = request.header(SIGNATURE_HEADER, format!("sha256={}", sign(hook.secret.expose(), &evt.body)));
+        }
+        // the url can carry the hook's secret, e.g. a Home Assistant webhook id, the errors leave it out
+        let sent = request.send().await.and_then(reqwest::Response::error_for_status);
+        match sent.map_err(|e| AppError::from(e.without_url())) {
+            Ok(_) => return Ok(()),
+            Err(e) if e.is_retryable() && attempt < hook.attempts => {
+                let delay = backoff.next_delay();
+                let host = host(&hook.url);
+                warn!(host, kind = evt.kind, attempt, error = ?e, retry_in = ?delay, "Webhook failed.");
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Sends the watering events to the configured webhooks, each delivery on its own task so a slow hook holds
+/// nothing up. A config reload replaces the hooks.
+pub async fn run_webhooks(
+    hooks: Vec<WebhookCfg>, sm_tx: Arc<Sender<CtrlSignal>>, web_tx: Sender<CtrlSignal>,
+    time_provider: Arc<dyn TimeProvider>,
+) {
+    let client = match reqwest::Client::builder().timeout(Duration::from_secs(10)).build() {
+        Ok(client) => client,
+        Err(e) => {
+            error!(error = ?e, "Failed to build the webhook client.");
+            return;
+        }
+    };
+    let mut hooks: Arc<[WebhookCfg]> = hooks.into();
+    info!(hooks = hooks.len(), "Webhooks ready.");
+    let (mut sm_rx, mut web_rx) = (sm_tx.subscribe(), web_tx.subscribe());
+    loop {
+        let signal = tokio::select! {
+            signal = sm_rx.recv() => signal,
+            signal = web_rx.recv() => signal,
+        };
+        let signal = match signal {
+            Ok(signal) => signal,
+            Err(RecvError::Lagged(missed)) => {
+                metrics::dropped("webhooks", missed);
+                warn!(missed, "Webhooks fell behind, events skipped.");
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+        // the deliveries on their way keep the hooks they started with
+        if let CtrlSignal::ConfigUpdate(cfg) = &signal {
+            if *cfg.webhooks != *hooks {
+                hooks = cfg.webhooks.clone().into();
+                info!(hooks = hooks.len(), "Webhooks updated.");
+            }
+            continue;
+        }
+        let Some(evt) = hook_event(&signal, time_provider.now()) else {
+            continue;
+        };
+        for n in (0..hooks.len()).filter(|&n| wants(&hooks[n], evt.kind)) {
+            let (client, hooks, evt) = (client.clone(), hooks.clone(), evt.clone());
+            tokio::spawn(async move {
+                let hook = &hooks[n];
+                match deliver(&client, hook, &evt, Backoff::new(Duration::from_secs(1), Duration::from_secs(30))).await
+                {
+                    Ok(()) => debug!(host = host(&hook.url), kind = evt.kind, "Webhook sent."),
+                    Err(e) => error!(host = host(&hook.url), kind = evt.kind, error = ?e, "Webhook given up."),
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        test::utils::{mock_cfg::mock_cfg, mock_time::MockTimeProvider},
+        watering::ds::{SectorFault, StateChange, StateEvent, ValveAction},
+    };
+    use axum::{extract::State, http::HeaderMap, http::StatusCode, routing::post, Router};
+    use std::sync::Mutex;
+    use tokio::sync::{broadcast, mpsc};
+
+    #[test]
+    fn signs_like_rfc_4231() {
+        assert_eq!(
+            sign("Jefe", "what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn events_and_filters() {
+        let evt = StateEvent { timestamp: 10, sector: None, cycle: Some(3), change: StateChange::CycleCompleted };
+        let hook_evt = hook_event(&CtrlSignal::StateChanged(evt), 99).unwrap();
+        assert_eq!(hook_evt.kind, "cycle_completed");
+        assert!(hook_evt.body.contains(r#""timestamp":10"#));
+
+        let fault = SectorFault { sector: 2, action: ValveAction::Open, error: "timeout".to_owned() };
+        let hook_evt = hook_event(&CtrlSignal::SectorFault(fault), 99).unwrap();
+        let body: serde_json::Value = serde_json::from_str(&hook_evt.body).unwrap();
+        assert_eq!(
+            (body["kind"].as_str(), body["timestamp"].as_i64(), body["sector"].as_u64()),
+            (Some("sector_fault"), Some(99), Some(2))
+        );
+        assert_eq!(hook_event(&CtrlSignal::StopMachine, 99), None);
+
+        let hook = WebhookCfg { events: vec!["sector_fault".to_owned()], ..Default::default() };
+        assert!(wants(&hook, "sector_fault"));
+        assert!(!wants(&hook, "cycle_started"));
+        assert!(wants(&WebhookCfg::default(), "cycle_started"));
+    }
+
+    #[tokio::test]
+    async fn retries_and_signs() {
+        // fails the first call, then records the signature
+        let seen = Arc::new(Mutex::new(vec![]));
+        let app = Router::new()
+            .route(
+                "/hook",
+                post(|State(seen): State<Arc<Mutex<Vec<String>>>>, headers: HeaderMap| async move {
+                    let mut seen = seen.lock().unwrap();
+                    let signature = headers.get(SIGNATURE_HEADER).and_then(|value| value.to_str().ok());
+                    seen.push(signature.unwrap_or_default().to_owned());
+                    if seen.len() == 1 {
+                        StatusCode::SERVICE_UNAVAILABLE
+                    } else {
+                        StatusCode::OK
+                    }
+                }),
+            )
+            .route("/gone", post(|| async { StatusCode::NOT_FOUND }))
+            .with_state(seen.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let client = reqwest::Client::new();
+        let evt = HookEvent { kind: "cycle_started", body: r#"{"kind":"cycle_started"}"#.to_owned() };
+        let backoff = || Backoff::new(Duration::from_millis(10), Duration::from_millis(10));
+        let hook = WebhookCfg { url: format!("http://{}/hook", addr), secret: "key".into(), ..Default::default() };
+        deliver(&client, &hook, &evt, backoff()).await.unwrap();
+        let expected = format!("sha256={}", sign("key", &evt.body));
+        assert_eq!(*seen.lock().unwrap(), [expected.clone(), expected]);
+
+        // a 4xx is not retried
+        let gone = WebhookCfg { url: format!("http://{}/gone", addr), ..Default::default() };
+        let e = deliver(&client, &gone, &evt, backoff()).await.unwrap_err();
+        assert!(!e.is_retryable());
+        assert!(!format!("{:?} {}", e, e).contains("/gone"), "{:?}", e);
+        assert_eq!(host(&gone.url), "127.0.0.1");
+    }
+
+    #[tokio::test]
+    async fn takes_the_hooks_of_a_reload() {
+        let (seen_tx, mut seen_rx) = mpsc::unbounded_channel();
+        let app = Router::new()
+            .route(
+                "/hook",
+                post(|State(seen_tx): State<mpsc::UnboundedSender<String>>, headers: HeaderMap| async move {
+                    let kind = headers.get(EVENT_HEADER).and_then(|value| value.to_str().ok());
+                    _ = seen_tx.send(kind.unwrap_or_default().to_owned());
+                    StatusCode::OK
+                }),
+            )
+            .with_state(seen_tx);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let (sm_tx, web_tx) = (Arc::new(broadcast::channel(16).0), broadcast::channel(16).0);
+        let time_provider = Arc::new(MockTimeProvider::new(100));
+        let task = tokio::spawn(run_webhooks(vec![], sm_tx.clone(), web_tx.clone(), time_provider));
+        while sm_tx.receiver_count() == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        // started with none, the reload adds one. On the same channel as the event, to come before it.
+        let mut cfg = mock_cfg();
+        cfg.webhooks.push(WebhookCfg { url: format!("http://{}/hook", addr), ..Default::default() });
+        sm_tx.send(CtrlSignal::ConfigUpdate(Arc::new(cfg))).unwrap();
+        let evt = StateEvent { timestamp: 10, sector: None, cycle: Some(3), change: StateChange::CycleCompleted };
+        sm_tx.send(CtrlSignal::StateChanged(evt)).unwrap();
+        let kind = tokio::time::timeout(Duration::from_secs(5), seen_rx.recv()).await.unwrap();
+        assert_eq!(kind.as_deref(), Some("cycle_completed"));
+        task.abort();
+    }
+}
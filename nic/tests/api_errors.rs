@@ -0,0 +1,28 @@
+use axum::{body::to_bytes, extract::State, response::IntoResponse};
+use chrono::{TimeZone, Utc};
+use hyper::StatusCode;
+use nic::{
+    api::{get_cycle, get_state},
+    error::{ApiError, ErrorBody},
+    test::utils::{mock_cfg::mock_cfg, set_app_and_ws0},
+    watering::modes::Mode,
+};
+
+#[tokio::test]
+async fn a_watering_loop_that_doesnt_answer_is_a_timeout() {
+    let now = Utc.with_ymd_and_hms(2024, 7, 2, 12, 0, 0).unwrap().timestamp();
+    let cfg = mock_cfg();
+    // the watering system isn't running, nothing answers the queries
+    let (app_state, _ws) = set_app_and_ws0(now, Some(Mode::Auto), cfg.watering).unwrap();
+
+    let e = get_state(State(app_state.clone())).await.unwrap_err();
+    assert_eq!(e, ApiError::Timeout("the watering loop didn't answer the state query".to_owned()));
+    let e = get_cycle(State(app_state.clone())).await.unwrap_err();
+    assert_eq!(e.status(), StatusCode::GATEWAY_TIMEOUT);
+
+    let response = e.into_response();
+    assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+    let body = to_bytes(response.into_body(), 1024).await.unwrap();
+    let body: ErrorBody = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body, ErrorBody { status: 504, error: "the watering loop didn't answer the cycle query".to_owned() });
+}
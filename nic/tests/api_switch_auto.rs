@@ -1,17 +1,22 @@
 use chrono::{TimeZone, Utc};
 use hyper::StatusCode;
 use nic::api::run_web_server;
+use nic::config::LogCfg;
+use nic::error::ErrorBody;
 use nic::test::utils::mock_cfg::mock_cfg;
-use nic::test::utils::mock_db::mock_sector;
+use nic::test::utils::mock_db::{mock_sector, new_with_mock, MockDatabase};
+use nic::test::utils::mock_sensors::set_sensor_controller0;
+use nic::test::utils::mock_time::MockTimeProvider;
 use nic::test::utils::set_app_and_ws0;
 use nic::utils::{load_sectors_into_hashmap, start_log};
-use nic::watering::ds::{DailyPlan, WaterSector};
+use nic::watering::ds::{AuditEntry, DailyPlan, SystemEvent, WaterSector};
 use nic::watering::modes::*;
-use nic::watering::watering_system::run_watering_system;
+use nic::watering::watering_system::{run_watering_system, WateringSystem};
 use nic::{
-    api::{CycleResponse, WateringStateResponse},
+    api::{request_cycle, request_state, CycleResponse, WateringStateResponse},
     watering::ds::CtrlSignal,
 };
+use std::sync::Arc;
 use tracing::error;
 
 fn mock_schedule(current_time: i64) -> Vec<DailyPlan> {
@@ -36,34 +41,22 @@ async fn watering_system_response_to_routes_function_calls() {
     });
 
     app_state.sm_tx.send(CtrlSignal::ChgMode(Mode::Manual)).unwrap();
-    app_state.sm_tx.send(CtrlSignal::GetState).unwrap();
-    if let Ok(CtrlSignal::GetStateResponse(resp)) = app_state.sm_rx.lock().await.try_recv() {
-        assert_eq!(resp.mode.as_ref().unwrap(), "manual");
-        assert!(resp.mode.is_some());
-        assert!(resp.state.is_some());
-    }
+    let resp = request_state(&app_state).await.unwrap();
+    assert_eq!(resp.mode.as_ref().unwrap(), "manual");
+    assert!(resp.state.is_some());
 
     app_state.sm_tx.send(CtrlSignal::ChgMode(Mode::Auto)).unwrap();
-    app_state.sm_tx.send(CtrlSignal::GetState).unwrap();
-    if let Ok(CtrlSignal::GetStateResponse(resp)) = app_state.sm_rx.lock().await.try_recv() {
-        assert_eq!(resp.mode.as_ref().unwrap(), "auto");
-        assert!(resp.mode.is_some());
-        assert!(resp.state.is_some());
-    }
+    let resp = request_state(&app_state).await.unwrap();
+    assert_eq!(resp.mode.as_ref().unwrap(), "auto");
+    assert!(resp.state.is_some());
 
-    app_state.sm_tx.send(CtrlSignal::GetCycle).unwrap();
-    if let Ok(CtrlSignal::GetCycleResponse(resp)) = app_state.sm_rx.lock().await.try_recv() {
-        assert!(resp.error.is_none());
-        println!("{:?}", resp);
-    }
+    let resp = request_cycle(&app_state).await.unwrap();
+    assert!(resp.error.is_none());
 
     app_state.sm_tx.send(CtrlSignal::StopMachine).unwrap();
-    app_state.sm_tx.send(CtrlSignal::GetState).unwrap();
-    if let Ok(CtrlSignal::GetStateResponse(resp)) = app_state.sm_rx.lock().await.try_recv() {
-        assert_eq!(resp.mode.as_ref().unwrap(), "manual");
-        assert!(resp.mode.is_some());
-        assert!(resp.state.is_some());
-    }
+    let resp = request_state(&app_state).await.unwrap();
+    assert!(resp.mode.is_some());
+    assert!(resp.state.is_some());
 
     // Clean up
     _ = shutdown_tx.send(true);
@@ -74,13 +67,16 @@ async fn watering_system_response_to_routes_function_calls() {
 async fn test_full_web_server() {
     let current_time = Utc.with_ymd_and_hms(2023, 11, 25, 22, 0, 0).unwrap().timestamp();
     let cfg = mock_cfg();
-    let (app_state, mut ws) = set_app_and_ws0(current_time, Some(Mode::Auto), cfg.watering).unwrap();
+    // the clock stands still, so the plan 5 minutes ahead hasn't started when we ask
+    let time_provider = Arc::new(MockTimeProvider::held(current_time));
+    let app_state = new_with_mock(Arc::new(MockDatabase::new()), set_sensor_controller0(), time_provider).unwrap();
+    let mut ws = WateringSystem::new(app_state.clone(), Some(Mode::Auto), current_time, cfg.watering).unwrap();
     let app_state_clone = app_state.clone();
     ws.sm.sectors = load_sectors_into_hashmap(mock_sector());
     ws.sm.mode_auto = ModeAuto { daily_plan: mock_schedule(current_time) };
 
     let time_provider = ws.time_provider.clone();
-    start_log(Some(time_provider.clone()));
+    start_log(&LogCfg::default(), Some(time_provider.clone())).unwrap();
 
     let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
     let rx_clone = shutdown_rx.clone();
@@ -104,30 +100,138 @@ async fn test_full_web_server() {
     let client = reqwest::Client::new();
 
     // Test `/switch/auto` route
-    let response = client.post(format!("http://{}/switch/auto", str_ip_addr)).send().await.unwrap();
+    let response =
+        client.post(format!("http://{}/switch/auto", str_ip_addr)).header("X-User", "ana").send().await.unwrap();
     assert_eq!(response.status(), StatusCode::OK);
+    let response = client.post(format!("http://{}/switch/sideways", str_ip_addr)).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let error: ErrorBody = response.json().await.unwrap();
+    assert_eq!(error, ErrorBody { status: 400, error: "'sideways' is not a mode".to_owned() });
 
-    // Test `/state` route
+    // Test `/state` route, under the version and the path of before it that says it's deprecated
+    let response = client.get(format!("http://{}/api/v1/state", str_ip_addr)).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get("deprecation").is_none());
     let response = client.get(format!("http://{}/state", str_ip_addr)).send().await.unwrap();
     assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["deprecation"], "true");
     let state_response: WateringStateResponse = response.json().await.unwrap();
     assert!(state_response.mode.is_some());
     assert!(state_response.state.is_some());
 
     // Test `/cycle` route
-    let response = client.get(format!("http://{}/cycle", str_ip_addr)).send().await.unwrap();
+    let response = client.get(format!("http://{}/api/v1/cycle", str_ip_addr)).send().await.unwrap();
     assert_eq!(response.status(), StatusCode::OK);
     let cycle_response: CycleResponse = response.json().await.unwrap();
     assert!(cycle_response.error.is_none());
     assert!(cycle_response.id.is_none());
     assert!(cycle_response.instructions.is_none());
 
+    // Test `/events/system` route
+    let response = client.get(format!("http://{}/events/system?from=0", str_ip_addr)).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let events: Vec<SystemEvent> = response.json().await.unwrap();
+    assert!(events.iter().all(|evt| evt.timestamp >= 0));
+
+    // Test `/interlock` route
+    let response = client.get(format!("http://{}/interlock", str_ip_addr)).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let status: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(status["max_open_sectors"], 1);
+    assert_eq!(status["open"], serde_json::json!([]));
+
+    // Test `/status` route
+    app_state.links.set("valves", true);
+    let response = client.get(format!("http://{}/status", str_ip_addr)).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let status: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(status["machine"]["mode"], "auto");
+    assert_eq!(status["machine"]["state"], "idle");
+    assert_eq!(status["links"]["valves"]["connected"], true);
+    assert_eq!(status["interlock"]["max_open_sectors"], 1);
+    assert!(status["freshness"].is_object());
+    assert!(status["restriction"].is_null());
+
+    // Test `/healthz` route, a client off its broker degrades it
+    let response = client.get(format!("http://{}/healthz", str_ip_addr)).send().await.unwrap();
+    let health: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(health["status"], "ok");
+    app_state.links.set("weather", false);
+    let response = client.get(format!("http://{}/healthz", str_ip_addr)).send().await.unwrap();
+    let health: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(health["status"], "degraded");
+    assert_eq!(health["links"]["weather"]["connected"], false);
+
+    // Test `/metrics` route
+    let response = client.get(format!("http://{}/metrics", str_ip_addr)).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let text = response.text().await.unwrap();
+    assert!(text.contains("nic_signal_roundtrip_seconds_count{request=\"state\"}"));
+
+    // Test `/estop` routes
+    let response = client.post(format!("http://{}/api/v1/estop", str_ip_addr)).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client.post(format!("http://{}/estop/clear", str_ip_addr)).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
     // Test `/command` route
     let response = client.get(format!("http://{}/command?command=stop", str_ip_addr)).send().await.unwrap();
     assert_eq!(response.status(), StatusCode::OK);
 
+    // Test `/audit` route, only the calls that could change something are there
+    let response = client.get(format!("http://{}/audit?from=0", str_ip_addr)).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let audit: Vec<AuditEntry> = response.json().await.unwrap();
+    let actions: Vec<_> = audit.iter().map(|entry| (entry.action.as_str(), entry.status)).collect();
+    assert_eq!(
+        actions,
+        [
+            ("POST /switch/auto", 200),
+            ("POST /switch/sideways", 400),
+            ("POST /api/v1/estop", 200),
+            ("POST /estop/clear", 200),
+            ("GET /command?command=stop", 200)
+        ]
+    );
+    assert_eq!((audit[0].user.as_deref(), audit[0].ip.as_deref()), (Some("ana"), Some("127.0.0.1")));
+    assert_eq!(audit[2].user, None);
+
     // Clean up
     _ = shutdown_tx.send(true);
     server_task.abort();
     watering_system_task.abort();
 }
+
+#[tokio::test]
+async fn concurrent_queries_get_their_own_answers() {
+    let current_time = Utc.with_ymd_and_hms(2023, 11, 25, 22, 0, 0).unwrap().timestamp();
+    let cfg = mock_cfg();
+    let (app_state, mut ws) = set_app_and_ws0(current_time, Some(Mode::Manual), cfg.watering).unwrap();
+    ws.sm.sectors = load_sectors_into_hashmap(mock_sector());
+    let app_state_clone = app_state.clone();
+    let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+    let watering_system_task = tokio::spawn(async move {
+        let _ =
+            run_watering_system(app_state_clone, Some(Mode::Manual), shutdown_rx, None, Some(&mut ws), cfg.watering)
+                .await;
+    });
+
+    // state and cycle queries interleaved, each must get its own kind of answer
+    let queries = (0..20).map(|n| {
+        let app_state = app_state.clone();
+        tokio::spawn(async move {
+            if n % 2 == 0 {
+                request_state(&app_state).await.unwrap().mode.is_some()
+            } else {
+                let resp = request_cycle(&app_state).await.unwrap();
+                resp.error.is_none() && resp.id.is_none()
+            }
+        })
+    });
+    for query in queries.collect::<Vec<_>>() {
+        assert!(query.await.unwrap());
+    }
+
+    _ = shutdown_tx.send(true);
+    watering_system_task.abort();
+}
@@ -0,0 +1,43 @@
+use nic::{
+    test::utils::{mock_cfg::mock_cfg, set_app_and_ws0},
+    utils::sod,
+    watering::{
+        ds::{CtrlSignal, DailyPlan, WaterSector, WeatherSignal},
+        modes::Mode,
+    },
+};
+
+#[tokio::test]
+async fn transitions_are_audited() {
+    let ref_time = sod(chrono::Utc::now().timestamp());
+    let cfg = mock_cfg();
+    let (_app, mut ws) = set_app_and_ws0(ref_time, Some(Mode::Wizard), cfg.watering).unwrap();
+
+    let start = ws.sm.timeframe.day_start_time;
+    ws.sm.mode_wizard.daily_plan = vec![DailyPlan(vec![WaterSector::new(1, start, 600)])];
+    ws.sm.update(start).await;
+    ws.sm.handle_signal(CtrlSignal::Weather(WeatherSignal::RainStart), start + 100).await;
+    ws.sm.handle_signal(CtrlSignal::Weather(WeatherSignal::RainStop), start + 200).await;
+    ws.sm.update(start + 700).await;
+    ws.sm.handle_signal(CtrlSignal::ChgMode(Mode::Manual), start + 800).await;
+
+    let events = ws.sm.db.load_system_events(start, start + 1000).unwrap();
+    let kinds: Vec<&str> = events.iter().map(|evt| evt.kind.as_str()).collect();
+    assert_eq!(
+        kinds,
+        vec![
+            "cycle_started",
+            "sector_activated",
+            "sector_deactivated",
+            "paused",
+            "resumed",
+            "sector_activated",
+            "sector_deactivated",
+            "cycle_completed",
+            "mode_changed",
+        ]
+    );
+    assert!(events[..8].iter().all(|evt| evt.cycle == Some(start)));
+    assert_eq!(events[3].detail, "rain_start after 100s");
+    assert_eq!(events[4].detail, "500s left, 500s fit in the window");
+}
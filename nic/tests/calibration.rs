@@ -0,0 +1,44 @@
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use chrono::{TimeZone, Utc};
+use hyper::StatusCode;
+use nic::{
+    api::{finish_calibration, start_calibration, CalibrationRequest},
+    test::utils::{mock_cfg::mock_cfg, set_app_and_ws0},
+    watering::{calibration::CalibrationMeasure, modes::Mode, state_machine::SMState},
+};
+
+#[tokio::test]
+async fn a_catch_cup_run_sets_the_debit() {
+    // Monday morning, before the 06:00 of the mock schedule
+    let now = Utc.with_ymd_and_hms(2023, 11, 27, 4, 0, 0).unwrap().timestamp();
+    let cfg = mock_cfg();
+    let (app_state, mut ws) = set_app_and_ws0(now, Some(Mode::Auto), cfg.watering).unwrap();
+    ws.sm.cfg.valve_check_secs = 0;
+    let cups = CalibrationMeasure::CatchCup { mm: 5. };
+
+    let run = ws.sm.start_calibration(2, 900, now).await.unwrap();
+    assert_eq!(run.sectors.iter().map(|sec| sec.sector).collect::<Vec<_>>(), [2]);
+    assert!(ws.sm.finish_calibration(2, cups, now + 60).is_err(), "still running");
+
+    let mut t = now;
+    while ws.sm.state != SMState::Idle && t < now + 1200 {
+        t += 10;
+        ws.sm.update(t).await;
+    }
+    assert!(ws.sm.finish_calibration(1, cups, t).is_err(), "another sector");
+    let calibrated = ws.sm.finish_calibration(2, cups, t).unwrap();
+    assert_eq!((calibrated.sprinkler_debit, calibrated.seconds), (2., 900));
+    assert_eq!(ws.sm.sectors[&2].sprinkler_debit, 2.);
+    assert!(ws.sm.finish_calibration(2, cups, t).is_err(), "measured once");
+
+    let short = start_calibration(Path(2), State(app_state.clone()), Json(CalibrationRequest { seconds: 10 })).await;
+    assert_eq!(short.unwrap_err().status(), StatusCode::BAD_REQUEST);
+    let none = start_calibration(Path(99), State(app_state.clone()), Json(CalibrationRequest { seconds: 600 })).await;
+    assert_eq!(none.unwrap_err().status(), StatusCode::NOT_FOUND);
+    let dry = CalibrationMeasure::Flow { litres_per_min: 0., area_m2: 20. };
+    let bad = finish_calibration(Path(2), State(app_state), Json(dry)).await;
+    assert_eq!(bad.unwrap_err().status(), StatusCode::BAD_REQUEST);
+}
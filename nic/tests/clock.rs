@@ -0,0 +1,64 @@
+use chrono::{TimeZone, Utc};
+use nic::{
+    clock::ClockJump,
+    config::SectorCfg,
+    db::{Database, DatabaseTrait},
+    test::utils::{
+        mock_cfg::mock_cfg, mock_db::new_with_mock, mock_sensors::set_sensor_controller0, mock_time::MockTimeProvider,
+    },
+    utils::sod,
+    watering::{
+        ds::{DailyPlan, WaterSector},
+        modes::Mode,
+        watering_system::{run_watering_system, WateringSystem},
+    },
+};
+use std::sync::Arc;
+
+fn sector(id: u32) -> SectorCfg {
+    SectorCfg {
+        id,
+        name: format!("zone {}", id),
+        sprinkler_debit: 1.0,
+        percolation_rate: 0.,
+        weekly_target: 2.5,
+        max_duration: 1800,
+        ignore_weather_pause: false,
+        max_daily_mm: None,
+        max_daily_minutes: None,
+        deficit_exempt: false,
+    }
+}
+
+#[tokio::test]
+async fn nothing_is_scheduled_until_the_clock_is_trusted_and_a_jump_drops_what_it_missed() {
+    // a wednesday, the window closes at 06:00
+    let now = Utc.with_ymd_and_hms(2024, 6, 5, 5, 50, 0).unwrap().timestamp();
+    let db = Arc::new(Database::new(":memory:").unwrap());
+    db.import_sectors(vec![sector(1)]).unwrap();
+    db.store_progress_day(sod(now) - 86_400).unwrap();
+    let app_state = new_with_mock(db.clone(), set_sensor_controller0(), Arc::new(MockTimeProvider::new(now))).unwrap();
+    let cfg = mock_cfg().watering;
+    let mut ws = WateringSystem::new(app_state.clone(), Some(Mode::Auto), now, cfg).unwrap();
+    let (_stop_tx, stop_rx) = tokio::sync::watch::channel(false);
+
+    app_state.clock.record(None, Some("3600s behind the last time trusted".to_owned()), None);
+    let seven = sod(now) + 7 * 3600;
+    run_watering_system(app_state.clone(), None, stop_rx.clone(), Some(seven), Some(&mut ws), cfg).await.unwrap();
+    assert_eq!(db.load_progress_day(), Some(sod(now) - 86_400), "held while the clock isn't trusted");
+
+    app_state.clock.record(None, None, Some(1));
+    let ten_past = seven + 600;
+    run_watering_system(app_state.clone(), None, stop_rx.clone(), Some(ten_past), Some(&mut ws), cfg).await.unwrap();
+    assert_eq!(db.load_progress_day(), Some(sod(now)));
+
+    // the clock jumped over the 06:30 cycle
+    let (missed, next) = (sod(now) + 6 * 3600 + 1800, sod(now) + 8 * 3600);
+    ws.sm.mode_auto.daily_plan =
+        vec![DailyPlan(vec![WaterSector::new(1, missed, 600)]), DailyPlan(vec![WaterSector::new(1, next, 600)])];
+    app_state.clock.record(Some(ClockJump { timestamp: ten_past, expected: missed - 600, secs: 4200 }), None, None);
+    run_watering_system(app_state, None, stop_rx, Some(ten_past + 600), Some(&mut ws), cfg).await.unwrap();
+    assert!(ws.sm.cycle.is_none(), "not watered late");
+    let starts: Vec<i64> = ws.sm.mode_auto.daily_plan.iter().map(|plan| plan.0[0].start).collect();
+    assert_eq!(starts, [next]);
+}
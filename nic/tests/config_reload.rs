@@ -0,0 +1,25 @@
+use nic::{
+    test::utils::{mock_cfg::mock_cfg, set_app_and_ws0},
+    utils::sod,
+    watering::modes::Mode,
+};
+
+#[tokio::test]
+async fn reload_moves_the_watering_window() {
+    let ref_time = sod(chrono::Utc::now().timestamp());
+    let cfg = mock_cfg();
+    let (_app, mut ws) = set_app_and_ws0(ref_time, Some(Mode::Wizard), cfg.watering).unwrap();
+    assert_eq!(ws.sm.timeframe.day_start_time, ref_time + 22 * 3600);
+
+    let mut new = mock_cfg();
+    new.watering.window_start_hour = 20;
+    new.watering.window_duration_hours = 4;
+    new.watering.max_pause_secs = 600;
+    ws.sm.apply_config(&new, ref_time + 3600);
+
+    assert_eq!(ws.sm.cfg.max_pause_secs, 600);
+    assert_eq!(ws.sm.timeframe.day_start_time, ref_time + 20 * 3600);
+    assert_eq!(ws.sm.timeframe.day_end_time, ref_time + 24 * 3600 - 1);
+    let events = ws.sm.db.load_system_events(ref_time + 3600, ref_time + 3601).unwrap();
+    assert_eq!(events.last().map(|evt| evt.kind.as_str()), Some("plan_recalculated"), "plans follow the window");
+}
@@ -0,0 +1,72 @@
+use chrono::{TimeZone, Utc};
+use nic::{
+    test::utils::{mock_cfg::mock_cfg, set_app_and_ws0},
+    watering::{
+        ds::{Cycle, DailyPlan, WaterSector},
+        modes::Mode,
+        state_machine::{ResumePoint, SMState},
+    },
+};
+
+#[tokio::test]
+async fn a_long_session_runs_as_several_cycles() {
+    let now = Utc.with_ymd_and_hms(2024, 6, 3, 12, 0, 0).unwrap().timestamp();
+    let cfg = mock_cfg();
+    let (_app, mut ws) = set_app_and_ws0(now, Some(Mode::Wizard), cfg.watering).unwrap();
+    ws.sm.cfg.valve_check_secs = 0;
+    ws.sm.load_plans(now);
+    let session: Vec<u32> =
+        ws.sm.mode_wizard.daily_plan.iter().flat_map(|plan| plan.0.iter().map(|sec| sec.id)).collect();
+    assert_eq!(ws.sm.mode_wizard.daily_plan.len(), 1);
+
+    // half an hour a sector, a cycle each
+    ws.sm.cfg.max_cycle_secs = 1800;
+    ws.sm.load_plans(now);
+    let plans = ws.sm.mode_wizard.daily_plan.clone();
+    assert!(plans.len() >= 3 && plans.iter().all(|plan| plan.0.len() == 1));
+    assert_eq!(plans.iter().map(|plan| plan.0[0].id).collect::<Vec<_>>(), session);
+
+    let (from, to) = (plans[0].0[0].start, ws.sm.timeframe.day_end_time + 1);
+    let mut t = from;
+    while t <= to {
+        ws.sm.update(t).await;
+        t += 10;
+    }
+    assert_eq!(ws.sm.state, SMState::Idle);
+    assert!(ws.sm.mode_wizard.daily_plan.is_empty());
+    let events = ws.sm.db.load_system_events(from, to).unwrap();
+    let started: Vec<Option<i64>> =
+        events.iter().filter(|evt| evt.kind == "cycle_started").map(|evt| evt.cycle).collect();
+    let planned: Vec<Option<i64>> = plans.iter().map(|plan| Some(plan.0[0].start)).collect();
+    assert_eq!(started, planned, "each in its turn");
+}
+
+#[tokio::test]
+async fn a_restored_cycle_leaves_the_queue_alone() {
+    let now = Utc.with_ymd_and_hms(2024, 6, 3, 21, 0, 0).unwrap().timestamp();
+    let cfg = mock_cfg();
+    let (_app, mut ws) = set_app_and_ws0(now, Some(Mode::Auto), cfg.watering).unwrap();
+    ws.sm.cfg.valve_check_secs = 0;
+    let start = ws.sm.timeframe.day_start_time;
+    let program = |id, offset| DailyPlan(vec![WaterSector::new(id, start + offset, 600)]);
+    ws.sm.mode_auto.daily_plan = vec![program(1, 3600), program(2, 7200)];
+
+    // a cycle of a schedule that changed since, back after a restart
+    let sector = WaterSector::new(3, start, 1200);
+    let cycle = Cycle::build(DailyPlan(vec![sector]));
+    ws.sm.restore(ResumePoint { cycle, sector, elapsed: 600, saved_at: start + 600 }, start + 900);
+    let mut t = start + 900;
+    while ws.sm.state != SMState::Idle {
+        ws.sm.update(t).await;
+        t += 10;
+    }
+    assert!(t < start + 3600);
+    assert_eq!(ws.sm.mode_auto.daily_plan, [program(1, 3600), program(2, 7200)]);
+
+    // the programs still run, and go in order
+    while t <= start + 7200 + 600 {
+        ws.sm.update(t).await;
+        t += 10;
+    }
+    assert!(ws.sm.mode_auto.daily_plan.is_empty());
+}
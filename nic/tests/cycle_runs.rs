@@ -0,0 +1,44 @@
+use chrono::{TimeZone, Utc};
+use nic::{
+    test::{
+        fixtures::{sector, Fixture},
+        utils::mock_cfg::mock_cfg,
+    },
+    watering::{modes::Mode, state_machine::SMState, watering_system::WateringSystem},
+};
+
+#[tokio::test]
+async fn each_cycle_keeps_what_was_planned_what_ran_and_how_it_ended() {
+    let now = Utc.with_ymd_and_hms(2024, 6, 3, 12, 0, 0).unwrap().timestamp();
+    let app_state = Fixture::new().with_sectors([sector(1), sector(2), sector(3)]).app_state(now);
+    let db = app_state.db.clone();
+    let mut ws = WateringSystem::new(app_state, Some(Mode::Wizard), now, mock_cfg().watering).unwrap();
+    (ws.sm.cfg.valve_check_secs, ws.sm.cfg.max_cycle_secs) = (0, 1800);
+    ws.sm.load_plans(now);
+    let plans = ws.sm.mode_wizard.daily_plan.clone();
+    assert!(plans.len() >= 2);
+
+    // the first cycle runs to the end
+    let mut t = plans[0].0[0].start;
+    while t < plans[1].0[0].start && (t == plans[0].0[0].start || ws.sm.state != SMState::Idle) {
+        ws.sm.update(t).await;
+        t += 10;
+    }
+    // the second is cut short by a switch to manual
+    t = plans[1].0[0].start;
+    ws.sm.update(t).await;
+    ws.sm.update(t + 60).await;
+    assert!(matches!(ws.sm.state, SMState::Watering(_)));
+    ws.sm.trans_change_mode(Mode::Manual, t + 120).await;
+    ws.db.flush().unwrap();
+
+    let runs = db.load_cycle_runs(now, t + 86_400).unwrap();
+    let outcomes: Vec<_> = runs.iter().map(|run| run.outcome.as_deref()).collect();
+    assert_eq!(outcomes, [Some("cycle_completed"), Some("cycle_aborted")]);
+    assert!(runs.iter().all(|run| run.mode == Mode::Wizard && run.end.is_some()));
+    assert_eq!(runs[0].planned, plans[0].0);
+    let ran = |run: usize| runs[run].executed.iter().map(|sec| sec.id).collect::<Vec<_>>();
+    assert_eq!(ran(0), plans[0].0.iter().map(|sec| sec.id).collect::<Vec<_>>());
+    assert_eq!(ran(1), [plans[1].0[0].id], "only the sector that was cut");
+    assert!(runs[1].executed[0].duration < plans[1].0[0].duration);
+}
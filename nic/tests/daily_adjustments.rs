@@ -0,0 +1,57 @@
+use chrono::{TimeZone, Utc};
+use nic::{
+    config::WeatherStation,
+    test::utils::{mock_cfg::mock_cfg, mock_db::MockDatabase, set_app_state_and_controller},
+    utils::sod,
+    watering::{modes::Mode, watering_system::run_watering_system, watering_system::WateringSystem},
+    weather::freshness::WeatherFreshness,
+};
+use std::sync::Arc;
+
+/// Runs the loop over the 06:00 adjustment of a Tuesday, no new week, with 2 cm of progress and no percolation.
+/// Returns the progress of each sector after it.
+async fn adjust(db: MockDatabase, freshness: WeatherFreshness) -> Vec<f64> {
+    let now = Utc.with_ymd_and_hms(2024, 7, 16, 5, 0, 0).unwrap().timestamp();
+    let cfg = mock_cfg();
+    let (app_state, _) = set_app_state_and_controller(now, Some(Arc::new(db))).await;
+    let mut ws = WateringSystem::new(app_state.clone(), Some(Mode::Manual), now, cfg.watering).unwrap();
+    ws.freshness = Arc::new(freshness);
+    for sector in ws.sm.sectors.values_mut() {
+        (sector.progress, sector.percolation_rate) = (2., 0.);
+    }
+
+    let (_shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+    let end = now + 2 * 3600;
+    run_watering_system(app_state, None, shutdown_rx, Some(end), Some(&mut ws), cfg.watering).await.unwrap();
+    let mut sectors: Vec<_> = ws.sm.sectors.values().map(|sector| (sector.id, sector.progress)).collect();
+    sectors.sort_by_key(|(id, _)| *id);
+    sectors.into_iter().map(|(_, progress)| progress).collect()
+}
+
+#[tokio::test]
+async fn the_rollup_in_mm_takes_cm_of_progress() {
+    let day = sod(Utc.with_ymd_and_hms(2024, 7, 16, 6, 0, 0).unwrap().timestamp());
+    let mut db = MockDatabase::new();
+    db.et_data.insert(day, 5.);
+    db.rain_data.insert(day, 1.);
+
+    // 5 mm of ET and 1 mm of rain take 0.4 cm
+    for progress in adjust(db, WeatherFreshness::disabled()).await {
+        assert!((progress - 1.6).abs() < 1e-9, "progress {}", progress);
+    }
+}
+
+#[tokio::test]
+async fn the_fallback_et_in_mm_takes_cm_of_progress() {
+    let day = sod(Utc.with_ymd_and_hms(2024, 7, 16, 6, 0, 0).unwrap().timestamp());
+    let mut db = MockDatabase::new();
+    db.et_data.insert(day, 8.);
+    db.rain_data.insert(day, 1.);
+    // the station never reported and there is no history to average
+    let cfg = WeatherStation { stale_after_hours: 6, fallback_et: 4., ..Default::default() };
+
+    // the rollups aren't trusted, an average dry day of 4 mm takes 0.4 cm
+    for progress in adjust(db, WeatherFreshness::new(&cfg, None)).await {
+        assert!((progress - 1.6).abs() < 1e-9, "progress {}", progress);
+    }
+}
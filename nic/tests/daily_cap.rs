@@ -0,0 +1,115 @@
+use chrono::{TimeZone, Utc};
+use nic::{
+    sensors::interface::ValveState,
+    test::utils::{mock_cfg::mock_cfg, mock_sensors::MockSensorController, set_app_and_ws0},
+    watering::{
+        ds::{DailyPlan, WaterSector},
+        modes::Mode,
+        state_machine::SMState,
+    },
+};
+use std::sync::{Arc, Mutex};
+
+#[tokio::test]
+async fn a_sector_stops_at_its_daily_cap() {
+    let now = Utc.with_ymd_and_hms(2024, 6, 3, 21, 0, 0).unwrap().timestamp();
+    let cfg = mock_cfg();
+    let (_app, mut ws) = set_app_and_ws0(now, Some(Mode::Wizard), cfg.watering).unwrap();
+    ws.sm.cfg.valve_check_secs = 0;
+    ws.sm.sectors.get_mut(&1).unwrap().max_daily_minutes = Some(10);
+
+    // a planner gone wrong, sector 1 twice in the night and for longer than its cap
+    let start = ws.sm.timeframe.day_start_time;
+    ws.sm.mode_wizard.daily_plan =
+        vec![DailyPlan(vec![WaterSector::new(1, start, 900)]), DailyPlan(vec![WaterSector::new(1, start + 3600, 600)])];
+    let mut t = start;
+    while t < start + 2 * 3600 {
+        ws.sm.update(t).await;
+        t += 10;
+    }
+    assert_eq!(ws.sm.state, SMState::Idle);
+    assert!(ws.sm.mode_wizard.daily_plan.is_empty(), "both cycles ran");
+    assert_eq!(ws.sm.watered_today[&1], 600);
+
+    let events = ws.sm.db.load_system_events(start, t).unwrap();
+    let caps: Vec<&str> =
+        events.iter().filter(|evt| evt.kind == "daily_cap_reached").map(|evt| evt.detail.as_str()).collect();
+    assert_eq!(caps, ["600s left of the daily cap", "0s left of the daily cap"]);
+    assert_eq!(events.iter().filter(|evt| evt.kind == "sector_activated").count(), 1, "the second one isn't opened");
+
+    // the next watering day starts over
+    ws.sm.update(start + 86_400).await;
+    assert!(ws.sm.watered_today.is_empty());
+}
+
+#[tokio::test]
+async fn a_sector_without_cap_left_is_skipped() {
+    let now = Utc.with_ymd_and_hms(2024, 6, 3, 21, 0, 0).unwrap().timestamp();
+    let cfg = mock_cfg();
+    let (_app, mut ws) = set_app_and_ws0(now, Some(Mode::Wizard), cfg.watering).unwrap();
+    ws.sm.cfg.valve_check_secs = 0;
+    ws.sm.sectors.get_mut(&1).unwrap().max_daily_minutes = Some(10);
+
+    let commands = Arc::new(Mutex::new(vec![]));
+    let (opened, closed) = (commands.clone(), commands.clone());
+    let mut controller = MockSensorController::new();
+    controller.expect_activate_sector().returning(move |sector| {
+        opened.lock().unwrap().push(("open", sector));
+        Ok(())
+    });
+    controller.expect_deactivate_sector().returning(move |sector| {
+        closed.lock().unwrap().push(("close", sector));
+        Ok(())
+    });
+    controller.expect_sector_state().returning(|_| Ok(ValveState::Unknown));
+    ws.sm.controller = Arc::new(controller);
+
+    // sector 1 already had its 10 minutes today
+    let start = ws.sm.timeframe.day_start_time;
+    ws.sm.watered_today.insert(1, 600);
+    ws.sm.mode_wizard.daily_plan = vec![
+        DailyPlan(vec![WaterSector::new(1, start, 300), WaterSector::new(2, start + 320, 300)]),
+        DailyPlan(vec![WaterSector::new(1, start + 3600, 300)]),
+    ];
+    let mut t = start;
+    while t < start + 2 * 3600 {
+        ws.sm.update(t).await;
+        t += 10;
+    }
+    assert_eq!(ws.sm.state, SMState::Idle);
+    assert!(ws.sm.mode_wizard.daily_plan.is_empty(), "both cycles ran");
+    assert_eq!(*commands.lock().unwrap(), [("open", 2), ("close", 2)], "sector 1 is never opened nor closed");
+
+    let events = ws.sm.db.load_system_events(start, t).unwrap();
+    let kinds: Vec<(&str, Option<u32>)> = events.iter().map(|evt| (evt.kind.as_str(), evt.sector)).collect();
+    assert_eq!(
+        kinds,
+        [
+            ("cycle_started", None),
+            ("daily_cap_reached", Some(1)),
+            ("sector_activated", Some(2)),
+            ("sector_deactivated", Some(2)),
+            ("cycle_completed", None),
+            ("cycle_started", None),
+            ("daily_cap_reached", Some(1)),
+            ("cycle_completed", None),
+        ]
+    );
+    assert!(
+        ws.sm.db.load_watering_events(start, t).unwrap().iter().all(|evt| evt.sector.id != 1),
+        "no phantom watering"
+    );
+}
+
+#[tokio::test]
+async fn the_wizard_plans_within_the_cap() {
+    let now = Utc.with_ymd_and_hms(2024, 6, 3, 12, 0, 0).unwrap().timestamp();
+    let cfg = mock_cfg();
+    let (_app, mut ws) = set_app_and_ws0(now, Some(Mode::Wizard), cfg.watering).unwrap();
+    ws.sm.cfg.max_daily_minutes = Some(8);
+    ws.sm.load_plans(now);
+
+    let plans = &ws.sm.mode_wizard.daily_plan;
+    assert!(!plans.is_empty());
+    assert!(plans.iter().flat_map(|plan| plan.0.iter()).all(|sec| sec.duration <= 8 * 60));
+}
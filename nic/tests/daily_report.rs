@@ -0,0 +1,59 @@
+use axum::extract::{Path, State};
+use chrono::{TimeZone, Utc};
+use hyper::StatusCode;
+use nic::{
+    api::get_daily_report,
+    test::utils::{mock_cfg::mock_cfg, set_app_and_ws0},
+    utils::load_sectors_into_hashmap,
+    watering::{
+        ds::{CtrlSignal, SectorInfo, SystemEvent},
+        modes::Mode,
+        watering_system::run_watering_system,
+    },
+};
+use std::collections::BTreeMap;
+
+#[tokio::test]
+async fn report_after_the_window() {
+    // Monday evening, nothing runs in manual
+    let now = Utc.with_ymd_and_hms(2023, 11, 27, 20, 0, 0).unwrap().timestamp();
+    let mut cfg = mock_cfg();
+    cfg.watering.notify_daily_report = true;
+    let (app_state, mut ws) = set_app_and_ws0(now, Some(Mode::Manual), cfg.watering).unwrap();
+    ws.sm.sectors = load_sectors_into_hashmap(vec![SectorInfo::build(1, 2.5, 2., 30 * 60, 0., 0.29, 0)]);
+    let mut web_rx = app_state.web_rx.resubscribe();
+
+    let evt = |hour: i64, kind: &str| SystemEvent {
+        timestamp: now + (hour - 20) * 3600,
+        kind: kind.to_owned(),
+        sector: Some(1),
+        cycle: None,
+        detail: String::new(),
+    };
+    for evt in [evt(21, "sector_activated"), evt(22, "sector_deactivated"), evt(23, "emergency_stop")] {
+        app_state.db.log_system_event(evt).unwrap();
+    }
+
+    let (_shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+    // the window closes at 06:00
+    let end = now + 11 * 3600;
+    run_watering_system(app_state.clone(), None, shutdown_rx, Some(end), Some(&mut ws), cfg.watering).await.unwrap();
+
+    let report = get_daily_report(Path("2023-11-27".to_owned()), State(app_state.clone())).await.unwrap().0;
+    assert_eq!(report.water, BTreeMap::from([(1, 2.)]));
+    assert_eq!(report.anomalies, ["23:00 emergency_stop sector 1"]);
+    assert!(report.plan.0.is_empty());
+
+    let mut pushed = None;
+    while let Ok(signal) = web_rx.try_recv() {
+        if let CtrlSignal::DailyReport(report) = signal {
+            pushed = Some(report);
+        }
+    }
+    assert_eq!(pushed, Some(report));
+
+    let missing = get_daily_report(Path("2023-11-28".to_owned()), State(app_state.clone())).await;
+    assert_eq!(missing.unwrap_err().status(), StatusCode::NOT_FOUND);
+    let bad = get_daily_report(Path("yesterday".to_owned()), State(app_state)).await;
+    assert_eq!(bad.unwrap_err().status(), StatusCode::BAD_REQUEST);
+}
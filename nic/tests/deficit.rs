@@ -0,0 +1,82 @@
+use chrono::{TimeZone, Utc};
+use nic::{
+    api::RestrictionStatus,
+    config::{Config, RestrictionCfg},
+    test::utils::{mock_cfg::mock_cfg, set_app_and_ws0},
+    watering::{modes::Mode, watering_system::WateringSystem},
+};
+
+fn summer() -> RestrictionCfg {
+    RestrictionCfg { from: "2024-07-01".to_owned(), to: "2024-08-31".to_owned(), et_pct: 80. }
+}
+
+#[tokio::test]
+async fn a_restriction_gives_back_part_of_the_et() {
+    // a tuesday, no new week to add
+    let now = Utc.with_ymd_and_hms(2024, 7, 16, 0, 0, 0).unwrap().timestamp();
+    let cfg = mock_cfg();
+    let (_app, mut ws) = set_app_and_ws0(now, Some(Mode::Wizard), cfg.watering).unwrap();
+    ws.sm.restrictions = vec![summer()];
+    ws.sm.sectors.get_mut(&2).unwrap().deficit_exempt = true;
+    for sector in ws.sm.sectors.values_mut() {
+        (sector.progress, sector.percolation_rate) = (2., 0.);
+    }
+
+    ws.sm.do_daily_adjustments(now, 0.5, 0.);
+    let progress = |id| ws.sm.sectors[&id].progress;
+    assert!((progress(1) - 1.6).abs() < 1e-9, "80% of the ET");
+    assert!((progress(2) - 1.5).abs() < 1e-9, "exempt, all of it");
+
+    // the day after the restriction ends is charged in full
+    let september = Utc.with_ymd_and_hms(2024, 9, 3, 0, 0, 0).unwrap().timestamp();
+    ws.sm.do_daily_adjustments(september, 0.5, 0.);
+    assert!((ws.sm.sectors[&1].progress - 1.1).abs() < 1e-9);
+}
+
+#[tokio::test]
+async fn the_wizard_plans_for_the_deficit() {
+    // the last night of the week, what is missing is watered tonight
+    let now = Utc.with_ymd_and_hms(2024, 7, 14, 12, 0, 0).unwrap().timestamp();
+    let cfg = mock_cfg();
+    let (_app, mut ws) = set_app_and_ws0(now, Some(Mode::Wizard), cfg.watering).unwrap();
+    for sector in ws.sm.sectors.values_mut() {
+        sector.progress = 2.2;
+    }
+    // 2 mm of the day, 0.5 cm to water in full, 0.4 cm with half of it
+    ws.sm.predicted_et = 2.;
+    let planned = |ws: &WateringSystem| -> Vec<i64> {
+        let plans = ws.sm.mode_wizard.daily_plan.iter();
+        plans.flat_map(|plan| plan.0.iter()).map(|sec| sec.duration).collect()
+    };
+    ws.sm.load_plans(now);
+    assert_eq!(planned(&ws), [1800; 4]);
+    ws.sm.restrictions = vec![RestrictionCfg { et_pct: 50., ..summer() }];
+    ws.sm.load_plans(now);
+    assert_eq!(planned(&ws), [1440; 4]);
+}
+
+#[test]
+fn the_status_shows_the_restriction() {
+    let mut cfg = Config::load_from_str(
+        r#"[[sectors]]
+           id = 1
+           sprinkler_debit = 1.0
+           percolation_rate = 0.5
+           weekly_target = 2.5
+           max_duration = 1800
+           [[sectors]]
+           id = 2
+           sprinkler_debit = 1.0
+           percolation_rate = 0.5
+           weekly_target = 2.5
+           max_duration = 1800
+           deficit_exempt = true"#,
+    );
+    let july = Utc.with_ymd_and_hms(2024, 7, 15, 12, 0, 0).unwrap().timestamp();
+    assert_eq!(RestrictionStatus::at(&cfg, july), None);
+    cfg.restrictions = vec![summer()];
+    let status = RestrictionStatus::at(&cfg, july).unwrap();
+    assert_eq!((status.from.as_str(), status.to.as_str(), status.et_pct), ("2024-07-01", "2024-08-31", 80.));
+    assert_eq!(status.exempt, [2]);
+    assert_eq!(RestrictionStatus::at(&cfg, july + 60 * 86_400), None);
+}
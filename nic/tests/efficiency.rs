@@ -0,0 +1,46 @@
+use chrono::{TimeZone, Utc};
+use nic::{
+    test::{
+        fixtures::{sector, Fixture},
+        utils::mock_cfg::mock_cfg,
+    },
+    watering::{efficiency::efficiency, modes::Mode, watering_system::WateringSystem},
+};
+
+#[tokio::test]
+async fn what_was_planned_against_what_was_watered() {
+    let now = Utc.with_ymd_and_hms(2024, 6, 3, 12, 0, 0).unwrap().timestamp();
+    let app_state = Fixture::new().with_sectors([sector(1), sector(2)]).app_state(now);
+    let (db, cfg) = (app_state.db.clone(), mock_cfg());
+    let mut ws = WateringSystem::new(app_state, Some(Mode::Wizard), now, cfg.watering).unwrap();
+    ws.sm.cfg.valve_check_secs = 0;
+    ws.sm.load_plans(now);
+    let plan = ws.sm.mode_wizard.daily_plan[0].0.clone();
+    assert_eq!(plan.len(), 2);
+
+    // the first sector runs through, the second is cut short by a switch to manual
+    let (first, second) = (plan[0], plan[1]);
+    let mut t = first.start;
+    while t < second.start + 600 {
+        ws.sm.update(t).await;
+        t += 10;
+    }
+    ws.sm.trans_change_mode(Mode::Manual, t).await;
+
+    // the night ends the next morning, the week up to the day after has it
+    let stats = efficiency(db.as_ref(), &ws.sm.sectors, now + 2 * 86_400, 1).unwrap();
+    assert_eq!(stats.iter().map(|sec| sec.sector).collect::<Vec<_>>(), [1, 2]);
+    let of = |id| stats.iter().find(|sec| sec.sector == id).unwrap();
+    let (one, two) = (of(first.id), of(second.id));
+    assert_eq!((one.planned_secs, one.lost_secs), (first.duration, 0));
+    assert!((one.watered_secs - first.duration).abs() <= 10);
+    assert_eq!(two.planned_secs, second.duration);
+    // it opens as soon as the first closes, the transition is the planner's margin
+    assert!((600..=630).contains(&two.watered_secs));
+    assert_eq!(two.lost_secs, second.duration - two.watered_secs);
+    assert!(one.applied_cm > two.applied_cm && one.achievement > two.achievement);
+
+    // what was planned before the switch stays on record
+    ws.sm.load_plans(t);
+    assert_eq!(db.load_wizard_plans(first.start, t).unwrap(), plan);
+}
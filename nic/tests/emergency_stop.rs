@@ -0,0 +1,60 @@
+use nic::{
+    sensors::interface::ValveState,
+    test::utils::{mock_cfg::mock_cfg, mock_sensors::MockSensorController, set_app_and_ws0},
+    utils::sod,
+    watering::{
+        ds::{CtrlSignal, DailyPlan, WaterSector},
+        modes::Mode,
+        state_machine::SMState,
+    },
+};
+use std::sync::{Arc, Mutex};
+
+#[tokio::test]
+async fn emergency_stop_latches_until_cleared() {
+    let ref_time = sod(chrono::Utc::now().timestamp());
+    let cfg = mock_cfg();
+    let (_app, mut ws) = set_app_and_ws0(ref_time, Some(Mode::Wizard), cfg.watering).unwrap();
+
+    let closed = Arc::new(Mutex::new(vec![]));
+    let closed_clone = closed.clone();
+    let mut controller = MockSensorController::new();
+    controller.expect_activate_sector().returning(|_| Ok(()));
+    controller.expect_deactivate_sector().returning(move |sector| {
+        closed_clone.lock().unwrap().push(sector);
+        Ok(())
+    });
+    controller.expect_sector_state().returning(|_| Ok(ValveState::Unknown));
+    ws.sm.controller = Arc::new(controller);
+    ws.sm.cfg.pump_sector = Some(9);
+
+    let start = ws.sm.timeframe.day_start_time;
+    ws.sm.mode_wizard.daily_plan =
+        vec![DailyPlan(vec![WaterSector::new(1, start, 600)]), DailyPlan(vec![WaterSector::new(2, start + 3600, 600)])];
+    ws.sm.update(start).await;
+    assert!(ws.sm.state.is_watering());
+
+    ws.sm.handle_signal(CtrlSignal::EmergencyStop, start + 60).await;
+    assert_eq!(ws.sm.state, SMState::Stopped);
+    assert!(ws.sm.cycle.is_none());
+    let closed_now = closed.lock().unwrap().clone();
+    assert_eq!(closed_now[..2], [9, 1], "master first, then the open sector");
+    let mut sectors: Vec<u32> = ws.sm.sectors.keys().copied().collect();
+    sectors.sort_unstable();
+    assert!(sectors.iter().all(|sector| closed_now.contains(sector)), "every sector is closed");
+
+    // schedules, mode changes and weather don't get it out of the stop
+    ws.sm.update(start + 3600).await;
+    ws.sm.handle_signal(CtrlSignal::ChgMode(Mode::Auto), start + 3601).await;
+    assert_eq!(ws.sm.state, SMState::Stopped);
+    assert_eq!(ws.sm.current_mode, Mode::Wizard);
+
+    ws.sm.handle_signal(CtrlSignal::ClearEmergencyStop, start + 3602).await;
+    assert_eq!(ws.sm.state, SMState::Idle);
+    ws.sm.update(start + 3603).await;
+    assert!(ws.sm.state.is_watering(), "re-armed, the next cycle runs");
+
+    let events = ws.sm.db.load_system_events(start, start + 3700).unwrap();
+    let kinds: Vec<&str> = events.iter().map(|evt| evt.kind.as_str()).collect();
+    assert!(kinds.contains(&"emergency_stop") && kinds.contains(&"emergency_stop_cleared"));
+}
@@ -0,0 +1,35 @@
+use chrono::{TimeZone, Utc};
+use nic::{
+    db::{Database, DatabaseTrait},
+    error::ErrorKind,
+    test::utils::{
+        mock_cfg::mock_cfg, mock_db::new_with_mock, mock_sensors::set_sensor_controller0, mock_time::MockTimeProvider,
+    },
+    watering::{
+        ds::CtrlSignal,
+        modes::Mode,
+        watering_system::{run_watering_system, WateringSystem},
+    },
+};
+use std::sync::Arc;
+
+#[tokio::test]
+async fn stops_on_a_broken_database() {
+    let now = Utc.with_ymd_and_hms(2023, 11, 27, 20, 0, 0).unwrap().timestamp();
+    let cfg = mock_cfg();
+    let db = Arc::new(Database::new(":memory:").unwrap());
+    let app_state = new_with_mock(db.clone(), set_sensor_controller0(), Arc::new(MockTimeProvider::new(now))).unwrap();
+    let mut ws = WateringSystem::new(app_state.clone(), Some(Mode::Manual), now, cfg.watering).unwrap();
+
+    // the mode change can't be logged anymore
+    db.execute("DROP TABLE events", vec![]).unwrap();
+    app_state.sm_tx.send(CtrlSignal::ChgMode(Mode::Off)).unwrap();
+
+    let (_shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+    let end = now + 3600;
+    let res = run_watering_system(app_state, None, shutdown_rx, Some(end), Some(&mut ws), cfg.watering).await;
+    let e = res.unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::Invariant);
+    assert!(!e.is_retryable());
+    assert_eq!(ws.sm.current_mode, Mode::Off);
+}
@@ -0,0 +1,58 @@
+use axum::body::to_bytes;
+use axum::extract::{Query, State};
+use chrono::{TimeZone, Utc};
+use hyper::StatusCode;
+use nic::{
+    api::{export_events, export_weather, ExportQuery},
+    test::fixtures::{sector, Fixture},
+    watering::{
+        ds::{WaterSector, WateringEvent, WeatherConditions},
+        modes::Mode,
+    },
+};
+
+fn days(from: &str, to: &str) -> Query<ExportQuery> {
+    Query(ExportQuery { from: Some(from.to_owned()), to: Some(to.to_owned()) })
+}
+
+#[tokio::test]
+async fn streams_the_rows_of_the_days_a_page_at_a_time() {
+    let now = Utc.with_ymd_and_hms(2023, 11, 28, 12, 0, 0).unwrap().timestamp();
+    let day = Utc.with_ymd_and_hms(2023, 11, 27, 0, 0, 0).unwrap().timestamp();
+    // more than a page, a minute apart from the day before
+    let weather = (0..1300).map(|minute| WeatherConditions {
+        timestamp: day - 86_400 + minute * 60,
+        temperature: 15.,
+        ..Default::default()
+    });
+    let app_state = Fixture::new().with_sectors([sector(1), sector(2)]).with_weather(weather).app_state(now);
+    let db = &app_state.db;
+    db.log_watering_event(WateringEvent::new(Some(1), WaterSector::new(1, day - 3600, 600), 0.2, Mode::Auto)).unwrap();
+    db.log_watering_event(WateringEvent::new(Some(2), WaterSector::new(2, day + 79_200, 900), 0.25, Mode::Wizard))
+        .unwrap();
+
+    let response = export_weather(days("2023-11-26", "2023-11-26"), State(app_state.clone())).await.unwrap();
+    assert_eq!(response.headers()["content-type"], "text/csv");
+    let csv = String::from_utf8(to_bytes(response.into_body(), usize::MAX).await.unwrap().to_vec()).unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines.len(), 1 + 1300);
+    assert!(lines[0].starts_with("time_utc,temperature"));
+    assert!(lines[1].starts_with("2023-11-26 00:00:00,15,"), "{}", lines[1]);
+    assert!(lines[1300].starts_with("2023-11-26 21:39:00,"), "{}", lines[1300]);
+
+    let response = export_events(days("2023-11-27", "2023-11-27"), State(app_state.clone())).await.unwrap();
+    let csv = String::from_utf8(to_bytes(response.into_body(), usize::MAX).await.unwrap().to_vec()).unwrap();
+    assert_eq!(
+        csv,
+        "id,cycle_id,sector,start_utc,duration_secs,water_applied_cm,mode\n2,2,2,2023-11-27 22:00:00,900,0.25,wizard\n"
+    );
+    // the last 30 days up to today
+    let response = export_events(Query(ExportQuery::default()), State(app_state.clone())).await.unwrap();
+    let csv = String::from_utf8(to_bytes(response.into_body(), usize::MAX).await.unwrap().to_vec()).unwrap();
+    assert_eq!(csv.lines().count(), 3);
+
+    let backwards = export_events(days("2023-11-27", "2023-11-01"), State(app_state.clone())).await;
+    assert_eq!(backwards.unwrap_err().status(), StatusCode::BAD_REQUEST);
+    let bad = export_weather(days("27/11/2023", "2023-11-28"), State(app_state)).await;
+    assert_eq!(bad.unwrap_err().status(), StatusCode::BAD_REQUEST);
+}
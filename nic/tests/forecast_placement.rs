@@ -0,0 +1,31 @@
+use chrono::{TimeZone, Utc};
+use nic::{
+    test::utils::{mock_cfg::mock_cfg, set_app_and_ws0},
+    watering::{ds::WaterSector, modes::Mode},
+    weather::forecast::HourlyForecast,
+};
+
+#[tokio::test]
+async fn the_wizard_keeps_out_of_the_forecast_wind() {
+    let now = Utc.with_ymd_and_hms(2024, 6, 3, 12, 0, 0).unwrap().timestamp();
+    let cfg = mock_cfg();
+    let (_app, mut ws) = set_app_and_ws0(now, Some(Mode::Wizard), cfg.watering).unwrap();
+    ws.sm.load_plans(now);
+    let planned = ws.sm.mode_wizard.daily_plan[0].clone();
+    let end = |plan: &[WaterSector]| plan.last().map_or(0, |sec| sec.start + sec.duration);
+    // the session ends with the window, at 06:00
+    assert!(ws.sm.timeframe.day_end_time - end(&planned.0) < 60);
+
+    // the wind picks up at 04:00
+    let wind_from = ws.sm.timeframe.day_start_time + 6 * 3600;
+    ws.sm.forecast = (0..3)
+        .map(|hour| HourlyForecast { timestamp: wind_from + hour * 3600, wind_speed: 35., ..Default::default() })
+        .collect();
+    ws.sm.load_plans(now);
+
+    let moved = &ws.sm.mode_wizard.daily_plan[0];
+    let ids = |plan: &[WaterSector]| plan.iter().map(|sec| sec.id).collect::<Vec<_>>();
+    assert_eq!(ids(&moved.0), ids(&planned.0), "the same session");
+    assert_eq!(end(&moved.0), wind_from, "done before the wind");
+    assert_eq!(moved.0[0].start - planned.0[0].start, wind_from - end(&planned.0));
+}
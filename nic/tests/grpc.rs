@@ -0,0 +1,80 @@
+#![cfg(feature = "grpc")]
+use chrono::{TimeZone, Utc};
+use nic::grpc::{
+    proto::{control_client::ControlClient, Empty, ManualSector, ManualStartRequest, SwitchModeRequest},
+    run_grpc_server,
+};
+use nic::test::utils::mock_cfg::mock_cfg;
+use nic::test::utils::mock_db::mock_sector;
+use nic::test::utils::set_app_and_ws0;
+use nic::utils::load_sectors_into_hashmap;
+use nic::watering::ds::{CtrlSignal, StateChange, StateEvent};
+use nic::watering::modes::Mode;
+use nic::watering::watering_system::run_watering_system;
+use tonic::Code;
+
+#[tokio::test]
+async fn controls_over_grpc() {
+    let current_time = Utc.with_ymd_and_hms(2023, 11, 25, 22, 0, 0).unwrap().timestamp();
+    let cfg = mock_cfg();
+    let (app_state, mut ws) = set_app_and_ws0(current_time, Some(Mode::Manual), cfg.watering).unwrap();
+    ws.sm.sectors = load_sectors_into_hashmap(mock_sector());
+
+    let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+    let (app_state_clone, rx_clone) = (app_state.clone(), shutdown_rx.clone());
+    let watering_system_task = tokio::spawn(async move {
+        let _ =
+            run_watering_system(app_state_clone, Some(Mode::Manual), rx_clone, None, Some(&mut ws), cfg.watering).await;
+    });
+    let addr = "127.0.0.1:3020";
+    tokio::spawn(run_grpc_server(app_state.clone(), addr.parse().unwrap(), shutdown_rx));
+    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+
+    let mut client = ControlClient::connect(format!("http://{}", addr)).await.unwrap();
+    let mut events = client.stream_events(Empty {}).await.unwrap().into_inner();
+
+    let reply = client.switch_mode(SwitchModeRequest { mode: "off".to_owned() }).await.unwrap().into_inner();
+    assert_eq!(reply.mode, "off");
+    let e = client.switch_mode(SwitchModeRequest { mode: "sideways".to_owned() }).await.unwrap_err();
+    assert_eq!(e.code(), Code::InvalidArgument);
+
+    let state = client.get_state(Empty {}).await.unwrap().into_inner();
+    assert_eq!(state.mode.as_deref(), Some("off"));
+    let cycle = client.get_cycle(Empty {}).await.unwrap().into_inner();
+    assert!(cycle.id.is_none());
+
+    let evt = StateEvent { timestamp: 10, sector: Some(2), cycle: Some(1), change: StateChange::SectorDeactivated };
+    app_state.web_tx.send(CtrlSignal::StateChanged(evt)).unwrap();
+    // the mode change went out before it
+    let mut kinds = vec![];
+    while let Some(evt) = events.message().await.unwrap() {
+        kinds.push(evt.kind.clone());
+        if evt.kind == "sector_deactivated" {
+            assert_eq!((evt.timestamp, evt.sector, evt.cycle), (10, Some(2), Some(1)));
+            break;
+        }
+    }
+    assert!(kinds.iter().any(|kind| kind == "mode_changed"));
+
+    let e = client.manual_start(ManualStartRequest { sectors: vec![] }).await.unwrap_err();
+    assert_eq!(e.code(), Code::InvalidArgument);
+    let sectors = vec![ManualSector { sector: 1, duration_secs: 60 }];
+    let e = client.manual_start(ManualStartRequest { sectors: sectors.clone() }).await.unwrap_err();
+    assert_eq!(e.code(), Code::FailedPrecondition, "not in off mode");
+    client.switch_mode(SwitchModeRequest { mode: "manual".to_owned() }).await.unwrap();
+    let reply = client.manual_start(ManualStartRequest { sectors }).await.unwrap().into_inner();
+    // the clock isn't held, the cycle runs to its end
+    let mut kinds = vec![];
+    while let Some(evt) = events.message().await.unwrap() {
+        if evt.cycle == Some(reply.cycle) {
+            kinds.push(evt.kind.clone());
+        }
+        if evt.kind == "cycle_completed" {
+            break;
+        }
+    }
+    assert_eq!(kinds, ["cycle_started", "sector_activated", "sector_deactivated", "cycle_completed"]);
+
+    _ = shutdown_tx.send(true);
+    watering_system_task.abort();
+}
@@ -0,0 +1,36 @@
+use axum::extract::{Query, State};
+use chrono::{TimeZone, Utc};
+use hyper::StatusCode;
+use nic::{
+    api::{import_from_controller, ImportQuery},
+    test::utils::{mock_cfg::mock_cfg, set_app_and_ws0},
+    watering::{ds::CtrlSignal, import::ControllerFormat, modes::Mode},
+};
+
+#[tokio::test]
+async fn an_opensprinkler_program_and_its_new_station_reach_the_state_machine() {
+    // Monday, before the window
+    let now = Utc.with_ymd_and_hms(2023, 11, 27, 6, 0, 0).unwrap().timestamp();
+    let cfg = mock_cfg();
+    let (app_state, mut ws) = set_app_and_ws0(now, Some(Mode::Auto), cfg.watering).unwrap();
+    let query = || Query(ImportQuery { format: ControllerFormat::OpenSprinkler });
+
+    let rejected = import_from_controller(query(), State(app_state.clone()), "days = mon".to_owned()).await;
+    assert_eq!(rejected.unwrap_err().status(), StatusCode::BAD_REQUEST);
+    let too_long = "[program Lawn]\ndays = mon\nstarts = 22:00\ndurations = 0, 0, 0, 0, 0, 7200\n".to_owned();
+    let rejected = import_from_controller(query(), State(app_state.clone()), too_long).await;
+    assert_eq!(rejected.unwrap_err().status(), StatusCode::BAD_REQUEST, "over the max_duration of a new sector");
+
+    let ini = "[stations]\n5 = Hedge\n[program Night]\ndays = mon\nstarts = 22:00\ndurations = 600, 0, 0, 0, 300\n";
+    let report = import_from_controller(query(), State(app_state.clone()), ini.to_owned()).await.unwrap();
+    assert_eq!((report.days, report.sectors_added.clone()), (1, vec![5]));
+
+    let mut rx = app_state.sm_rx.lock().await;
+    let CtrlSignal::SectorsAdded(sectors) = rx.try_recv().unwrap() else { panic!("expected the new sectors") };
+    ws.sm.add_sectors(sectors);
+    let CtrlSignal::ScheduleUpdate(schedule) = rx.try_recv().unwrap() else { panic!("expected the schedule") };
+    ws.sm.apply_schedule(schedule, now);
+    assert_eq!(ws.sm.sectors[&5].name, "Hedge");
+    let today: Vec<_> = ws.sm.mode_auto.daily_plan[0].0.iter().map(|sec| (sec.id, sec.start % 86400)).collect();
+    assert_eq!(today, [(1, 22 * 3600), (5, 22 * 3600 + 620)]);
+}
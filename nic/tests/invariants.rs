@@ -0,0 +1,101 @@
+use chrono::{TimeZone, Utc};
+use nic::{
+    test::utils::{mock_cfg::mock_cfg, set_app_and_ws0},
+    watering::{
+        ds::{CtrlSignal, Cycle, DailyPlan, SectorInfo, WaterSector, WeatherSignal},
+        modes::Mode,
+        state_machine::{PausedData, SMState},
+    },
+};
+use proptest::prelude::*;
+
+/// What may happen to the machine between two looks at it
+#[derive(Debug, Clone)]
+enum Step {
+    /// seconds
+    Advance(i64),
+    Weather(WeatherSignal),
+    ChgMode(Mode),
+    /// the adjustment of a new day, with its ET and rain in mm
+    Adjust(f64, f64),
+}
+
+fn step() -> impl Strategy<Value = Step> {
+    let signal = prop_oneof![
+        Just(WeatherSignal::RainStart),
+        Just(WeatherSignal::RainStop),
+        Just(WeatherSignal::WindHigh),
+        Just(WeatherSignal::WindLow),
+    ];
+    let mode = prop_oneof![Just(Mode::Auto), Just(Mode::Manual), Just(Mode::Wizard), Just(Mode::Off)];
+    prop_oneof![
+        4 => (1..7_200i64).prop_map(Step::Advance),
+        2 => signal.prop_map(Step::Weather),
+        1 => mode.prop_map(Step::ChgMode),
+        1 => (0. ..10., 0. ..20.).prop_map(|(et, rain)| Step::Adjust(et, rain)),
+    ]
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn the_invariants_hold_whatever_happens(steps in prop::collection::vec(step(), 1..60)) {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        runtime.block_on(async {
+            // Monday evening, before the window
+            let mut now = Utc.with_ymd_and_hms(2024, 6, 3, 20, 0, 0).unwrap().timestamp();
+            let (_app, mut ws) = set_app_and_ws0(now, Some(Mode::Wizard), mock_cfg().watering).unwrap();
+            ws.sm.sectors.insert(1, SectorInfo::build(1, 2.5, 1.6, 30 * 60, 0., 0.29, 0));
+            ws.sm.sectors.insert(2, SectorInfo::build(2, 1.5, 1.2, 20 * 60, 0.5, 0.5, 0));
+            ws.sm.load_plans(now);
+            for step in steps {
+                match step.clone() {
+                    Step::Advance(secs) => now += secs,
+                    Step::Weather(signal) => ws.sm.handle_signal(CtrlSignal::Weather(signal), now).await,
+                    Step::ChgMode(mode) => ws.sm.handle_signal(CtrlSignal::ChgMode(mode), now).await,
+                    Step::Adjust(et, rain) if ws.sm.cycle.is_none() => ws.sm.do_daily_adjustments(now, et / 10., rain / 10.),
+                    Step::Adjust(..) => {}
+                }
+                ws.time_provider.set(now);
+                ws.sm.update(now).await;
+                let broken = ws.sm.check_invariants();
+                prop_assert!(broken.is_empty(), "after {:?}: {:?}", step, broken);
+            }
+            Ok(())
+        })?;
+    }
+}
+
+#[test]
+fn says_what_is_broken() {
+    let now = Utc.with_ymd_and_hms(2024, 6, 3, 20, 0, 0).unwrap().timestamp();
+    let (_app, mut ws) = set_app_and_ws0(now, Some(Mode::Auto), mock_cfg().watering).unwrap();
+    assert!(ws.sm.check_invariants().is_empty());
+
+    let night = now + 2 * 3600;
+    let plan = DailyPlan(vec![WaterSector::new(2, night + 600, 600), WaterSector::new(1, night, 900)]);
+    ws.sm.mode_auto.daily_plan = vec![plan.clone()];
+    ws.sm.cycle = Some(Cycle::build(plan));
+    let state = Box::new(SMState::Watering(WaterSector::new(1, night, 900)));
+    ws.sm.state =
+        SMState::Paused(PausedData { state, signals: vec![WeatherSignal::RainStart], elapsed: 60, since: night });
+    // the auto programs aren't paused by default
+    assert_eq!(
+        ws.sm.check_invariants(),
+        [
+            "auto plans: sector 1 overlaps sector 2",
+            &format!("cycle {}: sectors out of start order", night + 600),
+            "paused by rain_start in auto, not the policy of the mode",
+        ]
+    );
+
+    // 20:00, the window opens at 22:00
+    ws.sm.current_mode = Mode::Wizard;
+    ws.sm.mode_auto.daily_plan.clear();
+    ws.sm.cycle = None;
+    ws.sm.state = SMState::Watering(WaterSector::new(1, now, 900));
+    let broken = ws.sm.check_invariants();
+    assert_eq!(broken.len(), 1);
+    assert!(broken[0].starts_with("sector 1 started at") && broken[0].ends_with("out of the window"), "{:?}", broken);
+}
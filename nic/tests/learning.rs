@@ -0,0 +1,67 @@
+use axum::extract::{Path, State};
+use axum::Json;
+use chrono::{TimeZone, Utc};
+use hyper::StatusCode;
+use nic::{
+    api::{add_moisture_reading, get_learned_params, resolve_learned_params, MoistureRequest},
+    test::utils::{mock_cfg::mock_cfg, set_app_and_ws0},
+    watering::{
+        ds::CtrlSignal,
+        learning::{LearnedParams, LearnedStatus},
+        modes::Mode,
+    },
+};
+
+#[tokio::test]
+async fn an_accepted_estimate_reaches_the_sector() {
+    let now = Utc.with_ymd_and_hms(2024, 6, 3, 12, 0, 0).unwrap().timestamp();
+    let cfg = mock_cfg();
+    let (app_state, mut ws) = set_app_and_ws0(now, Some(Mode::Wizard), cfg.watering).unwrap();
+
+    let reading = |water_cm| Json(MoistureRequest { timestamp: None, water_cm });
+    let stored = add_moisture_reading(Path(1), State(app_state.clone()), reading(3.5)).await.unwrap();
+    assert_eq!(stored.0, "Reading of sector 1 stored");
+    let rejected = add_moisture_reading(Path(1), State(app_state.clone()), reading(-1.)).await;
+    assert_eq!(rejected.unwrap_err().status(), StatusCode::BAD_REQUEST);
+    let unknown = add_moisture_reading(Path(99), State(app_state.clone()), reading(3.5)).await;
+    assert_eq!(unknown.unwrap_err().status(), StatusCode::NOT_FOUND);
+    assert_eq!(app_state.db.load_moisture_readings(now, now + 1).unwrap().len(), 1);
+    assert_eq!(app_state.db.load_moisture_readings(now, now + 1).unwrap()[0].water_cm, 3.5);
+
+    let sector = &ws.sm.sectors[&1];
+    let estimate = LearnedParams {
+        sector: 1,
+        learned_at: now,
+        sprinkler_debit: sector.sprinkler_debit * 1.2,
+        percolation_rate: 0.3,
+        previous_debit: sector.sprinkler_debit,
+        previous_percolation: sector.percolation_rate,
+        samples: 12,
+        from: now - 28 * 86_400,
+        to: now,
+        rmse: 0.04,
+        status: LearnedStatus::Pending,
+    };
+    app_state.db.store_learned_params(vec![estimate.clone()]).unwrap();
+    let listed = get_learned_params(State(app_state.clone())).await.unwrap().0;
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0], estimate);
+
+    let path = |sector, action: &str| Path((sector, action.to_owned()));
+    let bad = resolve_learned_params(path(1, "apply"), State(app_state.clone())).await;
+    assert_eq!(bad.unwrap_err().status(), StatusCode::BAD_REQUEST);
+    let none = resolve_learned_params(path(2, "accept"), State(app_state.clone())).await;
+    assert_eq!(none.unwrap_err().status(), StatusCode::NOT_FOUND);
+    let unknown = resolve_learned_params(path(99, "accept"), State(app_state.clone())).await;
+    assert_eq!(unknown.unwrap_err().to_string(), "no sector 99");
+
+    let accepted = resolve_learned_params(path(1, "accept"), State(app_state.clone())).await.unwrap();
+    assert_eq!(accepted.0.status, LearnedStatus::Accepted);
+    let signal = app_state.sm_rx.lock().await.try_recv().unwrap();
+    let CtrlSignal::LearnedAccepted(learned) = signal else {
+        panic!("expected the accepted estimate, got {:?}", signal);
+    };
+    ws.sm.apply_learned(&learned, now);
+    let sector = &ws.sm.sectors[&1];
+    assert_eq!((sector.sprinkler_debit, sector.percolation_rate), (estimate.sprinkler_debit, 0.3));
+}
@@ -0,0 +1,41 @@
+use axum::{extract::State, Json};
+use chrono::{TimeZone, Utc};
+use hyper::StatusCode;
+use nic::{
+    api::{manual_start, ManualSector, ManualStartRequest},
+    test::utils::{mock_cfg::mock_cfg, set_app_and_ws0},
+    watering::{modes::Mode, state_machine::SMState},
+};
+
+#[tokio::test]
+async fn waters_the_sectors_asked_for_in_manual() {
+    let now = Utc.with_ymd_and_hms(2023, 11, 27, 4, 0, 0).unwrap().timestamp();
+    let cfg = mock_cfg();
+    let (app_state, mut ws) = set_app_and_ws0(now, Some(Mode::Auto), cfg.watering).unwrap();
+    ws.sm.cfg.valve_check_secs = 0;
+    assert!(ws.sm.start_manual(vec![(2, 60)], now).await.is_err(), "only in manual");
+
+    ws.sm.trans_change_mode(Mode::Manual, now).await;
+    ws.sm.faulted.insert(3);
+    assert!(ws.sm.start_manual(vec![(3, 60)], now).await.is_err(), "faulted");
+    assert!(ws.sm.start_manual(vec![(9, 60)], now).await.is_err(), "no such sector");
+    let progress = ws.sm.sectors[&2].progress;
+
+    let cycle = ws.sm.start_manual(vec![(2, 60), (1, 120)], now).await.unwrap();
+    let plan: Vec<_> = ws.sm.cycle.as_ref().unwrap().daily_plan.0.iter().map(|sec| (sec.id, sec.start - now)).collect();
+    assert_eq!((cycle, plan), (now, vec![(2, 0), (1, 80)]));
+    assert!(ws.sm.start_manual(vec![(2, 60)], now + 1).await.is_err(), "one at a time");
+
+    let mut t = now;
+    while ws.sm.state != SMState::Idle && t < now + 600 {
+        t += 10;
+        ws.sm.update(t).await;
+    }
+    assert_eq!((ws.sm.state.clone(), ws.sm.current_mode), (SMState::Idle, Mode::Manual));
+    // 60 secs at 1 cm/h
+    assert!((ws.sm.sectors[&2].progress - progress - 1. / 60.).abs() < 1e-9);
+
+    let request = Json(ManualStartRequest { sectors: vec![ManualSector { sector: 2, duration_secs: 0 }] });
+    let rejected = manual_start(State(app_state), request).await;
+    assert_eq!(rejected.unwrap_err().status(), StatusCode::BAD_REQUEST);
+}
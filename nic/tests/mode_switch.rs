@@ -1,47 +1,124 @@
 use nic::{
     test::utils::{mock_cfg::mock_cfg, set_app_and_ws0},
-    watering::modes::Mode,
+    utils::sod,
+    watering::{
+        ds::{CtrlSignal, DailyPlan, StateChange, StateEvent, WaterSector},
+        modes::Mode,
+        state_machine::SMState,
+    },
 };
 
-#[test]
-fn mode_switching() {
+#[tokio::test]
+async fn mode_switching() {
     let cfg = mock_cfg();
     let (_app, mut ws) = set_app_and_ws0(0, None, cfg.watering).unwrap();
     assert_eq!(ws.sm.current_mode, Mode::Auto);
 
-    ws.sm.trans_change_mode(Mode::Manual);
+    ws.sm.trans_change_mode(Mode::Manual, 0).await;
     assert_eq!(ws.sm.current_mode, Mode::Manual);
 }
 
-#[test]
-fn all_mode_transitions() {
+#[tokio::test]
+async fn all_mode_transitions() {
     let cfg = mock_cfg();
     let (_app, mut ws) = set_app_and_ws0(0, None, cfg.watering).unwrap();
     // Initially in Auto mode
     assert_eq!(ws.sm.current_mode, Mode::Auto);
 
     // Transition from Auto -> Manual
-    ws.sm.trans_change_mode(Mode::Manual);
+    ws.sm.trans_change_mode(Mode::Manual, 0).await;
     assert_eq!(ws.sm.current_mode, Mode::Manual);
 
     // Transition from Manual -> Wizard
-    ws.sm.trans_change_mode(Mode::Wizard);
+    ws.sm.trans_change_mode(Mode::Wizard, 0).await;
     assert_eq!(ws.sm.current_mode, Mode::Wizard);
 
     // Transition from Wizard -> Auto
-    ws.sm.trans_change_mode(Mode::Auto);
+    ws.sm.trans_change_mode(Mode::Auto, 0).await;
     assert_eq!(ws.sm.current_mode, Mode::Auto);
 
     // Additional transitions to verify no unexpected behavior:
     // Auto -> Wizard
-    ws.sm.trans_change_mode(Mode::Wizard);
+    ws.sm.trans_change_mode(Mode::Wizard, 0).await;
     assert_eq!(ws.sm.current_mode, Mode::Wizard);
 
     // Wizard -> Manual
-    ws.sm.trans_change_mode(Mode::Manual);
+    ws.sm.trans_change_mode(Mode::Manual, 0).await;
     assert_eq!(ws.sm.current_mode, Mode::Manual);
 
     // Manual -> Auto
-    ws.sm.trans_change_mode(Mode::Auto);
+    ws.sm.trans_change_mode(Mode::Auto, 0).await;
     assert_eq!(ws.sm.current_mode, Mode::Auto);
 }
+
+#[tokio::test]
+async fn mode_change_stops_active_watering() {
+    let ref_time = sod(chrono::Utc::now().timestamp());
+    let cfg = mock_cfg();
+    let (app, mut ws) = set_app_and_ws0(ref_time, Some(Mode::Wizard), cfg.watering).unwrap();
+    let mut web_rx = app.web_tx.subscribe();
+
+    let start = ws.sm.timeframe.day_start_time;
+    ws.sm.mode_wizard.daily_plan = vec![DailyPlan(vec![WaterSector::new(1, start, 30 * 60)])];
+    ws.sm.trans_watering(start).await;
+    assert!(ws.sm.state.is_watering());
+
+    ws.sm.handle_signal(CtrlSignal::ChgMode(Mode::Manual), start + 60).await;
+    assert_eq!(ws.sm.state, SMState::Idle);
+    assert!(ws.sm.cycle.is_none());
+    assert!(ws.sm.mode_wizard.daily_plan.is_empty());
+    let expected = StateEvent {
+        timestamp: start + 60,
+        sector: Some(1),
+        cycle: Some(start),
+        change: StateChange::ModeChanged { from: Mode::Wizard, to: Mode::Manual },
+    };
+    let mut kinds = vec![];
+    loop {
+        match web_rx.try_recv() {
+            Ok(CtrlSignal::StateChanged(evt)) if evt.change.kind() == "mode_changed" => {
+                break assert_eq!(evt, expected)
+            }
+            Ok(CtrlSignal::StateChanged(evt)) => kinds.push(evt.change.kind()),
+            Ok(_) => continue,
+            Err(e) => panic!("no mode change event: {:?}", e),
+        }
+    }
+    // the sector is closed and the cycle dropped before the mode changes
+    assert_eq!(kinds[kinds.len() - 2..], ["sector_deactivated", "cycle_aborted"]);
+    assert_eq!(
+        serde_json::to_value(&expected).unwrap(),
+        serde_json::json!({"timestamp": start + 60, "sector": 1, "cycle": start, "kind": "mode_changed", "from": "wizard", "to": "manual"})
+    );
+}
+
+#[tokio::test]
+async fn off_mode_waters_nothing() {
+    let ref_time = sod(chrono::Utc::now().timestamp());
+    let cfg = mock_cfg();
+    let (_app, mut ws) = set_app_and_ws0(ref_time, Some(Mode::Wizard), cfg.watering).unwrap();
+
+    let start = ws.sm.timeframe.day_start_time;
+    ws.sm.mode_wizard.daily_plan = vec![DailyPlan(vec![WaterSector::new(1, start, 30 * 60)])];
+    ws.sm.update(start).await;
+    assert!(ws.sm.state.is_watering());
+
+    ws.sm.handle_signal(CtrlSignal::ChgMode("off".parse().unwrap()), start + 60).await;
+    assert_eq!(ws.sm.current_mode, Mode::Off);
+    assert_eq!(ws.sm.state, SMState::Idle);
+    assert!(ws.sm.mode_wizard.daily_plan.is_empty() && ws.sm.mode_auto.daily_plan.is_empty());
+    assert_eq!(ws.sm.db.load_mode(), Some(Mode::Off), "survives a restart");
+
+    ws.sm.do_daily_adjustments(start + 120, 0.5, 0.);
+    assert!(ws.sm.mode_wizard.daily_plan.is_empty(), "no plans while off");
+    for time in (start + 120..start + 3600).step_by(60) {
+        ws.sm.update(time).await;
+        assert_eq!(ws.sm.state, SMState::Idle);
+    }
+
+    ws.sm.handle_signal(CtrlSignal::ChgMode(Mode::Wizard), start + 3600).await;
+    assert_eq!(ws.sm.db.load_mode(), Some(Mode::Wizard));
+    let events = ws.sm.db.load_system_events(start + 3600, start + 3601).unwrap();
+    let kinds: Vec<&str> = events.iter().map(|evt| evt.kind.as_str()).collect();
+    assert_eq!(kinds, ["mode_changed", "plan_recalculated"], "plans are loaded again when switching on");
+}
@@ -24,7 +24,7 @@ async fn execute_wizard_mode() {
     // Execute wizard mode
     ws.time_provider.advance_time(3600).await;
     let now = ws.time_provider.now();
-    ws.sm.update(now);
+    ws.sm.update(now).await;
 
     // Assert state transitions
     assert!(ws.sm.cycle.is_some()); // A cycle should be active
@@ -48,3 +48,20 @@ fn handle_daily_adjustments() {
     assert_eq!(ws.sm.sectors[&1].progress, 0.6); // Adjusted for ET and rain
     assert_eq!(ws.sm.sectors[&2].progress, 0.6);
 }
+
+#[test]
+fn sessions_are_capped_by_the_config() {
+    let ref_time = Utc.with_ymd_and_hms(2024, 12, 14, 12, 0, 0).unwrap().timestamp(); // Saturday, last day of the week
+    let mut cfg = mock_cfg();
+    cfg.watering.max_duration_secs = 600;
+    let (_app, mut ws) = set_app_and_ws0(ref_time, Some(Mode::Wizard), cfg.watering).unwrap();
+    ws.sm.sectors.clear();
+    ws.sm.sectors.insert(1, SectorInfo::build(1, 2.5, 1.0, 30 * 60, 0., 0., 0));
+
+    ws.sm.load_plans(ref_time);
+
+    let planned: Vec<i64> =
+        ws.sm.mode_wizard.daily_plan.iter().flat_map(|plan| plan.0.iter().map(|sec| sec.duration)).collect();
+    assert!(!planned.is_empty());
+    assert!(planned.iter().all(|&duration| duration == 600), "{:?}", planned);
+}
@@ -0,0 +1,119 @@
+use chrono::{TimeZone, Utc};
+use nic::{
+    config::{Config, SectorCfg, WindowOverrideCfg},
+    db::{Database, DatabaseTrait},
+    test::utils::{
+        mock_cfg::mock_cfg, mock_db::new_with_mock, mock_sensors::set_sensor_controller0, mock_time::MockTimeProvider,
+    },
+    watering::{
+        ds::{DailyPlan, WaterSector},
+        modes::Mode,
+        state_machine::SMState,
+        watering_system::WateringSystem,
+    },
+};
+use std::sync::Arc;
+
+fn start(db: &Arc<Database>, now: i64) -> WateringSystem {
+    start_with(db, now, vec![])
+}
+
+/// `start`, with the window overrides in the running config
+fn start_with(db: &Arc<Database>, now: i64, window_overrides: Vec<WindowOverrideCfg>) -> WateringSystem {
+    let cfg = mock_cfg();
+    let app_state = new_with_mock(db.clone(), set_sensor_controller0(), Arc::new(MockTimeProvider::new(now))).unwrap();
+    app_state.config.apply(Config { window_overrides, ..cfg.clone() });
+    let mut ws = WateringSystem::new(app_state, Some(Mode::Wizard), now, cfg.watering).unwrap();
+    ws.sm.cfg.valve_check_secs = 0;
+    ws
+}
+
+fn lawn() -> SectorCfg {
+    SectorCfg {
+        id: 1,
+        name: "lawn".to_owned(),
+        sprinkler_debit: 1.0,
+        percolation_rate: 0.5,
+        weekly_target: 2.5,
+        max_duration: 3600,
+        ignore_weather_pause: false,
+        max_daily_mm: None,
+        max_daily_minutes: None,
+        deficit_exempt: false,
+    }
+}
+
+#[tokio::test]
+async fn the_sector_goes_on_after_a_power_cut() {
+    let now = Utc.with_ymd_and_hms(2024, 6, 3, 21, 0, 0).unwrap().timestamp();
+    let db = Arc::new(Database::new(":memory:").unwrap());
+    db.import_sectors(vec![lawn()]).unwrap();
+    let mut ws = start(&db, now);
+
+    // an hour from 23:30, the power goes at 00:10
+    let window_start = ws.sm.timeframe.day_start_time;
+    let sec_start = window_start + 5400;
+    ws.sm.mode_wizard.daily_plan = vec![DailyPlan(vec![WaterSector::new(1, sec_start, 3600)])];
+    let mut t = sec_start;
+    while t <= sec_start + 2400 {
+        ws.sm.update(t).await;
+        t += 10;
+    }
+    assert!(ws.sm.state.is_watering());
+    drop(ws);
+
+    // back at 00:25, in the window that opened yesterday
+    let restart = sec_start + 3300;
+    let mut ws = start(&db, restart);
+    assert!(ws.sm.state.is_paused());
+    assert_eq!(ws.sm.timeframe.day_start_time, window_start);
+    ws.sm.update(restart).await;
+    let SMState::Watering(sec) = ws.sm.state else { panic!("should be watering") };
+    assert_eq!((sec.id, sec.start, sec.duration), (1, restart, 1200), "the 20 minutes left");
+
+    // a cut that lasts past the window loses what was left of it
+    ws.sm.update(restart + 600).await;
+    drop(ws);
+    let mut ws = start(&db, window_start + 9 * 3600);
+    assert_eq!(ws.sm.state, SMState::Idle);
+    assert!(ws.sm.cycle.is_none() && db.load_resume_point().is_none());
+    ws.sm.update(window_start + 9 * 3600).await;
+    assert_eq!(ws.sm.state, SMState::Idle);
+}
+
+#[tokio::test]
+async fn the_sector_goes_on_after_a_power_cut_in_a_longer_window() {
+    let now = Utc.with_ymd_and_hms(2024, 6, 3, 21, 0, 0).unwrap().timestamp();
+    let db = Arc::new(Database::new(":memory:").unwrap());
+    db.import_sectors(vec![lawn()]).unwrap();
+    // mondays the window opened at 22:00 lasts 10 hours instead of 8
+    let longer = || {
+        let monday =
+            WindowOverrideCfg { weekdays: vec!["mon".to_owned()], duration_hours: Some(10), ..Default::default() };
+        vec![monday]
+    };
+    let mut ws = start_with(&db, now, longer());
+
+    // an hour from 05:30, the power goes at 06:10, past the end of the usual window
+    let window_start = ws.sm.timeframe.day_start_time;
+    assert_eq!(ws.sm.timeframe.day_end_time, window_start + 10 * 3600 - 1);
+    let sec_start = window_start + 7 * 3600 + 1800;
+    ws.sm.mode_wizard.daily_plan = vec![DailyPlan(vec![WaterSector::new(1, sec_start, 3600)])];
+    let mut t = sec_start;
+    while t <= sec_start + 2400 {
+        ws.sm.update(t).await;
+        t += 10;
+    }
+    assert!(ws.sm.state.is_watering());
+    drop(ws);
+
+    // back at 06:25, still in monday's window
+    let restart = sec_start + 3300;
+    let mut ws = start_with(&db, restart, longer());
+    assert!(ws.sm.state.is_paused());
+    assert_eq!(ws.sm.timeframe.day_start_time, window_start);
+    assert!(db.load_resume_point().is_some());
+    ws.sm.update(restart).await;
+    let SMState::Watering(sec) = ws.sm.state else { panic!("should be watering") };
+    assert_eq!((sec.id, sec.start, sec.duration), (1, restart, 1200), "the 20 minutes left");
+}
@@ -0,0 +1,122 @@
+use chrono::{TimeZone, Utc};
+use nic::{
+    config::SectorCfg,
+    db::{Database, DatabaseTrait},
+    test::utils::{
+        mock_cfg::mock_cfg, mock_db::new_with_mock, mock_sensors::set_sensor_controller0, mock_time::MockTimeProvider,
+    },
+    utils::sod,
+    watering::{
+        ds::{DailyPlan, WaterSector},
+        modes::Mode,
+        watering_system::{run_watering_system, WateringSystem},
+    },
+};
+use std::sync::Arc;
+
+fn sector(id: u32) -> SectorCfg {
+    SectorCfg {
+        id,
+        name: format!("zone {}", id),
+        sprinkler_debit: 1.0,
+        percolation_rate: 0.,
+        weekly_target: 2.5,
+        max_duration: 1800,
+        ignore_weather_pause: false,
+        max_daily_mm: None,
+        max_daily_minutes: None,
+        deficit_exempt: false,
+    }
+}
+
+/// The progress of sector 1 once the machine is back at `now`, `progress_day` the last adjustment before it stopped
+fn restarted(progress_day: i64, now: i64) -> f64 {
+    let db = Arc::new(Database::new(":memory:").unwrap());
+    db.import_sectors(vec![sector(1)]).unwrap();
+    db.add_sector_progress(1, 1.5, progress_day + 3600).unwrap();
+    db.store_progress_day(progress_day).unwrap();
+    let app_state = new_with_mock(db, set_sensor_controller0(), Arc::new(MockTimeProvider::new(now))).unwrap();
+    let ws = WateringSystem::new(app_state, Some(Mode::Wizard), now, mock_cfg().watering).unwrap();
+    ws.sm.sectors[&1].progress
+}
+
+#[tokio::test]
+async fn the_progress_of_the_week_survives_a_restart() {
+    // a wednesday, just after midnight
+    let now = Utc.with_ymd_and_hms(2024, 6, 5, 0, 10, 0).unwrap().timestamp();
+    let day = 86_400;
+    assert_eq!(restarted(sod(now), now), 1.5);
+    assert_eq!(restarted(sod(now) - day, now), 1.5, "down over midnight");
+    assert_eq!(restarted(sod(now) - 3 * day, now), 0., "the sunday before, a new week");
+    let saturday = Utc.with_ymd_and_hms(2024, 6, 8, 12, 0, 0).unwrap().timestamp();
+    assert_eq!(restarted(sod(now), saturday), 0., "down three days");
+}
+
+#[tokio::test]
+async fn starting_over_is_saved_for_the_next_restart() {
+    // a wednesday, the progress of the week before is still in the database
+    let now = Utc.with_ymd_and_hms(2024, 6, 5, 0, 10, 0).unwrap().timestamp();
+    let db = Arc::new(Database::new(":memory:").unwrap());
+    db.import_sectors(vec![sector(1)]).unwrap();
+    db.add_sector_progress(1, 1.5, 0).unwrap();
+    db.store_progress_day(sod(now) - 7 * 86_400).unwrap();
+    let restart = |now: i64| {
+        let app_state =
+            new_with_mock(db.clone(), set_sensor_controller0(), Arc::new(MockTimeProvider::new(now))).unwrap();
+        WateringSystem::new(app_state, Some(Mode::Wizard), now, mock_cfg().watering).unwrap()
+    };
+
+    let mut ws = restart(now);
+    assert_eq!(ws.sm.sectors[&1].progress, 0.);
+    // watered, then adjusted after the window, and down again
+    db.add_sector_progress(1, 0.5, now + 3600).unwrap();
+    let eight = sod(now) + 8 * 3600;
+    ws.sm.do_daily_adjustments(eight, 0., 0.);
+    assert_eq!(restart(eight + 3600).sm.sectors[&1].progress, 0.5, "the new water, not the old week's on top");
+}
+
+#[tokio::test]
+async fn the_daily_adjustment_is_saved_with_its_day() {
+    let now = Utc.with_ymd_and_hms(2024, 6, 5, 0, 0, 0).unwrap().timestamp();
+    let db = Arc::new(Database::new(":memory:").unwrap());
+    db.import_sectors(vec![sector(1), sector(2)]).unwrap();
+    db.add_sector_progress(1, 2., 0).unwrap();
+    db.add_sector_progress(2, 1., 0).unwrap();
+    db.store_progress_day(now - 86_400).unwrap();
+    let app_state = new_with_mock(db.clone(), set_sensor_controller0(), Arc::new(MockTimeProvider::new(now))).unwrap();
+    let mut ws = WateringSystem::new(app_state, Some(Mode::Wizard), now, mock_cfg().watering).unwrap();
+    ws.sm.do_daily_adjustments(now, 0.5, 0.);
+
+    assert_eq!(db.load_progress_day(), Some(now));
+    // written with the day, not left for the next batch
+    let stored: Vec<f64> = db.load_sectors().unwrap().iter().map(|sec| sec.progress).collect();
+    assert_eq!(stored, [1.5, 0.5]);
+}
+
+#[tokio::test]
+async fn the_adjustments_wait_for_the_window_to_close_and_the_cycle() {
+    // a wednesday in the window, which closes at 06:00
+    let now = Utc.with_ymd_and_hms(2024, 6, 5, 0, 10, 0).unwrap().timestamp();
+    let db = Arc::new(Database::new(":memory:").unwrap());
+    db.import_sectors(vec![sector(1)]).unwrap();
+    db.store_progress_day(sod(now) - 86_400).unwrap();
+    let app_state = new_with_mock(db.clone(), set_sensor_controller0(), Arc::new(MockTimeProvider::new(now))).unwrap();
+    let cfg = mock_cfg().watering;
+    let mut ws = WateringSystem::new(app_state.clone(), Some(Mode::Manual), now, cfg).unwrap();
+    let (_stop_tx, stop_rx) = tokio::sync::watch::channel(false);
+
+    let five = sod(now) + 5 * 3600;
+    run_watering_system(app_state.clone(), None, stop_rx.clone(), Some(five), Some(&mut ws), cfg).await.unwrap();
+    assert_eq!(db.load_progress_day(), Some(sod(now) - 86_400), "not mid-window");
+
+    // a manual cycle running past the window
+    ws.sm.cycle = DailyPlan(vec![WaterSector::new(1, five, 3 * 3600)]).get_cycle(five);
+    let seven = sod(now) + 7 * 3600;
+    run_watering_system(app_state.clone(), None, stop_rx.clone(), Some(seven), Some(&mut ws), cfg).await.unwrap();
+    assert_eq!(db.load_progress_day(), Some(sod(now) - 86_400), "not mid-cycle");
+
+    ws.sm.cycle = None;
+    let eight = sod(now) + 8 * 3600;
+    run_watering_system(app_state, None, stop_rx, Some(eight), Some(&mut ws), cfg).await.unwrap();
+    assert_eq!(db.load_progress_day(), Some(sod(now)));
+}
@@ -0,0 +1,44 @@
+use hyper::StatusCode;
+use nic::{api::run_web_server, error::ErrorBody, test::utils::set_app_state};
+
+#[tokio::test]
+async fn a_client_that_calls_too_often_is_held_back() {
+    let app_state = set_app_state(1_700_000_000);
+    let cfg = app_state.config.current().web_server.clone();
+    let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+    let addr = "127.0.0.1:3030";
+    let app_state_clone = app_state.clone();
+    let server_task = tokio::spawn(async move {
+        _ = run_web_server(app_state_clone, addr.parse().unwrap(), shutdown_rx).await;
+    });
+    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+    let client = reqwest::Client::new();
+
+    // the defaults: a burst of 10 calls, then 2 a second
+    for _ in 0..cfg.control_burst {
+        let response = client.post(format!("http://{}/api/v1/switch/off", addr)).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    let response = client.post(format!("http://{}/api/v1/switch/off", addr)).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    let error: ErrorBody = response.json().await.unwrap();
+    assert_eq!(error.status, 429);
+    // the reads aren't limited
+    let response = client.get(format!("http://{}/api/v1/interlock", addr)).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    // nor the emergency stop, with the bucket still empty
+    for path in ["/api/v1/estop", "/api/v1/estop/clear", "/estop"] {
+        let response = client.post(format!("http://{}{}", addr, path)).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK, "{}", path);
+    }
+
+    // a schedule bigger than `max_upload_bytes` isn't read
+    tokio::time::sleep(tokio::time::Duration::from_millis(600)).await;
+    let body = "mon,1,22:00,600\n".repeat(cfg.max_upload_bytes / 16 + 1);
+    let url = format!("http://{}/api/v1/schedule/auto?format=csv", addr);
+    let response = client.put(url).body(body).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    _ = shutdown_tx.send(true);
+    server_task.abort();
+}
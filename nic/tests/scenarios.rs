@@ -0,0 +1,23 @@
+#![cfg(feature = "sim")]
+
+use nic::{
+    config::Config,
+    simulation::{simulate, Scenario},
+};
+use std::{fs, path::Path};
+
+/// Every scenario of `tests/scenarios` is run for its `days`, and must meet its expectations
+#[tokio::test]
+async fn the_scenarios_meet_their_expectations() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/scenarios");
+    let mut files: Vec<_> = fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().path()).collect();
+    files.sort();
+    assert!(!files.is_empty());
+    for file in files {
+        let mut cfg = Config::default();
+        cfg.database.name = "/nonexistent/nic.db".to_owned();
+        let scenario = Scenario::load(&file).unwrap();
+        let report = simulate(&cfg, &scenario, 14, None).await.unwrap();
+        assert!(report.violations.is_empty(), "{}\n{}", file.display(), report);
+    }
+}
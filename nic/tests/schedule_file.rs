@@ -0,0 +1,145 @@
+use axum::extract::Path;
+use axum::extract::{Query, State};
+use chrono::{TimeZone, Utc};
+use hyper::StatusCode;
+use nic::{
+    api::{import_schedule, list_programs, set_program, ScheduleQuery},
+    db::{Database, DatabaseTrait},
+    test::utils::{
+        mock_cfg::mock_cfg, mock_db::new_with_mock, mock_sensors::set_sensor_controller0, mock_time::MockTimeProvider,
+        set_app_and_ws0,
+    },
+    watering::{
+        ds::{CtrlSignal, DailyPlan},
+        modes::Mode,
+        schedule_file::{import, ScheduleFormat},
+        watering_alg::SectorConstraint,
+        watering_system::WateringSystem,
+    },
+};
+use std::sync::Arc;
+
+#[tokio::test]
+async fn imported_schedule_reaches_the_state_machine() {
+    // Monday, before the window
+    let now = Utc.with_ymd_and_hms(2023, 11, 27, 6, 0, 0).unwrap().timestamp();
+    let cfg = mock_cfg();
+    let (app_state, mut ws) = set_app_and_ws0(now, Some(Mode::Auto), cfg.watering).unwrap();
+    let csv = Query(ScheduleQuery { format: ScheduleFormat::Csv });
+
+    let rejected = import_schedule(csv, State(app_state.clone()), "mon,1,22:00".to_owned()).await;
+    assert_eq!(rejected.unwrap_err().status(), StatusCode::BAD_REQUEST);
+
+    let csv = Query(ScheduleQuery { format: ScheduleFormat::Csv });
+    let body = "day,sector,start,duration\nmon,2,23:00,600\nmon,1,22:00,900\n".to_owned();
+    let imported = import_schedule(csv, State(app_state.clone()), body).await.unwrap();
+    assert_eq!(imported.0, "Schedule imported, 1 day(s)");
+    let signal = app_state.sm_rx.lock().await.try_recv().unwrap();
+    let CtrlSignal::ScheduleUpdate(schedule) = signal else {
+        panic!("expected a schedule update, got {:?}", signal);
+    };
+
+    ws.sm.apply_schedule(schedule, now);
+    let today = &ws.sm.mode_auto.daily_plan[0];
+    let sectors: Vec<_> = today.0.iter().map(|sec| (sec.id, sec.duration)).collect();
+    assert_eq!(sectors, [(1, 900), (2, 600)]);
+}
+
+#[tokio::test]
+async fn a_disabled_program_leaves_the_day() {
+    // Monday at the adjustments, programs A at 06:00, B at 08:00 and C at 09:00
+    let now = Utc.with_ymd_and_hms(2023, 11, 27, 6, 0, 0).unwrap().timestamp();
+    let cfg = mock_cfg();
+    let (app_state, mut ws) = set_app_and_ws0(now, Some(Mode::Auto), cfg.watering).unwrap();
+    assert_eq!(ws.sm.mode_auto.daily_plan.len(), 3);
+    let programs = list_programs(State(app_state.clone())).await.unwrap();
+    assert_eq!(programs.0.into_iter().filter(|(_, enabled)| *enabled).count(), 3);
+
+    let path = |program: &str, action: &str| Path((program.to_owned(), action.to_owned()));
+    let unknown = set_program(path("Z", "disable"), State(app_state.clone())).await;
+    assert_eq!(unknown.unwrap_err().status(), StatusCode::NOT_FOUND);
+    let bad = set_program(path("B", "pause"), State(app_state.clone())).await;
+    assert_eq!(bad.unwrap_err().status(), StatusCode::BAD_REQUEST);
+
+    let done = set_program(path("B", "disable"), State(app_state.clone())).await.unwrap();
+    assert_eq!(done.0, "Program B disabled");
+    let signal = app_state.sm_rx.lock().await.try_recv().unwrap();
+    let CtrlSignal::ScheduleUpdate(schedule) = signal else {
+        panic!("expected a schedule update, got {:?}", signal);
+    };
+    ws.sm.apply_schedule(schedule, now);
+    let sectors: Vec<Vec<u32>> =
+        ws.sm.mode_auto.daily_plan.iter().map(|plan| plan.0.iter().map(|sec| sec.id).collect()).collect();
+    assert_eq!(sectors, [vec![1, 2], vec![4]]);
+}
+
+#[tokio::test]
+async fn overlapping_programs_run_one_after_the_other() {
+    let now = Utc.with_ymd_and_hms(2023, 11, 27, 6, 0, 0).unwrap().timestamp();
+    let day = Utc.with_ymd_and_hms(2023, 11, 27, 0, 0, 0).unwrap().timestamp();
+    let mut cfg = mock_cfg();
+    cfg.watering.sector_transation_secs = 20;
+    let (_app_state, mut ws) = set_app_and_ws0(now, Some(Mode::Auto), cfg.watering).unwrap();
+    // B would start while A is still on sector 2
+    let csv = "mon,1,06:00,600,A\nmon,2,06:10,600,A\nmon,3,06:15,300,B\nmon,4,07:00,300,C";
+    ws.sm.apply_schedule(import(csv, ScheduleFormat::Csv).unwrap(), now);
+
+    let starts = |plans: &[DailyPlan]| -> Vec<Vec<i64>> {
+        plans.iter().map(|plan| plan.0.iter().map(|sec| sec.start - day).collect()).collect()
+    };
+    assert_eq!(
+        starts(&ws.sm.mode_auto.daily_plan),
+        [vec![6 * 3600, 6 * 3600 + 600], vec![6 * 3600 + 1220], vec![7 * 3600]]
+    );
+
+    // a slower valve
+    ws.sm.cfg.sector_transation_secs = 60;
+    ws.sm.load_plans(now);
+    assert_eq!(starts(&ws.sm.mode_auto.daily_plan)[1], [6 * 3600 + 1260]);
+}
+
+#[tokio::test]
+async fn a_schedule_breaking_the_constraints_is_rejected() {
+    let now = Utc.with_ymd_and_hms(2023, 11, 27, 5, 0, 0).unwrap().timestamp();
+    let cfg = mock_cfg();
+    let (app_state, _ws) = set_app_and_ws0(now, Some(Mode::Auto), cfg.watering).unwrap();
+    // a shared branch, it needs 6 hours to build the pressure back up
+    app_state.db.import_sector_constraints(vec![SectorConstraint { sectors: (3, 4), min_gap_secs: 6 * 3600 }]).unwrap();
+
+    let csv = || Query(ScheduleQuery { format: ScheduleFormat::Csv });
+    let body = "mon,3,06:00,600\nmon,4,10:00,600\n".to_owned();
+    let e = import_schedule(csv(), State(app_state.clone()), body).await.unwrap_err();
+    assert_eq!(e.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(e.to_string(), "sector 3 at Mon 06:00 and sector 4 at Mon 10:00 must be at least 21600s apart");
+
+    let body = "mon,3,06:00,600\nmon,4,22:00,600\n".to_owned();
+    assert!(import_schedule(csv(), State(app_state.clone()), body).await.is_ok());
+}
+
+#[tokio::test]
+async fn a_schedule_that_makes_no_sense_is_rejected_and_left_out_on_load() {
+    // Monday, before the window
+    let now = Utc.with_ymd_and_hms(2023, 11, 27, 6, 0, 0).unwrap().timestamp();
+    let cfg = mock_cfg();
+    let (app_state, _ws) = set_app_and_ws0(now, Some(Mode::Auto), cfg.watering).unwrap();
+
+    let csv = || Query(ScheduleQuery { format: ScheduleFormat::Csv });
+    let body = "mon,1,22:00,1800\nmon,2,22:15,600\nmon,1,22:00,600\n".to_owned();
+    let e = import_schedule(csv(), State(app_state.clone()), body).await.unwrap_err();
+    assert_eq!(e.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(
+        e.to_string(),
+        "A Mon 22:00 sector 1: the sector is already scheduled then; \
+         A Mon 22:15 sector 2: overlaps the run from 22:00 to 22:30 of the program"
+    );
+
+    // overlapping rows stored before the checks, the table keeps the keys unique, only the first is watered
+    let db = Arc::new(Database::new(":memory:").unwrap());
+    db.import_sectors(cfg.sectors.clone()).unwrap();
+    let legacy = "mon,1,22:00,1800\nmon,2,22:15,600\n";
+    db.save_auto_schedule(import(legacy, ScheduleFormat::Csv).unwrap()).unwrap();
+    let app_state = new_with_mock(db, set_sensor_controller0(), Arc::new(MockTimeProvider::new(now))).unwrap();
+    let ws = WateringSystem::new(app_state, Some(Mode::Auto), now, cfg.watering).unwrap();
+    let today: Vec<_> = ws.sm.mode_auto.daily_plan.iter().flat_map(|plan| plan.0.iter().map(|sec| sec.id)).collect();
+    assert_eq!(today, [1]);
+}
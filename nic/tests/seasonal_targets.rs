@@ -0,0 +1,49 @@
+use axum::extract::{Path, State};
+use axum::Json;
+use chrono::{TimeZone, Utc};
+use hyper::StatusCode;
+use nic::{
+    api::{get_sector_targets, set_sector_targets, MonthTarget},
+    error::ApiError,
+    test::utils::{mock_cfg::mock_cfg, set_app_and_ws0},
+    watering::{ds::CtrlSignal, modes::Mode, watering_system::WateringSystem},
+};
+
+#[tokio::test]
+async fn the_weekly_target_follows_the_month() {
+    // a tuesday in july
+    let now = Utc.with_ymd_and_hms(2024, 7, 2, 12, 0, 0).unwrap().timestamp();
+    let cfg = mock_cfg();
+    let (app_state, mut ws) = set_app_and_ws0(now, Some(Mode::Wizard), cfg.watering).unwrap();
+    let curve = |targets: &[(u32, f64)]| {
+        Json(targets.iter().map(|&(month, weekly_target)| MonthTarget { month, weekly_target }).collect())
+    };
+
+    let bad = set_sector_targets(Path(1), State(app_state.clone()), curve(&[(13, 1.)])).await;
+    assert_eq!(bad.unwrap_err(), ApiError::BadRequest("month 13 is not 1 to 12".to_owned()));
+    let twice = set_sector_targets(Path(1), State(app_state.clone()), curve(&[(7, 3.), (7, 3.5)])).await;
+    assert_eq!(twice.unwrap_err(), ApiError::BadRequest("month 7 is given twice".to_owned()));
+    let none = set_sector_targets(Path(99), State(app_state.clone()), curve(&[(7, 3.)])).await;
+    assert_eq!(none.unwrap_err().status(), StatusCode::NOT_FOUND);
+
+    let set = set_sector_targets(Path(1), State(app_state.clone()), curve(&[(10, 1.), (7, 3.5)])).await.unwrap();
+    assert_eq!(set.0.iter().map(|target| target.month).collect::<Vec<_>>(), [7, 10]);
+    assert_eq!(get_sector_targets(Path(1), State(app_state.clone())).await.unwrap().0, set.0);
+    assert!(get_sector_targets(Path(2), State(app_state.clone())).await.unwrap().0.is_empty());
+
+    let signal = app_state.sm_rx.lock().await.try_recv().unwrap();
+    let CtrlSignal::SectorTargets(sector, targets) = signal else {
+        panic!("expected the seasonal curve, got {:?}", signal);
+    };
+    ws.sm.apply_targets(sector, targets, now);
+    let target = |ws: &WateringSystem, id| ws.sm.sectors[&id].weekly_target;
+    assert_eq!((target(&ws, 1), target(&ws, 2)), (3.5, 2.5));
+
+    // picked at midnight, the months without one go back to the sector's
+    let october = Utc.with_ymd_and_hms(2024, 10, 15, 0, 0, 0).unwrap().timestamp();
+    ws.sm.do_daily_adjustments(october, 0., 0.);
+    assert_eq!(target(&ws, 1), 1.);
+    let november = Utc.with_ymd_and_hms(2024, 11, 5, 0, 0, 0).unwrap().timestamp();
+    ws.sm.do_daily_adjustments(november, 0., 0.);
+    assert_eq!(target(&ws, 1), 2.5);
+}
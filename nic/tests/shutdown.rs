@@ -0,0 +1,43 @@
+use nic::{
+    sensors::interface::ValveState,
+    test::utils::{mock_cfg::mock_cfg, mock_sensors::MockSensorController, set_app_and_ws0},
+    utils::sod,
+    watering::{
+        ds::{DailyPlan, WaterSector},
+        modes::Mode,
+        watering_system::run_watering_system,
+    },
+};
+use std::sync::{Arc, Mutex};
+
+#[tokio::test]
+async fn shutdown_closes_the_active_sector() {
+    let ref_time = sod(chrono::Utc::now().timestamp());
+    let cfg = mock_cfg();
+    let (app, mut ws) = set_app_and_ws0(ref_time, Some(Mode::Wizard), cfg.watering).unwrap();
+
+    let closed = Arc::new(Mutex::new(vec![]));
+    let closed_clone = closed.clone();
+    let mut controller = MockSensorController::new();
+    controller.expect_activate_sector().returning(|_| Ok(()));
+    controller.expect_deactivate_sector().returning(move |sector| {
+        closed_clone.lock().unwrap().push(sector);
+        Ok(())
+    });
+    controller.expect_sector_state().returning(|_| Ok(ValveState::Unknown));
+    ws.sm.controller = Arc::new(controller);
+    ws.sm.cfg.pump_sector = Some(9);
+
+    let start = ref_time + 22 * 3600;
+    ws.sm.mode_wizard.daily_plan = vec![DailyPlan(vec![WaterSector::new(1, start, 30 * 60)])];
+    ws.sm.trans_watering(start).await;
+    assert!(ws.sm.state.is_watering());
+
+    let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+    shutdown_tx.send(true).unwrap();
+    run_watering_system(app, None, shutdown_rx, None, Some(&mut ws), cfg.watering).await.unwrap();
+
+    assert_eq!(*closed.lock().unwrap(), vec![1, 9]);
+    // kept, so the next start resumes it
+    assert!(ws.sm.cycle.is_some());
+}
@@ -1,14 +1,20 @@
+use chrono::{TimeZone, Utc};
 use nic::{
+    config::{PauseAction, SignalPolicy},
+    metrics::{self, DROPPED_MESSAGES},
     test::utils::{mock_cfg::mock_cfg, set_app_and_ws0},
     utils::sod,
     watering::{
-        ds::{CtrlSignal, DailyPlan, WaterSector, WeatherSignal},
+        ds::{CtrlSignal, Cycle, DailyPlan, SectorFault, ValveAction, WaterSector, WeatherSignal},
         modes::Mode,
+        state_machine::{ResumePoint, SMState},
+        watering_system::run_watering_system,
     },
+    MAX_MSGS,
 };
 
-#[test]
-fn signal_handling() {
+#[tokio::test]
+async fn signal_handling() {
     let ref_time = sod(chrono::Utc::now().timestamp());
     let cfg = mock_cfg();
     let (_app, mut ws) = set_app_and_ws0(ref_time, Some(Mode::Wizard), cfg.watering).unwrap();
@@ -18,18 +24,18 @@ fn signal_handling() {
         WaterSector::new(1, start_time, 30 * 60), // Sector 1, , 30 mins duration
     ]);
     ws.sm.mode_wizard.daily_plan = vec![daily_plan];
-    ws.sm.trans_watering(start_time);
+    ws.sm.trans_watering(start_time).await;
     assert!(ws.sm.state.is_watering());
-    ws.sm.handle_signal(CtrlSignal::Weather(WeatherSignal::RainStart), start_time + 2);
+    ws.sm.handle_signal(CtrlSignal::Weather(WeatherSignal::RainStart), start_time + 2).await;
 
     assert!(ws.sm.state.is_paused());
 
-    ws.sm.handle_signal(CtrlSignal::Weather(WeatherSignal::RainStop), start_time + 4);
+    ws.sm.handle_signal(CtrlSignal::Weather(WeatherSignal::RainStop), start_time + 4).await;
     assert!(ws.sm.state.is_watering());
 }
 
-#[test]
-fn weather_signal_handling_all_states() {
+#[tokio::test]
+async fn weather_signal_handling_all_states() {
     let ref_time = sod(chrono::Utc::now().timestamp());
     let cfg = mock_cfg();
     let (_app, mut ws) = set_app_and_ws0(ref_time, Some(Mode::Wizard), cfg.watering).unwrap();
@@ -40,23 +46,212 @@ fn weather_signal_handling_all_states() {
     let daily_plan = DailyPlan(vec![sec]);
     ws.sm.mode_wizard.daily_plan = vec![daily_plan];
 
-    ws.sm.trans_watering(start_time);
+    ws.sm.trans_watering(start_time).await;
 
-    ws.sm.handle_signal(CtrlSignal::Weather(WeatherSignal::RainStart), start_time + 2);
+    ws.sm.handle_signal(CtrlSignal::Weather(WeatherSignal::RainStart), start_time + 2).await;
     assert!(ws.sm.state.is_paused());
 
-    ws.sm.handle_signal(CtrlSignal::Weather(WeatherSignal::RainStop), start_time + 4);
+    ws.sm.handle_signal(CtrlSignal::Weather(WeatherSignal::RainStop), start_time + 4).await;
     assert!(ws.sm.state.is_watering());
 
-    ws.sm.handle_signal(CtrlSignal::Weather(WeatherSignal::WindHigh), start_time + 6);
+    ws.sm.handle_signal(CtrlSignal::Weather(WeatherSignal::WindHigh), start_time + 6).await;
     assert!(ws.sm.state.is_paused());
 
-    ws.sm.handle_signal(CtrlSignal::Weather(WeatherSignal::WindLow), start_time + 8);
+    ws.sm.handle_signal(CtrlSignal::Weather(WeatherSignal::WindLow), start_time + 8).await;
     assert!(ws.sm.state.is_watering());
 
-    ws.sm.handle_signal(CtrlSignal::Weather(WeatherSignal::RainStart), start_time + 10);
+    ws.sm.handle_signal(CtrlSignal::Weather(WeatherSignal::RainStart), start_time + 10).await;
     assert!(ws.sm.state.is_paused());
 
-    ws.sm.handle_signal(CtrlSignal::Weather(WeatherSignal::RainStop), start_time + 12);
+    ws.sm.handle_signal(CtrlSignal::Weather(WeatherSignal::RainStop), start_time + 12).await;
     assert!(ws.sm.state.is_watering());
 }
+
+#[tokio::test]
+async fn sector_fault_aborts_cycle() {
+    let ref_time = sod(chrono::Utc::now().timestamp());
+    let cfg = mock_cfg();
+    let (_app, mut ws) = set_app_and_ws0(ref_time, Some(Mode::Wizard), cfg.watering).unwrap();
+
+    let start_time = ref_time + 22 * 3600;
+    let daily_plan =
+        DailyPlan(vec![WaterSector::new(1, start_time, 30 * 60), WaterSector::new(2, start_time, 30 * 60)]);
+    ws.sm.mode_wizard.daily_plan = vec![daily_plan];
+    ws.sm.trans_watering(start_time).await;
+    assert!(ws.sm.state.is_watering());
+
+    let fault = SectorFault { sector: 1, action: ValveAction::Open, error: "timeout".to_owned() };
+    ws.sm.handle_signal(CtrlSignal::SectorFault(fault), start_time + 2).await;
+    assert!(!ws.sm.state.is_watering());
+    assert!(ws.sm.cycle.is_none());
+    assert!(ws.sm.faulted.contains(&1));
+
+    // faulted sectors are left out of the next wizard plan
+    ws.sm.do_daily_adjustments(start_time + 3600, 5., 0.);
+    assert!(ws.sm.mode_wizard.daily_plan.iter().flat_map(|plan| plan.0.iter()).all(|sec| sec.id != 1));
+}
+
+#[tokio::test]
+async fn resume_waters_the_remaining_duration() {
+    let ref_time = sod(chrono::Utc::now().timestamp());
+    let cfg = mock_cfg();
+    let (_app, mut ws) = set_app_and_ws0(ref_time, Some(Mode::Wizard), cfg.watering).unwrap();
+
+    let start_time = ws.sm.timeframe.day_start_time;
+    let daily_plan = DailyPlan(vec![WaterSector::new(1, start_time, 30 * 60)]);
+    ws.sm.mode_wizard.daily_plan = vec![daily_plan];
+    ws.sm.trans_watering(start_time).await;
+
+    ws.sm.handle_signal(CtrlSignal::Weather(WeatherSignal::RainStart), start_time + 10 * 60).await;
+    ws.sm.handle_signal(CtrlSignal::Weather(WeatherSignal::RainStop), start_time + 15 * 60).await;
+    let SMState::Watering(sec) = ws.sm.state else { panic!("should be watering") };
+    assert_eq!((sec.start, sec.duration), (start_time + 15 * 60, 20 * 60));
+
+    // resuming close to the end of the window only waters what still fits
+    let end = ws.sm.timeframe.day_end_time;
+    ws.sm.handle_signal(CtrlSignal::Weather(WeatherSignal::RainStart), start_time + 20 * 60).await;
+    ws.sm.handle_signal(CtrlSignal::Weather(WeatherSignal::RainStop), end - 59).await;
+    let SMState::Watering(sec) = ws.sm.state else { panic!("should be watering") };
+    assert_eq!((sec.start, sec.duration), (end - 59, 60));
+}
+
+#[tokio::test]
+async fn restored_sector_resumes_after_restart() {
+    let ref_time = sod(chrono::Utc::now().timestamp());
+    let cfg = mock_cfg();
+    let (_app, mut ws) = set_app_and_ws0(ref_time, Some(Mode::Wizard), cfg.watering).unwrap();
+
+    let start_time = ws.sm.timeframe.day_start_time;
+    let sector = WaterSector::new(1, start_time, 30 * 60);
+    let cycle = Cycle { id: start_time, daily_plan: DailyPlan(vec![sector]), curr_sector: 0, run: None };
+    let point = ResumePoint { cycle, sector, elapsed: 25 * 60, saved_at: start_time + 25 * 60 };
+    ws.sm.restore(point, start_time + 40 * 60);
+    assert!(ws.sm.state.is_paused());
+
+    ws.sm.update(start_time + 40 * 60).await;
+    let SMState::Watering(sec) = ws.sm.state else { panic!("should be watering") };
+    assert_eq!((sec.start, sec.duration), (start_time + 40 * 60, 5 * 60));
+
+    // the restored cycle is dropped if we come back after the window
+    let point = ResumePoint { cycle: ws.sm.cycle.clone().unwrap(), sector, elapsed: 0, saved_at: start_time };
+    ws.sm.restore(point, start_time);
+    ws.sm.update(ws.sm.timeframe.day_end_time + 1).await;
+    assert!(ws.sm.cycle.is_none());
+}
+
+#[tokio::test]
+async fn long_pause_abandons_the_cycle() {
+    let ref_time = sod(chrono::Utc::now().timestamp());
+    let cfg = mock_cfg();
+    let (_app, mut ws) = set_app_and_ws0(ref_time, Some(Mode::Wizard), cfg.watering).unwrap();
+    ws.sm.cfg.max_pause_secs = 600;
+
+    let start_time = ws.sm.timeframe.day_start_time;
+    ws.sm.mode_wizard.daily_plan = vec![DailyPlan(vec![WaterSector::new(1, start_time, 30 * 60)])];
+    ws.sm.trans_watering(start_time).await;
+    ws.sm.handle_signal(CtrlSignal::Weather(WeatherSignal::RainStart), start_time + 60).await;
+
+    ws.sm.update(start_time + 60 + 599).await;
+    assert!(ws.sm.state.is_paused());
+
+    ws.sm.update(start_time + 60 + 600).await;
+    assert!(!ws.sm.state.is_paused());
+    assert!(ws.sm.cycle.is_none());
+    assert!(ws.sm.mode_wizard.daily_plan.is_empty());
+}
+
+#[tokio::test]
+async fn auto_mode_follows_the_pause_policy() {
+    let ref_time = sod(chrono::Utc::now().timestamp());
+    let cfg = mock_cfg();
+    let (_app, mut ws) = set_app_and_ws0(ref_time, Some(Mode::Auto), cfg.watering).unwrap();
+    ws.sm.pause_policy.auto = SignalPolicy { rain: PauseAction::Abort, wind: PauseAction::Ignore };
+
+    let start_time = ws.sm.timeframe.day_start_time;
+    ws.sm.mode_auto.daily_plan = vec![DailyPlan(vec![WaterSector::new(1, start_time, 30 * 60)])];
+    ws.sm.trans_watering(start_time).await;
+
+    ws.sm.handle_signal(CtrlSignal::Weather(WeatherSignal::WindHigh), start_time + 2).await;
+    assert!(ws.sm.state.is_watering());
+
+    ws.sm.handle_signal(CtrlSignal::Weather(WeatherSignal::RainStart), start_time + 4).await;
+    assert_eq!(ws.sm.state, SMState::Idle);
+    assert!(ws.sm.cycle.is_none());
+    assert!(ws.sm.mode_auto.daily_plan.is_empty());
+}
+
+#[tokio::test]
+async fn resumes_once_every_signal_ended() {
+    let ref_time = sod(chrono::Utc::now().timestamp());
+    let cfg = mock_cfg();
+    let (_app, mut ws) = set_app_and_ws0(ref_time, Some(Mode::Wizard), cfg.watering).unwrap();
+
+    let start_time = ws.sm.timeframe.day_start_time;
+    ws.sm.mode_wizard.daily_plan = vec![DailyPlan(vec![WaterSector::new(1, start_time, 30 * 60)])];
+    ws.sm.trans_watering(start_time).await;
+
+    ws.sm.handle_signal(CtrlSignal::Weather(WeatherSignal::RainStart), start_time + 2).await;
+    ws.sm.handle_signal(CtrlSignal::Weather(WeatherSignal::WindHigh), start_time + 4).await;
+    ws.sm.handle_signal(CtrlSignal::Weather(WeatherSignal::RainStop), start_time + 6).await;
+    assert!(ws.sm.state.is_paused(), "still windy");
+
+    ws.sm.handle_signal(CtrlSignal::Weather(WeatherSignal::WindLow), start_time + 8).await;
+    assert!(ws.sm.state.is_watering());
+}
+
+#[tokio::test]
+async fn resyncs_after_losing_signals() {
+    let now = Utc.with_ymd_and_hms(2023, 11, 27, 20, 0, 0).unwrap().timestamp();
+    let cfg = mock_cfg();
+    let (app_state, mut ws) = set_app_and_ws0(now, Some(Mode::Manual), cfg.watering).unwrap();
+    // more than the channel holds (rounded up to a power of two), before the loop reads any
+    for _ in 0..2 * MAX_MSGS {
+        app_state.sm_tx.send(CtrlSignal::StopMachine).unwrap();
+    }
+    let mut sm_rx = app_state.sm_tx.subscribe();
+    let label = ("receiver", "watering");
+    let before = metrics::registry().counter(DROPPED_MESSAGES, label);
+
+    let (_shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+    run_watering_system(app_state, None, shutdown_rx, Some(now + 600), Some(&mut ws), cfg.watering).await.unwrap();
+    // other tests share the registry
+    assert!(metrics::registry().counter(DROPPED_MESSAGES, label) > before);
+    let mut resynced = false;
+    while let Ok(signal) = sm_rx.try_recv() {
+        resynced |= matches!(signal, CtrlSignal::Resync);
+    }
+    assert!(resynced);
+}
+
+#[tokio::test]
+async fn weather_proof_sector_waters_through_the_rain() {
+    let ref_time = sod(chrono::Utc::now().timestamp());
+    let cfg = mock_cfg();
+    let (_app, mut ws) = set_app_and_ws0(ref_time, Some(Mode::Wizard), cfg.watering).unwrap();
+    ws.sm.sectors.get_mut(&1).unwrap().ignore_weather_pause = true;
+
+    let start_time = ws.sm.timeframe.day_start_time;
+    let plan = || DailyPlan(vec![WaterSector::new(1, start_time, 10 * 60), WaterSector::new(2, start_time + 600, 600)]);
+    ws.sm.mode_wizard.daily_plan = vec![plan()];
+    ws.sm.trans_watering(start_time).await;
+    ws.sm.handle_signal(CtrlSignal::Weather(WeatherSignal::RainStart), start_time + 2 * 60).await;
+    assert!(ws.sm.state.is_watering(), "the greenhouse keeps watering");
+
+    // the next sector waits for the rain to stop, with all of its time left
+    ws.sm.update(start_time + 10 * 60).await;
+    let SMState::Paused(data) = &ws.sm.state else { panic!("should be paused") };
+    assert_eq!((data.elapsed, data.signals.clone()), (0, vec![WeatherSignal::RainStart]));
+    ws.sm.handle_signal(CtrlSignal::Weather(WeatherSignal::RainStop), start_time + 15 * 60).await;
+    let SMState::Watering(sec) = ws.sm.state else { panic!("should be watering") };
+    assert_eq!((sec.id, sec.start, sec.duration), (2, start_time + 15 * 60, 10 * 60));
+
+    // rain that stopped before the sector ended is forgotten
+    ws.sm.stop();
+    ws.sm.mode_wizard.daily_plan = vec![plan()];
+    ws.sm.trans_watering(start_time).await;
+    ws.sm.handle_signal(CtrlSignal::Weather(WeatherSignal::RainStart), start_time + 2 * 60).await;
+    ws.sm.handle_signal(CtrlSignal::Weather(WeatherSignal::RainStop), start_time + 5 * 60).await;
+    ws.sm.update(start_time + 10 * 60).await;
+    let SMState::Watering(sec) = ws.sm.state else { panic!("should be watering") };
+    assert_eq!(sec.id, 2);
+}
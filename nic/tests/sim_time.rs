@@ -0,0 +1,60 @@
+use nic::{test::utils::mock_time::MockTimeProvider, time::TimeProvider};
+use std::{sync::Arc, time::Duration};
+
+#[tokio::test]
+async fn a_held_clock_waits_to_be_moved() {
+    let clock = Arc::new(MockTimeProvider::held(1_000));
+    let sleeper = clock.clone();
+    let woken = tokio::spawn(async move {
+        sleeper.sleep_until(1_600).await;
+        sleeper.now()
+    });
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert!(!woken.is_finished(), "nothing moved the clock");
+    assert_eq!(clock.now(), 1_000);
+
+    clock.advance_time(300).await;
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert!(!woken.is_finished(), "not there yet");
+    clock.set(1_600);
+    assert_eq!(tokio::time::timeout(Duration::from_secs(1), woken).await.unwrap().unwrap(), 1_600);
+}
+
+#[cfg(feature = "sim")]
+mod endpoints {
+    use axum::{extract::State, Json};
+    use hyper::StatusCode;
+    use nic::{
+        api::{sim_advance_time, sim_set_time, SimAdvance, SimSet},
+        test::utils::{
+            mock_db::{new_with_mock, MockDatabase},
+            mock_sensors::set_sensor_controller0,
+            mock_time::MockTimeProvider,
+        },
+        time::TimeProvider,
+    };
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn move_the_held_clock() {
+        let clock = Arc::new(MockTimeProvider::held(1_000));
+        let app_state = new_with_mock(Arc::new(MockDatabase::new()), set_sensor_controller0(), clock.clone()).unwrap();
+
+        let set = sim_set_time(State(app_state.clone()), Json(SimSet { time: 5_000 })).await.unwrap();
+        assert_eq!(set.0.now, 5_000);
+        let back = sim_set_time(State(app_state.clone()), Json(SimSet { time: 4_000 })).await.unwrap_err();
+        assert_eq!(back.status(), StatusCode::BAD_REQUEST);
+
+        let request = SimAdvance { secs: 3_600, step_secs: Some(600) };
+        let advanced = sim_advance_time(State(app_state.clone()), Json(request)).await.unwrap();
+        assert_eq!((advanced.0.now, clock.now()), (8_600, 8_600));
+        let request = SimAdvance { secs: -1, step_secs: None };
+        assert!(sim_advance_time(State(app_state.clone()), Json(request)).await.is_err());
+
+        // a clock that runs on its own isn't driven from outside
+        let free = Arc::new(MockTimeProvider::new(1_000));
+        let app_state = new_with_mock(Arc::new(MockDatabase::new()), set_sensor_controller0(), free).unwrap();
+        let free = sim_set_time(State(app_state), Json(SimSet { time: 5_000 })).await.unwrap_err();
+        assert_eq!(free.status(), StatusCode::CONFLICT);
+    }
+}
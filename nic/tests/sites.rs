@@ -0,0 +1,138 @@
+use nic::{
+    config::{Database as DatabaseCfg, SectorCfg},
+    db::{Database, DatabaseTrait},
+    sensors::telemetry::DeviceTelemetry,
+    watering::{
+        ds::{AuditEntry, DailyPlan, SystemEvent, WaterSector},
+        learning::{LearnedParams, LearnedStatus, MoistureReading},
+        modes::Mode,
+        watering_alg::SectorTarget,
+    },
+};
+use std::path::PathBuf;
+
+fn sector(id: u32) -> SectorCfg {
+    SectorCfg {
+        id,
+        name: format!("zone {}", id),
+        sprinkler_debit: 1.0,
+        percolation_rate: 0.5,
+        weekly_target: 2.5,
+        max_duration: 1800,
+        ignore_weather_pause: false,
+        max_daily_mm: None,
+        max_daily_minutes: None,
+        deficit_exempt: false,
+    }
+}
+
+fn event(timestamp: i64, sector: u32) -> SystemEvent {
+    let kind = "sector_activated".to_owned();
+    SystemEvent { timestamp, kind, sector: Some(sector), cycle: None, detail: String::new() }
+}
+
+/// The house and the allotment on one database file
+fn open_sites(test: &str) -> (Database, Database, PathBuf) {
+    let path = std::env::temp_dir().join(format!("nic-sites-{}-{}.db", test, std::process::id()));
+    let cfg = DatabaseCfg { name: path.to_string_lossy().into_owned(), ..Default::default() };
+    (Database::open(&cfg, "house").unwrap(), Database::open(&cfg, "allotment").unwrap(), path)
+}
+
+fn remove(path: PathBuf) {
+    for suffix in ["", "-wal", "-shm"] {
+        _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+    }
+}
+
+#[test]
+fn sites_sharing_a_database_only_see_their_rows() {
+    let (house, allotment, path) = open_sites("rows");
+
+    house.import_sectors(vec![sector(1), sector(2)]).unwrap();
+    allotment.import_sectors(vec![sector(3)]).unwrap();
+    assert!(allotment.import_sectors(vec![sector(1)]).is_err(), "sector 1 is the house's");
+    let ids = |db: &Database| db.load_sectors().unwrap().iter().map(|sec| sec.id).collect::<Vec<_>>();
+    assert_eq!((ids(&house), ids(&allotment)), (vec![1, 2], vec![3]));
+
+    house.store_mode(Mode::Wizard).unwrap();
+    allotment.store_mode(Mode::Off).unwrap();
+    assert_eq!((house.load_mode(), allotment.load_mode()), (Some(Mode::Wizard), Some(Mode::Off)));
+
+    house.log_system_event(event(100, 1)).unwrap();
+    allotment.log_system_event(event(200, 3)).unwrap();
+    assert_eq!(house.load_system_events(0, 300).unwrap(), [event(100, 1)]);
+    assert_eq!(allotment.load_system_events(0, 300).unwrap(), [event(200, 3)]);
+
+    house.store_wizard_plans(0, vec![DailyPlan(vec![WaterSector::new(1, 1000, 600)])]).unwrap();
+    allotment.store_wizard_plans(0, vec![DailyPlan(vec![WaterSector::new(3, 2000, 600)])]).unwrap();
+    assert_eq!(house.load_wizard_plans(0, 3000).unwrap(), [WaterSector::new(1, 1000, 600)]);
+    assert_eq!(allotment.load_wizard_queue(0).unwrap().len(), 1);
+
+    let audit = |timestamp, action: &str| AuditEntry {
+        timestamp,
+        ip: None,
+        user: None,
+        action: action.to_owned(),
+        status: 200,
+    };
+    house.log_audit(audit(100, "POST /switch/auto")).unwrap();
+    allotment.log_audit(audit(200, "POST /estop")).unwrap();
+    assert_eq!(house.load_audit(0, 300).unwrap(), [audit(100, "POST /switch/auto")]);
+    assert_eq!(allotment.load_audit(0, 300).unwrap(), [audit(200, "POST /estop")]);
+
+    let report =
+        |device: &str| DeviceTelemetry { device: device.to_owned(), timestamp: 100, battery: None, rssi: None };
+    house.store_device_telemetry(report("valve-1")).unwrap();
+    allotment.store_device_telemetry(report("valve-3")).unwrap();
+    assert_eq!(house.load_device_telemetry().unwrap(), [report("valve-1")]);
+    assert_eq!(allotment.load_device_telemetry().unwrap(), [report("valve-3")]);
+
+    drop((house, allotment));
+    remove(path);
+}
+
+#[test]
+fn sites_only_write_to_their_sectors() {
+    let (house, allotment, path) = open_sites("writes");
+    house.import_sectors(vec![sector(1)]).unwrap();
+    allotment.import_sectors(vec![sector(3)]).unwrap();
+    let estimate = LearnedParams {
+        sector: 1,
+        learned_at: 100,
+        sprinkler_debit: 2.,
+        percolation_rate: 1.,
+        previous_debit: 1.,
+        previous_percolation: 0.5,
+        samples: 5,
+        from: 0,
+        to: 100,
+        rmse: 0.1,
+        status: LearnedStatus::Pending,
+    };
+    house.store_learned_params(vec![estimate]).unwrap();
+
+    // the allotment names the house's sector 1
+    assert_eq!(allotment.resolve_learned_params(1, true).unwrap(), None);
+    allotment.set_sprinkler_debit(1, 9.).unwrap();
+    allotment.set_sector_targets(1, vec![SectorTarget { sector: 1, month: 7, weekly_target: 4. }]).unwrap();
+    allotment.add_moisture_reading(MoistureReading { sector: 1, timestamp: 100, water_cm: 3. }).unwrap();
+
+    let house_sector = &house.load_sectors().unwrap()[0];
+    assert_eq!((house_sector.sprinkler_debit, house_sector.percolation_rate), (1., 0.5));
+    assert_eq!(house.load_learned_params().unwrap()[0].status, LearnedStatus::Pending);
+    assert!(house.load_sector_targets().unwrap().is_empty());
+    assert!(house.load_moisture_readings(0, 200).unwrap().is_empty());
+
+    // the house itself still can
+    house.set_sector_targets(1, vec![SectorTarget { sector: 1, month: 7, weekly_target: 4. }]).unwrap();
+    house.add_moisture_reading(MoistureReading { sector: 1, timestamp: 100, water_cm: 3. }).unwrap();
+    assert!(house.resolve_learned_params(1, true).unwrap().is_some());
+    assert_eq!(house.load_sectors().unwrap()[0].sprinkler_debit, 2.);
+    assert_eq!(
+        (house.load_sector_targets().unwrap().len(), house.load_moisture_readings(0, 200).unwrap().len()),
+        (1, 1)
+    );
+
+    drop((house, allotment));
+    remove(path);
+}
@@ -1,11 +1,25 @@
 use nic::{
-    test::utils::{mock_cfg::mock_cfg, set_app_and_ws0},
+    config::SectorCfg,
+    db::{Database, DatabaseTrait},
+    error::AppError,
+    sensors::interface::ValveState,
+    test::utils::{
+        mock_cfg::mock_cfg,
+        mock_db::{new_with_mock, MockDatabase},
+        mock_sensors::{set_sensor_controller0, MockSensorController},
+        mock_time::MockTimeProvider,
+        set_app_and_ws0,
+    },
+    time::TimeProvider,
     utils::{load_sectors_into_hashmap, sod, ux_ts_to_string},
     watering::{
-        ds::{DailyPlan, SectorInfo, WaterSector},
+        ds::{CtrlSignal, DailyPlan, SectorInfo, WaterSector},
         modes::Mode,
+        state_machine::SMState,
+        watering_system::WateringSystem,
     },
 };
+use std::sync::{Arc, Mutex};
 
 #[tokio::test]
 async fn scheduler_triggers_auto_mode() {
@@ -32,7 +46,7 @@ async fn scheduler_triggers_auto_mode() {
         // Execute Auto Mode if within timeframe
         let now = time_provider.now();
         if ws.sm.timeframe.is_within(now) {
-            ws.sm.update(now);
+            ws.sm.update(now).await;
         }
         time_provider.advance_time(1).await;
     }
@@ -62,7 +76,7 @@ async fn scheduler_triggers_wizard_mode() {
     ws.sm.mode_wizard.daily_plan = vec![daily_plan];
     time_provider.set(sec_start_time - 1); // Start simulation slightly before the schedule
     for _ in 0..5 {
-        ws.sm.update(time_provider.now());
+        ws.sm.update(time_provider.now()).await;
         time_provider.advance_time(1).await;
     }
     assert!(ws.sm.cycle.is_some(), "Cycle should be active in Wizard Mode.");
@@ -73,3 +87,155 @@ async fn scheduler_triggers_wizard_mode() {
         "Cycle should target sector 1 with the correct duration."
     );
 }
+
+#[tokio::test]
+async fn valve_that_never_opens_raises_alarm() {
+    let now = sod(chrono::Utc::now().timestamp());
+    let cfg = mock_cfg();
+    let (app, mut ws) = set_app_and_ws0(now, Some(Mode::Wizard), cfg.watering).unwrap();
+    let mut web_rx = app.web_rx.resubscribe();
+
+    // the relay takes the command but the valve stays closed
+    let closed = Arc::new(Mutex::new(Vec::new()));
+    let closed_clone = closed.clone();
+    let mut controller = MockSensorController::new();
+    controller.expect_activate_sector().returning(|_| Ok(()));
+    controller.expect_deactivate_sector().returning(move |sector| {
+        closed_clone.lock().unwrap().push(sector);
+        Ok(())
+    });
+    controller.expect_sector_state().returning(|_| Ok(ValveState::Closed));
+    ws.sm.controller = Arc::new(controller);
+    ws.sm.cfg.valve_check_secs = 10;
+    ws.sm.cfg.pump_sector = Some(9);
+
+    let start = now + 22 * 3600;
+    ws.sm.mode_wizard.daily_plan = vec![DailyPlan(vec![WaterSector::new(1, start, 30 * 60)])];
+    for time in start..start + 10 {
+        ws.sm.update(time).await;
+        assert!(ws.sm.state.is_watering(), "still within the check window");
+    }
+    ws.sm.update(start + 10).await;
+    assert!(!ws.sm.state.is_watering());
+    assert!(ws.sm.cycle.is_none());
+    assert_eq!(*closed.lock().unwrap(), vec![9, 1], "pump first, then the sector");
+
+    loop {
+        match web_rx.try_recv() {
+            Ok(CtrlSignal::Alarm(alarm)) => {
+                assert_eq!(
+                    (alarm.sector, alarm.expected, alarm.actual),
+                    (1, ValveState::Open, Some(ValveState::Closed))
+                );
+                break assert_eq!(alarm.timestamp, start + 10);
+            }
+            Ok(CtrlSignal::StateChanged(_)) => continue,
+            other => panic!("expected an alarm, got {:?}", other),
+        }
+    }
+}
+
+#[tokio::test]
+async fn valve_that_cant_be_read_back_raises_no_alarm() {
+    let now = sod(chrono::Utc::now().timestamp());
+    let (app, mut ws) = set_app_and_ws0(now, Some(Mode::Wizard), mock_cfg().watering).unwrap();
+    let mut web_rx = app.web_rx.resubscribe();
+
+    // the service takes the commands but has nothing to read the valves with
+    let mut controller = MockSensorController::new();
+    controller.expect_activate_sector().returning(|_| Ok(()));
+    controller.expect_deactivate_sector().returning(|_| Ok(()));
+    controller.expect_sector_state().returning(|_| Err(AppError::SensorError("no read back".to_owned())));
+    ws.sm.controller = Arc::new(controller);
+    ws.sm.cfg.valve_check_secs = 10;
+
+    let start = now + 22 * 3600;
+    ws.sm.mode_wizard.daily_plan = vec![DailyPlan(vec![WaterSector::new(1, start, 30 * 60)])];
+    for time in start..start + 60 {
+        ws.sm.update(time).await;
+    }
+    assert!(ws.sm.state.is_watering());
+    assert!(ws.sm.valve_checks.is_empty());
+    while let Ok(signal) = web_rx.try_recv() {
+        assert!(!matches!(signal, CtrlSignal::Alarm(_)), "{:?}", signal);
+    }
+}
+
+#[tokio::test]
+async fn sleeps_until_the_next_event() {
+    let now = sod(chrono::Utc::now().timestamp());
+    let cfg = mock_cfg();
+    let (_app, mut ws) = set_app_and_ws0(now, Some(Mode::Wizard), cfg.watering).unwrap();
+    ws.sm.cfg.valve_check_secs = 0;
+
+    let start = ws.sm.timeframe.day_start_time;
+    ws.sm.mode_wizard.daily_plan = vec![DailyPlan(vec![WaterSector::new(1, start, 90)])];
+    assert_eq!(ws.sm.next_wakeup(start - 3600), start);
+
+    ws.sm.update(start).await;
+    assert_eq!(ws.sm.next_wakeup(start), start + 60, "progress is saved every minute");
+    assert_eq!(ws.sm.next_wakeup(start + 60), start + 90);
+
+    ws.sm.update(start + 90).await;
+    assert_eq!(ws.sm.next_wakeup(start + 90), ws.sm.timeframe.day_end_time + 1);
+}
+
+#[tokio::test]
+async fn water_applied_follows_the_elapsed_seconds() {
+    let now = sod(chrono::Utc::now().timestamp());
+    let cfg = mock_cfg();
+    let db = Arc::new(Database::new(":memory:").unwrap());
+    let sector = SectorCfg {
+        id: 1,
+        name: "lawn".to_owned(),
+        sprinkler_debit: 1.0,
+        percolation_rate: 0.5,
+        weekly_target: 2.5,
+        max_duration: 3600,
+        ignore_weather_pause: false,
+        max_daily_mm: None,
+        max_daily_minutes: None,
+        deficit_exempt: false,
+    };
+    db.import_sectors(vec![sector]).unwrap();
+    let app_state = new_with_mock(db.clone(), set_sensor_controller0(), Arc::new(MockTimeProvider::new(now))).unwrap();
+    let mut ws = WateringSystem::new(app_state, Some(Mode::Wizard), now, cfg.watering).unwrap();
+    ws.sm.cfg.valve_check_secs = 0;
+
+    // few and late updates water as much as one a second would
+    let start = ws.sm.timeframe.day_start_time;
+    ws.sm.mode_wizard.daily_plan = vec![DailyPlan(vec![WaterSector::new(1, start, 30 * 60)])];
+    for time in [start, start + 37, start + 1000, start + 30 * 60 + 25] {
+        ws.sm.update(time).await;
+    }
+    assert!(!ws.sm.state.is_watering());
+    assert_eq!(ws.sm.sectors[&1].progress, 0.5);
+    let query = "SELECT CAST(water_applied AS TEXT) || ' ' || CAST(duration AS TEXT) FROM watering_events";
+    assert_eq!(db.query_row(query, vec![]).unwrap(), "0.5 30.0");
+}
+
+#[tokio::test]
+async fn a_step_of_the_wall_clock_doesnt_change_how_long_a_sector_waters() {
+    let start = sod(chrono::Utc::now().timestamp()) + 22 * 3600;
+    let time_provider = Arc::new(MockTimeProvider::new(start));
+    let app = new_with_mock(Arc::new(MockDatabase::new()), set_sensor_controller0(), time_provider.clone()).unwrap();
+    let mut ws = WateringSystem::new(app, Some(Mode::Wizard), start, mock_cfg().watering).unwrap();
+    ws.sm.sectors = load_sectors_into_hashmap(vec![SectorInfo::build(1, 2.5, 1., 30 * 60, 0., 0.5, 0)]);
+    ws.sm.mode_wizard.daily_plan = vec![DailyPlan(vec![WaterSector::new(1, start, 30 * 60)])];
+
+    ws.sm.update(start).await;
+    time_provider.advance_time(600).await;
+    ws.sm.update(time_provider.now()).await;
+    // NTP sets the clock an hour forward, past the end of the sector
+    time_provider.step(3600);
+    ws.sm.update(time_provider.now()).await;
+    assert_eq!(ws.sm.state, SMState::Watering(WaterSector::new(1, start + 3600, 30 * 60)));
+
+    time_provider.advance_time(1199).await;
+    ws.sm.update(time_provider.now()).await;
+    assert!(ws.sm.state.is_watering());
+    time_provider.advance_time(1).await;
+    ws.sm.update(time_provider.now()).await;
+    assert!(ws.sm.cycle.is_none(), "watered its 30 minutes");
+    assert_eq!(ws.sm.watered_today[&1], 30 * 60);
+}
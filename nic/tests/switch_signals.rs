@@ -15,11 +15,8 @@ async fn test_rapid_signals() {
 
     let mut received_count = 0;
     while let Ok(signal) = rx.recv().await {
-        match signal {
-            CtrlSignal::Weather(WeatherSignal::RainStart) => {
-                received_count += 1;
-            }
-            _ => {}
+        if let CtrlSignal::Weather(WeatherSignal::RainStart) = signal {
+            received_count += 1;
         }
     }
 
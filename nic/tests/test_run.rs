@@ -0,0 +1,38 @@
+use axum::{extract::State, Json};
+use chrono::{TimeZone, Utc};
+use hyper::StatusCode;
+use nic::{
+    api::{start_test_run, TestRunRequest},
+    test::utils::{mock_cfg::mock_cfg, set_app_and_ws0},
+    watering::{modes::Mode, state_machine::SMState},
+};
+
+#[tokio::test]
+async fn walks_every_sector_and_keeps_the_plans() {
+    // Monday morning, the mock schedule waters from 06:00
+    let now = Utc.with_ymd_and_hms(2023, 11, 27, 4, 0, 0).unwrap().timestamp();
+    let cfg = mock_cfg();
+    let (app_state, mut ws) = set_app_and_ws0(now, Some(Mode::Auto), cfg.watering).unwrap();
+    ws.sm.cfg.valve_check_secs = 0;
+    ws.sm.faulted.insert(3);
+    let plans = ws.sm.mode_auto.daily_plan.len();
+
+    let run = ws.sm.start_test_run(60, now).await.unwrap();
+    let starts: Vec<_> = run.sectors.iter().map(|sec| (sec.sector, sec.start - now)).collect();
+    assert_eq!(starts, [(1, 0), (2, 80), (4, 160)]);
+    assert!(ws.sm.start_test_run(60, now + 1).await.is_err(), "one at a time");
+
+    let mut t = now;
+    while ws.sm.state != SMState::Idle && t < now + 600 {
+        t += 10;
+        ws.sm.update(t).await;
+    }
+    let run = ws.sm.test_run.clone().unwrap();
+    assert!(run.finished);
+    assert!(run.sectors.iter().all(|sec| sec.activated == Some(true) && sec.error.is_none()));
+    assert_eq!(ws.sm.mode_auto.daily_plan.len(), plans);
+
+    let request = Json(TestRunRequest { seconds_per_sector: 0 });
+    let rejected = start_test_run(State(app_state), request).await;
+    assert_eq!(rejected.unwrap_err().status(), StatusCode::BAD_REQUEST);
+}
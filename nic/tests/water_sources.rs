@@ -0,0 +1,73 @@
+use axum::extract::{Query, State};
+use chrono::{TimeZone, Utc};
+use nic::{
+    api::{get_usage, EventsQuery},
+    config::SourceCfg,
+    sensors::interface::ValveState,
+    test::utils::{mock_cfg::mock_cfg, mock_sensors::MockSensorController, set_app_and_ws0},
+    watering::{
+        ds::{DailyPlan, SourceUsage, WaterSector},
+        modes::Mode,
+        state_machine::SMState,
+    },
+};
+use std::sync::{Arc, Mutex};
+
+#[tokio::test]
+async fn the_well_at_night_and_the_city_for_the_rest() {
+    let now = Utc.with_ymd_and_hms(2024, 6, 3, 21, 0, 0).unwrap().timestamp();
+    let cfg = mock_cfg();
+    let (app_state, mut ws) = set_app_and_ws0(now, Some(Mode::Wizard), cfg.watering).unwrap();
+
+    let commands = Arc::new(Mutex::new(vec![]));
+    let (opened, closed) = (commands.clone(), commands.clone());
+    let mut controller = MockSensorController::new();
+    controller.expect_activate_sector().returning(move |sector| {
+        opened.lock().unwrap().push(("open", sector));
+        Ok(())
+    });
+    controller.expect_deactivate_sector().returning(move |sector| {
+        closed.lock().unwrap().push(("close", sector));
+        Ok(())
+    });
+    controller.expect_sector_state().returning(|_| Ok(ValveState::Unknown));
+    ws.sm.controller = Arc::new(controller);
+    let well = SourceCfg {
+        name: "well".to_owned(),
+        master_sector: Some(10),
+        flow_lpm: 40.,
+        hours: Some((22, 6)),
+        sectors: vec![1, 2],
+    };
+    let city =
+        SourceCfg { name: "city".to_owned(), master_sector: Some(11), flow_lpm: 20., hours: None, sectors: vec![] };
+    ws.sm.sources = vec![well, city];
+
+    let start = ws.sm.timeframe.day_start_time;
+    ws.sm.mode_wizard.daily_plan =
+        vec![DailyPlan(vec![WaterSector::new(1, start, 600), WaterSector::new(3, start + 600, 600)])];
+    let mut t = start;
+    ws.sm.update(t).await;
+    while ws.sm.state != SMState::Idle && t < start + 1800 {
+        t += 10;
+        ws.sm.update(t).await;
+    }
+
+    let commands = commands.lock().unwrap().clone();
+    let well_then_city = [
+        ("open", 10),
+        ("open", 1),
+        ("close", 1),
+        ("close", 10),
+        ("open", 11),
+        ("open", 3),
+        ("close", 3),
+        ("close", 11),
+    ];
+    assert_eq!(commands, well_then_city, "each sector behind the master valve of its source");
+
+    let query = EventsQuery { from: Some(now - 86_400), to: Some(now + 86_400) };
+    let usage = get_usage(Query(query), State(app_state)).await.0;
+    let usage_of = |source: &str, secs, litres| SourceUsage { source: source.to_owned(), secs, litres };
+    assert_eq!(usage, [usage_of("city", 600, 200.), usage_of("well", 600, 400.)]);
+}
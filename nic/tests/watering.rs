@@ -1,5 +1,6 @@
 use chrono::{TimeZone, Utc};
 use nic::{
+    config::LogCfg,
     test::utils::{mock_cfg::mock_cfg, set_app_and_ws0},
     utils::{load_sectors_into_hashmap, parse_datetime_to_utc_timestamp, sod, start_log, ux_ts_to_string},
     watering::{
@@ -11,8 +12,8 @@ use nic::{
     },
 };
 
-#[test]
-fn watering_at_right_times() {
+#[tokio::test]
+async fn watering_at_right_times() {
     let now = parse_datetime_to_utc_timestamp("2024-11-29T17:00:00+00:00", "%Y-%m-%dT%H:%M:%S%z").unwrap();
     let allowed_timeframe = WaterWin::new(now, 22, 8);
     let cfg = mock_cfg();
@@ -45,7 +46,7 @@ fn watering_at_right_times() {
         time_provider.set(time);
 
         // Call the execute function
-        ws.sm.update(time_provider.now());
+        ws.sm.update(time_provider.now()).await;
 
         {
             // Verify watering state
@@ -70,7 +71,7 @@ async fn run_watering_system_fast_forward() {
     let time_provider = ws.time_provider.clone();
     let allowed_timeframe = WaterWin::new(now, 22, 8); // 10 PM to 6 AM
     ws.sm.timeframe = allowed_timeframe;
-    start_log(Some(time_provider.clone()));
+    start_log(&LogCfg::default(), Some(time_provider.clone())).unwrap();
 
     // Simulation parameters
     let simulation_duration_seconds = 13 * 24 * 3600;
@@ -128,7 +129,7 @@ async fn test_auto_mode_trigger_watering() {
 
     // Simulate an update loop
     for time in (current_time..current_time + 10_800).step_by(900) {
-        ws.sm.update(time);
+        ws.sm.update(time).await;
 
         if time == current_time + 3600 {
             assert!(matches!(ws.sm.state, SMState::Watering(WaterSector { id: 2, .. })));
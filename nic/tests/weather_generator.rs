@@ -0,0 +1,67 @@
+use nic::{
+    config::WeatherStation,
+    db::{Database, DatabaseTrait},
+    links::Links,
+    test::utils::mock_time::MockTimeProvider,
+    utils::{init_broadcast_channels, init_channels},
+    watering::ds::{CtrlSignal, WeatherSignal},
+    weather::{
+        freshness::WeatherFreshness,
+        generator::{run_weather_generator, SyntheticWeather},
+        provider::ProviderCtx,
+    },
+};
+use std::{sync::Arc, time::Duration};
+use tokio::{sync::broadcast::error::TryRecvError, time::timeout};
+
+const START: i64 = 1_717_372_800; // 2024-06-03
+
+#[tokio::test]
+async fn plays_the_weather_until_stopped() {
+    let db = Arc::new(Database::new(":memory:").unwrap());
+    let (sm_tx, _) = init_channels();
+    let (web_tx, mut web_rx) = init_broadcast_channels();
+    let mut sm_rx = sm_tx.subscribe();
+    let time_provider = Arc::new(MockTimeProvider::new(START));
+    let station =
+        WeatherStation { rain_threshold: 0.5, rain_debounce_secs: 0, wind_threshold: 1000., ..Default::default() };
+    let ctx = ProviderCtx::new(
+        &station,
+        db.clone(),
+        sm_tx.clone(),
+        web_tx,
+        Arc::new(WeatherFreshness::disabled()),
+        Arc::new(Links::new(time_provider.clone())),
+    );
+    tokio::spawn(run_weather_generator(ctx, time_provider));
+    tokio::time::sleep(Duration::from_millis(20)).await;
+
+    // a minute of weather every 10 ms, raining from the start
+    let weather = SyntheticWeather { hours: 0, speed: 6000., rain_chance: 1., ..Default::default() };
+    sm_tx.send(CtrlSignal::GenWeather(Some(weather))).unwrap();
+    let data = timeout(Duration::from_secs(2), async {
+        loop {
+            if let Ok(CtrlSignal::WeatherData(data)) = web_rx.recv().await {
+                return data;
+            }
+        }
+    });
+    assert!(data.await.unwrap().rain > 0.);
+    let signal = timeout(Duration::from_secs(2), async {
+        loop {
+            if let Ok(CtrlSignal::Weather(signal)) = sm_rx.recv().await {
+                return signal;
+            }
+        }
+    });
+    assert_eq!(signal.await.unwrap(), WeatherSignal::RainStart);
+
+    sm_tx.send(CtrlSignal::GenWeather(None)).unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    // played, not stored as the station's
+    assert!(db.load_observations_page(START, START + 86_400, 10_000).unwrap().is_empty());
+    // stopped, nothing more comes
+    while web_rx.try_recv().is_ok() {}
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(matches!(web_rx.try_recv(), Err(TryRecvError::Empty)));
+}
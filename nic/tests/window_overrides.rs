@@ -0,0 +1,58 @@
+use axum::extract::State;
+use axum::Json;
+use chrono::{TimeZone, Utc};
+use hyper::StatusCode;
+use nic::{
+    api::{get_windows, set_window_overrides},
+    config::WindowOverrideCfg,
+    test::utils::{mock_cfg::mock_cfg, set_app_and_ws0},
+    watering::{ds::CtrlSignal, modes::Mode},
+};
+
+fn no_watering(weekday: &str) -> WindowOverrideCfg {
+    WindowOverrideCfg { weekdays: vec![weekday.to_owned()], duration_hours: Some(0), ..Default::default() }
+}
+
+#[tokio::test]
+async fn the_window_follows_the_overrides() {
+    // a friday at noon, the 22:00 window is tonight's
+    let now = Utc.with_ymd_and_hms(2024, 7, 5, 12, 0, 0).unwrap().timestamp();
+    let cfg = mock_cfg();
+    let (app_state, mut ws) = set_app_and_ws0(now, Some(Mode::Wizard), cfg.watering).unwrap();
+
+    let bad = WindowOverrideCfg { weekdays: vec!["fri".to_owned()], start_hour: Some(25), ..Default::default() };
+    let err = set_window_overrides(State(app_state.clone()), Json(vec![bad])).await.unwrap_err();
+    assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+
+    // no watering friday nights, and a heat wave with longer windows
+    let heat_wave = WindowOverrideCfg {
+        from: Some("2024-07-06".to_owned()),
+        to: Some("2024-07-07".to_owned()),
+        start_hour: Some(20),
+        duration_hours: Some(10),
+        ..Default::default()
+    };
+    let overrides = vec![no_watering("fri"), heat_wave];
+    let reload = set_window_overrides(State(app_state.clone()), Json(overrides.clone())).await.unwrap();
+    assert_eq!(reload.0.applied, ["window_overrides"]);
+
+    let windows = get_windows(State(app_state.clone())).await.0;
+    let overridden: Vec<bool> = windows.iter().map(|day| day.overridden).collect();
+    assert_eq!(overridden, [true, true, true, false, false, false, false]);
+    assert!(windows[0].end < windows[0].start, "nothing tonight");
+    assert_eq!(windows[1].start, now + 8 * 3600 + 86_400);
+    assert_eq!(windows[1].end - windows[1].start + 1, 10 * 3600);
+
+    let signal = app_state.sm_rx.lock().await.try_recv().unwrap();
+    let CtrlSignal::ConfigUpdate(cfg) = signal else {
+        panic!("expected the config update, got {:?}", signal);
+    };
+    ws.sm.apply_config(&cfg, now);
+    assert_eq!(ws.sm.window_overrides, overrides);
+    assert!(!ws.sm.timeframe.is_within(now + 10 * 3600), "tonight has no window");
+
+    // past friday's empty window, saturday's opens at 20:00
+    ws.sm.update(now + 10 * 3600).await;
+    assert_eq!(ws.sm.timeframe.day_start_time, now + 8 * 3600 + 86_400);
+    assert!(ws.sm.timeframe.is_within(now + 86_400 + 17 * 3600));
+}
@@ -0,0 +1,63 @@
+use chrono::{TimeZone, Utc};
+use nic::{
+    config::SectorCfg,
+    db::{Database, DatabaseTrait},
+    test::utils::{
+        mock_cfg::mock_cfg, mock_db::new_with_mock, mock_sensors::set_sensor_controller0, mock_time::MockTimeProvider,
+    },
+    watering::{modes::Mode, state_machine::SMState, watering_system::WateringSystem},
+};
+use std::sync::Arc;
+
+fn sector(id: u32) -> SectorCfg {
+    SectorCfg {
+        id,
+        name: format!("zone {}", id),
+        sprinkler_debit: 1.0,
+        percolation_rate: 0.5,
+        weekly_target: 2.5,
+        max_duration: 1800,
+        ignore_weather_pause: false,
+        max_daily_mm: None,
+        max_daily_minutes: None,
+        deficit_exempt: false,
+    }
+}
+
+fn start(db: &Arc<Database>, now: i64) -> WateringSystem {
+    let cfg = mock_cfg();
+    let app_state = new_with_mock(db.clone(), set_sensor_controller0(), Arc::new(MockTimeProvider::new(now))).unwrap();
+    let mut ws = WateringSystem::new(app_state, Some(Mode::Wizard), now, cfg.watering).unwrap();
+    (ws.sm.cfg.valve_check_secs, ws.sm.cfg.max_cycle_secs) = (0, 1800);
+    ws
+}
+
+#[tokio::test]
+async fn tonights_plans_survive_a_restart() {
+    let now = Utc.with_ymd_and_hms(2024, 6, 3, 12, 0, 0).unwrap().timestamp();
+    let db = Arc::new(Database::new(":memory:").unwrap());
+    db.import_sectors(vec![sector(1), sector(2), sector(3)]).unwrap();
+    let mut ws = start(&db, now);
+    assert!(ws.sm.mode_wizard.daily_plan.is_empty());
+    ws.sm.load_plans(now);
+    let plans = ws.sm.mode_wizard.daily_plan.clone();
+    assert_eq!(plans.len(), 3, "a cycle a sector");
+
+    // back up before the window, nothing lost
+    let mut ws = start(&db, now + 3600);
+    assert_eq!(ws.sm.mode_wizard.daily_plan, plans);
+
+    // the first cycle runs, and the machine restarts before the next
+    let mut t = plans[0].0[0].start;
+    while t < plans[1].0[0].start && (t == plans[0].0[0].start || ws.sm.state != SMState::Idle) {
+        ws.sm.update(t).await;
+        t += 10;
+    }
+    assert_eq!(ws.sm.mode_wizard.daily_plan, plans[1..]);
+    let ws = start(&db, t);
+    assert_eq!(ws.sm.mode_wizard.daily_plan, plans[1..], "the cycle that ran is completed");
+
+    // a window that closed while the machine was down is left
+    let ws = start(&db, plans[2].0[0].start + 86_400);
+    assert!(ws.sm.mode_wizard.daily_plan.is_empty());
+}
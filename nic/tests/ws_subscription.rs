@@ -0,0 +1,79 @@
+use futures_util::{SinkExt, StreamExt};
+use hyper::StatusCode;
+use nic::{
+    api::run_web_server,
+    error::ErrorBody,
+    test::utils::set_app_state,
+    watering::ds::{CtrlSignal, RapidWind, WeatherData, WeatherSignal},
+};
+use serde_json::Value;
+use std::time::Duration;
+use tokio::time::{sleep, timeout};
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+
+fn weather() -> CtrlSignal {
+    let data = WeatherData {
+        rain: 0.,
+        wind_intensity: 12.,
+        wind_direction: 90.,
+        humidity: 60.,
+        rain_probability: None,
+        et: None,
+    };
+    CtrlSignal::WeatherData(data)
+}
+
+fn wind(timestamp: i64) -> CtrlSignal {
+    CtrlSignal::RapidWind(RapidWind { timestamp, speed: 10., direction: 180. })
+}
+
+#[tokio::test]
+async fn a_client_gets_its_topics_at_most_as_often_as_it_asked() {
+    let app_state = set_app_state(1_700_000_000);
+    let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+    let addr = "127.0.0.1:3040";
+    let app_state_clone = app_state.clone();
+    let server_task = tokio::spawn(async move {
+        _ = run_web_server(app_state_clone, addr.parse().unwrap(), shutdown_rx).await;
+    });
+    sleep(Duration::from_millis(100)).await;
+
+    // a topic that doesn't exist is refused before the upgrade
+    let refused = connect_async(format!("ws://{}/api/v1/ws/weather?topics=wind", addr)).await;
+    let Err(tokio_tungstenite::tungstenite::Error::Http(response)) = refused else { panic!("expected a 400") };
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let url = format!("ws://{}/api/v1/ws/weather?topics=rapid_wind,signals&every_secs=1", addr);
+    let (mut socket, _) = connect_async(url).await.unwrap();
+    sleep(Duration::from_millis(100)).await;
+    _ = app_state.web_tx.send(weather());
+    for timestamp in 1..=3 {
+        _ = app_state.web_tx.send(wind(timestamp));
+    }
+    _ = app_state.sm_tx.send(CtrlSignal::Weather(WeatherSignal::WindHigh));
+
+    let mut received = Vec::new();
+    while let Ok(Some(Ok(message))) = timeout(Duration::from_millis(1500), socket.next()).await {
+        received.push(serde_json::from_str::<Value>(message.to_text().unwrap()).unwrap());
+    }
+    let timestamps: Vec<_> = received.iter().filter_map(|json| json["timestamp"].as_i64()).collect();
+    // the first right away, the last of the second after it
+    assert_eq!(timestamps, [1, 3]);
+    assert!(received.contains(&Value::from("wind_high")), "{:?}", received);
+    assert_eq!(received.len(), 3, "no observation: {:?}", received);
+
+    // a message changes the subscription, one that doesn't parse is answered with an error
+    socket.send(Message::text(r#"{"topics": ["observations"]}"#)).await.unwrap();
+    socket.send(Message::text("observations")).await.unwrap();
+    let reply = socket.next().await.unwrap().unwrap();
+    let error: ErrorBody = serde_json::from_str(reply.to_text().unwrap()).unwrap();
+    assert_eq!(error.status, 400);
+    _ = app_state.web_tx.send(wind(4));
+    _ = app_state.web_tx.send(weather());
+    let data = socket.next().await.unwrap().unwrap();
+    let data: Value = serde_json::from_str(data.to_text().unwrap()).unwrap();
+    assert_eq!(data["wind_intensity"], 12.);
+
+    _ = shutdown_tx.send(true);
+    server_task.abort();
+}